@@ -0,0 +1,171 @@
+//! Query timeouts which follow the latency actually observed.
+//!
+//! A static timeout is too loose on a fast network, where a lost packet
+//! costs seconds before the next nameserver is tried, and too tight on a
+//! slow one, where answers on their way are abandoned. An
+//! [`AdaptiveTimeout`] records how long each query takes in a
+//! [`LatencyHistogram`] and times queries out at a high percentile of that,
+//! p99 by default, plus a margin, kept between a floor and a ceiling. Until
+//! enough queries have been seen it falls back on the static timeout. Queries
+//! which time out are recorded at the time they were given, so a network
+//! which slows down raises the timeout rather than failing every query.
+use crate::histogram::LatencyHistogram;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Percentile of recent latency a query is given unless told otherwise
+pub const DEFAULT_PERCENTILE: f64 = 99.0;
+
+/// Per query timeouts set from recent latency.
+///
+/// Shared by the clones of a resolver, so each learns from all.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::adaptive::AdaptiveTimeout;
+/// use std::time::Duration;
+///
+/// let adaptive = AdaptiveTimeout::new()
+///     .with_margin(Duration::from_millis(20))
+///     .with_floor(Duration::from_millis(50));
+/// let fallback = Duration::from_secs(5);
+/// assert_eq!(adaptive.timeout(fallback), fallback);
+/// for _ in 0..100 {
+///     adaptive.record(Duration::from_millis(40));
+/// }
+/// let timeout = adaptive.timeout(fallback);
+/// assert!(timeout >= Duration::from_millis(60) && timeout < Duration::from_millis(65));
+/// ```
+#[derive(Debug)]
+pub struct AdaptiveTimeout {
+    percentile: f64,
+    margin: Duration,
+    floor: Duration,
+    ceiling: Option<Duration>,
+    min_samples: u64,
+    latencies: Mutex<LatencyHistogram>,
+}
+
+impl Default for AdaptiveTimeout {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AdaptiveTimeout {
+    /// Timeouts at p99 of the last minute's latency plus 100ms, at least
+    /// 200ms and at most the static timeout, once 20 queries have been seen
+    pub fn new() -> Self {
+        Self {
+            percentile: DEFAULT_PERCENTILE,
+            margin: Duration::from_millis(100),
+            floor: Duration::from_millis(200),
+            ceiling: None,
+            min_samples: 20,
+            latencies: Mutex::new(LatencyHistogram::new(Duration::from_secs(60))),
+        }
+    }
+
+    /// Time queries out at `percentile` of recent latency, returning the
+    /// updated strategy
+    pub fn with_percentile(mut self, percentile: f64) -> Self {
+        self.percentile = percentile.clamp(0.0, 100.0);
+        self
+    }
+
+    /// Allow `margin` beyond the percentile, returning the updated strategy
+    pub fn with_margin(mut self, margin: Duration) -> Self {
+        self.margin = margin;
+        self
+    }
+
+    /// Never time out sooner than `floor`, returning the updated strategy
+    pub fn with_floor(mut self, floor: Duration) -> Self {
+        self.floor = floor;
+        self
+    }
+
+    /// Never wait longer than `ceiling`, returning the updated strategy.
+    /// Defaults to the static timeout.
+    pub fn with_ceiling(mut self, ceiling: Duration) -> Self {
+        self.ceiling = Some(ceiling);
+        self
+    }
+
+    /// Fall back on the static timeout until `samples` queries have been
+    /// seen within the window, returning the updated strategy
+    pub fn with_min_samples(mut self, samples: u64) -> Self {
+        self.min_samples = samples.max(1);
+        self
+    }
+
+    /// Consider the latency over the last `window`, returning the updated
+    /// strategy. Defaults to a minute.
+    pub fn with_window(self, window: Duration) -> Self {
+        Self {
+            latencies: Mutex::new(LatencyHistogram::new(window)),
+            ..self
+        }
+    }
+
+    /// Record a query which took `latency`, or timed out after it
+    pub fn record(&self, latency: Duration) {
+        self.latencies
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .record(latency);
+    }
+
+    /// The timeout for the next query, given the static timeout `fallback`
+    pub fn timeout(&self, fallback: Duration) -> Duration {
+        let latencies = self
+            .latencies
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if latencies.count() < self.min_samples {
+            return fallback;
+        }
+        let ceiling = self.ceiling.unwrap_or(fallback);
+        match latencies.percentile(self.percentile) {
+            Some(latency) => (latency + self.margin).max(self.floor).min(ceiling),
+            None => fallback,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeouts_stay_between_the_floor_and_ceiling() {
+        let adaptive = AdaptiveTimeout::new().with_min_samples(1);
+        adaptive.record(Duration::from_millis(1));
+        assert_eq!(
+            adaptive.timeout(Duration::from_secs(5)),
+            Duration::from_millis(200)
+        );
+        let adaptive = AdaptiveTimeout::new()
+            .with_min_samples(1)
+            .with_ceiling(Duration::from_secs(2));
+        adaptive.record(Duration::from_secs(10));
+        assert_eq!(
+            adaptive.timeout(Duration::from_secs(5)),
+            Duration::from_secs(2)
+        );
+    }
+    #[test]
+    fn timed_out_queries_raise_the_timeout() {
+        let adaptive = AdaptiveTimeout::new()
+            .with_min_samples(10)
+            .with_floor(Duration::from_millis(0));
+        for _ in 0..10 {
+            adaptive.record(Duration::from_millis(10));
+        }
+        let before = adaptive.timeout(Duration::from_secs(5));
+        // a query timing out is recorded at its timeout
+        adaptive.record(before);
+        assert!(adaptive.timeout(Duration::from_secs(5)) > before);
+    }
+}
@@ -0,0 +1,202 @@
+//! An address of either family with integer conversions and bit operations.
+//!
+//! Storing addresses as integers, masking them and extracting prefixes all
+//! need the family's width at hand. [`Address`] wraps an `IpAddr` and does
+//! that bookkeeping in one place.
+use crate::parse::{parse_ip, ParseError};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+/// An IPv4 or IPv6 address.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::address::Address;
+///
+/// let addr: Address = "192.0.2.77".parse().unwrap();
+/// assert_eq!(addr.to_u32(), Some(0xc000024d));
+/// assert_eq!(addr.with_prefix(24).unwrap().to_string(), "192.0.2.0");
+/// assert_eq!(Address::from_u32(0xc000024d), addr);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "diesel",
+    derive(diesel::expression::AsExpression, diesel::deserialize::FromSqlRow)
+)]
+#[cfg_attr(feature = "diesel", diesel(sql_type = diesel::sql_types::Inet))]
+pub struct Address(IpAddr);
+
+impl Address {
+    /// Wrap `ip`
+    pub fn new(ip: IpAddr) -> Self {
+        Self(ip)
+    }
+
+    /// The wrapped address
+    pub fn ip(&self) -> IpAddr {
+        self.0
+    }
+
+    /// The IPv4 address with the value `bits`
+    pub fn from_u32(bits: u32) -> Self {
+        Self(IpAddr::V4(Ipv4Addr::from(bits)))
+    }
+
+    /// The IPv6 address with the value `bits`
+    pub fn from_u128(bits: u128) -> Self {
+        Self(IpAddr::V6(Ipv6Addr::from(bits)))
+    }
+
+    /// The value of an IPv4 address, or None for IPv6
+    pub fn to_u32(&self) -> Option<u32> {
+        match self.0 {
+            IpAddr::V4(ip) => Some(u32::from(ip)),
+            IpAddr::V6(_) => None,
+        }
+    }
+
+    /// The value of the address. IPv4 addresses occupy the low 32 bits, so
+    /// store the family alongside the value to tell `10.0.0.1` from `::a00:1`.
+    pub fn to_u128(&self) -> u128 {
+        match self.0 {
+            IpAddr::V4(ip) => u32::from(ip) as u128,
+            IpAddr::V6(ip) => u128::from(ip),
+        }
+    }
+
+    /// The width of the family in bits, 32 or 128
+    pub fn bits(&self) -> u8 {
+        match self.0 {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        }
+    }
+
+    /// The address of this family with `value`, truncated to its width
+    fn with_value(&self, value: u128) -> Self {
+        match self.0 {
+            IpAddr::V4(_) => Self::from_u32(value as u32),
+            IpAddr::V6(_) => Self::from_u128(value),
+        }
+    }
+
+    /// The netmask of this family with the leading `prefix_len` bits set, or
+    /// None if `prefix_len` exceeds the width
+    pub fn netmask(&self, prefix_len: u8) -> Option<Self> {
+        if prefix_len > self.bits() {
+            return None;
+        }
+        let all = if self.bits() == 32 {
+            u32::MAX as u128
+        } else {
+            u128::MAX
+        };
+        let host_bits = (self.bits() - prefix_len) as u32;
+        Some(self.with_value(all.checked_shl(host_bits).unwrap_or(0) & all))
+    }
+
+    /// The prefix length of a netmask such as `255.255.255.0`, or None if
+    /// its set bits are not contiguous from the top
+    pub fn mask_prefix_len(&self) -> Option<u8> {
+        let value = self.to_u128() << (128 - self.bits() as u32);
+        let len = value.leading_ones();
+        if value.checked_shl(len).unwrap_or(0) != 0 {
+            return None;
+        }
+        Some(len as u8)
+    }
+
+    /// The bitwise and of the address with `mask`, or None if they are of
+    /// different families
+    pub fn mask(&self, mask: Address) -> Option<Self> {
+        if self.bits() != mask.bits() {
+            return None;
+        }
+        Some(self.with_value(self.to_u128() & mask.to_u128()))
+    }
+
+    /// The address with all but the leading `prefix_len` bits cleared, or
+    /// None if `prefix_len` exceeds the width
+    pub fn with_prefix(&self, prefix_len: u8) -> Option<Self> {
+        self.mask(self.netmask(prefix_len)?)
+    }
+
+    /// The leading `prefix_len` bits of the address as an integer, eg the
+    /// /16 prefix of `192.0.2.1` is `0xc000`. None if `prefix_len` exceeds
+    /// the width.
+    pub fn prefix(&self, prefix_len: u8) -> Option<u128> {
+        if prefix_len > self.bits() {
+            return None;
+        }
+        let host_bits = (self.bits() - prefix_len) as u32;
+        Some(self.to_u128().checked_shr(host_bits).unwrap_or(0))
+    }
+}
+
+impl From<IpAddr> for Address {
+    fn from(ip: IpAddr) -> Self {
+        Self(ip)
+    }
+}
+
+impl From<Address> for IpAddr {
+    fn from(addr: Address) -> Self {
+        addr.0
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for Address {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_ip(s).map(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(text: &str) -> Address {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn integer_round_trips() {
+        let v6 = addr("2001:db8::1");
+        assert_eq!(Address::from_u128(v6.to_u128()), v6);
+        assert_eq!(v6.to_u32(), None);
+        assert_eq!(addr("10.0.0.1").to_u128(), 0x0a000001);
+        assert_eq!(addr("255.255.255.255").to_u32(), Some(u32::MAX));
+    }
+    #[test]
+    fn masks_and_prefixes() {
+        let v4 = addr("192.0.2.77");
+        assert_eq!(v4.netmask(20).unwrap(), addr("255.255.240.0"));
+        assert_eq!(v4.netmask(0).unwrap(), addr("0.0.0.0"));
+        assert_eq!(v4.netmask(33), None);
+        assert_eq!(addr("255.255.240.0").mask_prefix_len(), Some(20));
+        assert_eq!(addr("255.0.255.0").mask_prefix_len(), None);
+        assert_eq!(addr("ffff:ffff::").mask_prefix_len(), Some(32));
+        assert_eq!(v4.mask(addr("255.255.0.0")).unwrap(), addr("192.0.0.0"));
+        assert_eq!(v4.mask(addr("::")), None);
+        assert_eq!(v4.prefix(16), Some(0xc000));
+        assert_eq!(v4.prefix(0), Some(0));
+        assert_eq!(
+            addr("2001:db8::1").with_prefix(32).unwrap(),
+            addr("2001:db8::")
+        );
+        assert_eq!(
+            addr("2001:db8::1").prefix(128),
+            Some(addr("2001:db8::1").to_u128())
+        );
+    }
+}
@@ -0,0 +1,267 @@
+//! Mapping addresses to the autonomous system announcing them.
+//!
+//! Network analytics want the ASN, the announced prefix and the registry of
+//! an address next to the address itself. [`asn_for_ip`] asks the Team
+//! Cymru IP to ASN service over DNS; [`AsnTable`] answers offline from a
+//! table of prefixes, such as one exported from a routing table or RIR
+//! delegation files.
+use crate::cidr::Cidr;
+use crate::direct::DirectResolver;
+use crate::errors::IpaddrConversionError;
+use crate::rdns::reversed_labels;
+use crate::trie::PrefixTrie;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// The zone of the Team Cymru origin records for IPv4 addresses
+pub const CYMRU_ORIGIN_ZONE: &str = "origin.asn.cymru.com";
+/// The zone of the Team Cymru origin records for IPv6 addresses
+pub const CYMRU_ORIGIN6_ZONE: &str = "origin6.asn.cymru.com";
+/// The zone of the Team Cymru records describing each AS
+pub const CYMRU_ASN_ZONE: &str = "asn.cymru.com";
+
+/// The autonomous system announcing an address
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AsnInfo {
+    pub asn: u32,
+    /// The announced prefix covering the address
+    pub prefix: Cidr,
+    /// The registry which allocated the prefix, such as `arin` or `ripencc`
+    pub registry: Option<String>,
+    /// The country code of the allocation
+    pub country: Option<String>,
+}
+
+/// Split a Cymru TXT answer into its `|` separated fields, with empty
+/// fields as None
+fn fields(text: &str) -> Vec<Option<&str>> {
+    text.split('|')
+        .map(str::trim)
+        .map(|field| Some(field).filter(|field| !field.is_empty()))
+        .collect()
+}
+
+/// Parse a Cymru origin answer: `ASN | prefix | country | registry | date`.
+/// Of several ASNs announcing the prefix, the first is taken.
+fn parse_origin(text: &str) -> Option<AsnInfo> {
+    let fields = fields(text);
+    let asn = fields
+        .first()?
+        .as_ref()?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()?;
+    let prefix = fields.get(1)?.as_ref()?.parse().ok()?;
+    let field = |idx: usize| fields.get(idx).copied().flatten().map(str::to_string);
+    Some(AsnInfo {
+        asn,
+        prefix,
+        country: field(2),
+        registry: field(3),
+    })
+}
+
+/// Look up the AS announcing `addr` through the Team Cymru service,
+/// querying the system's nameservers directly.
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::asn::asn_for_ip;
+///
+/// let info = asn_for_ip("1.1.1.1".parse().unwrap()).unwrap();
+/// println!("AS{} announces {}", info.asn, info.prefix);
+/// ```
+pub fn asn_for_ip(addr: IpAddr) -> Result<AsnInfo, IpaddrConversionError> {
+    asn_for_ip_with(&DirectResolver::from_system()?, addr)
+}
+
+/// Look up the AS announcing `addr` through the Team Cymru service
+///
+/// # Parameters
+///
+/// * `resolver` - queries the TXT records
+/// * `addr` - the address; an IPv4-mapped IPv6 address is looked up as its
+///   IPv4 address
+///
+/// # Returns
+/// The most specific announcement covering `addr`, or NotFound if it is not
+/// announced
+pub fn asn_for_ip_with(
+    resolver: &DirectResolver,
+    addr: IpAddr,
+) -> Result<AsnInfo, IpaddrConversionError> {
+    let addr = addr.to_canonical();
+    let zone = match addr {
+        IpAddr::V4(_) => CYMRU_ORIGIN_ZONE,
+        IpAddr::V6(_) => CYMRU_ORIGIN6_ZONE,
+    };
+    let name = match addr {
+        // the service wants only the 16 nibbles of the /64
+        IpAddr::V6(_) => {
+            let labels = reversed_labels(addr);
+            format!("{}.{}", &labels[labels.len() - 31..], zone)
+        }
+        IpAddr::V4(_) => format!("{}.{}", reversed_labels(addr), zone),
+    };
+    resolver
+        .txt(&name)?
+        .iter()
+        .filter_map(|text| parse_origin(text))
+        .filter(|info| info.prefix.contains(addr))
+        .max_by_key(|info| info.prefix.prefix_len())
+        .ok_or_else(|| IpaddrConversionError::NotFound(addr.to_string()))
+}
+
+/// The name of AS `asn`, such as `CLOUDFLARENET, US`, through the Team
+/// Cymru service
+pub fn as_name_with(resolver: &DirectResolver, asn: u32) -> Result<String, IpaddrConversionError> {
+    resolver
+        .txt(&format!("AS{}.{}", asn, CYMRU_ASN_ZONE))?
+        .iter()
+        // ASN | country | registry | date | name
+        .find_map(|text| fields(text).get(4).copied().flatten().map(str::to_string))
+        .ok_or_else(|| IpaddrConversionError::NotFound(format!("AS{}", asn)))
+}
+
+/// A table of prefixes and the ASes announcing them, for lookups without
+/// network access.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::asn::AsnTable;
+///
+/// let table = AsnTable::parse("1.1.1.0/24 13335 apnic\n2606:4700::/32 13335 arin\n");
+/// let info = table.lookup("1.1.1.1".parse().unwrap()).unwrap();
+/// assert_eq!((info.asn, info.registry.as_deref()), (13335, Some("apnic")));
+/// assert!(table.lookup("192.0.2.1".parse().unwrap()).is_none());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct AsnTable {
+    prefixes: PrefixTrie<AsnInfo>,
+}
+
+impl AsnTable {
+    /// An empty table
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a table with a prefix per line: the prefix, the ASN, with or
+    /// without an `AS` prefix, and optionally the registry and the country,
+    /// separated by whitespace, commas or `|`. Blank lines, `#` comments and
+    /// malformed lines are skipped.
+    pub fn parse(text: &str) -> Self {
+        let mut table = Self::new();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut fields = line
+                .split(|c: char| c.is_whitespace() || c == ',' || c == '|')
+                .filter(|field| !field.is_empty());
+            let prefix = fields.next().and_then(|prefix| prefix.parse::<Cidr>().ok());
+            let asn = fields.next().and_then(|asn| {
+                asn.trim_start_matches(['A', 'S', 'a', 's'])
+                    .parse::<u32>()
+                    .ok()
+            });
+            if let (Some(prefix), Some(asn)) = (prefix, asn) {
+                table.insert(AsnInfo {
+                    asn,
+                    prefix,
+                    registry: fields.next().map(str::to_string),
+                    country: fields.next().map(str::to_string),
+                });
+            }
+        }
+        table
+    }
+
+    /// Read a table from `path`, as [`parse`](Self::parse) does
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, IpaddrConversionError> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    /// Add or replace the entry for `info.prefix`
+    pub fn insert(&mut self, info: AsnInfo) {
+        self.prefixes.insert(info.prefix, info);
+    }
+
+    /// The number of prefixes in the table
+    pub fn len(&self) -> usize {
+        self.prefixes.len()
+    }
+
+    /// Whether the table has no prefixes
+    pub fn is_empty(&self) -> bool {
+        self.prefixes.is_empty()
+    }
+
+    /// The most specific entry covering `addr`
+    pub fn lookup(&self, addr: IpAddr) -> Option<&AsnInfo> {
+        self.prefixes
+            .longest_match(addr.to_canonical())
+            .map(|(_, info)| info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::direct::tests::{answer, config_for, spawn_udp_server};
+    use crate::dns::{RData, RCODE_NXDOMAIN};
+
+    #[test]
+    fn cymru_answers_are_used() {
+        let server = spawn_udp_server(|request| {
+            let txt = |texts: &[&str]| {
+                texts
+                    .iter()
+                    .map(|text| RData::Txt(vec![text.to_string()]))
+                    .collect()
+            };
+            let data = match request.questions[0].name.as_str() {
+                "1.1.1.1.origin.asn.cymru.com" => txt(&[
+                    "13335 | 1.1.0.0/16 | AU | apnic | 2011-08-11",
+                    "13335 4826 | 1.1.1.0/24 | AU | apnic | 2011-08-11",
+                ]),
+                "0.0.0.0.0.0.0.0.0.0.7.4.6.0.6.2.origin6.asn.cymru.com" => {
+                    txt(&["13335 | 2606:4700::/32 | US | arin | 2011-11-01"])
+                }
+                "AS13335.asn.cymru.com" => {
+                    txt(&["13335 | US | arin | 2010-07-14 | CLOUDFLARENET, US"])
+                }
+                _ => return Some(answer(request, RCODE_NXDOMAIN, vec![])),
+            };
+            Some(answer(request, 0, data))
+        });
+        let resolver = DirectResolver::new(config_for(vec![server]));
+        let info = asn_for_ip_with(&resolver, "::ffff:1.1.1.1".parse().unwrap()).unwrap();
+        assert_eq!(info.asn, 13335);
+        assert_eq!(info.prefix.to_string(), "1.1.1.0/24");
+        assert_eq!(info.registry.as_deref(), Some("apnic"));
+        assert_eq!(info.country.as_deref(), Some("AU"));
+        let info = asn_for_ip_with(&resolver, "2606:4700::1111".parse().unwrap()).unwrap();
+        assert_eq!(info.registry.as_deref(), Some("arin"));
+        assert_eq!(as_name_with(&resolver, 13335).unwrap(), "CLOUDFLARENET, US");
+        assert!(asn_for_ip_with(&resolver, "192.0.2.1".parse().unwrap()).is_err());
+    }
+    #[test]
+    fn tables_are_parsed() {
+        let table = AsnTable::parse(
+            "# prefix asn registry country\n\
+             10.0.0.0/8,AS64512,private\n\
+             10.1.0.0/16 | 64513 | private | ZZ\n\
+             not a prefix 1\n",
+        );
+        assert_eq!(table.len(), 2);
+        let info = table.lookup("10.1.2.3".parse().unwrap()).unwrap();
+        assert_eq!((info.asn, info.country.as_deref()), (64513, Some("ZZ")));
+        assert_eq!(
+            table.lookup("10.2.0.1".parse().unwrap()).unwrap().asn,
+            64512
+        );
+    }
+}
@@ -0,0 +1,351 @@
+//! A tamper-evident record of every lookup, for compliance review.
+//!
+//! [`AuditResolver`] appends each lookup through it to an [`AuditLog`]: when
+//! it happened, which resolver answered, the name and the answer or error.
+//! Each line carries the SHA-256 of the line before it, so editing,
+//! removing or reordering any line breaks the chain from there on, which
+//! [`verify_audit_log`] detects. The log proves what was resolved, not who
+//! wrote it; anyone able to rewrite the whole file can forge a new chain, so
+//! export it somewhere append-only, or record [`AuditLog::head`] elsewhere.
+use crate::digest::{hex, sha256};
+use crate::errors::IpaddrConversionError;
+use crate::redact::{addr_text, is_redacting, name_text};
+use crate::resolver::Resolve;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The hash the first line chains to
+const GENESIS: [u8; 32] = [0; 32];
+
+/// Where the lines go
+#[derive(Debug)]
+enum Sink {
+    Memory(Vec<String>),
+    File { path: PathBuf, file: File },
+}
+
+#[derive(Debug)]
+struct Chain {
+    next_sequence: u64,
+    head: [u8; 32],
+    sink: Sink,
+}
+
+/// Escape tabs, newlines and backslashes, so each field stays one field
+fn escape(field: &str) -> String {
+    field
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// The hash of a line, from the hash before it and the fields it records
+fn link(previous: &[u8; 32], fields: &str) -> [u8; 32] {
+    let mut data = previous.to_vec();
+    data.extend_from_slice(fields.as_bytes());
+    sha256(&data)
+}
+
+/// A hash-chained log of lookups, one line per lookup.
+///
+/// Each line holds, separated by tabs: the sequence number, the time in
+/// milliseconds since the unix epoch, the resolver's identity, the name, the
+/// answer as `ok` and the addresses or `err` and the error, the hash of the
+/// line before, and the hash of this line. Names and addresses are masked
+/// while [redaction](crate::redact::set_redacting) is on.
+#[derive(Debug)]
+pub struct AuditLog {
+    chain: Mutex<Chain>,
+}
+
+impl Default for AuditLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AuditLog {
+    /// A log kept in memory, until [exported](Self::export)
+    pub fn new() -> Self {
+        AuditLog {
+            chain: Mutex::new(Chain {
+                next_sequence: 0,
+                head: GENESIS,
+                sink: Sink::Memory(Vec::new()),
+            }),
+        }
+    }
+
+    /// A log appended to the file at `path`, continuing the chain of the
+    /// lines already in it
+    ///
+    /// # Returns
+    /// The log, or an InvalidInput error if the lines already in the file do
+    /// not verify, as the chain cannot honestly be continued from them
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, IpaddrConversionError> {
+        let path = path.as_ref().to_path_buf();
+        let (next_sequence, head) = match File::open(&path) {
+            Ok(file) => verify_chain(BufReader::new(file))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (0, GENESIS),
+            Err(err) => return Err(err.into()),
+        };
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(AuditLog {
+            chain: Mutex::new(Chain {
+                next_sequence,
+                head,
+                sink: Sink::File { path, file },
+            }),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Chain> {
+        self.chain
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Append a lookup of `hostname` by the resolver called `resolver`
+    ///
+    /// # Parameters
+    ///
+    /// * `resolver` - the identity of the resolver, such as its nameservers
+    /// * `hostname` - the name looked up, as given
+    /// * `result` - what the lookup gave
+    ///
+    /// # Returns
+    /// The line appended, or the error writing it to the file
+    pub fn append(
+        &self,
+        resolver: &str,
+        hostname: &str,
+        result: &Result<Vec<IpAddr>, IpaddrConversionError>,
+    ) -> Result<String, IpaddrConversionError> {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis());
+        let answer = match result {
+            Ok(addrs) => {
+                let addrs: Vec<String> = addrs.iter().map(|addr| addr_text(*addr)).collect();
+                format!("ok {}", addrs.join(","))
+            }
+            Err(err) if is_redacting() && !hostname.is_empty() => format!(
+                "err {}",
+                escape(&err.to_string().replace(hostname, &name_text(hostname)))
+            ),
+            Err(err) => format!("err {}", escape(&err.to_string())),
+        };
+        let mut chain = self.lock();
+        let fields = format!(
+            "{}\t{}\t{}\t{}\t{}",
+            chain.next_sequence,
+            millis,
+            escape(resolver),
+            escape(&name_text(hostname)),
+            answer
+        );
+        let hash = link(&chain.head, &fields);
+        let line = format!("{}\t{}\t{}", fields, hex(&chain.head), hex(&hash));
+        match chain.sink {
+            Sink::Memory(ref mut lines) => lines.push(line.clone()),
+            Sink::File { ref mut file, .. } => writeln!(file, "{}", line)?,
+        }
+        chain.next_sequence += 1;
+        chain.head = hash;
+        Ok(line)
+    }
+
+    /// The hash of the last line, which commits to every line before it.
+    /// Recording it somewhere else lets a rewritten log be caught.
+    pub fn head(&self) -> String {
+        hex(&self.lock().head)
+    }
+
+    /// The number of lines in the log
+    pub fn len(&self) -> u64 {
+        self.lock().next_sequence
+    }
+
+    /// Whether nothing has been logged
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Write every line of the log to `writer`, for review
+    pub fn export<W: Write>(&self, mut writer: W) -> Result<(), IpaddrConversionError> {
+        let chain = self.lock();
+        match chain.sink {
+            Sink::Memory(ref lines) => {
+                for line in lines {
+                    writeln!(writer, "{}", line)?;
+                }
+            }
+            Sink::File { ref path, .. } => writer.write_all(&fs::read(path)?)?,
+        }
+        Ok(())
+    }
+}
+
+/// Check every line of a log chains to the one before it, returning the
+/// number of lines and the hash of the last
+fn verify_chain<R: BufRead>(reader: R) -> Result<(u64, [u8; 32]), IpaddrConversionError> {
+    let mut head = GENESIS;
+    let mut count = 0u64;
+    for line in reader.lines() {
+        let line = line?;
+        let broken = |msg: &str| {
+            IpaddrConversionError::InvalidInput(format!("audit line {}: {}", count + 1, msg))
+        };
+        let mut parts = line.rsplitn(3, '\t');
+        let (hash, previous, fields) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(hash), Some(previous), Some(fields)) => (hash, previous, fields),
+            _ => return Err(broken("malformed")),
+        };
+        if fields.split('\t').next() != Some(count.to_string().as_str()) {
+            return Err(broken("out of sequence"));
+        }
+        if previous != hex(&head) {
+            return Err(broken("does not follow the line before"));
+        }
+        let expected = link(&head, fields);
+        if hash != hex(&expected) {
+            return Err(broken("hash does not match"));
+        }
+        head = expected;
+        count += 1;
+    }
+    Ok((count, head))
+}
+
+/// Check an exported log has not been tampered with
+///
+/// # Parameters
+///
+/// * `reader` - the lines of the log, from its first
+///
+/// # Returns
+/// The hash of the last line, to compare against a head recorded elsewhere,
+/// or an InvalidInput error naming the first line which was edited,
+/// removed or moved
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::audit::{verify_audit_log, AuditResolver};
+/// use ipaddr_from_str::{Resolve, StaticResolver};
+///
+/// let resolver = AuditResolver::new(StaticResolver::new(), "static");
+/// resolver.resolve("10.0.0.1").unwrap();
+/// let mut exported = Vec::new();
+/// resolver.log().export(&mut exported).unwrap();
+/// assert_eq!(verify_audit_log(exported.as_slice()).unwrap(), resolver.log().head());
+///
+/// let forged = String::from_utf8(exported).unwrap().replace("10.0.0.1", "10.6.6.6");
+/// assert!(verify_audit_log(forged.as_bytes()).is_err());
+/// ```
+pub fn verify_audit_log<R: BufRead>(reader: R) -> Result<String, IpaddrConversionError> {
+    Ok(hex(&verify_chain(reader)?.1))
+}
+
+/// Resolver logging every lookup of another resolver to an [`AuditLog`].
+///
+/// Lookups fail closed: if the log cannot be written, the lookup fails with
+/// the write error, so nothing is resolved without a record of it.
+/// Clones share their log.
+#[derive(Debug, Clone)]
+pub struct AuditResolver<R> {
+    resolver: R,
+    identity: String,
+    log: Arc<AuditLog>,
+}
+
+impl<R: Resolve> AuditResolver<R> {
+    /// Log the lookups of `resolver`, identified as `identity`, in memory
+    pub fn new(resolver: R, identity: &str) -> Self {
+        Self::with_log(resolver, identity, Arc::new(AuditLog::new()))
+    }
+
+    /// Log the lookups of `resolver`, identified as `identity`, to `log`,
+    /// which may be shared by several resolvers
+    pub fn with_log(resolver: R, identity: &str, log: Arc<AuditLog>) -> Self {
+        Self {
+            resolver,
+            identity: identity.to_string(),
+            log,
+        }
+    }
+
+    /// The log being appended to
+    pub fn log(&self) -> &Arc<AuditLog> {
+        &self.log
+    }
+
+    /// The resolver lookups go to
+    pub fn inner(&self) -> &R {
+        &self.resolver
+    }
+}
+
+impl<R: Resolve> Resolve for AuditResolver<R> {
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        let result = self.resolver.resolve(hostname_or_address);
+        self.log
+            .append(&self.identity, hostname_or_address, &result)?;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::StaticResolver;
+
+    #[test]
+    fn lines_chain_and_tampering_is_caught() {
+        let resolver = AuditResolver::new(
+            StaticResolver::new().with_entry("db", vec!["10.0.0.1".parse().unwrap()]),
+            "static\tresolver",
+        );
+        resolver.resolve("db").unwrap();
+        assert!(resolver.resolve("missing").is_err());
+        resolver.resolve("db").unwrap();
+        assert_eq!(resolver.log().len(), 3);
+        let mut exported = Vec::new();
+        resolver.log().export(&mut exported).unwrap();
+        let text = String::from_utf8(exported).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines[0].contains("\tstatic\\tresolver\tdb\tok 10.0.0.1\t"));
+        assert!(lines[1].contains("\tmissing\terr NotFound missing\t"));
+        assert_eq!(
+            verify_audit_log(text.as_bytes()).unwrap(),
+            resolver.log().head()
+        );
+        let removed = format!("{}\n{}\n", lines[0], lines[2]);
+        assert!(verify_audit_log(removed.as_bytes()).is_err());
+        let swapped = format!("{}\n{}\n{}\n", lines[1], lines[0], lines[2]);
+        assert!(verify_audit_log(swapped.as_bytes()).is_err());
+        // a truncated log still verifies, up to its own head
+        assert!(verify_audit_log(lines[0].as_bytes()).is_ok());
+    }
+    #[test]
+    fn file_logs_continue_their_chain() {
+        let path = std::env::temp_dir().join(format!("ipaddr-audit-{}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let log = AuditLog::open(&path).unwrap();
+        log.append("a", "db", &Ok(vec![])).unwrap();
+        drop(log);
+        let log = AuditLog::open(&path).unwrap();
+        log.append("a", "cache", &Ok(vec!["::1".parse().unwrap()]))
+            .unwrap();
+        let head = verify_audit_log(BufReader::new(File::open(&path).unwrap())).unwrap();
+        assert_eq!((log.len(), head), (2, log.head()));
+        fs::write(&path, "0\t0\ta\tdb\tok \tbad\tbad\n").unwrap();
+        assert!(AuditLog::open(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,130 @@
+//! Choosing a resolver backend at runtime.
+//!
+//! Which backend answers lookups is a configuration detail: a [`Backend`]
+//! parses from a name such as `"hickory"`, found in a config file or an
+//! option, and builds the resolver, which callers then use through the
+//! [`Resolve`] trait whatever it is. Every backend name parses in every
+//! build, so configs stay portable; building one whose feature is not
+//! enabled fails with an InvalidInput error naming the feature.
+use crate::errors::IpaddrConversionError;
+use crate::parse::ParseError;
+use crate::resolver::{Resolve, SystemResolver};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// A resolver backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Backend {
+    /// The operating system's resolver, via getaddrinfo
+    System,
+    /// The crate's own [`DirectResolver`](crate::direct::DirectResolver),
+    /// configured from the system
+    Direct,
+    /// hickory-resolver, with the `hickory` feature
+    Hickory,
+    /// c-ares, with the `c-ares` feature
+    CAres,
+}
+
+impl Backend {
+    /// Every backend, whether or not this build can construct it
+    pub const ALL: [Backend; 4] = [
+        Backend::System,
+        Backend::Direct,
+        Backend::Hickory,
+        Backend::CAres,
+    ];
+
+    /// The name the backend parses from
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::System => "system",
+            Backend::Direct => "direct",
+            Backend::Hickory => "hickory",
+            Backend::CAres => "c-ares",
+        }
+    }
+
+    /// Whether this build can construct the backend
+    pub fn is_available(self) -> bool {
+        match self {
+            Backend::System | Backend::Direct => true,
+            Backend::Hickory => cfg!(feature = "hickory"),
+            Backend::CAres => cfg!(feature = "c-ares"),
+        }
+    }
+
+    /// Build the backend's resolver, configured from the system.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ipaddr_from_str::backend::Backend;
+    ///
+    /// let backend: Backend = "system".parse().unwrap();
+    /// let resolver = backend.resolver().unwrap();
+    /// assert!(resolver.resolve("127.0.0.1").is_ok());
+    /// ```
+    pub fn resolver(self) -> Result<Arc<dyn Resolve + Send + Sync>, IpaddrConversionError> {
+        match self {
+            Backend::System => Ok(Arc::new(SystemResolver)),
+            Backend::Direct => Ok(Arc::new(crate::direct::DirectResolver::from_system()?)),
+            #[cfg(feature = "hickory")]
+            Backend::Hickory => Ok(Arc::new(crate::hickory::HickoryResolver::from_system()?)),
+            #[cfg(feature = "c-ares")]
+            Backend::CAres => Ok(Arc::new(crate::cares::CAresResolver::from_system()?)),
+            #[allow(unreachable_patterns)]
+            _ => Err(IpaddrConversionError::InvalidInput(format!(
+                "the {} backend needs the {} feature",
+                self,
+                self.name()
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Backend {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let wanted = s.trim();
+        Backend::ALL
+            .iter()
+            .copied()
+            .find(|backend| backend.name().eq_ignore_ascii_case(wanted))
+            .ok_or_else(|| ParseError::new(0, "one of system, direct, hickory or c-ares"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_round_trip() {
+        for backend in &Backend::ALL {
+            assert_eq!(backend.to_string().parse::<Backend>().unwrap(), *backend);
+        }
+        assert_eq!("C-Ares".parse::<Backend>().unwrap(), Backend::CAres);
+        assert!("bind".parse::<Backend>().is_err());
+    }
+    #[test]
+    fn unavailable_backends_name_their_feature() {
+        for backend in Backend::ALL.iter().filter(|b| !b.is_available()) {
+            match backend.resolver() {
+                Err(IpaddrConversionError::InvalidInput(msg)) => {
+                    assert!(msg.contains(backend.name()))
+                }
+                other => panic!("unexpected {:?}", other.map(|_| ())),
+            }
+        }
+        assert!(Backend::System.is_available());
+    }
+}
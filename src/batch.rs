@@ -0,0 +1,156 @@
+//! Resolving several names at once.
+use crate::deadline;
+use crate::errors::IpaddrConversionError;
+use crate::resolver::Resolve;
+use std::net::IpAddr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Outcome of resolving a set of names within a time budget.
+#[derive(Debug, Default)]
+pub struct PartialResolution {
+    /// Names which finished in time, with their result, in the order given
+    pub finished: Vec<(String, Result<Vec<IpAddr>, IpaddrConversionError>)>,
+    /// Names still being resolved when the budget ran out, in the order given
+    pub unfinished: Vec<String>,
+}
+
+impl PartialResolution {
+    /// Whether every name finished, successfully or not
+    pub fn is_complete(&self) -> bool {
+        self.unfinished.is_empty()
+    }
+
+    /// The addresses of `hostname`, if it finished and resolved successfully
+    pub fn addrs(&self, hostname: &str) -> Option<&[IpAddr]> {
+        self.finished
+            .iter()
+            .find(|(name, _)| name == hostname)
+            .and_then(|(_, result)| result.as_ref().ok())
+            .map(|addrs| addrs.as_slice())
+    }
+}
+
+/// Resolve every name in `hosts` concurrently, sharing a single time budget.
+///
+/// Each name is resolved on its own thread, so one slow name does not hold up
+/// the rest. Once `total_budget` has elapsed, whatever has finished is
+/// returned and the remaining names are reported as unfinished; their lookups
+/// carry on in the background and their results are discarded.
+///
+/// # Parameters
+///
+/// * `resolver` - the resolver to use; it is cloned for each thread, so share
+///   expensive resolvers through an `Arc`
+/// * `hosts` - the names to resolve
+/// * `total_budget` - the time allowed for the whole set
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::batch::resolve_all_within;
+/// use ipaddr_from_str::SystemResolver;
+/// use std::time::Duration;
+///
+/// let result = resolve_all_within(&SystemResolver, &["127.0.0.1", "::1"], Duration::from_secs(1));
+/// assert!(result.is_complete());
+/// ```
+pub fn resolve_all_within<R, S>(
+    resolver: &R,
+    hosts: &[S],
+    total_budget: Duration,
+) -> PartialResolution
+where
+    R: Resolve + Clone + Send + 'static,
+    S: AsRef<str>,
+{
+    let deadline = deadline::after(total_budget);
+    let (sender, receiver) = mpsc::channel();
+    for (idx, host) in hosts.iter().enumerate() {
+        let resolver = resolver.clone();
+        let sender = sender.clone();
+        let host = host.as_ref().to_string();
+        thread::spawn(move || {
+            let result = resolver.resolve(&host);
+            // the receiver is gone once the budget has run out
+            let _ = sender.send((idx, result));
+        });
+    }
+    drop(sender);
+
+    let mut results: Vec<Option<Result<Vec<IpAddr>, IpaddrConversionError>>> =
+        hosts.iter().map(|_| None).collect();
+    let mut outstanding = hosts.len();
+    while outstanding > 0 {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match receiver.recv_timeout(remaining) {
+            Ok((idx, result)) => {
+                results[idx] = Some(result);
+                outstanding -= 1;
+            }
+            Err(_) => break,
+        }
+    }
+
+    let mut resolution = PartialResolution::default();
+    for (host, result) in hosts.iter().zip(results) {
+        let host = host.as_ref().to_string();
+        match result {
+            Some(result) => resolution.finished.push((host, result)),
+            None => resolution.unfinished.push(host),
+        }
+    }
+    resolution
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::StaticResolver;
+
+    /// Resolver which takes `delay` to answer names starting with "slow"
+    #[derive(Clone)]
+    struct Slow {
+        delay: Duration,
+        inner: StaticResolver,
+    }
+
+    impl Resolve for Slow {
+        fn resolve(&self, host: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+            if host.starts_with("slow") {
+                thread::sleep(self.delay);
+            }
+            self.inner.resolve(host)
+        }
+    }
+
+    #[test]
+    fn slow_names_are_reported_unfinished() {
+        let resolver = Slow {
+            delay: Duration::from_secs(5),
+            inner: StaticResolver::new()
+                .with_entry("fast.example", vec!["10.0.0.1".parse().unwrap()])
+                .with_entry("slow.example", vec!["10.0.0.2".parse().unwrap()]),
+        };
+        let start = Instant::now();
+        let result = resolve_all_within(
+            &resolver,
+            &["slow.example", "fast.example", "missing.example"],
+            Duration::from_millis(200),
+        );
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(!result.is_complete());
+        assert_eq!(result.unfinished, vec!["slow.example"]);
+        assert_eq!(result.finished[0].0, "fast.example");
+        assert!(result.addrs("fast.example").is_some());
+        assert!(result.finished[1].1.is_err());
+    }
+    #[test]
+    fn empty_input_completes_immediately() {
+        let result =
+            resolve_all_within::<_, &str>(&StaticResolver::new(), &[], Duration::from_secs(0));
+        assert!(result.is_complete());
+        assert!(result.finished.is_empty());
+    }
+}
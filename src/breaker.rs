@@ -0,0 +1,242 @@
+//! Circuit breakers around failing nameservers.
+//!
+//! A nameserver which has died costs every lookup sent to it a full timeout
+//! before the next one is tried. A [`CircuitBreaker`] counts the consecutive
+//! failures of each server, timeouts, errors and SERVFAIL alike, and once
+//! they reach a threshold opens the server's circuit: a
+//! [`DirectResolver`](crate::direct::DirectResolver) given the breaker then
+//! skips the server. After a cooldown the circuit is half open, and a
+//! single query is let through to probe the server; an answer closes the
+//! circuit and a failure opens it for another cooldown. Should every server
+//! be open they are all queried regardless, as failing without asking would
+//! be no better.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Consecutive failures opening a circuit unless told otherwise
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
+
+/// How long a circuit stays open unless told otherwise
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Whether queries are sent to a server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    /// Queries are sent
+    Closed,
+    /// The server failed too often and is skipped
+    Open,
+    /// The cooldown is over and the next query probes the server
+    HalfOpen,
+}
+
+impl fmt::Display for BreakerState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            BreakerState::Closed => "closed",
+            BreakerState::Open => "open",
+            BreakerState::HalfOpen => "half-open",
+        })
+    }
+}
+
+/// The health of one server, as kept by a breaker
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerHealth {
+    pub server: SocketAddr,
+    pub state: BreakerState,
+    /// Failures since the server last answered
+    pub consecutive_failures: u32,
+    /// Times the circuit has opened
+    pub trips: u64,
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    failures: u32,
+    opened: Option<Instant>,
+    probing: bool,
+    trips: u64,
+}
+
+impl Health {
+    fn state(&self, cooldown: Duration) -> BreakerState {
+        match self.opened {
+            None => BreakerState::Closed,
+            Some(opened) if opened.elapsed() < cooldown => BreakerState::Open,
+            Some(_) => BreakerState::HalfOpen,
+        }
+    }
+}
+
+/// Per nameserver circuit breakers.
+///
+/// Shared by the clones of a resolver, so each learns from all.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::breaker::{BreakerState, CircuitBreaker};
+///
+/// let breaker = CircuitBreaker::new().with_threshold(2);
+/// let server = "192.0.2.53:53".parse().unwrap();
+/// breaker.record_failure(server);
+/// assert!(breaker.allow(server));
+/// breaker.record_failure(server);
+/// assert_eq!(breaker.state(server), BreakerState::Open);
+/// assert!(!breaker.allow(server));
+/// ```
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    servers: Mutex<BTreeMap<SocketAddr, Health>>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CircuitBreaker {
+    /// Breaker opening after [`DEFAULT_FAILURE_THRESHOLD`] failures for the
+    /// [`DEFAULT_COOLDOWN`]
+    pub fn new() -> Self {
+        Self {
+            threshold: DEFAULT_FAILURE_THRESHOLD,
+            cooldown: DEFAULT_COOLDOWN,
+            servers: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Open a server's circuit after `failures` in a row, returning the
+    /// updated breaker
+    pub fn with_threshold(mut self, failures: u32) -> Self {
+        self.threshold = failures.max(1);
+        self
+    }
+
+    /// Keep a circuit open for `cooldown` before probing the server again,
+    /// returning the updated breaker
+    pub fn with_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    fn update<T, F: FnOnce(&mut Health) -> T>(&self, server: SocketAddr, update: F) -> T {
+        let mut servers = self
+            .servers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        update(servers.entry(server).or_default())
+    }
+
+    /// Whether a query may be sent to `server`. Once the cooldown is over
+    /// the first caller is let through as the probe, and the rest are not
+    /// for another cooldown, unless the probe is answered.
+    pub fn allow(&self, server: SocketAddr) -> bool {
+        let cooldown = self.cooldown;
+        self.update(server, |health| match health.state(cooldown) {
+            BreakerState::Closed => true,
+            BreakerState::Open => false,
+            BreakerState::HalfOpen => {
+                // a probe which is never recorded, being cancelled, is
+                // followed by another after the cooldown
+                health.opened = Some(Instant::now());
+                health.probing = true;
+                true
+            }
+        })
+    }
+
+    /// Record that `server` answered, closing its circuit
+    pub fn record_success(&self, server: SocketAddr) {
+        self.update(server, |health| {
+            health.failures = 0;
+            health.opened = None;
+            health.probing = false;
+        });
+    }
+
+    /// Record that `server` failed, opening its circuit if it has failed
+    /// too often or was being probed
+    pub fn record_failure(&self, server: SocketAddr) {
+        let threshold = self.threshold;
+        self.update(server, |health| {
+            health.failures = health.failures.saturating_add(1);
+            if health.probing || (health.opened.is_none() && health.failures >= threshold) {
+                health.opened = Some(Instant::now());
+                health.probing = false;
+                health.trips += 1;
+            }
+        });
+    }
+
+    /// The state of the circuit of `server`
+    pub fn state(&self, server: SocketAddr) -> BreakerState {
+        let servers = self
+            .servers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        servers
+            .get(&server)
+            .map_or(BreakerState::Closed, |health| health.state(self.cooldown))
+    }
+
+    /// The health of every server queried so far, by address
+    pub fn health(&self) -> Vec<ServerHealth> {
+        let servers = self
+            .servers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        servers
+            .iter()
+            .map(|(server, health)| ServerHealth {
+                server: *server,
+                state: health.state(self.cooldown),
+                consecutive_failures: health.failures,
+                trips: health.trips,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn an_answer_closes_the_circuit() {
+        let breaker = CircuitBreaker::new();
+        let server = "192.0.2.53:53".parse().unwrap();
+        breaker.record_failure(server);
+        breaker.record_failure(server);
+        breaker.record_success(server);
+        breaker.record_failure(server);
+        breaker.record_failure(server);
+        assert_eq!(breaker.state(server), BreakerState::Closed);
+        assert_eq!(breaker.health()[0].consecutive_failures, 2);
+    }
+    #[test]
+    fn one_probe_is_let_through_after_the_cooldown() {
+        let breaker = CircuitBreaker::new()
+            .with_threshold(1)
+            .with_cooldown(Duration::from_millis(20));
+        let server = "192.0.2.53:53".parse().unwrap();
+        breaker.record_failure(server);
+        assert!(!breaker.allow(server));
+        thread::sleep(Duration::from_millis(30));
+        assert_eq!(breaker.state(server), BreakerState::HalfOpen);
+        assert!(breaker.allow(server));
+        assert!(!breaker.allow(server));
+        // the probe failed, so the circuit opens again
+        breaker.record_failure(server);
+        assert_eq!(breaker.state(server), BreakerState::Open);
+        assert_eq!(breaker.health()[0].trips, 2);
+    }
+}
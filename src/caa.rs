@@ -0,0 +1,298 @@
+//! CAA records (RFC 8659): which certificate authorities may issue
+//! certificates for a domain.
+//!
+//! The records which apply to a name are those of the closest enclosing
+//! name which has any, so [`resolve_caa`] climbs from the name towards the
+//! root. [`CaaSet::permits`] then answers the question issuance tooling
+//! asks, treating unknown critical properties as RFC 8659 requires.
+use crate::direct::DirectResolver;
+use crate::dns::{RData, RecordType};
+use crate::errors::IpaddrConversionError;
+use crate::resolver::normalize_name;
+
+/// The flag marking a property critical: a certificate authority which does
+/// not understand it must not issue
+pub const CRITICAL_FLAG: u8 = 128;
+
+/// An `issue` or `issuewild` value: who may issue, and on what terms
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Issuer {
+    /// The domain of the authority, lowercased, or None if no authority may
+    /// issue
+    pub domain: Option<String>,
+    /// The `name=value` parameters after the domain, such as `accounturi`
+    pub parameters: Vec<(String, String)>,
+}
+
+impl Issuer {
+    /// Parse an `issue` or `issuewild` value, such as
+    /// `ca.example.net; account=230123`
+    pub fn parse(value: &str) -> Self {
+        let mut parts = value.split(';');
+        let domain = parts
+            .next()
+            .map(|domain| domain.trim().to_ascii_lowercase())
+            .filter(|domain| !domain.is_empty());
+        let parameters = parts
+            .filter_map(|parameter| {
+                let (name, value) = parameter.split_once('=')?;
+                Some((name.trim().to_string(), value.trim().to_string()))
+            })
+            .collect();
+        Issuer { domain, parameters }
+    }
+}
+
+/// The property of a CAA record
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CaaProperty {
+    /// Who may issue certificates for the name
+    Issue(Issuer),
+    /// Who may issue wildcard certificates for the name
+    IssueWild(Issuer),
+    /// Where to report requests which broke the policy, a `mailto:` or
+    /// `https:` url
+    Iodef(String),
+    /// A property this crate does not know
+    Other { tag: String, value: Vec<u8> },
+}
+
+/// A parsed CAA record
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CaaRecord {
+    /// Whether the property must be understood to issue
+    pub critical: bool,
+    pub property: CaaProperty,
+}
+
+impl From<crate::dns::Caa> for CaaRecord {
+    fn from(caa: crate::dns::Caa) -> Self {
+        let value = String::from_utf8_lossy(&caa.value);
+        let property = match caa.tag.to_ascii_lowercase().as_str() {
+            "issue" => CaaProperty::Issue(Issuer::parse(&value)),
+            "issuewild" => CaaProperty::IssueWild(Issuer::parse(&value)),
+            "iodef" => CaaProperty::Iodef(value.into_owned()),
+            _ => CaaProperty::Other {
+                tag: caa.tag,
+                value: caa.value,
+            },
+        };
+        CaaRecord {
+            critical: caa.flags & CRITICAL_FLAG != 0,
+            property,
+        }
+    }
+}
+
+/// The CAA records applying to a name
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CaaSet {
+    /// The name the records were found at, the name asked about or one
+    /// enclosing it, or None if no enclosing name has any
+    pub domain: Option<String>,
+    pub records: Vec<CaaRecord>,
+}
+
+impl CaaSet {
+    /// The issuers of the `issue` records
+    pub fn issuers(&self) -> impl Iterator<Item = &Issuer> {
+        self.records
+            .iter()
+            .filter_map(|record| match record.property {
+                CaaProperty::Issue(ref issuer) => Some(issuer),
+                _ => None,
+            })
+    }
+
+    /// The issuers of the `issuewild` records
+    pub fn wildcard_issuers(&self) -> impl Iterator<Item = &Issuer> {
+        self.records
+            .iter()
+            .filter_map(|record| match record.property {
+                CaaProperty::IssueWild(ref issuer) => Some(issuer),
+                _ => None,
+            })
+    }
+
+    /// Where violations should be reported
+    pub fn iodef(&self) -> impl Iterator<Item = &str> {
+        self.records
+            .iter()
+            .filter_map(|record| match record.property {
+                CaaProperty::Iodef(ref url) => Some(url.as_str()),
+                _ => None,
+            })
+    }
+
+    /// Whether the authority with domain `issuer` may issue a certificate
+    ///
+    /// # Parameters
+    ///
+    /// * `issuer` - the domain the authority identifies itself by, such as
+    ///   `letsencrypt.org`
+    /// * `wildcard` - whether the certificate is for a wildcard name, so
+    ///   `issuewild` records take precedence
+    ///
+    /// # Returns
+    /// False if a critical property is not understood. Otherwise true if
+    /// no record restricts issuance, or one names `issuer`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ipaddr_from_str::caa::{CaaProperty, CaaRecord, CaaSet, Issuer};
+    ///
+    /// let set = CaaSet {
+    ///     domain: Some("example.com".into()),
+    ///     records: vec![CaaRecord {
+    ///         critical: false,
+    ///         property: CaaProperty::Issue(Issuer::parse("letsencrypt.org")),
+    ///     }],
+    /// };
+    /// assert!(set.permits("LetsEncrypt.org", false));
+    /// assert!(!set.permits("ca.example.net", true));
+    /// ```
+    pub fn permits(&self, issuer: &str, wildcard: bool) -> bool {
+        if self
+            .records
+            .iter()
+            .any(|record| record.critical && matches!(record.property, CaaProperty::Other { .. }))
+        {
+            return false;
+        }
+        let issuer = normalize_name(issuer);
+        let mut relevant: Vec<&Issuer> = if wildcard {
+            self.wildcard_issuers().collect()
+        } else {
+            Vec::new()
+        };
+        if relevant.is_empty() {
+            relevant = self.issuers().collect();
+        }
+        relevant.is_empty()
+            || relevant
+                .iter()
+                .any(|candidate| candidate.domain.as_deref() == Some(issuer.as_str()))
+    }
+}
+
+/// The CAA records applying to `domain`, found through the system's
+/// nameservers
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::caa::resolve_caa;
+///
+/// let set = resolve_caa("www.example.com").unwrap();
+/// if !set.permits("letsencrypt.org", false) {
+///     eprintln!("CAA records of {:?} forbid issuing", set.domain);
+/// }
+/// ```
+pub fn resolve_caa(domain: &str) -> Result<CaaSet, IpaddrConversionError> {
+    resolve_caa_with(&DirectResolver::from_system()?, domain)
+}
+
+/// The CAA records applying to `domain`: those of `domain`, or if it has
+/// none those of its parent, and so on up to the top level domain
+///
+/// # Parameters
+///
+/// * `resolver` - queries the CAA records
+/// * `domain` - the name a certificate is wanted for; a leading `*.` is
+///   dropped
+///
+/// # Returns
+/// The records, an empty set if no enclosing name has any, or the error of
+/// a failing query, since issuing without a policy which exists is unsafe
+pub fn resolve_caa_with(
+    resolver: &DirectResolver,
+    domain: &str,
+) -> Result<CaaSet, IpaddrConversionError> {
+    let domain = normalize_name(domain);
+    let mut name = domain.strip_prefix("*.").unwrap_or(&domain);
+    loop {
+        let records: Vec<CaaRecord> = match resolver.query(name, RecordType::Caa) {
+            Ok(response) => response
+                .answers
+                .into_iter()
+                .filter_map(|record| match record.data {
+                    RData::Caa(caa) => Some(caa.into()),
+                    _ => None,
+                })
+                .collect(),
+            Err(IpaddrConversionError::NotFound(_)) => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        if !records.is_empty() {
+            return Ok(CaaSet {
+                domain: Some(name.to_string()),
+                records,
+            });
+        }
+        match name.split_once('.') {
+            Some((_, parent)) if !parent.is_empty() => name = parent,
+            _ => return Ok(CaaSet::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::direct::tests::{answer, config_for, spawn_udp_server};
+    use crate::dns::{Caa, RCODE_NXDOMAIN};
+
+    fn caa(flags: u8, tag: &str, value: &str) -> RData {
+        RData::Caa(Caa {
+            flags,
+            tag: tag.into(),
+            value: value.as_bytes().to_vec(),
+        })
+    }
+
+    #[test]
+    fn records_are_found_up_the_tree() {
+        let server = spawn_udp_server(|request| {
+            let data = match request.questions[0].name.as_str() {
+                "example.com" => vec![
+                    caa(0, "issue", "letsencrypt.org"),
+                    caa(0, "issuewild", ";"),
+                    caa(0, "iodef", "mailto:security@example.com"),
+                ],
+                "strict.example.com" => vec![caa(CRITICAL_FLAG, "tbs", "unknown")],
+                "com" | "www.example.com" => vec![],
+                _ => return Some(answer(request, RCODE_NXDOMAIN, vec![])),
+            };
+            Some(answer(request, 0, data))
+        });
+        let resolver = DirectResolver::new(config_for(vec![server]));
+        let set = resolve_caa_with(&resolver, "a.www.example.com").unwrap();
+        assert_eq!(set.domain.as_deref(), Some("example.com"));
+        assert_eq!(
+            set.iodef().collect::<Vec<_>>(),
+            vec!["mailto:security@example.com"]
+        );
+        assert!(set.permits("letsencrypt.org", false));
+        assert!(!set.permits("letsencrypt.org", true));
+        assert!(!set.permits("ca.example.net", false));
+        let strict = resolve_caa_with(&resolver, "*.strict.example.com").unwrap();
+        assert!(!strict.permits("letsencrypt.org", false));
+        let open = resolve_caa_with(&resolver, "example.org").unwrap();
+        assert_eq!(open, CaaSet::default());
+        assert!(open.permits("ca.example.net", true));
+    }
+    #[test]
+    fn issuers_are_parsed() {
+        let issuer = Issuer::parse(" CA.example.net ; account=230123; policy=ev");
+        assert_eq!(issuer.domain.as_deref(), Some("ca.example.net"));
+        assert_eq!(
+            issuer.parameters,
+            vec![
+                ("account".to_string(), "230123".to_string()),
+                ("policy".to_string(), "ev".to_string())
+            ]
+        );
+        assert_eq!(Issuer::parse(";").domain, None);
+    }
+}
@@ -0,0 +1,608 @@
+//! Caching of resolution results.
+//!
+//! [`CachingResolver`] keeps the answers of another resolver for a fixed time
+//! to live. The cache can be persisted to a file so that short lived processes
+//! still benefit from earlier lookups: entries are written when the resolver is
+//! dropped, reloaded when it is created, and any which expired in between are
+//! revalidated on first use rather than up front.
+//!
+//! Entries are held in a [`CacheBackend`], in memory by default. Backing the
+//! cache with a store shared by a fleet, such as Redis or memcached, lets
+//! every instance answer from the lookups of the others; with a negative
+//! time to live, names which do not exist are shared as well.
+use crate::deadline;
+use crate::dump::{CacheDump, DebugDump};
+use crate::errors::IpaddrConversionError;
+use crate::resolver::{normalize_name, Resolve};
+use crate::stats::StatsCollector;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const FILE_HEADER: &str = "# ipaddr-from-str cache v1";
+
+/// A cached answer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheEntry {
+    /// The addresses of the name, or none for a name which does not exist
+    pub addrs: Vec<IpAddr>,
+    /// When the answer should be revalidated
+    pub expires: SystemTime,
+}
+
+impl CacheEntry {
+    /// Whether the entry records that the name does not exist
+    pub fn is_negative(&self) -> bool {
+        self.addrs.is_empty()
+    }
+}
+
+/// Where a [`CachingResolver`] keeps its entries.
+///
+/// Names are normalized before they reach the backend. An entry past its
+/// expiry is still asked for, to be served if revalidating it fails; a
+/// shared store which drops entries once they expire gives that up.
+/// Backends report their own failures by behaving as a miss.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::cache::{CacheBackend, CacheEntry, CachingResolver, MemoryBackend};
+/// use ipaddr_from_str::{Resolve, StaticResolver};
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// let shared = Arc::new(MemoryBackend::new());
+/// let resolver = StaticResolver::new().with_entry("db", vec!["10.0.0.1".parse().unwrap()]);
+/// let first = CachingResolver::with_backend(resolver, Duration::from_secs(60), shared.clone());
+/// first.resolve("db").unwrap();
+/// // a second instance answers from the entry the first stored
+/// let second = CachingResolver::with_backend(StaticResolver::new(), Duration::from_secs(60), shared);
+/// assert!(second.resolve("db").is_ok());
+/// ```
+pub trait CacheBackend: fmt::Debug + Send + Sync {
+    /// The entry for `name`, expired or not
+    fn get(&self, name: &str) -> Option<CacheEntry>;
+
+    /// Store `entry` for `name`, replacing any already there
+    fn insert(&self, name: &str, entry: CacheEntry);
+
+    /// Every entry held. Used to save and dump the cache, so a shared store
+    /// may return only what it can list cheaply.
+    fn entries(&self) -> Vec<(String, CacheEntry)>;
+
+    /// Drop every entry
+    fn clear(&self);
+
+    /// Number of entries held
+    fn len(&self) -> usize {
+        self.entries().len()
+    }
+
+    /// Whether no entries are held
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Make room to insert `name` into a cache holding at most
+    /// `max_entries`. Does nothing by default, for stores which evict by
+    /// themselves.
+    fn make_room(&self, _name: &str, _max_entries: usize) {}
+}
+
+/// Entries held in this process, the default backend
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    entries: Mutex<HashMap<String, CacheEntry>>,
+}
+
+impl MemoryBackend {
+    /// New backend, holding nothing
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, CacheEntry>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl CacheBackend for MemoryBackend {
+    fn get(&self, name: &str) -> Option<CacheEntry> {
+        self.lock().get(name).cloned()
+    }
+
+    fn insert(&self, name: &str, entry: CacheEntry) {
+        self.lock().insert(name.to_string(), entry);
+    }
+
+    fn entries(&self) -> Vec<(String, CacheEntry)> {
+        self.lock()
+            .iter()
+            .map(|(name, entry)| (name.clone(), entry.clone()))
+            .collect()
+    }
+
+    fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Drops the entry closest to expiring
+    fn make_room(&self, name: &str, max_entries: usize) {
+        let mut entries = self.lock();
+        if entries.len() < max_entries || entries.contains_key(name) {
+            return;
+        }
+        let soonest = entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.expires)
+            .map(|(name, _)| name.clone());
+        if let Some(soonest) = soonest {
+            entries.remove(&soonest);
+        }
+    }
+}
+
+/// Resolver remembering the answers of another resolver.
+///
+/// Clones share their entries, and the cache file of a persistent cache is
+/// written once the last clone is dropped.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::cache::CachingResolver;
+/// use ipaddr_from_str::{Resolve, SystemResolver};
+/// use std::time::Duration;
+///
+/// let resolver = CachingResolver::new(SystemResolver, Duration::from_secs(60));
+/// assert!(resolver.resolve("127.0.0.1").is_ok());
+/// ```
+#[derive(Debug, Clone)]
+pub struct CachingResolver<R> {
+    resolver: R,
+    ttl: Duration,
+    negative_ttl: Option<Duration>,
+    serve_stale: bool,
+    max_entries: Option<usize>,
+    shared: Arc<Shared>,
+    stats: Option<Arc<StatsCollector>>,
+}
+
+/// The state common to every clone of a cache
+#[derive(Debug)]
+struct Shared {
+    path: Option<PathBuf>,
+    backend: Arc<dyn CacheBackend>,
+}
+
+impl<R: Resolve> CachingResolver<R> {
+    /// New cache in front of `resolver`, keeping answers for `ttl`
+    pub fn new(resolver: R, ttl: Duration) -> Self {
+        Self::with_backend(resolver, ttl, Arc::new(MemoryBackend::new()))
+    }
+
+    /// New cache in front of `resolver`, keeping answers for `ttl` in
+    /// `backend`, which may be shared with other caches
+    pub fn with_backend(resolver: R, ttl: Duration, backend: Arc<dyn CacheBackend>) -> Self {
+        Self {
+            resolver,
+            ttl,
+            negative_ttl: None,
+            serve_stale: true,
+            max_entries: None,
+            shared: Arc::new(Shared {
+                path: None,
+                backend,
+            }),
+            stats: None,
+        }
+    }
+
+    /// New cache backed by the file at `path`. Entries in the file are loaded
+    /// now, and the cache is written back when the resolver is dropped. A
+    /// missing file is treated as an empty cache.
+    pub fn persistent<P: Into<PathBuf>>(
+        resolver: R,
+        ttl: Duration,
+        path: P,
+    ) -> Result<Self, IpaddrConversionError> {
+        let mut cache = Self::new(resolver, ttl);
+        let path = path.into();
+        match cache.load(&path) {
+            Ok(()) => {}
+            Err(IpaddrConversionError::IoError(ref err))
+                if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        // set only after loading, so a file which fails to load is never
+        // overwritten
+        Arc::get_mut(&mut cache.shared)
+            .expect("a new cache is not shared")
+            .path = Some(path);
+        Ok(cache)
+    }
+
+    /// Remember names which do not exist for `ttl`, returning the updated
+    /// resolver. Not found answers are not cached by default.
+    pub fn with_negative_ttl(mut self, ttl: Duration) -> Self {
+        self.negative_ttl = Some(ttl);
+        self
+    }
+
+    /// Set whether an expired entry is returned when revalidating it fails,
+    /// returning the updated resolver. Defaults to true.
+    pub fn with_serve_stale(mut self, serve_stale: bool) -> Self {
+        self.serve_stale = serve_stale;
+        self
+    }
+
+    /// Hold at most `max_entries` answers, dropping the one closest to
+    /// expiring to make room for another, returning the updated resolver.
+    /// Unbounded by default. Shared backends may evict by their own rules
+    /// instead.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries.max(1));
+        self
+    }
+
+    /// Record hits and misses in `collector`, returning the updated resolver
+    pub fn with_stats(mut self, collector: Arc<StatsCollector>) -> Self {
+        self.stats = Some(collector);
+        self
+    }
+
+    /// The backend holding the entries
+    pub fn backend(&self) -> &Arc<dyn CacheBackend> {
+        &self.shared.backend
+    }
+
+    /// Number of entries held, including expired ones awaiting revalidation
+    pub fn len(&self) -> usize {
+        self.shared.backend.len()
+    }
+
+    /// Whether the cache holds no entries
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drop every entry
+    pub fn clear(&self) {
+        self.shared.backend.clear();
+    }
+
+    /// The entries held and how the cache is configured, for debugging
+    pub fn debug_dump(&self) -> DebugDump {
+        let mut entries: Vec<_> = self
+            .shared
+            .backend
+            .entries()
+            .into_iter()
+            .map(|(name, entry)| (name, entry.addrs, entry.expires))
+            .collect();
+        entries.sort();
+        let dump = DebugDump::new().with_cache(CacheDump {
+            ttl: self.ttl,
+            serve_stale: self.serve_stale,
+            max_entries: self.max_entries,
+            entries,
+        });
+        match self.stats {
+            Some(ref collector) => dump.with_stats(collector),
+            None => dump,
+        }
+    }
+
+    /// Write the cache to `path`.
+    ///
+    /// The file holds one entry per line: the name, the expiry as seconds
+    /// since the unix epoch, and a comma separated list of addresses. It is
+    /// written to a temporary file first and renamed into place, so readers
+    /// never see a partial cache. Names which do not exist are not saved.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), IpaddrConversionError> {
+        Ok(save_entries(path.as_ref(), self.shared.backend.as_ref())?)
+    }
+
+    /// Merge the entries saved in `path` into the cache. Malformed lines are
+    /// skipped.
+    pub fn load<P: AsRef<Path>>(&self, path: P) -> Result<(), IpaddrConversionError> {
+        let contents = fs::read_to_string(path)?;
+        for line in contents.lines().filter(|l| !l.starts_with('#')) {
+            let mut fields = line.split_whitespace();
+            let (name, expires, addrs) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(name), Some(expires), Some(addrs)) => (name, expires, addrs),
+                _ => continue,
+            };
+            let expires = match expires.parse::<u64>() {
+                Ok(secs) => match UNIX_EPOCH.checked_add(Duration::from_secs(secs)) {
+                    Some(expires) => expires,
+                    None => continue,
+                },
+                Err(_) => continue,
+            };
+            let addrs = addrs
+                .split(',')
+                .map(|a| a.parse::<IpAddr>())
+                .collect::<Result<Vec<_>, _>>();
+            if let Ok(addrs) = addrs {
+                self.shared
+                    .backend
+                    .insert(&normalize_name(name), CacheEntry { addrs, expires });
+            }
+        }
+        Ok(())
+    }
+
+    fn store(&self, key: &str, addrs: Vec<IpAddr>, ttl: Duration) {
+        if let Some(max) = self.max_entries {
+            self.shared.backend.make_room(key, max);
+        }
+        self.shared.backend.insert(
+            key,
+            CacheEntry {
+                addrs,
+                expires: deadline::system_time_after(ttl),
+            },
+        );
+    }
+}
+
+impl<R: Resolve> Resolve for CachingResolver<R> {
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        if let Ok(ip) = hostname_or_address.parse::<IpAddr>() {
+            return Ok(vec![ip]);
+        }
+        let key = normalize_name(hostname_or_address);
+        let now = SystemTime::now();
+        let fresh = match self.shared.backend.get(&key) {
+            Some(entry) if entry.expires > now => Ok(entry.addrs),
+            Some(entry) => Err(Some(entry.addrs)),
+            None => Err(None),
+        };
+        if let Some(ref stats) = self.stats {
+            stats.record_cache(fresh.is_ok());
+        }
+        let stale = match fresh {
+            Ok(ref addrs) if addrs.is_empty() => {
+                return Err(IpaddrConversionError::NotFound(
+                    hostname_or_address.to_string(),
+                ))
+            }
+            Ok(addrs) => return Ok(addrs),
+            // a stale negative answer is no better than none
+            Err(stale) => stale.filter(|addrs| !addrs.is_empty()),
+        };
+        // the backend is not locked while resolving, so a slow lookup does
+        // not block answers for other names
+        match self.resolver.resolve(hostname_or_address) {
+            Ok(addrs) => {
+                // an empty answer, kept, would be read back as a negative one
+                if !addrs.is_empty() {
+                    self.store(&key, addrs.clone(), self.ttl);
+                }
+                Ok(addrs)
+            }
+            Err(err) => match stale {
+                Some(addrs) if self.serve_stale => Ok(addrs),
+                _ => {
+                    if let (IpaddrConversionError::NotFound(_), Some(ttl)) =
+                        (&err, self.negative_ttl)
+                    {
+                        self.store(&key, Vec::new(), ttl);
+                    }
+                    Err(err)
+                }
+            },
+        }
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        // errors cannot be reported from drop; a failed save only costs the
+        // next process some lookups
+        if let Some(path) = self.path.take() {
+            let _ = save_entries(&path, self.backend.as_ref());
+        }
+    }
+}
+
+fn format_entry(name: &str, entry: &CacheEntry) -> String {
+    let expires = entry
+        .expires
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let addrs = entry
+        .addrs
+        .iter()
+        .map(|ip| ip.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("{} {} {}\n", name, expires, addrs)
+}
+
+fn save_entries(path: &Path, backend: &dyn CacheBackend) -> io::Result<()> {
+    let mut contents = String::from(FILE_HEADER);
+    contents.push('\n');
+    for (name, entry) in backend.entries().iter() {
+        if !entry.is_negative() {
+            contents.push_str(&format_entry(name, entry));
+        }
+    }
+    let tmp = path.with_extension("tmp");
+    fs::File::create(&tmp)?.write_all(contents.as_bytes())?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::StaticResolver;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Counts lookups reaching the wrapped resolver
+    #[derive(Clone)]
+    struct Counting {
+        inner: StaticResolver,
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl Resolve for Counting {
+        fn resolve(&self, host: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            self.inner.resolve(host)
+        }
+    }
+
+    fn counting(entries: &[(&str, &str)]) -> (Counting, Arc<AtomicUsize>) {
+        let mut inner = StaticResolver::new();
+        for (name, addr) in entries {
+            inner.insert(*name, vec![addr.parse().unwrap()]);
+        }
+        let calls = Arc::new(AtomicUsize::new(0));
+        (
+            Counting {
+                inner,
+                calls: calls.clone(),
+            },
+            calls,
+        )
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ipaddr-cache-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn answers_are_cached_until_they_expire() {
+        let (backend, calls) = counting(&[("a.example", "10.0.0.1")]);
+        let cache = CachingResolver::new(backend, Duration::from_secs(60));
+        cache.resolve("a.example").unwrap();
+        cache.resolve("A.example.").unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let (backend, calls) = counting(&[("a.example", "10.0.0.1")]);
+        let cache = CachingResolver::new(backend, Duration::from_secs(0));
+        cache.resolve("a.example").unwrap();
+        cache.resolve("a.example").unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+    #[test]
+    fn overlong_ttls_are_kept_rather_than_overflowing() {
+        let (backend, calls) = counting(&[("a.example", "10.0.0.1")]);
+        let cache = CachingResolver::new(backend, Duration::from_secs(u64::MAX));
+        cache.resolve("a.example").unwrap();
+        cache.resolve("a.example").unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+    #[test]
+    fn persisted_entries_are_reloaded() {
+        let path = temp_path("reload");
+        {
+            let (backend, _) = counting(&[("a.example", "10.0.0.1")]);
+            let cache =
+                CachingResolver::persistent(backend, Duration::from_secs(60), &path).unwrap();
+            cache.resolve("a.example").unwrap();
+        }
+        let (backend, calls) = counting(&[]);
+        let cache = CachingResolver::persistent(backend, Duration::from_secs(60), &path).unwrap();
+        assert_eq!(
+            cache.resolve("a.example").unwrap(),
+            vec!["10.0.0.1".parse::<IpAddr>().unwrap()]
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        drop(cache);
+        fs::remove_file(&path).unwrap();
+    }
+    #[test]
+    fn clones_share_entries() {
+        let (backend, calls) = counting(&[("a.example", "10.0.0.1")]);
+        let cache = CachingResolver::new(backend, Duration::from_secs(60));
+        let clone = cache.clone();
+        std::thread::spawn(move || clone.resolve("a.example").unwrap())
+            .join()
+            .unwrap();
+        cache.resolve("a.example").unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.len(), 1);
+    }
+    #[test]
+    fn full_caches_drop_the_entry_expiring_first() {
+        let (backend, calls) = counting(&[("a.example", "10.0.0.1"), ("b.example", "10.0.0.2")]);
+        let cache = CachingResolver::new(backend, Duration::from_secs(60)).with_max_entries(1);
+        cache.resolve("a.example").unwrap();
+        cache.resolve("b.example").unwrap();
+        assert_eq!(cache.len(), 1);
+        cache.resolve("b.example").unwrap();
+        cache.resolve("a.example").unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+    #[test]
+    fn stale_entries_are_revalidated_lazily() {
+        let path = temp_path("stale");
+        fs::write(
+            &path,
+            "A.example. 1 10.0.0.1\nb.example 1 10.0.0.2\nbad line\n",
+        )
+        .unwrap();
+        let (backend, calls) = counting(&[("a.example", "10.0.0.9")]);
+        let cache = CachingResolver::new(backend, Duration::from_secs(60));
+        cache.load(&path).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        // refreshed from the backend
+        assert_eq!(
+            cache.resolve("a.example").unwrap(),
+            vec!["10.0.0.9".parse::<IpAddr>().unwrap()]
+        );
+        // unknown to the backend, so the stale answer is served
+        assert_eq!(
+            cache.resolve("b.example").unwrap(),
+            vec!["10.0.0.2".parse::<IpAddr>().unwrap()]
+        );
+        let cache = cache.with_serve_stale(false);
+        assert!(cache.resolve("b.example").is_err());
+        fs::remove_file(&path).unwrap();
+    }
+    #[test]
+    fn empty_answers_are_returned_but_not_kept() {
+        let (mut backend, calls) = counting(&[]);
+        backend.inner.insert("empty.example", vec![]);
+        let cache = CachingResolver::new(backend, Duration::from_secs(60));
+        assert!(cache.resolve("empty.example").unwrap().is_empty());
+        assert!(cache.resolve("empty.example").unwrap().is_empty());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert!(cache.is_empty());
+    }
+    #[test]
+    fn missing_names_are_cached_for_the_negative_ttl() {
+        let (backend, calls) = counting(&[]);
+        let cache = CachingResolver::new(backend, Duration::from_secs(60))
+            .with_negative_ttl(Duration::from_secs(30));
+        assert!(cache.resolve("missing.example").is_err());
+        match cache.resolve("Missing.example.") {
+            Err(IpaddrConversionError::NotFound(name)) => assert_eq!(name, "Missing.example."),
+            other => panic!("expected NotFound, got {:?}", other),
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(cache
+            .backend()
+            .get("missing.example")
+            .unwrap()
+            .is_negative());
+    }
+}
@@ -0,0 +1,199 @@
+//! Shutting down resolvers and abandoning lookups.
+//!
+//! A [`CancellationToken`] is shared between whoever decides to stop and the
+//! resolvers, watchers and lookups that should stop: once cancelled,
+//! [`DirectResolver`](crate::direct::DirectResolver) queries in flight give
+//! up within a few milliseconds, [`Watcher`](crate::watch::Watcher) loops
+//! return, and lookups wrapped in [`Cancellable`] return at once. Lookups
+//! through the system resolver cannot be interrupted, so `Cancellable` runs
+//! them on a thread of their own which is left to finish in the background.
+use crate::deadline;
+use crate::errors::IpaddrConversionError;
+use crate::resolver::Resolve;
+use std::net::IpAddr;
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Longest a blocked wait goes without checking for cancellation
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// A flag which, once set, stays set, and which can be waited on.
+///
+/// Clones share the flag.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::cancel::CancellationToken;
+/// use std::time::Duration;
+///
+/// let token = CancellationToken::new();
+/// let canceller = token.clone();
+/// std::thread::spawn(move || canceller.cancel());
+/// // returns early, as soon as the other thread cancels
+/// assert!(token.sleep(Duration::from_secs(60)));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    state: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl CancellationToken {
+    /// New token, not yet cancelled
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel, waking everything sleeping on the token
+    pub fn cancel(&self) {
+        let (ref cancelled, ref wakeup) = *self.state;
+        *cancelled
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = true;
+        wakeup.notify_all();
+    }
+
+    /// Whether the token has been cancelled
+    pub fn is_cancelled(&self) -> bool {
+        *self
+            .state
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Sleep for `duration` or until cancelled, returning whether cancelled
+    pub fn sleep(&self, duration: Duration) -> bool {
+        let (ref cancelled, ref wakeup) = *self.state;
+        let deadline = deadline::after(duration);
+        let mut guard = cancelled
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        while !*guard {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_secs(0) {
+                return false;
+            }
+            guard = wakeup
+                .wait_timeout(guard, remaining)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+        }
+        true
+    }
+}
+
+/// The error for a lookup of `name` abandoned by cancellation
+pub(crate) fn cancelled(name: &str) -> IpaddrConversionError {
+    IpaddrConversionError::Cancelled(name.to_string())
+}
+
+/// Resolver whose lookups return as soon as its token is cancelled, even if
+/// the resolver it wraps cannot be interrupted.
+#[derive(Debug, Clone)]
+pub struct Cancellable<R> {
+    resolver: R,
+    token: CancellationToken,
+}
+
+impl<R> Cancellable<R>
+where
+    R: Resolve + Clone + Send + 'static,
+{
+    /// Wrap `resolver`, abandoning its lookups once `token` is cancelled
+    pub fn new(resolver: R, token: CancellationToken) -> Self {
+        Self { resolver, token }
+    }
+
+    /// The token lookups are abandoned on
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    /// Cancel the token, abandoning every lookup in flight
+    pub fn shutdown(&self) {
+        self.token.cancel();
+    }
+}
+
+impl<R> Resolve for Cancellable<R>
+where
+    R: Resolve + Clone + Send + 'static,
+{
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        if self.token.is_cancelled() {
+            return Err(cancelled(hostname_or_address));
+        }
+        let (tx, rx) = mpsc::channel();
+        let resolver = self.resolver.clone();
+        let name = hostname_or_address.to_string();
+        let ambient = deadline::current();
+        thread::spawn(move || {
+            let _ = tx.send(deadline::inherit(ambient, || resolver.resolve(&name)));
+        });
+        loop {
+            match rx.recv_timeout(POLL_INTERVAL) {
+                Ok(result) => return result,
+                Err(mpsc::RecvTimeoutError::Timeout) if !self.token.is_cancelled() => {}
+                Err(mpsc::RecvTimeoutError::Timeout) => return Err(cancelled(hostname_or_address)),
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    return Err(IpaddrConversionError::DnsError(format!(
+                        "lookup of {} panicked",
+                        hostname_or_address
+                    )))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Resolver which never answers
+    #[derive(Clone)]
+    struct Stuck;
+
+    impl Resolve for Stuck {
+        fn resolve(&self, _: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+            thread::sleep(Duration::from_secs(3600));
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn stuck_lookups_are_abandoned() {
+        let resolver = Cancellable::new(Stuck, CancellationToken::new());
+        let canceller = resolver.token().clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            canceller.cancel();
+        });
+        let started = Instant::now();
+        assert!(matches!(
+            resolver.resolve("db.example"),
+            Err(IpaddrConversionError::Cancelled(ref name)) if name == "db.example"
+        ));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(resolver.resolve("db.example").is_err());
+    }
+    #[test]
+    fn sleep_runs_out_when_not_cancelled() {
+        let token = CancellationToken::new();
+        assert!(!token.sleep(Duration::from_millis(10)));
+        token.cancel();
+        assert!(token.is_cancelled() && token.sleep(Duration::from_secs(60)));
+    }
+    #[test]
+    fn sleeping_forever_is_still_cancelled() {
+        let token = CancellationToken::new();
+        let canceller = token.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            canceller.cancel();
+        });
+        assert!(token.sleep(Duration::MAX));
+    }
+}
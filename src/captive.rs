@@ -0,0 +1,328 @@
+//! Detecting captive portals, which intercept traffic until a user signs in.
+//!
+//! Hotel and airport networks often resolve every name, or hand out an
+//! address, and then answer every plain http request with a redirect to a
+//! sign-in page. Names resolve, but nothing else works. Operating systems
+//! detect this by fetching well-known probe urls whose content is known;
+//! [`detect_captive_portal`] fetches the same probes and reports whether the
+//! answers, or the name resolution behind them, were tampered with.
+use crate::errors::IpaddrConversionError;
+use crate::resolver::{Resolve, SystemResolver};
+use crate::socket::SocketOptions;
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+/// How long each probe may take to connect, and to answer, by default
+pub const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Most of a probe response read; probe responses are tiny, portal pages
+/// often are not
+const MAX_RESPONSE: u64 = 64 * 1024;
+
+/// What an uncaptured probe answers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expectation {
+    /// A response with this status, such as 204 No Content
+    Status(u16),
+    /// A 200 response whose body, trimmed, is this text
+    Body(String),
+}
+
+/// A plain http url with a known answer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Probe {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+    pub expected: Expectation,
+}
+
+impl Probe {
+    /// Probe of `http://host/path` on port 80
+    pub fn new(host: &str, path: &str, expected: Expectation) -> Self {
+        Self {
+            host: host.to_string(),
+            port: 80,
+            path: path.to_string(),
+            expected,
+        }
+    }
+}
+
+/// The probes Android, Apple and Windows use, in that order
+pub fn well_known_probes() -> Vec<Probe> {
+    vec![
+        Probe::new(
+            "connectivitycheck.gstatic.com",
+            "/generate_204",
+            Expectation::Status(204),
+        ),
+        Probe::new(
+            "captive.apple.com",
+            "/hotspot-detect.html",
+            Expectation::Body(
+                "<HTML><HEAD><TITLE>Success</TITLE></HEAD><BODY>Success</BODY></HTML>".into(),
+            ),
+        ),
+        Probe::new(
+            "www.msftconnecttest.com",
+            "/connecttest.txt",
+            Expectation::Body("Microsoft Connect Test".into()),
+        ),
+    ]
+}
+
+/// How a probe was interfered with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Interception {
+    /// The probe's name resolved to private or otherwise unroutable
+    /// addresses, as portals answering every name do
+    HijackedAnswer(Vec<IpAddr>),
+    /// The probe was redirected, usually to the sign-in page, with the
+    /// `Location` given if there was one
+    Redirect(Option<String>),
+    /// The probe answered with another status
+    Status(u16),
+    /// The probe answered 200, but with other content
+    Body,
+}
+
+/// What the probes found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PortalStatus {
+    /// A probe answered as expected, so the network is open
+    Open,
+    /// A probe was intercepted
+    Intercepted {
+        host: String,
+        interception: Interception,
+    },
+    /// No probe could be resolved or fetched, so the network may be down
+    /// rather than captured
+    Unreachable,
+}
+
+impl PortalStatus {
+    /// Whether a portal was detected
+    pub fn is_captive(&self) -> bool {
+        matches!(self, PortalStatus::Intercepted { .. })
+    }
+}
+
+/// Whether a portal could have given `addr` in answer for a public name.
+/// Loopback is left out, as no portal answers with it.
+fn is_unroutable(addr: &IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(v4) => {
+            v4.is_private()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                // shared address space, RFC 6598
+                || (v4.octets()[0] == 100 && v4.octets()[1] & 0xc0 == 64)
+        }
+        IpAddr::V6(v6) => {
+            let first = v6.segments()[0];
+            v6.is_unspecified() || first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80
+        }
+    }
+}
+
+/// What a raw http `response` to `probe` says about interception, or None
+/// if it is the expected answer
+fn assess(probe: &Probe, response: &[u8]) -> Option<Interception> {
+    let text = String::from_utf8_lossy(response);
+    let (head, body) = text.split_once("\r\n\r\n").unwrap_or((&text, ""));
+    let mut lines = head.lines();
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse::<u16>().ok())
+        .unwrap_or(0);
+    if (300..400).contains(&status) {
+        let location = lines.find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            if name.trim().eq_ignore_ascii_case("location") {
+                Some(value.trim().to_string())
+            } else {
+                None
+            }
+        });
+        return Some(Interception::Redirect(location));
+    }
+    match &probe.expected {
+        Expectation::Status(expected) if status == *expected => None,
+        Expectation::Body(expected) if status == 200 => {
+            if body.trim() == expected {
+                None
+            } else {
+                Some(Interception::Body)
+            }
+        }
+        _ => Some(Interception::Status(status)),
+    }
+}
+
+/// Fetch `probe` from `addr`, returning the raw response
+fn fetch(
+    probe: &Probe,
+    addr: SocketAddr,
+    timeout: Duration,
+) -> Result<Vec<u8>, IpaddrConversionError> {
+    let mut stream = SocketOptions::new()
+        .connect_timeout(timeout)
+        .connect(addr)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    // HTTP/1.0, so the answer is never chunked
+    write!(
+        stream,
+        "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: ipaddr-from-str\r\nConnection: close\r\n\r\n",
+        probe.path, probe.host
+    )?;
+    let mut response = Vec::new();
+    stream.take(MAX_RESPONSE).read_to_end(&mut response)?;
+    Ok(response)
+}
+
+/// Probe the [well-known probes](well_known_probes) through the system
+/// resolver, with [`DEFAULT_PROBE_TIMEOUT`].
+///
+/// This sends real requests to the probes' servers.
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::captive::detect_captive_portal;
+///
+/// if detect_captive_portal().is_captive() {
+///     eprintln!("sign in to the network first");
+/// }
+/// ```
+pub fn detect_captive_portal() -> PortalStatus {
+    detect_captive_portal_with(&SystemResolver, &well_known_probes(), DEFAULT_PROBE_TIMEOUT)
+}
+
+/// Try `probes` in order, resolving them through `resolver`, until one
+/// gives a verdict
+///
+/// # Parameters
+///
+/// * `resolver` - resolves the probes' hosts
+/// * `probes` - the probes to try; the first which can be fetched decides
+/// * `timeout` - how long each connection may take to connect, and to answer
+///
+/// # Returns
+/// Open if the first probe fetched answered as expected, Intercepted if it
+/// did not or its name resolved to unroutable addresses, or Unreachable if
+/// no probe could be fetched
+pub fn detect_captive_portal_with<R: Resolve + ?Sized>(
+    resolver: &R,
+    probes: &[Probe],
+    timeout: Duration,
+) -> PortalStatus {
+    for probe in probes {
+        let addrs = match resolver.resolve(&probe.host) {
+            Ok(addrs) => addrs,
+            Err(_) => continue,
+        };
+        if !addrs.is_empty() && addrs.iter().all(is_unroutable) {
+            return PortalStatus::Intercepted {
+                host: probe.host.clone(),
+                interception: Interception::HijackedAnswer(addrs),
+            };
+        }
+        let response = addrs
+            .iter()
+            .find_map(|ip| fetch(probe, SocketAddr::new(*ip, probe.port), timeout).ok());
+        let response = match response {
+            Some(response) if !response.is_empty() => response,
+            _ => continue,
+        };
+        return match assess(probe, &response) {
+            None => PortalStatus::Open,
+            Some(interception) => PortalStatus::Intercepted {
+                host: probe.host.clone(),
+                interception,
+            },
+        };
+    }
+    PortalStatus::Unreachable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::StaticResolver;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// A probe of a local server answering every request with `response`
+    fn serve(response: &'static str, expected: Expectation) -> (StaticResolver, Probe) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request);
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+        let resolver = StaticResolver::new()
+            .with_entry("probe.test", vec!["127.0.0.1".parse().unwrap()])
+            .with_entry("hijacked.test", vec!["10.1.1.1".parse().unwrap()]);
+        let mut probe = Probe::new("probe.test", "/generate_204", expected);
+        probe.port = port;
+        (resolver, probe)
+    }
+
+    #[test]
+    fn responses_are_assessed() {
+        let probe = Probe::new("example.com", "/", Expectation::Body("Success".into()));
+        assert_eq!(assess(&probe, b"HTTP/1.1 200 OK\r\n\r\nSuccess\n"), None);
+        assert_eq!(
+            assess(&probe, b"HTTP/1.1 200 OK\r\n\r\n<html>Sign in</html>"),
+            Some(Interception::Body)
+        );
+        assert_eq!(
+            assess(
+                &probe,
+                b"HTTP/1.1 302 Found\r\nlocation: http://portal.local/\r\n\r\n"
+            ),
+            Some(Interception::Redirect(Some("http://portal.local/".into())))
+        );
+        let probe = Probe::new("example.com", "/", Expectation::Status(204));
+        assert_eq!(assess(&probe, b"HTTP/1.1 204 No Content\r\n\r\n"), None);
+        assert_eq!(
+            assess(&probe, b"HTTP/1.1 200 OK\r\n\r\n"),
+            Some(Interception::Status(200))
+        );
+    }
+    #[test]
+    fn probes_are_fetched_in_order() {
+        let timeout = Duration::from_secs(2);
+        let (resolver, probe) = serve("HTTP/1.1 204 No Content\r\n\r\n", Expectation::Status(204));
+        let missing = Probe::new("missing.test", "/", Expectation::Status(204));
+        assert_eq!(
+            detect_captive_portal_with(&resolver, &[missing.clone(), probe], timeout),
+            PortalStatus::Open
+        );
+        let (resolver, probe) = serve(
+            "HTTP/1.1 302 Found\r\nLocation: http://10.0.0.1/login\r\n\r\n",
+            Expectation::Status(204),
+        );
+        let status = detect_captive_portal_with(&resolver, &[probe], timeout);
+        assert!(status.is_captive());
+        let hijacked = Probe::new("hijacked.test", "/", Expectation::Status(204));
+        assert!(matches!(
+            detect_captive_portal_with(&resolver, &[hijacked], timeout),
+            PortalStatus::Intercepted {
+                interception: Interception::HijackedAnswer(_),
+                ..
+            }
+        ));
+        assert_eq!(
+            detect_captive_portal_with(&resolver, &[missing], timeout),
+            PortalStatus::Unreachable
+        );
+    }
+}
@@ -0,0 +1,215 @@
+//! A resolver backend built on c-ares.
+//!
+//! Enabled by the `c-ares` feature, for environments standardized on c-ares.
+//! [`CAresResolver`] looks up A and AAAA records at once, through the
+//! blocking [`Resolve`] trait or without blocking through
+//! [`resolve_async`](CAresResolver::resolve_async), whose queries need no
+//! runtime of their own: c-ares drives them on its own event thread.
+use crate::errors::IpaddrConversionError;
+use crate::resolv_conf::ResolvConf;
+use crate::resolver::Resolve;
+use c_ares_resolver::{BlockingResolver, FutureResolver, Options};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::thread;
+
+/// Resolver querying nameservers through c-ares.
+///
+/// Clones share the underlying c-ares channels.
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::cares::CAresResolver;
+/// use ipaddr_from_str::Resolve;
+///
+/// let resolver = CAresResolver::from_system().unwrap();
+/// let addrs = resolver.resolve("example.com").unwrap();
+/// ```
+#[derive(Clone)]
+pub struct CAresResolver {
+    blocking: Arc<BlockingResolver>,
+    future: Arc<FutureResolver>,
+}
+
+impl CAresResolver {
+    /// Resolver configured from the system, as c-ares reads it
+    pub fn from_system() -> Result<Self, IpaddrConversionError> {
+        Self::with_options(Options::new, &[])
+    }
+
+    /// Resolver querying the nameservers of `conf`, with its search list,
+    /// ndots, timeout and attempts
+    pub fn from_resolv_conf(conf: &ResolvConf) -> Result<Self, IpaddrConversionError> {
+        let options = || {
+            let mut options = Options::new();
+            let domains: Vec<&str> = conf.search.iter().map(String::as_str).collect();
+            options
+                .set_domains(&domains)
+                .set_ndots(conf.ndots as u32)
+                .set_timeout(conf.timeout.as_millis() as u32)
+                .set_tries(conf.attempts as u32);
+            let mut flags = c_ares::Flags::empty();
+            if !conf.rotate {
+                flags |= c_ares::Flags::NOROTATE;
+            }
+            if conf.use_vc {
+                flags |= c_ares::Flags::USEVC;
+            }
+            options.set_flags(flags);
+            options
+        };
+        let servers: Vec<String> = conf.nameservers.iter().map(|s| s.to_string()).collect();
+        Self::with_options(options, &servers)
+    }
+
+    /// Build both channels from `options`, pointed at `servers` unless that
+    /// is empty
+    fn with_options<F: Fn() -> Options>(
+        options: F,
+        servers: &[String],
+    ) -> Result<Self, IpaddrConversionError> {
+        let servers: Vec<&str> = servers.iter().map(String::as_str).collect();
+        let blocking = BlockingResolver::with_options(options()).map_err(resolver_error)?;
+        let future = FutureResolver::with_options(options()).map_err(resolver_error)?;
+        if !servers.is_empty() {
+            blocking
+                .set_servers(&servers)
+                .map_err(|err| ares_error("", err))?;
+            future
+                .set_servers(&servers)
+                .map_err(|err| ares_error("", err))?;
+        }
+        Ok(Self {
+            blocking: Arc::new(blocking),
+            future: Arc::new(future),
+        })
+    }
+
+    /// Resolve `hostname_or_address` without blocking
+    pub async fn resolve_async(
+        &self,
+        hostname_or_address: &str,
+    ) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        if let Ok(ip) = hostname_or_address.parse::<IpAddr>() {
+            return Ok(vec![ip]);
+        }
+        // both queries are sent before either is awaited
+        let v4 = self.future.query_a(hostname_or_address);
+        let v6 = self.future.query_aaaa(hostname_or_address);
+        let v4 = v4
+            .await
+            .map(|results| results.iter().map(|r| IpAddr::V4(r.ipv4())).collect());
+        let v6 = v6
+            .await
+            .map(|results| results.iter().map(|r| IpAddr::V6(r.ipv6())).collect());
+        merge(hostname_or_address, v4, v6)
+    }
+}
+
+impl std::fmt::Debug for CAresResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("CAresResolver").finish_non_exhaustive()
+    }
+}
+
+impl Resolve for CAresResolver {
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        if let Ok(ip) = hostname_or_address.parse::<IpAddr>() {
+            return Ok(vec![ip]);
+        }
+        let blocking = &self.blocking;
+        let (v4, v6) = thread::scope(|scope| {
+            let v6 = scope.spawn(|| {
+                blocking
+                    .query_aaaa(hostname_or_address)
+                    .map(|results| results.iter().map(|r| IpAddr::V6(r.ipv6())).collect())
+            });
+            let v4 = blocking
+                .query_a(hostname_or_address)
+                .map(|results| results.iter().map(|r| IpAddr::V4(r.ipv4())).collect());
+            (v4, v6.join().expect("AAAA query panicked"))
+        });
+        merge(hostname_or_address, v4, v6)
+    }
+}
+
+/// Combine the answers for each family: what either found, or the error if
+/// neither found anything
+fn merge(
+    name: &str,
+    v4: c_ares::Result<Vec<IpAddr>>,
+    v6: c_ares::Result<Vec<IpAddr>>,
+) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+    let mut addrs = Vec::new();
+    let mut failure = None;
+    for result in [v4, v6] {
+        match result {
+            Ok(found) => addrs.extend(found),
+            // no records of one family says nothing of the other
+            Err(c_ares::Error::ENODATA) => {}
+            Err(err) => {
+                failure.get_or_insert(err);
+            }
+        }
+    }
+    match failure {
+        Some(err) if addrs.is_empty() => Err(ares_error(name, err)),
+        _ if addrs.is_empty() => Err(IpaddrConversionError::NotFound(name.to_string())),
+        _ => Ok(addrs),
+    }
+}
+
+/// Map c-ares's error for a lookup of `name` onto the crate's
+fn ares_error(name: &str, err: c_ares::Error) -> IpaddrConversionError {
+    match err {
+        c_ares::Error::ENOTFOUND | c_ares::Error::ENODATA => {
+            IpaddrConversionError::NotFound(name.to_string())
+        }
+        c_ares::Error::ETIMEOUT => IpaddrConversionError::Timeout(name.to_string()),
+        err => IpaddrConversionError::DnsError(err.to_string()),
+    }
+}
+
+fn resolver_error(err: c_ares_resolver::Error) -> IpaddrConversionError {
+    match err {
+        c_ares_resolver::Error::Io(err) => IpaddrConversionError::IoError(err),
+        c_ares_resolver::Error::Ares(err) => ares_error("", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_family_missing_is_not_an_error() {
+        let v4: IpAddr = "192.0.2.1".parse().unwrap();
+        assert_eq!(
+            merge("a.example", Ok(vec![v4]), Err(c_ares::Error::ENODATA)).unwrap(),
+            vec![v4]
+        );
+        assert!(matches!(
+            merge("a.example", Err(c_ares::Error::ETIMEOUT), Ok(vec![])),
+            Err(IpaddrConversionError::Timeout(_))
+        ));
+        assert!(matches!(
+            merge(
+                "a.example",
+                Err(c_ares::Error::ENOTFOUND),
+                Err(c_ares::Error::ENOTFOUND)
+            ),
+            Err(IpaddrConversionError::NotFound(_))
+        ));
+    }
+    #[test]
+    fn literals_resolve_without_querying() {
+        let mut conf = ResolvConf::default();
+        conf.nameservers = vec!["127.0.0.1:9".parse().unwrap()];
+        let resolver = CAresResolver::from_resolv_conf(&conf).unwrap();
+        assert_eq!(
+            resolver.resolve("10.0.0.1").unwrap(),
+            vec!["10.0.0.1".parse::<IpAddr>().unwrap()]
+        );
+    }
+}
@@ -0,0 +1,344 @@
+//! Blocks of addresses: CIDR prefixes and inclusive ranges.
+use crate::address::Address;
+use crate::parse::{parse_cidr, parse_range, ParseError};
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// The most blocks [`Cidr::split`] gives, enough to split a /16 into
+/// single addresses
+pub const MAX_SPLIT: usize = 1 << 16;
+
+/// An address and prefix length, such as `10.0.0.0/8`.
+///
+/// The address is kept as given, so `10.1.2.3/8` remembers the host part;
+/// [`network`](Cidr::network) gives the first address of the block.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::cidr::Cidr;
+///
+/// let block: Cidr = "10.1.2.3/8".parse().unwrap();
+/// assert_eq!(block.network().to_string(), "10.0.0.0");
+/// assert!(block.contains("10.200.0.1".parse().unwrap()));
+/// assert!(!block.contains("11.0.0.1".parse().unwrap()));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "diesel",
+    derive(diesel::expression::AsExpression, diesel::deserialize::FromSqlRow)
+)]
+#[cfg_attr(feature = "diesel", diesel(sql_type = diesel::sql_types::Cidr))]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// New block, or None if `prefix_len` is longer than the address
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Option<Self> {
+        if prefix_len > Address::new(addr).bits() {
+            return None;
+        }
+        Some(Self { addr, prefix_len })
+    }
+
+    /// The address as given
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    /// The number of leading bits fixed by the block
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// The first address of the block
+    pub fn network(&self) -> IpAddr {
+        Address::new(self.addr)
+            .with_prefix(self.prefix_len)
+            .expect("prefix length checked")
+            .ip()
+    }
+
+    /// Whether `addr` falls within the block. Addresses of the other family
+    /// never do.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        addr.is_ipv4() == self.addr.is_ipv4()
+            && Address::new(addr).with_prefix(self.prefix_len) == Some(self.network().into())
+    }
+
+    /// The address of the block's family with `value`
+    fn at(&self, value: u128) -> IpAddr {
+        match self.addr {
+            IpAddr::V4(_) => Address::from_u32(value as u32).ip(),
+            IpAddr::V6(_) => Address::from_u128(value).ip(),
+        }
+    }
+
+    /// The number of host bits
+    fn host_bits(&self) -> u32 {
+        (Address::new(self.addr).bits() - self.prefix_len) as u32
+    }
+
+    /// The netmask, such as `255.255.255.0` for a /24
+    pub fn netmask(&self) -> IpAddr {
+        Address::new(self.addr)
+            .netmask(self.prefix_len)
+            .expect("prefix length checked")
+            .ip()
+    }
+
+    /// The wildcard mask, the inverse of the netmask, such as `0.0.0.255`
+    /// for a /24
+    pub fn wildcard_mask(&self) -> IpAddr {
+        self.at(u128::MAX.checked_shr(128 - self.host_bits()).unwrap_or(0))
+    }
+
+    /// The last address of the block, the broadcast address of an IPv4
+    /// block
+    pub fn last(&self) -> IpAddr {
+        let network = Address::new(self.network()).to_u128();
+        self.at(network | Address::new(self.wildcard_mask()).to_u128())
+    }
+
+    /// The number of addresses in the block, saturating at `u128::MAX` for
+    /// `::/0`
+    pub fn size(&self) -> u128 {
+        1u128.checked_shl(self.host_bits()).unwrap_or(u128::MAX)
+    }
+
+    /// The number of addresses usable by hosts: all but the network and
+    /// broadcast addresses of an IPv4 block, except that both addresses of a
+    /// /31 are usable (RFC 3021), and every address of an IPv6 block
+    pub fn host_count(&self) -> u128 {
+        match self.addr {
+            IpAddr::V4(_) if self.prefix_len < 31 => self.size() - 2,
+            _ => self.size(),
+        }
+    }
+
+    /// The first address usable by a host, as [`host_count`](Self::host_count)
+    /// counts them
+    pub fn first_host(&self) -> IpAddr {
+        match self.addr {
+            IpAddr::V4(_) if self.prefix_len < 31 => {
+                self.at(Address::new(self.network()).to_u128() + 1)
+            }
+            _ => self.network(),
+        }
+    }
+
+    /// The last address usable by a host, as [`host_count`](Self::host_count)
+    /// counts them
+    pub fn last_host(&self) -> IpAddr {
+        match self.addr {
+            IpAddr::V4(_) if self.prefix_len < 31 => {
+                self.at(Address::new(self.last()).to_u128() - 1)
+            }
+            _ => self.last(),
+        }
+    }
+
+    /// The blocks of length `prefix_len` making up this one, in order
+    ///
+    /// # Returns
+    /// The blocks, or None if `prefix_len` is shorter than the block's own
+    /// or longer than the address
+    pub fn subnets(&self, prefix_len: u8) -> Option<impl Iterator<Item = Cidr>> {
+        if prefix_len < self.prefix_len || prefix_len > Address::new(self.addr).bits() {
+            return None;
+        }
+        let block = *self;
+        let network = Address::new(self.network()).to_u128();
+        let step_bits = (Address::new(self.addr).bits() - prefix_len) as u32;
+        let count = 1u128
+            .checked_shl((prefix_len - self.prefix_len) as u32)
+            .unwrap_or(u128::MAX);
+        Some((0..count).map_while(move |idx| {
+            // the only block of ::/0 taken whole is shifted by all 128 bits
+            let offset = match idx.checked_shl(step_bits) {
+                Some(offset) => offset,
+                None if idx == 0 => 0,
+                None => return None,
+            };
+            Some(Cidr {
+                addr: block.at(network.checked_add(offset)?),
+                prefix_len,
+            })
+        }))
+    }
+
+    /// Split the block into `count` equal blocks, as `ipcalc` does
+    ///
+    /// # Returns
+    /// The blocks in order, or None if `count` is not a power of two, is
+    /// more than [`MAX_SPLIT`], or the block has too few addresses to split
+    /// that many ways. Use [`subnets`](Self::subnets) to walk more blocks
+    /// than that without collecting them.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ipaddr_from_str::cidr::Cidr;
+    ///
+    /// let block: Cidr = "192.0.2.0/24".parse().unwrap();
+    /// let quarters: Vec<String> = block.split(4).unwrap().iter().map(Cidr::to_string).collect();
+    /// assert_eq!(quarters, ["192.0.2.0/26", "192.0.2.64/26", "192.0.2.128/26", "192.0.2.192/26"]);
+    /// assert!(block.split(3).is_none());
+    /// ```
+    pub fn split(&self, count: usize) -> Option<Vec<Cidr>> {
+        if !count.is_power_of_two() || count > MAX_SPLIT {
+            return None;
+        }
+        let extra = count.trailing_zeros();
+        let prefix_len = u8::try_from(self.prefix_len as u32 + extra).ok()?;
+        Some(self.subnets(prefix_len)?.collect())
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_cidr(s)
+    }
+}
+
+/// An inclusive range of addresses of one family, such as
+/// `10.0.0.1-10.0.0.9`.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::cidr::IpRange;
+///
+/// let range: IpRange = "10.0.0.1-10.0.0.9".parse().unwrap();
+/// assert!(range.contains("10.0.0.9".parse().unwrap()));
+/// assert!(!range.contains("10.0.0.10".parse().unwrap()));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "diesel",
+    derive(diesel::expression::AsExpression, diesel::deserialize::FromSqlRow)
+)]
+#[cfg_attr(feature = "diesel", diesel(sql_type = diesel::sql_types::Text))]
+pub struct IpRange {
+    start: IpAddr,
+    end: IpAddr,
+}
+
+impl IpRange {
+    /// New range, or None if the addresses are of different families or
+    /// `end` is below `start`
+    pub fn new(start: IpAddr, end: IpAddr) -> Option<Self> {
+        if start.is_ipv4() != end.is_ipv4() || end < start {
+            return None;
+        }
+        Some(Self { start, end })
+    }
+
+    /// The first address of the range
+    pub fn start(&self) -> IpAddr {
+        self.start
+    }
+
+    /// The last address of the range
+    pub fn end(&self) -> IpAddr {
+        self.end
+    }
+
+    /// Whether `addr` falls within the range
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        addr.is_ipv4() == self.start.is_ipv4() && self.start <= addr && addr <= self.end
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_range(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefix_edges() {
+        let all: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains("255.255.255.255".parse().unwrap()));
+        assert!(!all.contains("::1".parse().unwrap()));
+        let host: Cidr = "2001:db8::1/128".parse().unwrap();
+        assert!(host.contains("2001:db8::1".parse().unwrap()));
+        assert!(!host.contains("2001:db8::2".parse().unwrap()));
+        let block: Cidr = "2001:db8:abcd::/33".parse().unwrap();
+        assert_eq!(block.network().to_string(), "2001:db8:8000::");
+        assert!(Cidr::new("10.0.0.0".parse().unwrap(), 33).is_none());
+    }
+    #[test]
+    fn calculator_figures() {
+        let block: Cidr = "10.1.2.3/22".parse().unwrap();
+        assert_eq!(block.netmask().to_string(), "255.255.252.0");
+        assert_eq!(block.wildcard_mask().to_string(), "0.0.3.255");
+        assert_eq!(block.last().to_string(), "10.1.3.255");
+        assert_eq!((block.size(), block.host_count()), (1024, 1022));
+        assert_eq!(block.first_host().to_string(), "10.1.0.1");
+        assert_eq!(block.last_host().to_string(), "10.1.3.254");
+        let p2p: Cidr = "192.0.2.6/31".parse().unwrap();
+        assert_eq!(
+            (p2p.host_count(), p2p.first_host().to_string()),
+            (2, "192.0.2.6".into())
+        );
+        let all: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert_eq!(all.last().to_string(), "255.255.255.255");
+        assert_eq!(all.wildcard_mask().to_string(), "255.255.255.255");
+        let v6: Cidr = "2001:db8::/126".parse().unwrap();
+        assert_eq!(
+            (v6.host_count(), v6.last_host().to_string()),
+            (4, "2001:db8::3".into())
+        );
+        assert_eq!("::/0".parse::<Cidr>().unwrap().size(), u128::MAX);
+        let host: Cidr = "2001:db8::1/128".parse().unwrap();
+        assert_eq!(host.split(2), None);
+        assert_eq!(
+            block.split(1).unwrap(),
+            vec!["10.1.0.0/22".parse().unwrap()]
+        );
+    }
+    #[test]
+    fn the_whole_ipv6_space_splits_into_itself() {
+        let all: Cidr = "::/0".parse().unwrap();
+        assert_eq!(all.subnets(0).unwrap().collect::<Vec<_>>(), vec![all]);
+        assert_eq!(all.split(1).unwrap(), vec![all]);
+        let halves: Vec<String> = all.split(2).unwrap().iter().map(Cidr::to_string).collect();
+        assert_eq!(halves, ["::/1", "8000::/1"]);
+        assert_eq!(all.split(MAX_SPLIT).unwrap().len(), MAX_SPLIT);
+        assert_eq!(all.split(MAX_SPLIT * 2), None);
+        assert_eq!(all.split(1 << 63), None);
+    }
+    #[test]
+    fn ranges_are_ordered_and_single_family() {
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.5".parse().unwrap();
+        assert!(IpRange::new(b, a).is_none());
+        assert!(IpRange::new(a, "::1".parse().unwrap()).is_none());
+        assert_eq!(IpRange::new(a, b).unwrap().to_string(), "10.0.0.1-10.0.0.5");
+    }
+}
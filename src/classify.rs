@@ -0,0 +1,171 @@
+//! What kind of address an address is, by the IANA special-purpose
+//! registries.
+//!
+//! Deciding whether an address may be connected to, logged or exposed
+//! comes down to which special-purpose block it falls in, if any.
+//! [`classify`] names the block, with [`AddrClass::Global`] for ordinary
+//! addresses.
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// The special-purpose block an address is in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AddrClass {
+    /// `0.0.0.0` or `::`
+    Unspecified,
+    /// 127/8 or `::1`
+    Loopback,
+    /// The RFC 1918 blocks 10/8, 172.16/12 and 192.168/16
+    Private,
+    /// 100.64/10, shared by carrier-grade NAT
+    Shared,
+    /// 169.254/16 or fe80::/10
+    LinkLocal,
+    /// The example blocks 192.0.2/24, 198.51.100/24, 203.0.113/24 and
+    /// 2001:db8::/32
+    Documentation,
+    /// 198.18/15, for network benchmarks
+    Benchmarking,
+    /// 224/4 or ff00::/8
+    Multicast,
+    /// `255.255.255.255`
+    Broadcast,
+    /// fc00::/7
+    UniqueLocal,
+    /// 0/8, 192.0.0/24, 240/4 and the other blocks no host should have
+    Reserved,
+    /// Any other address
+    Global,
+}
+
+impl AddrClass {
+    /// Whether addresses of the class are reachable across the internet
+    pub fn is_global(&self) -> bool {
+        matches!(self, AddrClass::Global | AddrClass::Multicast)
+    }
+}
+
+impl fmt::Display for AddrClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            AddrClass::Unspecified => "unspecified",
+            AddrClass::Loopback => "loopback",
+            AddrClass::Private => "private",
+            AddrClass::Shared => "shared",
+            AddrClass::LinkLocal => "link-local",
+            AddrClass::Documentation => "documentation",
+            AddrClass::Benchmarking => "benchmarking",
+            AddrClass::Multicast => "multicast",
+            AddrClass::Broadcast => "broadcast",
+            AddrClass::UniqueLocal => "unique-local",
+            AddrClass::Reserved => "reserved",
+            AddrClass::Global => "global",
+        })
+    }
+}
+
+fn classify_v4(addr: Ipv4Addr) -> AddrClass {
+    let octets = addr.octets();
+    if addr.is_unspecified() {
+        AddrClass::Unspecified
+    } else if addr.is_loopback() {
+        AddrClass::Loopback
+    } else if addr.is_private() {
+        AddrClass::Private
+    } else if octets[0] == 100 && octets[1] & 0xc0 == 64 {
+        AddrClass::Shared
+    } else if addr.is_link_local() {
+        AddrClass::LinkLocal
+    } else if addr.is_documentation() {
+        AddrClass::Documentation
+    } else if octets[0] == 198 && octets[1] & 0xfe == 18 {
+        AddrClass::Benchmarking
+    } else if addr.is_multicast() {
+        AddrClass::Multicast
+    } else if addr.is_broadcast() {
+        AddrClass::Broadcast
+    } else if octets[0] == 0 || octets[0] >= 240 || octets[..3] == [192, 0, 0] {
+        AddrClass::Reserved
+    } else {
+        AddrClass::Global
+    }
+}
+
+fn classify_v6(addr: Ipv6Addr) -> AddrClass {
+    let segments = addr.segments();
+    if addr.is_unspecified() {
+        AddrClass::Unspecified
+    } else if addr.is_loopback() {
+        AddrClass::Loopback
+    } else if segments[0] & 0xffc0 == 0xfe80 {
+        AddrClass::LinkLocal
+    } else if segments[0] & 0xfe00 == 0xfc00 {
+        AddrClass::UniqueLocal
+    } else if segments[..2] == [0x2001, 0xdb8] {
+        AddrClass::Documentation
+    } else if addr.is_multicast() {
+        AddrClass::Multicast
+    } else if segments[0] == 0x100 && segments[1..4] == [0, 0, 0] {
+        // the discard-only block 100::/64
+        AddrClass::Reserved
+    } else {
+        AddrClass::Global
+    }
+}
+
+/// The special-purpose block `addr` is in. An IPv4-mapped IPv6 address is
+/// classified as its IPv4 address.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::classify::{classify, AddrClass};
+///
+/// assert_eq!(classify("10.1.2.3".parse().unwrap()), AddrClass::Private);
+/// assert_eq!(classify("100.72.0.1".parse().unwrap()), AddrClass::Shared);
+/// assert_eq!(classify("fd00::1".parse().unwrap()).to_string(), "unique-local");
+/// assert!(classify("1.1.1.1".parse().unwrap()).is_global());
+/// ```
+pub fn classify(addr: IpAddr) -> AddrClass {
+    match addr.to_canonical() {
+        IpAddr::V4(v4) => classify_v4(v4),
+        IpAddr::V6(v6) => classify_v6(v6),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn class(addr: &str) -> AddrClass {
+        classify(addr.parse().unwrap())
+    }
+
+    #[test]
+    fn ipv4_blocks() {
+        assert_eq!(class("0.0.0.0"), AddrClass::Unspecified);
+        assert_eq!(class("0.1.2.3"), AddrClass::Reserved);
+        assert_eq!(class("127.8.8.8"), AddrClass::Loopback);
+        assert_eq!(class("172.31.255.255"), AddrClass::Private);
+        assert_eq!(class("172.32.0.0"), AddrClass::Global);
+        assert_eq!(class("100.127.255.255"), AddrClass::Shared);
+        assert_eq!(class("100.128.0.0"), AddrClass::Global);
+        assert_eq!(class("198.19.0.1"), AddrClass::Benchmarking);
+        assert_eq!(class("203.0.113.9"), AddrClass::Documentation);
+        assert_eq!(class("239.1.1.1"), AddrClass::Multicast);
+        assert_eq!(class("250.0.0.1"), AddrClass::Reserved);
+        assert_eq!(class("255.255.255.255"), AddrClass::Broadcast);
+        assert_eq!(class("::ffff:192.168.1.1"), AddrClass::Private);
+    }
+    #[test]
+    fn ipv6_blocks() {
+        assert_eq!(class("::"), AddrClass::Unspecified);
+        assert_eq!(class("::1"), AddrClass::Loopback);
+        assert_eq!(class("febf::1"), AddrClass::LinkLocal);
+        assert_eq!(class("fc12::1"), AddrClass::UniqueLocal);
+        assert_eq!(class("2001:db8:1::1"), AddrClass::Documentation);
+        assert_eq!(class("ff02::1"), AddrClass::Multicast);
+        assert_eq!(class("100::1"), AddrClass::Reserved);
+        assert_eq!(class("2606:4700::1111"), AddrClass::Global);
+    }
+}
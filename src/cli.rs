@@ -0,0 +1,145 @@
+//! Argument parsers for clap.
+//!
+//! Enabled by the `clap` feature. [`Cidr`], [`IpRange`], [`HostOrIp`] and
+//! [`HostPort`] implement `ValueParserFactory`, so `value_parser!` picks up
+//! parsers whose errors quote the argument and point at the mistake:
+//!
+//! ```text
+//! error: invalid value '10.0.0.0/33' for '--network <CIDR>': expected prefix length of at most 32 at byte 9
+//!   10.0.0.0/33
+//!            ^
+//! ```
+//!
+//! A `HostPort` parsed through `value_parser!` must include its port; use
+//! [`host_port_with_default`] to allow leaving it out.
+//!
+//! # Example
+//!
+//! ```
+//! use clap::{value_parser, Arg, Command};
+//! use ipaddr_from_str::cidr::Cidr;
+//! use ipaddr_from_str::cli::host_port_with_default;
+//! use ipaddr_from_str::host::HostPort;
+//!
+//! let cmd = Command::new("ipam")
+//!     .arg(Arg::new("network").long("network").value_parser(value_parser!(Cidr)))
+//!     .arg(Arg::new("db").long("db").value_parser(host_port_with_default(5432)));
+//! let matches = cmd.get_matches_from(["ipam", "--network", "10.0.0.0/8", "--db", "db.example.com"]);
+//! assert_eq!(matches.get_one::<Cidr>("network").unwrap().prefix_len(), 8);
+//! assert_eq!(matches.get_one::<HostPort>("db").unwrap().port, 5432);
+//! ```
+use crate::cidr::{Cidr, IpRange};
+use crate::dns::RecordType;
+use crate::host::{HostOrIp, HostPort};
+use crate::parse::ParseError;
+use clap::builder::{TypedValueParser, ValueParserFactory};
+use clap::error::ErrorKind;
+use clap::{Arg, Command, Error};
+use std::ffi::OsStr;
+use std::sync::Arc;
+
+/// One of the crate's parsers, as an `ArgParser` holds it
+type ParseFn<T> = dyn Fn(&str) -> Result<T, ParseError> + Send + Sync;
+
+/// A clap parser built on one of the crate's parsers
+#[derive(Clone)]
+pub struct ArgParser<T> {
+    parse: Arc<ParseFn<T>>,
+}
+
+impl<T> ArgParser<T> {
+    fn new<F>(parse: F) -> Self
+    where
+        F: Fn(&str) -> Result<T, ParseError> + Send + Sync + 'static,
+    {
+        Self {
+            parse: Arc::new(parse),
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for ArgParser<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ArgParser").finish()
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> TypedValueParser for ArgParser<T> {
+    type Value = T;
+
+    fn parse_ref(&self, cmd: &Command, arg: Option<&Arg>, value: &OsStr) -> Result<T, Error> {
+        let text = value
+            .to_str()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidUtf8).with_cmd(cmd))?;
+        (self.parse)(text).map_err(|err| {
+            let name = arg.map_or_else(|| "...".to_string(), |arg| arg.to_string());
+            let message = format!(
+                "invalid value '{}' for '{}': {}\n",
+                text,
+                name,
+                err.annotate(text)
+            );
+            Error::raw(ErrorKind::ValueValidation, message).with_cmd(cmd)
+        })
+    }
+}
+
+/// Parser for a [`HostPort`] which uses `default_port` when the argument has
+/// no port
+pub fn host_port_with_default(default_port: u16) -> ArgParser<HostPort> {
+    ArgParser::new(move |text| HostPort::parse_with_default(text, default_port))
+}
+
+macro_rules! from_str_parser {
+    ($($ty:ty),*) => {
+        $(
+            impl ValueParserFactory for $ty {
+                type Parser = ArgParser<$ty>;
+
+                fn value_parser() -> Self::Parser {
+                    ArgParser::new(|text| text.parse())
+                }
+            }
+        )*
+    };
+}
+
+from_str_parser!(Cidr, IpRange, HostOrIp, HostPort, RecordType);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::value_parser;
+
+    fn command() -> Command {
+        Command::new("test")
+            .arg(
+                Arg::new("range")
+                    .long("range")
+                    .value_parser(value_parser!(IpRange)),
+            )
+            .arg(
+                Arg::new("peer")
+                    .long("peer")
+                    .value_parser(host_port_with_default(53)),
+            )
+    }
+
+    #[test]
+    fn errors_point_at_the_mistake() {
+        let err = command()
+            .try_get_matches_from(["test", "--range", "10.0.0.9-10.0.0.1"])
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ValueValidation);
+        let message = err.to_string();
+        assert!(message.contains("address no lower than the start at byte 9"));
+        assert!(message.contains("\n  10.0.0.9-10.0.0.1\n           ^"));
+    }
+    #[test]
+    fn default_port_applies() {
+        let matches = command()
+            .try_get_matches_from(["test", "--peer", "ns1.example.com"])
+            .unwrap();
+        assert_eq!(matches.get_one::<HostPort>("peer").unwrap().port, 53);
+    }
+}
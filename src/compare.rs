@@ -0,0 +1,195 @@
+//! Comparing the answers several resolvers give for the same name.
+//!
+//! Anycast, CDN steering and split-horizon DNS all make a name resolve
+//! differently depending on who asks. [`compare`] puts the name to a set of
+//! labelled resolvers, typically [`DirectResolver`](crate::direct::DirectResolver)s
+//! pointed at different nameservers, and reports where their answers differ.
+use crate::errors::IpaddrConversionError;
+use crate::resolver::Resolve;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::net::IpAddr;
+use std::thread;
+
+/// What one resolver answered
+#[derive(Debug)]
+pub struct Answer {
+    /// The label the resolver was given
+    pub vantage: String,
+    pub result: Result<Vec<IpAddr>, IpaddrConversionError>,
+}
+
+impl Answer {
+    fn addr_set(&self) -> Option<BTreeSet<IpAddr>> {
+        self.result
+            .as_ref()
+            .ok()
+            .map(|addrs| addrs.iter().copied().collect())
+    }
+}
+
+/// The answers for one name, in the order the resolvers were given
+#[derive(Debug)]
+pub struct Comparison {
+    pub name: String,
+    pub answers: Vec<Answer>,
+}
+
+impl Comparison {
+    /// Whether every resolver answered, with the same set of addresses
+    pub fn is_consistent(&self) -> bool {
+        let mut sets = self.answers.iter().map(Answer::addr_set);
+        match sets.next() {
+            Some(Some(first)) => sets.all(|set| set.as_ref() == Some(&first)),
+            Some(None) => false,
+            None => true,
+        }
+    }
+
+    /// The addresses every resolver that answered agrees on
+    pub fn common(&self) -> Vec<IpAddr> {
+        let mut sets = self.answers.iter().filter_map(Answer::addr_set);
+        let first = match sets.next() {
+            Some(first) => first,
+            None => return Vec::new(),
+        };
+        sets.fold(first, |acc, set| acc.intersection(&set).copied().collect())
+            .into_iter()
+            .collect()
+    }
+
+    /// The addresses `vantage` answered that no other resolver did
+    pub fn only_from(&self, vantage: &str) -> Vec<IpAddr> {
+        let ours = match self
+            .answers
+            .iter()
+            .find(|answer| answer.vantage == vantage)
+            .and_then(Answer::addr_set)
+        {
+            Some(ours) => ours,
+            None => return Vec::new(),
+        };
+        let others: BTreeSet<IpAddr> = self
+            .answers
+            .iter()
+            .filter(|answer| answer.vantage != vantage)
+            .filter_map(Answer::addr_set)
+            .flatten()
+            .collect();
+        ours.difference(&others).copied().collect()
+    }
+
+    /// The resolvers which failed, with their errors
+    pub fn failures(&self) -> impl Iterator<Item = (&str, &IpaddrConversionError)> {
+        self.answers
+            .iter()
+            .filter_map(|answer| match answer.result {
+                Err(ref err) => Some((answer.vantage.as_str(), err)),
+                Ok(_) => None,
+            })
+    }
+}
+
+impl fmt::Display for Comparison {
+    /// One line per resolver, marking the addresses not common to all
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let common = self.common();
+        writeln!(
+            f,
+            "{}: {}",
+            self.name,
+            if self.is_consistent() {
+                "consistent"
+            } else {
+                "inconsistent"
+            }
+        )?;
+        for answer in &self.answers {
+            write!(f, "  {}:", answer.vantage)?;
+            match answer.result {
+                Ok(ref addrs) => {
+                    for addr in addrs {
+                        let marker = if common.contains(addr) { "" } else { "*" };
+                        write!(f, " {}{}", addr, marker)?;
+                    }
+                    writeln!(f)?;
+                }
+                Err(ref err) => writeln!(f, " error: {}", err)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Resolve `name` through each of `vantages` at once and compare the
+/// answers.
+///
+/// # Parameters
+///
+/// * `vantages` - resolvers, each with a label to report it by
+/// * `name` - the name to resolve
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::compare::compare;
+/// use ipaddr_from_str::StaticResolver;
+///
+/// let inside = StaticResolver::new().with_entry("app.example", vec!["10.0.0.5".parse().unwrap()]);
+/// let outside = StaticResolver::new().with_entry("app.example", vec!["192.0.2.5".parse().unwrap()]);
+/// let comparison = compare(&[("inside", &inside), ("outside", &outside)], "app.example");
+/// assert!(!comparison.is_consistent());
+/// assert_eq!(comparison.only_from("inside"), vec!["10.0.0.5".parse::<std::net::IpAddr>().unwrap()]);
+/// ```
+pub fn compare(vantages: &[(&str, &(dyn Resolve + Sync))], name: &str) -> Comparison {
+    let answers = thread::scope(|scope| {
+        let handles: Vec<_> = vantages
+            .iter()
+            .map(|&(vantage, resolver)| (vantage, scope.spawn(move || resolver.resolve(name))))
+            .collect();
+        handles
+            .into_iter()
+            .map(|(vantage, handle)| Answer {
+                vantage: vantage.to_string(),
+                result: handle.join().unwrap_or_else(|_| {
+                    Err(IpaddrConversionError::DnsError("resolver panicked".into()))
+                }),
+            })
+            .collect()
+    });
+    Comparison {
+        name: name.to_string(),
+        answers,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::StaticResolver;
+    use crate::test_util::ips;
+
+    #[test]
+    fn reports_disagreements() {
+        let a = StaticResolver::new().with_entry("cdn.example", ips(&["192.0.2.1", "192.0.2.2"]));
+        let b = StaticResolver::new().with_entry("cdn.example", ips(&["192.0.2.2", "192.0.2.3"]));
+        let empty = StaticResolver::new();
+        let comparison = compare(&[("a", &a), ("b", &b), ("c", &empty)], "cdn.example");
+        assert!(!comparison.is_consistent());
+        assert_eq!(comparison.common(), ips(&["192.0.2.2"]));
+        assert_eq!(comparison.only_from("b"), ips(&["192.0.2.3"]));
+        assert_eq!(
+            comparison.failures().map(|(v, _)| v).collect::<Vec<_>>(),
+            vec!["c"]
+        );
+        assert!(comparison
+            .to_string()
+            .contains("  a: 192.0.2.1* 192.0.2.2\n"));
+    }
+    #[test]
+    fn order_does_not_matter() {
+        let a = StaticResolver::new().with_entry("svc", ips(&["10.0.0.1", "10.0.0.2"]));
+        let b = StaticResolver::new().with_entry("svc", ips(&["10.0.0.2", "10.0.0.1"]));
+        assert!(compare(&[("a", &a), ("b", &b)], "svc").is_consistent());
+    }
+}
@@ -0,0 +1,767 @@
+//! Resolver profiles declared in a TOML file.
+//!
+//! Services sharing one resolver setup read it from a file rather than each
+//! building it in code. A file names profiles, each with its nameservers,
+//! search domains and resolver options, a cache, a policy, a response
+//! policy zone, and fixed overrides:
+//!
+//! ```toml
+//! default_profile = "corp"
+//!
+//! [profiles.corp]
+//! nameservers = ["10.0.0.53", "${BACKUP_DNS:-10.0.1.53}:5353"]
+//! search = ["corp.example"]
+//! timeout_secs = 2
+//! selection = "fastest"
+//! adaptive_timeout = true
+//! circuit_breaker = true
+//! bind_device = "eth1"
+//! cache_size = 4096
+//! cache_ttl_secs = 300
+//! policy = ["when host matches *.ads.example -> reject"]
+//! rpz_file = "/etc/dns/corp.rpz"
+//!
+//! [profiles.corp.overrides]
+//! "db.corp.example" = ["10.1.2.3"]
+//! ```
+//!
+//! `ndots` may be from 0 to 15, `timeout_secs` from 1 to 30 and `attempts`
+//! from 1 to 5, the ranges resolv.conf allows, and `cache_ttl_secs` from 1
+//! to a week.
+//!
+//! `${NAME}` in a string is replaced by the environment variable `NAME`, and
+//! `${NAME:-default}` by `default` where it is unset; a variable unset and
+//! without a default is an error, rather than an empty nameserver. The file
+//! is read by a small TOML reader of this crate's own: strings, integers,
+//! booleans, arrays and tables, without dotted keys, dates, floats or inline
+//! tables, none of which a profile needs.
+use crate::adaptive::AdaptiveTimeout;
+use crate::breaker::CircuitBreaker;
+use crate::cache::CachingResolver;
+use crate::direct::DirectResolver;
+use crate::errors::IpaddrConversionError;
+use crate::policy::{Policy, PolicyResolver};
+use crate::profile::{register_profile, Profile};
+use crate::resolv_conf::{ResolvConf, DNS_PORT, MAX_ATTEMPTS, MAX_NDOTS, MAX_TIMEOUT_SECS};
+use crate::resolver::Resolve;
+use crate::rpz::{Rpz, RpzResolver};
+use crate::selection::ServerSelection;
+use crate::socket::{Ipv6SourcePreference, SocketOptions};
+use std::collections::{btree_map, BTreeMap};
+use std::env;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+
+/// Longest a profile may cache an answer, the week RFC 8767 caps TTLs to
+const MAX_CACHE_TTL_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// A TOML value
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+    Array(Vec<Value>),
+}
+
+/// A `key = value` line, with the number of the line it is on
+type Entry = (String, Value, usize);
+
+/// A table's path, such as `profiles.corp`, and its entries
+type Table = (Vec<String>, Vec<Entry>);
+
+/// Reads TOML into the entries of each table, by the table's path
+struct Reader<'a, F> {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    env: &'a F,
+}
+
+fn invalid(line: usize, why: &str) -> IpaddrConversionError {
+    IpaddrConversionError::InvalidInput(format!("line {}: {}", line, why))
+}
+
+impl<'a, F: Fn(&str) -> Option<String>> Reader<'a, F> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, why: &str) -> IpaddrConversionError {
+        invalid(self.line, why)
+    }
+
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), Some(' ') | Some('\t')) {
+            self.bump();
+        }
+    }
+
+    /// Skip spaces, comments and line ends
+    fn skip_blank(&mut self) {
+        loop {
+            match self.peek() {
+                Some(' ') | Some('\t') | Some('\r') | Some('\n') => {
+                    self.bump();
+                }
+                Some('#') => {
+                    while !matches!(self.peek(), None | Some('\n')) {
+                        self.bump();
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// Expect nothing but a comment before the end of the line
+    fn end_of_line(&mut self) -> Result<(), IpaddrConversionError> {
+        self.skip_spaces();
+        if self.peek() == Some('\r') {
+            self.bump();
+        }
+        match self.peek() {
+            None | Some('\n') | Some('#') => Ok(()),
+            Some(c) => Err(self.error(&format!("unexpected '{}'", c))),
+        }
+    }
+
+    fn key(&mut self) -> Result<String, IpaddrConversionError> {
+        if let Some(quote @ ('"' | '\'')) = self.peek() {
+            return self.string(quote);
+        }
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            self.bump();
+        }
+        if start == self.pos {
+            return Err(self.error("expected a key"));
+        }
+        Ok(self.chars[start..self.pos].iter().collect())
+    }
+
+    /// A string opened by `quote`, with escapes read in basic strings
+    fn string(&mut self, quote: char) -> Result<String, IpaddrConversionError> {
+        self.bump();
+        let mut text = String::new();
+        loop {
+            match self.bump() {
+                None | Some('\n') => return Err(self.error("unterminated string")),
+                Some(c) if c == quote => return Ok(text),
+                Some('\\') if quote == '"' => match self.bump() {
+                    Some('n') => text.push('\n'),
+                    Some('t') => text.push('\t'),
+                    Some('"') => text.push('"'),
+                    Some('\\') => text.push('\\'),
+                    _ => return Err(self.error("unknown escape")),
+                },
+                Some(c) => text.push(c),
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, IpaddrConversionError> {
+        match self.peek() {
+            Some(quote @ ('"' | '\'')) => {
+                let line = self.line;
+                let text = self.string(quote)?;
+                Ok(Value::Str(
+                    expand(&text, self.env).map_err(|why| invalid(line, &why))?,
+                ))
+            }
+            Some('[') => {
+                self.bump();
+                let mut values = Vec::new();
+                loop {
+                    self.skip_blank();
+                    if self.peek() == Some(']') {
+                        self.bump();
+                        return Ok(Value::Array(values));
+                    }
+                    values.push(self.value()?);
+                    self.skip_blank();
+                    match self.bump() {
+                        Some(',') => {}
+                        Some(']') => return Ok(Value::Array(values)),
+                        _ => return Err(self.error("expected ',' or ']' in array")),
+                    }
+                }
+            }
+            _ => {
+                let start = self.pos;
+                while self
+                    .peek()
+                    .is_some_and(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '+'))
+                {
+                    self.bump();
+                }
+                let word: String = self.chars[start..self.pos].iter().collect();
+                match word.as_str() {
+                    "true" => Ok(Value::Bool(true)),
+                    "false" => Ok(Value::Bool(false)),
+                    _ => word
+                        .replace('_', "")
+                        .parse()
+                        .map(Value::Int)
+                        .map_err(|_| self.error(&format!("unsupported value '{}'", word))),
+                }
+            }
+        }
+    }
+
+    /// The entries of each table, the top level under the empty path
+    fn tables(mut self) -> Result<Vec<Table>, IpaddrConversionError> {
+        let mut tables: Vec<Table> = vec![(Vec::new(), Vec::new())];
+        loop {
+            self.skip_blank();
+            match self.peek() {
+                None => return Ok(tables),
+                Some('[') => {
+                    self.bump();
+                    let mut path = Vec::new();
+                    loop {
+                        self.skip_spaces();
+                        path.push(self.key()?);
+                        self.skip_spaces();
+                        match self.bump() {
+                            Some('.') => {}
+                            Some(']') => break,
+                            _ => return Err(self.error("expected '.' or ']' in table header")),
+                        }
+                    }
+                    if tables.iter().any(|(seen, _)| *seen == path) {
+                        return Err(self.error(&format!("table {} repeated", path.join("."))));
+                    }
+                    tables.push((path, Vec::new()));
+                }
+                Some(_) => {
+                    let line = self.line;
+                    let key = self.key()?;
+                    self.skip_spaces();
+                    if self.bump() != Some('=') {
+                        return Err(self.error("expected '=' after key"));
+                    }
+                    self.skip_spaces();
+                    let value = self.value()?;
+                    let entries = &mut tables.last_mut().expect("the top level table").1;
+                    if entries.iter().any(|(seen, _, _)| *seen == key) {
+                        return Err(invalid(line, &format!("key {} repeated", key)));
+                    }
+                    entries.push((key, value, line));
+                }
+            }
+            self.end_of_line()?;
+        }
+    }
+}
+
+/// `text` with `${NAME}` and `${NAME:-default}` replaced from `env`
+fn expand<F: Fn(&str) -> Option<String>>(text: &str, env: &F) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("unterminated ${{ in '{}'", text))?;
+        let reference = &rest[start + 2..start + end];
+        let (name, default) = match reference.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (reference, None),
+        };
+        match env(name).or_else(|| default.map(str::to_string)) {
+            Some(value) => expanded.push_str(&value),
+            None => return Err(format!("environment variable {} is not set", name)),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+fn string(value: Value, line: usize, key: &str) -> Result<String, IpaddrConversionError> {
+    match value {
+        Value::Str(text) => Ok(text),
+        _ => Err(invalid(line, &format!("{} must be a string", key))),
+    }
+}
+
+fn strings(value: Value, line: usize, key: &str) -> Result<Vec<String>, IpaddrConversionError> {
+    match value {
+        Value::Array(values) => values
+            .into_iter()
+            .map(|value| string(value, line, key))
+            .collect(),
+        _ => Err(invalid(
+            line,
+            &format!("{} must be an array of strings", key),
+        )),
+    }
+}
+
+fn count(value: Value, line: usize, key: &str) -> Result<u64, IpaddrConversionError> {
+    match value {
+        Value::Int(n) if n >= 0 => Ok(n as u64),
+        _ => Err(invalid(
+            line,
+            &format!("{} must be a non-negative integer", key),
+        )),
+    }
+}
+
+fn count_within(
+    value: Value,
+    line: usize,
+    key: &str,
+    min: u64,
+    max: u64,
+) -> Result<u64, IpaddrConversionError> {
+    match count(value, line, key)? {
+        n if (min..=max).contains(&n) => Ok(n),
+        _ => Err(invalid(
+            line,
+            &format!("{} must be from {} to {}", key, min, max),
+        )),
+    }
+}
+
+fn flag(value: Value, line: usize, key: &str) -> Result<bool, IpaddrConversionError> {
+    match value {
+        Value::Bool(flag) => Ok(flag),
+        _ => Err(invalid(line, &format!("{} must be true or false", key))),
+    }
+}
+
+/// A nameserver as `addr` or `addr:port`, IPv6 with a port bracketed
+fn nameserver(text: &str, line: usize) -> Result<SocketAddr, IpaddrConversionError> {
+    text.parse::<SocketAddr>()
+        .or_else(|_| {
+            text.parse::<IpAddr>()
+                .map(|ip| SocketAddr::new(ip, DNS_PORT))
+        })
+        .map_err(|_| invalid(line, &format!("bad nameserver '{}'", text)))
+}
+
+/// One profile of a config file
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileConfig {
+    /// The nameservers, search domains and options; the local host is
+    /// queried if no nameserver is listed, as glibc does
+    pub resolv_conf: ResolvConf,
+    /// The local address and interface queries are sent from
+    pub source: SocketOptions,
+    /// Whether queries time out from recent latency, the timeout then
+    /// being the longest they wait
+    pub adaptive_timeout: bool,
+    /// Whether nameservers which keep failing are skipped for a while
+    pub circuit_breaker: bool,
+    /// The order in which nameservers are tried, as listed or with `rotate`
+    /// in turn unless set
+    pub selection: Option<ServerSelection>,
+    /// How many answers are cached, None for no cache
+    pub cache_size: Option<usize>,
+    /// How long cached answers are kept, 60 seconds unless set
+    pub cache_ttl: Duration,
+    /// Rules names and addresses are checked against
+    pub policy: Option<Policy>,
+    /// Response policy zone, applied after the policy
+    pub rpz: Option<Rpz>,
+    /// Names answered without querying
+    pub overrides: BTreeMap<String, Vec<IpAddr>>,
+}
+
+impl ProfileConfig {
+    fn parse(entries: Vec<Entry>) -> Result<Self, IpaddrConversionError> {
+        let mut resolv_conf = ResolvConf::parse("");
+        resolv_conf.nameservers.clear();
+        let mut profile = Self {
+            resolv_conf,
+            source: SocketOptions::new(),
+            adaptive_timeout: false,
+            circuit_breaker: false,
+            selection: None,
+            cache_size: None,
+            cache_ttl: Duration::from_secs(60),
+            policy: None,
+            rpz: None,
+            overrides: BTreeMap::new(),
+        };
+        for (key, value, line) in entries {
+            let conf = &mut profile.resolv_conf;
+            match key.as_str() {
+                "nameservers" => {
+                    conf.nameservers = strings(value, line, &key)?
+                        .iter()
+                        .map(|text| nameserver(text, line))
+                        .collect::<Result<_, _>>()?
+                }
+                "search" => conf.search = strings(value, line, &key)?,
+                "ndots" => {
+                    conf.ndots = count_within(value, line, &key, 0, MAX_NDOTS as u64)? as usize
+                }
+                "timeout_secs" => {
+                    let secs = count_within(value, line, &key, 1, MAX_TIMEOUT_SECS)?;
+                    conf.timeout = Duration::from_secs(secs)
+                }
+                "attempts" => {
+                    conf.attempts =
+                        count_within(value, line, &key, 1, MAX_ATTEMPTS as u64)? as usize
+                }
+                "rotate" => conf.rotate = flag(value, line, &key)?,
+                "use_vc" => conf.use_vc = flag(value, line, &key)?,
+                "adaptive_timeout" => profile.adaptive_timeout = flag(value, line, &key)?,
+                "circuit_breaker" => profile.circuit_breaker = flag(value, line, &key)?,
+                "selection" => {
+                    let text = string(value, line, &key)?;
+                    profile.selection = Some(
+                        text.parse::<ServerSelection>()
+                            .map_err(|err| invalid(line, &format!("'{}': {}", text, err)))?,
+                    );
+                }
+                "cache_size" => {
+                    profile.cache_size = Some(count(value, line, &key)? as usize).filter(|&n| n > 0)
+                }
+                "cache_ttl_secs" => {
+                    let secs = count_within(value, line, &key, 1, MAX_CACHE_TTL_SECS)?;
+                    profile.cache_ttl = Duration::from_secs(secs)
+                }
+                "policy" => {
+                    let rules = strings(value, line, &key)?.join("\n");
+                    profile.policy =
+                        Some(Policy::parse(&rules).map_err(|err| invalid(line, &err.to_string()))?);
+                }
+                "policy_file" => {
+                    let path = string(value, line, &key)?;
+                    profile.policy = Some(
+                        Policy::load(&path)
+                            .map_err(|err| invalid(line, &format!("{}: {}", path, err)))?,
+                    );
+                }
+                "local_addr" => {
+                    let text = string(value, line, &key)?;
+                    let addr = text
+                        .parse::<IpAddr>()
+                        .map_err(|_| invalid(line, &format!("bad address '{}'", text)))?;
+                    profile.source = profile.source.clone().local_addr(addr);
+                }
+                "bind_device" => {
+                    let device = string(value, line, &key)?;
+                    profile.source = profile.source.clone().bind_device(&device);
+                }
+                "ipv6_source" => {
+                    let text = string(value, line, &key)?;
+                    let preference = text
+                        .parse::<Ipv6SourcePreference>()
+                        .map_err(|err| invalid(line, &format!("'{}': {}", text, err)))?;
+                    profile.source = profile.source.clone().prefer_ipv6_source(preference);
+                }
+                "rpz_file" => {
+                    let path = string(value, line, &key)?;
+                    profile.rpz = Some(
+                        Rpz::load(&path)
+                            .map_err(|err| invalid(line, &format!("{}: {}", path, err)))?,
+                    );
+                }
+                _ => return Err(invalid(line, &format!("unknown key {}", key))),
+            }
+        }
+        if profile.resolv_conf.nameservers.is_empty() {
+            profile.resolv_conf.nameservers = ResolvConf::parse("").nameservers;
+        }
+        Ok(profile)
+    }
+
+    fn parse_overrides(&mut self, entries: Vec<Entry>) -> Result<(), IpaddrConversionError> {
+        for (name, value, line) in entries {
+            let addrs = strings(value, line, &name)?
+                .iter()
+                .map(|text| {
+                    text.parse::<IpAddr>()
+                        .map_err(|_| invalid(line, &format!("bad address '{}'", text)))
+                })
+                .collect::<Result<_, _>>()?;
+            self.overrides.insert(name, addrs);
+        }
+        Ok(())
+    }
+
+    /// A resolver answering as the profile says: overrides first, then the
+    /// policy, the response policy zone, the cache and the nameservers in
+    /// turn
+    pub fn build(&self) -> Profile {
+        // the profile applies the search domains itself
+        let mut direct = DirectResolver::new(self.resolv_conf.clone())
+            .with_source(self.source.clone())
+            .with_search(false);
+        if self.adaptive_timeout {
+            direct = direct.with_adaptive_timeout(AdaptiveTimeout::new());
+        }
+        if let Some(selection) = self.selection {
+            direct = direct.with_selection(selection);
+        }
+        if self.circuit_breaker {
+            direct = direct.with_circuit_breaker(CircuitBreaker::new());
+        }
+        let backend: Box<dyn Resolve + Send + Sync> = match self.cache_size {
+            Some(size) => {
+                Box::new(CachingResolver::new(direct, self.cache_ttl).with_max_entries(size))
+            }
+            None => Box::new(direct),
+        };
+        let backend: Box<dyn Resolve + Send + Sync> = match self.rpz {
+            Some(ref zone) => Box::new(RpzResolver::new(backend, zone.clone())),
+            None => backend,
+        };
+        let backend: Box<dyn Resolve + Send + Sync> = match self.policy {
+            Some(ref policy) => Box::new(PolicyResolver::new(backend, policy.clone())),
+            None => backend,
+        };
+        self.overrides.iter().fold(
+            Profile::with_backend(self.resolv_conf.clone(), backend),
+            |profile, (name, addrs)| profile.with_override(name.clone(), addrs.clone()),
+        )
+    }
+}
+
+/// The profiles of a config file
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Config {
+    /// The profile used when none is named
+    pub default_profile: Option<String>,
+    pub profiles: BTreeMap<String, ProfileConfig>,
+}
+
+impl Config {
+    /// Read a config from the text of a file, expanding variables from the
+    /// environment
+    ///
+    /// # Returns
+    /// The config, or an InvalidInput error naming the line of the first
+    /// thing which cannot be read, an unknown key or table, or a variable
+    /// which is not set
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ipaddr_from_str::config::Config;
+    /// use ipaddr_from_str::Resolve;
+    ///
+    /// let config = Config::parse(r#"
+    ///     [profiles.lab]
+    ///     nameservers = ["192.0.2.53"]
+    ///     search = ["lab.example"]
+    ///
+    ///     [profiles.lab.overrides]
+    ///     "gw.lab.example" = ["192.0.2.1"]
+    /// "#).unwrap();
+    /// let lab = config.resolver("lab").unwrap();
+    /// assert_eq!(lab.resolve("gw").unwrap()[0].to_string(), "192.0.2.1");
+    /// ```
+    pub fn parse(text: &str) -> Result<Self, IpaddrConversionError> {
+        Self::parse_with_env(text, &|name: &str| env::var(name).ok())
+    }
+
+    fn parse_with_env<F: Fn(&str) -> Option<String>>(
+        text: &str,
+        env: &F,
+    ) -> Result<Self, IpaddrConversionError> {
+        let reader = Reader {
+            chars: text.chars().collect(),
+            pos: 0,
+            line: 1,
+            env,
+        };
+        let mut config = Self::default();
+        // overrides may come before the table of their profile
+        let mut overrides = Vec::new();
+        for (path, entries) in reader.tables()? {
+            let line = entries.first().map_or(0, |(_, _, line)| *line);
+            let path: Vec<&str> = path.iter().map(String::as_str).collect();
+            match path.as_slice() {
+                [] => {
+                    for (key, value, line) in entries {
+                        match key.as_str() {
+                            "default_profile" => {
+                                config.default_profile = Some(string(value, line, &key)?)
+                            }
+                            _ => return Err(invalid(line, &format!("unknown key {}", key))),
+                        }
+                    }
+                }
+                ["profiles", name] => {
+                    let profile = ProfileConfig::parse(entries)?;
+                    config.profiles.insert(name.to_string(), profile);
+                }
+                ["profiles", name, "overrides"] => overrides.push((name.to_string(), entries)),
+                _ => return Err(invalid(line, &format!("unknown table {}", path.join(".")))),
+            }
+        }
+        for (name, entries) in overrides {
+            let profile = match config.profiles.entry(name) {
+                btree_map::Entry::Occupied(entry) => entry.into_mut(),
+                btree_map::Entry::Vacant(entry) => entry.insert(ProfileConfig::parse(Vec::new())?),
+            };
+            profile.parse_overrides(entries)?;
+        }
+        if let Some(ref name) = config.default_profile {
+            if !config.profiles.contains_key(name) {
+                return Err(IpaddrConversionError::InvalidInput(format!(
+                    "default profile {} is not defined",
+                    name
+                )));
+            }
+        }
+        Ok(config)
+    }
+
+    /// Read the config file at `path`
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, IpaddrConversionError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// A resolver for the profile `name`
+    ///
+    /// # Returns
+    /// The resolver, or an InvalidInput error if there is no such profile or
+    /// no default
+    pub fn resolver(&self, name: &str) -> Result<Profile, IpaddrConversionError> {
+        self.profiles
+            .get(name)
+            .map(ProfileConfig::build)
+            .ok_or_else(|| IpaddrConversionError::InvalidInput(format!("no profile {}", name)))
+    }
+
+    /// A resolver for the default profile
+    pub fn default_resolver(&self) -> Result<Profile, IpaddrConversionError> {
+        match self.default_profile {
+            Some(ref name) => self.resolver(name),
+            None => Err(IpaddrConversionError::InvalidInput(
+                "no default profile".into(),
+            )),
+        }
+    }
+
+    /// [Register](crate::profile::register_profile) a resolver for every
+    /// profile under its name, so policies and
+    /// [`get_ipaddr_with_profile`](crate::get_ipaddr_with_profile) can use
+    /// them
+    pub fn register(&self) {
+        for (name, profile) in &self.profiles {
+            register_profile(name.clone(), profile.build());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(name: &str) -> Option<String> {
+        match name {
+            "CORP_DNS" => Some("10.0.0.53".into()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn profiles_are_read_with_variables_expanded() {
+        let config = Config::parse_with_env(
+            r#"
+            default_profile = "corp" # the VPN
+            [profiles.corp]
+            nameservers = [
+                "${CORP_DNS}",
+                "${BACKUP_DNS:-[2001:db8::53]:5353}",
+            ]
+            search = ['corp.example']
+            ndots = 2
+            rotate = true
+            cache_size = 1_000
+            policy = ["when ip in 10.9/16 -> reject"]
+
+            [profiles.corp.overrides]
+            "db.corp.example" = ["10.1.2.3", "2001:db8::3"]
+
+            [profiles.public]
+            "#,
+            &env,
+        )
+        .unwrap();
+        let corp = &config.profiles["corp"];
+        assert_eq!(
+            corp.resolv_conf.nameservers,
+            vec![
+                "10.0.0.53:53".parse().unwrap(),
+                "[2001:db8::53]:5353".parse().unwrap()
+            ]
+        );
+        assert_eq!(corp.resolv_conf.search, vec!["corp.example"]);
+        assert_eq!(corp.resolv_conf.ndots, 2);
+        assert!(corp.resolv_conf.rotate);
+        assert_eq!(corp.cache_size, Some(1000));
+        assert!(!corp
+            .policy
+            .as_ref()
+            .unwrap()
+            .permits_ip("10.9.0.1".parse().unwrap()));
+        assert_eq!(corp.overrides["db.corp.example"].len(), 2);
+        assert_eq!(
+            config.profiles["public"].resolv_conf.nameservers,
+            vec!["127.0.0.1:53".parse().unwrap()]
+        );
+        let resolver = config.default_resolver().unwrap();
+        assert_eq!(resolver.resolve("db").unwrap()[0].to_string(), "10.1.2.3");
+    }
+    #[test]
+    fn mistakes_are_reported_with_their_line() {
+        let err = |text: &str| Config::parse_with_env(text, &env).unwrap_err().to_string();
+        assert!(err("[profiles.a]\nnameservers = [\"${MISSING}\"]")
+            .contains("line 2: environment variable MISSING"));
+        assert!(err("[profiles.a]\n\nnameserver = []").contains("line 3: unknown key nameserver"));
+        assert!(err("[profiles.a]\nndots = \"2\"").contains("line 2: ndots must be"));
+        assert!(
+            err("[profiles.a]\ntimeout_secs = 0").contains("line 2: timeout_secs must be from 1")
+        );
+        assert!(err("[profiles.a]\nndots = 16").contains("line 2: ndots must be from 0 to 15"));
+        assert!(err("[profiles.a]\nattempts = 0").contains("line 2: attempts must be from 1 to 5"));
+        assert!(err("[profiles.a]\ncache_ttl_secs = 9223372036854775807")
+            .contains("line 2: cache_ttl_secs must be from 1"));
+        assert!(err("[profile.a]\nndots = 1").contains("unknown table profile.a"));
+        assert!(err("search = [\"a\"").contains("line 1: expected ','"));
+        assert!(err("default_profile = \"b\"").contains("default profile b"));
+    }
+    #[test]
+    fn overrides_may_come_before_their_profile() {
+        let config = Config::parse_with_env(
+            r#"
+            [profiles.corp.overrides]
+            "db.corp.example" = ["10.1.2.3"]
+
+            [profiles.corp]
+            nameservers = ["${CORP_DNS}"]
+            "#,
+            &env,
+        )
+        .unwrap();
+        let corp = &config.profiles["corp"];
+        assert_eq!(
+            corp.resolv_conf.nameservers,
+            vec!["10.0.0.53:53".parse().unwrap()]
+        );
+        assert_eq!(corp.overrides["db.corp.example"].len(), 1);
+    }
+}
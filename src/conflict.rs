@@ -0,0 +1,361 @@
+//! Checking addresses about to be assigned against the live network.
+//!
+//! An address written into a config may already be taken by a host nobody
+//! recorded. A [`ConflictChecker`] asks [`Liveness`] sources whether
+//! anything is using each intended address, and reports those which are,
+//! along with addresses the config itself assigns more than once, so a
+//! deployment can stop before it collides. With the `probe` feature,
+//! [`ConnectLiveness`] tries TCP ports and [`NeighborLiveness`] looks in the
+//! ARP and NDP tables.
+use crate::errors::IpaddrConversionError;
+use crate::neighbor::MacAddr;
+use crate::redact::{addr_text, name_text};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+#[cfg(feature = "probe")]
+use std::{io::ErrorKind, net::SocketAddr, net::TcpStream, time::Duration};
+
+/// What showed an address to be in use
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Evidence {
+    /// The neighbor table holds a hardware address for it
+    Neighbor(MacAddr),
+    /// It accepted or refused a TCP connection to the port; either way a
+    /// host answered
+    Answered(u16),
+}
+
+impl fmt::Display for Evidence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Evidence::Neighbor(mac) => write!(f, "neighbor table holds {}", mac),
+            Evidence::Answered(port) => write!(f, "answered on port {}", port),
+        }
+    }
+}
+
+/// A source of live data about which addresses are in use
+pub trait Liveness: Send + Sync {
+    /// Evidence that something is using `addr`, None if nothing was seen
+    fn seen(&self, addr: IpAddr) -> Result<Option<Evidence>, IpaddrConversionError>;
+}
+
+/// Tries TCP ports on each address, taking a connection accepted or
+/// refused as a host being there
+#[cfg(feature = "probe")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectLiveness {
+    ports: Vec<u16>,
+    timeout: Duration,
+}
+
+#[cfg(feature = "probe")]
+impl ConnectLiveness {
+    /// Try `ports` in turn, waiting up to `timeout` for each
+    pub fn new(ports: Vec<u16>, timeout: Duration) -> Self {
+        Self { ports, timeout }
+    }
+}
+
+#[cfg(feature = "probe")]
+impl Liveness for ConnectLiveness {
+    fn seen(&self, addr: IpAddr) -> Result<Option<Evidence>, IpaddrConversionError> {
+        for &port in &self.ports {
+            match TcpStream::connect_timeout(&SocketAddr::new(addr, port), self.timeout) {
+                Ok(_) => return Ok(Some(Evidence::Answered(port))),
+                Err(err) if err.kind() == ErrorKind::ConnectionRefused => {
+                    return Ok(Some(Evidence::Answered(port)))
+                }
+                Err(_) => {}
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Looks for each address in the system's neighbor table.
+///
+/// Only hosts on the local links which were recently talked to are there,
+/// so it is best used after a [`ConnectLiveness`], whose connection
+/// attempts have the OS look up the hardware address even of a host which
+/// drops them.
+#[cfg(feature = "probe")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NeighborLiveness;
+
+#[cfg(feature = "probe")]
+impl Liveness for NeighborLiveness {
+    fn seen(&self, addr: IpAddr) -> Result<Option<Evidence>, IpaddrConversionError> {
+        Ok(crate::neighbor::lookup_mac(addr)?.map(Evidence::Neighbor))
+    }
+}
+
+/// An intended address which cannot be assigned as it is
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Conflict {
+    /// The config assigns the address to more than one name, given in the
+    /// order they were
+    Repeated { addr: IpAddr, names: Vec<String> },
+    /// Something on the network is already using the address intended for
+    /// `name`
+    InUse {
+        addr: IpAddr,
+        name: String,
+        evidence: Evidence,
+    },
+}
+
+impl Conflict {
+    /// The address in conflict
+    pub fn addr(&self) -> IpAddr {
+        match self {
+            Conflict::Repeated { addr, .. } | Conflict::InUse { addr, .. } => *addr,
+        }
+    }
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Conflict::Repeated { addr, names } => {
+                let names: Vec<String> = names.iter().map(|name| name_text(name)).collect();
+                write!(
+                    f,
+                    "{} is assigned to {}",
+                    addr_text(*addr),
+                    names.join(", ")
+                )
+            }
+            Conflict::InUse {
+                addr,
+                name,
+                evidence,
+            } => write!(
+                f,
+                "{} for {} is in use: {}",
+                addr_text(*addr),
+                name_text(name),
+                evidence
+            ),
+        }
+    }
+}
+
+/// The outcome of a check
+#[derive(Debug, Default)]
+pub struct ConflictReport {
+    /// The conflicts, repeats first, then those in use in the order the
+    /// addresses were given
+    pub conflicts: Vec<Conflict>,
+    /// The addresses a source failed to check, with its error
+    pub unchecked: Vec<(IpAddr, IpaddrConversionError)>,
+}
+
+impl ConflictReport {
+    /// Whether every address was checked and none conflicts
+    pub fn is_clear(&self) -> bool {
+        self.conflicts.is_empty() && self.unchecked.is_empty()
+    }
+}
+
+/// Checks intended addresses for repeats and against [`Liveness`] sources.
+///
+/// Sources are asked in the order they were added until one sees the
+/// address; one failing leaves the address unchecked rather than clear.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "probe")]
+/// # {
+/// use ipaddr_from_str::conflict::{ConflictChecker, ConnectLiveness, NeighborLiveness};
+/// use std::time::Duration;
+///
+/// let checker = ConflictChecker::new()
+///     .with_source(ConnectLiveness::new(vec![22, 80, 443], Duration::from_millis(300)))
+///     .with_source(NeighborLiveness)
+///     .with_concurrency(32);
+/// let report = checker.check(&[
+///     ("db1", "10.0.0.21".parse().unwrap()),
+///     ("db2", "10.0.0.22".parse().unwrap()),
+/// ]);
+/// for conflict in &report.conflicts {
+///     eprintln!("{}", conflict);
+/// }
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ConflictChecker {
+    sources: Vec<Arc<dyn Liveness>>,
+    concurrency: usize,
+}
+
+impl fmt::Debug for ConflictChecker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ConflictChecker")
+            .field("sources", &self.sources.len())
+            .field("concurrency", &self.concurrency)
+            .finish()
+    }
+}
+
+impl Default for ConflictChecker {
+    fn default() -> Self {
+        Self {
+            sources: Vec::new(),
+            concurrency: 8,
+        }
+    }
+}
+
+impl ConflictChecker {
+    /// A checker with no sources, checking 8 addresses at once
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask `source` about each address, after any sources already added,
+    /// returning the updated checker
+    pub fn with_source<L: Liveness + 'static>(mut self, source: L) -> Self {
+        self.sources.push(Arc::new(source));
+        self
+    }
+
+    /// Check up to `concurrency` addresses at once, returning the updated
+    /// checker
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    fn seen(&self, addr: IpAddr) -> Result<Option<Evidence>, IpaddrConversionError> {
+        for source in &self.sources {
+            if let Some(evidence) = source.seen(addr)? {
+                return Ok(Some(evidence));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Check the addresses `intended` assigns to names
+    ///
+    /// # Parameters
+    ///
+    /// * `intended` - each name and the address it is to be given
+    ///
+    /// # Returns
+    /// The conflicts found and the addresses which could not be checked
+    pub fn check(&self, intended: &[(&str, IpAddr)]) -> ConflictReport {
+        let mut report = ConflictReport::default();
+        let mut names: HashMap<IpAddr, Vec<String>> = HashMap::new();
+        let mut unique = Vec::new();
+        for &(name, addr) in intended {
+            let assigned = names.entry(addr).or_default();
+            if assigned.is_empty() {
+                unique.push((name, addr));
+            }
+            assigned.push(name.to_string());
+        }
+        for &(_, addr) in &unique {
+            if names[&addr].len() > 1 {
+                report.conflicts.push(Conflict::Repeated {
+                    addr,
+                    names: names[&addr].clone(),
+                });
+            }
+        }
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(Vec::with_capacity(unique.len()));
+        thread::scope(|scope| {
+            for _ in 0..self.concurrency.min(unique.len()) {
+                scope.spawn(|| {
+                    while let Some(&(_, addr)) = unique.get(next.fetch_add(1, Ordering::SeqCst)) {
+                        let seen = self.seen(addr);
+                        results
+                            .lock()
+                            .unwrap_or_else(|poisoned| poisoned.into_inner())
+                            .push((addr, seen));
+                    }
+                });
+            }
+        });
+        let mut results: HashMap<IpAddr, _> = results
+            .into_inner()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .into_iter()
+            .collect();
+        for (name, addr) in unique {
+            match results.remove(&addr) {
+                Some(Ok(Some(evidence))) => report.conflicts.push(Conflict::InUse {
+                    addr,
+                    name: name.to_string(),
+                    evidence,
+                }),
+                Some(Err(err)) => report.unchecked.push((addr, err)),
+                _ => {}
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sees the addresses it was given, and fails for 192.0.2.99
+    struct Seen(Vec<IpAddr>);
+
+    impl Liveness for Seen {
+        fn seen(&self, addr: IpAddr) -> Result<Option<Evidence>, IpaddrConversionError> {
+            if addr.to_string() == "192.0.2.99" {
+                return Err(IpaddrConversionError::Timeout("probe".into()));
+            }
+            Ok(self.0.contains(&addr).then_some(Evidence::Answered(22)))
+        }
+    }
+
+    fn ip(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn repeats_and_live_addresses_conflict() {
+        let checker = ConflictChecker::new()
+            .with_source(Seen(vec![]))
+            .with_source(Seen(vec![ip("192.0.2.2")]))
+            .with_concurrency(2);
+        let report = checker.check(&[
+            ("a", ip("192.0.2.1")),
+            ("b", ip("192.0.2.2")),
+            ("c", ip("192.0.2.1")),
+            ("d", ip("192.0.2.3")),
+            ("e", ip("192.0.2.99")),
+        ]);
+        let conflicts: Vec<String> = report.conflicts.iter().map(Conflict::to_string).collect();
+        assert_eq!(
+            conflicts,
+            [
+                "192.0.2.1 is assigned to a, c",
+                "192.0.2.2 for b is in use: answered on port 22"
+            ]
+        );
+        assert_eq!(report.unchecked[0].0, ip("192.0.2.99"));
+        assert!(!report.is_clear());
+        assert!(checker.check(&[("a", ip("192.0.2.1"))]).is_clear());
+    }
+    #[cfg(feature = "probe")]
+    #[test]
+    fn a_listening_port_is_in_use() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let checker = ConflictChecker::new()
+            .with_source(ConnectLiveness::new(vec![port], Duration::from_secs(1)));
+        let report = checker.check(&[("self", ip("127.0.0.1"))]);
+        assert_eq!(report.conflicts[0].addr(), ip("127.0.0.1"));
+    }
+}
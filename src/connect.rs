@@ -0,0 +1,232 @@
+//! Racing connection attempts across address families (RFC 8305).
+//!
+//! Enabled by the `async` feature. Resolving a name and connecting to its
+//! first address stalls for a full connect timeout whenever that address is
+//! unreachable, which is common for IPv6 on broken networks. Happy eyeballs
+//! starts with IPv6, and starts the next address, alternating families, each
+//! time an attempt fails or has been pending for the stagger delay. The first
+//! connection to succeed wins and the others are aborted.
+use crate::errors::IpaddrConversionError;
+use crate::resolver::{Resolve, SystemResolver};
+use crate::socket::SocketOptions;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+
+/// Delay before starting the next attempt, recommended by RFC 8305
+pub const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Order addresses for connecting: alternate families, starting with IPv6,
+/// keeping the resolver's order within each family
+pub(crate) fn interleave(addrs: Vec<IpAddr>) -> Vec<IpAddr> {
+    let (mut v6, mut v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(IpAddr::is_ipv6);
+    let mut ordered = Vec::with_capacity(v6.len() + v4.len());
+    v6.reverse();
+    v4.reverse();
+    loop {
+        match (v6.pop(), v4.pop()) {
+            (None, None) => return ordered,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+/// Connect to `host` on `port`, racing its addresses through the system
+/// resolver.
+///
+/// # Parameters
+///
+/// * `host` - a hostname or address literal
+/// * `port` - the port to connect to
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::connect::connect_happy_eyeballs;
+///
+/// # async fn run() -> Result<(), ipaddr_from_str::IpaddrConversionError> {
+/// let stream = connect_happy_eyeballs("example.com", 443).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn connect_happy_eyeballs(
+    host: &str,
+    port: u16,
+) -> Result<TcpStream, IpaddrConversionError> {
+    connect_with(
+        Arc::new(SystemResolver),
+        host,
+        port,
+        CONNECTION_ATTEMPT_DELAY,
+    )
+    .await
+}
+
+/// Connect to `host` on `port` like [`connect_happy_eyeballs`], resolving
+/// through `resolver` and staggering attempts by `delay`. The lookup gives up
+/// at the caller's [deadline](crate::deadline::scope_async), if one is set.
+pub async fn connect_with<R>(
+    resolver: Arc<R>,
+    host: &str,
+    port: u16,
+    delay: Duration,
+) -> Result<TcpStream, IpaddrConversionError>
+where
+    R: Resolve + Send + Sync + 'static,
+{
+    race(resolver, host, port, delay, TcpStream::connect).await
+}
+
+/// Connect to `host` on `port` like [`connect_with`], setting `options` on
+/// each socket before it connects.
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::connect::{connect_with_options, CONNECTION_ATTEMPT_DELAY};
+/// use ipaddr_from_str::socket::SocketOptions;
+/// use ipaddr_from_str::SystemResolver;
+/// use std::sync::Arc;
+///
+/// # async fn run() -> Result<(), ipaddr_from_str::IpaddrConversionError> {
+/// let options = SocketOptions::new().nodelay(true).bind_device("eth1");
+/// let stream = connect_with_options(
+///     Arc::new(SystemResolver),
+///     "example.com",
+///     443,
+///     CONNECTION_ATTEMPT_DELAY,
+///     &options,
+/// )
+/// .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn connect_with_options<R>(
+    resolver: Arc<R>,
+    host: &str,
+    port: u16,
+    delay: Duration,
+    options: &SocketOptions,
+) -> Result<TcpStream, IpaddrConversionError>
+where
+    R: Resolve + Send + Sync + 'static,
+{
+    let options = Arc::new(options.clone());
+    race(resolver, host, port, delay, move |addr| {
+        let options = Arc::clone(&options);
+        async move { connect_socket(&options, addr).await }
+    })
+    .await
+}
+
+/// Connect a socket with `options` set to `addr` without blocking
+async fn connect_socket(options: &SocketOptions, addr: SocketAddr) -> io::Result<TcpStream> {
+    let socket = options.connecting_socket(&addr)?;
+    socket.set_nonblocking(true)?;
+    match socket.connect(&addr.into()) {
+        Ok(()) => {}
+        #[cfg(unix)]
+        Err(err) if err.raw_os_error() == Some(libc::EINPROGRESS) => {}
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+        Err(err) => return Err(err),
+    }
+    let stream = TcpStream::from_std(socket.into_tcp_stream())?;
+    // the socket turns writable once the handshake completes or fails
+    stream.writable().await?;
+    match stream.take_error()? {
+        Some(err) => Err(err),
+        None => Ok(stream),
+    }
+}
+
+/// Resolve `host` and race `connect` across its addresses
+async fn race<R, F, Fut>(
+    resolver: Arc<R>,
+    host: &str,
+    port: u16,
+    delay: Duration,
+    connect: F,
+) -> Result<TcpStream, IpaddrConversionError>
+where
+    R: Resolve + Send + Sync + 'static,
+    F: Fn(SocketAddr) -> Fut,
+    Fut: Future<Output = io::Result<TcpStream>> + Send + 'static,
+{
+    let addrs = crate::deadline::resolve(resolver, host).await?;
+    let mut pending: VecDeque<SocketAddr> = interleave(addrs)
+        .into_iter()
+        .map(|ip| SocketAddr::new(ip, port))
+        .collect();
+    // dropping the set aborts whichever attempts are still running
+    let mut attempts = JoinSet::new();
+    let mut last_err = None;
+    loop {
+        if let Some(addr) = pending.pop_front() {
+            attempts.spawn(connect(addr));
+        }
+        let finished = if !pending.is_empty() {
+            match tokio::time::timeout(delay, attempts.join_next()).await {
+                Ok(finished) => finished,
+                // stagger delay elapsed, start the next attempt alongside
+                Err(_) => continue,
+            }
+        } else {
+            attempts.join_next().await
+        };
+        match finished {
+            Some(Ok(Ok(stream))) => return Ok(stream),
+            Some(Ok(Err(err))) => last_err = Some(err),
+            Some(Err(err)) => last_err = Some(io::Error::other(err)),
+            None => break,
+        }
+    }
+    Err(match last_err {
+        Some(err) => IpaddrConversionError::IoError(err),
+        None => IpaddrConversionError::NotFound(host.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::StaticResolver;
+    use crate::test_util::ips;
+
+    #[test]
+    fn families_alternate_starting_with_v6() {
+        assert_eq!(
+            interleave(ips(&["10.0.0.1", "10.0.0.2", "2001:db8::1", "10.0.0.3"])),
+            ips(&["2001:db8::1", "10.0.0.1", "10.0.0.2", "10.0.0.3"])
+        );
+        assert!(interleave(vec![]).is_empty());
+    }
+    #[test]
+    fn falls_back_to_a_reachable_address() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            // 192.0.2.0/24 is reserved for documentation, so never answers
+            let resolver = StaticResolver::new()
+                .with_entry("svc.example", ips(&["::1", "192.0.2.1", "127.0.0.1"]));
+            let stream = connect_with(
+                Arc::new(resolver),
+                "svc.example",
+                port,
+                Duration::from_millis(50),
+            )
+            .await
+            .unwrap();
+            assert_eq!(stream.peer_addr().unwrap().port(), port);
+            assert!(stream.peer_addr().unwrap().ip().is_loopback());
+        });
+    }
+}
@@ -0,0 +1,272 @@
+//! Reading addresses from configuration files with serde.
+//!
+//! Enabled by the `serde` feature. [`Address`], [`Backend`], [`Cidr`],
+//! [`IpRange`], [`HostOrIp`], [`HostPort`] and [`RecordType`] serialize as, and
+//! deserialize from, the strings they display as and parse from, with errors
+//! naming the bad value and what was wrong with it. The `deserialize_*` functions are for
+//! `#[serde(deserialize_with = "...")]` on fields whose types are not the
+//! crate's own, and [`ResolveOnDeserialize`] resolves a hostname as the
+//! configuration is loaded, so a name which does not resolve fails startup.
+//! A [`Policy`] deserializes from a list of its rules, or a table holding
+//! them as `rules`, and an [`Allocator`] from the list of lines
+//! [`Allocator::to_lines`] gives.
+//!
+//! # Example
+//!
+//! ```
+//! use ipaddr_from_str::cidr::Cidr;
+//! use ipaddr_from_str::de::ResolveOnDeserialize;
+//! use serde::de::value::{Error, StrDeserializer};
+//! use serde::de::IntoDeserializer;
+//! use serde::Deserialize;
+//!
+//! let text: StrDeserializer<Error> = "10.0.0.0/8".into_deserializer();
+//! assert_eq!(Cidr::deserialize(text).unwrap().prefix_len(), 8);
+//! let text: StrDeserializer<Error> = "127.0.0.1".into_deserializer();
+//! let upstream = ResolveOnDeserialize::deserialize(text).unwrap();
+//! assert!(upstream.addrs()[0].is_loopback());
+//! ```
+use crate::address::Address;
+use crate::backend::Backend;
+use crate::cidr::{Cidr, IpRange};
+use crate::dns::RecordType;
+use crate::errors::IpaddrConversionError;
+use crate::host::{HostOrIp, HostPort};
+use crate::ipam::Allocator;
+use crate::parse::ParseError;
+use crate::policy::{Policy, Rule};
+use crate::resolver::{Resolve, SystemResolver};
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::marker::PhantomData;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Visitor parsing a string with `FromStr`
+struct FromStrVisitor<T> {
+    what: &'static str,
+    marker: PhantomData<T>,
+}
+
+impl<'de, T: FromStr<Err = ParseError>> Visitor<'de> for FromStrVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} as a string", self.what)
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<T, E> {
+        value
+            .parse()
+            .map_err(|err| E::custom(format!("invalid {} '{}': {}", self.what, value, err)))
+    }
+}
+
+macro_rules! string_serde {
+    ($($ty:ty => $what:expr),*) => {
+        $(
+            impl<'de> Deserialize<'de> for $ty {
+                fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    deserializer.deserialize_str(FromStrVisitor {
+                        what: $what,
+                        marker: PhantomData,
+                    })
+                }
+            }
+
+            impl Serialize for $ty {
+                fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    serializer.collect_str(self)
+                }
+            }
+        )*
+    };
+}
+
+string_serde!(
+    Address => "address",
+    Backend => "backend",
+    Cidr => "CIDR block",
+    IpRange => "address range",
+    HostOrIp => "hostname or address",
+    HostPort => "host and port",
+    RecordType => "record type"
+);
+
+/// Deserialize a [`HostOrIp`], for use with `deserialize_with`
+pub fn deserialize_host_or_ip<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HostOrIp, D::Error> {
+    HostOrIp::deserialize(deserializer)
+}
+
+/// Deserialize a list of [`Cidr`] blocks, for use with `deserialize_with`
+pub fn deserialize_cidr_list<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Cidr>, D::Error> {
+    Vec::<Cidr>::deserialize(deserializer)
+}
+
+/// A hostname or address, resolved through the system resolver when it is
+/// deserialized.
+///
+/// It serializes back to the host as written, not the addresses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolveOnDeserialize {
+    host: HostOrIp,
+    addrs: Vec<IpAddr>,
+}
+
+impl ResolveOnDeserialize {
+    /// The host as written in the configuration
+    pub fn host(&self) -> &HostOrIp {
+        &self.host
+    }
+
+    /// The addresses the host resolved to at load time
+    pub fn addrs(&self) -> &[IpAddr] {
+        &self.addrs
+    }
+
+    /// Resolve `host` now through `resolver`, as deserializing does through
+    /// the system resolver
+    pub fn resolve_with<R: Resolve + ?Sized>(
+        host: HostOrIp,
+        resolver: &R,
+    ) -> Result<Self, IpaddrConversionError> {
+        let addrs = host.resolve(resolver)?;
+        Ok(Self { host, addrs })
+    }
+}
+
+impl<'de> Deserialize<'de> for ResolveOnDeserialize {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let host = HostOrIp::deserialize(deserializer)?;
+        let addrs = host.resolve(&SystemResolver).map_err(|err| {
+            <D::Error as de::Error>::custom(format!("could not resolve '{}': {}", host, err))
+        })?;
+        Ok(Self { host, addrs })
+    }
+}
+
+impl Serialize for ResolveOnDeserialize {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.host.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Rule {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse()
+            .map_err(|err: IpaddrConversionError| de::Error::custom(err.to_string()))
+    }
+}
+
+impl Serialize for Rule {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Visitor reading a policy from a list of rules, or a table with the list
+/// as `rules`, as a TOML file has to hold it
+struct PolicyVisitor;
+
+impl<'de> Visitor<'de> for PolicyVisitor {
+    type Value = Policy;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a list of policy rules, or a table of them as 'rules'")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Policy, A::Error> {
+        let mut rules = Vec::new();
+        while let Some(rule) = seq.next_element()? {
+            rules.push(rule);
+        }
+        Ok(Policy::new(rules))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Policy, A::Error> {
+        let mut rules = None;
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "rules" {
+                rules = Some(map.next_value::<Vec<Rule>>()?);
+            } else {
+                map.next_value::<de::IgnoredAny>()?;
+            }
+        }
+        rules
+            .map(Policy::new)
+            .ok_or_else(|| de::Error::missing_field("rules"))
+    }
+}
+
+impl<'de> Deserialize<'de> for Policy {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(PolicyVisitor)
+    }
+}
+
+impl Serialize for Policy {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.rules())
+    }
+}
+
+impl<'de> Deserialize<'de> for Allocator {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let lines = Vec::<String>::deserialize(deserializer)?;
+        Allocator::parse(&lines.join("\n")).map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for Allocator {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.to_lines())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::value::{Error, SeqDeserializer, StrDeserializer};
+    use serde::de::IntoDeserializer;
+
+    fn from_str<'a, T: Deserialize<'a>>(text: &'a str) -> Result<T, Error> {
+        let deserializer: StrDeserializer<Error> = text.into_deserializer();
+        T::deserialize(deserializer)
+    }
+
+    #[test]
+    fn errors_name_the_value() {
+        let err = from_str::<Cidr>("10.0.0.0/33").unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("invalid CIDR block '10.0.0.0/33': expected prefix length of at most 32"));
+        let err = from_str::<ResolveOnDeserialize>("no-such-host.invalid").unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("could not resolve 'no-such-host.invalid'"));
+    }
+    #[test]
+    fn lists_and_helpers() {
+        let deserializer: SeqDeserializer<_, Error> =
+            vec!["10.0.0.0/8", "2001:db8::/32"].into_deserializer();
+        let blocks = deserialize_cidr_list(deserializer).unwrap();
+        assert_eq!(blocks[1].prefix_len(), 32);
+        let deserializer: StrDeserializer<Error> = "db.example.com".into_deserializer();
+        assert_eq!(
+            deserialize_host_or_ip(deserializer).unwrap(),
+            HostOrIp::Host("db.example.com".into())
+        );
+        let deserializer: SeqDeserializer<_, Error> =
+            vec!["when ip in 10/8 -> reject"].into_deserializer();
+        assert_eq!(Policy::deserialize(deserializer).unwrap().rules().len(), 1);
+        let deserializer: SeqDeserializer<_, Error> =
+            vec!["pool lab 10.0.0.0/24", "alloc lab 10.0.0.1/32 gw"].into_deserializer();
+        assert_eq!(Allocator::deserialize(deserializer).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,270 @@
+//! Ambient deadlines which nested lookups inherit.
+//!
+//! A caller with a time budget sets a deadline around the work it spends the
+//! budget on, and every lookup made inside, however deeply nested, gives up
+//! with a Timeout error once the deadline passes, without the budget being
+//! threaded through each call. Nested deadlines can only shorten the budget:
+//! the earliest one in scope applies.
+//!
+//! [`DirectResolver`](crate::direct::DirectResolver) shortens its per query
+//! timeouts to fit the deadline. With a tokio feature (`async`, `hyper` or
+//! `reqwest`), [`scope_async`]
+//! sets a deadline for a task, and [`resolve`] runs a blocking lookup which
+//! both inherits the task's deadline and is abandoned once it passes, so it
+//! composes with `tokio::time::timeout` set further out.
+use std::cell::Cell;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Durations longer than this, a century, are as good as never
+const FOREVER: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+thread_local! {
+    static THREAD_DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+#[cfg(any(feature = "async", feature = "hyper", feature = "reqwest"))]
+tokio::task_local! {
+    static TASK_DEADLINE: Option<Instant>;
+}
+
+/// The earliest deadline in scope, if any
+pub fn current() -> Option<Instant> {
+    let thread = THREAD_DEADLINE.with(Cell::get);
+    #[cfg(any(feature = "async", feature = "hyper", feature = "reqwest"))]
+    {
+        if let Ok(task) = TASK_DEADLINE.try_with(|deadline| *deadline) {
+            return earliest(thread, task);
+        }
+    }
+    thread
+}
+
+/// Time left before the deadline in scope, if any
+pub fn remaining() -> Option<Duration> {
+    current().map(|deadline| deadline.saturating_duration_since(Instant::now()))
+}
+
+/// Whether the deadline in scope has passed
+pub fn expired() -> bool {
+    remaining() == Some(Duration::from_secs(0))
+}
+
+/// Run `f` with `deadline` applying to the lookups it makes on this thread.
+///
+/// # Parameters
+///
+/// * `deadline` - when lookups should give up, unless an earlier deadline is
+///   already in scope
+/// * `f` - the work to run
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::deadline;
+/// use std::time::{Duration, Instant};
+///
+/// let outer = Instant::now() + Duration::from_secs(1);
+/// deadline::scope(outer, || {
+///     // an inner budget cannot extend the outer one
+///     deadline::scope(outer + Duration::from_secs(60), || {
+///         assert_eq!(deadline::current(), Some(outer));
+///     });
+/// });
+/// assert_eq!(deadline::current(), None);
+/// ```
+pub fn scope<T, F: FnOnce() -> T>(deadline: Instant, f: F) -> T {
+    /// Restores the previous deadline, even if `f` panics
+    struct Restore(Option<Instant>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            THREAD_DEADLINE.with(|cell| cell.set(self.0));
+        }
+    }
+
+    let previous = THREAD_DEADLINE.with(|cell| cell.replace(earliest(cell.get(), Some(deadline))));
+    let _restore = Restore(previous);
+    f()
+}
+
+/// Run `f` under `deadline` if there is one, for carrying the deadline in
+/// scope over to another thread
+pub(crate) fn inherit<T, F: FnOnce() -> T>(deadline: Option<Instant>, f: F) -> T {
+    match deadline {
+        Some(deadline) => scope(deadline, f),
+        None => f(),
+    }
+}
+
+/// `timeout`, shortened to fit the deadline in scope. Never zero, since
+/// socket timeouts cannot be; callers check [`expired`] first.
+pub(crate) fn clip(timeout: Duration) -> Duration {
+    match remaining() {
+        Some(remaining) => timeout.min(remaining).max(Duration::from_millis(1)),
+        None => timeout,
+    }
+}
+
+/// `duration` from now, or a century from now where adding `duration`
+/// would overflow, as a budget or TTL that long means never
+pub(crate) fn after(duration: Duration) -> Instant {
+    let now = Instant::now();
+    now.checked_add(duration).unwrap_or_else(|| now + FOREVER)
+}
+
+/// [`after`], in wall clock time, for expiries which are saved
+pub(crate) fn system_time_after(duration: Duration) -> SystemTime {
+    let now = SystemTime::now();
+    now.checked_add(duration).unwrap_or_else(|| now + FOREVER)
+}
+
+fn earliest(a: Option<Instant>, b: Option<Instant>) -> Option<Instant> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+#[cfg(any(feature = "async", feature = "hyper", feature = "reqwest"))]
+pub use self::task::{resolve, scope_async};
+
+#[cfg(any(feature = "async", feature = "hyper", feature = "reqwest"))]
+mod task {
+    use super::*;
+    use crate::errors::IpaddrConversionError;
+    use crate::resolver::Resolve;
+    use std::future::Future;
+    use std::io;
+    use std::net::IpAddr;
+    use std::sync::Arc;
+
+    /// Run `future` with `deadline` applying to the lookups it makes, like
+    /// [`scope`] but for a task. The future itself is not cut short; wrap it
+    /// in `tokio::time::timeout_at` for that.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use ipaddr_from_str::{deadline, SystemResolver};
+    /// use std::sync::Arc;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// # async fn run() -> Result<(), ipaddr_from_str::IpaddrConversionError> {
+    /// let budget = Instant::now() + Duration::from_millis(500);
+    /// let addrs = deadline::scope_async(budget, async {
+    ///     deadline::resolve(Arc::new(SystemResolver), "example.com").await
+    /// })
+    /// .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn scope_async<F: Future>(deadline: Instant, future: F) -> F::Output {
+        let deadline = earliest(current(), Some(deadline));
+        TASK_DEADLINE.scope(deadline, future).await
+    }
+
+    /// Resolve `name` on the blocking pool under the deadline in scope,
+    /// returning a Timeout error once it passes. The blocking lookup is
+    /// left to finish in the background.
+    pub async fn resolve<R>(
+        resolver: Arc<R>,
+        name: &str,
+    ) -> Result<Vec<IpAddr>, IpaddrConversionError>
+    where
+        R: Resolve + Send + Sync + ?Sized + 'static,
+    {
+        let deadline = current();
+        if expired() {
+            return Err(IpaddrConversionError::Timeout(name.to_string()));
+        }
+        let owned = name.to_string();
+        let lookup =
+            tokio::task::spawn_blocking(move || inherit(deadline, || resolver.resolve(&owned)));
+        let joined = match deadline {
+            Some(deadline) => {
+                match tokio::time::timeout_at(tokio::time::Instant::from_std(deadline), lookup)
+                    .await
+                {
+                    Ok(joined) => joined,
+                    Err(_) => return Err(IpaddrConversionError::Timeout(name.to_string())),
+                }
+            }
+            None => lookup.await,
+        };
+        joined.map_err(|err| IpaddrConversionError::IoError(io::Error::other(err)))?
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::resolver::StaticResolver;
+
+        /// Finds names only while a deadline is in scope
+        struct Probe;
+
+        impl Resolve for Probe {
+            fn resolve(&self, name: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+                match current() {
+                    Some(_) => Ok(vec![]),
+                    None => Err(IpaddrConversionError::NotFound(name.to_string())),
+                }
+            }
+        }
+
+        #[test]
+        fn task_deadline_reaches_the_blocking_lookup() {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            let budget = Instant::now() + Duration::from_secs(5);
+            let inside = runtime.block_on(scope_async(budget, resolve(Arc::new(Probe), "a")));
+            assert!(inside.is_ok());
+            let outside = runtime.block_on(resolve(Arc::new(Probe), "a"));
+            assert!(outside.is_err());
+            let result = runtime.block_on(scope_async(Instant::now(), async {
+                resolve(Arc::new(StaticResolver::new()), "a.example").await
+            }));
+            assert!(matches!(result, Err(IpaddrConversionError::Timeout(_))));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn earliest_deadline_applies_and_is_restored() {
+        let now = Instant::now();
+        let outer = now + Duration::from_secs(1);
+        scope(outer, || {
+            scope(now + Duration::from_secs(60), || {
+                assert_eq!(current(), Some(outer))
+            });
+            scope(now, || assert!(expired()));
+            assert_eq!(current(), Some(outer));
+            assert!(clip(Duration::from_secs(30)) <= Duration::from_secs(1));
+        });
+        assert_eq!(current(), None);
+        assert_eq!(clip(Duration::from_secs(30)), Duration::from_secs(30));
+    }
+    #[test]
+    fn overlong_durations_end_a_century_on() {
+        let never = Duration::from_secs(u64::MAX);
+        assert!(after(never) > Instant::now() + Duration::from_secs(1 << 31));
+        assert!(system_time_after(never) > SystemTime::now() + Duration::from_secs(1 << 31));
+    }
+    #[test]
+    fn inherit_carries_a_deadline_to_another_thread() {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        scope(deadline, || {
+            let ambient = current();
+            let seen = thread::spawn(move || inherit(ambient, current))
+                .join()
+                .unwrap();
+            assert_eq!(seen, Some(deadline));
+        });
+    }
+}
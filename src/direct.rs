@@ -0,0 +1,1479 @@
+//! A resolver which queries nameservers directly rather than going through the
+//! system's getaddrinfo.
+//!
+//! [`DirectResolver`] speaks DNS over UDP to the nameservers of a
+//! [`ResolvConf`], retrying over TCP when an answer is truncated, and honors
+//! its search list, ndots, timeout, attempts, rotate and use-vc settings, so
+//! it behaves like the system resolver while remaining under the crate's
+//! control.
+//! Like the system resolver it can consult a hosts file, in the order given by
+//! nsswitch.conf.
+//!
+//! To resist cache poisoning each query goes out with a random id from a
+//! random source port, and responses must echo the question. Optionally the
+//! letters of the name are sent in random case (the "0x20" scheme) and the
+//! response must echo that case too, adding a bit of entropy per letter.
+//!
+//! [`DirectResolver::shutdown`] makes queries in flight give up promptly,
+//! and later ones fail at once, for a clean exit. Queries also give up at the
+//! caller's [deadline](crate::deadline), if one is in scope.
+//!
+//! With a [`CircuitBreaker`], nameservers which keep failing are skipped
+//! until a probe finds them answering again, rather than costing every
+//! lookup a timeout.
+use crate::adaptive::AdaptiveTimeout;
+use crate::breaker::{BreakerState, CircuitBreaker};
+use crate::cancel::{self, CancellationToken};
+use crate::deadline;
+use crate::dns::{self, Message, Mx, RData, RecordType, Srv};
+use crate::dump::DebugDump;
+use crate::errors::IpaddrConversionError;
+use crate::hosts::HostsFile;
+use crate::lookup::LookupResult;
+use crate::nsswitch::Source;
+use crate::rdns::{confirm_names, reverse_name, ReverseVerdict};
+use crate::resolv_conf::ResolvConf;
+use crate::resolver::{normalize_name, Resolve};
+use crate::selection::{Selector, ServerSelection};
+use crate::socket::SocketOptions;
+use crate::stats::StatsCollector;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// largest response accepted over udp without EDNS
+const MAX_UDP_RESPONSE: usize = 512;
+
+/// How long a lookup waits on its A and AAAA queries, which run at once
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FamilyWait {
+    /// Wait for both, returning the addresses of each
+    Both,
+    /// Return as soon as either has answered with addresses, abandoning the
+    /// other
+    First,
+}
+
+/// Resolver sending queries straight to the configured nameservers.
+///
+/// Names which are not fully qualified are expanded with the search domains
+/// of the configuration and tried in the order glibc tries them, which
+/// [`ResolvConf::candidates`] gives, unless expansion is turned off with
+/// `with_search`; other queries, such as for SRV records, go out exactly as
+/// given. A and AAAA records are requested at once and the addresses of
+/// both are returned, IPv4 first, or with [`FamilyWait::First`] those of
+/// whichever answers first. Queries go over UDP, falling back to TCP for
+/// responses which had to be truncated, unless TCP is forced with
+/// `with_tcp` or the `use-vc` option.
+///
+/// Sources are consulted in order until one finds the name. A resolver built
+/// with `new` only uses DNS; `from_system` also reads the hosts file and the
+/// nsswitch.conf lookup order (on Windows, the hosts file is always consulted
+/// first, as the DNS client does).
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::direct::DirectResolver;
+/// use ipaddr_from_str::Resolve;
+///
+/// let resolver = DirectResolver::from_system().unwrap();
+/// let addrs = resolver.resolve("www.example.com");
+/// ```
+#[derive(Debug, Clone)]
+pub struct DirectResolver {
+    config: Arc<ResolvConf>,
+    sources: Vec<Source>,
+    hosts: Arc<HostsFile>,
+    selection: ServerSelection,
+    // shared by clones, so that rotation and round trip times span them
+    selector: Arc<Selector>,
+    randomize_case: bool,
+    family_wait: FamilyWait,
+    search: bool,
+    token: CancellationToken,
+    source: SocketOptions,
+    adaptive: Option<Arc<AdaptiveTimeout>>,
+    breaker: Option<Arc<CircuitBreaker>>,
+    stats: Option<Arc<StatsCollector>>,
+    #[cfg(feature = "dot")]
+    tls: Option<Arc<crate::dot::DotTransport>>,
+}
+
+impl DirectResolver {
+    /// New resolver using the nameservers and options in `config`
+    pub fn new(config: ResolvConf) -> Self {
+        let selection = if config.rotate {
+            ServerSelection::Rotate
+        } else {
+            ServerSelection::InOrder
+        };
+        Self {
+            selection,
+            config: Arc::new(config),
+            sources: vec![Source::Dns],
+            hosts: Arc::new(HostsFile::default()),
+            selector: Arc::new(Selector::default()),
+            randomize_case: false,
+            family_wait: FamilyWait::Both,
+            search: true,
+            token: CancellationToken::new(),
+            source: SocketOptions::new(),
+            adaptive: None,
+            breaker: None,
+            stats: None,
+            #[cfg(feature = "dot")]
+            tls: None,
+        }
+    }
+
+    /// New resolver configured like the system resolver: nameservers and
+    /// options from resolv.conf (or the registry on Windows) and the hosts
+    /// file consulted in the order nsswitch.conf specifies. A missing hosts
+    /// file is treated as empty.
+    pub fn from_system() -> Result<Self, IpaddrConversionError> {
+        let hosts = HostsFile::system().unwrap_or_default();
+        Ok(Self::new(ResolvConf::system()?)
+            .with_sources(system_sources()?)
+            .with_hosts(hosts))
+    }
+
+    /// Set the order in which sources are consulted, returning the updated
+    /// resolver. Sources other than files and dns are skipped.
+    pub fn with_sources(mut self, sources: Vec<Source>) -> Self {
+        self.sources = sources;
+        self
+    }
+
+    /// Set the hosts file consulted for the files source, returning the
+    /// updated resolver
+    pub fn with_hosts(mut self, hosts: HostsFile) -> Self {
+        self.hosts = Arc::new(hosts);
+        self
+    }
+
+    /// Set whether every query goes over TCP, returning the updated resolver
+    pub fn with_tcp(mut self, force: bool) -> Self {
+        Arc::make_mut(&mut self.config).use_vc = force;
+        self
+    }
+
+    /// Set whether names are sent with the case of their letters randomized,
+    /// and responses which do not echo it ignored, returning the updated
+    /// resolver. A few nameservers do not preserve case and so never answer.
+    pub fn with_case_randomization(mut self, enabled: bool) -> Self {
+        self.randomize_case = enabled;
+        self
+    }
+
+    /// Set whether lookups wait for both the A and AAAA answers, returning
+    /// the updated resolver
+    pub fn with_family_wait(mut self, wait: FamilyWait) -> Self {
+        self.family_wait = wait;
+        self
+    }
+
+    /// Set whether names which are not fully qualified are expanded with the
+    /// search domains, returning the updated resolver. Turn it off where the
+    /// names are expanded already, or should be queried only as written.
+    pub fn with_search(mut self, enabled: bool) -> Self {
+        self.search = enabled;
+        self
+    }
+
+    /// Give up on every query once `token` is cancelled, returning the
+    /// updated resolver
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.token = token;
+        self
+    }
+
+    /// Send queries from sockets set up with `options`, returning the
+    /// updated resolver. Their [local address](SocketOptions::local_addr)
+    /// and [interface](SocketOptions::bind_device) choose where queries
+    /// leave a multi-homed host; a clone with other options sends a single
+    /// call's queries elsewhere, sharing the rest of the resolver.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use ipaddr_from_str::direct::DirectResolver;
+    /// use ipaddr_from_str::socket::SocketOptions;
+    /// use ipaddr_from_str::Resolve;
+    ///
+    /// let resolver = DirectResolver::from_system().unwrap();
+    /// let over_vpn = resolver
+    ///     .clone()
+    ///     .with_source(SocketOptions::new().bind_device("wg0"));
+    /// let addrs = over_vpn.resolve("intranet.corp.example");
+    /// ```
+    pub fn with_source(mut self, options: SocketOptions) -> Self {
+        self.source = options;
+        self
+    }
+
+    /// Time each query out as `adaptive` sets from the latency of earlier
+    /// ones, in place of the configuration's static timeout, which becomes
+    /// its fallback and ceiling, returning the updated resolver
+    pub fn with_adaptive_timeout(mut self, adaptive: AdaptiveTimeout) -> Self {
+        self.adaptive = Some(Arc::new(adaptive));
+        self
+    }
+
+    /// The timeout for the next query to a nameserver, before any deadline
+    /// shortens it
+    fn query_timeout(&self) -> Duration {
+        match self.adaptive {
+            Some(ref adaptive) => adaptive.timeout(self.config.timeout),
+            None => self.config.timeout,
+        }
+    }
+
+    /// Try the nameservers in the order `selection` sets, in place of the
+    /// order listed or, with the `rotate` option, in turn, returning the
+    /// updated resolver
+    pub fn with_selection(mut self, selection: ServerSelection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    /// The order in which nameservers are tried
+    pub fn selection(&self) -> ServerSelection {
+        self.selection
+    }
+
+    /// The smoothed round trip time to `server`, as fastest selection
+    /// orders by, or None if it has not answered, failed or timed out yet
+    pub fn server_rtt(&self, server: SocketAddr) -> Option<Duration> {
+        self.selector.rtt(server)
+    }
+
+    /// Skip the nameservers whose circuit `breaker` has opened, returning
+    /// the updated resolver
+    pub fn with_circuit_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.breaker = Some(Arc::new(breaker));
+        self.report_health();
+        self
+    }
+
+    /// The circuit breaker consulted, if any
+    pub fn circuit_breaker(&self) -> Option<&CircuitBreaker> {
+        self.breaker.as_deref()
+    }
+
+    /// Record in `collector` the queries which skipped a nameserver, its
+    /// circuit being open, and the health of the nameservers, returning the
+    /// updated resolver
+    pub fn with_stats(mut self, collector: Arc<StatsCollector>) -> Self {
+        self.stats = Some(collector);
+        self.report_health();
+        self
+    }
+
+    fn report_health(&self) {
+        if let (Some(stats), Some(breaker)) = (&self.stats, &self.breaker) {
+            stats.report_health(breaker.clone());
+        }
+    }
+
+    /// Cancel the resolver's token: queries in flight over UDP give up
+    /// within a few milliseconds, those over TCP at their next timeout, and
+    /// later ones fail at once with a Cancelled error
+    pub fn shutdown(&self) {
+        self.token.cancel();
+    }
+
+    /// Send every query over DNS over TLS, returning the updated resolver.
+    /// Nameservers are contacted on the DoT port rather than the port in the
+    /// configuration.
+    #[cfg(feature = "dot")]
+    pub fn with_tls(
+        mut self,
+        config: crate::dot::DotConfig,
+    ) -> Result<Self, IpaddrConversionError> {
+        self.tls = Some(Arc::new(crate::dot::DotTransport::new(config)?));
+        Ok(self)
+    }
+
+    /// The configuration in use
+    pub fn config(&self) -> &ResolvConf {
+        &self.config
+    }
+
+    /// The order in which sources are consulted
+    pub fn sources(&self) -> &[Source] {
+        &self.sources
+    }
+
+    /// The configuration in use, the health of the nameservers and any
+    /// figures recorded, for debugging
+    pub fn debug_dump(&self) -> DebugDump {
+        let mut dump = DebugDump::new().with_config(&self.config);
+        if let Some(ref breaker) = self.breaker {
+            dump = dump.with_breaker(breaker);
+        }
+        match self.stats {
+            Some(ref collector) => dump.with_stats(collector),
+            None => dump,
+        }
+    }
+
+    /// Query `name` for records of `qtype`, returning the first usable
+    /// response.
+    ///
+    /// Records the response had no business carrying are dropped first: see
+    /// [`drop_out_of_bailiwick`].
+    ///
+    /// Each attempt walks the nameserver list in the order the resolver's
+    /// [`ServerSelection`] sets. Servers which time out, fail or
+    /// refuse are skipped, as are those whose circuit is open unless every
+    /// one's is. A name error is authoritative and ends the search.
+    pub fn query(&self, name: &str, qtype: RecordType) -> Result<Message, IpaddrConversionError> {
+        self.query_until(name, qtype, &AtomicBool::new(false))
+    }
+
+    /// [`query`](Self::query), giving up with a timeout once `cancel` is set
+    fn query_until(
+        &self,
+        name: &str,
+        qtype: RecordType,
+        cancel: &AtomicBool,
+    ) -> Result<Message, IpaddrConversionError> {
+        let servers = &self.config.nameservers;
+        if servers.is_empty() {
+            return Err(IpaddrConversionError::DnsError(
+                "no nameservers configured".into(),
+            ));
+        }
+        let order = self.selector.order(self.selection, servers, name);
+        // with every circuit open, failing without asking would be no better
+        let breaker = self.breaker.as_ref().filter(|breaker| {
+            !servers
+                .iter()
+                .all(|server| breaker.state(*server) == BreakerState::Open)
+        });
+        let mut last_err = None;
+        for _ in 0..self.config.attempts.max(1) {
+            for &server in order.iter() {
+                if self.token.is_cancelled() {
+                    return Err(cancel::cancelled(name));
+                }
+                if cancel.load(Ordering::Relaxed) || deadline::expired() {
+                    return Err(IpaddrConversionError::Timeout(name.to_string()));
+                }
+                if breaker.is_some_and(|breaker| !breaker.allow(server)) {
+                    if let Some(ref stats) = self.stats {
+                        stats.record_skipped_server();
+                    }
+                    continue;
+                }
+                let id = random_u64() as u16;
+                let request = if self.randomize_case {
+                    Message::query(id, &randomize_case(name), qtype)
+                } else {
+                    Message::query(id, name, qtype)
+                };
+                let started = Instant::now();
+                let result = self.exchange(server, &request, cancel);
+                match result {
+                    Ok(ref response) if answered(response) => {
+                        self.selector.record(server, started.elapsed())
+                    }
+                    Err(IpaddrConversionError::Cancelled(_)) => {}
+                    _ if cancel.load(Ordering::Relaxed) || deadline::expired() => {}
+                    Err(IpaddrConversionError::Timeout(_)) => {
+                        self.selector.record(server, started.elapsed())
+                    }
+                    // a server refusing or failing is no quicker to answer
+                    // than one timing out
+                    _ => self.selector.record(server, self.query_timeout()),
+                }
+                if let Some(ref adaptive) = self.adaptive {
+                    // as for selection, a query cut short by the other
+                    // family answering or by the deadline says nothing of
+                    // how long a query takes
+                    match result {
+                        Ok(_) => adaptive.record(started.elapsed()),
+                        Err(IpaddrConversionError::Timeout(_))
+                            if !cancel.load(Ordering::Relaxed) && !deadline::expired() =>
+                        {
+                            adaptive.record(started.elapsed())
+                        }
+                        _ => {}
+                    }
+                }
+                if let Some(ref breaker) = self.breaker {
+                    record_health(breaker, server, &result, cancel);
+                }
+                match result {
+                    Ok(response) => match response.header.rcode {
+                        dns::RCODE_NOERROR => {
+                            let mut response = response;
+                            drop_out_of_bailiwick(&mut response, name, qtype);
+                            return Ok(response);
+                        }
+                        dns::RCODE_NXDOMAIN => {
+                            return Err(IpaddrConversionError::NotFound(name.to_string()))
+                        }
+                        rcode => {
+                            last_err = Some(IpaddrConversionError::DnsError(format!(
+                                "{} answered with rcode {}",
+                                server, rcode
+                            )))
+                        }
+                    },
+                    // report the name as given rather than as sent
+                    Err(IpaddrConversionError::Cancelled(_)) => {
+                        return Err(cancel::cancelled(name))
+                    }
+                    Err(IpaddrConversionError::Timeout(_)) => {
+                        last_err = Some(IpaddrConversionError::Timeout(name.to_string()))
+                    }
+                    Err(err) => last_err = Some(err),
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| IpaddrConversionError::Timeout(name.to_string())))
+    }
+
+    /// Send `request` to `server`, over TCP if forced or if the UDP response
+    /// was truncated
+    fn exchange(
+        &self,
+        server: SocketAddr,
+        request: &Message,
+        cancel: &AtomicBool,
+    ) -> Result<Message, IpaddrConversionError> {
+        #[cfg(feature = "dot")]
+        {
+            if let Some(ref tls) = self.tls {
+                let stream = tls
+                    .connect(server, &self.source, deadline::clip(self.query_timeout()))
+                    .map_err(|err| stream_error(err, request))?;
+                return exchange_stream(stream, server, request, self.randomize_case);
+            }
+        }
+        if !self.config.use_vc {
+            let response = self.exchange_udp(server, request, cancel)?;
+            if !response.header.truncated {
+                return Ok(response);
+            }
+        }
+        self.exchange_tcp(server, request)
+    }
+
+    /// Send `request` to `server` over TCP, using the two byte length prefix
+    /// framing of RFC 1035 section 4.2.2
+    fn exchange_tcp(
+        &self,
+        server: SocketAddr,
+        request: &Message,
+    ) -> Result<Message, IpaddrConversionError> {
+        let timeout = deadline::clip(self.query_timeout());
+        let socket = self.source.connecting_socket(&server)?;
+        socket
+            .connect_timeout(&server.into(), timeout)
+            .map_err(|err| stream_error(err, request))?;
+        let stream = socket.into_tcp_stream();
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        exchange_stream(stream, server, request, self.randomize_case)
+    }
+
+    /// Send `request` to `server` over udp and wait for the matching
+    /// response, or until `cancel` is set
+    fn exchange_udp(
+        &self,
+        server: SocketAddr,
+        request: &Message,
+        cancel: &AtomicBool,
+    ) -> Result<Message, IpaddrConversionError> {
+        let socket = bind_random_port(server, &self.source)?;
+        socket.connect(server)?;
+        socket.send(&request.encode().map_err(wire_error)?)?;
+        let deadline = deadline::after(deadline::clip(self.query_timeout()));
+        let mut buf = [0u8; MAX_UDP_RESPONSE];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if self.token.is_cancelled() {
+                return Err(cancel::cancelled(""));
+            }
+            if remaining == Duration::from_secs(0) || cancel.load(Ordering::Relaxed) {
+                return Err(timeout(request));
+            }
+            socket.set_read_timeout(Some(remaining.min(cancel::POLL_INTERVAL)))?;
+            let len = match socket.recv(&mut buf) {
+                Ok(len) => len,
+                Err(ref err)
+                    if err.kind() == io::ErrorKind::WouldBlock
+                        || err.kind() == io::ErrorKind::TimedOut =>
+                {
+                    continue
+                }
+                Err(err) => return Err(err.into()),
+            };
+            // anything which does not parse, or answers a different query, is
+            // ignored rather than treated as the answer
+            match Message::decode(&buf[..len]) {
+                Ok(response) if answers(&response, request, self.randomize_case) => {
+                    return Ok(response)
+                }
+                _ => continue,
+            }
+        }
+    }
+}
+
+/// Record how `server` did in `breaker`: it failed if it timed out, erred or
+/// answered other than with an answer or a name error. Queries abandoned by
+/// the caller say nothing about the server.
+fn record_health(
+    breaker: &CircuitBreaker,
+    server: SocketAddr,
+    result: &Result<Message, IpaddrConversionError>,
+    cancel: &AtomicBool,
+) {
+    match result {
+        Ok(response) if answered(response) => breaker.record_success(server),
+        Err(IpaddrConversionError::Cancelled(_)) => {}
+        Err(IpaddrConversionError::Timeout(_))
+            if cancel.load(Ordering::Relaxed) || deadline::expired() => {}
+        _ => breaker.record_failure(server),
+    }
+}
+
+/// Whether `response` answers the query, with records or a name error,
+/// rather than refusing or failing it
+fn answered(response: &Message) -> bool {
+    response.header.rcode == dns::RCODE_NOERROR || response.header.rcode == dns::RCODE_NXDOMAIN
+}
+
+/// Send `request` down a connected stream and read the response, using the
+/// two byte length prefix framing shared by TCP and TLS
+fn exchange_stream<S: Read + Write>(
+    mut stream: S,
+    server: SocketAddr,
+    request: &Message,
+    exact_case: bool,
+) -> Result<Message, IpaddrConversionError> {
+    let body = request.encode().map_err(wire_error)?;
+    let mut framed = Vec::with_capacity(body.len() + 2);
+    framed.extend_from_slice(&(body.len() as u16).to_be_bytes());
+    framed.extend_from_slice(&body);
+    stream
+        .write_all(&framed)
+        .map_err(|err| stream_error(err, request))?;
+    let mut len = [0u8; 2];
+    stream
+        .read_exact(&mut len)
+        .map_err(|err| stream_error(err, request))?;
+    let mut buf = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream
+        .read_exact(&mut buf)
+        .map_err(|err| stream_error(err, request))?;
+    let response = Message::decode(&buf).map_err(wire_error)?;
+    if !answers(&response, request, exact_case) {
+        return Err(IpaddrConversionError::DnsError(format!(
+            "{} answered a different query",
+            server
+        )));
+    }
+    Ok(response)
+}
+
+/// Map io errors on a stream, reporting timeouts as such
+fn stream_error(err: io::Error, request: &Message) -> IpaddrConversionError {
+    match err.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => timeout(request),
+        _ => err.into(),
+    }
+}
+
+/// Whether `response` is a response to `request`, comparing the question
+/// names exactly when `exact_case` is set and case-insensitively otherwise
+fn answers(response: &Message, request: &Message, exact_case: bool) -> bool {
+    let same_question = |a: &dns::Question, b: &dns::Question| {
+        let same_name = if exact_case {
+            a.name == b.name
+        } else {
+            a.name.eq_ignore_ascii_case(&b.name)
+        };
+        same_name && a.qtype == b.qtype && a.qclass == b.qclass
+    };
+    response.header.response
+        && response.header.id == request.header.id
+        && response.questions.len() == request.questions.len()
+        && response
+            .questions
+            .iter()
+            .zip(&request.questions)
+            .all(|(a, b)| same_question(a, b))
+}
+
+/// A random number, from the SipHash keys std seeds from the operating
+/// system's random source, unpredictable to anyone off this host
+pub(crate) fn random_u64() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.finish()
+}
+
+/// Lowest port picked as a query's source port
+const MIN_SOURCE_PORT: u16 = 1024;
+/// Ports tried before leaving the choice to the operating system
+const SOURCE_PORT_ATTEMPTS: usize = 8;
+
+/// A UDP socket for talking to `server`, set up with `source` and bound to a
+/// random port so that a forged response must guess the port as well as the
+/// id
+fn bind_random_port(server: SocketAddr, source: &SocketOptions) -> io::Result<UdpSocket> {
+    let ip: IpAddr = source
+        .local_addr_for(&server)
+        .unwrap_or_else(|| match server {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        });
+    let socket = source.socket(&server, false)?;
+    let span = u64::from(u16::MAX - MIN_SOURCE_PORT) + 1;
+    for _ in 0..SOURCE_PORT_ATTEMPTS {
+        let port = MIN_SOURCE_PORT + (random_u64() % span) as u16;
+        // a failed bind leaves the socket unbound, to be tried again
+        match socket.bind(&SocketAddr::new(ip, port).into()) {
+            Ok(()) => return Ok(socket.into_udp_socket()),
+            Err(ref err)
+                if err.kind() == io::ErrorKind::AddrInUse
+                    || err.kind() == io::ErrorKind::PermissionDenied => {}
+            Err(err) => return Err(err),
+        }
+    }
+    socket.bind(&SocketAddr::new(ip, 0).into())?;
+    Ok(socket.into_udp_socket())
+}
+
+/// `name` with each letter upper or lower case at random
+fn randomize_case(name: &str) -> String {
+    let mut bits = 0;
+    let mut left = 0;
+    name.chars()
+        .map(|c| {
+            if !c.is_ascii_alphabetic() {
+                return c;
+            }
+            if left == 0 {
+                bits = random_u64();
+                left = 64;
+            }
+            left -= 1;
+            let upper = bits & 1 == 1;
+            bits >>= 1;
+            if upper {
+                c.to_ascii_uppercase()
+            } else {
+                c.to_ascii_lowercase()
+            }
+        })
+        .collect()
+}
+
+fn timeout(request: &Message) -> IpaddrConversionError {
+    let name = request
+        .questions
+        .first()
+        .map(|q| q.name.as_str())
+        .unwrap_or("");
+    IpaddrConversionError::Timeout(name.to_string())
+}
+
+fn wire_error(err: dns::WireError) -> IpaddrConversionError {
+    IpaddrConversionError::DnsError(err.to_string())
+}
+
+/// Whether the zone `zone` contains `name`, both already normalized
+fn in_zone(name: &str, zone: &str) -> bool {
+    zone.is_empty()
+        || name == zone
+        || (name.len() > zone.len()
+            && name.ends_with(zone)
+            && name.as_bytes()[name.len() - zone.len() - 1] == b'.')
+}
+
+/// Drop the records of a response to a query for `name` and `qtype` which
+/// could have been injected, returning how many were dropped.
+///
+/// Answers are kept only if they are CNAMEs or records of `qtype` for `name`
+/// or a name its CNAME chain leads to. Authority records are kept only for
+/// zones containing `name`. Additional records, which a stub resolver never
+/// needs, are all dropped. Without this a nameserver could slip in addresses
+/// for unrelated names, to be cached under them.
+pub fn drop_out_of_bailiwick(response: &mut Message, name: &str, qtype: RecordType) -> usize {
+    let before = response.answers.len() + response.authorities.len() + response.additionals.len();
+    let name = normalize_name(name);
+    let (mut chain, canonical) = cname_chain(response, &name);
+    chain.push(canonical);
+    response.answers.retain(|record| {
+        record.rclass == dns::RecordClass::In
+            && (record.rtype == RecordType::Cname || record.rtype == qtype)
+            && chain.contains(&normalize_name(&record.name))
+    });
+    response
+        .authorities
+        .retain(|record| in_zone(&name, &normalize_name(&record.name)));
+    response.additionals.clear();
+    before - response.answers.len() - response.authorities.len()
+}
+
+/// The addresses carried by the answer section of `response`
+fn addresses(response: &Message) -> Vec<IpAddr> {
+    response
+        .answers
+        .iter()
+        .filter_map(|record| match record.data {
+            RData::A(ip) => Some(IpAddr::V4(ip)),
+            RData::Aaaa(ip) => Some(IpAddr::V6(ip)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(not(windows))]
+fn system_sources() -> Result<Vec<Source>, IpaddrConversionError> {
+    Ok(crate::nsswitch::NsSwitch::system()?.hosts)
+}
+
+#[cfg(windows)]
+fn system_sources() -> Result<Vec<Source>, IpaddrConversionError> {
+    Ok(vec![Source::Files, Source::Dns])
+}
+
+/// The CNAME chain starting at `name` in the answer section of `response`,
+/// returning the names passed through and the name it ends at
+fn cname_chain(response: &Message, name: &str) -> (Vec<String>, String) {
+    let mut aliases = Vec::new();
+    let mut current = normalize_name(name);
+    // bounded by the record count, so a looping chain terminates
+    for _ in 0..response.answers.len() {
+        let target = response
+            .answers
+            .iter()
+            .find_map(|record| match record.data {
+                RData::Cname(ref target) if normalize_name(&record.name) == current => {
+                    Some(normalize_name(target))
+                }
+                _ => None,
+            });
+        match target {
+            Some(target) => aliases.push(std::mem::replace(&mut current, target)),
+            None => break,
+        }
+    }
+    (aliases, current)
+}
+
+impl DirectResolver {
+    fn lookup_dns(&self, hostname_or_address: &str) -> Result<LookupResult, IpaddrConversionError> {
+        let cancel = AtomicBool::new(false);
+        let ambient = deadline::current();
+        let (v4, v6) = thread::scope(|scope| {
+            let (tx, rx) = mpsc::channel();
+            for qtype in [RecordType::A, RecordType::Aaaa] {
+                let tx = tx.clone();
+                let cancel = &cancel;
+                scope.spawn(move || {
+                    let result = deadline::inherit(ambient, || {
+                        self.query_until(hostname_or_address, qtype, cancel)
+                    });
+                    let _ = tx.send((qtype, result));
+                });
+            }
+            drop(tx);
+            let (mut v4, mut v6) = (None, None);
+            for (qtype, result) in rx {
+                let useful = matches!(result, Ok(ref response) if !addresses(response).is_empty());
+                if useful && self.family_wait == FamilyWait::First {
+                    cancel.store(true, Ordering::Relaxed);
+                }
+                if qtype == RecordType::A {
+                    v4 = Some(result);
+                } else {
+                    v6 = Some(result);
+                }
+            }
+            (
+                v4.expect("A query reports back"),
+                v6.expect("AAAA query reports back"),
+            )
+        });
+        let mut addrs = Vec::new();
+        let mut chain = None;
+        let mut failure = None;
+        for result in [v4, v6] {
+            match result {
+                Ok(response) => {
+                    addrs.extend(addresses(&response));
+                    if chain.is_none() && !response.answers.is_empty() {
+                        chain = Some(cname_chain(&response, hostname_or_address));
+                    }
+                }
+                // a name error for either family holds for both
+                Err(err @ IpaddrConversionError::NotFound(_)) => return Err(err),
+                Err(err) => {
+                    failure.get_or_insert(err);
+                }
+            }
+        }
+        if addrs.is_empty() {
+            return Err(failure.unwrap_or_else(|| {
+                IpaddrConversionError::NotFound(hostname_or_address.to_string())
+            }));
+        }
+        let (aliases, canonical_name) =
+            chain.unwrap_or_else(|| (Vec::new(), normalize_name(hostname_or_address)));
+        let mut result = LookupResult::new(hostname_or_address, Some(canonical_name), addrs);
+        result.aliases = aliases;
+        Ok(result)
+    }
+
+    /// Look `name` up in DNS under each of its search candidates in turn, as
+    /// glibc does: a name not found or a failing server moves on to the next
+    /// candidate, while a timeout ends the search. The first failure is
+    /// reported if no candidate is found.
+    fn search_dns(&self, name: &str) -> Result<LookupResult, IpaddrConversionError> {
+        if !self.search {
+            return self.lookup_dns(name);
+        }
+        let mut failure = None;
+        for candidate in self.config.candidates(name) {
+            match self.lookup_dns(&candidate) {
+                Ok(result) => return Ok(result),
+                Err(IpaddrConversionError::NotFound(_)) => {}
+                Err(err @ IpaddrConversionError::DnsError(_)) => {
+                    failure.get_or_insert(err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Err(failure.unwrap_or_else(|| IpaddrConversionError::NotFound(name.to_string())))
+    }
+
+    /// Look up `hostname_or_address` like [`Resolve::resolve`], also
+    /// reporting the canonical name and every alias of the CNAME chain
+    /// leading to it. Names found in the hosts file are their own canonical
+    /// name.
+    pub fn lookup(&self, hostname_or_address: &str) -> Result<LookupResult, IpaddrConversionError> {
+        if let Ok(ip) = hostname_or_address.parse::<IpAddr>() {
+            return Ok(LookupResult::new(hostname_or_address, None, vec![ip]));
+        }
+        let mut last_err = None;
+        for source in &self.sources {
+            let result = match source {
+                Source::Files => self
+                    .hosts
+                    .resolve(hostname_or_address)
+                    .map(|addrs| LookupResult::new(hostname_or_address, None, addrs)),
+                Source::Dns => self.search_dns(hostname_or_address),
+                Source::Other(_) => continue,
+            };
+            match result {
+                Ok(result) => return Ok(result),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err
+            .unwrap_or_else(|| IpaddrConversionError::NotFound(hostname_or_address.to_string())))
+    }
+
+    /// Look up the SRV records of `name`, such as `_grpc._tcp.example.com`,
+    /// in the order to try them: by priority, and within a priority in a
+    /// random order weighted by weight (RFC 2782). A lone target of "."
+    /// means the service is not offered, giving no records.
+    pub fn srv(&self, name: &str) -> Result<Vec<Srv>, IpaddrConversionError> {
+        let response = self.query(name, RecordType::Srv)?;
+        let records: Vec<Srv> = response
+            .answers
+            .into_iter()
+            .filter_map(|record| match record.data {
+                RData::Srv(srv) => Some(srv),
+                _ => None,
+            })
+            .collect();
+        if records
+            .iter()
+            .any(|srv| srv.target.trim_end_matches('.').is_empty())
+        {
+            return Ok(vec![]);
+        }
+        Ok(order_srv(records))
+    }
+
+    /// Look up the TXT records of `name`, each with its character strings
+    /// joined, as SPF and most other users of TXT records take them
+    pub fn txt(&self, name: &str) -> Result<Vec<String>, IpaddrConversionError> {
+        let response = self.query(name, RecordType::Txt)?;
+        Ok(response
+            .answers
+            .into_iter()
+            .filter_map(|record| match record.data {
+                RData::Txt(strings) => Some(strings.concat()),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Look up the mail exchangers of `name`, most preferred first
+    pub fn mx(&self, name: &str) -> Result<Vec<Mx>, IpaddrConversionError> {
+        let response = self.query(name, RecordType::Mx)?;
+        let mut records: Vec<Mx> = response
+            .answers
+            .into_iter()
+            .filter_map(|record| match record.data {
+                RData::Mx(mx) => Some(mx),
+                _ => None,
+            })
+            .collect();
+        records.sort_by_key(|mx| mx.preference);
+        Ok(records)
+    }
+
+    /// Look up the names the PTR records of `addr` give. Unlike the system
+    /// resolver's reverse lookup, every name is returned, not only the first.
+    pub fn ptr(&self, addr: IpAddr) -> Result<Vec<String>, IpaddrConversionError> {
+        let response = self.query(&reverse_name(addr), RecordType::Ptr)?;
+        Ok(response
+            .answers
+            .into_iter()
+            .filter_map(|record| match record.data {
+                RData::Ptr(name) => Some(normalize_name(&name)),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Check the PTR names of `addr` resolve back to it, as
+    /// [`confirm_reverse`](crate::rdns::confirm_reverse) does through the
+    /// system resolver, querying both ways through this resolver
+    pub fn confirm_reverse(&self, addr: IpAddr) -> Result<ReverseVerdict, IpaddrConversionError> {
+        match self.ptr(addr) {
+            Ok(names) => confirm_names(addr, &names, self),
+            Err(IpaddrConversionError::NotFound(_)) => Ok(ReverseVerdict::NoName),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Order SRV records by priority, shuffling each priority weighted by weight
+fn order_srv(mut records: Vec<Srv>) -> Vec<Srv> {
+    records.sort_by_key(|srv| srv.priority);
+    let mut ordered = Vec::with_capacity(records.len());
+    while !records.is_empty() {
+        let priority = records[0].priority;
+        let end = records.partition_point(|srv| srv.priority == priority);
+        let mut group: Vec<Srv> = records.drain(..end).collect();
+        while !group.is_empty() {
+            let total: u64 = group.iter().map(|srv| u64::from(srv.weight)).sum();
+            let idx = if total == 0 {
+                (random_u64() % group.len() as u64) as usize
+            } else {
+                let mut pick = random_u64() % total;
+                group
+                    .iter()
+                    .position(|srv| {
+                        let weight = u64::from(srv.weight);
+                        if pick < weight {
+                            return true;
+                        }
+                        pick -= weight;
+                        false
+                    })
+                    .expect("pick is below the total weight")
+            };
+            ordered.push(group.remove(idx));
+        }
+    }
+    ordered
+}
+
+impl Resolve for DirectResolver {
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        Ok(self.lookup(hostname_or_address)?.addrs())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::dns::Record;
+    use std::net::TcpListener;
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+
+    const RCODE_SERVFAIL: u8 = 2;
+    const RCODE_REFUSED: u8 = 5;
+
+    /// Answer queries on a local udp socket with `handler`, returning the
+    /// socket's address
+    pub(crate) fn spawn_udp_server<F>(handler: F) -> SocketAddr
+    where
+        F: Fn(&Message) -> Option<Message> + Send + 'static,
+    {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf) {
+                let request = Message::decode(&buf[..len]).unwrap();
+                if let Some(response) = handler(&request) {
+                    socket.send_to(&response.encode().unwrap(), peer).unwrap();
+                }
+            }
+        });
+        addr
+    }
+
+    /// Answer queries over TCP on `port` of the loopback address with `handler`
+    pub(crate) fn spawn_tcp_server<F>(port: u16, handler: F)
+    where
+        F: Fn(&Message) -> Message + Send + 'static,
+    {
+        let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut len = [0u8; 2];
+                stream.read_exact(&mut len).unwrap();
+                let mut buf = vec![0u8; u16::from_be_bytes(len) as usize];
+                stream.read_exact(&mut buf).unwrap();
+                let body = handler(&Message::decode(&buf).unwrap()).encode().unwrap();
+                stream
+                    .write_all(&(body.len() as u16).to_be_bytes())
+                    .unwrap();
+                stream.write_all(&body).unwrap();
+            }
+        });
+    }
+
+    /// Build a response to `request` carrying `data` as answers
+    pub(crate) fn answer(request: &Message, rcode: u8, data: Vec<RData>) -> Message {
+        let mut response = request.clone();
+        response.header.response = true;
+        response.header.rcode = rcode;
+        let name = request.questions[0].name.clone();
+        response.answers = data
+            .into_iter()
+            .map(|data| Record {
+                name: name.clone(),
+                rtype: match data {
+                    RData::A(_) => RecordType::A,
+                    RData::Aaaa(_) => RecordType::Aaaa,
+                    RData::Ptr(_) => RecordType::Ptr,
+                    RData::Mx(_) => RecordType::Mx,
+                    RData::Txt(_) => RecordType::Txt,
+                    RData::Srv(_) => RecordType::Srv,
+                    RData::Caa(_) => RecordType::Caa,
+                    _ => RecordType::Cname,
+                },
+                rclass: dns::RecordClass::In,
+                ttl: 300,
+                data,
+            })
+            .collect();
+        response
+    }
+
+    pub(crate) fn config_for(servers: Vec<SocketAddr>) -> ResolvConf {
+        ResolvConf {
+            nameservers: servers,
+            timeout: Duration::from_millis(200),
+            attempts: 1,
+            ..ResolvConf::default()
+        }
+    }
+
+    pub(crate) fn example_server() -> SocketAddr {
+        spawn_udp_server(|request| {
+            let data = match (
+                request.questions[0].name.as_str(),
+                request.questions[0].qtype,
+            ) {
+                ("www.example.com", RecordType::A) => vec![RData::A(Ipv4Addr::new(192, 0, 2, 1))],
+                ("www.example.com", _) => vec![RData::Aaaa("2001:db8::1".parse().unwrap())],
+                _ => return Some(answer(request, dns::RCODE_NXDOMAIN, vec![])),
+            };
+            Some(answer(request, dns::RCODE_NOERROR, data))
+        })
+    }
+
+    #[test]
+    fn can_resolve_both_families() {
+        let resolver = DirectResolver::new(config_for(vec![example_server()]));
+        let addrs = resolver.resolve("www.example.com").unwrap();
+        assert_eq!(
+            addrs,
+            vec![
+                "192.0.2.1".parse::<IpAddr>().unwrap(),
+                "2001:db8::1".parse().unwrap()
+            ]
+        );
+    }
+    // only linux routes all of 127/8 to the loopback interface
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn queries_leave_from_the_source_address() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = socket.local_addr().unwrap();
+        let peers = thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            let (len, peer) = socket.recv_from(&mut buf).unwrap();
+            let request = Message::decode(&buf[..len]).unwrap();
+            let response = answer(&request, dns::RCODE_NOERROR, vec![]);
+            socket.send_to(&response.encode().unwrap(), peer).unwrap();
+            peer
+        });
+        let source = SocketOptions::new().local_addr("127.0.0.2".parse().unwrap());
+        let resolver = DirectResolver::new(config_for(vec![server])).with_source(source);
+        let _ = resolver.query("www.example.com", RecordType::A);
+        assert_eq!(
+            peers.join().unwrap().ip(),
+            "127.0.0.2".parse::<IpAddr>().unwrap()
+        );
+    }
+    #[test]
+    fn adaptive_timeouts_follow_answer_latency() {
+        let config = ResolvConf {
+            timeout: Duration::from_secs(2),
+            ..config_for(vec![example_server()])
+        };
+        let adaptive = AdaptiveTimeout::new()
+            .with_floor(Duration::from_millis(20))
+            .with_min_samples(4);
+        let resolver = DirectResolver::new(config).with_adaptive_timeout(adaptive);
+        assert_eq!(resolver.query_timeout(), Duration::from_secs(2));
+        for _ in 0..2 {
+            resolver.resolve("www.example.com").unwrap();
+        }
+        assert!(resolver.query_timeout() < Duration::from_millis(500));
+    }
+    #[test]
+    fn queries_cut_short_by_the_deadline_leave_timeouts_alone() {
+        let config = ResolvConf {
+            timeout: Duration::from_secs(2),
+            ..config_for(vec![spawn_udp_server(|_| None)])
+        };
+        let adaptive = AdaptiveTimeout::new()
+            .with_floor(Duration::from_millis(20))
+            .with_min_samples(1);
+        let resolver = DirectResolver::new(config).with_adaptive_timeout(adaptive);
+        let deadline = Instant::now() + Duration::from_millis(50);
+        assert!(deadline::scope(deadline, || resolver.resolve("www.example.com")).is_err());
+        assert_eq!(resolver.query_timeout(), Duration::from_secs(2));
+    }
+    #[test]
+    fn short_names_are_expanded_with_the_search_domains() {
+        let config = ResolvConf {
+            search: vec!["corp.example".into(), "example.com".into()],
+            ..config_for(vec![example_server()])
+        };
+        let resolver = DirectResolver::new(config);
+        let result = resolver.lookup("www").unwrap();
+        assert_eq!(result.canonical_name, "www.example.com");
+        assert_eq!(result.addrs().len(), 2);
+        match resolver.with_search(false).resolve("www") {
+            Err(IpaddrConversionError::NotFound(name)) => assert_eq!(name, "www"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+    #[test]
+    fn lookup_follows_cname_chain() {
+        let server = spawn_udp_server(|request| {
+            if request.questions[0].qtype != RecordType::A {
+                return Some(answer(request, dns::RCODE_NOERROR, vec![]));
+            }
+            let data = vec![
+                RData::Cname("edge.example.net".into()),
+                RData::Cname("host.example.net".into()),
+                RData::A("192.0.2.7".parse().unwrap()),
+            ];
+            let mut response = answer(request, dns::RCODE_NOERROR, data);
+            response.answers[1].name = "edge.example.net".into();
+            response.answers[2].name = "host.example.net".into();
+            Some(response)
+        });
+        let result = DirectResolver::new(config_for(vec![server]))
+            .lookup("www.example.com")
+            .unwrap();
+        assert_eq!(result.canonical_name, "host.example.net");
+        assert_eq!(result.aliases, vec!["www.example.com", "edge.example.net"]);
+        assert_eq!(result.addrs(), vec!["192.0.2.7".parse::<IpAddr>().unwrap()]);
+    }
+    #[test]
+    fn nxdomain_is_not_found() {
+        let resolver = DirectResolver::new(config_for(vec![example_server()]));
+        match resolver.resolve("missing.example.com") {
+            Err(IpaddrConversionError::NotFound(name)) => assert_eq!(name, "missing.example.com"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+    #[test]
+    fn falls_through_unresponsive_and_failing_servers() {
+        let silent = spawn_udp_server(|_| None);
+        let failing = spawn_udp_server(|request| Some(answer(request, RCODE_SERVFAIL, vec![])));
+        let resolver = DirectResolver::new(config_for(vec![silent, failing, example_server()]));
+        assert_eq!(resolver.resolve("www.example.com").unwrap().len(), 2);
+    }
+    #[test]
+    fn refusing_servers_drop_behind_answering_ones_when_fastest() {
+        let queries = Arc::new(AtomicUsize::new(0));
+        let counted = queries.clone();
+        let refusing = spawn_udp_server(move |request| {
+            counted.fetch_add(1, Ordering::SeqCst);
+            Some(answer(request, RCODE_REFUSED, vec![]))
+        });
+        let answering = example_server();
+        let resolver = DirectResolver::new(config_for(vec![refusing, answering]))
+            .with_selection(ServerSelection::Fastest);
+        resolver.resolve("www.example.com").unwrap();
+        assert_eq!(
+            resolver.server_rtt(refusing),
+            Some(resolver.query_timeout())
+        );
+        assert!(resolver.server_rtt(answering) < resolver.server_rtt(refusing));
+        let asked = queries.load(Ordering::SeqCst);
+        resolver.resolve("www.example.com").unwrap();
+        assert_eq!(queries.load(Ordering::SeqCst), asked);
+    }
+    #[test]
+    fn servers_with_an_open_circuit_are_skipped() {
+        let silent = spawn_udp_server(|_| None);
+        let collector = Arc::new(StatsCollector::new());
+        let resolver = DirectResolver::new(config_for(vec![silent, example_server()]))
+            .with_circuit_breaker(CircuitBreaker::new().with_threshold(1))
+            .with_stats(collector.clone());
+        resolver.resolve("www.example.com").unwrap();
+        assert_eq!(
+            resolver.circuit_breaker().unwrap().state(silent),
+            BreakerState::Open
+        );
+        let started = Instant::now();
+        resolver.resolve("www.example.com").unwrap();
+        assert!(started.elapsed() < Duration::from_millis(150));
+        assert_eq!(collector.stats().servers_skipped, 2);
+    }
+    #[test]
+    fn breaker_health_is_reported_in_the_stats() {
+        let silent = spawn_udp_server(|_| None);
+        let collector = Arc::new(StatsCollector::new());
+        let resolver = DirectResolver::new(config_for(vec![silent, example_server()]))
+            .with_stats(collector.clone())
+            .with_circuit_breaker(CircuitBreaker::new().with_threshold(1));
+        resolver.resolve("www.example.com").unwrap();
+        let servers = collector.snapshot().servers;
+        assert_eq!(servers.len(), 2);
+        assert_eq!(
+            servers
+                .iter()
+                .find(|health| health.server == silent)
+                .unwrap()
+                .state,
+            BreakerState::Open
+        );
+        assert!(StatsCollector::new().stats().servers.is_empty());
+    }
+    #[test]
+    fn sources_are_consulted_in_order() {
+        let hosts = HostsFile::parse("10.9.9.9 www.example.com\n10.8.8.8 db.example.com");
+        let dns_first = DirectResolver::new(config_for(vec![example_server()]))
+            .with_sources(vec![Source::Dns, Source::Files])
+            .with_hosts(hosts.clone());
+        assert_eq!(dns_first.resolve("www.example.com").unwrap().len(), 2);
+        assert_eq!(
+            dns_first.resolve("db.example.com").unwrap(),
+            vec!["10.8.8.8".parse::<IpAddr>().unwrap()]
+        );
+        // the silent server would time out, so an answer proves dns was not used
+        let files_first = DirectResolver::new(config_for(vec![spawn_udp_server(|_| None)]))
+            .with_sources(vec![
+                Source::Other("mdns".into()),
+                Source::Files,
+                Source::Dns,
+            ])
+            .with_hosts(hosts);
+        assert_eq!(
+            files_first.resolve("www.example.com").unwrap(),
+            vec!["10.9.9.9".parse::<IpAddr>().unwrap()]
+        );
+    }
+    #[test]
+    fn truncated_responses_are_retried_over_tcp() {
+        let server = spawn_udp_server(|request| {
+            let mut response = answer(request, dns::RCODE_NOERROR, vec![]);
+            response.header.truncated = true;
+            Some(response)
+        });
+        spawn_tcp_server(server.port(), |request| {
+            let data = (1..=40)
+                .map(|i| match request.questions[0].qtype {
+                    RecordType::A => RData::A(Ipv4Addr::new(192, 0, 2, i)),
+                    _ => RData::Aaaa(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, i.into())),
+                })
+                .collect();
+            answer(request, dns::RCODE_NOERROR, data)
+        });
+        let resolver = DirectResolver::new(config_for(vec![server]));
+        assert_eq!(resolver.resolve("big.example.com").unwrap().len(), 80);
+    }
+    #[test]
+    fn tcp_can_be_forced() {
+        let server = spawn_udp_server(|_| None);
+        spawn_tcp_server(server.port(), |request| {
+            let data = match request.questions[0].qtype {
+                RecordType::A => vec![RData::A(Ipv4Addr::new(192, 0, 2, 9))],
+                _ => vec![],
+            };
+            answer(request, dns::RCODE_NOERROR, data)
+        });
+        let resolver = DirectResolver::new(config_for(vec![server])).with_tcp(true);
+        assert_eq!(
+            resolver.resolve("www.example.com").unwrap(),
+            vec!["192.0.2.9".parse::<IpAddr>().unwrap()]
+        );
+    }
+    #[test]
+    fn first_family_to_answer_can_win() {
+        // answers A at once and never answers AAAA
+        let server = spawn_udp_server(|request| match request.questions[0].qtype {
+            RecordType::A => Some(answer(
+                request,
+                dns::RCODE_NOERROR,
+                vec![RData::A(Ipv4Addr::new(192, 0, 2, 1))],
+            )),
+            _ => None,
+        });
+        let mut config = config_for(vec![server]);
+        config.timeout = Duration::from_secs(5);
+        let first = DirectResolver::new(config).with_family_wait(FamilyWait::First);
+        let started = Instant::now();
+        assert_eq!(first.resolve("www.example.com").unwrap().len(), 1);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        // waiting for both still returns what arrived once AAAA times out
+        let both = DirectResolver::new(config_for(vec![server]));
+        assert_eq!(both.resolve("www.example.com").unwrap().len(), 1);
+    }
+    #[test]
+    fn unsolicited_records_are_dropped() {
+        let server = spawn_udp_server(|request| {
+            if request.questions[0].qtype != RecordType::A {
+                return Some(answer(request, dns::RCODE_NOERROR, vec![]));
+            }
+            let data = vec![
+                RData::A(Ipv4Addr::new(192, 0, 2, 1)),
+                RData::A(Ipv4Addr::new(203, 0, 113, 66)),
+                RData::Aaaa("2001:db8::66".parse().unwrap()),
+            ];
+            let mut response = answer(request, dns::RCODE_NOERROR, data);
+            response.answers[1].name = "bank.example.org".into();
+            response.authorities = vec![response.answers[1].clone()];
+            response.additionals = vec![response.answers[1].clone()];
+            Some(response)
+        });
+        let resolver = DirectResolver::new(config_for(vec![server]));
+        let response = resolver.query("www.example.com", RecordType::A).unwrap();
+        assert_eq!(response.answers.len(), 1);
+        assert!(response.authorities.is_empty() && response.additionals.is_empty());
+        assert_eq!(
+            resolver.resolve("www.example.com").unwrap(),
+            vec!["192.0.2.1".parse::<IpAddr>().unwrap()]
+        );
+        assert!(in_zone("www.example.com", "example.com"));
+        assert!(!in_zone("www.badexample.com", "example.com"));
+    }
+    #[test]
+    fn case_randomization_requires_an_echo() {
+        let echoing = spawn_udp_server(|request| {
+            let name = request.questions[0].name.to_ascii_lowercase();
+            let data = match (name.as_str(), request.questions[0].qtype) {
+                ("www.example.com", RecordType::A) => vec![RData::A(Ipv4Addr::new(192, 0, 2, 1))],
+                _ => vec![],
+            };
+            Some(answer(request, dns::RCODE_NOERROR, data))
+        });
+        let resolver = DirectResolver::new(config_for(vec![echoing])).with_case_randomization(true);
+        assert_eq!(resolver.resolve("www.example.com").unwrap().len(), 1);
+        let lowercasing = spawn_udp_server(|request| {
+            let mut response = answer(request, dns::RCODE_NOERROR, vec![]);
+            response.questions[0].name = request.questions[0].name.to_ascii_lowercase();
+            Some(response)
+        });
+        let resolver =
+            DirectResolver::new(config_for(vec![lowercasing])).with_case_randomization(true);
+        assert!(matches!(
+            resolver.resolve("www.example.com"),
+            Err(IpaddrConversionError::Timeout(ref name)) if name == "www.example.com"
+        ));
+        let mixed = randomize_case(&"a".repeat(200));
+        assert!(mixed.contains('a') && mixed.contains('A'));
+    }
+    #[test]
+    fn shutdown_interrupts_queries() {
+        let mut config = config_for(vec![spawn_udp_server(|_| None)]);
+        config.timeout = Duration::from_secs(30);
+        let resolver = std::sync::Arc::new(DirectResolver::new(config));
+        let stopper = resolver.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            stopper.shutdown();
+        });
+        let started = Instant::now();
+        assert!(matches!(
+            resolver.resolve("www.example.com"),
+            Err(IpaddrConversionError::Cancelled(ref name)) if name == "www.example.com"
+        ));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+    #[test]
+    fn queries_inherit_the_callers_deadline() {
+        let mut config = config_for(vec![spawn_udp_server(|_| None)]);
+        config.timeout = Duration::from_secs(30);
+        config.attempts = 3;
+        let resolver = DirectResolver::new(config);
+        let started = Instant::now();
+        let result = deadline::scope(started + Duration::from_millis(100), || {
+            resolver.resolve("www.example.com")
+        });
+        assert!(matches!(result, Err(IpaddrConversionError::Timeout(_))));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+    #[test]
+    fn srv_records_are_ordered_by_priority() {
+        let srv = |priority, weight, target: &str| Srv {
+            priority,
+            weight,
+            port: 50051,
+            target: target.into(),
+        };
+        for _ in 0..20 {
+            let ordered = order_srv(vec![
+                srv(20, 0, "backup.example"),
+                srv(10, 1, "a.example"),
+                srv(10, 0, "never.example"),
+                srv(10, 100, "b.example"),
+            ]);
+            assert_eq!(ordered.len(), 4);
+            assert!(ordered[..3].iter().all(|srv| srv.priority == 10));
+            // weight zero goes last within its priority while others remain
+            assert_eq!(ordered[2].target, "never.example");
+            assert_eq!(ordered[3].target, "backup.example");
+        }
+    }
+    #[test]
+    fn times_out_when_nobody_answers() {
+        let resolver = DirectResolver::new(config_for(vec![spawn_udp_server(|_| None)]));
+        match resolver.resolve("www.example.com") {
+            Err(IpaddrConversionError::Timeout(name)) => assert_eq!(name, "www.example.com"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
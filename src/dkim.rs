@@ -0,0 +1,236 @@
+//! DKIM (RFC 6376) public key records.
+//!
+//! A domain signing its mail publishes the public key of each signing
+//! selector in a TXT record at `selector._domainkey.domain`. [`dkim_key`]
+//! fetches and parses one, for tools auditing how a domain signs its mail;
+//! verifying signatures is left to mail libraries.
+use crate::direct::DirectResolver;
+use crate::errors::IpaddrConversionError;
+use crate::resolver::normalize_name;
+use crate::ssh::base64_decode;
+
+/// Split a tag list, `name=value` pairs separated by semicolons, as DKIM
+/// and DMARC records are written. Names are lowercased and values have
+/// their whitespace trimmed; a repeated tag is an error.
+pub(crate) fn tag_list(text: &str) -> Result<Vec<(String, String)>, IpaddrConversionError> {
+    let mut tags: Vec<(String, String)> = Vec::new();
+    for spec in text
+        .split(';')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+    {
+        let (name, value) = spec.split_once('=').ok_or_else(|| {
+            IpaddrConversionError::InvalidInput(format!("{}: tag without a value", spec))
+        })?;
+        let name = name.trim().to_ascii_lowercase();
+        if name.is_empty() || tags.iter().any(|(seen, _)| *seen == name) {
+            return Err(IpaddrConversionError::InvalidInput(format!(
+                "{}: missing or repeated tag",
+                spec
+            )));
+        }
+        tags.push((name, value.trim().to_string()));
+    }
+    Ok(tags)
+}
+
+/// The colon separated values of a tag, trimmed
+fn colon_list(value: &str) -> Vec<String> {
+    value
+        .split(':')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// The algorithm of a DKIM key
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum KeyType {
+    Rsa,
+    Ed25519,
+    /// Another algorithm, by its `k=` name
+    Other(String),
+}
+
+/// A parsed DKIM key record.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::dkim::{DkimKey, KeyType};
+///
+/// let key = DkimKey::parse("v=DKIM1; k=ed25519; t=y; p=Zm9vYmFy").unwrap();
+/// assert_eq!(key.key_type, KeyType::Ed25519);
+/// assert_eq!(key.public_key, b"foobar");
+/// assert!(key.is_testing());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DkimKey {
+    pub key_type: KeyType,
+    /// The decoded public key, empty if the key has been revoked
+    pub public_key: Vec<u8>,
+    /// The hash algorithms signatures may use, `h=`; empty means any
+    pub hash_algorithms: Vec<String>,
+    /// The services the key is for, `s=`; `*`, the default, means all
+    pub service_types: Vec<String>,
+    /// The flags, `t=`: `y` for testing, `s` for no subdomains
+    pub flags: Vec<String>,
+    /// Notes for people, `n=`
+    pub notes: Option<String>,
+}
+
+impl DkimKey {
+    /// Parse the text of a DKIM key record, with its character strings
+    /// joined
+    ///
+    /// # Parameters
+    ///
+    /// * `text` - the record, a tag list which must have a `p=` tag
+    ///
+    /// # Returns
+    /// The key, or an InvalidInput error if the record is malformed, of
+    /// a version other than DKIM1, or its key is not base64
+    pub fn parse(text: &str) -> Result<Self, IpaddrConversionError> {
+        let invalid = |msg: &str| IpaddrConversionError::InvalidInput(format!("{}: {}", text, msg));
+        let tags = tag_list(text)?;
+        if let Some(pos) = tags.iter().position(|(name, _)| name == "v") {
+            if pos != 0 || tags[pos].1 != "DKIM1" {
+                return Err(invalid("version must come first and be DKIM1"));
+            }
+        }
+        let mut key = DkimKey {
+            key_type: KeyType::Rsa,
+            public_key: Vec::new(),
+            hash_algorithms: Vec::new(),
+            service_types: vec!["*".to_string()],
+            flags: Vec::new(),
+            notes: None,
+        };
+        let mut has_key = false;
+        for (name, value) in tags {
+            match name.as_str() {
+                "k" => {
+                    key.key_type = match value.to_ascii_lowercase().as_str() {
+                        "rsa" => KeyType::Rsa,
+                        "ed25519" => KeyType::Ed25519,
+                        other => KeyType::Other(other.to_string()),
+                    }
+                }
+                "p" => {
+                    let encoded: String = value.split_whitespace().collect();
+                    key.public_key = if encoded.is_empty() {
+                        Vec::new()
+                    } else {
+                        base64_decode(&encoded).ok_or_else(|| invalid("key is not base64"))?
+                    };
+                    has_key = true;
+                }
+                "h" => key.hash_algorithms = colon_list(&value),
+                "s" => key.service_types = colon_list(&value),
+                "t" => key.flags = colon_list(&value),
+                "n" => key.notes = Some(value),
+                // unknown tags are ignored, RFC 6376 section 3.6.1
+                _ => {}
+            }
+        }
+        if !has_key {
+            return Err(invalid("missing p= tag"));
+        }
+        Ok(key)
+    }
+
+    /// Whether the key has been revoked, by publishing an empty `p=`
+    pub fn is_revoked(&self) -> bool {
+        self.public_key.is_empty()
+    }
+
+    /// Whether the domain is testing DKIM, so failures should not count
+    /// against its mail
+    pub fn is_testing(&self) -> bool {
+        self.flags.iter().any(|flag| flag == "y")
+    }
+}
+
+/// The name the key of `selector` for `domain` is published under
+pub fn dkim_record_name(selector: &str, domain: &str) -> String {
+    format!(
+        "{}._domainkey.{}",
+        normalize_name(selector),
+        normalize_name(domain)
+    )
+}
+
+/// Fetch and parse the DKIM key of `selector` for `domain`
+///
+/// # Parameters
+///
+/// * `resolver` - queries the TXT records
+/// * `selector` - the selector, the `s=` tag of a signature
+/// * `domain` - the signing domain, the `d=` tag of a signature
+///
+/// # Returns
+/// The key, None if no key is published, or an InvalidInput error if the
+/// record is malformed or there are several
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::direct::DirectResolver;
+/// use ipaddr_from_str::dkim::dkim_key;
+///
+/// let resolver = DirectResolver::from_system().unwrap();
+/// if let Some(key) = dkim_key(&resolver, "google", "example.com").unwrap() {
+///     println!("{:?} key of {} bytes", key.key_type, key.public_key.len());
+/// }
+/// ```
+pub fn dkim_key(
+    resolver: &DirectResolver,
+    selector: &str,
+    domain: &str,
+) -> Result<Option<DkimKey>, IpaddrConversionError> {
+    let name = dkim_record_name(selector, domain);
+    let texts = match resolver.txt(&name) {
+        Ok(texts) => texts,
+        Err(IpaddrConversionError::NotFound(_)) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    match texts.as_slice() {
+        [] => Ok(None),
+        [text] => DkimKey::parse(text).map(Some),
+        _ => Err(IpaddrConversionError::InvalidInput(format!(
+            "{}: several key records",
+            name
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_parsed() {
+        let key =
+            DkimKey::parse("v=DKIM1; h=sha256 ; s=email; p=Zm9v\r\n YmFy; x=ignored").unwrap();
+        assert_eq!(key.key_type, KeyType::Rsa);
+        assert_eq!(key.public_key, b"foobar");
+        assert_eq!(key.hash_algorithms, vec!["sha256"]);
+        assert_eq!(key.service_types, vec!["email"]);
+        assert!(!key.is_testing() && !key.is_revoked());
+        assert!(DkimKey::parse("p=").unwrap().is_revoked());
+        for bad in [
+            "k=rsa",
+            "p=Zm9",
+            "p=Zm9v; p=Zm9v",
+            "k=rsa; v=DKIM1; p=",
+            "v=DKIM2; p=",
+        ] {
+            assert!(DkimKey::parse(bad).is_err(), "{}", bad);
+        }
+        assert_eq!(
+            dkim_record_name("Mail", "Example.com."),
+            "mail._domainkey.example.com"
+        );
+    }
+}
@@ -0,0 +1,262 @@
+//! DMARC (RFC 7489) policy records.
+//!
+//! A domain tells receivers what to do with mail failing SPF and DKIM
+//! alignment in a TXT record at `_dmarc.domain`. [`dmarc_record`] fetches
+//! and parses it. Falling back to the organizational domain needs the
+//! public suffix list, which this crate does not carry, so only the domain
+//! given is looked up.
+use crate::direct::DirectResolver;
+use crate::dkim::tag_list;
+use crate::errors::IpaddrConversionError;
+use crate::resolver::normalize_name;
+
+/// What a receiver should do with mail failing DMARC
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Policy {
+    /// Deliver it as usual; the domain only wants reports
+    None,
+    /// Treat it as suspicious, such as by filing it as spam
+    Quarantine,
+    /// Refuse it
+    Reject,
+}
+
+/// How closely the SPF or DKIM domain must match the `From` domain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Alignment {
+    /// The organizational domains must match, the default
+    Relaxed,
+    /// The domains must be equal
+    Strict,
+}
+
+/// A parsed DMARC record.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::dmarc::{DmarcRecord, Policy};
+///
+/// let record = DmarcRecord::parse("v=DMARC1; p=reject; sp=none; pct=50; rua=mailto:dmarc@example.com").unwrap();
+/// assert_eq!(record.policy, Policy::Reject);
+/// assert_eq!(record.subdomain_policy(), Policy::None);
+/// assert_eq!(record.percent, 50);
+/// assert_eq!(record.aggregate_reports, vec!["mailto:dmarc@example.com"]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DmarcRecord {
+    /// The policy for the domain, `p=`
+    pub policy: Policy,
+    /// The policy for its subdomains, `sp=`, if it differs
+    pub subdomain: Option<Policy>,
+    /// The share of failing mail to apply the policy to, `pct=`
+    pub percent: u8,
+    /// `adkim=`
+    pub dkim_alignment: Alignment,
+    /// `aspf=`
+    pub spf_alignment: Alignment,
+    /// Where to send aggregate reports, `rua=`
+    pub aggregate_reports: Vec<String>,
+    /// Where to send failure reports, `ruf=`
+    pub failure_reports: Vec<String>,
+    /// Seconds between aggregate reports, `ri=`
+    pub report_interval: u32,
+    /// When to send failure reports, `fo=`, such as `0` or `1:d`
+    pub failure_options: String,
+}
+
+fn parse_policy(value: &str) -> Option<Policy> {
+    match value.to_ascii_lowercase().as_str() {
+        "none" => Some(Policy::None),
+        "quarantine" => Some(Policy::Quarantine),
+        "reject" => Some(Policy::Reject),
+        _ => None,
+    }
+}
+
+fn parse_alignment(value: &str) -> Option<Alignment> {
+    match value.to_ascii_lowercase().as_str() {
+        "r" => Some(Alignment::Relaxed),
+        "s" => Some(Alignment::Strict),
+        _ => None,
+    }
+}
+
+/// Whether `text` is a DMARC record rather than some other TXT record
+pub fn is_dmarc(text: &str) -> bool {
+    let version = text.split(';').next().unwrap_or("");
+    version.replace(char::is_whitespace, "") == "v=DMARC1"
+}
+
+impl DmarcRecord {
+    /// Parse the text of a DMARC record, with its character strings joined
+    ///
+    /// # Parameters
+    ///
+    /// * `text` - the record, starting `v=DMARC1` and with a `p=` tag
+    ///
+    /// # Returns
+    /// The record, or an InvalidInput error if it is malformed
+    pub fn parse(text: &str) -> Result<Self, IpaddrConversionError> {
+        let invalid = |msg: &str| IpaddrConversionError::InvalidInput(format!("{}: {}", text, msg));
+        if !is_dmarc(text) {
+            return Err(invalid("not a DMARC record"));
+        }
+        let mut policy = None;
+        let mut record = DmarcRecord {
+            policy: Policy::None,
+            subdomain: None,
+            percent: 100,
+            dkim_alignment: Alignment::Relaxed,
+            spf_alignment: Alignment::Relaxed,
+            aggregate_reports: Vec::new(),
+            failure_reports: Vec::new(),
+            report_interval: 86400,
+            failure_options: "0".to_string(),
+        };
+        let uris = |value: &str| -> Vec<String> {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|uri| !uri.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+        for (name, value) in tag_list(text)?.into_iter().skip(1) {
+            match name.as_str() {
+                "p" => policy = Some(parse_policy(&value).ok_or_else(|| invalid("bad p="))?),
+                "sp" => {
+                    record.subdomain = Some(parse_policy(&value).ok_or_else(|| invalid("bad sp="))?)
+                }
+                "pct" => {
+                    record.percent = value
+                        .parse()
+                        .ok()
+                        .filter(|pct| *pct <= 100)
+                        .ok_or_else(|| invalid("bad pct="))?
+                }
+                "adkim" => {
+                    record.dkim_alignment =
+                        parse_alignment(&value).ok_or_else(|| invalid("bad adkim="))?
+                }
+                "aspf" => {
+                    record.spf_alignment =
+                        parse_alignment(&value).ok_or_else(|| invalid("bad aspf="))?
+                }
+                "rua" => record.aggregate_reports = uris(&value),
+                "ruf" => record.failure_reports = uris(&value),
+                "ri" => record.report_interval = value.parse().map_err(|_| invalid("bad ri="))?,
+                "fo" => record.failure_options = value,
+                // unknown tags are ignored, RFC 7489 section 6.3
+                _ => {}
+            }
+        }
+        record.policy = policy.ok_or_else(|| invalid("missing p= tag"))?;
+        Ok(record)
+    }
+
+    /// The policy for subdomains: `sp=` if given, else `p=`
+    pub fn subdomain_policy(&self) -> Policy {
+        self.subdomain.unwrap_or(self.policy)
+    }
+}
+
+/// Fetch and parse the DMARC record of `domain`
+///
+/// # Parameters
+///
+/// * `resolver` - queries the TXT records
+/// * `domain` - the domain of the `From` address
+///
+/// # Returns
+/// The record, None if the domain publishes none or several, as RFC 7489
+/// says to treat them, or an InvalidInput error if it is malformed
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::direct::DirectResolver;
+/// use ipaddr_from_str::dmarc::dmarc_record;
+///
+/// let resolver = DirectResolver::from_system().unwrap();
+/// match dmarc_record(&resolver, "example.com").unwrap() {
+///     Some(record) => println!("policy {:?}", record.policy),
+///     None => println!("no DMARC policy"),
+/// }
+/// ```
+pub fn dmarc_record(
+    resolver: &DirectResolver,
+    domain: &str,
+) -> Result<Option<DmarcRecord>, IpaddrConversionError> {
+    let name = format!("_dmarc.{}", normalize_name(domain));
+    let texts = match resolver.txt(&name) {
+        Ok(texts) => texts,
+        Err(IpaddrConversionError::NotFound(_)) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let mut records = texts.iter().filter(|text| is_dmarc(text));
+    match (records.next(), records.next()) {
+        (Some(text), None) => DmarcRecord::parse(text).map(Some),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::direct::tests::{answer, config_for, spawn_udp_server};
+    use crate::dkim::dkim_key;
+    use crate::dns::{RData, RCODE_NXDOMAIN};
+
+    #[test]
+    fn records_are_parsed() {
+        let record = DmarcRecord::parse(
+            "v=DMARC1;p=quarantine;adkim=s;ruf=mailto:a@example.com, mailto:b@example.com;fo=1:d",
+        )
+        .unwrap();
+        assert_eq!(record.policy, Policy::Quarantine);
+        assert_eq!(record.subdomain_policy(), Policy::Quarantine);
+        assert_eq!(record.dkim_alignment, Alignment::Strict);
+        assert_eq!(record.spf_alignment, Alignment::Relaxed);
+        assert_eq!(record.failure_reports.len(), 2);
+        assert_eq!(record.failure_options, "1:d");
+        assert_eq!(record.report_interval, 86400);
+        for bad in [
+            "p=reject; v=DMARC1",
+            "v=DMARC1; sp=none",
+            "v=DMARC1; p=drop",
+            "v=DMARC1; p=none; pct=101",
+        ] {
+            assert!(DmarcRecord::parse(bad).is_err(), "{}", bad);
+        }
+    }
+    #[test]
+    fn records_are_fetched() {
+        let server = spawn_udp_server(|request| {
+            let txt = |texts: &[&str]| {
+                texts
+                    .iter()
+                    .map(|text| RData::Txt(vec![text.to_string()]))
+                    .collect()
+            };
+            let data = match request.questions[0].name.as_str() {
+                "_dmarc.example.com" => txt(&["v=DMARC1; p=reject", "google-site-verification=x"]),
+                "_dmarc.twice.example.com" => txt(&["v=DMARC1; p=reject", "v=DMARC1; p=none"]),
+                "mail._domainkey.example.com" => txt(&["v=DKIM1; p=Zm9vYmFy"]),
+                _ => return Some(answer(request, RCODE_NXDOMAIN, vec![])),
+            };
+            Some(answer(request, 0, data))
+        });
+        let resolver = DirectResolver::new(config_for(vec![server]));
+        let record = dmarc_record(&resolver, "Example.com.").unwrap().unwrap();
+        assert_eq!(record.policy, Policy::Reject);
+        assert_eq!(dmarc_record(&resolver, "twice.example.com").unwrap(), None);
+        assert_eq!(
+            dmarc_record(&resolver, "missing.example.com").unwrap(),
+            None
+        );
+        let key = dkim_key(&resolver, "mail", "example.com").unwrap().unwrap();
+        assert_eq!(key.public_key, b"foobar");
+        assert_eq!(dkim_key(&resolver, "other", "example.com").unwrap(), None);
+    }
+}
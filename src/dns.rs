@@ -0,0 +1,806 @@
+//! Encoding and decoding of DNS messages (RFC 1035).
+//!
+//! This is the wire format the crate's own backends speak, exposed for
+//! building other transports on. It has no I/O of its own: [`Message::encode`]
+//! gives the bytes to send, compressing repeated names, and
+//! [`Message::decode`] parses the bytes received. Names are decoded with
+//! their case as sent, so compare them case-insensitively.
+//!
+//! # Example
+//!
+//! ```
+//! use ipaddr_from_str::dns::{Message, RecordType};
+//!
+//! let query = Message::query(0x1234, "www.example.com", RecordType::A);
+//! let bytes = query.encode().unwrap();
+//! assert_eq!(Message::decode(&bytes).unwrap(), query);
+//! ```
+use crate::parse::ParseError;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+/// Define an enum of DNS codes with their mnemonics, converting to and from
+/// the numeric code and parsing and displaying the mnemonic, or the generic
+/// `PREFIXn` form of RFC 3597 for codes without one
+macro_rules! dns_codes {
+    ($(#[$doc:meta])* $name:ident, $prefix:expr, $what:expr, { $($variant:ident = $code:literal => $text:expr,)* }) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum $name {
+            $($variant,)*
+            /// A code without a variant of its own
+            Unknown(u16),
+        }
+
+        impl From<u16> for $name {
+            fn from(code: u16) -> Self {
+                match code {
+                    $($code => $name::$variant,)*
+                    other => $name::Unknown(other),
+                }
+            }
+        }
+
+        impl From<$name> for u16 {
+            fn from(value: $name) -> u16 {
+                match value {
+                    $($name::$variant => $code,)*
+                    $name::Unknown(code) => code,
+                }
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                match *self {
+                    $($name::$variant => f.write_str($text),)*
+                    $name::Unknown(code) => write!(f, "{}{}", $prefix, code),
+                }
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = ParseError;
+
+            /// Parse a mnemonic, in any case, or the generic form
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                $(
+                    if s.eq_ignore_ascii_case($text) {
+                        return Ok($name::$variant);
+                    }
+                )*
+                match s.get(..$prefix.len()) {
+                    Some(prefix) if prefix.eq_ignore_ascii_case($prefix) => s[$prefix.len()..]
+                        .parse::<u16>()
+                        .map($name::from)
+                        .map_err(|_| ParseError::new($prefix.len(), "number of at most 65535")),
+                    _ => Err(ParseError::new(0, $what)),
+                }
+            }
+        }
+    };
+}
+
+dns_codes!(
+    /// The type of a record or question.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ipaddr_from_str::dns::RecordType;
+    ///
+    /// assert_eq!("aaaa".parse::<RecordType>().unwrap(), RecordType::Aaaa);
+    /// assert_eq!("TYPE65".parse::<RecordType>().unwrap(), RecordType::Https);
+    /// assert_eq!(RecordType::from(999).to_string(), "TYPE999");
+    /// assert!("AAA".parse::<RecordType>().is_err());
+    /// ```
+    RecordType, "TYPE", "record type", {
+        A = 1 => "A",
+        Ns = 2 => "NS",
+        Cname = 5 => "CNAME",
+        Soa = 6 => "SOA",
+        Ptr = 12 => "PTR",
+        Mx = 15 => "MX",
+        Txt = 16 => "TXT",
+        Aaaa = 28 => "AAAA",
+        Srv = 33 => "SRV",
+        Naptr = 35 => "NAPTR",
+        Opt = 41 => "OPT",
+        Ds = 43 => "DS",
+        Rrsig = 46 => "RRSIG",
+        Nsec = 47 => "NSEC",
+        Dnskey = 48 => "DNSKEY",
+        Svcb = 64 => "SVCB",
+        Https = 65 => "HTTPS",
+        Any = 255 => "ANY",
+        Caa = 257 => "CAA",
+    }
+);
+
+dns_codes!(
+    /// The class of a record or question; in practice always `IN`
+    RecordClass, "CLASS", "record class", {
+        In = 1 => "IN",
+        Ch = 3 => "CH",
+        Hs = 4 => "HS",
+        None = 254 => "NONE",
+        Any = 255 => "ANY",
+    }
+);
+
+pub const RCODE_NOERROR: u8 = 0;
+pub const RCODE_NXDOMAIN: u8 = 3;
+
+const HEADER_LEN: usize = 12;
+const MAX_LABEL_LEN: usize = 63;
+const MAX_NAME_LEN: usize = 255;
+// bound on compression pointers followed while reading a name, to stop loops
+const MAX_POINTERS: usize = 64;
+// compression pointers hold a 14 bit offset
+const MAX_POINTER_TARGET: usize = 0x3fff;
+
+/// Error returned when a message cannot be encoded or decoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WireError {
+    offset: usize,
+    message: &'static str,
+}
+
+impl WireError {
+    fn new(offset: usize, message: &'static str) -> Self {
+        Self { offset, message }
+    }
+
+    /// Offset into the message of the problem
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// What was wrong
+    pub fn message(&self) -> &'static str {
+        self.message
+    }
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at byte {}", self.message, self.offset)
+    }
+}
+
+impl Error for WireError {}
+
+/// Message header, with the flags word broken out
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Header {
+    pub id: u16,
+    pub response: bool,
+    pub opcode: u8,
+    pub authoritative: bool,
+    pub truncated: bool,
+    pub recursion_desired: bool,
+    pub recursion_available: bool,
+    pub rcode: u8,
+}
+
+impl Header {
+    fn flags(&self) -> u16 {
+        (self.response as u16) << 15
+            | u16::from(self.opcode & 0x0f) << 11
+            | (self.authoritative as u16) << 10
+            | (self.truncated as u16) << 9
+            | (self.recursion_desired as u16) << 8
+            | (self.recursion_available as u16) << 7
+            | u16::from(self.rcode & 0x0f)
+    }
+
+    fn with_flags(id: u16, flags: u16) -> Self {
+        Self {
+            id,
+            response: flags & 0x8000 != 0,
+            opcode: ((flags >> 11) & 0x0f) as u8,
+            authoritative: flags & 0x0400 != 0,
+            truncated: flags & 0x0200 != 0,
+            recursion_desired: flags & 0x0100 != 0,
+            recursion_available: flags & 0x0080 != 0,
+            rcode: (flags & 0x0f) as u8,
+        }
+    }
+}
+
+/// An entry of the question section
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Question {
+    pub name: String,
+    pub qtype: RecordType,
+    pub qclass: RecordClass,
+}
+
+/// Decoded record data for the types the backends make use of
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(String),
+    Ptr(String),
+    Mx(Mx),
+    /// The character strings of a TXT record, decoded lossily as UTF-8
+    Txt(Vec<String>),
+    Srv(Srv),
+    Caa(Caa),
+    Other(Vec<u8>),
+}
+
+/// The data of a SRV record (RFC 2782): where a service is offered
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Srv {
+    /// Lower values are tried first
+    pub priority: u16,
+    /// Relative share of the traffic among targets of equal priority
+    pub weight: u16,
+    pub port: u16,
+    /// The host offering the service, or "." if it is not offered
+    pub target: String,
+}
+
+impl fmt::Display for Srv {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}.",
+            self.priority,
+            self.weight,
+            self.port,
+            self.target.trim_end_matches('.')
+        )
+    }
+}
+
+/// The data of a MX record: a host accepting mail for the name
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Mx {
+    /// Lower values are tried first
+    pub preference: u16,
+    pub exchange: String,
+}
+
+impl fmt::Display for Mx {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {}.",
+            self.preference,
+            self.exchange.trim_end_matches('.')
+        )
+    }
+}
+
+/// The data of a CAA record (RFC 8659): a property limiting which
+/// certificate authorities may issue for the name
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Caa {
+    /// The flags; bit 128 marks the property critical
+    pub flags: u8,
+    /// The property, such as `issue`, `issuewild` or `iodef`
+    pub tag: String,
+    pub value: Vec<u8>,
+}
+
+impl fmt::Display for Caa {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {:?}",
+            self.flags,
+            self.tag,
+            String::from_utf8_lossy(&self.value)
+        )
+    }
+}
+
+/// A resource record of the answer, authority or additional section
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub name: String,
+    pub rtype: RecordType,
+    pub rclass: RecordClass,
+    pub ttl: u32,
+    pub data: RData,
+}
+
+/// A whole query or response
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Message {
+    pub header: Header,
+    pub questions: Vec<Question>,
+    pub answers: Vec<Record>,
+    pub authorities: Vec<Record>,
+    pub additionals: Vec<Record>,
+}
+
+impl Message {
+    /// A recursive query for `name`
+    pub fn query(id: u16, name: &str, qtype: RecordType) -> Self {
+        Self {
+            header: Header {
+                id,
+                recursion_desired: true,
+                ..Header::default()
+            },
+            questions: vec![Question {
+                name: name.trim_end_matches('.').to_string(),
+                qtype,
+                qclass: RecordClass::In,
+            }],
+            ..Self::default()
+        }
+    }
+
+    /// The message in wire format, with repeated names compressed
+    pub fn encode(&self) -> Result<Vec<u8>, WireError> {
+        let mut encoder = Encoder::default();
+        encoder.put_u16(self.header.id);
+        encoder.put_u16(self.header.flags());
+        for count in &[
+            self.questions.len(),
+            self.answers.len(),
+            self.authorities.len(),
+            self.additionals.len(),
+        ] {
+            encoder.put_u16(*count as u16);
+        }
+        for question in &self.questions {
+            encoder.put_name(&question.name)?;
+            encoder.put_u16(question.qtype.into());
+            encoder.put_u16(question.qclass.into());
+        }
+        for record in self
+            .answers
+            .iter()
+            .chain(&self.authorities)
+            .chain(&self.additionals)
+        {
+            encoder.put_record(record)?;
+        }
+        Ok(encoder.buf)
+    }
+
+    /// Parse a message in wire format
+    pub fn decode(buf: &[u8]) -> Result<Self, WireError> {
+        if buf.len() < HEADER_LEN {
+            return Err(WireError::new(buf.len(), "truncated header"));
+        }
+        let header = Header::with_flags(read_u16(buf, 0)?, read_u16(buf, 2)?);
+        let qdcount = read_u16(buf, 4)?;
+        let ancount = read_u16(buf, 6)?;
+        let nscount = read_u16(buf, 8)?;
+        let arcount = read_u16(buf, 10)?;
+        let mut offset = HEADER_LEN;
+        let mut questions = Vec::with_capacity(qdcount as usize);
+        for _ in 0..qdcount {
+            let (name, next) = read_name(buf, offset)?;
+            questions.push(Question {
+                name,
+                qtype: read_u16(buf, next)?.into(),
+                qclass: read_u16(buf, next + 2)?.into(),
+            });
+            offset = next + 4;
+        }
+        let mut sections = [Vec::new(), Vec::new(), Vec::new()];
+        for (section, count) in sections.iter_mut().zip(&[ancount, nscount, arcount]) {
+            for _ in 0..*count {
+                let (record, next) = read_record(buf, offset)?;
+                section.push(record);
+                offset = next;
+            }
+        }
+        let [answers, authorities, additionals] = sections;
+        Ok(Self {
+            header,
+            questions,
+            answers,
+            authorities,
+            additionals,
+        })
+    }
+}
+
+/// Buffer for encoding a message, remembering where each name suffix was
+/// written so that later occurrences can point back to it
+#[derive(Default)]
+struct Encoder {
+    buf: Vec<u8>,
+    names: HashMap<String, usize>,
+}
+
+impl Encoder {
+    fn put_u16(&mut self, value: u16) {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn put_name(&mut self, name: &str) -> Result<(), WireError> {
+        self.put_labels(name, true)
+    }
+
+    /// Write `name`, pointing back to an earlier suffix if `compress` is set
+    /// and one was written
+    fn put_labels(&mut self, name: &str, compress: bool) -> Result<(), WireError> {
+        let labels: Vec<&str> = name
+            .trim_end_matches('.')
+            .split('.')
+            .filter(|l| !l.is_empty())
+            .collect();
+        let start = self.buf.len();
+        let mut wire_len = 1;
+        for label in &labels {
+            if label.len() > MAX_LABEL_LEN {
+                return Err(WireError::new(start, "label longer than 63 bytes"));
+            }
+            wire_len += 1 + label.len();
+        }
+        if wire_len > MAX_NAME_LEN {
+            return Err(WireError::new(start, "name longer than 255 bytes"));
+        }
+        for (i, label) in labels.iter().enumerate() {
+            let suffix = labels[i..].join(".");
+            if let Some(&target) = self.names.get(&suffix).filter(|_| compress) {
+                self.put_u16(0xc000 | target as u16);
+                return Ok(());
+            }
+            if self.buf.len() <= MAX_POINTER_TARGET {
+                self.names.insert(suffix, self.buf.len());
+            }
+            self.buf.push(label.len() as u8);
+            self.buf.extend_from_slice(label.as_bytes());
+        }
+        self.buf.push(0);
+        Ok(())
+    }
+
+    fn put_record(&mut self, record: &Record) -> Result<(), WireError> {
+        self.put_name(&record.name)?;
+        self.put_u16(record.rtype.into());
+        self.put_u16(record.rclass.into());
+        self.buf.extend_from_slice(&record.ttl.to_be_bytes());
+        let len_at = self.buf.len();
+        self.put_u16(0);
+        match record.data {
+            RData::A(ip) => self.buf.extend_from_slice(&ip.octets()),
+            RData::Aaaa(ip) => self.buf.extend_from_slice(&ip.octets()),
+            RData::Cname(ref name) | RData::Ptr(ref name) => self.put_name(name)?,
+            RData::Mx(ref mx) => {
+                self.put_u16(mx.preference);
+                self.put_name(&mx.exchange)?;
+            }
+            RData::Txt(ref strings) => {
+                // TXT data holds at least one character string (RFC 1035)
+                if strings.is_empty() {
+                    self.buf.push(0);
+                }
+                for string in strings {
+                    if string.is_empty() {
+                        self.buf.push(0);
+                    }
+                    // a character string holds at most 255 bytes
+                    for chunk in string.as_bytes().chunks(255) {
+                        self.buf.push(chunk.len() as u8);
+                        self.buf.extend_from_slice(chunk);
+                    }
+                }
+            }
+            RData::Srv(ref srv) => {
+                self.put_u16(srv.priority);
+                self.put_u16(srv.weight);
+                self.put_u16(srv.port);
+                // RFC 2782 forbids compressing the target
+                self.put_labels(&srv.target, false)?;
+            }
+            RData::Caa(ref caa) => {
+                if caa.tag.is_empty() || caa.tag.len() > 255 {
+                    return Err(WireError::new(self.buf.len(), "CAA tag of bad length"));
+                }
+                self.buf.push(caa.flags);
+                self.buf.push(caa.tag.len() as u8);
+                self.buf.extend_from_slice(caa.tag.as_bytes());
+                self.buf.extend_from_slice(&caa.value);
+            }
+            RData::Other(ref data) => self.buf.extend_from_slice(data),
+        }
+        let rdlen = (self.buf.len() - len_at - 2) as u16;
+        self.buf[len_at..len_at + 2].copy_from_slice(&rdlen.to_be_bytes());
+        Ok(())
+    }
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> Result<u16, WireError> {
+    buf.get(offset..offset + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or_else(|| WireError::new(offset, "unexpected end of message"))
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Result<u32, WireError> {
+    buf.get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| WireError::new(offset, "unexpected end of message"))
+}
+
+/// Read a possibly compressed name starting at `offset`, returning it along
+/// with the offset just past it
+fn read_name(buf: &[u8], offset: usize) -> Result<(String, usize), WireError> {
+    let mut name = String::new();
+    let mut pos = offset;
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let len =
+            *buf.get(pos)
+                .ok_or_else(|| WireError::new(pos, "unexpected end of name"))? as usize;
+        match len & 0xc0 {
+            0x00 if len == 0 => break,
+            0x00 => {
+                let label = buf
+                    .get(pos + 1..pos + 1 + len)
+                    .ok_or_else(|| WireError::new(pos, "label runs past end of message"))?;
+                if !name.is_empty() {
+                    name.push('.');
+                }
+                name.push_str(&String::from_utf8_lossy(label));
+                if name.len() > MAX_NAME_LEN {
+                    return Err(WireError::new(pos, "name longer than 255 bytes"));
+                }
+                pos += 1 + len;
+            }
+            0xc0 => {
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return Err(WireError::new(pos, "compression pointer loop"));
+                }
+                let target = (read_u16(buf, pos)? & 0x3fff) as usize;
+                end.get_or_insert(pos + 2);
+                pos = target;
+            }
+            _ => return Err(WireError::new(pos, "unsupported label type")),
+        }
+    }
+    Ok((name, end.unwrap_or(pos + 1)))
+}
+
+/// Read the length-prefixed character strings filling `rdata`, which starts
+/// at `offset` of the message
+fn read_strings(rdata: &[u8], offset: usize) -> Result<Vec<String>, WireError> {
+    let mut strings = Vec::new();
+    let mut pos = 0;
+    while pos < rdata.len() {
+        let len = rdata[pos] as usize;
+        let string = rdata
+            .get(pos + 1..pos + 1 + len)
+            .ok_or_else(|| WireError::new(offset + pos, "character string runs past record"))?;
+        strings.push(String::from_utf8_lossy(string).into_owned());
+        pos += 1 + len;
+    }
+    Ok(strings)
+}
+
+fn read_record(buf: &[u8], offset: usize) -> Result<(Record, usize), WireError> {
+    let (name, pos) = read_name(buf, offset)?;
+    let rtype = RecordType::from(read_u16(buf, pos)?);
+    let rclass = RecordClass::from(read_u16(buf, pos + 2)?);
+    let ttl = read_u32(buf, pos + 4)?;
+    let rdlen = read_u16(buf, pos + 8)? as usize;
+    let start = pos + 10;
+    let rdata = buf
+        .get(start..start + rdlen)
+        .ok_or_else(|| WireError::new(start, "record data runs past end of message"))?;
+    let data = match (rtype, rdlen) {
+        (RecordType::A, 4) => RData::A(Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3])),
+        (RecordType::Aaaa, 16) => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(rdata);
+            RData::Aaaa(Ipv6Addr::from(octets))
+        }
+        (RecordType::A, _) | (RecordType::Aaaa, _) => {
+            return Err(WireError::new(start, "address record of wrong length"))
+        }
+        (RecordType::Cname, _) => RData::Cname(read_name(buf, start)?.0),
+        (RecordType::Ptr, _) => RData::Ptr(read_name(buf, start)?.0),
+        (RecordType::Mx, _) if rdlen < 3 => {
+            return Err(WireError::new(start, "MX record too short"))
+        }
+        (RecordType::Mx, _) => RData::Mx(Mx {
+            preference: read_u16(buf, start)?,
+            exchange: read_name(buf, start + 2)?.0,
+        }),
+        (RecordType::Txt, _) => RData::Txt(read_strings(rdata, start)?),
+        (RecordType::Srv, _) if rdlen < 7 => {
+            return Err(WireError::new(start, "SRV record too short"))
+        }
+        (RecordType::Srv, _) => RData::Srv(Srv {
+            priority: read_u16(buf, start)?,
+            weight: read_u16(buf, start + 2)?,
+            port: read_u16(buf, start + 4)?,
+            // decompressed anyway, as some servers compress it regardless
+            target: read_name(buf, start + 6)?.0,
+        }),
+        (RecordType::Caa, _) => {
+            let tag_len = rdata.get(1).map_or(0, |len| *len as usize);
+            if tag_len == 0 || rdlen < 2 + tag_len {
+                return Err(WireError::new(start, "CAA record too short"));
+            }
+            RData::Caa(Caa {
+                flags: rdata[0],
+                tag: String::from_utf8_lossy(&rdata[2..2 + tag_len]).into_owned(),
+                value: rdata[2 + tag_len..].to_vec(),
+            })
+        }
+        _ => RData::Other(rdata.to_vec()),
+    };
+    Ok((
+        Record {
+            name,
+            rtype,
+            rclass,
+            ttl,
+            data,
+        },
+        start + rdlen,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_round_trips() {
+        let query = Message::query(0x1234, "www.example.com.", RecordType::Aaaa);
+        let bytes = query.encode().unwrap();
+        assert_eq!(&bytes[..4], &[0x12, 0x34, 0x01, 0x00]);
+        assert_eq!(Message::decode(&bytes).unwrap(), query);
+    }
+    #[test]
+    fn can_decode_compressed_answers() {
+        #[rustfmt::skip]
+        let bytes = [
+            0xab, 0xcd, 0x81, 0x80, 0, 1, 0, 2, 0, 0, 0, 0,
+            3, b'w', b'w', b'w', 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
+            0, 1, 0, 1,
+            // www.example.com CNAME web.example.com
+            0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 6, 3, b'w', b'e', b'b', 0xc0, 16,
+            // web.example.com A 192.0.2.1
+            0xc0, 45, 0, 1, 0, 1, 0, 0, 1, 0, 0, 4, 192, 0, 2, 1,
+        ];
+        let message = Message::decode(&bytes).unwrap();
+        assert!(message.header.response);
+        assert_eq!(message.header.rcode, RCODE_NOERROR);
+        assert_eq!(message.questions[0].name, "www.example.com");
+        assert_eq!(
+            message.answers[0].data,
+            RData::Cname("web.example.com".into())
+        );
+        assert_eq!(message.answers[1].name, "web.example.com");
+        assert_eq!(message.answers[1].ttl, 256);
+        assert_eq!(
+            message.answers[1].data,
+            RData::A(Ipv4Addr::new(192, 0, 2, 1))
+        );
+    }
+    #[test]
+    fn rejects_pointer_loops_and_truncation() {
+        let mut bytes = Message::query(1, "a", RecordType::A).encode().unwrap();
+        bytes[12] = 0xc0;
+        bytes[13] = 12;
+        assert!(Message::decode(&bytes).is_err());
+        assert!(Message::decode(&bytes[..5]).is_err());
+    }
+    #[test]
+    fn compresses_repeated_names() {
+        let mut response = Message::query(7, "www.example.com", RecordType::A);
+        response.header.response = true;
+        let record = |name: &str, data| Record {
+            name: name.into(),
+            rtype: RecordType::Cname,
+            rclass: RecordClass::In,
+            ttl: 60,
+            data,
+        };
+        response.answers = vec![
+            record("www.example.com", RData::Cname("web.example.com".into())),
+            record("web.example.com", RData::A(Ipv4Addr::new(192, 0, 2, 1))),
+        ];
+        response.answers[1].rtype = RecordType::A;
+        let bytes = response.encode().unwrap();
+        // the question name in full, then pointers and the one new label
+        assert_eq!(bytes.len(), 12 + 17 + 4 + (2 + 10 + 6) + (2 + 10 + 4));
+        assert_eq!(&bytes[33..35], &[0xc0, 12]);
+        assert_eq!(Message::decode(&bytes).unwrap(), response);
+    }
+    #[test]
+    fn srv_targets_round_trip_uncompressed() {
+        let mut response = Message::query(9, "_grpc._tcp.example.com", RecordType::Srv);
+        response.header.response = true;
+        let srv = Srv {
+            priority: 10,
+            weight: 5,
+            port: 50051,
+            target: "example.com".into(),
+        };
+        response.answers = vec![Record {
+            name: "_grpc._tcp.example.com".into(),
+            rtype: RecordType::Srv,
+            rclass: RecordClass::In,
+            ttl: 60,
+            data: RData::Srv(srv.clone()),
+        }];
+        let bytes = response.encode().unwrap();
+        assert_eq!(&bytes[bytes.len() - 13..bytes.len() - 9], b"\x07exa");
+        assert_eq!(Message::decode(&bytes).unwrap(), response);
+        assert_eq!(srv.to_string(), "10 5 50051 example.com.");
+    }
+    #[test]
+    fn mx_txt_and_caa_records_round_trip() {
+        let mut response = Message::query(3, "example.com", RecordType::Mx);
+        response.header.response = true;
+        let record = |rtype, data| Record {
+            name: "example.com".into(),
+            rtype,
+            rclass: RecordClass::In,
+            ttl: 60,
+            data,
+        };
+        let mx = Mx {
+            preference: 10,
+            exchange: "mail.example.com".into(),
+        };
+        let long = "v=spf1 ".to_string() + &"a".repeat(300);
+        response.answers = vec![
+            record(RecordType::Mx, RData::Mx(mx.clone())),
+            record(RecordType::Txt, RData::Txt(vec!["v=spf1 -all".into()])),
+            record(RecordType::Txt, RData::Txt(vec![long.clone()])),
+            record(
+                RecordType::Caa,
+                RData::Caa(Caa {
+                    flags: 128,
+                    tag: "issue".into(),
+                    value: b"ca.example.net".to_vec(),
+                }),
+            ),
+        ];
+        let decoded = Message::decode(&response.encode().unwrap()).unwrap();
+        assert_eq!(decoded.answers[..2], response.answers[..2]);
+        assert_eq!(decoded.answers[3], response.answers[3]);
+        // long strings are split into 255 byte character strings
+        assert_eq!(
+            decoded.answers[2].data,
+            RData::Txt(vec![long[..255].to_string(), long[255..].to_string()])
+        );
+        assert_eq!(mx.to_string(), "10 mail.example.com.");
+    }
+    #[test]
+    fn empty_txt_strings_are_kept() {
+        let mut response = Message::query(9, "example.com", RecordType::Txt);
+        let record = |strings: Vec<String>| Record {
+            name: "example.com".into(),
+            rtype: RecordType::Txt,
+            rclass: RecordClass::In,
+            ttl: 60,
+            data: RData::Txt(strings),
+        };
+        response.answers = vec![
+            record(vec![String::new()]),
+            record(vec!["a".into(), String::new(), "b".into()]),
+            record(vec![]),
+        ];
+        let decoded = Message::decode(&response.encode().unwrap()).unwrap();
+        assert_eq!(decoded.answers[..2], response.answers[..2]);
+        // no strings at all goes out as a single empty one
+        assert_eq!(decoded.answers[2].data, RData::Txt(vec![String::new()]));
+    }
+    #[test]
+    fn rejects_long_labels() {
+        let name = "a".repeat(64);
+        assert!(Message::query(1, &name, RecordType::A).encode().is_err());
+    }
+}
@@ -0,0 +1,465 @@
+//! Synthesizing IPv6 addresses for IPv4-only names, as DNS64 does.
+//!
+//! On an IPv6-only network, hosts reach IPv4-only servers through a NAT64
+//! translator, at IPv6 addresses embedding the IPv4 one in the translator's
+//! prefix (RFC 6052). [`Dns64Resolver`] synthesizes those addresses for
+//! answers with no IPv6 address of their own, using a configured prefix or
+//! one discovered, per RFC 7050, from the answer for `ipv4only.arpa`.
+//! [`Nat64Environment`] probes whether the host is on such a network at
+//! all, so an application can set that up only where it is needed.
+use crate::errors::IpaddrConversionError;
+use crate::iface::{interfaces, Interface};
+use crate::resolver::{Resolve, SystemResolver};
+use crate::route::source_ip_for;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// The name whose AAAA answer reveals the NAT64 prefix in use
+pub const IPV4ONLY_ARPA: &str = "ipv4only.arpa";
+/// The IPv4 addresses `ipv4only.arpa` resolves to
+const IPV4ONLY_ADDRS: [Ipv4Addr; 2] =
+    [Ipv4Addr::new(192, 0, 0, 170), Ipv4Addr::new(192, 0, 0, 171)];
+/// Prefix lengths RFC 6052 allows, longest first
+const PREFIX_LENS: [u8; 6] = [96, 64, 56, 48, 40, 32];
+/// Global destinations routes are probed for. Probing sends nothing.
+const PROBE_V4: Ipv4Addr = Ipv4Addr::new(8, 8, 8, 8);
+const PROBE_V6: Ipv6Addr = Ipv6Addr::new(0x2001, 0x4860, 0x4860, 0, 0, 0, 0, 0x8888);
+
+/// A NAT64 prefix, which IPv4 addresses are embedded in.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::dns64::Nat64Prefix;
+///
+/// let prefix = Nat64Prefix::new("2001:db8:100::".parse().unwrap(), 40).unwrap();
+/// let synthesized = prefix.synthesize("192.0.2.33".parse().unwrap());
+/// assert_eq!(synthesized.to_string(), "2001:db8:1c0:2:21::");
+/// assert_eq!(prefix.extract(synthesized), Some("192.0.2.33".parse().unwrap()));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Nat64Prefix {
+    prefix: Ipv6Addr,
+    len: u8,
+}
+
+impl Nat64Prefix {
+    /// The well-known prefix, `64:ff9b::/96`
+    pub fn well_known() -> Self {
+        Self {
+            prefix: Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0),
+            len: 96,
+        }
+    }
+
+    /// Prefix of the first `len` bits of `prefix`
+    ///
+    /// # Returns
+    /// The prefix, or None if `len` is not one of the lengths RFC 6052
+    /// allows: 32, 40, 48, 56, 64 or 96
+    pub fn new(prefix: Ipv6Addr, len: u8) -> Option<Self> {
+        if !PREFIX_LENS.contains(&len) {
+            return None;
+        }
+        let mut octets = prefix.octets();
+        for octet in &mut octets[usize::from(len / 8)..] {
+            *octet = 0;
+        }
+        Some(Self {
+            prefix: octets.into(),
+            len,
+        })
+    }
+
+    /// The prefix's address, with the bits past its length zero
+    pub fn prefix(&self) -> Ipv6Addr {
+        self.prefix
+    }
+
+    /// The prefix length
+    pub fn prefix_len(&self) -> u8 {
+        self.len
+    }
+
+    /// Whether this is the well-known prefix, which must only carry
+    /// globally reachable IPv4 addresses
+    pub fn is_well_known(&self) -> bool {
+        *self == Self::well_known()
+    }
+
+    /// The octets past the prefix which hold the IPv4 address. Bits 64 to 71
+    /// are always zero, so the address skips octet 8.
+    fn positions(&self) -> impl Iterator<Item = usize> {
+        (usize::from(self.len / 8)..16)
+            .filter(|at| *at != 8)
+            .take(4)
+    }
+
+    /// The IPv6 address `addr` is reached at through the translator
+    pub fn synthesize(&self, addr: Ipv4Addr) -> Ipv6Addr {
+        let mut octets = self.prefix.octets();
+        for (at, octet) in self.positions().zip(addr.octets().iter()) {
+            octets[at] = *octet;
+        }
+        octets.into()
+    }
+
+    /// The IPv4 address embedded in `addr`, if it is inside the prefix
+    pub fn extract(&self, addr: Ipv6Addr) -> Option<Ipv4Addr> {
+        let octets = addr.octets();
+        let prefix_len = usize::from(self.len / 8);
+        if octets[..prefix_len] != self.prefix.octets()[..prefix_len] {
+            return None;
+        }
+        let mut v4 = [0u8; 4];
+        for (octet, at) in v4.iter_mut().zip(self.positions()) {
+            *octet = octets[at];
+        }
+        Some(v4.into())
+    }
+
+    /// Discover the prefixes in use from `resolver`'s answer for
+    /// `ipv4only.arpa`, per RFC 7050. Networks without NAT64 answer only its
+    /// IPv4 addresses.
+    ///
+    /// # Returns
+    /// The prefixes found, or a NotFound error if there were none
+    pub fn discover<R: Resolve>(resolver: &R) -> Result<Vec<Self>, IpaddrConversionError> {
+        let mut prefixes = Vec::new();
+        for addr in resolver.resolve(IPV4ONLY_ARPA)? {
+            let v6 = match addr {
+                IpAddr::V6(v6) => v6,
+                IpAddr::V4(_) => continue,
+            };
+            let found = PREFIX_LENS
+                .iter()
+                .filter_map(|len| Self::new(v6, *len))
+                .find(|prefix| {
+                    prefix
+                        .extract(v6)
+                        .is_some_and(|v4| IPV4ONLY_ADDRS.contains(&v4))
+                });
+            if let Some(prefix) = found {
+                if !prefixes.contains(&prefix) {
+                    prefixes.push(prefix);
+                }
+            }
+        }
+        if prefixes.is_empty() {
+            return Err(IpaddrConversionError::NotFound(format!(
+                "no NAT64 prefix in the answer for {}",
+                IPV4ONLY_ARPA
+            )));
+        }
+        Ok(prefixes)
+    }
+}
+
+impl fmt::Display for Nat64Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.prefix, self.len)
+    }
+}
+
+/// The NAT64 prefix of the network this host is on, discovered through the
+/// system resolver as [`Nat64Prefix::discover`] does.
+///
+/// # Returns
+/// The first prefix found, None if the network has no NAT64, or an error if
+/// `ipv4only.arpa` could not be resolved at all
+pub fn detect_nat64_prefix() -> Result<Option<Nat64Prefix>, IpaddrConversionError> {
+    detect_nat64_prefix_with(&SystemResolver)
+}
+
+/// The NAT64 prefix discovered through `resolver`, as
+/// [`detect_nat64_prefix`] finds it through the system resolver
+pub fn detect_nat64_prefix_with<R: Resolve>(
+    resolver: &R,
+) -> Result<Option<Nat64Prefix>, IpaddrConversionError> {
+    match Nat64Prefix::discover(resolver) {
+        Ok(prefixes) => Ok(prefixes.first().copied()),
+        Err(IpaddrConversionError::NotFound(_)) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Whether `iface` looks like a CLAT, the host side of 464XLAT, which
+/// gives applications IPv4 over an IPv6-only network: an IPv4 address in
+/// `192.0.0.0/29`, set aside for it by RFC 7335, or the names Android and
+/// clatd give the interface
+fn is_clat(iface: &Interface) -> bool {
+    let clat_addr = iface.addrs.iter().any(|addr| match addr {
+        IpAddr::V4(v4) => v4.octets()[..3] == [192, 0, 0] && v4.octets()[3] < 8,
+        IpAddr::V6(_) => false,
+    });
+    clat_addr || iface.name.starts_with("clat") || iface.name.starts_with("v4-")
+}
+
+/// What a host can learn about IPv6-only networking where it is.
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::dns64::{Dns64Resolver, Nat64Environment};
+/// use ipaddr_from_str::SystemResolver;
+///
+/// let environment = Nat64Environment::detect();
+/// if let Some(resolver) = environment.dns64_resolver(SystemResolver) {
+///     println!("synthesizing in {}", resolver.prefix());
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nat64Environment {
+    /// The NAT64 prefix, if the network has a translator
+    pub prefix: Option<Nat64Prefix>,
+    /// Whether there is a route to the IPv4 internet, natively or through a
+    /// CLAT
+    pub ipv4_route: bool,
+    /// Whether there is a route to the IPv6 internet
+    pub ipv6_route: bool,
+    /// The CLAT interface, if there is one
+    pub clat: Option<String>,
+}
+
+impl Nat64Environment {
+    /// Probe this host's interfaces and routes, and discover the NAT64
+    /// prefix through the system resolver. Probes which fail count as
+    /// finding nothing.
+    pub fn detect() -> Self {
+        Self::detect_with(&SystemResolver)
+    }
+
+    /// Probe as [`detect`](Self::detect) does, discovering the prefix
+    /// through `resolver`
+    pub fn detect_with<R: Resolve>(resolver: &R) -> Self {
+        let interfaces = interfaces().unwrap_or_default();
+        Self::from_probes(
+            detect_nat64_prefix_with(resolver).ok().flatten(),
+            &interfaces,
+            source_ip_for(PROBE_V4.into()).is_ok(),
+            source_ip_for(PROBE_V6.into()).is_ok(),
+        )
+    }
+
+    /// The environment the results of the probes describe
+    pub fn from_probes(
+        prefix: Option<Nat64Prefix>,
+        interfaces: &[Interface],
+        ipv4_route: bool,
+        ipv6_route: bool,
+    ) -> Self {
+        let clat = interfaces
+            .iter()
+            .find(|iface| iface.is_up && is_clat(iface))
+            .map(|iface| iface.name.clone());
+        Self {
+            prefix,
+            ipv4_route,
+            ipv6_route,
+            clat,
+        }
+    }
+
+    /// Whether the host has IPv4 connectivity of its own, rather than
+    /// through a CLAT
+    pub fn has_native_ipv4(&self) -> bool {
+        self.ipv4_route && self.clat.is_none()
+    }
+
+    /// Whether the network is IPv6-only, with IPv4 reached, if at all, by
+    /// translation
+    pub fn is_ipv6_only(&self) -> bool {
+        self.ipv6_route && !self.has_native_ipv4()
+    }
+
+    /// Whether names should have addresses synthesized for them: the network
+    /// is IPv6-only and there is a translator to synthesize for
+    pub fn needs_dns64(&self) -> bool {
+        self.is_ipv6_only() && self.prefix.is_some()
+    }
+
+    /// A resolver synthesizing addresses over `resolver` if the environment
+    /// needs it, or None if names can be used as they resolve
+    pub fn dns64_resolver<R: Resolve>(&self, resolver: R) -> Option<Dns64Resolver<R>> {
+        match self.prefix {
+            Some(prefix) if self.needs_dns64() => Some(Dns64Resolver::new(resolver, prefix)),
+            _ => None,
+        }
+    }
+}
+
+/// Whether the well-known prefix may carry `addr`, per RFC 6052: only
+/// globally reachable addresses
+fn is_global(addr: Ipv4Addr) -> bool {
+    !(addr.is_private()
+        || addr.is_loopback()
+        || addr.is_link_local()
+        || addr.is_unspecified()
+        || addr.is_broadcast()
+        || addr.is_documentation()
+        || addr.octets()[..3] == [192, 0, 0])
+}
+
+/// `addrs` with IPv6 addresses synthesized for its IPv4 ones, ahead of
+/// them, if it has no IPv6 address of its own
+pub(crate) fn synthesize(prefix: &Nat64Prefix, addrs: Vec<IpAddr>) -> Vec<IpAddr> {
+    if addrs.iter().any(IpAddr::is_ipv6) {
+        return addrs;
+    }
+    let synthesized = addrs.iter().filter_map(|addr| match addr {
+        IpAddr::V4(v4) if !prefix.is_well_known() || is_global(*v4) => {
+            Some(IpAddr::V6(prefix.synthesize(*v4)))
+        }
+        _ => None,
+    });
+    synthesized.chain(addrs.iter().copied()).collect()
+}
+
+/// Resolver adding NAT64 addresses to the answers of another resolver which
+/// have no IPv6 address.
+///
+/// The synthesized addresses come first, followed by the IPv4 addresses
+/// they were made from, so clients which prefer the first address connect
+/// through the translator. Answers with an IPv6 address are left alone.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::dns64::{Dns64Resolver, Nat64Prefix};
+/// use ipaddr_from_str::{Resolve, StaticResolver};
+/// use std::net::IpAddr;
+///
+/// let inner = StaticResolver::new().with_entry("legacy.example.com", vec!["93.184.216.34".parse().unwrap()]);
+/// let resolver = Dns64Resolver::new(inner, Nat64Prefix::well_known());
+/// let addrs = resolver.resolve("legacy.example.com").unwrap();
+/// assert_eq!(addrs[0], "64:ff9b::5db8:d822".parse::<IpAddr>().unwrap());
+/// assert_eq!(addrs[1], "93.184.216.34".parse::<IpAddr>().unwrap());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Dns64Resolver<R> {
+    resolver: R,
+    prefix: Nat64Prefix,
+}
+
+impl<R: Resolve> Dns64Resolver<R> {
+    /// Synthesize addresses in `prefix` for the answers of `resolver`
+    pub fn new(resolver: R, prefix: Nat64Prefix) -> Self {
+        Self { resolver, prefix }
+    }
+
+    /// Synthesize addresses in the first prefix discovered through
+    /// `resolver`, as [`Nat64Prefix::discover`] does
+    ///
+    /// # Returns
+    /// The resolver, or an error if no prefix was discovered, which is the
+    /// case on networks without NAT64
+    pub fn discover(resolver: R) -> Result<Self, IpaddrConversionError> {
+        let prefix = Nat64Prefix::discover(&resolver)?[0];
+        Ok(Self::new(resolver, prefix))
+    }
+
+    /// The prefix addresses are synthesized in
+    pub fn prefix(&self) -> Nat64Prefix {
+        self.prefix
+    }
+
+    /// The resolver whose answers are added to
+    pub fn inner(&self) -> &R {
+        &self.resolver
+    }
+}
+
+impl<R: Resolve> Resolve for Dns64Resolver<R> {
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        let addrs = self.resolver.resolve(hostname_or_address)?;
+        Ok(synthesize(&self.prefix, addrs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::StaticResolver;
+    use crate::test_util::ips;
+
+    #[test]
+    fn embeds_at_every_prefix_length() {
+        // the examples of RFC 6052 section 2.4
+        let v4: Ipv4Addr = "192.0.2.33".parse().unwrap();
+        for (len, expected) in [
+            (32, "2001:db8:c000:221::"),
+            (40, "2001:db8:1c0:2:21::"),
+            (48, "2001:db8:122:c000:2:2100::"),
+            (56, "2001:db8:122:3c0:0:221::"),
+            (64, "2001:db8:122:344:c0:2:2100:0"),
+            (96, "2001:db8:122:344::192.0.2.33"),
+        ] {
+            let prefix = Nat64Prefix::new("2001:db8:122:344::".parse().unwrap(), len).unwrap();
+            let synthesized = prefix.synthesize(v4);
+            assert_eq!(
+                synthesized,
+                expected.parse::<Ipv6Addr>().unwrap(),
+                "/{}",
+                len
+            );
+            assert_eq!(prefix.extract(synthesized), Some(v4));
+        }
+        assert!(Nat64Prefix::new(Ipv6Addr::UNSPECIFIED, 80).is_none());
+    }
+    #[test]
+    fn discovers_the_prefix_from_ipv4only_arpa() {
+        let nat64 = StaticResolver::new()
+            .with_entry(
+                IPV4ONLY_ARPA,
+                ips(&["2001:db8:64::c000:aa", "2001:db8:64::c000:ab"]),
+            )
+            .with_entry("legacy.example.com", ips(&["198.51.100.7"]))
+            .with_entry("dual.example.com", ips(&["198.51.100.8", "2001:db8::8"]));
+        let resolver = Dns64Resolver::discover(nat64).unwrap();
+        assert_eq!(resolver.prefix().to_string(), "2001:db8:64::/96");
+        assert_eq!(
+            resolver.resolve("legacy.example.com").unwrap(),
+            ips(&["2001:db8:64::c633:6407", "198.51.100.7"])
+        );
+        assert_eq!(
+            resolver.resolve("dual.example.com").unwrap(),
+            ips(&["198.51.100.8", "2001:db8::8"])
+        );
+        let plain = StaticResolver::new().with_entry(IPV4ONLY_ARPA, ips(&["192.0.0.170"]));
+        assert!(Dns64Resolver::discover(plain).is_err());
+    }
+    #[test]
+    fn environments_are_classified() {
+        let iface = |name: &str, addrs: &[&str]| Interface {
+            name: name.into(),
+            index: 2,
+            is_up: true,
+            is_loopback: false,
+            addrs: ips(addrs),
+        };
+        let prefix = Some(Nat64Prefix::well_known());
+        let mobile = [
+            iface("rmnet0", &["2001:db8::5"]),
+            iface("v4-rmnet0", &["192.0.0.4"]),
+        ];
+        let environment = Nat64Environment::from_probes(prefix, &mobile, true, true);
+        assert_eq!(environment.clat.as_deref(), Some("v4-rmnet0"));
+        assert!(environment.is_ipv6_only() && environment.needs_dns64());
+        assert!(environment.dns64_resolver(StaticResolver::new()).is_some());
+        let dual = [iface("eth0", &["192.168.1.5", "2001:db8::5"])];
+        let environment = Nat64Environment::from_probes(prefix, &dual, true, true);
+        assert!(environment.has_native_ipv4() && !environment.needs_dns64());
+        let plain = StaticResolver::new().with_entry(IPV4ONLY_ARPA, ips(&["192.0.0.171"]));
+        assert_eq!(detect_nat64_prefix_with(&plain).unwrap(), None);
+    }
+    #[test]
+    fn well_known_prefix_skips_private_addresses() {
+        let inner =
+            StaticResolver::new().with_entry("mixed.internal", ips(&["10.0.0.1", "8.8.8.8"]));
+        let resolver = Dns64Resolver::new(inner, Nat64Prefix::well_known());
+        assert_eq!(
+            resolver.resolve("mixed.internal").unwrap(),
+            ips(&["64:ff9b::808:808", "10.0.0.1", "8.8.8.8"])
+        );
+    }
+}
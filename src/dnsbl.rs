@@ -0,0 +1,261 @@
+//! DNS blocklist (DNSBL, also RBL) queries, RFC 5782.
+//!
+//! A blocklist publishes the addresses it lists as A records under its
+//! zone: `192.0.2.99` is listed by `zen.spamhaus.org` if
+//! `99.2.0.192.zen.spamhaus.org` resolves, and the 127.0.0.x address it
+//! resolves to says why. Many lists answer public resolvers with error
+//! codes rather than listings, and some resolvers answer every name, so
+//! [`check_dnsbl`] tells those apart from real listings.
+use crate::batch::resolve_all_within;
+use crate::errors::IpaddrConversionError;
+use crate::rdns::reversed_labels;
+use crate::resolver::{normalize_name, Resolve, SystemResolver};
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+
+/// What a blocklist said of an address
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DnsblAnswer {
+    /// The address is not listed
+    NotListed,
+    /// The address is listed, with these return codes, each of 127/8. What
+    /// a code means depends on the list; many use 127.0.0.2 for a plain
+    /// listing, and some give a bitmask in the last octet.
+    Listed(Vec<Ipv4Addr>),
+    /// The list refused to answer, with a 127.255.255.x code, as lists do
+    /// when queried through public resolvers or too often
+    Refused(Ipv4Addr),
+    /// The answer was outside 127/8, as a resolver answering every name
+    /// gives, so nothing can be concluded
+    Invalid(Vec<IpAddr>),
+}
+
+impl DnsblAnswer {
+    /// Whether the address is listed
+    pub fn is_listed(&self) -> bool {
+        matches!(self, DnsblAnswer::Listed(_))
+    }
+
+    /// The last octets of the return codes, OR-ed together, as lists giving
+    /// a bitmask of the sublists listing an address return them
+    pub fn bits(&self) -> u8 {
+        match self {
+            DnsblAnswer::Listed(codes) => {
+                codes.iter().fold(0, |bits, code| bits | code.octets()[3])
+            }
+            _ => 0,
+        }
+    }
+
+    /// Interpret the addresses a blocklist query resolved to
+    fn from_addrs(addrs: Vec<IpAddr>) -> Self {
+        let mut codes = Vec::new();
+        for addr in &addrs {
+            match addr {
+                IpAddr::V4(v4) if v4.octets()[..3] == [127, 255, 255] => {
+                    return DnsblAnswer::Refused(*v4)
+                }
+                IpAddr::V4(v4) if v4.octets()[0] == 127 => codes.push(*v4),
+                _ => return DnsblAnswer::Invalid(addrs),
+            }
+        }
+        if codes.is_empty() {
+            DnsblAnswer::NotListed
+        } else {
+            DnsblAnswer::Listed(codes)
+        }
+    }
+}
+
+/// The name queried to ask `zone` whether it lists `addr`
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::dnsbl::dnsbl_query_name;
+///
+/// assert_eq!(
+///     dnsbl_query_name("192.0.2.99".parse().unwrap(), "zen.spamhaus.org."),
+///     "99.2.0.192.zen.spamhaus.org"
+/// );
+/// ```
+pub fn dnsbl_query_name(addr: IpAddr, zone: &str) -> String {
+    format!(
+        "{}.{}",
+        reversed_labels(addr.to_canonical()),
+        normalize_name(zone)
+    )
+}
+
+/// Ask the blocklist `zone` whether it lists `addr`, through the system
+/// resolver.
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::dnsbl::check_dnsbl;
+///
+/// let answer = check_dnsbl("192.0.2.99".parse().unwrap(), "zen.spamhaus.org").unwrap();
+/// if answer.is_listed() {
+///     eprintln!("192.0.2.99 is listed: {:?}", answer);
+/// }
+/// ```
+pub fn check_dnsbl(addr: IpAddr, zone: &str) -> Result<DnsblAnswer, IpaddrConversionError> {
+    check_dnsbl_with(&SystemResolver, addr, zone)
+}
+
+/// Ask the blocklist `zone` whether it lists `addr`
+///
+/// # Parameters
+///
+/// * `resolver` - resolves the query name
+/// * `addr` - the address to check; an IPv4-mapped IPv6 address is checked
+///   as its IPv4 address
+/// * `zone` - the zone of the list, such as `zen.spamhaus.org`
+///
+/// # Returns
+/// The answer, with a name which does not exist meaning the address is not
+/// listed, or the error of a failing lookup
+pub fn check_dnsbl_with<R: Resolve + ?Sized>(
+    resolver: &R,
+    addr: IpAddr,
+    zone: &str,
+) -> Result<DnsblAnswer, IpaddrConversionError> {
+    interpret(resolver.resolve(&dnsbl_query_name(addr, zone)))
+}
+
+fn interpret(
+    result: Result<Vec<IpAddr>, IpaddrConversionError>,
+) -> Result<DnsblAnswer, IpaddrConversionError> {
+    match result {
+        Ok(addrs) => Ok(DnsblAnswer::from_addrs(addrs)),
+        Err(IpaddrConversionError::NotFound(_)) => Ok(DnsblAnswer::NotListed),
+        Err(IpaddrConversionError::LookupError(ref err))
+            if matches!(err.kind(), dns_lookup::LookupErrorKind::NoName) =>
+        {
+            Ok(DnsblAnswer::NotListed)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Ask each of `zones` whether it lists `addr`, all at once
+///
+/// # Parameters
+///
+/// * `resolver` - resolves the query names; it is cloned for each list
+/// * `addr` - the address to check
+/// * `zones` - the zones of the lists
+/// * `budget` - the time allowed for all the lists to answer
+///
+/// # Returns
+/// Each zone with its answer, in the order given; lists which did not
+/// answer within `budget` give a Timeout error
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::dnsbl::check_dnsbls;
+/// use ipaddr_from_str::SystemResolver;
+/// use std::time::Duration;
+///
+/// let zones = ["zen.spamhaus.org", "bl.spamcop.net"];
+/// let addr = "192.0.2.99".parse().unwrap();
+/// for (zone, answer) in check_dnsbls(&SystemResolver, addr, &zones, Duration::from_secs(5)) {
+///     println!("{}: {:?}", zone, answer);
+/// }
+/// ```
+pub fn check_dnsbls<R, S>(
+    resolver: &R,
+    addr: IpAddr,
+    zones: &[S],
+    budget: Duration,
+) -> Vec<(String, Result<DnsblAnswer, IpaddrConversionError>)>
+where
+    R: Resolve + Clone + Send + 'static,
+    S: AsRef<str>,
+{
+    let names: Vec<String> = zones
+        .iter()
+        .map(|zone| dnsbl_query_name(addr, zone.as_ref()))
+        .collect();
+    let mut resolution = resolve_all_within(resolver, &names, budget);
+    zones
+        .iter()
+        .zip(names)
+        .map(|(zone, name)| {
+            let result = match resolution
+                .finished
+                .iter()
+                .position(|(done, _)| *done == name)
+            {
+                Some(idx) => interpret(resolution.finished.swap_remove(idx).1),
+                None => Err(IpaddrConversionError::Timeout(name)),
+            };
+            (zone.as_ref().to_string(), result)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::StaticResolver;
+
+    #[test]
+    fn answers_are_interpreted() {
+        let code = |last| IpAddr::V4(Ipv4Addr::new(127, 0, 0, last));
+        let resolver = StaticResolver::new()
+            .with_entry("99.2.0.192.bl.example.org", vec![code(2), code(4)])
+            .with_entry(
+                "99.2.0.192.refusing.example.org",
+                vec![IpAddr::V4(Ipv4Addr::new(127, 255, 255, 254))],
+            )
+            .with_entry(
+                "99.2.0.192.wildcard.example.org",
+                vec!["198.51.100.1".parse().unwrap()],
+            )
+            .with_entry(
+                "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.bl.example.org",
+                vec![code(2)],
+            );
+        let addr = "::ffff:192.0.2.99".parse().unwrap();
+        let answer = check_dnsbl_with(&resolver, addr, "bl.example.org").unwrap();
+        assert!(answer.is_listed());
+        assert_eq!(answer.bits(), 6);
+        assert!(matches!(
+            check_dnsbl_with(&resolver, addr, "refusing.example.org").unwrap(),
+            DnsblAnswer::Refused(_)
+        ));
+        assert!(matches!(
+            check_dnsbl_with(&resolver, addr, "wildcard.example.org").unwrap(),
+            DnsblAnswer::Invalid(_)
+        ));
+        assert!(
+            check_dnsbl_with(&resolver, "2001:db8::1".parse().unwrap(), "bl.example.org")
+                .unwrap()
+                .is_listed()
+        );
+        assert_eq!(
+            check_dnsbl_with(&resolver, "192.0.2.1".parse().unwrap(), "bl.example.org").unwrap(),
+            DnsblAnswer::NotListed
+        );
+    }
+    #[test]
+    fn lists_are_checked_together() {
+        let resolver = StaticResolver::new().with_entry(
+            "99.2.0.192.bl.example.org",
+            vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2))],
+        );
+        let addr = "192.0.2.99".parse().unwrap();
+        let answers = check_dnsbls(
+            &resolver,
+            addr,
+            &["bl.example.org", "other.example.org"],
+            Duration::from_secs(1),
+        );
+        assert_eq!(answers[0].0, "bl.example.org");
+        assert!(answers[0].1.as_ref().unwrap().is_listed());
+        assert_eq!(answers[1].1.as_ref().unwrap(), &DnsblAnswer::NotListed);
+    }
+}
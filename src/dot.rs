@@ -0,0 +1,242 @@
+//! DNS over TLS (RFC 7858) transport for the direct backend.
+//!
+//! Enabled by the `dot` feature. Queries are framed exactly as over TCP, inside
+//! a TLS session to port 853. The TLS client config is shared between queries,
+//! so sessions are resumed rather than renegotiated from scratch.
+use crate::digest::sha256;
+use crate::errors::IpaddrConversionError;
+use crate::socket::SocketOptions;
+use crate::tls::der_header;
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::{Certificate, ClientConfig, ClientConnection, OwnedTrustAnchor, RootCertStore};
+use rustls::{ServerName, StreamOwned};
+use std::convert::TryFrom;
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// Port DNS over TLS servers listen on
+pub const DOT_PORT: u16 = 853;
+
+/// Settings for DNS over TLS.
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::direct::DirectResolver;
+/// use ipaddr_from_str::dot::DotConfig;
+/// use ipaddr_from_str::resolv_conf::ResolvConf;
+///
+/// let conf = ResolvConf::parse("nameserver 1.1.1.1");
+/// let resolver = DirectResolver::new(conf)
+///     .with_tls(DotConfig::new("cloudflare-dns.com"))
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DotConfig {
+    server_name: String,
+    port: u16,
+    pins: Vec<[u8; 32]>,
+}
+
+impl DotConfig {
+    /// New config presenting `server_name` as SNI and verifying the server's
+    /// certificate against it, using the webpki root store
+    pub fn new<I: Into<String>>(server_name: I) -> Self {
+        Self {
+            server_name: server_name.into(),
+            port: DOT_PORT,
+            pins: Vec::new(),
+        }
+    }
+
+    /// Connect on `port` rather than 853, returning the updated config
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Pin the SHA-256 digest of the server's SubjectPublicKeyInfo, returning
+    /// the updated config.
+    ///
+    /// Once any pin is set, the server must present a certificate whose key
+    /// matches one of them, and the certificate chain is not otherwise
+    /// validated; this is the out-of-band key-pinned profile of RFC 7858,
+    /// which allows self-signed resolver certificates.
+    pub fn with_pin(mut self, spki_sha256: [u8; 32]) -> Self {
+        self.pins.push(spki_sha256);
+        self
+    }
+
+    /// The SNI value presented to the server
+    pub fn server_name(&self) -> &str {
+        &self.server_name
+    }
+
+    /// The port connected to
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+/// Verifies the server's key against a set of SPKI pins
+struct PinnedKeyVerifier {
+    pins: Vec<[u8; 32]>,
+}
+
+impl ServerCertVerifier for PinnedKeyVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let spki = spki(&end_entity.0).ok_or_else(|| {
+            rustls::Error::General("could not find the certificate's public key".into())
+        })?;
+        if self.pins.contains(&sha256(spki)) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(
+                "certificate key does not match any pin".into(),
+            ))
+        }
+    }
+}
+
+/// The DER encoded SubjectPublicKeyInfo of an X.509 certificate
+fn spki(cert: &[u8]) -> Option<&[u8]> {
+    const SEQUENCE: u8 = 0x30;
+    const VERSION: u8 = 0xa0;
+    let (tag, cert_start, _) = der_header(cert, 0)?;
+    if tag != SEQUENCE {
+        return None;
+    }
+    let (tag, mut offset, _) = der_header(cert, cert_start)?;
+    if tag != SEQUENCE {
+        return None;
+    }
+    // skip the optional version, then serial, signature, issuer, validity and
+    // subject to reach subjectPublicKeyInfo
+    let mut skip = 5;
+    if der_header(cert, offset)?.0 == VERSION {
+        skip += 1;
+    }
+    for _ in 0..skip {
+        let (_, start, len) = der_header(cert, offset)?;
+        offset = start + len;
+    }
+    let (tag, start, len) = der_header(cert, offset)?;
+    if tag != SEQUENCE {
+        return None;
+    }
+    cert.get(offset..start + len)
+}
+
+/// A DNS over TLS client, holding the TLS config shared across queries
+pub(crate) struct DotTransport {
+    config: DotConfig,
+    server_name: ServerName,
+    tls: Arc<ClientConfig>,
+}
+
+impl std::fmt::Debug for DotTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("DotTransport")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl DotTransport {
+    pub fn new(config: DotConfig) -> Result<Self, IpaddrConversionError> {
+        let server_name = ServerName::try_from(config.server_name.as_str()).map_err(|_| {
+            IpaddrConversionError::DnsError(format!("invalid server name {}", config.server_name))
+        })?;
+        let mut roots = RootCertStore::empty();
+        roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                ta.subject,
+                ta.spki,
+                ta.name_constraints,
+            )
+        }));
+        let verifier: Arc<dyn ServerCertVerifier> = if config.pins.is_empty() {
+            Arc::new(WebPkiVerifier::new(roots.clone(), None))
+        } else {
+            Arc::new(PinnedKeyVerifier {
+                pins: config.pins.clone(),
+            })
+        };
+        let mut tls = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        tls.dangerous().set_certificate_verifier(verifier);
+        Ok(Self {
+            config,
+            server_name,
+            tls: Arc::new(tls),
+        })
+    }
+
+    /// Open a TLS session to the nameserver at `server`'s address, over a
+    /// connection set up with `source`
+    pub fn connect(
+        &self,
+        server: SocketAddr,
+        source: &SocketOptions,
+        timeout: Duration,
+    ) -> io::Result<StreamOwned<ClientConnection, TcpStream>> {
+        let addr = SocketAddr::new(server.ip(), self.config.port);
+        let socket = source.connecting_socket(&addr)?;
+        socket.connect_timeout(&addr.into(), timeout)?;
+        let tcp = socket.into_tcp_stream();
+        tcp.set_read_timeout(Some(timeout))?;
+        tcp.set_write_timeout(Some(timeout))?;
+        let connection = ClientConnection::new(self.tls.clone(), self.server_name.clone())
+            .map_err(io::Error::other)?;
+        Ok(StreamOwned::new(connection, tcp))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_find_spki_in_certificate() {
+        #[rustfmt::skip]
+        let cert = [
+            0x30, 0x17,                   // Certificate
+            0x30, 0x15,                   // TBSCertificate
+            0xa0, 0x03, 0x02, 0x01, 0x02, // version
+            0x02, 0x01, 0x01,             // serial
+            0x30, 0x00,                   // signature
+            0x30, 0x00,                   // issuer
+            0x30, 0x00,                   // validity
+            0x30, 0x00,                   // subject
+            0x30, 0x03, 0x04, 0x01, 0xff, // subjectPublicKeyInfo
+        ];
+        assert_eq!(spki(&cert).unwrap(), &[0x30, 0x03, 0x04, 0x01, 0xff]);
+        assert!(spki(&cert[..10]).is_none());
+    }
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn sessions_connect_from_the_source_address() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap();
+        let transport =
+            DotTransport::new(DotConfig::new("dns.example").with_port(server.port())).unwrap();
+        let source = SocketOptions::new().local_addr("127.0.0.2".parse().unwrap());
+        let _stream = transport
+            .connect(server, &source, Duration::from_secs(1))
+            .unwrap();
+        let (_, peer) = listener.accept().unwrap();
+        assert_eq!(peer.ip(), "127.0.0.2".parse::<std::net::IpAddr>().unwrap());
+    }
+}
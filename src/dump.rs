@@ -0,0 +1,353 @@
+//! Dumping the state of a resolver, for debugging.
+//!
+//! A [`DebugDump`] gathers what the resolvers of a stack know about
+//! themselves: the configuration of a
+//! [`DirectResolver`](crate::direct::DirectResolver), the entries of a
+//! [`CachingResolver`](crate::cache::CachingResolver), and the figures and
+//! recent failures of a [`StatsCollector`], and the health of the nameservers
+//! behind a [`CircuitBreaker`]. Each of those has a `debug_dump`
+//! method, and the dumps of a stack are merged into one, to be rendered as
+//! JSON for an admin endpoint or as text for a log when a signal arrives.
+//! Names and client addresses are written through [`redact`](crate::redact).
+use crate::breaker::{CircuitBreaker, ServerHealth};
+use crate::redact::{addr_text, name_text};
+use crate::resolv_conf::ResolvConf;
+use crate::stats::{RecentError, ResolverStats, StatsCollector};
+use std::fmt;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// What a cache holds, as gathered by the cache
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CacheDump {
+    pub(crate) ttl: Duration,
+    pub(crate) serve_stale: bool,
+    pub(crate) max_entries: Option<usize>,
+    /// Name, addresses and expiry, by name
+    pub(crate) entries: Vec<(String, Vec<IpAddr>, SystemTime)>,
+}
+
+/// The state of a resolver stack at one moment.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::cache::CachingResolver;
+/// use ipaddr_from_str::stats::StatsResolver;
+/// use ipaddr_from_str::{Resolve, StaticResolver};
+/// use std::time::Duration;
+///
+/// let cache = CachingResolver::new(
+///     StaticResolver::new().with_entry("db", vec!["10.0.0.1".parse().unwrap()]),
+///     Duration::from_secs(60),
+/// );
+/// let resolver = StatsResolver::new(cache.clone());
+/// resolver.resolve("db").unwrap();
+/// assert!(resolver.resolve("missing").is_err());
+///
+/// let dump = resolver.debug_dump().merge(cache.debug_dump());
+/// let json = dump.to_json();
+/// assert!(json.contains(r#""name":"db","addrs":["10.0.0.1"]"#));
+/// assert!(json.contains(r#""name":"missing","kind":"NotFound""#));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugDump {
+    taken: SystemTime,
+    config: Option<ResolvConf>,
+    cache: Option<CacheDump>,
+    stats: Option<ResolverStats>,
+    recent_errors: Vec<RecentError>,
+    servers: Vec<ServerHealth>,
+}
+
+impl Default for DebugDump {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DebugDump {
+    /// New dump, taken now, with nothing in it
+    pub fn new() -> Self {
+        Self {
+            taken: SystemTime::now(),
+            config: None,
+            cache: None,
+            stats: None,
+            recent_errors: Vec::new(),
+            servers: Vec::new(),
+        }
+    }
+
+    /// Include `config`, returning the updated dump
+    pub fn with_config(mut self, config: &ResolvConf) -> Self {
+        self.config = Some(config.clone());
+        self
+    }
+
+    pub(crate) fn with_cache(mut self, cache: CacheDump) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Include the figures since `collector` was created and the failures
+    /// it kept, returning the updated dump
+    pub fn with_stats(mut self, collector: &StatsCollector) -> Self {
+        self.stats = Some(collector.stats());
+        self.recent_errors = collector.recent_errors();
+        self
+    }
+
+    /// Include the health of the nameservers `breaker` has seen, returning
+    /// the updated dump
+    pub fn with_breaker(mut self, breaker: &CircuitBreaker) -> Self {
+        self.servers = breaker.health();
+        self
+    }
+
+    /// Fill in what this dump lacks from `other`, the dump of another
+    /// resolver of the stack, returning the updated dump
+    pub fn merge(mut self, other: DebugDump) -> Self {
+        self.config = self.config.or(other.config);
+        self.cache = self.cache.or(other.cache);
+        if self.stats.is_none() {
+            self.stats = other.stats;
+            self.recent_errors = other.recent_errors;
+        }
+        if self.servers.is_empty() {
+            self.servers = other.servers;
+        }
+        self
+    }
+
+    /// The dump as a JSON object, with only the parts it holds
+    pub fn to_json(&self) -> String {
+        let mut fields = vec![format!("\"taken\":{}", unix_secs(self.taken))];
+        if let Some(ref config) = self.config {
+            let nameservers: Vec<String> = config
+                .nameservers
+                .iter()
+                .map(|server| json_string(&server.to_string()))
+                .collect();
+            let search: Vec<String> = config.search.iter().map(|d| json_string(d)).collect();
+            fields.push(format!(
+                "\"config\":{{\"nameservers\":[{}],\"search\":[{}],\"ndots\":{},\"timeout_ms\":{},\
+                 \"attempts\":{},\"rotate\":{},\"use_vc\":{}}}",
+                nameservers.join(","),
+                search.join(","),
+                config.ndots,
+                config.timeout.as_millis(),
+                config.attempts,
+                config.rotate,
+                config.use_vc,
+            ));
+        }
+        if let Some(ref cache) = self.cache {
+            let entries: Vec<String> = cache
+                .entries
+                .iter()
+                .map(|(name, addrs, expires)| {
+                    let addrs: Vec<String> = addrs
+                        .iter()
+                        .map(|addr| json_string(&addr_text(*addr)))
+                        .collect();
+                    format!(
+                        "{{\"name\":{},\"addrs\":[{}],\"expires_in\":{}}}",
+                        json_string(&name_text(name)),
+                        addrs.join(","),
+                        self.expires_in(*expires),
+                    )
+                })
+                .collect();
+            let max_entries = cache
+                .max_entries
+                .map_or("null".to_string(), |max| max.to_string());
+            fields.push(format!(
+                "\"cache\":{{\"ttl_secs\":{},\"serve_stale\":{},\"max_entries\":{},\"entries\":[{}]}}",
+                cache.ttl.as_secs(),
+                cache.serve_stale,
+                max_entries,
+                entries.join(","),
+            ));
+        }
+        if let Some(ref stats) = self.stats {
+            let ms = |d: Option<Duration>| {
+                d.map_or("null".to_string(), |d| {
+                    format!("{:.3}", d.as_secs_f64() * 1000.0)
+                })
+            };
+            let errors: Vec<String> = stats
+                .errors
+                .iter()
+                .map(|(kind, count)| format!("{}:{}", json_string(kind), count))
+                .collect();
+            let hit_ratio = stats
+                .cache_hit_ratio()
+                .map_or("null".to_string(), |ratio| format!("{:.3}", ratio));
+            fields.push(format!(
+                "\"stats\":{{\"period_ms\":{},\"queries\":{},\"errors\":{{{}}},\"cache_hits\":{},\
+                 \"cache_misses\":{},\"cache_hit_ratio\":{},\"servers_skipped\":{},\
+                 \"mean_latency_ms\":{},\"p50_ms\":{},\"p99_ms\":{}}}",
+                stats.period.as_millis(),
+                stats.queries,
+                errors.join(","),
+                stats.cache_hits,
+                stats.cache_misses,
+                hit_ratio,
+                stats.servers_skipped,
+                ms(stats.mean_latency()),
+                ms(stats.latency_percentile(50.0)),
+                ms(stats.latency_percentile(99.0)),
+            ));
+            let recent: Vec<String> = self
+                .recent_errors
+                .iter()
+                .map(|err| {
+                    format!(
+                        "{{\"at\":{},\"name\":{},\"kind\":{},\"error\":{}}}",
+                        unix_secs(err.at),
+                        json_string(&name_text(&err.name)),
+                        json_string(err.kind),
+                        json_string(&err.error),
+                    )
+                })
+                .collect();
+            fields.push(format!("\"recent_errors\":[{}]", recent.join(",")));
+        }
+        if !self.servers.is_empty() {
+            let servers: Vec<String> = self
+                .servers
+                .iter()
+                .map(|health| {
+                    format!(
+                        "{{\"server\":{},\"state\":{},\"consecutive_failures\":{},\"trips\":{}}}",
+                        json_string(&health.server.to_string()),
+                        json_string(&health.state.to_string()),
+                        health.consecutive_failures,
+                        health.trips,
+                    )
+                })
+                .collect();
+            fields.push(format!("\"servers\":[{}]", servers.join(",")));
+        }
+        format!("{{{}}}", fields.join(","))
+    }
+
+    /// Seconds from when the dump was taken until `expires`, negative once
+    /// expired
+    fn expires_in(&self, expires: SystemTime) -> i64 {
+        match expires.duration_since(self.taken) {
+            Ok(left) => left.as_secs() as i64,
+            Err(past) => -(past.duration().as_secs() as i64),
+        }
+    }
+}
+
+impl fmt::Display for DebugDump {
+    /// The dump as text, one line per item
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "resolver state at {}", unix_secs(self.taken))?;
+        if let Some(ref config) = self.config {
+            let nameservers: Vec<String> =
+                config.nameservers.iter().map(|s| s.to_string()).collect();
+            writeln!(
+                f,
+                "config: nameservers {} search {} ndots {} timeout {:?} attempts {}{}{}",
+                nameservers.join(","),
+                config.search.join(","),
+                config.ndots,
+                config.timeout,
+                config.attempts,
+                if config.rotate { " rotate" } else { "" },
+                if config.use_vc { " use-vc" } else { "" },
+            )?;
+        }
+        if let Some(ref cache) = self.cache {
+            writeln!(
+                f,
+                "cache: {} entries ttl {:?}",
+                cache.entries.len(),
+                cache.ttl
+            )?;
+            for (name, addrs, expires) in cache.entries.iter() {
+                let addrs: Vec<String> = addrs.iter().map(|addr| addr_text(*addr)).collect();
+                writeln!(
+                    f,
+                    "  {} {} expires in {}s",
+                    name_text(name),
+                    addrs.join(","),
+                    self.expires_in(*expires)
+                )?;
+            }
+        }
+        if let Some(ref stats) = self.stats {
+            writeln!(f, "stats: {}", stats)?;
+            writeln!(f, "recent errors: {}", self.recent_errors.len())?;
+            for err in self.recent_errors.iter() {
+                writeln!(
+                    f,
+                    "  {} {} {}",
+                    unix_secs(err.at),
+                    name_text(&err.name),
+                    err.error
+                )?;
+            }
+        }
+        for health in self.servers.iter() {
+            writeln!(
+                f,
+                "server {}: {} after {} failures, tripped {} times",
+                health.server, health.state, health.consecutive_failures, health.trips
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// `text` as a quoted JSON string
+pub(crate) fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::direct::DirectResolver;
+
+    #[test]
+    fn dumps_only_the_parts_gathered() {
+        let config = ResolvConf::parse("nameserver 192.0.2.53\nsearch corp.example\n");
+        let dump = DirectResolver::new(config).debug_dump();
+        let json = dump.to_json();
+        assert!(json.contains(
+            r#""config":{"nameservers":["192.0.2.53:53"],"search":["corp.example"],"ndots":1,"#
+        ));
+        assert!(!json.contains("\"cache\""));
+        assert!(!json.contains("\"stats\""));
+        assert!(dump
+            .to_string()
+            .contains("nameservers 192.0.2.53:53 search corp.example"));
+    }
+    #[test]
+    fn escapes_json_strings() {
+        assert_eq!(json_string("a\"b\\c\n\u{1}"), r#""a\"b\\c\n\u0001""#);
+    }
+}
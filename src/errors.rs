@@ -1,13 +1,25 @@
+//! The error type shared by every part of the crate.
+use crate::parse::ParseError;
 use dns_lookup::LookupError;
 use std::error::Error;
 use std::fmt;
 use std::net::AddrParseError;
 
+/// Why a conversion, lookup or parse failed
 #[derive(Debug)]
 pub enum IpaddrConversionError {
     LookupError(LookupError),
     AddrParseError(AddrParseError),
     IoError(std::io::Error),
+    NotFound(String),
+    Timeout(String),
+    DnsError(String),
+    InvalidInput(String),
+    ParseError(ParseError),
+    Cancelled(String),
+    ProtocolMismatch(String),
+    RateLimited(String),
+    Blocked(String),
 }
 
 impl fmt::Display for IpaddrConversionError {
@@ -16,9 +28,54 @@ impl fmt::Display for IpaddrConversionError {
             IpaddrConversionError::LookupError(ref err) => write!(f, "LookupError {:?}", err),
             IpaddrConversionError::AddrParseError(ref err) => write!(f, "AddrParseError {}", err),
             IpaddrConversionError::IoError(ref err) => write!(f, "IoError {}", err),
+            IpaddrConversionError::NotFound(ref name) => write!(f, "NotFound {}", name),
+            IpaddrConversionError::Timeout(ref name) => write!(f, "Timeout {}", name),
+            IpaddrConversionError::DnsError(ref msg) => write!(f, "DnsError {}", msg),
+            IpaddrConversionError::InvalidInput(ref msg) => write!(f, "InvalidInput {}", msg),
+            IpaddrConversionError::ParseError(ref err) => write!(f, "ParseError {}", err),
+            IpaddrConversionError::Cancelled(ref name) => write!(f, "Cancelled {}", name),
+            IpaddrConversionError::ProtocolMismatch(ref msg) => {
+                write!(f, "ProtocolMismatch {}", msg)
+            }
+            IpaddrConversionError::RateLimited(ref name) => write!(f, "RateLimited {}", name),
+            IpaddrConversionError::Blocked(ref msg) => write!(f, "Blocked {}", msg),
         }
     }
 }
+impl IpaddrConversionError {
+    /// The name of the variant, for counting errors by kind
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            IpaddrConversionError::LookupError(_) => "LookupError",
+            IpaddrConversionError::AddrParseError(_) => "AddrParseError",
+            IpaddrConversionError::IoError(_) => "IoError",
+            IpaddrConversionError::NotFound(_) => "NotFound",
+            IpaddrConversionError::Timeout(_) => "Timeout",
+            IpaddrConversionError::DnsError(_) => "DnsError",
+            IpaddrConversionError::InvalidInput(_) => "InvalidInput",
+            IpaddrConversionError::ParseError(_) => "ParseError",
+            IpaddrConversionError::Cancelled(_) => "Cancelled",
+            IpaddrConversionError::ProtocolMismatch(_) => "ProtocolMismatch",
+            IpaddrConversionError::RateLimited(_) => "RateLimited",
+            IpaddrConversionError::Blocked(_) => "Blocked",
+        }
+    }
+
+    /// Whether the same lookup might succeed if tried again: timeouts, io
+    /// errors, failing nameservers and temporary system resolver failures
+    pub fn is_transient(&self) -> bool {
+        match *self {
+            IpaddrConversionError::LookupError(ref err) => {
+                matches!(err.kind(), dns_lookup::LookupErrorKind::Again)
+            }
+            IpaddrConversionError::IoError(_)
+            | IpaddrConversionError::Timeout(_)
+            | IpaddrConversionError::DnsError(_) => true,
+            _ => false,
+        }
+    }
+}
+
 impl Error for IpaddrConversionError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         // Generic error, underlying cause isn't tracked.
@@ -43,3 +100,9 @@ impl From<std::io::Error> for IpaddrConversionError {
         IpaddrConversionError::IoError(err)
     }
 }
+
+impl From<ParseError> for IpaddrConversionError {
+    fn from(err: ParseError) -> IpaddrConversionError {
+        IpaddrConversionError::ParseError(err)
+    }
+}
@@ -0,0 +1,99 @@
+//! Finding the addresses mentioned in free text, such as logs and configs.
+use std::net::IpAddr;
+
+/// An address found in text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FoundAddr {
+    /// The line it is on, from 1
+    pub line: usize,
+    /// The byte of the line it starts at, from 1
+    pub column: usize,
+    pub addr: IpAddr,
+}
+
+/// Whether `c` can be part of an address
+fn is_addr_char(c: char) -> bool {
+    c.is_ascii_hexdigit() || c == '.' || c == ':'
+}
+
+/// The address a run of address characters holds, if any: the run itself,
+/// or the run without a trailing port or dot, as in `192.0.2.1:80` or at
+/// the end of a sentence
+fn addr_in(run: &str) -> Option<IpAddr> {
+    let run = run.trim_end_matches('.');
+    if let Ok(addr) = run.parse() {
+        return Some(addr);
+    }
+    match run.rsplit_once(':') {
+        Some((host, port)) if host.contains('.') && port.parse::<u16>().is_ok() => {
+            host.parse().ok()
+        }
+        _ => run.trim_end_matches(':').parse().ok(),
+    }
+}
+
+/// Every address in `text`, in the order they appear.
+///
+/// Addresses are found between characters which cannot be part of one, so
+/// those in URLs, CIDR blocks, brackets and quotes are found, while version
+/// numbers and hex strings are not mistaken for them.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::extract::find_addrs;
+///
+/// let found = find_addrs("accepted from 192.0.2.7:51514\nproxy [2001:db8::1]:443 v1.2.3");
+/// let addrs: Vec<String> = found.iter().map(|f| f.addr.to_string()).collect();
+/// assert_eq!(addrs, vec!["192.0.2.7", "2001:db8::1"]);
+/// assert_eq!((found[1].line, found[1].column), (2, 8));
+/// ```
+pub fn find_addrs(text: &str) -> Vec<FoundAddr> {
+    let mut found = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        let mut start = None;
+        // a sentinel space ends a run at the end of the line
+        for (pos, c) in line.char_indices().chain(Some((line.len(), ' '))) {
+            match (is_addr_char(c), start) {
+                (true, None) => start = Some(pos),
+                (false, Some(from)) => {
+                    if let Some(addr) = addr_in(&line[from..pos]) {
+                        found.push(FoundAddr {
+                            line: idx + 1,
+                            column: from + 1,
+                            addr,
+                        });
+                    }
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs(text: &str) -> Vec<String> {
+        find_addrs(text)
+            .iter()
+            .map(|found| found.addr.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn addresses_are_found_in_context() {
+        assert_eq!(
+            addrs("route 10.0.0.0/8 via 10.1.1.1. Next: fe80::1%eth0, ::1"),
+            vec!["10.0.0.0", "10.1.1.1", "fe80::1", "::1"]
+        );
+        assert_eq!(
+            addrs("http://198.51.100.4:8080/x \"2001:db8::2\""),
+            vec!["198.51.100.4", "2001:db8::2"]
+        );
+        assert!(addrs("version 1.2.3, hash deadbeef, time 12:30:15, 999.1.1.1").is_empty());
+    }
+}
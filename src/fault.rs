@@ -0,0 +1,260 @@
+//! Injecting faults into lookups, for testing how callers cope with them.
+//!
+//! [`FaultInjectingResolver`] wraps another resolver and, at configured
+//! rates, delays lookups, fails them with a chosen error, drops addresses
+//! from answers, or answers with what the name resolved to the time before.
+//! A seed makes a run reproducible, and faults can be switched off and on
+//! while it runs.
+use crate::errors::IpaddrConversionError;
+use crate::resolver::{normalize_name, Resolve};
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// The error an injected failure returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Fault {
+    NotFound,
+    Timeout,
+    /// A failing nameserver, as SERVFAIL is reported
+    DnsError,
+    /// A refused connection to the nameserver
+    IoError,
+}
+
+impl Fault {
+    fn error(&self, name: &str) -> IpaddrConversionError {
+        match self {
+            Fault::NotFound => IpaddrConversionError::NotFound(name.to_string()),
+            Fault::Timeout => IpaddrConversionError::Timeout(name.to_string()),
+            Fault::DnsError => {
+                IpaddrConversionError::DnsError(format!("injected SERVFAIL for {}", name))
+            }
+            Fault::IoError => IpaddrConversionError::IoError(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "injected connection refused",
+            )),
+        }
+    }
+}
+
+/// State shared by clones
+#[derive(Debug)]
+struct Shared {
+    enabled: AtomicBool,
+    rng: Mutex<u64>,
+    /// The previous answer for each name, for stale answers
+    previous: Mutex<HashMap<String, Vec<IpAddr>>>,
+}
+
+/// Resolver injecting faults into the lookups of another resolver.
+///
+/// Each rate is a probability from 0 to 1, drawn separately for each
+/// lookup. Clones share the random sequence and the on/off switch.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::fault::{Fault, FaultInjectingResolver};
+/// use ipaddr_from_str::{IpaddrConversionError, Resolve, StaticResolver};
+///
+/// let resolver = FaultInjectingResolver::new(StaticResolver::new()).with_failures(1.0, Fault::Timeout);
+/// assert!(matches!(resolver.resolve("10.0.0.1"), Err(IpaddrConversionError::Timeout(_))));
+/// resolver.set_enabled(false);
+/// assert!(resolver.resolve("10.0.0.1").is_ok());
+/// ```
+#[derive(Debug, Clone)]
+pub struct FaultInjectingResolver<R> {
+    resolver: R,
+    delay: Option<(f64, Duration)>,
+    failure: Option<(f64, Fault)>,
+    drop_rate: f64,
+    stale_rate: f64,
+    shared: Arc<Shared>,
+}
+
+impl<R: Resolve> FaultInjectingResolver<R> {
+    /// Wrap `resolver`, injecting nothing until faults are configured
+    pub fn new(resolver: R) -> Self {
+        Self {
+            resolver,
+            delay: None,
+            failure: None,
+            drop_rate: 0.0,
+            stale_rate: 0.0,
+            shared: Arc::new(Shared {
+                enabled: AtomicBool::new(true),
+                rng: Mutex::new(crate::direct::random_u64() | 1),
+                previous: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Draw faults from a sequence seeded with `seed`, so a run can be
+    /// repeated, returning the updated resolver
+    pub fn with_seed(self, seed: u64) -> Self {
+        // xorshift never leaves zero
+        *self.shared.rng.lock().unwrap_or_else(|p| p.into_inner()) = seed | 1;
+        self
+    }
+
+    /// Delay a `rate` of lookups by `delay` before they start, returning the
+    /// updated resolver
+    pub fn with_delay(mut self, rate: f64, delay: Duration) -> Self {
+        self.delay = Some((rate, delay));
+        self
+    }
+
+    /// Fail a `rate` of lookups with `fault`, without consulting the wrapped
+    /// resolver, returning the updated resolver
+    pub fn with_failures(mut self, rate: f64, fault: Fault) -> Self {
+        self.failure = Some((rate, fault));
+        self
+    }
+
+    /// Drop each address of an answer with probability `rate`, returning the
+    /// updated resolver. An answer left with no addresses fails with
+    /// NotFound.
+    pub fn with_dropped_addrs(mut self, rate: f64) -> Self {
+        self.drop_rate = rate;
+        self
+    }
+
+    /// Answer a `rate` of lookups with the previous answer for the name, if
+    /// there was one, as a cache past its TTL would, returning the updated
+    /// resolver
+    pub fn with_stale_answers(mut self, rate: f64) -> Self {
+        self.stale_rate = rate;
+        self
+    }
+
+    /// Switch injection off or back on, for every clone
+    pub fn set_enabled(&self, enabled: bool) {
+        self.shared.enabled.store(enabled, Ordering::SeqCst);
+    }
+
+    /// Whether faults are being injected
+    pub fn is_enabled(&self) -> bool {
+        self.shared.enabled.load(Ordering::SeqCst)
+    }
+
+    /// Whether an event of probability `rate` happens this time
+    fn roll(&self, rate: f64) -> bool {
+        if rate <= 0.0 {
+            return false;
+        }
+        let mut state = self.shared.rng.lock().unwrap_or_else(|p| p.into_inner());
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        // the top 53 bits, as a fraction in [0, 1)
+        ((*state >> 11) as f64 / (1u64 << 53) as f64) < rate
+    }
+}
+
+impl<R: Resolve> Resolve for FaultInjectingResolver<R> {
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        if !self.is_enabled() {
+            return self.resolver.resolve(hostname_or_address);
+        }
+        if let Some((rate, delay)) = self.delay {
+            if self.roll(rate) {
+                thread::sleep(delay);
+            }
+        }
+        if let Some((rate, fault)) = self.failure {
+            if self.roll(rate) {
+                return Err(fault.error(hostname_or_address));
+            }
+        }
+        let name = normalize_name(hostname_or_address);
+        if self.roll(self.stale_rate) {
+            let previous = self
+                .shared
+                .previous
+                .lock()
+                .unwrap_or_else(|p| p.into_inner());
+            if let Some(addrs) = previous.get(&name) {
+                return Ok(addrs.clone());
+            }
+        }
+        let addrs = self.resolver.resolve(hostname_or_address)?;
+        if self.stale_rate > 0.0 {
+            self.shared
+                .previous
+                .lock()
+                .unwrap_or_else(|p| p.into_inner())
+                .insert(name, addrs.clone());
+        }
+        if self.drop_rate <= 0.0 {
+            return Ok(addrs);
+        }
+        let kept: Vec<IpAddr> = addrs
+            .into_iter()
+            .filter(|_| !self.roll(self.drop_rate))
+            .collect();
+        if kept.is_empty() {
+            return Err(Fault::NotFound.error(hostname_or_address));
+        }
+        Ok(kept)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::StaticResolver;
+    use std::sync::atomic::AtomicU8;
+    use std::time::Instant;
+
+    /// Answers 10.0.0.n for the nth lookup
+    struct Counting(AtomicU8);
+
+    impl Resolve for Counting {
+        fn resolve(&self, _: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+            let n = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(vec![IpAddr::from([10, 0, 0, n])])
+        }
+    }
+
+    #[test]
+    fn seeded_runs_repeat() {
+        let addrs: Vec<IpAddr> = (1..=8).map(|n| IpAddr::from([10, 0, 0, n])).collect();
+        let inner = StaticResolver::new().with_entry("pool.internal", addrs);
+        let run = |seed| {
+            let resolver = FaultInjectingResolver::new(inner.clone())
+                .with_seed(seed)
+                .with_dropped_addrs(0.5)
+                .with_failures(0.2, Fault::DnsError);
+            (0..20)
+                .map(|_| resolver.resolve("pool.internal").ok())
+                .collect::<Vec<_>>()
+        };
+        let first = run(7);
+        assert_eq!(first, run(7));
+        assert!(first.iter().any(Option::is_none));
+        assert!(first.iter().flatten().any(|addrs| addrs.len() < 8));
+    }
+    #[test]
+    fn stale_answers_repeat_the_previous_one() {
+        let resolver =
+            FaultInjectingResolver::new(Counting(AtomicU8::new(0))).with_stale_answers(1.0);
+        let first = resolver.resolve("svc.internal").unwrap();
+        assert_eq!(resolver.resolve("svc.internal").unwrap(), first);
+        resolver.set_enabled(false);
+        assert_ne!(resolver.resolve("svc.internal").unwrap(), first);
+    }
+    #[test]
+    fn delays_apply_before_lookup() {
+        let resolver = FaultInjectingResolver::new(StaticResolver::new())
+            .with_delay(1.0, Duration::from_millis(30))
+            .with_failures(0.0, Fault::IoError);
+        let started = Instant::now();
+        assert!(resolver.resolve("127.0.0.1").is_ok());
+        assert!(started.elapsed() >= Duration::from_millis(30));
+    }
+}
@@ -0,0 +1,360 @@
+//! Reading threat intelligence feeds of bad names and addresses.
+//!
+//! Indicator feeds come as plain lists, as CSV exports with the indicator
+//! in one column, or as STIX, whose patterns such as
+//! `[ipv4-addr:value = '198.51.100.7']` name them. A [`Feed`] reads any of
+//! these into [`Indicator`]s, refanging the `evil[.]example` form analysts
+//! write, and turns them into a [`Policy`] rejecting them or a [`Screen`]
+//! for cheap pre-filtering. Only the `ipv4-addr`, `ipv6-addr` and
+//! `domain-name` values of STIX patterns are read, from a bundle or from
+//! patterns one per line; other observables are skipped.
+use crate::cidr::Cidr;
+use crate::errors::IpaddrConversionError;
+use crate::host::check_hostname;
+use crate::policy::{Action, Condition, Policy, Rule};
+use crate::resolver::normalize_name;
+use crate::screen::Screen;
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+/// What a feed lists
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Indicator {
+    /// An address, as a block of one, or a block of them
+    Block(Cidr),
+    /// A domain, and every name under it
+    Domain(String),
+}
+
+impl FromStr for Indicator {
+    type Err = IpaddrConversionError;
+
+    /// Parse an address, block or domain, refanged
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.trim().replace("[.]", ".").replace("[:]", ":");
+        if let Ok(addr) = text.parse::<IpAddr>() {
+            let width = if addr.is_ipv4() { 32 } else { 128 };
+            return Ok(Indicator::Block(
+                Cidr::new(addr, width).expect("full width prefix"),
+            ));
+        }
+        if let Ok(block) = text.parse::<Cidr>() {
+            return Ok(Indicator::Block(block));
+        }
+        match check_hostname(&text, 0) {
+            Ok(()) => Ok(Indicator::Domain(normalize_name(&text))),
+            Err(_) => Err(IpaddrConversionError::InvalidInput(format!(
+                "indicator '{}' is not an address, block or domain",
+                text
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Indicator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Indicator::Block(ref block) => write!(f, "{}", block),
+            Indicator::Domain(ref domain) => write!(f, "{}", domain),
+        }
+    }
+}
+
+/// The indicators of a feed, in the order listed.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::feed::Feed;
+/// use ipaddr_from_str::policy::PolicyResolver;
+/// use ipaddr_from_str::{IpaddrConversionError, Resolve, StaticResolver};
+///
+/// let feed = Feed::parse_csv(
+///     "first_seen,indicator,type\n\
+///      2024-01-02,evil[.]example,domain\n\
+///      2024-01-03,\"203.0.113.0/24\",network\n",
+///     "indicator",
+/// )
+/// .unwrap();
+/// assert_eq!(feed.len(), 2);
+/// let backend = StaticResolver::new()
+///     .with_entry("cdn.evil.example", vec!["192.0.2.1".parse().unwrap()])
+///     .with_entry("app.example", vec!["203.0.113.9".parse().unwrap()]);
+/// let resolver = PolicyResolver::new(backend, feed.to_policy());
+/// assert!(matches!(
+///     resolver.resolve("cdn.evil.example"),
+///     Err(IpaddrConversionError::Blocked(_))
+/// ));
+/// assert!(matches!(
+///     resolver.resolve("app.example"),
+///     Err(IpaddrConversionError::Blocked(_))
+/// ));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Feed {
+    indicators: Vec<Indicator>,
+}
+
+impl Feed {
+    /// A feed of `indicators`
+    pub fn new(indicators: Vec<Indicator>) -> Self {
+        Self { indicators }
+    }
+
+    /// Parse a list with an indicator per line. Blank lines and `#` or `;`
+    /// comments are skipped, and of a line of several fields, such as a
+    /// hosts file's `0.0.0.0 evil.example`, the last is taken.
+    ///
+    /// # Returns
+    /// The feed, or an InvalidInput error naming the first bad line
+    pub fn parse_list(text: &str) -> Result<Self, IpaddrConversionError> {
+        let mut indicators = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split(['#', ';']).next().unwrap_or("");
+            if let Some(field) = line.split_whitespace().last() {
+                indicators.push(on_line(number, field.parse())?);
+            }
+        }
+        Ok(Self::new(indicators))
+    }
+
+    /// Parse a CSV export, taking the indicator from the column headed
+    /// `column`, ignoring case. The header is the first line, or the last
+    /// `#` comment before the data, as some feeds write it. Rows with an
+    /// empty indicator are skipped.
+    ///
+    /// # Returns
+    /// The feed, or an InvalidInput error if there is no such column or
+    /// naming the first bad row
+    pub fn parse_csv(text: &str, column: &str) -> Result<Self, IpaddrConversionError> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .filter(|(_, l)| !l.trim().is_empty());
+        let mut commented = None;
+        let mut index = None;
+        let mut indicators = Vec::new();
+        for (number, line) in lines.by_ref() {
+            if let Some(comment) = line.strip_prefix('#') {
+                commented = Some(comment);
+                continue;
+            }
+            let position = |header: &str| {
+                csv_fields(header)
+                    .iter()
+                    .position(|name| name.trim().eq_ignore_ascii_case(column))
+            };
+            match commented.and_then(position) {
+                Some(found) => {
+                    index = Some(found);
+                    add_csv_row(&mut indicators, number, line, found)?;
+                }
+                None => index = position(line),
+            }
+            break;
+        }
+        let index = index.ok_or_else(|| {
+            IpaddrConversionError::InvalidInput(format!("feed has no column '{}'", column))
+        })?;
+        for (number, line) in lines.filter(|(_, l)| !l.starts_with('#')) {
+            add_csv_row(&mut indicators, number, line, index)?;
+        }
+        Ok(Self::new(indicators))
+    }
+
+    /// Parse the `ipv4-addr`, `ipv6-addr` and `domain-name` values of the
+    /// STIX patterns in `text`, a bundle or patterns one per line
+    ///
+    /// # Returns
+    /// The feed, or an InvalidInput error naming the first bad value
+    pub fn parse_stix(text: &str) -> Result<Self, IpaddrConversionError> {
+        let mut indicators = Vec::new();
+        let mut rest = text;
+        while let Some(start) = rest.find(":value") {
+            let object = rest[..start]
+                .rsplit(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+                .next()
+                .unwrap_or("");
+            rest = &rest[start + ":value".len()..];
+            if !matches!(object, "ipv4-addr" | "ipv6-addr" | "domain-name") {
+                continue;
+            }
+            // `= '...'`, single quotes needing no escape inside JSON
+            let after = rest
+                .trim_start()
+                .strip_prefix('=')
+                .unwrap_or("")
+                .trim_start();
+            let value = match after.strip_prefix('\'') {
+                Some(quoted) => quoted.split('\'').next().unwrap_or(""),
+                None => continue,
+            };
+            indicators.push(value.parse()?);
+        }
+        Ok(Self::new(indicators))
+    }
+
+    /// Read the feed at `path`, as a STIX bundle if it has a `.json`
+    /// extension and as a list otherwise
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, IpaddrConversionError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => Self::parse_stix(&text),
+            _ => Self::parse_list(&text),
+        }
+    }
+
+    /// The indicators, in the order listed
+    pub fn indicators(&self) -> &[Indicator] {
+        &self.indicators
+    }
+
+    /// Number of indicators
+    pub fn len(&self) -> usize {
+        self.indicators.len()
+    }
+
+    /// Whether the feed lists nothing
+    pub fn is_empty(&self) -> bool {
+        self.indicators.is_empty()
+    }
+
+    /// Add the indicators of `other` after these, returning the updated feed
+    pub fn merge(mut self, other: Feed) -> Self {
+        self.indicators.extend(other.indicators);
+        self
+    }
+
+    /// A policy rejecting every name under a listed domain and every address
+    /// in a listed block
+    pub fn to_policy(&self) -> Policy {
+        let mut rules = Vec::new();
+        for indicator in self.indicators.iter() {
+            let conditions = match *indicator {
+                Indicator::Domain(ref domain) => vec![
+                    Condition::HostMatches(domain.clone()),
+                    Condition::HostMatches(format!("*.{}", domain)),
+                ],
+                Indicator::Block(block) => vec![Condition::IpIn(block)],
+            };
+            rules.extend(conditions.into_iter().map(|condition| Rule {
+                condition,
+                action: Action::Reject,
+            }));
+        }
+        Policy::new(rules)
+    }
+
+    /// A screen of the domains and single addresses listed, sized for
+    /// `false_positive_rate`. Wider blocks cannot be screened and are left
+    /// to [`to_policy`](Self::to_policy).
+    pub fn to_screen(&self, false_positive_rate: f64) -> Result<Screen, IpaddrConversionError> {
+        let indicators: Vec<String> = self
+            .indicators
+            .iter()
+            .filter_map(|indicator| match *indicator {
+                Indicator::Domain(ref domain) => Some(domain.clone()),
+                Indicator::Block(block) if block.size() == 1 => Some(block.addr().to_string()),
+                Indicator::Block(_) => None,
+            })
+            .collect();
+        Screen::from_indicators(&indicators, false_positive_rate)
+    }
+}
+
+/// `result`, with an error naming the line it came from
+fn on_line<T>(
+    number: usize,
+    result: Result<T, IpaddrConversionError>,
+) -> Result<T, IpaddrConversionError> {
+    result.map_err(|err| match err {
+        IpaddrConversionError::InvalidInput(msg) => {
+            IpaddrConversionError::InvalidInput(format!("feed line {}: {}", number + 1, msg))
+        }
+        err => err,
+    })
+}
+
+fn add_csv_row(
+    indicators: &mut Vec<Indicator>,
+    number: usize,
+    line: &str,
+    index: usize,
+) -> Result<(), IpaddrConversionError> {
+    match csv_fields(line).get(index).map(|field| field.trim()) {
+        Some(field) if !field.is_empty() => {
+            indicators.push(on_line(number, field.parse())?);
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The fields of a CSV line, unquoted
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.trim().chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_skip_comments_and_take_the_last_field() {
+        let feed = Feed::parse_list(
+            "# blocklist\n\
+             0.0.0.0 Tracker.Example\n\
+             198.51.100[.]7 ; defanged\n\
+             \n\
+             2001:db8::/32\n",
+        )
+        .unwrap();
+        let listed: Vec<String> = feed.indicators().iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            listed,
+            ["tracker.example", "198.51.100.7/32", "2001:db8::/32"]
+        );
+        let err = Feed::parse_list("good.example\nbad_name!\n").unwrap_err();
+        assert!(err.to_string().contains("feed line 2"), "{}", err);
+        let screen = feed.to_screen(0.001).unwrap();
+        assert_eq!(screen.len(), 2);
+        assert!(screen.screens_name("a.tracker.example"));
+    }
+    #[test]
+    fn stix_values_of_address_and_domain_patterns_are_read() {
+        let bundle = r#"{"type": "bundle", "objects": [
+            {"type": "indicator", "pattern": "[ipv4-addr:value = '203.0.113.5']"},
+            {"type": "indicator", "pattern": "[domain-name:value = 'evil.example' OR ipv6-addr:value = '2001:db8::66']"},
+            {"type": "indicator", "pattern": "[file:hashes.'SHA-256' = 'abcd'] AND [url:value = 'http://x.example/']"}
+        ]}"#;
+        let feed = Feed::parse_stix(bundle).unwrap();
+        let listed: Vec<String> = feed.indicators().iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            listed,
+            ["203.0.113.5/32", "evil.example", "2001:db8::66/128"]
+        );
+        let csv = Feed::parse_csv("# \"id\",\"dst_ip\"\n\"1\",\"192.0.2.1\"\n", "DST_IP").unwrap();
+        assert_eq!(csv.len(), 1);
+        assert!(Feed::parse_csv("a,b\n1,2\n", "indicator").is_err());
+    }
+}
@@ -0,0 +1,512 @@
+//! Telling what answers on the open ports of a host.
+//!
+//! [`fingerprint`] connects to each open port of a [`PortScan`] and keeps
+//! what the service says first, or says back to an HTTP request where it
+//! waits to be spoken to, then names the service and product from it and
+//! guesses the operating system from the products' version strings. With
+//! the `dot` feature the certificate of each TLS port is captured too.
+//! [`inventory`] runs resolution, scan and fingerprinting together, for one
+//! report per address of a host. Enabled by the `probe` feature.
+use crate::digest::{hex, sha256};
+use crate::errors::IpaddrConversionError;
+use crate::portscan::{scan_ports_within, PortScan};
+use crate::resolver::Resolve;
+use crate::tls::{certificate_names, CertificateNames};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// The most of a banner kept
+pub const MAX_BANNER_LEN: usize = 512;
+
+/// Sent to services which wait for the client to speak first
+const HTTP_PROBE: &[u8] = b"HEAD / HTTP/1.0\r\n\r\n";
+
+/// Ports whose services speak TLS from the start
+const TLS_PORTS: &[u16] = &[443, 465, 636, 853, 993, 995, 5061, 6443, 8443];
+
+/// Services known by how their banners start
+const SERVICES: &[(&str, &str)] = &[
+    ("SSH-", "ssh"),
+    ("HTTP/", "http"),
+    ("+OK", "pop3"),
+    ("* OK", "imap"),
+    ("RFB ", "vnc"),
+    ("AMQP", "amqp"),
+];
+
+/// Operating systems named in the version strings of their packages
+const SYSTEMS: &[(&str, &str)] = &[
+    ("ubuntu", "Ubuntu"),
+    ("debian", "Debian"),
+    ("raspbian", "Debian"),
+    ("centos", "CentOS"),
+    ("red hat", "Red Hat"),
+    ("rhel", "Red Hat"),
+    ("fedora", "Fedora"),
+    ("alpine", "Alpine"),
+    ("suse", "SUSE"),
+    ("freebsd", "FreeBSD"),
+    ("openbsd", "OpenBSD"),
+    ("netbsd", "NetBSD"),
+    ("darwin", "macOS"),
+    ("microsoft", "Windows"),
+    ("win32", "Windows"),
+    ("win64", "Windows"),
+    ("windows", "Windows"),
+];
+
+/// How services are fingerprinted
+#[derive(Debug, Clone, PartialEq)]
+pub struct FingerprintConfig {
+    timeout: Duration,
+    concurrency: usize,
+    rate_limit: Option<f64>,
+    http_probe: bool,
+    tls_ports: Vec<u16>,
+}
+
+impl Default for FingerprintConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(2),
+            concurrency: 8,
+            rate_limit: None,
+            http_probe: true,
+            tls_ports: TLS_PORTS.to_vec(),
+        }
+    }
+}
+
+impl FingerprintConfig {
+    /// Wait 2 seconds for each answer, fingerprint 8 ports at once, send an
+    /// HTTP request to silent services and capture certificates on the
+    /// usual TLS ports by default
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set how long each connection and answer is waited for, returning the
+    /// updated config
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set how many connections are made at once, returning the updated
+    /// config
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Set the most connections an [`inventory`] scan starts a second,
+    /// returning the updated config
+    pub fn with_rate_limit(mut self, rate_limit: Option<f64>) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    /// Set whether services which say nothing are sent an HTTP request,
+    /// returning the updated config
+    pub fn with_http_probe(mut self, http_probe: bool) -> Self {
+        self.http_probe = http_probe;
+        self
+    }
+
+    /// Set the ports whose certificates are captured, returning the updated
+    /// config. Certificates are only captured with the `dot` feature.
+    pub fn with_tls_ports(mut self, tls_ports: Vec<u16>) -> Self {
+        self.tls_ports = tls_ports;
+        self
+    }
+}
+
+/// A certificate a TLS service presented
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Certificate {
+    /// The SHA-256 of the DER encoded certificate, as hex
+    pub sha256: String,
+    pub names: CertificateNames,
+}
+
+impl Certificate {
+    /// The certificate encoded as `der`
+    pub fn from_der(der: &[u8]) -> Self {
+        Self {
+            sha256: hex(&sha256(der)),
+            names: certificate_names(der).unwrap_or_default(),
+        }
+    }
+}
+
+/// What was learned of the service on a port
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Service {
+    pub port: u16,
+    /// The protocol spoken, such as `ssh` or `smtp`
+    pub name: Option<&'static str>,
+    /// The software answering, with its version where it gave one
+    pub product: Option<String>,
+    /// What the service said, with unprintable characters replaced
+    pub banner: Option<String>,
+    pub certificate: Option<Certificate>,
+}
+
+/// The services of a host, and the operating system they hint at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostFingerprint {
+    pub addr: IpAddr,
+    /// A service for every open port, in port order
+    pub services: Vec<Service>,
+    /// The operating system named by a service's product, if any
+    pub os: Option<&'static str>,
+}
+
+/// The scan and fingerprint of one address of a host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostReport {
+    pub scan: PortScan,
+    pub fingerprint: HostFingerprint,
+}
+
+/// `data` as text, without unprintable characters or trailing blank lines
+fn printable(data: &[u8]) -> String {
+    String::from_utf8_lossy(data)
+        .lines()
+        .map(|line| {
+            line.chars()
+                .map(|c| if c.is_control() { '.' } else { c })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim_end()
+        .to_string()
+}
+
+/// The service and product a banner names
+fn identify(banner: &str) -> (Option<&'static str>, Option<String>) {
+    let first = banner.lines().next().unwrap_or_default();
+    let name = SERVICES
+        .iter()
+        .find(|(prefix, _)| first.starts_with(prefix))
+        .map(|(_, name)| *name)
+        .or_else(|| match first.get(..4) {
+            Some("220 ") | Some("220-") if first.contains("FTP") => Some("ftp"),
+            Some("220 ") | Some("220-") => Some("smtp"),
+            _ => None,
+        });
+    let product = match name {
+        // SSH-2.0-OpenSSH_8.9p1 Ubuntu-3ubuntu0.1
+        Some("ssh") => first.splitn(3, '-').nth(2).map(str::to_string),
+        Some("http") => banner.lines().find_map(|line| {
+            let (header, value) = line.split_once(':')?;
+            Some(value.trim().to_string()).filter(|_| header.eq_ignore_ascii_case("server"))
+        }),
+        Some(_) => first
+            .get(4..)
+            .map(str::trim)
+            .filter(|rest| !rest.is_empty())
+            .map(str::to_string),
+        None => None,
+    };
+    (name, product.filter(|product| !product.is_empty()))
+}
+
+/// The operating system named in `text`, if any
+fn operating_system(text: &str) -> Option<&'static str> {
+    let text = text.to_ascii_lowercase();
+    SYSTEMS
+        .iter()
+        .find(|(needle, _)| text.contains(needle))
+        .map(|(_, system)| *system)
+}
+
+/// What the service at `port` of `addr` says, first unprompted and then, if
+/// it said nothing, to an HTTP request
+fn banner(addr: IpAddr, port: u16, config: &FingerprintConfig) -> Option<String> {
+    let mut stream =
+        TcpStream::connect_timeout(&SocketAddr::new(addr, port), config.timeout).ok()?;
+    stream.set_read_timeout(Some(config.timeout)).ok()?;
+    stream.set_write_timeout(Some(config.timeout)).ok()?;
+    let mut buf = [0u8; MAX_BANNER_LEN];
+    let len = match stream.read(&mut buf) {
+        Ok(len) => len,
+        Err(err)
+            if config.http_probe
+                && matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+        {
+            stream.write_all(HTTP_PROBE).ok()?;
+            stream.read(&mut buf).ok()?
+        }
+        Err(_) => return None,
+    };
+    Some(printable(&buf[..len])).filter(|banner| !banner.is_empty())
+}
+
+#[cfg(feature = "dot")]
+mod capture {
+    use rustls::client::{ServerCertVerified, ServerCertVerifier};
+    use rustls::{Certificate, ClientConfig, ClientConnection, RootCertStore, ServerName};
+    use std::io;
+    use std::net::{IpAddr, SocketAddr, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, SystemTime};
+
+    /// Accepts any certificate, keeping the server's own
+    #[derive(Default)]
+    struct Recorder {
+        certificate: Mutex<Option<Vec<u8>>>,
+    }
+
+    impl Recorder {
+        fn take(&self) -> Option<Vec<u8>> {
+            self.certificate
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .take()
+        }
+    }
+
+    impl ServerCertVerifier for Recorder {
+        fn verify_server_cert(
+            &self,
+            end_entity: &Certificate,
+            _intermediates: &[Certificate],
+            _server_name: &ServerName,
+            _scts: &mut dyn Iterator<Item = &[u8]>,
+            _ocsp_response: &[u8],
+            _now: SystemTime,
+        ) -> Result<ServerCertVerified, rustls::Error> {
+            *self
+                .certificate
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(end_entity.0.clone());
+            Ok(ServerCertVerified::assertion())
+        }
+    }
+
+    /// The DER encoded certificate the TLS service at `port` of `addr`
+    /// presents
+    pub(super) fn peer_certificate(
+        addr: IpAddr,
+        port: u16,
+        timeout: Duration,
+    ) -> io::Result<Option<Vec<u8>>> {
+        let recorder = Arc::new(Recorder::default());
+        let mut config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(RootCertStore::empty())
+            .with_no_client_auth();
+        config
+            .dangerous()
+            .set_certificate_verifier(recorder.clone());
+        let mut tcp = TcpStream::connect_timeout(&SocketAddr::new(addr, port), timeout)?;
+        tcp.set_read_timeout(Some(timeout))?;
+        tcp.set_write_timeout(Some(timeout))?;
+        let mut connection = ClientConnection::new(Arc::new(config), ServerName::IpAddress(addr))
+            .map_err(io::Error::other)?;
+        // the certificate is verified, and so kept, partway through
+        while connection.is_handshaking() {
+            if connection.complete_io(&mut tcp).is_err() {
+                break;
+            }
+        }
+        Ok(recorder.take())
+    }
+}
+
+/// The certificate of the TLS service at `port` of `addr`
+#[cfg(feature = "dot")]
+fn certificate(addr: IpAddr, port: u16, config: &FingerprintConfig) -> Option<Certificate> {
+    capture::peer_certificate(addr, port, config.timeout)
+        .ok()
+        .flatten()
+        .map(|der| Certificate::from_der(&der))
+}
+
+/// Certificates are only captured with the `dot` feature
+#[cfg(not(feature = "dot"))]
+fn certificate(_addr: IpAddr, _port: u16, _config: &FingerprintConfig) -> Option<Certificate> {
+    None
+}
+
+fn service(addr: IpAddr, port: u16, config: &FingerprintConfig) -> Service {
+    let certificate = Some(port)
+        .filter(|port| config.tls_ports.contains(port))
+        .and_then(|port| certificate(addr, port, config));
+    if certificate.is_some() {
+        return Service {
+            port,
+            name: Some("tls"),
+            product: None,
+            banner: None,
+            certificate,
+        };
+    }
+    let banner = banner(addr, port, config);
+    let (name, product) = banner.as_deref().map(identify).unwrap_or_default();
+    Service {
+        port,
+        name,
+        product,
+        banner,
+        certificate: None,
+    }
+}
+
+/// Fingerprint the services on the open ports of `scan`
+///
+/// # Parameters
+///
+/// * `scan` - a scan of the host, whose open ports are connected to
+/// * `config` - how the services are fingerprinted
+///
+/// # Returns
+/// What each service said and is taken to be, and the operating system the
+/// first product naming one hints at
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::fingerprint::{fingerprint, FingerprintConfig};
+/// use ipaddr_from_str::portscan::scan_ports;
+///
+/// let scan = scan_ports("192.0.2.10".parse().unwrap(), &[1..=1024], 32, None).unwrap();
+/// let host = fingerprint(&scan, &FingerprintConfig::new());
+/// for service in host.services {
+///     println!("{} {:?} {:?}", service.port, service.name, service.product);
+/// }
+/// ```
+pub fn fingerprint(scan: &PortScan, config: &FingerprintConfig) -> HostFingerprint {
+    let ports = scan.open();
+    let next = AtomicUsize::new(0);
+    let services = Mutex::new(Vec::with_capacity(ports.len()));
+    thread::scope(|scope| {
+        for _ in 0..config.concurrency.clamp(1, ports.len().max(1)) {
+            scope.spawn(|| {
+                while let Some(&port) = ports.get(next.fetch_add(1, Ordering::SeqCst)) {
+                    let service = service(scan.addr, port, config);
+                    services
+                        .lock()
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .push(service);
+                }
+            });
+        }
+    });
+    let mut services = services
+        .into_inner()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    services.sort_by_key(|service| service.port);
+    let os = services
+        .iter()
+        .filter_map(|service| service.product.as_deref())
+        .find_map(operating_system);
+    HostFingerprint {
+        addr: scan.addr,
+        services,
+        os,
+    }
+}
+
+/// Resolve `host`, then scan and fingerprint every address of it
+///
+/// # Parameters
+///
+/// * `resolver` - resolves `host` to the addresses scanned
+/// * `host` - the name or address to inventory
+/// * `ranges` - the ports scanned
+/// * `config` - the concurrency, rate limit and timeout of the scans, and how
+///   the services found are fingerprinted
+///
+/// # Returns
+/// A report for each address, in the order resolved, or the error resolving
+/// the host or starting a scan
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::fingerprint::{inventory, FingerprintConfig};
+/// use ipaddr_from_str::SystemResolver;
+///
+/// let config = FingerprintConfig::new().with_rate_limit(Some(200.0));
+/// for report in inventory(&SystemResolver, "db.internal", &[1..=1024], &config).unwrap() {
+///     println!("{}: {:?}", report.scan.addr, report.fingerprint.os);
+/// }
+/// ```
+pub fn inventory<R: Resolve + ?Sized>(
+    resolver: &R,
+    host: &str,
+    ranges: &[RangeInclusive<u16>],
+    config: &FingerprintConfig,
+) -> Result<Vec<HostReport>, IpaddrConversionError> {
+    resolver
+        .resolve(host)?
+        .into_iter()
+        .map(|addr| {
+            let scan = scan_ports_within(
+                addr,
+                ranges,
+                config.concurrency,
+                config.rate_limit,
+                config.timeout,
+            )?;
+            let fingerprint = fingerprint(&scan, config);
+            Ok(HostReport { scan, fingerprint })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::StaticResolver;
+    use std::net::TcpListener;
+
+    #[test]
+    fn banners_name_services_and_systems() {
+        let (name, product) = identify("SSH-2.0-OpenSSH_8.9p1 Ubuntu-3ubuntu0.1");
+        assert_eq!(name, Some("ssh"));
+        assert_eq!(product.as_deref(), Some("OpenSSH_8.9p1 Ubuntu-3ubuntu0.1"));
+        assert_eq!(operating_system(&product.unwrap()), Some("Ubuntu"));
+        let (name, product) = identify("HTTP/1.1 200 OK\nServer: Microsoft-IIS/10.0");
+        assert_eq!(name, Some("http"));
+        assert_eq!(operating_system(&product.unwrap()), Some("Windows"));
+        assert_eq!(identify("220 ftp.example.com FTP ready").0, Some("ftp"));
+        assert_eq!(identify("\u{0}\u{1}").0, None);
+        assert_eq!(printable(b"220 mail\r\n\x07\r\n"), "220 mail\n.");
+    }
+    #[test]
+    fn inventories_fingerprint_open_ports() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            // one connection to scan, then one to fingerprint
+            for stream in listener.incoming().take(2) {
+                let _ = stream
+                    .unwrap()
+                    .write_all(b"SSH-2.0-OpenSSH_9.6 FreeBSD-20240104\r\n");
+            }
+        });
+        let resolver =
+            StaticResolver::new().with_entry("box.test", vec!["127.0.0.1".parse().unwrap()]);
+        let config = FingerprintConfig::new().with_timeout(Duration::from_secs(1));
+        let mut reports = inventory(&resolver, "box.test", &[port..=port], &config).unwrap();
+        server.join().unwrap();
+        let report = reports.remove(0);
+        assert_eq!(report.scan.open(), vec![port]);
+        let service = &report.fingerprint.services[0];
+        assert_eq!(service.name, Some("ssh"));
+        assert_eq!(
+            service.banner.as_deref(),
+            Some("SSH-2.0-OpenSSH_9.6 FreeBSD-20240104")
+        );
+        assert_eq!(report.fingerprint.os, Some("FreeBSD"));
+    }
+}
@@ -0,0 +1,511 @@
+//! Asking the local gateway for its external address.
+//!
+//! A home or office router knows the address it translates to, and will
+//! say so over NAT-PMP (RFC 6886), its successor PCP (RFC 6887), or UPnP
+//! IGD, without a round trip to the internet. [`external_address`] tries
+//! each in turn against the default gateway. Behind carrier-grade NAT the
+//! router's own external address is private too, so a
+//! [`public_ip`](crate::public_ip) source outside is still the last word.
+use crate::deadline;
+use crate::errors::IpaddrConversionError;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// The port NAT-PMP and PCP servers listen on
+pub const NATPMP_PORT: u16 = 5351;
+/// The group and port SSDP discovery is multicast to
+const SSDP_ADDR: (Ipv4Addr, u16) = (Ipv4Addr::new(239, 255, 255, 250), 1900);
+/// The UPnP services which can report the external address
+const WAN_SERVICES: [&str; 2] = [
+    "urn:schemas-upnp-org:service:WANIPConnection:1",
+    "urn:schemas-upnp-org:service:WANPPPConnection:1",
+];
+/// How long a PCP mapping made to learn the address lasts, if deleting it
+/// afterwards fails
+const PCP_LIFETIME: u32 = 30;
+/// Most of an http response read from the gateway
+const MAX_RESPONSE: u64 = 256 * 1024;
+
+/// The protocol a gateway answered over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GatewayProtocol {
+    NatPmp,
+    Pcp,
+    Upnp,
+}
+
+/// The default IPv4 gateway of this host
+#[cfg(target_os = "linux")]
+pub fn default_gateway() -> Result<Ipv4Addr, IpaddrConversionError> {
+    parse_proc_net_route(&std::fs::read_to_string("/proc/net/route")?)
+        .ok_or_else(|| IpaddrConversionError::NotFound("default gateway".to_string()))
+}
+
+/// The default IPv4 gateway of this host
+#[cfg(any(
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd"
+))]
+pub fn default_gateway() -> Result<Ipv4Addr, IpaddrConversionError> {
+    let output = crate::neighbor::command_output("route", &["-n", "get", "default"])?;
+    output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("gateway:")?.trim().parse().ok())
+        .ok_or_else(|| IpaddrConversionError::NotFound("default gateway".to_string()))
+}
+
+/// The default IPv4 gateway of this host; not supported on this platform
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd"
+)))]
+pub fn default_gateway() -> Result<Ipv4Addr, IpaddrConversionError> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "finding the default gateway is not supported on this platform",
+    )
+    .into())
+}
+
+/// The gateway of the default route in Linux's `/proc/net/route`, whose
+/// addresses are hex in host byte order:
+/// `Iface  Destination  Gateway  Flags ...`
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_proc_net_route(contents: &str) -> Option<Ipv4Addr> {
+    // RTF_UP | RTF_GATEWAY
+    const UP_GATEWAY: u32 = 0x0003;
+    contents.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let flags = u32::from_str_radix(fields.get(3)?, 16).ok()?;
+        if *fields.get(1)? != "00000000" || flags & UP_GATEWAY != UP_GATEWAY {
+            return None;
+        }
+        let gateway = u32::from_str_radix(fields.get(2)?, 16).ok()?;
+        Some(Ipv4Addr::from(u32::from_be(gateway)))
+    })
+}
+
+/// Send `request` to `server` and wait for a response `accept` takes,
+/// resending with doubling waits, from 250ms, as RFC 6886 has clients do
+fn exchange<T, F>(
+    socket: &UdpSocket,
+    server: SocketAddr,
+    request: &[u8],
+    timeout: Duration,
+    accept: F,
+) -> Result<T, IpaddrConversionError>
+where
+    F: Fn(&[u8]) -> Option<Result<T, IpaddrConversionError>>,
+{
+    let deadline = deadline::after(timeout);
+    let mut wait = Duration::from_millis(250);
+    let mut response = [0u8; 1100];
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        socket.send_to(request, server)?;
+        let until = Instant::now() + wait.min(left);
+        while let Some(left) = until.checked_duration_since(Instant::now()) {
+            socket.set_read_timeout(Some(left.max(Duration::from_millis(1))))?;
+            match socket.recv_from(&mut response) {
+                Ok((len, from)) if from.ip() == server.ip() => {
+                    if let Some(result) = accept(&response[..len]) {
+                        return result;
+                    }
+                }
+                Ok(_) => {}
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    break
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        wait *= 2;
+    }
+    Err(IpaddrConversionError::Timeout(server.to_string()))
+}
+
+/// The external address in a NAT-PMP public address `response`, None if it
+/// is not one, or an error if the gateway refused
+fn parse_natpmp_response(response: &[u8]) -> Option<Result<Ipv4Addr, IpaddrConversionError>> {
+    if response.len() < 12 || response[0] != 0 || response[1] != 128 {
+        return None;
+    }
+    let result = u16::from_be_bytes([response[2], response[3]]);
+    if result != 0 {
+        return Some(Err(IpaddrConversionError::DnsError(format!(
+            "NAT-PMP request refused with result {}",
+            result
+        ))));
+    }
+    Some(Ok(Ipv4Addr::new(
+        response[8],
+        response[9],
+        response[10],
+        response[11],
+    )))
+}
+
+fn natpmp_query(server: SocketAddr, timeout: Duration) -> Result<Ipv4Addr, IpaddrConversionError> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    // version 0, opcode 0: the public address
+    exchange(&socket, server, &[0, 0], timeout, parse_natpmp_response)
+}
+
+/// Ask `gateway` for its external address over NAT-PMP
+pub fn natpmp_external_address(
+    gateway: Ipv4Addr,
+    timeout: Duration,
+) -> Result<Ipv4Addr, IpaddrConversionError> {
+    natpmp_query((gateway, NATPMP_PORT).into(), timeout)
+}
+
+/// An address as the 16 bytes PCP carries, IPv4 mapped into IPv6
+fn pcp_addr(addr: IpAddr) -> [u8; 16] {
+    match addr {
+        IpAddr::V4(v4) => v4.to_ipv6_mapped().octets(),
+        IpAddr::V6(v6) => v6.octets(),
+    }
+}
+
+/// A PCP MAP request for UDP from `client`, with `nonce` and `lifetime`
+fn pcp_map_request(client: SocketAddr, nonce: &[u8; 12], lifetime: u32) -> Vec<u8> {
+    // version 2, opcode MAP, two reserved bytes
+    let mut request = vec![2, 1, 0, 0];
+    request.extend_from_slice(&lifetime.to_be_bytes());
+    request.extend_from_slice(&pcp_addr(client.ip()));
+    request.extend_from_slice(nonce);
+    // UDP, with three reserved bytes
+    request.extend_from_slice(&[17, 0, 0, 0]);
+    request.extend_from_slice(&client.port().to_be_bytes());
+    // no suggested external port or address
+    request.extend_from_slice(&[0; 2]);
+    request.extend_from_slice(&[0; 16]);
+    request
+}
+
+/// The assigned external address in a PCP MAP `response` to `nonce`
+fn parse_pcp_response(
+    response: &[u8],
+    nonce: &[u8; 12],
+) -> Option<Result<IpAddr, IpaddrConversionError>> {
+    if response.len() < 60
+        || response[0] != 2
+        || response[1] != 0x81
+        || response[24..36] != nonce[..]
+    {
+        return None;
+    }
+    if response[3] != 0 {
+        return Some(Err(IpaddrConversionError::DnsError(format!(
+            "PCP request refused with result {}",
+            response[3]
+        ))));
+    }
+    let mut octets = [0u8; 16];
+    octets.copy_from_slice(&response[44..60]);
+    let addr = Ipv6Addr::from(octets);
+    Some(Ok(match addr.to_ipv4_mapped() {
+        Some(v4) => IpAddr::V4(v4),
+        None => IpAddr::V6(addr),
+    }))
+}
+
+fn pcp_query(server: SocketAddr, timeout: Duration) -> Result<IpAddr, IpaddrConversionError> {
+    // the client address in the request must be the one the server sees
+    let socket = UdpSocket::bind((crate::route::source_ip_for(server.ip())?, 0))?;
+    let client = socket.local_addr()?;
+    let mut nonce = [0u8; 12];
+    nonce[..8].copy_from_slice(&crate::direct::random_u64().to_be_bytes());
+    nonce[8..].copy_from_slice(&(crate::direct::random_u64() as u32).to_be_bytes());
+    let request = pcp_map_request(client, &nonce, PCP_LIFETIME);
+    let addr = exchange(&socket, server, &request, timeout, |response| {
+        parse_pcp_response(response, &nonce)
+    })?;
+    // best effort: the mapping expires by itself anyway
+    let _ = socket.send_to(&pcp_map_request(client, &nonce, 0), server);
+    Ok(addr)
+}
+
+/// Ask `gateway` for its external address over PCP, by making a short-lived
+/// mapping and deleting it again
+pub fn pcp_external_address(
+    gateway: Ipv4Addr,
+    timeout: Duration,
+) -> Result<IpAddr, IpaddrConversionError> {
+    pcp_query((gateway, NATPMP_PORT).into(), timeout)
+}
+
+/// The host, port and path of an `http://` url
+fn split_http_url(url: &str) -> Option<(String, u16, String)> {
+    let rest = url.strip_prefix("http://")?;
+    let (authority, path) = match rest.find('/') {
+        Some(at) => rest.split_at(at),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().ok()?),
+        None => (authority, 80),
+    };
+    Some((host.to_string(), port, path.to_string()))
+}
+
+/// Send `request` to `host`:`port`, a literal address as gateways
+/// advertise, returning the body of a 200 response
+fn http_exchange(
+    host: &str,
+    port: u16,
+    request: &str,
+    timeout: Duration,
+) -> Result<String, IpaddrConversionError> {
+    let ip: IpAddr = host
+        .trim_matches(['[', ']'])
+        .parse()
+        .map_err(|_| IpaddrConversionError::InvalidInput(format!("gateway advertised {}", host)))?;
+    let mut stream = TcpStream::connect_timeout(&SocketAddr::new(ip, port), timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.write_all(request.as_bytes())?;
+    let mut response = Vec::new();
+    stream.take(MAX_RESPONSE).read_to_end(&mut response)?;
+    let text = String::from_utf8_lossy(&response);
+    let (head, body) = text.split_once("\r\n\r\n").unwrap_or((&text, ""));
+    if head.split_whitespace().nth(1) != Some("200") {
+        return Err(IpaddrConversionError::DnsError(format!(
+            "gateway answered {}",
+            head.lines().next().unwrap_or("nothing")
+        )));
+    }
+    Ok(body.to_string())
+}
+
+/// The text of the first `<tag>` element in `xml`, ignoring any namespace
+/// prefix
+fn element<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let mut rest = xml;
+    loop {
+        let start = rest.find('<')?;
+        rest = &rest[start + 1..];
+        let end = rest.find('>')?;
+        let name = rest[..end].split_whitespace().next().unwrap_or("");
+        let local = name.rsplit(':').next().unwrap_or(name);
+        if local == tag {
+            let body = &rest[end + 1..];
+            let close = body.find("</")?;
+            return Some(body[..close].trim());
+        }
+    }
+}
+
+/// The service type and absolute control url of the WAN connection service
+/// in a device description fetched from `location`
+fn wan_control_url(description: &str, location: &str) -> Option<(&'static str, String)> {
+    let (host, port, _) = split_http_url(location)?;
+    description.split("<service>").skip(1).find_map(|service| {
+        let kind = element(service, "serviceType")?;
+        let kind = WAN_SERVICES.iter().find(|known| **known == kind)?;
+        let control = element(service, "controlURL")?;
+        let url = if control.starts_with("http://") {
+            control.to_string()
+        } else {
+            format!(
+                "http://{}:{}/{}",
+                host,
+                port,
+                control.trim_start_matches('/')
+            )
+        };
+        Some((*kind, url))
+    })
+}
+
+/// The LOCATION of the first internet gateway device answering an SSDP
+/// search
+fn ssdp_search(timeout: Duration) -> Result<String, IpaddrConversionError> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_multicast_ttl_v4(2)?;
+    let search = format!(
+        "M-SEARCH * HTTP/1.1\r\nHOST: {}:{}\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\nST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\r\n",
+        SSDP_ADDR.0, SSDP_ADDR.1
+    );
+    socket.send_to(search.as_bytes(), SSDP_ADDR)?;
+    let deadline = deadline::after(timeout);
+    let mut response = [0u8; 2048];
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        socket.set_read_timeout(Some(left.max(Duration::from_millis(1))))?;
+        let len = match socket.recv(&mut response) {
+            Ok(len) => len,
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                break
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let text = String::from_utf8_lossy(&response[..len]);
+        let location = text.lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            if name.trim().eq_ignore_ascii_case("location") {
+                Some(value.trim().to_string())
+            } else {
+                None
+            }
+        });
+        if let Some(location) = location {
+            return Ok(location);
+        }
+    }
+    Err(IpaddrConversionError::Timeout(
+        "SSDP search for an internet gateway".to_string(),
+    ))
+}
+
+/// Ask the internet gateway device found by SSDP for its external address
+/// over UPnP IGD
+pub fn upnp_external_address(timeout: Duration) -> Result<IpAddr, IpaddrConversionError> {
+    let location = ssdp_search(timeout)?;
+    let (host, port, path) = split_http_url(&location).ok_or_else(|| {
+        IpaddrConversionError::InvalidInput(format!("unusable gateway location {}", location))
+    })?;
+    let description = http_exchange(
+        &host,
+        port,
+        &format!("GET {} HTTP/1.0\r\nHost: {}:{}\r\n\r\n", path, host, port),
+        timeout,
+    )?;
+    let (service, control) = wan_control_url(&description, &location).ok_or_else(|| {
+        IpaddrConversionError::NotFound(format!("WAN connection service at {}", location))
+    })?;
+    let (host, port, path) = split_http_url(&control).ok_or_else(|| {
+        IpaddrConversionError::InvalidInput(format!("unusable control url {}", control))
+    })?;
+    let envelope = format!(
+        "<?xml version=\"1.0\"?>\r\n<s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\"><s:Body><u:GetExternalIPAddress xmlns:u=\"{}\"></u:GetExternalIPAddress></s:Body></s:Envelope>\r\n",
+        service
+    );
+    let request = format!(
+        "POST {} HTTP/1.0\r\nHost: {}:{}\r\nContent-Type: text/xml; charset=\"utf-8\"\r\nSOAPAction: \"{}#GetExternalIPAddress\"\r\nContent-Length: {}\r\n\r\n{}",
+        path,
+        host,
+        port,
+        service,
+        envelope.len(),
+        envelope
+    );
+    let response = http_exchange(&host, port, &request, timeout)?;
+    let addr = element(&response, "NewExternalIPAddress").ok_or_else(|| {
+        IpaddrConversionError::DnsError("the gateway gave no external address".to_string())
+    })?;
+    addr.parse().map_err(|_| {
+        IpaddrConversionError::DnsError(format!("the gateway gave {} as its address", addr))
+    })
+}
+
+/// The external address of the default gateway, asking over NAT-PMP, then
+/// PCP, then UPnP IGD, giving each `timeout`
+///
+/// # Returns
+/// The address and the protocol which gave it, or the error of the last
+/// protocol tried if none did
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::gateway::external_address;
+/// use std::time::Duration;
+///
+/// let (addr, protocol) = external_address(Duration::from_secs(2)).unwrap();
+/// println!("{:?} says we are {}", protocol, addr);
+/// ```
+pub fn external_address(
+    timeout: Duration,
+) -> Result<(IpAddr, GatewayProtocol), IpaddrConversionError> {
+    let gateway = default_gateway();
+    if let Ok(gateway) = gateway {
+        if let Ok(addr) = natpmp_external_address(gateway, timeout) {
+            return Ok((IpAddr::V4(addr), GatewayProtocol::NatPmp));
+        }
+        if let Ok(addr) = pcp_external_address(gateway, timeout) {
+            return Ok((addr, GatewayProtocol::Pcp));
+        }
+    }
+    upnp_external_address(timeout).map(|addr| (addr, GatewayProtocol::Upnp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn natpmp_is_asked_over_loopback() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut request = [0u8; 16];
+            let (len, peer) = server.recv_from(&mut request).unwrap();
+            assert_eq!(&request[..len], &[0, 0]);
+            let response = [0, 128, 0, 0, 0, 0, 1, 0, 198, 51, 100, 4];
+            server.send_to(&response, peer).unwrap();
+        });
+        let external = natpmp_query(addr, Duration::from_secs(2)).unwrap();
+        assert_eq!(external, Ipv4Addr::new(198, 51, 100, 4));
+        let table = "Iface\tDestination\tGateway \tFlags\n\
+                     eth0\t0002A8C0\t00000000\t0001\n\
+                     eth0\t00000000\t0102A8C0\t0003\n";
+        assert_eq!(
+            parse_proc_net_route(table),
+            Some(Ipv4Addr::new(192, 168, 2, 1))
+        );
+    }
+    #[test]
+    fn pcp_map_responses_are_decoded() {
+        let client: SocketAddr = "192.168.2.10:40000".parse().unwrap();
+        let nonce = [9u8; 12];
+        let request = pcp_map_request(client, &nonce, PCP_LIFETIME);
+        assert_eq!(request.len(), 60);
+        let mut response = request.clone();
+        response[1] = 0x81;
+        response[3] = 0;
+        response[44..60].copy_from_slice(&pcp_addr("203.0.113.20".parse().unwrap()));
+        assert_eq!(
+            parse_pcp_response(&response, &nonce).unwrap().unwrap(),
+            "203.0.113.20".parse::<IpAddr>().unwrap()
+        );
+        assert!(parse_pcp_response(&response, &[0u8; 12]).is_none());
+        response[3] = 8;
+        assert!(parse_pcp_response(&response, &nonce).unwrap().is_err());
+    }
+    #[test]
+    fn upnp_descriptions_are_read() {
+        let description = "<root><device><serviceList>\
+            <service><serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType><controlURL>/l3f</controlURL></service>\
+            <service><serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType><controlURL>/ctl/IPConn</controlURL></service>\
+            </serviceList></device></root>";
+        assert_eq!(
+            wan_control_url(description, "http://192.168.2.1:5000/rootDesc.xml"),
+            Some((
+                WAN_SERVICES[0],
+                "http://192.168.2.1:5000/ctl/IPConn".to_string()
+            ))
+        );
+        let response = "<s:Envelope><s:Body><u:GetExternalIPAddressResponse xmlns:u=\"x\">\
+            <NewExternalIPAddress>203.0.113.30</NewExternalIPAddress>\
+            </u:GetExternalIPAddressResponse></s:Body></s:Envelope>";
+        assert_eq!(
+            element(response, "NewExternalIPAddress"),
+            Some("203.0.113.30")
+        );
+    }
+}
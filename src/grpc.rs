@@ -0,0 +1,296 @@
+//! Name resolution for tonic gRPC channels.
+//!
+//! Enabled by the `tonic` feature. A tonic channel resolves its endpoint
+//! through hyper's own resolver, bypassing the crate's caching, overrides and
+//! policies. [`ResolvingConnector`] connects through a [`Resolve`]
+//! implementation instead, resolving again on every connect, so a channel
+//! which reconnects picks up changed addresses:
+//!
+//! ```no_run
+//! use ipaddr_from_str::grpc;
+//! use ipaddr_from_str::SystemResolver;
+//! use tonic::transport::Endpoint;
+//!
+//! # async fn run() -> Result<(), tonic::transport::Error> {
+//! let endpoint = Endpoint::from_static("http://users.internal:50051");
+//! let channel = grpc::connect(endpoint, SystemResolver).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`SrvDiscovery`] balances a channel across the targets of a SRV record
+//! instead, looking the record up again periodically and adding and removing
+//! endpoints as the targets change.
+use crate::connect::{connect_with, CONNECTION_ATTEMPT_DELAY};
+use crate::deadline;
+use crate::direct::DirectResolver;
+use crate::errors::IpaddrConversionError;
+use crate::resolver::Resolve;
+use std::collections::HashSet;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tonic::transport::{Channel, Endpoint, Uri};
+use tower::discover::Change;
+use tower::Service;
+
+/// How often [`SrvDiscovery`] looks its record up again by default
+pub const DEFAULT_REFRESH: Duration = Duration::from_secs(30);
+
+/// Largest number of changes waiting to be applied to a balanced channel
+const CHANGE_CAPACITY: usize = 64;
+
+/// A connector for `Endpoint::connect_with_connector` which resolves the
+/// endpoint's host through a [`Resolve`] implementation on every connect,
+/// racing the addresses with happy eyeballs.
+///
+/// A uri without a port uses 443 for https and 80 otherwise.
+#[derive(Debug)]
+pub struct ResolvingConnector<R> {
+    resolver: Arc<R>,
+}
+
+impl<R> ResolvingConnector<R> {
+    /// Connect through `resolver`
+    pub fn new(resolver: R) -> Self {
+        Self::from_arc(Arc::new(resolver))
+    }
+
+    /// Connect through an already shared `resolver`
+    pub fn from_arc(resolver: Arc<R>) -> Self {
+        Self { resolver }
+    }
+}
+
+impl<R> Clone for ResolvingConnector<R> {
+    fn clone(&self) -> Self {
+        Self {
+            resolver: self.resolver.clone(),
+        }
+    }
+}
+
+impl<R> Service<Uri> for ResolvingConnector<R>
+where
+    R: Resolve + Send + Sync + 'static,
+{
+    type Response = TcpStream;
+    type Error = IpaddrConversionError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let resolver = self.resolver.clone();
+        Box::pin(async move {
+            let host = uri
+                .host()
+                .ok_or_else(|| IpaddrConversionError::InvalidInput(format!("no host in {}", uri)))?
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string();
+            let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+                Some("https") => 443,
+                _ => 80,
+            });
+            let stream = connect_with(resolver, &host, port, CONNECTION_ATTEMPT_DELAY).await?;
+            stream.set_nodelay(true)?;
+            Ok(stream)
+        })
+    }
+}
+
+/// Connect a channel to `endpoint`, resolving its host through `resolver`
+/// now and whenever the channel reconnects.
+///
+/// # Parameters
+///
+/// * `endpoint` - the endpoint, with whatever timeouts and tls it needs
+/// * `resolver` - the resolver to look the endpoint's host up with
+pub async fn connect<R>(endpoint: Endpoint, resolver: R) -> Result<Channel, tonic::transport::Error>
+where
+    R: Resolve + Send + Sync + 'static,
+{
+    endpoint
+        .connect_with_connector(ResolvingConnector::new(resolver))
+        .await
+}
+
+/// Like [`connect`], but connecting on first use rather than now
+pub fn connect_lazy<R>(endpoint: Endpoint, resolver: R) -> Channel
+where
+    R: Resolve + Send + Sync + 'static,
+{
+    endpoint.connect_with_connector_lazy(ResolvingConnector::new(resolver))
+}
+
+/// Keeps a balanced channel pointed at the targets of a SRV record, such as
+/// `_grpc._tcp.users.internal`.
+///
+/// Each target is resolved through the same [`DirectResolver`], and every
+/// address becomes an endpoint of the channel. Lookups which fail leave the
+/// endpoints as they were.
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::direct::DirectResolver;
+/// use ipaddr_from_str::grpc::SrvDiscovery;
+///
+/// # fn run() -> Result<(), ipaddr_from_str::IpaddrConversionError> {
+/// let channel = SrvDiscovery::new(DirectResolver::from_system()?, "_grpc._tcp.users.internal")
+///     .channel();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct SrvDiscovery {
+    resolver: Arc<DirectResolver>,
+    name: String,
+    scheme: &'static str,
+    refresh: Duration,
+    configure: fn(Endpoint) -> Endpoint,
+}
+
+impl SrvDiscovery {
+    /// Discover the targets of the SRV record `name` through `resolver`
+    pub fn new<N: Into<String>>(resolver: DirectResolver, name: N) -> Self {
+        Self {
+            resolver: Arc::new(resolver),
+            name: name.into(),
+            scheme: "http",
+            refresh: DEFAULT_REFRESH,
+            configure: |endpoint| endpoint,
+        }
+    }
+
+    /// Look the record up again every `refresh`, returning the updated
+    /// discovery
+    pub fn with_refresh(mut self, refresh: Duration) -> Self {
+        self.refresh = refresh;
+        self
+    }
+
+    /// Connect to targets over https, returning the updated discovery. The
+    /// tls config itself is set by [`with_endpoint`](Self::with_endpoint).
+    pub fn with_https(mut self) -> Self {
+        self.scheme = "https";
+        self
+    }
+
+    /// Apply `configure` to each endpoint before it joins the channel, to set
+    /// timeouts or tls, returning the updated discovery
+    pub fn with_endpoint(mut self, configure: fn(Endpoint) -> Endpoint) -> Self {
+        self.configure = configure;
+        self
+    }
+
+    /// A channel balanced across the record's targets, kept up to date by a
+    /// task spawned on the current tokio runtime. The task stops once the
+    /// channel and all its clones are dropped.
+    pub fn channel(self) -> Channel {
+        let (channel, changes) = Channel::balance_channel(CHANGE_CAPACITY);
+        tokio::spawn(async move {
+            let mut current: HashSet<SocketAddr> = HashSet::new();
+            loop {
+                if let Ok(addrs) = self.addrs().await {
+                    for addr in current.difference(&addrs) {
+                        if changes.send(Change::Remove(*addr)).await.is_err() {
+                            return;
+                        }
+                    }
+                    for addr in addrs.difference(&current) {
+                        let endpoint = match self.endpoint(*addr) {
+                            Some(endpoint) => endpoint,
+                            None => continue,
+                        };
+                        if changes.send(Change::Insert(*addr, endpoint)).await.is_err() {
+                            return;
+                        }
+                    }
+                    current = addrs;
+                }
+                tokio::time::sleep(self.refresh).await;
+                if changes.is_closed() {
+                    return;
+                }
+            }
+        });
+        channel
+    }
+
+    /// Every address of every target of the record
+    async fn addrs(&self) -> Result<HashSet<SocketAddr>, IpaddrConversionError> {
+        let resolver = self.resolver.clone();
+        let name = self.name.clone();
+        let targets = tokio::task::spawn_blocking(move || resolver.srv(&name))
+            .await
+            .map_err(|err| IpaddrConversionError::IoError(std::io::Error::other(err)))??;
+        let mut addrs = HashSet::new();
+        for target in targets {
+            // a target which fails to resolve is skipped rather than
+            // emptying the channel
+            if let Ok(ips) = deadline::resolve(self.resolver.clone(), &target.target).await {
+                addrs.extend(ips.into_iter().map(|ip| SocketAddr::new(ip, target.port)));
+            }
+        }
+        Ok(addrs)
+    }
+
+    fn endpoint(&self, addr: SocketAddr) -> Option<Endpoint> {
+        let host = match addr.ip() {
+            IpAddr::V4(ip) => ip.to_string(),
+            IpAddr::V6(ip) => format!("[{}]", ip),
+        };
+        let uri = format!("{}://{}:{}", self.scheme, host, addr.port());
+        Endpoint::from_shared(uri).ok().map(self.configure)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::StaticResolver;
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn connector_resolves_through_the_crate() {
+        block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let resolver = StaticResolver::new()
+                .with_entry("users.internal", vec!["127.0.0.1".parse().unwrap()]);
+            let mut connector = ResolvingConnector::new(resolver);
+            let uri: Uri = format!("http://users.internal:{}", port).parse().unwrap();
+            let stream = connector.call(uri).await.unwrap();
+            assert_eq!(stream.peer_addr().unwrap().port(), port);
+            let missing: Uri = "http://missing.internal:1".parse().unwrap();
+            assert!(connector.call(missing).await.is_err());
+        });
+    }
+    #[test]
+    fn endpoints_bracket_ipv6_addresses() {
+        let discovery = SrvDiscovery::new(
+            DirectResolver::new(Default::default()),
+            "_grpc._tcp.example",
+        )
+        .with_https();
+        let endpoint = discovery
+            .endpoint("[2001:db8::1]:50051".parse().unwrap())
+            .unwrap();
+        assert_eq!(endpoint.uri().to_string(), "https://[2001:db8::1]:50051/");
+    }
+}
@@ -0,0 +1,192 @@
+//! A pure Rust resolver backend built on hickory-resolver.
+//!
+//! Enabled by the `hickory` feature, which also makes [`get_ipaddr`] and
+//! [`SystemResolver`](crate::SystemResolver) resolve through hickory, reading
+//! the system's configuration, rather than through getaddrinfo. The crate's
+//! API is unchanged: errors are mapped onto [`IpaddrConversionError`], with
+//! names which do not exist reported as `NotFound` and timeouts as `Timeout`.
+//!
+//! [`HickoryResolver`] can also be built from a [`ResolvConf`] or from
+//! hickory's own configuration, and resolves asynchronously as well as
+//! through the blocking [`Resolve`] trait.
+//!
+//! [`get_ipaddr`]: crate::get_ipaddr
+use crate::errors::IpaddrConversionError;
+use crate::resolv_conf::ResolvConf;
+use crate::resolver::Resolve;
+use hickory_resolver::config::{
+    NameServerConfig, NameServerConfigGroup, Protocol, ResolverConfig, ResolverOpts,
+};
+use hickory_resolver::error::{ResolveError, ResolveErrorKind};
+use hickory_resolver::{Name, Resolver, TokioAsyncResolver};
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
+
+/// Resolver querying nameservers through hickory.
+///
+/// Clones share the underlying resolvers, and with them hickory's cache.
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::hickory::HickoryResolver;
+/// use ipaddr_from_str::Resolve;
+///
+/// let resolver = HickoryResolver::from_system().unwrap();
+/// let addrs = resolver.resolve("example.com").unwrap();
+/// ```
+#[derive(Clone)]
+pub struct HickoryResolver {
+    blocking: Arc<Resolver>,
+    tokio: TokioAsyncResolver,
+}
+
+impl HickoryResolver {
+    /// Resolver using hickory's `config` and `opts`
+    pub fn new(config: ResolverConfig, opts: ResolverOpts) -> Result<Self, IpaddrConversionError> {
+        let blocking = Resolver::new(config.clone(), opts.clone())?;
+        Ok(Self {
+            blocking: Arc::new(blocking),
+            tokio: TokioAsyncResolver::tokio(config, opts),
+        })
+    }
+
+    /// Resolver configured from the system, as getaddrinfo would be
+    pub fn from_system() -> Result<Self, IpaddrConversionError> {
+        let (config, opts) = hickory_resolver::system_conf::read_system_conf()
+            .map_err(|err| IpaddrConversionError::DnsError(err.to_string()))?;
+        Self::new(config, opts)
+    }
+
+    /// Resolver querying the nameservers of `conf`, with its search list,
+    /// ndots, timeout and attempts
+    pub fn from_resolv_conf(conf: &ResolvConf) -> Result<Self, IpaddrConversionError> {
+        let protocol = if conf.use_vc {
+            Protocol::Tcp
+        } else {
+            Protocol::Udp
+        };
+        let servers: Vec<NameServerConfig> = conf
+            .nameservers
+            .iter()
+            .map(|server| NameServerConfig::new(*server, protocol))
+            .collect();
+        let search = conf
+            .search
+            .iter()
+            .map(|domain| {
+                Name::from_str(domain).map_err(|err| {
+                    IpaddrConversionError::InvalidInput(format!(
+                        "invalid search domain {}: {}",
+                        domain, err
+                    ))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let config = ResolverConfig::from_parts(None, search, NameServerConfigGroup::from(servers));
+        let mut opts = ResolverOpts::default();
+        opts.ndots = conf.ndots;
+        opts.timeout = conf.timeout;
+        opts.attempts = conf.attempts;
+        Self::new(config, opts)
+    }
+
+    /// Resolve `hostname_or_address` without blocking
+    pub async fn resolve_async(
+        &self,
+        hostname_or_address: &str,
+    ) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        let lookup = self
+            .tokio
+            .lookup_ip(hostname_or_address)
+            .await
+            .map_err(|err| map_error(hostname_or_address, err))?;
+        Ok(lookup.iter().collect())
+    }
+
+    /// The underlying async resolver, for hickory's other record lookups
+    pub fn tokio_resolver(&self) -> &TokioAsyncResolver {
+        &self.tokio
+    }
+}
+
+impl std::fmt::Debug for HickoryResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("HickoryResolver").finish_non_exhaustive()
+    }
+}
+
+impl Resolve for HickoryResolver {
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        let lookup = self
+            .blocking
+            .lookup_ip(hostname_or_address)
+            .map_err(|err| map_error(hostname_or_address, err))?;
+        Ok(lookup.iter().collect())
+    }
+}
+
+impl From<ResolveError> for IpaddrConversionError {
+    fn from(err: ResolveError) -> IpaddrConversionError {
+        IpaddrConversionError::DnsError(err.to_string())
+    }
+}
+
+/// Map hickory's error for a lookup of `name` onto the crate's
+fn map_error(name: &str, err: ResolveError) -> IpaddrConversionError {
+    match err.kind() {
+        ResolveErrorKind::NoRecordsFound { .. } => {
+            IpaddrConversionError::NotFound(name.to_string())
+        }
+        ResolveErrorKind::Timeout => IpaddrConversionError::Timeout(name.to_string()),
+        ResolveErrorKind::Io(io) => {
+            IpaddrConversionError::IoError(std::io::Error::new(io.kind(), io.to_string()))
+        }
+        _ => err.into(),
+    }
+}
+
+/// Resolve `hostname` through the system configured hickory resolver, in
+/// place of getaddrinfo
+pub(crate) fn lookup_host(hostname: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+    static SYSTEM: OnceLock<Result<HickoryResolver, String>> = OnceLock::new();
+    let system = SYSTEM.get_or_init(|| HickoryResolver::from_system().map_err(|e| e.to_string()));
+    match *system {
+        Ok(ref resolver) => resolver.resolve(hostname),
+        Err(ref err) => Err(IpaddrConversionError::DnsError(err.clone())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn errors_map_onto_the_crates() {
+        assert!(matches!(
+            map_error("db.example", ResolveErrorKind::Timeout.into()),
+            IpaddrConversionError::Timeout(ref name) if name == "db.example"
+        ));
+        assert!(matches!(
+            map_error("db.example", ResolveError::from("refused")),
+            IpaddrConversionError::DnsError(_)
+        ));
+    }
+    #[test]
+    fn literals_resolve_without_querying() {
+        let conf = ResolvConf {
+            // nothing listens here, so any query would time out
+            nameservers: vec!["127.0.0.1:9".parse().unwrap()],
+            timeout: Duration::from_millis(100),
+            search: vec!["corp.example".into()],
+            ..Default::default()
+        };
+        let resolver = HickoryResolver::from_resolv_conf(&conf).unwrap();
+        assert_eq!(
+            resolver.resolve("2001:db8::1").unwrap(),
+            vec!["2001:db8::1".parse::<IpAddr>().unwrap()]
+        );
+    }
+}
@@ -0,0 +1,198 @@
+//! Latency histograms over a sliding window.
+//!
+//! [`LatencyHistogram`] counts latencies in buckets in the manner of HDR
+//! histograms: exact below 16 microseconds, and above that sixteen buckets
+//! to each doubling, so any percentile is reported within about 6% in fixed
+//! memory however many lookups are recorded. Counts are kept in slots, each
+//! a tenth of the window, and a slot is dropped once it falls out of the
+//! window, so percentiles follow how the network behaves now rather than an
+//! hour ago. A [`StatsCollector`](crate::stats::StatsCollector) keeps one
+//! of the lookups it counts, and an
+//! [`AdaptiveTimeout`](crate::adaptive::AdaptiveTimeout) one of its own, of
+//! the queries it sets timeouts for.
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Slots a window is divided into
+pub const WINDOW_SLOTS: u32 = 10;
+
+/// Buckets to each doubling of latency, as a power of two
+const SUB_BUCKET_BITS: u32 = 4;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+/// Latencies above this many microseconds, over nineteen hours, are
+/// counted as this
+const MAX_MICROS: u64 = 1 << 36;
+
+/// The bucket counting `micros`
+fn bucket(micros: u64) -> usize {
+    let micros = micros.min(MAX_MICROS);
+    if micros < SUB_BUCKETS {
+        return micros as usize;
+    }
+    let shift = 63 - micros.leading_zeros() - SUB_BUCKET_BITS;
+    (u64::from(shift) * SUB_BUCKETS + (micros >> shift)) as usize
+}
+
+/// The highest latency, in microseconds, counted by `bucket`
+fn bucket_ceiling(bucket: usize) -> u64 {
+    let bucket = bucket as u64;
+    if bucket < SUB_BUCKETS * 2 {
+        return bucket;
+    }
+    let shift = bucket / SUB_BUCKETS - 1;
+    let mantissa = bucket - shift * SUB_BUCKETS;
+    ((mantissa + 1) << shift) - 1
+}
+
+/// Counts of latencies recorded over a sliding window.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::histogram::LatencyHistogram;
+/// use std::time::Duration;
+///
+/// let mut histogram = LatencyHistogram::new(Duration::from_secs(60));
+/// for ms in 1..=100 {
+///     histogram.record(Duration::from_millis(ms));
+/// }
+/// assert_eq!(histogram.count(), 100);
+/// let p99 = histogram.percentile(99.0).unwrap();
+/// assert!(p99 >= Duration::from_millis(99) && p99 < Duration::from_millis(105));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    window: Duration,
+    /// When each slot started, and its counts by bucket, oldest first
+    slots: VecDeque<(Instant, Vec<u64>)>,
+}
+
+impl LatencyHistogram {
+    /// Empty histogram covering the last `window`
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window: window.max(Duration::from_millis(u64::from(WINDOW_SLOTS))),
+            slots: VecDeque::new(),
+        }
+    }
+
+    /// How far back the histogram covers
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Drop the slots which have left the window as of `now`
+    fn expire(&mut self, now: Instant) {
+        while let Some(&(started, _)) = self.slots.front() {
+            if now.saturating_duration_since(started) < self.window {
+                break;
+            }
+            self.slots.pop_front();
+        }
+    }
+
+    /// Count `latency`
+    pub fn record(&mut self, latency: Duration) {
+        self.record_at(latency, Instant::now());
+    }
+
+    fn record_at(&mut self, latency: Duration, now: Instant) {
+        self.expire(now);
+        let slot_len = self.window / WINDOW_SLOTS;
+        let current = match self.slots.back() {
+            Some(&(started, _)) => now.saturating_duration_since(started) < slot_len,
+            None => false,
+        };
+        if !current {
+            self.slots.push_back((now, Vec::new()));
+        }
+        let counts = &mut self.slots.back_mut().expect("a slot was pushed").1;
+        let index = bucket(latency.as_micros().min(u128::from(MAX_MICROS)) as u64);
+        if counts.len() <= index {
+            counts.resize(index + 1, 0);
+        }
+        counts[index] += 1;
+    }
+
+    /// The counts by bucket of the slots still in the window
+    fn merged(&self) -> Vec<u64> {
+        let now = Instant::now();
+        let mut merged: Vec<u64> = Vec::new();
+        for (_, counts) in self
+            .slots
+            .iter()
+            .filter(|(started, _)| now.saturating_duration_since(*started) < self.window)
+        {
+            if merged.len() < counts.len() {
+                merged.resize(counts.len(), 0);
+            }
+            for (total, count) in merged.iter_mut().zip(counts) {
+                *total += count;
+            }
+        }
+        merged
+    }
+
+    /// Latencies counted within the window
+    pub fn count(&self) -> u64 {
+        self.merged().iter().sum()
+    }
+
+    /// Whether nothing was counted within the window
+    pub fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+
+    /// The latency below which `percentile` percent of those in the window
+    /// fell, rounded up to its bucket. None if there were none.
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        let merged = self.merged();
+        let total: u64 = merged.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        let rank = ((percentile.clamp(0.0, 100.0) / 100.0 * total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        merged.iter().enumerate().find_map(|(index, count)| {
+            seen += count;
+            if seen >= rank {
+                Some(Duration::from_micros(bucket_ceiling(index)))
+            } else {
+                None
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_are_contiguous_and_within_their_precision() {
+        for micros in 1..100_000 {
+            let index = bucket(micros);
+            assert!(index - bucket(micros - 1) <= 1, "{} at {}", index, micros);
+            let ceiling = bucket_ceiling(index);
+            assert!(ceiling >= micros, "{} above {}", micros, ceiling);
+            assert!(ceiling - micros <= micros / SUB_BUCKETS);
+        }
+        assert_eq!(bucket(u64::MAX), bucket(MAX_MICROS));
+        assert!(bucket_ceiling(bucket(MAX_MICROS)) >= MAX_MICROS);
+    }
+    #[test]
+    fn latencies_leave_with_their_slot() {
+        let start = Instant::now();
+        let mut histogram = LatencyHistogram::new(Duration::from_secs(10));
+        histogram.record_at(Duration::from_secs(2), start);
+        histogram.record_at(Duration::from_millis(3), start + Duration::from_secs(5));
+        histogram.record_at(Duration::from_millis(4), start + Duration::from_secs(11));
+        // the first slot has left the window
+        assert_eq!(histogram.slots.len(), 2);
+        assert_eq!(
+            histogram.percentile(100.0),
+            Some(Duration::from_micros(4095))
+        );
+        assert_eq!(histogram.percentile(0.0), Some(Duration::from_micros(3071)));
+    }
+}
@@ -0,0 +1,319 @@
+//! Spotting hostnames made to look like other hostnames.
+//!
+//! Internationalized names let `аррӏе.com`, spelled in Cyrillic, pass for
+//! `apple.com`. [`assess`] decodes each punycoded label and looks for the
+//! usual tricks: mixing scripts within a label, labels written wholly in
+//! letters that mimic Latin ones, invisible characters, and punycode that is
+//! malformed or does not round trip. The checks follow the spirit of UTS #39
+//! with a built in approximation of its script and confusable data, so they
+//! flag the common attacks rather than every possible one.
+use crate::punycode::{self, ACE_PREFIX};
+use std::fmt;
+
+/// The writing system of a character, for the scripts IDNs commonly use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Script {
+    /// Digits, hyphens and other characters shared by all scripts
+    Common,
+    Latin,
+    Greek,
+    Cyrillic,
+    Armenian,
+    Hebrew,
+    Arabic,
+    Thai,
+    Han,
+    Hiragana,
+    Katakana,
+    Hangul,
+    Other,
+}
+
+impl Script {
+    /// The script of `c`
+    pub fn of(c: char) -> Self {
+        match c as u32 {
+            0x30..=0x39 | 0x2d | 0x5f => Script::Common,
+            0x41..=0x5a | 0x61..=0x7a => Script::Latin,
+            0xc0..=0x24f | 0x1e00..=0x1eff => Script::Latin,
+            0x370..=0x3ff | 0x1f00..=0x1fff => Script::Greek,
+            0x400..=0x52f => Script::Cyrillic,
+            0x530..=0x58f => Script::Armenian,
+            0x590..=0x5ff => Script::Hebrew,
+            0x600..=0x6ff => Script::Arabic,
+            0xe00..=0xe7f => Script::Thai,
+            0x3400..=0x4dbf | 0x4e00..=0x9fff => Script::Han,
+            0x3040..=0x309f => Script::Hiragana,
+            0x30a0..=0x30ff => Script::Katakana,
+            0x1100..=0x11ff | 0xac00..=0xd7af => Script::Hangul,
+            _ => Script::Other,
+        }
+    }
+}
+
+/// Characters from other scripts that render like a Latin letter
+const CONFUSABLES: &[(char, char)] = &[
+    ('а', 'a'),
+    ('в', 'b'),
+    ('с', 'c'),
+    ('ԁ', 'd'),
+    ('е', 'e'),
+    ('һ', 'h'),
+    ('і', 'i'),
+    ('ј', 'j'),
+    ('к', 'k'),
+    ('ӏ', 'l'),
+    ('м', 'm'),
+    ('н', 'h'),
+    ('о', 'o'),
+    ('р', 'p'),
+    ('ԛ', 'q'),
+    ('ѕ', 's'),
+    ('т', 't'),
+    ('у', 'y'),
+    ('ԝ', 'w'),
+    ('х', 'x'),
+    ('α', 'a'),
+    ('ε', 'e'),
+    ('ι', 'i'),
+    ('κ', 'k'),
+    ('ν', 'v'),
+    ('ο', 'o'),
+    ('ρ', 'p'),
+    ('τ', 't'),
+    ('υ', 'u'),
+    ('χ', 'x'),
+    ('օ', 'o'),
+    ('հ', 'h'),
+    ('ս', 'u'),
+];
+
+/// Characters which render as nothing
+const INVISIBLE: &[char] = &[
+    '\u{ad}', '\u{200b}', '\u{200c}', '\u{200d}', '\u{2060}', '\u{feff}',
+];
+
+/// How likely a name is to be an imitation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Risk {
+    /// Plain ASCII
+    None,
+    /// Internationalized, with nothing untoward
+    Low,
+    /// Malformed in a way that is odd rather than deceptive
+    Medium,
+    /// Built the way imitations are
+    High,
+}
+
+/// Something found in one label of a name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Indicator {
+    /// Letters from scripts which are not normally combined
+    MixedScripts { label: String, scripts: Vec<Script> },
+    /// A label wholly in another script's lookalikes of Latin letters
+    WholeScriptConfusable { label: String, imitates: String },
+    /// A character which renders as nothing
+    InvisibleCharacter { label: String },
+    /// An `xn--` label which does not decode, or decodes to plain ASCII
+    InvalidPunycode { label: String },
+    /// Hyphens in the third and fourth positions outside of `xn--`,
+    /// which RFC 5891 reserves for future encodings
+    ReservedHyphens { label: String },
+}
+
+impl Indicator {
+    /// The risk this indicator alone represents
+    pub fn risk(&self) -> Risk {
+        match self {
+            Indicator::MixedScripts { .. }
+            | Indicator::WholeScriptConfusable { .. }
+            | Indicator::InvisibleCharacter { .. } => Risk::High,
+            Indicator::InvalidPunycode { .. } | Indicator::ReservedHyphens { .. } => Risk::Medium,
+        }
+    }
+}
+
+impl fmt::Display for Indicator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Indicator::MixedScripts { label, scripts } => {
+                write!(f, "'{}' mixes scripts {:?}", label, scripts)
+            }
+            Indicator::WholeScriptConfusable { label, imitates } => {
+                write!(f, "'{}' imitates '{}'", label, imitates)
+            }
+            Indicator::InvisibleCharacter { label } => {
+                write!(f, "'{}' contains an invisible character", label)
+            }
+            Indicator::InvalidPunycode { label } => write!(f, "'{}' is not valid punycode", label),
+            Indicator::ReservedHyphens { label } => {
+                write!(f, "'{}' has reserved hyphens in positions 3 and 4", label)
+            }
+        }
+    }
+}
+
+/// The verdict on a hostname
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assessment {
+    /// The name with its punycoded labels decoded
+    pub unicode: String,
+    pub risk: Risk,
+    pub indicators: Vec<Indicator>,
+}
+
+/// Whether the scripts of one label are a combination in everyday use:
+/// one script, or Latin with Japanese, Chinese or Korean writing
+fn scripts_allowed(scripts: &[Script]) -> bool {
+    use Script::*;
+    if scripts.len() <= 1 {
+        return true;
+    }
+    let within = |allowed: &[Script]| scripts.iter().all(|s| allowed.contains(s));
+    within(&[Latin, Han, Hiragana, Katakana]) || within(&[Latin, Han, Hangul])
+}
+
+/// The Latin word `label` imitates, if every letter in it is a lookalike
+fn imitation(label: &str) -> Option<String> {
+    let mut imitates = String::with_capacity(label.len());
+    let mut any_lookalike = false;
+    for c in label.chars() {
+        match CONFUSABLES.iter().find(|&&(from, _)| from == c) {
+            Some(&(_, latin)) => {
+                imitates.push(latin);
+                any_lookalike = true;
+            }
+            None if Script::of(c) == Script::Common => imitates.push(c),
+            None => return None,
+        }
+    }
+    if any_lookalike {
+        Some(imitates)
+    } else {
+        None
+    }
+}
+
+/// Assess `hostname`, which may be in its ASCII or Unicode form.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::homograph::{assess, Risk};
+///
+/// assert_eq!(assess("www.example.com").risk, Risk::None);
+/// assert_eq!(assess("bücher.example").risk, Risk::Low);
+/// // "аррӏе" in Cyrillic
+/// let verdict = assess("xn--80ak6aa92e.com");
+/// assert_eq!(verdict.risk, Risk::High);
+/// assert_eq!(verdict.indicators[0].to_string(), "'аррӏе' imitates 'apple'");
+/// ```
+pub fn assess(hostname: &str) -> Assessment {
+    let mut indicators = Vec::new();
+    let mut labels = Vec::new();
+    let mut non_ascii = false;
+    for raw in hostname.trim_end_matches('.').split('.') {
+        let is_ace = raw
+            .get(..4)
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case(ACE_PREFIX));
+        let label = if is_ace {
+            match punycode::decode(&raw[4..]) {
+                Some(decoded) if !decoded.is_ascii() => decoded,
+                _ => {
+                    indicators.push(Indicator::InvalidPunycode {
+                        label: raw.to_string(),
+                    });
+                    raw.to_string()
+                }
+            }
+        } else {
+            if raw.get(2..4) == Some("--") {
+                indicators.push(Indicator::ReservedHyphens {
+                    label: raw.to_string(),
+                });
+            }
+            raw.to_string()
+        };
+        if !label.is_ascii() {
+            non_ascii = true;
+            check_label(&label, &mut indicators);
+        }
+        labels.push(label);
+    }
+    let floor = if non_ascii { Risk::Low } else { Risk::None };
+    let risk = indicators
+        .iter()
+        .map(Indicator::risk)
+        .max()
+        .map_or(floor, |worst| worst.max(floor));
+    Assessment {
+        unicode: labels.join("."),
+        risk,
+        indicators,
+    }
+}
+
+/// Check one decoded, non-ASCII label
+fn check_label(label: &str, indicators: &mut Vec<Indicator>) {
+    if label.chars().any(|c| INVISIBLE.contains(&c)) {
+        indicators.push(Indicator::InvisibleCharacter {
+            label: label.to_string(),
+        });
+    }
+    let mut scripts: Vec<Script> = label
+        .chars()
+        .map(Script::of)
+        .filter(|&s| s != Script::Common)
+        .collect();
+    scripts.sort();
+    scripts.dedup();
+    if !scripts_allowed(&scripts) {
+        indicators.push(Indicator::MixedScripts {
+            label: label.to_string(),
+            scripts,
+        });
+    } else if let Some(imitates) = imitation(label) {
+        indicators.push(Indicator::WholeScriptConfusable {
+            label: label.to_string(),
+            imitates,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_mixed_scripts_and_invisibles() {
+        // Latin "pay" with a Cyrillic "р"
+        let verdict = assess("раypal.com");
+        assert_eq!(verdict.risk, Risk::High);
+        assert!(matches!(
+            verdict.indicators[0],
+            Indicator::MixedScripts { ref scripts, .. } if scripts == &[Script::Latin, Script::Cyrillic]
+        ));
+        assert_eq!(assess("ex\u{200d}ample.com").risk, Risk::High);
+        // Japanese mixes kanji, kana and Latin routinely
+        assert_eq!(assess("東京タワーtv.jp").risk, Risk::Low);
+        // Cyrillic which imitates nothing is fine
+        assert_eq!(assess("пример.рф").risk, Risk::Low);
+    }
+    #[test]
+    fn flags_odd_punycode() {
+        let verdict = assess("xn--abc-.example");
+        assert_eq!(verdict.risk, Risk::Medium);
+        assert!(matches!(
+            verdict.indicators[0],
+            Indicator::InvalidPunycode { .. }
+        ));
+        assert_eq!(
+            assess("ab--cd.example").indicators,
+            vec![Indicator::ReservedHyphens {
+                label: "ab--cd".into()
+            }]
+        );
+        assert_eq!(assess("XN--bcher-kva.example").unicode, "bücher.example");
+    }
+}
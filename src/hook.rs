@@ -0,0 +1,179 @@
+//! Post-processing the results of lookups in one place.
+//!
+//! Policies such as dropping addresses from a range, reordering answers or
+//! synthesizing NAT64 addresses otherwise have to be applied after every
+//! call to a resolver. [`HookedResolver`] runs hooks over the answers of
+//! another resolver, so the policy is registered once, where the resolver is
+//! built.
+use crate::errors::IpaddrConversionError;
+use crate::resolver::Resolve;
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::Arc;
+
+/// The well-known NAT64 prefix, `64:ff9b::/96`, of RFC 6052
+pub const NAT64_WELL_KNOWN_PREFIX: Ipv6Addr = Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0);
+
+/// A hook, given the name looked up and the addresses found so far
+type Hook = Arc<dyn Fn(&str, Vec<IpAddr>) -> Vec<IpAddr> + Send + Sync>;
+
+/// Resolver passing the answers of another resolver through hooks.
+///
+/// Hooks run in the order they were added, each given the previous one's
+/// output. An answer the hooks leave empty fails with NotFound. Address
+/// literals go through the hooks too, as they would at a call site.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::hook::HookedResolver;
+/// use ipaddr_from_str::{Resolve, StaticResolver};
+/// use std::net::IpAddr;
+///
+/// let addrs: Vec<IpAddr> = vec!["10.0.0.1".parse().unwrap(), "192.0.2.1".parse().unwrap()];
+/// let resolver = HookedResolver::new(StaticResolver::new().with_entry("api.example.com", addrs))
+///     .with_hook(|_, mut addrs| {
+///         addrs.retain(|addr| !addr.to_string().starts_with("10."));
+///         addrs
+///     });
+/// assert_eq!(resolver.resolve("api.example.com").unwrap(), vec!["192.0.2.1".parse::<IpAddr>().unwrap()]);
+/// ```
+#[derive(Clone)]
+pub struct HookedResolver<R> {
+    resolver: R,
+    hooks: Vec<Hook>,
+}
+
+impl<R> fmt::Debug for HookedResolver<R>
+where
+    R: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HookedResolver")
+            .field("resolver", &self.resolver)
+            .field("hooks", &self.hooks.len())
+            .finish()
+    }
+}
+
+impl<R: Resolve> HookedResolver<R> {
+    /// Wrap `resolver`, with no hooks yet
+    pub fn new(resolver: R) -> Self {
+        Self {
+            resolver,
+            hooks: Vec::new(),
+        }
+    }
+
+    /// Run `hook` over every answer, after any hooks already added, returning
+    /// the updated resolver
+    ///
+    /// # Parameters
+    ///
+    /// * `hook` - given the name as passed to `resolve` and the addresses,
+    ///   returning the addresses to answer with instead
+    pub fn with_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&str, Vec<IpAddr>) -> Vec<IpAddr> + Send + Sync + 'static,
+    {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// The resolver whose answers the hooks are given
+    pub fn inner(&self) -> &R {
+        &self.resolver
+    }
+}
+
+impl<R: Resolve> Resolve for HookedResolver<R> {
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        let addrs = self.resolver.resolve(hostname_or_address)?;
+        let addrs = self
+            .hooks
+            .iter()
+            .fold(addrs, |addrs, hook| hook(hostname_or_address, addrs));
+        if addrs.is_empty() {
+            return Err(IpaddrConversionError::NotFound(
+                hostname_or_address.to_string(),
+            ));
+        }
+        Ok(addrs)
+    }
+}
+
+/// A hook synthesizing NAT64 addresses, for IPv6-only hosts behind a
+/// translator. Each IPv4 address is replaced by its embedding in the `/96`
+/// `prefix`, such as [`NAT64_WELL_KNOWN_PREFIX`], unless the answer already
+/// has an IPv6 address, as DNS64 does.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::hook::{nat64, HookedResolver, NAT64_WELL_KNOWN_PREFIX};
+/// use ipaddr_from_str::{Resolve, StaticResolver};
+/// use std::net::IpAddr;
+///
+/// let resolver = HookedResolver::new(StaticResolver::new()).with_hook(nat64(NAT64_WELL_KNOWN_PREFIX));
+/// assert_eq!(resolver.resolve("192.0.2.33").unwrap(), vec!["64:ff9b::c000:221".parse::<IpAddr>().unwrap()]);
+/// ```
+pub fn nat64(prefix: Ipv6Addr) -> impl Fn(&str, Vec<IpAddr>) -> Vec<IpAddr> + Send + Sync {
+    let high = u128::from(prefix) & !u128::from(u32::MAX);
+    move |_, addrs| {
+        if addrs.iter().any(IpAddr::is_ipv6) {
+            return addrs;
+        }
+        addrs
+            .into_iter()
+            .map(|addr| match addr {
+                IpAddr::V4(v4) => IpAddr::V6(Ipv6Addr::from(high | u128::from(u32::from(v4)))),
+                v6 => v6,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::StaticResolver;
+    use crate::test_util::ips;
+
+    #[test]
+    fn hooks_run_in_order() {
+        let inner = StaticResolver::new().with_entry("db.internal", ips(&["10.0.0.2", "10.0.0.1"]));
+        let resolver = HookedResolver::new(inner)
+            .with_hook(|_, mut addrs| {
+                addrs.sort();
+                addrs
+            })
+            .with_hook(|name, mut addrs| {
+                if name == "db.internal" {
+                    addrs.truncate(1);
+                }
+                addrs
+            });
+        assert_eq!(resolver.resolve("db.internal").unwrap(), ips(&["10.0.0.1"]));
+        let emptied = HookedResolver::new(StaticResolver::new()).with_hook(|_, _| Vec::new());
+        assert!(matches!(
+            emptied.resolve("10.0.0.1"),
+            Err(IpaddrConversionError::NotFound(_))
+        ));
+    }
+    #[test]
+    fn nat64_keeps_native_ipv6_answers() {
+        let inner = StaticResolver::new()
+            .with_entry("dual.example.com", ips(&["192.0.2.1", "2001:db8::1"]))
+            .with_entry("v4.example.com", ips(&["192.0.2.1", "192.0.2.2"]));
+        let resolver =
+            HookedResolver::new(inner).with_hook(nat64("2001:db8:64::".parse().unwrap()));
+        assert_eq!(
+            resolver.resolve("dual.example.com").unwrap(),
+            ips(&["192.0.2.1", "2001:db8::1"])
+        );
+        assert_eq!(
+            resolver.resolve("v4.example.com").unwrap(),
+            ips(&["2001:db8:64::c000:201", "2001:db8:64::c000:202"])
+        );
+    }
+}
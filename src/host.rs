@@ -0,0 +1,406 @@
+//! Hostnames or address literals, optionally with a port.
+//!
+//! Command lines and configuration files usually accept either a hostname or
+//! an address where a peer is named. [`HostOrIp`] validates such a value up
+//! front, so a typo is reported where it was typed rather than as a failed
+//! lookup later, and [`HostPort`] adds a port which may default.
+//! [`to_url_host`] and [`split_url_host`] write and read the host part of a
+//! URL, where IPv6 addresses must be bracketed.
+use crate::errors::IpaddrConversionError;
+use crate::parse::{parse_ipv4, parse_ipv6, ParseError};
+use crate::resolver::Resolve;
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+
+/// Longest hostname, in bytes, excluding any trailing dot
+const MAX_HOSTNAME_LEN: usize = 253;
+/// Longest label of a hostname
+const MAX_LABEL_LEN: usize = 63;
+
+/// Check `host` is a syntactically valid hostname. Underscores are allowed,
+/// as they appear in service labels.
+pub(crate) fn check_hostname(host: &str, base: usize) -> Result<(), ParseError> {
+    let name = host.strip_suffix('.').unwrap_or(host);
+    if name.len() > MAX_HOSTNAME_LEN {
+        return Err(ParseError::new(
+            base + MAX_HOSTNAME_LEN,
+            "hostname of at most 253 bytes",
+        ));
+    }
+    let mut offset = base;
+    for label in name.split('.') {
+        if label.is_empty() {
+            return Err(ParseError::new(offset, "hostname label"));
+        }
+        if let Some(pos) =
+            label.find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+        {
+            return Err(ParseError::new(offset + pos, "letter, digit or '-'"));
+        }
+        if label.len() > MAX_LABEL_LEN {
+            return Err(ParseError::new(
+                offset + MAX_LABEL_LEN,
+                "label of at most 63 characters",
+            ));
+        }
+        if label.starts_with('-') {
+            return Err(ParseError::new(offset, "letter or digit"));
+        }
+        if label.ends_with('-') {
+            return Err(ParseError::new(offset + label.len() - 1, "letter or digit"));
+        }
+        offset += label.len() + 1;
+    }
+    Ok(())
+}
+
+/// A hostname or an address literal.
+///
+/// Text containing a colon is taken to be an IPv6 address, and text whose
+/// last label is numeric an IPv4 address, since no top level domain is all
+/// digits; anything else must be a valid hostname.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::host::HostOrIp;
+///
+/// assert!("db.example.com".parse::<HostOrIp>().unwrap().as_ip().is_none());
+/// assert!("10.0.0.1".parse::<HostOrIp>().unwrap().as_ip().is_some());
+/// let err = "10.0.0.256".parse::<HostOrIp>().unwrap_err();
+/// assert_eq!(err.to_string(), "expected octet of at most 255 at byte 7");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum HostOrIp {
+    Ip(IpAddr),
+    Host(String),
+}
+
+impl HostOrIp {
+    /// Parse `text`, reporting offsets relative to `base`
+    fn parse_at(text: &str, base: usize) -> Result<Self, ParseError> {
+        let shifted = |err: ParseError| ParseError::new(base + err.offset(), err.expected());
+        if text.contains(':') {
+            return parse_ipv6(text)
+                .map(|ip| HostOrIp::Ip(ip.into()))
+                .map_err(shifted);
+        }
+        let last_label = text
+            .strip_suffix('.')
+            .unwrap_or(text)
+            .rsplit('.')
+            .next()
+            .unwrap_or("");
+        if !last_label.is_empty() && last_label.bytes().all(|c| c.is_ascii_digit()) {
+            return parse_ipv4(text)
+                .map(|ip| HostOrIp::Ip(ip.into()))
+                .map_err(shifted);
+        }
+        check_hostname(text, base)?;
+        Ok(HostOrIp::Host(text.to_string()))
+    }
+
+    /// The address, if this is an address literal
+    pub fn as_ip(&self) -> Option<IpAddr> {
+        match self {
+            HostOrIp::Ip(ip) => Some(*ip),
+            HostOrIp::Host(_) => None,
+        }
+    }
+
+    /// Resolve through `resolver`; address literals resolve to themselves
+    pub fn resolve<R: Resolve + ?Sized>(
+        &self,
+        resolver: &R,
+    ) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        match self {
+            HostOrIp::Ip(ip) => Ok(vec![*ip]),
+            HostOrIp::Host(host) => resolver.resolve(host),
+        }
+    }
+}
+
+impl fmt::Display for HostOrIp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HostOrIp::Ip(ip) => ip.fmt(f),
+            HostOrIp::Host(host) => host.fmt(f),
+        }
+    }
+}
+
+impl FromStr for HostOrIp {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_at(s, 0)
+    }
+}
+
+/// A hostname or address, and a port.
+///
+/// Written `host:port`, `192.0.2.1:port` or `[2001:db8::1]:port`. A bare
+/// IPv6 address cannot carry a port, as its last group would be mistaken for
+/// one, so it must be bracketed to have one.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::host::HostPort;
+///
+/// let peer = HostPort::parse_with_default("db.example.com", 5432).unwrap();
+/// assert_eq!(peer.port, 5432);
+/// let peer: HostPort = "[2001:db8::1]:8080".parse().unwrap();
+/// assert_eq!(peer.to_string(), "[2001:db8::1]:8080");
+/// assert!("db.example.com".parse::<HostPort>().is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HostPort {
+    pub host: HostOrIp,
+    pub port: u16,
+}
+
+impl HostPort {
+    /// Parse `text`, using `default_port` when it has none
+    pub fn parse_with_default(text: &str, default_port: u16) -> Result<Self, ParseError> {
+        Self::parse(text, Some(default_port))
+    }
+
+    fn parse(text: &str, default_port: Option<u16>) -> Result<Self, ParseError> {
+        let (host, port) = Self::parse_parts(text)?;
+        let port = match (port, default_port) {
+            (Some(port), _) | (None, Some(port)) => port,
+            (None, None) => return Err(ParseError::new(text.len(), "':' and a port")),
+        };
+        Ok(Self { host, port })
+    }
+
+    /// The host of `text`, and its port if it has one
+    fn parse_parts(text: &str) -> Result<(HostOrIp, Option<u16>), ParseError> {
+        let (host, rest, rest_offset) = if let Some(inner) = text.strip_prefix('[') {
+            let close = inner
+                .find(']')
+                .ok_or_else(|| ParseError::new(text.len(), "']'"))?;
+            let ip = parse_ipv6(&inner[..close])
+                .map_err(|err| ParseError::new(err.offset() + 1, err.expected()))?;
+            (HostOrIp::Ip(ip.into()), &inner[close + 1..], close + 2)
+        } else if text.matches(':').count() > 1 {
+            (HostOrIp::parse_at(text, 0)?, "", text.len())
+        } else {
+            let split = text.find(':').unwrap_or(text.len());
+            (
+                HostOrIp::parse_at(&text[..split], 0)?,
+                &text[split..],
+                split,
+            )
+        };
+        let port = match rest.strip_prefix(':') {
+            Some(port) => Some(parse_port(port, rest_offset + 1)?),
+            None if !rest.is_empty() => return Err(ParseError::new(rest_offset, "':'")),
+            None => None,
+        };
+        Ok((host, port))
+    }
+
+    /// Resolve the host through `resolver`, pairing each address with the
+    /// port
+    pub fn resolve<R: Resolve + ?Sized>(
+        &self,
+        resolver: &R,
+    ) -> Result<Vec<SocketAddr>, IpaddrConversionError> {
+        Ok(self
+            .host
+            .resolve(resolver)?
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip, self.port))
+            .collect())
+    }
+}
+
+fn parse_port(text: &str, offset: usize) -> Result<u16, ParseError> {
+    if let Some(pos) = text.find(|c: char| !c.is_ascii_digit()) {
+        return Err(ParseError::new(offset + pos, "digit"));
+    }
+    if text.is_empty() {
+        return Err(ParseError::new(offset, "port"));
+    }
+    text.parse()
+        .map_err(|_| ParseError::new(offset, "port of at most 65535"))
+}
+
+impl fmt::Display for HostPort {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.host {
+            HostOrIp::Ip(IpAddr::V6(ip)) => write!(f, "[{}]:{}", ip, self.port),
+            ref host => write!(f, "{}:{}", host, self.port),
+        }
+    }
+}
+
+impl FromStr for HostPort {
+    type Err = ParseError;
+
+    /// Parse `s`, which must include a port
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s, None)
+    }
+}
+
+/// The port `scheme` implies when a URL gives none, for the common schemes
+pub fn default_port_for_scheme(scheme: &str) -> Option<u16> {
+    match scheme.to_ascii_lowercase().as_str() {
+        "http" | "ws" => Some(80),
+        "https" | "wss" => Some(443),
+        "ftp" => Some(21),
+        "ssh" => Some(22),
+        "ldap" => Some(389),
+        "ldaps" => Some(636),
+        _ => None,
+    }
+}
+
+/// `addr_or_name` as the host of a URL, with `port` after it if given.
+///
+/// IPv6 addresses are bracketed, once, and a zone is written `%25zone`, as
+/// RFC 6874 has it; hostnames and IPv4 addresses are left as they are.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::host::to_url_host;
+///
+/// assert_eq!(to_url_host("2001:db8::1", Some(8080)), "[2001:db8::1]:8080");
+/// assert_eq!(to_url_host("[2001:db8::1]", None), "[2001:db8::1]");
+/// assert_eq!(to_url_host("fe80::1%eth0", None), "[fe80::1%25eth0]");
+/// assert_eq!(to_url_host("db.example.com", Some(5432)), "db.example.com:5432");
+/// ```
+pub fn to_url_host(addr_or_name: &str, port: Option<u16>) -> String {
+    let bare = addr_or_name
+        .strip_prefix('[')
+        .and_then(|inner| inner.strip_suffix(']'))
+        .unwrap_or(addr_or_name);
+    let (addr, zone) = match bare.split_once('%') {
+        Some((addr, zone)) => (addr, Some(zone.strip_prefix("25").unwrap_or(zone))),
+        None => (bare, None),
+    };
+    let mut host = match addr.parse::<Ipv6Addr>() {
+        Ok(ip) => match zone {
+            Some(zone) => format!("[{}%25{}]", ip, zone),
+            None => format!("[{}]", ip),
+        },
+        Err(_) => addr_or_name.to_string(),
+    };
+    if let Some(port) = port {
+        host.push_str(&format!(":{}", port));
+    }
+    host
+}
+
+/// `addr_or_name` and `port` as the authority of a `scheme` URL, leaving the
+/// port out if it is the scheme's default
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::host::to_url_host_for;
+///
+/// assert_eq!(to_url_host_for("https", "::1", 443), "[::1]");
+/// assert_eq!(to_url_host_for("http", "::1", 8080), "[::1]:8080");
+/// ```
+pub fn to_url_host_for(scheme: &str, addr_or_name: &str, port: u16) -> String {
+    let port = Some(port).filter(|port| default_port_for_scheme(scheme) != Some(*port));
+    to_url_host(addr_or_name, port)
+}
+
+/// Split the host part of a URL, as [`to_url_host`] writes it, into the
+/// host and its port, if it has one. IPv6 addresses must be bracketed when
+/// they have a port; zones are not supported.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::host::{split_url_host, HostOrIp};
+///
+/// let (host, port) = split_url_host("[2001:db8::1]:8443").unwrap();
+/// assert_eq!((host.to_string(), port), ("2001:db8::1".to_string(), Some(8443)));
+/// assert_eq!(split_url_host("example.com").unwrap().1, None);
+/// ```
+pub fn split_url_host(text: &str) -> Result<(HostOrIp, Option<u16>), ParseError> {
+    HostPort::parse_parts(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(text: &str) -> (usize, &'static str) {
+        let err = text.parse::<HostOrIp>().unwrap_err();
+        (err.offset(), err.expected())
+    }
+
+    #[test]
+    fn hostnames_are_validated() {
+        assert_eq!(
+            "_sip._tcp.example.com.".parse::<HostOrIp>().unwrap(),
+            HostOrIp::Host("_sip._tcp.example.com.".into())
+        );
+        assert_eq!(error("web..example.com"), (4, "hostname label"));
+        assert_eq!(error("web server.example"), (3, "letter, digit or '-'"));
+        assert_eq!(error("-web.example"), (0, "letter or digit"));
+        assert_eq!(error("web-.example"), (3, "letter or digit"));
+        assert_eq!(
+            error(&"a".repeat(64)),
+            (63, "label of at most 63 characters")
+        );
+        assert_eq!(error("::g"), (2, "end of input"));
+        assert_eq!(error(""), (0, "hostname label"));
+    }
+    #[test]
+    fn ports_default_or_are_required() {
+        let parse = |text| HostPort::parse_with_default(text, 80).unwrap();
+        assert_eq!(parse("example.com").port, 80);
+        assert_eq!(parse("example.com:8080").port, 8080);
+        assert_eq!(
+            parse("2001:db8::1").host.as_ip(),
+            Some("2001:db8::1".parse().unwrap())
+        );
+        assert_eq!(parse("[2001:db8::1]").port, 80);
+        assert_eq!(parse("192.0.2.1:443").to_string(), "192.0.2.1:443");
+
+        let error = |text: &str| {
+            let err = text.parse::<HostPort>().unwrap_err();
+            (err.offset(), err.expected())
+        };
+        assert_eq!(error("example.com"), (11, "':' and a port"));
+        assert_eq!(error("example.com:http"), (12, "digit"));
+        assert_eq!(error("example.com:70000"), (12, "port of at most 65535"));
+        assert_eq!(error("[::1"), (4, "']'"));
+        assert_eq!(error("[::1]x"), (5, "':'"));
+        assert_eq!(error("[::g]:1"), (3, "end of input"));
+    }
+    #[test]
+    fn url_hosts_round_trip() {
+        for (host, port, url) in [
+            ("2001:db8::1", Some(443), "[2001:db8::1]:443"),
+            ("2001:DB8:0::1", None, "[2001:db8::1]"),
+            ("192.0.2.1", Some(80), "192.0.2.1:80"),
+            ("example.com", None, "example.com"),
+        ] {
+            assert_eq!(to_url_host(host, port), url);
+            let (split, split_port) = split_url_host(url).unwrap();
+            assert_eq!(split, host.parse::<HostOrIp>().unwrap());
+            assert_eq!(split_port, port);
+        }
+        assert_eq!(
+            to_url_host("[fe80::1%25en0]", Some(80)),
+            "[fe80::1%25en0]:80"
+        );
+        assert_eq!(to_url_host_for("HTTP", "example.com", 80), "example.com");
+        assert_eq!(
+            to_url_host_for("gopher", "example.com", 70),
+            "example.com:70"
+        );
+        assert_eq!(split_url_host("[::1]x").unwrap_err().offset(), 5);
+    }
+}
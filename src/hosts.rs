@@ -0,0 +1,122 @@
+//! Parsing of hosts(5) files.
+use crate::errors::IpaddrConversionError;
+use crate::resolver::{normalize_name, Resolve};
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Location of the system hosts file
+#[cfg(not(windows))]
+pub const HOSTS_PATH: &str = "/etc/hosts";
+/// Location of the system hosts file
+#[cfg(windows)]
+pub const HOSTS_PATH: &str = r"C:\Windows\System32\drivers\etc\hosts";
+
+/// The name to address mappings of a hosts file.
+///
+/// A name listed on several lines maps to every address it appears with, in
+/// file order. Names are matched case insensitively.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostsFile {
+    entries: HashMap<String, Vec<IpAddr>>,
+}
+
+impl HostsFile {
+    /// Parse the contents of a hosts file. Lines whose address does not parse
+    /// are skipped.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ipaddr_from_str::hosts::HostsFile;
+    /// let hosts = HostsFile::parse("127.0.0.1 localhost\n10.0.0.5 db db.corp # primary");
+    /// assert_eq!(hosts.lookup("DB.corp").unwrap(), &["10.0.0.5".parse::<std::net::IpAddr>().unwrap()][..]);
+    /// ```
+    pub fn parse(contents: &str) -> Self {
+        let mut entries: HashMap<String, Vec<IpAddr>> = HashMap::new();
+        for line in contents.lines() {
+            let line = match line.find('#') {
+                Some(idx) => &line[..idx],
+                None => line,
+            };
+            let mut words = line.split_whitespace();
+            let addr = match words.next().and_then(|w| w.parse::<IpAddr>().ok()) {
+                Some(addr) => addr,
+                None => continue,
+            };
+            for name in words {
+                let addrs = entries.entry(normalize_name(name)).or_default();
+                if !addrs.contains(&addr) {
+                    addrs.push(addr);
+                }
+            }
+        }
+        Self { entries }
+    }
+
+    /// Read and parse the hosts file at `path`
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, IpaddrConversionError> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    /// Read and parse the system hosts file
+    pub fn system() -> Result<Self, IpaddrConversionError> {
+        Self::from_file(HOSTS_PATH)
+    }
+
+    /// The addresses listed for `hostname`, if any
+    pub fn lookup(&self, hostname: &str) -> Option<&[IpAddr]> {
+        self.entries
+            .get(&normalize_name(hostname))
+            .map(|addrs| addrs.as_slice())
+    }
+
+    /// The number of distinct names in the file
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the file maps no names at all
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Resolve for HostsFile {
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        if let Ok(ip) = hostname_or_address.parse::<IpAddr>() {
+            return Ok(vec![ip]);
+        }
+        self.lookup(hostname_or_address)
+            .map(|addrs| addrs.to_vec())
+            .ok_or_else(|| IpaddrConversionError::NotFound(hostname_or_address.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_accumulate_addresses() {
+        let hosts = HostsFile::parse(
+            "# comment\n127.0.0.1\tlocalhost\n::1 localhost ip6-localhost\nbogus name\n",
+        );
+        assert_eq!(hosts.len(), 2);
+        assert_eq!(
+            hosts.lookup("localhost").unwrap(),
+            &[
+                "127.0.0.1".parse::<IpAddr>().unwrap(),
+                "::1".parse().unwrap()
+            ][..]
+        );
+        assert!(hosts.lookup("name").is_none());
+    }
+    #[test]
+    fn unknown_names_are_not_found() {
+        let hosts = HostsFile::parse("10.0.0.1 a");
+        assert!(hosts.resolve("b").is_err());
+        assert!(hosts.resolve("A.").is_ok());
+    }
+}
@@ -0,0 +1,248 @@
+//! Adapters which let http clients resolve names through this crate.
+//!
+//! Enable the `hyper` feature for [`HyperResolver`], which implements hyper's
+//! resolver service and can be handed to `HttpConnector::new_with_resolver`,
+//! and the `reqwest` feature for [`ReqwestResolver`], which implements
+//! `reqwest::dns::Resolve` for use with `ClientBuilder::dns_resolver`.
+//!
+//! Both wrap any [`Resolve`] implementation, and run the (blocking) lookup on
+//! tokio's blocking thread pool under the caller's
+//! [deadline](crate::deadline::scope_async), if one is set.
+use crate::errors::IpaddrConversionError;
+use crate::resolver::Resolve;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+/// Run a lookup on the blocking pool, returning socket addresses with a port
+/// of 0. The http clients overwrite the port with the one from the url.
+async fn resolve_blocking<R>(
+    resolver: Arc<R>,
+    name: String,
+) -> Result<Vec<SocketAddr>, IpaddrConversionError>
+where
+    R: Resolve + Send + Sync + 'static,
+{
+    let addrs = crate::deadline::resolve(resolver, &name).await?;
+    Ok(to_socket_addrs(addrs))
+}
+
+fn to_socket_addrs(addrs: Vec<IpAddr>) -> Vec<SocketAddr> {
+    addrs.into_iter().map(|ip| SocketAddr::new(ip, 0)).collect()
+}
+
+#[cfg(feature = "hyper")]
+pub use self::hyper_resolver::HyperResolver;
+
+#[cfg(feature = "hyper")]
+mod hyper_resolver {
+    use super::*;
+    use hyper::client::connect::dns::Name;
+    use hyper::service::Service;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    /// A hyper resolver service backed by a [`Resolve`] implementation.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use hyper::client::HttpConnector;
+    /// use ipaddr_from_str::{http::HyperResolver, SystemResolver};
+    ///
+    /// let connector = HttpConnector::new_with_resolver(HyperResolver::new(SystemResolver));
+    /// let client = hyper::Client::builder().build::<_, hyper::Body>(connector);
+    /// ```
+    #[derive(Debug)]
+    pub struct HyperResolver<R> {
+        resolver: Arc<R>,
+    }
+
+    impl<R> HyperResolver<R> {
+        /// Wrap `resolver` for use by hyper
+        pub fn new(resolver: R) -> Self {
+            Self::from_arc(Arc::new(resolver))
+        }
+
+        /// Wrap an already shared `resolver` for use by hyper
+        pub fn from_arc(resolver: Arc<R>) -> Self {
+            Self { resolver }
+        }
+    }
+
+    impl<R> Clone for HyperResolver<R> {
+        fn clone(&self) -> Self {
+            Self {
+                resolver: self.resolver.clone(),
+            }
+        }
+    }
+
+    impl<R> Service<Name> for HyperResolver<R>
+    where
+        R: Resolve + Send + Sync + 'static,
+    {
+        type Response = std::vec::IntoIter<SocketAddr>;
+        type Error = IpaddrConversionError;
+        type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, name: Name) -> Self::Future {
+            let resolver = self.resolver.clone();
+            let name = name.as_str().to_string();
+            Box::pin(async move { Ok(resolve_blocking(resolver, name).await?.into_iter()) })
+        }
+    }
+}
+
+#[cfg(feature = "reqwest")]
+pub use self::reqwest_resolver::ReqwestResolver;
+
+#[cfg(feature = "reqwest")]
+mod reqwest_resolver {
+    use super::*;
+    // reqwest 0.11 takes hyper's name type rather than exporting its own
+    use hyper::client::connect::dns::Name;
+    use reqwest::dns::{Addrs, Resolving};
+
+    /// A `reqwest::dns::Resolve` implementation backed by a [`Resolve`]
+    /// implementation.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use ipaddr_from_str::{http::ReqwestResolver, SystemResolver};
+    /// use std::sync::Arc;
+    ///
+    /// let client = reqwest::Client::builder()
+    ///     .dns_resolver(Arc::new(ReqwestResolver::new(SystemResolver)))
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    #[derive(Debug)]
+    pub struct ReqwestResolver<R> {
+        resolver: Arc<R>,
+    }
+
+    impl<R> ReqwestResolver<R> {
+        /// Wrap `resolver` for use by reqwest
+        pub fn new(resolver: R) -> Self {
+            Self::from_arc(Arc::new(resolver))
+        }
+
+        /// Wrap an already shared `resolver` for use by reqwest
+        pub fn from_arc(resolver: Arc<R>) -> Self {
+            Self { resolver }
+        }
+    }
+
+    impl<R> reqwest::dns::Resolve for ReqwestResolver<R>
+    where
+        R: Resolve + Send + Sync + 'static,
+    {
+        fn resolve(&self, name: Name) -> Resolving {
+            let resolver = self.resolver.clone();
+            let name = name.as_str().to_string();
+            Box::pin(async move {
+                let addrs: Addrs = Box::new(resolve_blocking(resolver, name).await?.into_iter());
+                Ok(addrs)
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deadline;
+    use crate::resolver::StaticResolver;
+    use hyper::client::connect::dns::Name;
+    use std::future::Future;
+    use std::time::Instant;
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn resolver() -> StaticResolver {
+        StaticResolver::new().with_entry(
+            "db.internal",
+            vec!["10.0.0.1".parse().unwrap(), "2001:db8::1".parse().unwrap()],
+        )
+    }
+
+    fn name(text: &str) -> Name {
+        text.parse().unwrap()
+    }
+
+    #[cfg(feature = "hyper")]
+    #[test]
+    fn hyper_resolves_through_the_crate() {
+        use hyper::service::Service;
+
+        let mut service = HyperResolver::new(resolver());
+        block_on(async {
+            let addrs: Vec<SocketAddr> = service.call(name("db.internal")).await.unwrap().collect();
+            assert_eq!(
+                addrs,
+                vec![
+                    "10.0.0.1:0".parse().unwrap(),
+                    "[2001:db8::1]:0".parse().unwrap()
+                ]
+            );
+            assert!(matches!(
+                service.call(name("missing.internal")).await,
+                Err(IpaddrConversionError::NotFound(_))
+            ));
+            let expired = deadline::scope_async(Instant::now(), service.call(name("db.internal")));
+            assert!(matches!(
+                expired.await,
+                Err(IpaddrConversionError::Timeout(_))
+            ));
+        });
+    }
+    #[cfg(feature = "reqwest")]
+    #[test]
+    fn reqwest_resolves_through_the_crate() {
+        use reqwest::dns::Resolve as _;
+
+        let resolver = ReqwestResolver::new(resolver());
+        block_on(async {
+            let addrs: Vec<SocketAddr> = resolver
+                .resolve(name("db.internal"))
+                .await
+                .unwrap()
+                .collect();
+            assert_eq!(
+                addrs,
+                vec![
+                    "10.0.0.1:0".parse().unwrap(),
+                    "[2001:db8::1]:0".parse().unwrap()
+                ]
+            );
+            let err = resolver
+                .resolve(name("missing.internal"))
+                .await
+                .err()
+                .unwrap();
+            assert!(matches!(
+                err.downcast_ref::<IpaddrConversionError>(),
+                Some(IpaddrConversionError::NotFound(_))
+            ));
+            let expired =
+                deadline::scope_async(Instant::now(), resolver.resolve(name("db.internal")));
+            let err = expired.await.err().unwrap();
+            assert!(matches!(
+                err.downcast_ref::<IpaddrConversionError>(),
+                Some(IpaddrConversionError::Timeout(_))
+            ));
+        });
+    }
+}
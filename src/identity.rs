@@ -0,0 +1,173 @@
+//! Deciding whether two strings name the same host.
+//!
+//! Session affinity, connection pooling and certificate checks all ask
+//! whether `a` and `b` are the same endpoint, and answering with string
+//! equality gets case, trailing dots, IDNA forms and IPv6 spellings wrong.
+//! [`HostIdentity::matches`] answers under an explicit [`IdentityPolicy`],
+//! so every caller shares one definition.
+use crate::punycode::to_unicode;
+use crate::resolver::Resolve;
+use std::collections::HashSet;
+use std::fmt;
+use std::net::IpAddr;
+
+/// How strictly two hosts are compared
+#[derive(Clone, Copy)]
+pub enum IdentityPolicy<'a> {
+    /// The strings are equal, byte for byte
+    Literal,
+    /// They are equal as [`HostIdentity`]s: names ignoring case, a trailing
+    /// dot and the IDNA encoding, addresses however they are written
+    Normalized,
+    /// They are equal normalized, or resolve through the resolver to at
+    /// least one address in common. A name which fails to resolve matches
+    /// only what it equals normalized.
+    Resolved(&'a dyn Resolve),
+}
+
+impl fmt::Debug for IdentityPolicy<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IdentityPolicy::Literal => f.write_str("Literal"),
+            IdentityPolicy::Normalized => f.write_str("Normalized"),
+            IdentityPolicy::Resolved(_) => f.write_str("Resolved(..)"),
+        }
+    }
+}
+
+/// A host in a canonical form, for comparison.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::identity::{HostIdentity, IdentityPolicy};
+///
+/// assert!(HostIdentity::matches("Example.COM.", "example.com", IdentityPolicy::Normalized));
+/// assert!(HostIdentity::matches("[2001:DB8::0:1]", "2001:db8::1", IdentityPolicy::Normalized));
+/// assert!(!HostIdentity::matches("Example.COM", "example.com", IdentityPolicy::Literal));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum HostIdentity {
+    /// An address literal; IPv4-mapped IPv6 addresses are taken as the
+    /// IPv4 address
+    Ip(IpAddr),
+    /// A hostname, lowercased, in Unicode, without a trailing dot
+    Name(String),
+}
+
+impl HostIdentity {
+    /// The canonical form of `host`, an address literal, bracketed or not,
+    /// or a hostname
+    pub fn new(host: &str) -> Self {
+        let host = host.trim();
+        let bare = host
+            .strip_prefix('[')
+            .and_then(|inner| inner.strip_suffix(']'))
+            .unwrap_or(host);
+        match bare.parse::<IpAddr>() {
+            Ok(IpAddr::V6(v6)) => match v6.to_ipv4_mapped() {
+                Some(v4) => HostIdentity::Ip(IpAddr::V4(v4)),
+                None => HostIdentity::Ip(IpAddr::V6(v6)),
+            },
+            Ok(ip) => HostIdentity::Ip(ip),
+            Err(_) => HostIdentity::Name(to_unicode(bare.trim_end_matches('.')).to_lowercase()),
+        }
+    }
+
+    /// The address, if this is an address literal
+    pub fn as_ip(&self) -> Option<IpAddr> {
+        match self {
+            HostIdentity::Ip(ip) => Some(*ip),
+            HostIdentity::Name(_) => None,
+        }
+    }
+
+    /// Whether `a` and `b` refer to the same host under `policy`
+    ///
+    /// # Parameters
+    ///
+    /// * `a`, `b` - hostnames or address literals, without ports
+    /// * `policy` - how strictly to compare them
+    ///
+    /// # Returns
+    /// Whether they are the same host
+    pub fn matches(a: &str, b: &str, policy: IdentityPolicy) -> bool {
+        if a == b {
+            return true;
+        }
+        let resolver = match policy {
+            IdentityPolicy::Literal => return false,
+            IdentityPolicy::Normalized => None,
+            IdentityPolicy::Resolved(resolver) => Some(resolver),
+        };
+        let (a, b) = (Self::new(a), Self::new(b));
+        if a == b {
+            return true;
+        }
+        match resolver {
+            Some(resolver) => match (a.addrs(resolver), b.addrs(resolver)) {
+                (Some(a), Some(b)) => !a.is_disjoint(&b),
+                _ => false,
+            },
+            None => false,
+        }
+    }
+
+    /// The addresses this resolves to, or None if it does not resolve
+    fn addrs(&self, resolver: &dyn Resolve) -> Option<HashSet<IpAddr>> {
+        match self {
+            HostIdentity::Ip(ip) => Some(std::iter::once(*ip).collect()),
+            HostIdentity::Name(name) => resolver
+                .resolve(name)
+                .ok()
+                .map(|addrs| addrs.into_iter().collect()),
+        }
+    }
+}
+
+impl fmt::Display for HostIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HostIdentity::Ip(ip) => ip.fmt(f),
+            HostIdentity::Name(name) => name.fmt(f),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::StaticResolver;
+
+    #[test]
+    fn hosts_are_normalized() {
+        assert_eq!(
+            HostIdentity::new("xn--bcher-kva.Example."),
+            HostIdentity::new("bücher.example")
+        );
+        assert_eq!(
+            HostIdentity::new("::ffff:192.0.2.1"),
+            HostIdentity::Ip("192.0.2.1".parse().unwrap())
+        );
+        assert_eq!(HostIdentity::new("[::1]").to_string(), "::1");
+        let same = |a, b| HostIdentity::matches(a, b, IdentityPolicy::Normalized);
+        assert!(!same("www.example.com", "example.com"));
+        assert!(same("10.0.0.1", "::ffff:10.0.0.1"));
+    }
+    #[test]
+    fn resolved_hosts_match_on_shared_addresses() {
+        let resolver = StaticResolver::new()
+            .with_entry(
+                "api.example.com",
+                vec!["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()],
+            )
+            .with_entry("api-v2.example.com", vec!["10.0.0.2".parse().unwrap()])
+            .with_entry("db.example.com", vec!["10.0.0.9".parse().unwrap()]);
+        let same = |a, b| HostIdentity::matches(a, b, IdentityPolicy::Resolved(&resolver));
+        assert!(same("api.example.com", "API-v2.example.com."));
+        assert!(same("api.example.com", "10.0.0.1"));
+        assert!(!same("api.example.com", "db.example.com"));
+        assert!(!same("missing.example.com", "10.0.0.1"));
+        assert!(same("Missing.example.com", "missing.example.com"));
+    }
+}
@@ -0,0 +1,267 @@
+//! Network interfaces, and scoping link-local addresses to them.
+//!
+//! A link-local IPv6 address (`fe80::/10`) is only usable together with the
+//! interface it was seen on, but name resolution returns it bare. When a
+//! name resolves only to link-local addresses, as peers announced over mDNS
+//! on an isolated network do, [`resolve_scoped`] pairs each with the
+//! interfaces it could be reached through.
+use crate::errors::IpaddrConversionError;
+use crate::resolver::Resolve;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr, SocketAddrV6};
+
+/// A network interface and its addresses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interface {
+    pub name: String,
+    /// The index used as an IPv6 scope id
+    pub index: u32,
+    pub is_up: bool,
+    pub is_loopback: bool,
+    pub addrs: Vec<IpAddr>,
+}
+
+impl Interface {
+    /// Whether the interface has an IPv6 link-local address of its own
+    pub fn has_link_local(&self) -> bool {
+        self.addrs.iter().any(is_link_local)
+    }
+}
+
+/// Whether `addr` is an IPv6 unicast link-local address
+pub fn is_link_local(addr: &IpAddr) -> bool {
+    match addr {
+        IpAddr::V6(v6) => v6.segments()[0] & 0xffc0 == 0xfe80,
+        IpAddr::V4(_) => false,
+    }
+}
+
+/// An address, together with the interface it is scoped to if it needs one
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ScopedAddr {
+    pub addr: IpAddr,
+    /// The interface index, or 0 when unscoped
+    pub scope_id: u32,
+    /// The interface name, when scoped
+    pub interface: Option<String>,
+}
+
+impl ScopedAddr {
+    /// The socket address for connecting to `port`, carrying the scope id
+    pub fn socket_addr(&self, port: u16) -> SocketAddr {
+        match self.addr {
+            IpAddr::V6(v6) => SocketAddrV6::new(v6, port, 0, self.scope_id).into(),
+            IpAddr::V4(v4) => SocketAddr::new(v4.into(), port),
+        }
+    }
+}
+
+impl From<IpAddr> for ScopedAddr {
+    fn from(addr: IpAddr) -> Self {
+        ScopedAddr {
+            addr,
+            scope_id: 0,
+            interface: None,
+        }
+    }
+}
+
+impl fmt::Display for ScopedAddr {
+    /// Written `fe80::1%eth0` when scoped, as in RFC 4007
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.interface {
+            Some(ref name) => write!(f, "{}%{}", self.addr, name),
+            None => self.addr.fmt(f),
+        }
+    }
+}
+
+/// The interfaces a link-local address could be reached through: those up,
+/// not loopback and with a link-local address of their own, or failing that
+/// any up and not loopback
+pub fn candidate_interfaces(interfaces: &[Interface]) -> Vec<&Interface> {
+    let usable: Vec<&Interface> = interfaces
+        .iter()
+        .filter(|iface| iface.is_up && !iface.is_loopback)
+        .collect();
+    let with_link_local: Vec<&Interface> = usable
+        .iter()
+        .copied()
+        .filter(|iface| iface.has_link_local())
+        .collect();
+    if with_link_local.is_empty() {
+        usable
+    } else {
+        with_link_local
+    }
+}
+
+/// Scope `addrs` to `interfaces`.
+///
+/// Each link-local address becomes one scoped address per candidate
+/// interface; other addresses are passed through unscoped.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::iface::{scope_addrs, Interface};
+///
+/// let eth0 = Interface {
+///     name: "eth0".into(),
+///     index: 2,
+///     is_up: true,
+///     is_loopback: false,
+///     addrs: vec!["fe80::2".parse().unwrap()],
+/// };
+/// let scoped = scope_addrs(&["fe80::1".parse().unwrap()], &[eth0]);
+/// assert_eq!(scoped[0].to_string(), "fe80::1%eth0");
+/// assert_eq!(scoped[0].socket_addr(80).to_string(), "[fe80::1%2]:80");
+/// ```
+pub fn scope_addrs(addrs: &[IpAddr], interfaces: &[Interface]) -> Vec<ScopedAddr> {
+    let candidates = candidate_interfaces(interfaces);
+    let mut scoped = Vec::new();
+    for addr in addrs {
+        if !is_link_local(addr) {
+            scoped.push(ScopedAddr::from(*addr));
+            continue;
+        }
+        scoped.extend(candidates.iter().map(|iface| ScopedAddr {
+            addr: *addr,
+            scope_id: iface.index,
+            interface: Some(iface.name.clone()),
+        }));
+    }
+    scoped
+}
+
+/// Resolve `host` through `resolver`, scoping the result to this host's
+/// interfaces when it holds only link-local addresses.
+///
+/// # Returns
+///
+/// The addresses unscoped when any is routable, as those are preferred, or
+/// the link-local addresses once per candidate interface. It is an error for
+/// there to be no candidate interface.
+pub fn resolve_scoped<R: Resolve + ?Sized>(
+    resolver: &R,
+    host: &str,
+) -> Result<Vec<ScopedAddr>, IpaddrConversionError> {
+    let addrs = resolver.resolve(host)?;
+    if !addrs.iter().all(is_link_local) {
+        return Ok(addrs.into_iter().map(ScopedAddr::from).collect());
+    }
+    let scoped = scope_addrs(&addrs, &interfaces()?);
+    if scoped.is_empty() {
+        return Err(IpaddrConversionError::NotFound(format!(
+            "{}: no interface to reach link-local addresses through",
+            host
+        )));
+    }
+    Ok(scoped)
+}
+
+/// The network interfaces of this host, in the order the system lists them
+#[cfg(unix)]
+pub fn interfaces() -> Result<Vec<Interface>, IpaddrConversionError> {
+    use std::ffi::CStr;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    let mut head: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut head) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let mut interfaces: Vec<Interface> = Vec::new();
+    let mut cursor = head;
+    while !cursor.is_null() {
+        // SAFETY: the list stays valid until freeifaddrs below
+        let entry = unsafe { &*cursor };
+        cursor = entry.ifa_next;
+        let name = unsafe { CStr::from_ptr(entry.ifa_name) };
+        let addr = if entry.ifa_addr.is_null() {
+            None
+        } else {
+            match i32::from(unsafe { (*entry.ifa_addr).sa_family }) {
+                libc::AF_INET => {
+                    let sin = unsafe { &*(entry.ifa_addr as *const libc::sockaddr_in) };
+                    Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                        sin.sin_addr.s_addr,
+                    ))))
+                }
+                libc::AF_INET6 => {
+                    let sin6 = unsafe { &*(entry.ifa_addr as *const libc::sockaddr_in6) };
+                    Some(IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr)))
+                }
+                _ => None,
+            }
+        };
+        let name = name.to_string_lossy();
+        let iface = match interfaces.iter_mut().position(|iface| iface.name == name) {
+            Some(pos) => &mut interfaces[pos],
+            None => {
+                interfaces.push(Interface {
+                    name: name.to_string(),
+                    index: unsafe { libc::if_nametoindex(entry.ifa_name) },
+                    is_up: entry.ifa_flags & libc::IFF_UP as u32 != 0,
+                    is_loopback: entry.ifa_flags & libc::IFF_LOOPBACK as u32 != 0,
+                    addrs: Vec::new(),
+                });
+                interfaces.last_mut().expect("just pushed")
+            }
+        };
+        iface.addrs.extend(addr);
+    }
+    unsafe { libc::freeifaddrs(head) };
+    Ok(interfaces)
+}
+
+/// The network interfaces of this host; not supported on this platform
+#[cfg(not(unix))]
+pub fn interfaces() -> Result<Vec<Interface>, IpaddrConversionError> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        "interface enumeration is not supported on this platform",
+    )
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn iface(name: &str, index: u32, is_up: bool, addrs: &[&str]) -> Interface {
+        Interface {
+            name: name.into(),
+            index,
+            is_up,
+            is_loopback: name == "lo",
+            addrs: addrs.iter().map(|a| a.parse().unwrap()).collect(),
+        }
+    }
+
+    #[test]
+    fn link_local_is_scoped_per_candidate() {
+        let interfaces = vec![
+            iface("lo", 1, true, &["::1", "127.0.0.1"]),
+            iface("eth0", 2, true, &["fe80::a", "10.0.0.2"]),
+            iface("wlan0", 3, true, &["fe80::b"]),
+            iface("eth1", 4, false, &["fe80::c"]),
+            iface("tun0", 5, true, &["10.8.0.1"]),
+        ];
+        let addrs = vec!["fe80::1".parse().unwrap(), "2001:db8::1".parse().unwrap()];
+        let scoped: Vec<String> = scope_addrs(&addrs, &interfaces)
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        assert_eq!(scoped, vec!["fe80::1%eth0", "fe80::1%wlan0", "2001:db8::1"]);
+        // with no link-local addresses anywhere fall back on any usable one
+        let scoped = scope_addrs(&addrs[..1], &interfaces[4..]);
+        assert_eq!(scoped[0].scope_id, 5);
+    }
+    #[test]
+    fn lists_loopback() {
+        let interfaces = interfaces().unwrap();
+        let lo = interfaces.iter().find(|iface| iface.is_loopback).unwrap();
+        assert!(lo.index > 0);
+        assert!(lo.addrs.iter().any(|a| a.is_loopback()));
+    }
+}
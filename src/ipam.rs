@@ -0,0 +1,786 @@
+//! Tracking which addresses and subnets of a lab's pools are in use.
+//!
+//! An [`Allocator`] holds named pools of addresses and the allocations
+//! made from them. It allocates the next free address or aligned subnet of
+//! a pool, reserves specific blocks, reports which allocations a block
+//! would overlap, and persists to a small text file, so a lab can keep its
+//! address plan in a library rather than a service. With the `serde`
+//! feature it also serializes as the lines of that file.
+//!
+//! Allocations can instead be leased for a time, for short-lived test
+//! environments: a lease is renewed while in use, and once it expires its
+//! block returns to the pool and the allocator's expiry hooks are told.
+use crate::address::Address;
+use crate::cancel::CancellationToken;
+use crate::cidr::Cidr;
+use crate::deadline;
+use crate::errors::IpaddrConversionError;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const FILE_HEADER: &str = "# ipaddr-from-str ipam v1";
+
+/// A block allocated from a pool
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Allocation {
+    /// The name of the pool it was allocated from
+    pub pool: String,
+    /// The block, a host route for a single address
+    pub block: Cidr,
+    /// Who or what it was allocated to
+    pub owner: String,
+    /// When a lease expires, None for an allocation which never does
+    pub expires: Option<SystemTime>,
+}
+
+impl Allocation {
+    /// The address of a single address allocation, or the network of a
+    /// subnet
+    pub fn addr(&self) -> IpAddr {
+        self.block.network()
+    }
+
+    /// Whether the allocation is a lease which has expired by `now`
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+}
+
+/// A hook, given each lease as it expires
+type ExpiryHook = Arc<dyn Fn(&Allocation) + Send + Sync>;
+
+#[derive(Debug, Clone)]
+struct Pool {
+    block: Cidr,
+    /// The allocations, by the value of their first address
+    allocations: BTreeMap<u128, Allocation>,
+}
+
+/// The first and last values of `block`
+fn span(block: &Cidr) -> (u128, u128) {
+    (
+        Address::new(block.network()).to_u128(),
+        Address::new(block.last()).to_u128(),
+    )
+}
+
+/// The block of `pool`'s family at `value`
+fn block_at(pool: &Cidr, value: u128, prefix_len: u8) -> Cidr {
+    let addr = match pool.addr() {
+        IpAddr::V4(_) => Address::from_u32(value as u32).ip(),
+        IpAddr::V6(_) => Address::from_u128(value).ip(),
+    };
+    Cidr::new(addr, prefix_len).expect("prefix within width")
+}
+
+/// The block holding only `addr`
+fn host_route(addr: IpAddr) -> Cidr {
+    Cidr::new(addr, Address::new(addr).bits()).expect("full width prefix")
+}
+
+/// Whether `inner` lies entirely within `outer`
+fn covers(outer: &Cidr, inner: &Cidr) -> bool {
+    outer.prefix_len() <= inner.prefix_len() && outer.contains(inner.network())
+}
+
+impl Pool {
+    /// The allocations overlapping the values `start..=end`
+    fn overlapping(&self, start: u128, end: u128) -> impl Iterator<Item = &Allocation> {
+        // allocations never overlap each other, so their ends rise with
+        // their starts
+        self.allocations
+            .range(..=end)
+            .rev()
+            .map(|(_, allocation)| allocation)
+            .take_while(move |allocation| span(&allocation.block).1 >= start)
+    }
+
+    /// The first value of the first free, aligned run of `size` values
+    /// within `lo..=hi`
+    fn find_free(&self, size: u128, lo: u128, hi: u128) -> Option<u128> {
+        let align = |value: u128| value.checked_add(size - 1).map(|end| end & !(size - 1));
+        let mut candidate = align(lo)?;
+        for (&start, allocation) in &self.allocations {
+            let end = span(&allocation.block).1;
+            if end < candidate {
+                continue;
+            }
+            if candidate.checked_add(size - 1)? < start {
+                break;
+            }
+            candidate = align(end.checked_add(1)?)?;
+        }
+        Some(candidate).filter(|candidate| {
+            candidate
+                .checked_add(size - 1)
+                .is_some_and(|last| last <= hi)
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    pools: BTreeMap<String, Pool>,
+}
+
+impl State {
+    /// The name and pool containing all of `block`
+    fn pool_for(&mut self, block: &Cidr) -> Option<(&String, &mut Pool)> {
+        self.pools
+            .iter_mut()
+            .find(|(_, pool)| covers(&pool.block, block))
+    }
+
+    /// Remove the leases expired by `now`, returning them
+    fn sweep(&mut self, now: SystemTime) -> Vec<Allocation> {
+        let mut expired = Vec::new();
+        for pool in self.pools.values_mut() {
+            let starts: Vec<u128> = pool
+                .allocations
+                .iter()
+                .filter(|(_, allocation)| allocation.is_expired(now))
+                .map(|(&start, _)| start)
+                .collect();
+            for start in starts {
+                expired.extend(pool.allocations.remove(&start));
+            }
+        }
+        expired
+    }
+}
+
+/// Pools of addresses and the blocks allocated from them.
+///
+/// Clones share their pools. Expired leases are removed, and the expiry
+/// hooks run, whenever the allocator is next used, or by
+/// [`expire`](Self::expire).
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::ipam::Allocator;
+///
+/// let ipam = Allocator::new();
+/// ipam.add_pool("lab", "10.20.0.0/24".parse().unwrap()).unwrap();
+/// ipam.reserve("10.20.0.1/32".parse().unwrap(), "gateway").unwrap();
+/// assert_eq!(ipam.allocate_addr("lab", "web1").unwrap().to_string(), "10.20.0.2");
+/// assert_eq!(ipam.allocate_subnet("lab", 28, "k8s").unwrap().to_string(), "10.20.0.16/28");
+/// assert_eq!(ipam.conflicts("10.20.0.0/28".parse().unwrap()).len(), 2);
+/// ```
+#[derive(Clone, Default)]
+pub struct Allocator {
+    state: Arc<Mutex<State>>,
+    hooks: Vec<ExpiryHook>,
+}
+
+impl fmt::Debug for Allocator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Allocator")
+            .field("state", &self.state)
+            .field("hooks", &self.hooks.len())
+            .finish()
+    }
+}
+
+impl Allocator {
+    /// An allocator without pools
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `hook` on each lease as it expires, after any hooks already
+    /// added, returning the updated allocator. Hooks run without the
+    /// allocator locked, so they may use it.
+    pub fn with_expiry_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&Allocation) + Send + Sync + 'static,
+    {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// The state, with expired leases removed
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.expire();
+        self.lock_unswept()
+    }
+
+    fn lock_unswept(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Return the blocks of expired leases to their pools, running the
+    /// expiry hooks on each
+    ///
+    /// # Returns
+    /// The leases which expired
+    pub fn expire(&self) -> Vec<Allocation> {
+        let expired = self.lock_unswept().sweep(SystemTime::now());
+        for allocation in &expired {
+            for hook in &self.hooks {
+                hook(allocation);
+            }
+        }
+        expired
+    }
+
+    /// Expire leases every `interval` on a new thread, until `token` is
+    /// cancelled
+    pub fn expire_every(&self, interval: Duration, token: CancellationToken) -> JoinHandle<()> {
+        let allocator = self.clone();
+        thread::spawn(move || {
+            while !token.sleep(interval) {
+                allocator.expire();
+            }
+        })
+    }
+
+    /// Add a pool of the addresses of `block` named `name`
+    ///
+    /// # Returns
+    /// Nothing, or an InvalidInput error if the name is taken or not a single
+    /// word, or the block overlaps another pool
+    pub fn add_pool(&self, name: &str, block: Cidr) -> Result<(), IpaddrConversionError> {
+        let mut state = self.lock();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(IpaddrConversionError::InvalidInput(format!(
+                "pool name '{}' must be a single word",
+                name
+            )));
+        }
+        if state.pools.contains_key(name) {
+            return Err(IpaddrConversionError::InvalidInput(format!(
+                "pool {} already exists",
+                name
+            )));
+        }
+        let network = Cidr::new(block.network(), block.prefix_len()).expect("same prefix length");
+        if let Some((other, _)) = state
+            .pools
+            .iter()
+            .find(|(_, pool)| covers(&pool.block, &network) || covers(&network, &pool.block))
+        {
+            return Err(IpaddrConversionError::InvalidInput(format!(
+                "{} overlaps pool {}",
+                network, other
+            )));
+        }
+        state.pools.insert(
+            name.to_string(),
+            Pool {
+                block: network,
+                allocations: BTreeMap::new(),
+            },
+        );
+        Ok(())
+    }
+
+    /// The pools, by name
+    pub fn pools(&self) -> Vec<(String, Cidr)> {
+        self.lock()
+            .pools
+            .iter()
+            .map(|(name, pool)| (name.clone(), pool.block))
+            .collect()
+    }
+
+    /// Allocate the lowest free address of `pool` to `owner`. The network
+    /// and broadcast addresses of an IPv4 pool are never allocated.
+    ///
+    /// # Returns
+    /// The address, or an InvalidInput error if there is no such pool or it
+    /// has no free address
+    pub fn allocate_addr(&self, pool: &str, owner: &str) -> Result<IpAddr, IpaddrConversionError> {
+        self.take_addr(pool, owner, None)
+    }
+
+    /// Lease the lowest free address of `pool` to `owner` for `ttl`, as
+    /// [`allocate_addr`](Self::allocate_addr) allocates it
+    pub fn lease_addr(
+        &self,
+        pool: &str,
+        owner: &str,
+        ttl: Duration,
+    ) -> Result<IpAddr, IpaddrConversionError> {
+        self.take_addr(pool, owner, Some(deadline::system_time_after(ttl)))
+    }
+
+    fn take_addr(
+        &self,
+        pool: &str,
+        owner: &str,
+        expires: Option<SystemTime>,
+    ) -> Result<IpAddr, IpaddrConversionError> {
+        let mut state = self.lock();
+        let entry = state
+            .pools
+            .get_mut(pool)
+            .ok_or_else(|| unknown_pool(pool))?;
+        let lo = Address::new(entry.block.first_host()).to_u128();
+        let hi = Address::new(entry.block.last_host()).to_u128();
+        let width = Address::new(entry.block.addr()).bits();
+        let value = entry
+            .find_free(1, lo, hi)
+            .ok_or_else(|| IpaddrConversionError::InvalidInput(format!("pool {} is full", pool)))?;
+        let block = block_at(&entry.block, value, width);
+        entry.allocations.insert(
+            value,
+            Allocation {
+                pool: pool.to_string(),
+                block,
+                owner: owner.to_string(),
+                expires,
+            },
+        );
+        Ok(block.network())
+    }
+
+    /// Allocate the lowest free, aligned subnet of `prefix_len` in `pool`
+    /// to `owner`
+    ///
+    /// # Returns
+    /// The subnet, or an InvalidInput error if there is no such pool, the
+    /// prefix does not fit within it, or no subnet that large is free
+    pub fn allocate_subnet(
+        &self,
+        pool: &str,
+        prefix_len: u8,
+        owner: &str,
+    ) -> Result<Cidr, IpaddrConversionError> {
+        self.take_subnet(pool, prefix_len, owner, None)
+    }
+
+    /// Lease the lowest free, aligned subnet of `prefix_len` in `pool` to
+    /// `owner` for `ttl`, as [`allocate_subnet`](Self::allocate_subnet)
+    /// allocates it
+    pub fn lease_subnet(
+        &self,
+        pool: &str,
+        prefix_len: u8,
+        owner: &str,
+        ttl: Duration,
+    ) -> Result<Cidr, IpaddrConversionError> {
+        self.take_subnet(
+            pool,
+            prefix_len,
+            owner,
+            Some(deadline::system_time_after(ttl)),
+        )
+    }
+
+    fn take_subnet(
+        &self,
+        pool: &str,
+        prefix_len: u8,
+        owner: &str,
+        expires: Option<SystemTime>,
+    ) -> Result<Cidr, IpaddrConversionError> {
+        let mut state = self.lock();
+        let entry = state
+            .pools
+            .get_mut(pool)
+            .ok_or_else(|| unknown_pool(pool))?;
+        let width = Address::new(entry.block.addr()).bits();
+        let size = (prefix_len >= entry.block.prefix_len() && prefix_len <= width)
+            .then(|| 1u128.checked_shl((width - prefix_len) as u32))
+            .flatten()
+            .ok_or_else(|| {
+                IpaddrConversionError::InvalidInput(format!(
+                    "a /{} cannot be allocated from pool {}",
+                    prefix_len, pool
+                ))
+            })?;
+        let (lo, hi) = span(&entry.block);
+        let value = entry.find_free(size, lo, hi).ok_or_else(|| {
+            IpaddrConversionError::InvalidInput(format!(
+                "pool {} has no free /{}",
+                pool, prefix_len
+            ))
+        })?;
+        let block = block_at(&entry.block, value, prefix_len);
+        entry.allocations.insert(
+            value,
+            Allocation {
+                pool: pool.to_string(),
+                block,
+                owner: owner.to_string(),
+                expires,
+            },
+        );
+        Ok(block)
+    }
+
+    /// Allocate exactly `block`, a host route for a single address, to
+    /// `owner`
+    ///
+    /// # Returns
+    /// Nothing, or an InvalidInput error if no pool holds the block or it
+    /// overlaps an allocation
+    pub fn reserve(&self, block: Cidr, owner: &str) -> Result<(), IpaddrConversionError> {
+        self.take_block(block, owner, None).map(drop)
+    }
+
+    /// Lease exactly `block` to `owner` for `ttl`, as
+    /// [`reserve`](Self::reserve) allocates it
+    pub fn lease(
+        &self,
+        block: Cidr,
+        owner: &str,
+        ttl: Duration,
+    ) -> Result<(), IpaddrConversionError> {
+        self.take_block(block, owner, Some(deadline::system_time_after(ttl)))
+            .map(drop)
+    }
+
+    /// Allocate `block`, returning the name of its pool
+    fn take_block(
+        &self,
+        block: Cidr,
+        owner: &str,
+        expires: Option<SystemTime>,
+    ) -> Result<String, IpaddrConversionError> {
+        let block = Cidr::new(block.network(), block.prefix_len()).expect("same prefix length");
+        let mut state = self.lock();
+        let (name, pool) = state.pool_for(&block).ok_or_else(|| {
+            IpaddrConversionError::InvalidInput(format!("{} is in no pool", block))
+        })?;
+        let (start, end) = span(&block);
+        if let Some(existing) = pool.overlapping(start, end).next() {
+            return Err(IpaddrConversionError::InvalidInput(format!(
+                "{} overlaps {} allocated to {}",
+                block, existing.block, existing.owner
+            )));
+        }
+        let allocation = Allocation {
+            pool: name.clone(),
+            block,
+            owner: owner.to_string(),
+            expires,
+        };
+        let name = name.clone();
+        pool.allocations.insert(start, allocation);
+        Ok(name)
+    }
+
+    /// Extend the allocation of exactly `block` to expire `ttl` from now.
+    /// Renewing an allocation which never expires makes it a lease.
+    ///
+    /// # Returns
+    /// When the lease now expires, or an InvalidInput error if `block` is
+    /// not allocated or its lease has already expired
+    pub fn renew(&self, block: Cidr, ttl: Duration) -> Result<SystemTime, IpaddrConversionError> {
+        let block = Cidr::new(block.network(), block.prefix_len()).expect("same prefix length");
+        let mut state = self.lock();
+        let allocation = state
+            .pool_for(&block)
+            .and_then(|(_, pool)| pool.allocations.get_mut(&span(&block).0))
+            .filter(|allocation| allocation.block == block)
+            .ok_or_else(|| {
+                IpaddrConversionError::InvalidInput(format!("{} is not allocated", block))
+            })?;
+        let expires = deadline::system_time_after(ttl);
+        allocation.expires = Some(expires);
+        Ok(expires)
+    }
+
+    /// Free the allocation of exactly `block`, returning it
+    pub fn release(&self, block: Cidr) -> Option<Allocation> {
+        let block = Cidr::new(block.network(), block.prefix_len()).expect("same prefix length");
+        let mut state = self.lock();
+        let (_, pool) = state.pool_for(&block)?;
+        let start = span(&block).0;
+        match pool.allocations.get(&start) {
+            Some(allocation) if allocation.block == block => pool.allocations.remove(&start),
+            _ => None,
+        }
+    }
+
+    /// Free the allocation of the single address `addr`, returning it
+    pub fn release_addr(&self, addr: IpAddr) -> Option<Allocation> {
+        self.release(host_route(addr))
+    }
+
+    /// The allocations `block` overlaps, which allocating it would conflict
+    /// with, in address order
+    pub fn conflicts(&self, block: Cidr) -> Vec<Allocation> {
+        let (start, end) = span(&block);
+        let state = self.lock();
+        let mut found: Vec<Allocation> = state
+            .pools
+            .values()
+            .filter(|pool| pool.block.addr().is_ipv4() == block.addr().is_ipv4())
+            .flat_map(|pool| pool.overlapping(start, end).cloned().collect::<Vec<_>>())
+            .collect();
+        found.sort_by_key(|allocation| span(&allocation.block).0);
+        found
+    }
+
+    /// The allocation holding `addr`, if any
+    pub fn owner_of(&self, addr: IpAddr) -> Option<Allocation> {
+        self.conflicts(host_route(addr)).into_iter().next()
+    }
+
+    /// Every allocation, by pool name and then address
+    pub fn allocations(&self) -> Vec<Allocation> {
+        self.lock()
+            .pools
+            .values()
+            .flat_map(|pool| pool.allocations.values().cloned())
+            .collect()
+    }
+
+    /// The number of allocations
+    pub fn len(&self) -> usize {
+        self.lock()
+            .pools
+            .values()
+            .map(|pool| pool.allocations.len())
+            .sum()
+    }
+
+    /// Whether nothing is allocated
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The pools and allocations as lines of text, as [`save`](Self::save)
+    /// writes them: `pool <name> <block>` for each pool, then
+    /// `alloc <pool> <block> <owner>` for each allocation, or
+    /// `lease <pool> <block> <expiry> <owner>` for a lease, its expiry in
+    /// seconds since the Unix epoch
+    pub fn to_lines(&self) -> Vec<String> {
+        let state = self.lock();
+        let pools = state
+            .pools
+            .iter()
+            .map(|(name, pool)| format!("pool {} {}", name, pool.block));
+        let allocations = state.pools.values().flat_map(|pool| {
+            pool.allocations
+                .values()
+                .map(|allocation| match allocation.expires {
+                    Some(expires) => format!(
+                        "lease {} {} {} {}",
+                        allocation.pool,
+                        allocation.block,
+                        expires
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_secs(),
+                        allocation.owner
+                    ),
+                    None => format!(
+                        "alloc {} {} {}",
+                        allocation.pool, allocation.block, allocation.owner
+                    ),
+                })
+        });
+        pools.chain(allocations).collect()
+    }
+
+    /// An allocator holding the pools and allocations of `text`, in the
+    /// form [`to_lines`](Self::to_lines) gives. Leases which have expired
+    /// since are dropped.
+    ///
+    /// # Returns
+    /// The allocator, or an InvalidInput error naming the first line which is
+    /// malformed or conflicts with those before it
+    pub fn parse(text: &str) -> Result<Self, IpaddrConversionError> {
+        let allocator = Self::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad = |why: String| {
+                IpaddrConversionError::InvalidInput(format!("line {}: {}", idx + 1, why))
+            };
+            let fields = if line.starts_with("lease ") { 5 } else { 4 };
+            let fields: Vec<&str> = line.splitn(fields, ' ').collect();
+            let block = |text: &str| {
+                text.parse::<Cidr>()
+                    .map_err(|err| bad(format!("bad block '{}': {}", text, err)))
+            };
+            let (pool, allocated, owner, expires) = match fields.as_slice() {
+                ["pool", name, pool] => {
+                    allocator
+                        .add_pool(name, block(pool)?)
+                        .map_err(|err| bad(err.to_string()))?;
+                    continue;
+                }
+                ["alloc", pool, allocated, owner] => (pool, allocated, owner, None),
+                ["lease", pool, allocated, expiry, owner] => {
+                    let expires = expiry
+                        .parse()
+                        .ok()
+                        .and_then(|secs| UNIX_EPOCH.checked_add(Duration::from_secs(secs)))
+                        .ok_or_else(|| bad(format!("bad expiry '{}'", expiry)))?;
+                    (pool, allocated, owner, Some(expires))
+                }
+                _ => return Err(bad(format!("malformed '{}'", line))),
+            };
+            let allocated = block(allocated)?;
+            let found = allocator
+                .take_block(allocated, owner, expires)
+                .map_err(|err| bad(err.to_string()))?;
+            if found != *pool {
+                return Err(bad(format!("{} is not in pool {}", allocated, pool)));
+            }
+        }
+        Ok(allocator)
+    }
+
+    /// Write the pools and allocations to `path`. The file is written to a
+    /// temporary file first and renamed into place, so a crash never leaves
+    /// a partial plan.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), IpaddrConversionError> {
+        let path = path.as_ref();
+        let mut contents = String::from(FILE_HEADER);
+        contents.push('\n');
+        for line in self.to_lines() {
+            contents.push_str(&line);
+            contents.push('\n');
+        }
+        let tmp = path.with_extension("tmp");
+        fs::File::create(&tmp)?.write_all(contents.as_bytes())?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Read an allocator saved to `path`
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, IpaddrConversionError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+}
+
+fn unknown_pool(pool: &str) -> IpaddrConversionError {
+    IpaddrConversionError::InvalidInput(format!("unknown pool {}", pool))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(text: &str) -> Cidr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn allocations_fill_gaps_and_conflicts_are_found() {
+        let ipam = Allocator::new();
+        ipam.add_pool("lab", cidr("192.0.2.0/29")).unwrap();
+        assert!(ipam.add_pool("other", cidr("192.0.2.4/30")).is_err());
+        assert!(ipam.add_pool("lab", cidr("198.51.100.0/24")).is_err());
+        ipam.reserve(cidr("192.0.2.2/32"), "printer").unwrap();
+        assert!(ipam.reserve(cidr("192.0.2.0/30"), "switch").is_err());
+        assert!(ipam.reserve(cidr("203.0.113.1/32"), "stray").is_err());
+        let addrs: Vec<String> = (0..5)
+            .map(|n| {
+                ipam.allocate_addr("lab", &format!("host{}", n))
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(
+            addrs,
+            [
+                "192.0.2.1",
+                "192.0.2.3",
+                "192.0.2.4",
+                "192.0.2.5",
+                "192.0.2.6"
+            ]
+        );
+        assert!(ipam.allocate_addr("lab", "one-too-many").is_err());
+        assert_eq!(
+            ipam.release_addr("192.0.2.4".parse().unwrap())
+                .unwrap()
+                .owner,
+            "host2"
+        );
+        assert_eq!(
+            ipam.allocate_addr("lab", "again").unwrap().to_string(),
+            "192.0.2.4"
+        );
+        assert_eq!(
+            ipam.owner_of("192.0.2.2".parse().unwrap()).unwrap().owner,
+            "printer"
+        );
+        assert_eq!(ipam.conflicts(cidr("192.0.2.0/30")).len(), 3);
+        assert!(ipam.conflicts(cidr("2001:db8::/32")).is_empty());
+    }
+    #[test]
+    fn subnets_are_aligned_and_plans_persist() {
+        let ipam = Allocator::new();
+        ipam.add_pool("v6", cidr("2001:db8::/56")).unwrap();
+        ipam.reserve(cidr("2001:db8::1/128"), "router one").unwrap();
+        assert_eq!(
+            ipam.allocate_subnet("v6", 64, "a").unwrap(),
+            cidr("2001:db8:0:1::/64")
+        );
+        assert_eq!(
+            ipam.allocate_subnet("v6", 63, "b").unwrap(),
+            cidr("2001:db8:0:2::/63")
+        );
+        assert!(ipam.allocate_subnet("v6", 48, "c").is_err());
+        assert_eq!(ipam.release(cidr("2001:db8:0:1::/64")).unwrap().owner, "a");
+        assert!(ipam.release(cidr("2001:db8:0:1::/64")).is_none());
+        let path = std::env::temp_dir().join(format!("ipaddr-ipam-{}.txt", std::process::id()));
+        ipam.save(&path).unwrap();
+        let loaded = Allocator::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.to_lines(), ipam.to_lines());
+        assert_eq!(loaded.allocations()[0].owner, "router one");
+        assert!(Allocator::parse("pool v6 2001:db8::/56\nalloc v4 2001:db8::5/128 x").is_err());
+    }
+    #[test]
+    fn expired_leases_return_to_the_pool() {
+        let expired = Arc::new(Mutex::new(Vec::new()));
+        let seen = expired.clone();
+        let ipam = Allocator::new().with_expiry_hook(move |allocation| {
+            seen.lock().unwrap().push(allocation.owner.clone());
+        });
+        ipam.add_pool("ci", cidr("10.1.0.0/30")).unwrap();
+        let first = ipam
+            .lease_addr("ci", "job 1", Duration::from_secs(60))
+            .unwrap();
+        let second = ipam.lease_addr("ci", "job 2", Duration::ZERO).unwrap();
+        assert_eq!(second.to_string(), "10.1.0.2");
+        // the expired lease is freed, and its owner told, before allocating
+        assert_eq!(ipam.allocate_addr("ci", "job 3").unwrap(), second);
+        assert_eq!(*expired.lock().unwrap(), ["job 2"]);
+        assert!(ipam.renew(host_route(first), Duration::ZERO).is_ok());
+        assert!(ipam
+            .renew(host_route(first), Duration::from_secs(1))
+            .is_err());
+        assert_eq!(ipam.len(), 1);
+        let lines = Allocator::parse("pool ci 10.1.0.0/30\nlease ci 10.1.0.1/32 4102444800 job 4")
+            .unwrap()
+            .to_lines();
+        assert_eq!(lines[1], "lease ci 10.1.0.1/32 4102444800 job 4");
+        let stale = Allocator::parse("pool ci 10.1.0.0/30\nlease ci 10.1.0.1/32 1 job 5").unwrap();
+        assert!(stale.is_empty());
+    }
+    #[test]
+    fn overlong_leases_are_kept_rather_than_overflowing() {
+        let ipam = Allocator::new();
+        ipam.add_pool("ci", cidr("10.1.0.0/30")).unwrap();
+        let addr = ipam.lease_addr("ci", "job 1", Duration::MAX).unwrap();
+        assert!(ipam.renew(host_route(addr), Duration::MAX).is_ok());
+        let loaded = Allocator::parse(&ipam.to_lines().join("\n")).unwrap();
+        assert_eq!(loaded.len(), 1);
+        let overflowing = format!(
+            "pool ci 10.1.0.0/30\nlease ci 10.1.0.1/32 {} job 2",
+            u64::MAX
+        );
+        assert!(Allocator::parse(&overflowing).is_err());
+    }
+}
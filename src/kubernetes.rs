@@ -0,0 +1,242 @@
+//! Resolution following the conventions of in-cluster Kubernetes DNS.
+//!
+//! Pods get a resolv.conf from the kubelet with a search list of
+//! `<namespace>.svc.<cluster domain>`, `svc.<cluster domain>` and
+//! `<cluster domain>`, and `ndots:5`. [`KubernetesResolver`] reproduces that
+//! expansion on top of any [`Resolve`] implementation, so that `myservice`
+//! resolves the same way it would from inside a pod, and exposes the per-pod
+//! records CoreDNS publishes for headless services.
+use crate::errors::IpaddrConversionError;
+use crate::resolv_conf::search_candidates;
+use crate::resolver::Resolve;
+use std::env;
+use std::fs;
+use std::net::IpAddr;
+
+/// Default cluster domain used by kubeadm and most distributions
+pub const DEFAULT_CLUSTER_DOMAIN: &str = "cluster.local";
+/// ndots value the kubelet writes into pod resolv.conf files
+pub const DEFAULT_NDOTS: usize = 5;
+/// File mounted into pods holding the pod's namespace
+pub const NAMESPACE_FILE: &str = "/var/run/secrets/kubernetes.io/serviceaccount/namespace";
+
+/// The cluster conventions used to expand names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KubernetesConfig {
+    namespace: String,
+    cluster_domain: String,
+    ndots: usize,
+}
+
+impl KubernetesConfig {
+    /// New config for `namespace`, using the default cluster domain and ndots
+    pub fn new<I: Into<String>>(namespace: I) -> Self {
+        Self {
+            namespace: namespace.into(),
+            cluster_domain: DEFAULT_CLUSTER_DOMAIN.to_string(),
+            ndots: DEFAULT_NDOTS,
+        }
+    }
+
+    /// Build a config for the current pod. The namespace is read from the
+    /// `POD_NAMESPACE` environment variable if set, otherwise from the service
+    /// account namespace file.
+    pub fn from_environment() -> Result<Self, IpaddrConversionError> {
+        let namespace = match env::var("POD_NAMESPACE") {
+            Ok(namespace) => namespace,
+            Err(_) => fs::read_to_string(NAMESPACE_FILE)?,
+        };
+        Ok(Self::new(namespace.trim()))
+    }
+
+    /// Set the cluster domain, returning the updated config
+    pub fn with_cluster_domain<I: Into<String>>(mut self, cluster_domain: I) -> Self {
+        self.cluster_domain = cluster_domain.into().trim_matches('.').to_string();
+        self
+    }
+
+    /// Set the ndots threshold, returning the updated config
+    pub fn with_ndots(mut self, ndots: usize) -> Self {
+        self.ndots = ndots;
+        self
+    }
+
+    /// The namespace names are expanded relative to
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// The cluster domain
+    pub fn cluster_domain(&self) -> &str {
+        &self.cluster_domain
+    }
+
+    /// The ndots threshold
+    pub fn ndots(&self) -> usize {
+        self.ndots
+    }
+
+    /// The search list a pod in the namespace would get from the kubelet
+    pub fn search_domains(&self) -> Vec<String> {
+        vec![
+            format!("{}.svc.{}", self.namespace, self.cluster_domain),
+            format!("svc.{}", self.cluster_domain),
+            self.cluster_domain.clone(),
+        ]
+    }
+
+    /// Names to try, in order, when resolving `name`
+    ///
+    /// A trailing dot marks the name as fully qualified and disables expansion.
+    /// Otherwise names with at least `ndots` dots are tried as given before the
+    /// search list, and names with fewer are tried after it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ipaddr_from_str::kubernetes::KubernetesConfig;
+    /// let config = KubernetesConfig::new("prod");
+    /// assert_eq!(config.candidates("db")[0], "db.prod.svc.cluster.local");
+    /// ```
+    pub fn candidates(&self, name: &str) -> Vec<String> {
+        search_candidates(name, &self.search_domains(), self.ndots)
+    }
+}
+
+/// One backing pod of a headless service.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PodEndpoint {
+    /// The pod's address
+    pub addr: IpAddr,
+    /// The per-pod name CoreDNS publishes, eg `10-0-0-5.db.prod.svc.cluster.local`
+    pub hostname: String,
+}
+
+/// The per-pod hostname CoreDNS publishes for `addr` behind the fully
+/// qualified service name `service_fqdn`
+pub fn pod_hostname(addr: &IpAddr, service_fqdn: &str) -> String {
+    let label = addr.to_string().replace(['.', ':'], "-");
+    format!("{}.{}", label, service_fqdn.trim_end_matches('.'))
+}
+
+/// Resolver applying the cluster's search list and ndots rules before
+/// delegating to another resolver.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::kubernetes::{KubernetesConfig, KubernetesResolver};
+/// use ipaddr_from_str::{Resolve, StaticResolver};
+///
+/// let backend = StaticResolver::new()
+///     .with_entry("db.prod.svc.cluster.local", vec!["10.0.0.5".parse().unwrap()]);
+/// let resolver = KubernetesResolver::new(KubernetesConfig::new("prod"), backend);
+/// assert!(resolver.resolve("db").is_ok());
+/// ```
+#[derive(Debug, Clone)]
+pub struct KubernetesResolver<R> {
+    config: KubernetesConfig,
+    resolver: R,
+}
+
+impl<R: Resolve> KubernetesResolver<R> {
+    /// New resolver expanding names per `config` and resolving them with `resolver`
+    pub fn new(config: KubernetesConfig, resolver: R) -> Self {
+        Self { config, resolver }
+    }
+
+    /// The config in use
+    pub fn config(&self) -> &KubernetesConfig {
+        &self.config
+    }
+
+    /// Resolve `name`, returning the candidate which answered along with its
+    /// addresses
+    pub fn resolve_qualified(
+        &self,
+        name: &str,
+    ) -> Result<(String, Vec<IpAddr>), IpaddrConversionError> {
+        if let Ok(ip) = name.parse::<IpAddr>() {
+            return Ok((name.to_string(), vec![ip]));
+        }
+        let mut last_err = None;
+        for candidate in self.config.candidates(name) {
+            match self.resolver.resolve(&candidate) {
+                Ok(addrs) if !addrs.is_empty() => return Ok((candidate, addrs)),
+                Ok(_) => last_err = Some(IpaddrConversionError::NotFound(candidate)),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| IpaddrConversionError::NotFound(name.to_string())))
+    }
+
+    /// Resolve a headless service to every backing pod, along with the
+    /// per-pod hostname of each.
+    pub fn headless_endpoints(
+        &self,
+        service: &str,
+    ) -> Result<Vec<PodEndpoint>, IpaddrConversionError> {
+        let (fqdn, addrs) = self.resolve_qualified(service)?;
+        Ok(addrs
+            .into_iter()
+            .map(|addr| PodEndpoint {
+                hostname: pod_hostname(&addr, &fqdn),
+                addr,
+            })
+            .collect())
+    }
+}
+
+impl<R: Resolve> Resolve for KubernetesResolver<R> {
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        self.resolve_qualified(hostname_or_address)
+            .map(|(_, addrs)| addrs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::StaticResolver;
+
+    #[test]
+    fn short_names_try_search_list_first() {
+        let config = KubernetesConfig::new("prod");
+        assert_eq!(
+            config.candidates("db"),
+            vec![
+                "db.prod.svc.cluster.local",
+                "db.svc.cluster.local",
+                "db.cluster.local",
+                "db"
+            ]
+        );
+    }
+    #[test]
+    fn names_over_ndots_try_as_given_first() {
+        let config = KubernetesConfig::new("prod").with_ndots(2);
+        assert_eq!(config.candidates("www.example.com")[0], "www.example.com");
+        assert_eq!(
+            config.candidates("example.com")[0],
+            "example.com.prod.svc.cluster.local"
+        );
+        assert_eq!(config.candidates("db.")[..], ["db".to_string()]);
+    }
+    #[test]
+    fn headless_endpoints_get_pod_hostnames() {
+        let backend = StaticResolver::new().with_entry(
+            "db.other.svc.cluster.local",
+            vec!["10.0.0.5".parse().unwrap(), "fd00::5".parse().unwrap()],
+        );
+        let resolver = KubernetesResolver::new(KubernetesConfig::new("prod"), backend);
+        let endpoints = resolver.headless_endpoints("db.other").unwrap();
+        assert_eq!(endpoints[0].hostname, "10-0-0-5.db.other.svc.cluster.local");
+        assert_eq!(endpoints[1].hostname, "fd00--5.db.other.svc.cluster.local");
+    }
+    #[test]
+    fn unknown_names_are_not_found() {
+        let resolver =
+            KubernetesResolver::new(KubernetesConfig::new("prod"), StaticResolver::new());
+        assert!(resolver.resolve("missing").is_err());
+    }
+}
@@ -0,0 +1,389 @@
+//! Parsing of DHCP lease files, for resolving LAN hosts DNS does not know.
+//!
+//! Both the dnsmasq lease file, one lease per line, and the ISC dhcpd lease
+//! database, a journal of `lease` blocks in which later blocks supersede
+//! earlier ones for the same address, are understood. Only leases which are
+//! active and unexpired answer lookups; a lease without an expiry never
+//! expires.
+use crate::errors::IpaddrConversionError;
+use crate::resolver::{normalize_name, Resolve};
+use std::convert::TryFrom;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Default location of the dnsmasq lease file
+pub const DNSMASQ_LEASES_PATH: &str = "/var/lib/misc/dnsmasq.leases";
+/// Default location of the ISC dhcpd lease database
+pub const DHCPD_LEASES_PATH: &str = "/var/lib/dhcp/dhcpd.leases";
+
+/// One address handed out by a DHCP server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    pub addr: IpAddr,
+    /// The name the client asked for, if it sent one
+    pub hostname: Option<String>,
+    /// The client's hardware address, if the file records one
+    pub mac: Option<String>,
+    /// When the lease runs out, or None if it never does
+    pub expires: Option<SystemTime>,
+}
+
+impl Lease {
+    /// Whether the lease is still held at `now`
+    pub fn is_active_at(&self, now: SystemTime) -> bool {
+        self.expires.is_none_or(|expires| expires > now)
+    }
+}
+
+/// The leases of a DHCP server.
+///
+/// Names are matched case insensitively, and with a [domain](Self::with_domain)
+/// set, also when qualified with it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LeaseFile {
+    leases: Vec<Lease>,
+    domain: Option<String>,
+}
+
+impl LeaseFile {
+    /// Parse the contents of either kind of lease file, telling them apart by
+    /// whether they hold `lease` blocks
+    pub fn parse(contents: &str) -> Self {
+        if tokens(contents)
+            .windows(3)
+            .any(|w| w[0] == "lease" && w[2] == "{")
+        {
+            Self::parse_dhcpd(contents)
+        } else {
+            Self::parse_dnsmasq(contents)
+        }
+    }
+
+    /// Parse a dnsmasq lease file. Lines which do not parse are skipped.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ipaddr_from_str::leases::LeaseFile;
+    /// use std::time::SystemTime;
+    ///
+    /// let leases = LeaseFile::parse_dnsmasq("0 aa:bb:cc:dd:ee:ff 192.168.1.20 nas 01:aa:bb:cc:dd:ee:ff\n")
+    ///     .with_domain("lan");
+    /// let addr: std::net::IpAddr = "192.168.1.20".parse().unwrap();
+    /// assert_eq!(leases.lookup("nas.lan", SystemTime::now()), vec![addr]);
+    /// ```
+    pub fn parse_dnsmasq(contents: &str) -> Self {
+        let mut leases = Vec::new();
+        for line in contents.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 4 {
+                continue;
+            }
+            let (expiry, addr) = match (fields[0].parse::<u64>(), fields[2].parse::<IpAddr>()) {
+                (Ok(expiry), Ok(addr)) => (expiry, addr),
+                _ => continue,
+            };
+            leases.push(Lease {
+                addr,
+                hostname: Some(fields[3])
+                    .filter(|name| *name != "*")
+                    .map(str::to_string),
+                // IPv6 lines hold the IAID here rather than a hardware address
+                mac: Some(fields[1])
+                    .filter(|mac| mac.contains(':'))
+                    .map(str::to_string),
+                expires: match expiry {
+                    0 => None,
+                    secs => Some(UNIX_EPOCH + Duration::from_secs(secs)),
+                },
+            });
+        }
+        Self {
+            leases,
+            domain: None,
+        }
+    }
+
+    /// Parse an ISC dhcpd lease database. Leases whose binding state is not
+    /// active are dropped, as are blocks which do not parse.
+    pub fn parse_dhcpd(contents: &str) -> Self {
+        let tokens = tokens(contents);
+        let mut leases: Vec<Lease> = Vec::new();
+        let mut depth = 0;
+        let mut i = 0;
+        while i < tokens.len() {
+            let lease_start = depth == 0
+                && tokens[i] == "lease"
+                && tokens.get(i + 2).map(String::as_str) == Some("{");
+            match tokens[i].as_str() {
+                _ if lease_start => {
+                    let (lease, end) = parse_lease_block(&tokens, i + 1);
+                    // a later block is the current state of the address
+                    if let Ok(addr) = tokens[i + 1].parse::<IpAddr>() {
+                        leases.retain(|earlier| earlier.addr != addr);
+                    }
+                    leases.extend(lease);
+                    i = end;
+                    continue;
+                }
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            i += 1;
+        }
+        Self {
+            leases,
+            domain: None,
+        }
+    }
+
+    /// Read and parse the lease file at `path`, of either kind
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, IpaddrConversionError> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    /// Also match names qualified with `domain`, such as `nas.lan` for a
+    /// lease named `nas`, returning the updated lease file
+    pub fn with_domain(mut self, domain: &str) -> Self {
+        self.domain = Some(normalize_name(domain));
+        self
+    }
+
+    /// Every lease in the file, held or not
+    pub fn leases(&self) -> &[Lease] {
+        &self.leases
+    }
+
+    /// The addresses leased under `hostname` and still held at `now`
+    pub fn lookup(&self, hostname: &str, now: SystemTime) -> Vec<IpAddr> {
+        let wanted = normalize_name(hostname);
+        let short = self.domain.as_ref().and_then(|domain| {
+            wanted
+                .strip_suffix(domain.as_str())
+                .and_then(|rest| rest.strip_suffix('.'))
+        });
+        let mut addrs = Vec::new();
+        for lease in &self.leases {
+            let name = match lease.hostname {
+                Some(ref name) => normalize_name(name),
+                None => continue,
+            };
+            let matches = name == wanted || short == Some(name.as_str());
+            if matches && lease.is_active_at(now) && !addrs.contains(&lease.addr) {
+                addrs.push(lease.addr);
+            }
+        }
+        addrs
+    }
+}
+
+impl Resolve for LeaseFile {
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        if let Ok(ip) = hostname_or_address.parse::<IpAddr>() {
+            return Ok(vec![ip]);
+        }
+        let addrs = self.lookup(hostname_or_address, SystemTime::now());
+        if addrs.is_empty() {
+            return Err(IpaddrConversionError::NotFound(
+                hostname_or_address.to_string(),
+            ));
+        }
+        Ok(addrs)
+    }
+}
+
+/// Split a dhcpd lease database into words, quoted strings (unquoted) and
+/// the punctuation `;`, `{` and `}`, dropping comments
+fn tokens(contents: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    let mut chars = contents.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '#' => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '"' => {
+                for c in chars.by_ref() {
+                    if c == '"' {
+                        break;
+                    }
+                    word.push(c);
+                }
+                tokens.push(std::mem::take(&mut word));
+                continue;
+            }
+            ';' | '{' | '}' => {}
+            c if c.is_whitespace() => {}
+            c => {
+                word.push(c);
+                continue;
+            }
+        }
+        if !word.is_empty() {
+            tokens.push(std::mem::take(&mut word));
+        }
+        if let ';' | '{' | '}' = c {
+            tokens.push(c.to_string());
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+    tokens
+}
+
+/// Parse the `lease` block whose address is at `start`, returning the lease
+/// if it is active, and the index just past the block
+fn parse_lease_block(tokens: &[String], start: usize) -> (Option<Lease>, usize) {
+    let addr = tokens[start].parse::<IpAddr>().ok();
+    let mut lease = addr.map(|addr| Lease {
+        addr,
+        hostname: None,
+        mac: None,
+        expires: None,
+    });
+    let mut active = true;
+    let mut i = start + 2;
+    let mut depth = 1;
+    let mut statement: Vec<&str> = Vec::new();
+    while i < tokens.len() && depth > 0 {
+        match tokens[i].as_str() {
+            "{" => depth += 1,
+            "}" => depth -= 1,
+            ";" if depth == 1 => {
+                if let Some(ref mut lease) = lease {
+                    match statement.as_slice() {
+                        ["ends", rest @ ..] => lease.expires = parse_dhcpd_time(rest),
+                        ["binding", "state", state] => active = *state == "active",
+                        ["hardware", "ethernet", mac] => lease.mac = Some(mac.to_string()),
+                        ["client-hostname", name] => lease.hostname = Some(name.to_string()),
+                        _ => {}
+                    }
+                }
+                statement.clear();
+            }
+            word if depth == 1 => statement.push(word),
+            _ => {}
+        }
+        i += 1;
+    }
+    (lease.filter(|_| active), i)
+}
+
+/// The time of an `ends` statement: `never`, `epoch <secs>`, or
+/// `<weekday> <yyyy/mm/dd> <hh:mm:ss>` in UTC. Unparseable times are taken
+/// as never, so that a lease is not lost to a format this does not know.
+fn parse_dhcpd_time(words: &[&str]) -> Option<SystemTime> {
+    match *words {
+        ["epoch", secs, ..] => secs
+            .parse::<u64>()
+            .ok()
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+        [_, date, time, ..] => {
+            let date: Vec<i64> = date.split('/').filter_map(|n| n.parse().ok()).collect();
+            let time: Vec<u64> = time.split(':').filter_map(|n| n.parse().ok()).collect();
+            match (date.as_slice(), time.as_slice()) {
+                (&[year, month, day], &[hour, minute, second]) => {
+                    let days = days_from_civil(year, month, day);
+                    let secs =
+                        u64::try_from(days).ok()? * 86_400 + hour * 3600 + minute * 60 + second;
+                    Some(UNIX_EPOCH + Duration::from_secs(secs))
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Days from 1970-01-01 to the given date of the proleptic Gregorian
+/// calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_from_march = (month + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn dnsmasq_leases_expire() {
+        let leases = LeaseFile::parse(
+            "1700000000 aa:bb:cc:dd:ee:01 192.168.1.10 printer *\n\
+             4000000000 aa:bb:cc:dd:ee:02 192.168.1.11 Laptop 01:aa:bb:cc:dd:ee:02\n\
+             duid 00:01:00:01:2c:1f:aa:bb\n\
+             0 1234 fd00::11 laptop 00:01:00:01\n\
+             0 aa:bb:cc:dd:ee:03 192.168.1.12 * *\n",
+        );
+        assert_eq!(leases.leases().len(), 4);
+        assert_eq!(leases.leases()[3].hostname, None);
+        let at = UNIX_EPOCH + Duration::from_secs(1_800_000_000);
+        assert_eq!(
+            leases.lookup("LAPTOP", at),
+            vec![ip("192.168.1.11"), ip("fd00::11")]
+        );
+        let later = UNIX_EPOCH + Duration::from_secs(4_000_000_001);
+        assert_eq!(leases.lookup("laptop", later), vec![ip("fd00::11")]);
+        assert!(leases.resolve("printer").is_err());
+    }
+    #[test]
+    fn later_dhcpd_blocks_supersede_earlier_ones() {
+        let leases = LeaseFile::parse(
+            r#"# The format of this file is documented in the dhcpd.leases(5) manual page.
+lease 10.0.0.20 {
+  starts 1 2023/11/13 10:00:00;
+  ends 1 2023/11/13 22:00:00;
+  binding state active;
+  hardware ethernet 52:54:00:12:34:56;
+  client-hostname "nas";
+}
+lease 10.0.0.21 {
+  ends never;
+  binding state active;
+  client-hostname "camera";
+}
+lease 10.0.0.20 {
+  ends epoch 4000000000; # 2096/10/02 07:06:40
+  binding state active;
+  hardware ethernet 52:54:00:12:34:56;
+  client-hostname "nas";
+}
+lease 10.0.0.21 {
+  binding state free;
+}
+"#,
+        )
+        .with_domain("lan");
+        let now = UNIX_EPOCH + Duration::from_secs(1_800_000_000);
+        assert_eq!(leases.lookup("nas.lan.", now), vec![ip("10.0.0.20")]);
+        assert_eq!(leases.leases()[0].mac.as_deref(), Some("52:54:00:12:34:56"));
+        assert!(leases.lookup("camera", now).is_empty());
+    }
+    #[test]
+    fn dhcpd_times_are_utc() {
+        assert_eq!(
+            parse_dhcpd_time(&["1", "2023/11/13", "22:00:00"]),
+            Some(UNIX_EPOCH + Duration::from_secs(1_699_912_800))
+        );
+        assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+        assert_eq!(parse_dhcpd_time(&["never"]), None);
+    }
+}
@@ -1,18 +1,143 @@
+#[cfg(not(feature = "hickory"))]
 use dns_lookup::lookup_host;
-use lazy_static::lazy_static;
+#[cfg(feature = "hickory")]
+use hickory::lookup_host;
+#[cfg(feature = "regex")]
 use regex::Regex;
 use std::net::IpAddr;
 use std::str::FromStr;
 
+pub mod adaptive;
+pub mod address;
+pub mod asn;
+pub mod audit;
+pub mod backend;
+pub mod batch;
+pub mod breaker;
+pub mod caa;
+pub mod cache;
+pub mod cancel;
+pub mod captive;
+#[cfg(feature = "c-ares")]
+pub mod cares;
+pub mod cidr;
+pub mod classify;
+#[cfg(feature = "clap")]
+pub mod cli;
+pub mod compare;
+pub mod config;
+pub mod conflict;
+#[cfg(feature = "async")]
+pub mod connect;
+#[cfg(feature = "serde")]
+pub mod de;
+pub mod deadline;
+mod digest;
+pub mod direct;
+pub mod dkim;
+pub mod dmarc;
+pub mod dns;
+pub mod dns64;
+pub mod dnsbl;
+#[cfg(feature = "dot")]
+pub mod dot;
+pub mod dump;
 pub mod errors;
+pub mod extract;
+pub mod fault;
+pub mod feed;
+#[cfg(feature = "probe")]
+pub mod fingerprint;
+#[cfg(feature = "gateway")]
+pub mod gateway;
+#[cfg(feature = "tonic")]
+pub mod grpc;
 pub use errors::IpaddrConversionError;
+#[cfg(feature = "hickory")]
+pub mod hickory;
+pub mod histogram;
+pub mod homograph;
+pub mod hook;
+pub mod host;
+pub mod hosts;
+#[cfg(any(feature = "hyper", feature = "reqwest"))]
+pub mod http;
+pub mod identity;
+pub mod iface;
+pub mod ipam;
+pub mod kubernetes;
+pub mod leases;
+pub mod lint;
+pub mod lookup;
+pub use lookup::{lookup_addrs, LookupResult};
+#[cfg(feature = "mrt")]
+pub mod mrt;
+#[cfg(feature = "probe")]
+pub mod mtu;
+pub mod multicast;
+pub mod neighbor;
+#[cfg(feature = "probe")]
+pub mod nmap;
+pub mod nsswitch;
+pub mod parse;
+pub mod pin;
+pub mod policy;
+#[cfg(feature = "probe")]
+pub mod portscan;
+pub mod profile;
+pub mod public_ip;
+pub use profile::get_ipaddr_with_profile;
+pub mod proxy;
+pub mod punycode;
+pub mod rdns;
+pub mod redact;
+pub mod reload;
+pub mod resolv_conf;
+pub mod resolver;
+pub mod route;
+pub mod rpz;
+pub mod sanitize;
+pub mod screen;
+pub mod seeded;
+pub mod selection;
+#[cfg(feature = "tower")]
+pub mod service;
+pub mod socket;
+pub mod spf;
+#[cfg(any(feature = "postgres", feature = "sqlx", feature = "diesel"))]
+mod sql;
+pub mod ssh;
+pub mod stats;
+pub mod stress;
+#[cfg(feature = "probe")]
+pub mod survey;
+#[cfg(feature = "probe")]
+pub mod sweep;
+pub mod template;
+pub mod tenant;
+#[cfg(test)]
+mod test_util;
+pub mod tls;
+#[cfg(feature = "probe")]
+pub mod trace;
+pub mod trie;
+pub mod verify;
+pub mod vip;
+pub mod vlsm;
+pub mod watch;
+pub use resolver::{Resolve, StaticResolver, SystemResolver};
+#[cfg(windows)]
+pub mod win_registry;
 
 /// Retrieve an IpAddr given a &str
 ///
+/// Names are looked up with getaddrinfo, or with hickory when the `hickory`
+/// feature is enabled.
+///
 /// # Parameters
 ///
 /// * `hostname_or_address` - a str reference which may either be a hostname or an
-/// ipv4 address
+///   ipv4 address
 ///
 /// # Returns
 /// May either return an IpAddr enum or an IpaddrConversionError
@@ -36,8 +161,10 @@ pub fn get_ipaddr(hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConver
 ///
 /// # Arguments
 ///
-/// * `input` - the candidate ipv4 address which we will test against a regex to
-/// determine whether it is in the form of an ipv4 address or not.
+/// * `input` - the candidate ipv4 address which we will test to determine
+///   whether it is in the form of an ipv4 address or not: four dot separated
+///   groups of one to three digits, each at most 255. The `regex` feature
+///   tests it against the original regular expression instead.
 ///
 /// # Returns
 /// bool
@@ -48,14 +175,27 @@ pub fn get_ipaddr(hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConver
 /// let result = ipaddr_from_str::is_ipaddrv4("0.0.0.0");
 /// assert!(result);
 /// ```
+#[cfg(not(feature = "regex"))]
 pub fn is_ipaddrv4(input: &str) -> bool {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(r"^(\d{1,3})\.(\d{1,3})\.(\d{1,3})\.(\d{1,3})$").unwrap();
-    }
-    if let Some(val) = RE.captures_iter(input).next() {
+    let mut groups = 0;
+    input.split('.').all(|group| {
+        groups += 1;
+        (1..=3).contains(&group.len())
+            && group.bytes().all(|b| b.is_ascii_digit())
+            && group.parse::<u8>().is_ok()
+    }) && groups == 4
+}
+
+/// Test if input is an ipaddr v4, against a regular expression
+#[cfg(feature = "regex")]
+pub fn is_ipaddrv4(input: &str) -> bool {
+    static RE: std::sync::OnceLock<Regex> = std::sync::OnceLock::new();
+    let re =
+        RE.get_or_init(|| Regex::new(r"^(\d{1,3})\.(\d{1,3})\.(\d{1,3})\.(\d{1,3})$").unwrap());
+    if let Some(val) = re.captures_iter(input).next() {
         val.iter() // we only have one group
             .skip(1) // drop the whole match
-            .map(|v| v.map_or(None, |x| x.as_str().parse::<u8>().ok()))
+            .map(|v| v.and_then(|x| x.as_str().parse::<u8>().ok()))
             .all(|v| v.is_some())
     } else {
         false
@@ -63,6 +203,7 @@ pub fn is_ipaddrv4(input: &str) -> bool {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
     use std::net::Ipv4Addr;
@@ -88,6 +229,13 @@ mod tests {
         assert_eq!(result, false);
     }
     #[test]
+    fn leading_zeros_and_trailing_text_match_the_regex() {
+        assert!(is_ipaddrv4("010.0.0.001"));
+        assert!(!is_ipaddrv4("10.0.0.1\n"));
+        assert!(!is_ipaddrv4("10.0.0.1."));
+        assert!(!is_ipaddrv4("1.2.3.0001"));
+    }
+    #[test]
     fn can_get_ipaddr_from_ip() {
         let ip = get_ipaddr("0.0.0.0").unwrap();
         let expect = vec![IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0))];
@@ -0,0 +1,199 @@
+//! Checking IPv6 text against the canonical form of RFC 5952.
+//!
+//! Many spellings parse to the same IPv6 address, which makes configuration
+//! hard to diff and grep. [`check_ipv6`] reports every way a valid address
+//! departs from the canonical spelling, with a stable code for each, so
+//! review tooling can enforce one form.
+use crate::parse::{parse_ipv6, ParseError};
+use std::fmt;
+
+/// A way in which IPv6 text is not canonical
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Ipv6Issue {
+    /// Hex digits must be lowercase
+    Uppercase,
+    /// Groups must not have leading zeros
+    LeadingZeros,
+    /// A run of two or more zero groups must be written as `::`
+    ZerosNotCompressed,
+    /// `::` must not stand for a single zero group
+    SingleZeroCompressed,
+    /// `::` must compress the longest run of zero groups, the first if tied
+    NotLongestZeroRun,
+    /// Dotted IPv4 notation is only canonical for IPv4-mapped addresses
+    EmbeddedIpv4,
+}
+
+impl Ipv6Issue {
+    /// Stable machine readable name of the issue
+    pub fn code(&self) -> &'static str {
+        match self {
+            Ipv6Issue::Uppercase => "uppercase-hex",
+            Ipv6Issue::LeadingZeros => "leading-zeros",
+            Ipv6Issue::ZerosNotCompressed => "zeros-not-compressed",
+            Ipv6Issue::SingleZeroCompressed => "single-zero-compressed",
+            Ipv6Issue::NotLongestZeroRun => "not-longest-zero-run",
+            Ipv6Issue::EmbeddedIpv4 => "embedded-ipv4",
+        }
+    }
+}
+
+/// An issue found, and the byte offset of the text it concerns
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Finding {
+    pub offset: usize,
+    pub issue: Ipv6Issue,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at byte {}", self.issue.code(), self.offset)
+    }
+}
+
+/// The first longest run of at least two zero groups, as (start, length)
+fn longest_zero_run(segments: &[u16; 8]) -> Option<(usize, usize)> {
+    let mut best: Option<(usize, usize)> = None;
+    let mut start = 0;
+    while start < 8 {
+        let len = segments[start..].iter().take_while(|&&s| s == 0).count();
+        if len >= 2 && best.is_none_or(|(_, best_len)| len > best_len) {
+            best = Some((start, len));
+        }
+        start += len.max(1);
+    }
+    best
+}
+
+/// Check that `input` is an IPv6 address in canonical form.
+///
+/// # Parameters
+///
+/// * `input` - the text to check
+///
+/// # Returns
+/// The issues found, in the order they occur in the text and empty when it
+/// is canonical, or a `ParseError` if it is not an IPv6 address at all
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::lint::{check_ipv6, Ipv6Issue};
+///
+/// assert!(check_ipv6("2001:db8::1").unwrap().is_empty());
+/// let issues: Vec<_> = check_ipv6("2001:0DB8:0:0:0:0:0:1")
+///     .unwrap()
+///     .into_iter()
+///     .map(|finding| finding.issue)
+///     .collect();
+/// assert_eq!(
+///     issues,
+///     vec![Ipv6Issue::LeadingZeros, Ipv6Issue::Uppercase, Ipv6Issue::ZerosNotCompressed]
+/// );
+/// ```
+pub fn check_ipv6(input: &str) -> Result<Vec<Finding>, ParseError> {
+    let segments = parse_ipv6(input)?.segments();
+    let mut findings = Vec::new();
+    let mut found = |offset, issue| findings.push(Finding { offset, issue });
+
+    // offsets of each written hex group and of the dotted quad
+    let mut groups = Vec::new();
+    let mut dotted = None;
+    let mut offset = 0;
+    for token in input.split(':') {
+        if token.contains('.') {
+            dotted = Some(offset);
+        } else if !token.is_empty() {
+            groups.push((offset, token));
+        }
+        offset += token.len() + 1;
+    }
+    // where "::" is, and how many groups are written before it
+    let gap = input.find("::").map(|pos| {
+        let before = groups.iter().filter(|&&(offset, _)| offset < pos).count();
+        (pos, before)
+    });
+
+    let best = longest_zero_run(&segments);
+    let zeros_at = |index: usize| groups.get(index).map_or(0, |&(offset, _)| offset);
+    for &(offset, text) in &groups {
+        if text.len() > 1 && text.starts_with('0') {
+            found(offset, Ipv6Issue::LeadingZeros);
+        }
+        if let Some(pos) = text.find(|c: char| c.is_ascii_uppercase()) {
+            found(offset + pos, Ipv6Issue::Uppercase);
+        }
+    }
+    match (gap, best) {
+        (None, Some((start, _))) => found(zeros_at(start), Ipv6Issue::ZerosNotCompressed),
+        (Some((offset, before)), best) => {
+            let written = groups.len() + if dotted.is_some() { 2 } else { 0 };
+            let compressed = (before, 8 - written);
+            if compressed.1 == 1 {
+                found(offset, Ipv6Issue::SingleZeroCompressed);
+            } else if best != Some(compressed) {
+                found(offset, Ipv6Issue::NotLongestZeroRun);
+            }
+        }
+        (None, None) => {}
+    }
+    let mapped = segments[..5].iter().all(|&s| s == 0) && segments[5] == 0xffff;
+    if let Some(offset) = dotted.filter(|_| !mapped) {
+        found(offset, Ipv6Issue::EmbeddedIpv4);
+    }
+    findings.sort_by_key(|finding| finding.offset);
+    Ok(findings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn issues(input: &str) -> Vec<(usize, &'static str)> {
+        check_ipv6(input)
+            .unwrap()
+            .into_iter()
+            .map(|f| (f.offset, f.issue.code()))
+            .collect()
+    }
+
+    #[test]
+    fn canonical_forms_pass() {
+        for input in &[
+            "::",
+            "::1",
+            "1::",
+            "2001:db8::1",
+            "2001:db8:0:1:1:1:1:1",
+            "2001:0:0:1::1",
+            "::ffff:192.0.2.1",
+            "fe80::1:2",
+        ] {
+            assert_eq!(issues(input), vec![], "{}", input);
+            assert_eq!(
+                input.parse::<std::net::Ipv6Addr>().unwrap().to_string(),
+                *input
+            );
+        }
+    }
+    #[test]
+    fn compression_misuse_is_flagged() {
+        assert_eq!(
+            issues("2001:db8::1:1:1:1:1"),
+            vec![(8, "single-zero-compressed")]
+        );
+        assert_eq!(issues("2001::1:0:0:0:1"), vec![(4, "not-longest-zero-run")]);
+        assert_eq!(issues("2001:0::1"), vec![(6, "not-longest-zero-run")]);
+        assert_eq!(issues("1:0:0:2:0:0:0:3"), vec![(8, "zeros-not-compressed")]);
+        assert_eq!(issues("1::2:0:0:0:3"), vec![(1, "not-longest-zero-run")]);
+    }
+    #[test]
+    fn digits_and_embedded_ipv4() {
+        assert_eq!(
+            issues("2001:DB8::01"),
+            vec![(5, "uppercase-hex"), (10, "leading-zeros")]
+        );
+        assert_eq!(issues("64:ff9b::192.0.2.1"), vec![(9, "embedded-ipv4")]);
+        assert!(check_ipv6("2001:db8::g").is_err());
+    }
+}
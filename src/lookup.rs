@@ -0,0 +1,190 @@
+//! Detailed lookup results, in the manner of `hostent`.
+//!
+//! Where [`get_ipaddr`](crate::get_ipaddr) only returns addresses,
+//! [`lookup_addrs`] also reports the canonical name a hostname resolved to,
+//! the aliases passed through on the way, and the family of each address.
+use crate::errors::IpaddrConversionError;
+use crate::punycode::to_unicode;
+use crate::resolver::normalize_name;
+use dns_lookup::{getaddrinfo, lookup_addr, AddrInfoHints, SockType};
+use std::net::IpAddr;
+
+/// `AI_CANONNAME` has the same value on every platform getaddrinfo exists on
+const AI_CANONNAME: i32 = 0x2;
+
+/// Address family of a lookup entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Family {
+    /// IPv4, `AF_INET`
+    V4,
+    /// IPv6, `AF_INET6`
+    V6,
+}
+
+/// A single address returned by a lookup
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LookupEntry {
+    pub addr: IpAddr,
+    pub family: Family,
+}
+
+impl From<IpAddr> for LookupEntry {
+    fn from(addr: IpAddr) -> Self {
+        let family = match addr {
+            IpAddr::V4(_) => Family::V4,
+            IpAddr::V6(_) => Family::V6,
+        };
+        Self { addr, family }
+    }
+}
+
+/// Everything a lookup found out about a name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LookupResult {
+    /// The name the addresses are actually registered under
+    pub canonical_name: String,
+    /// Names which led to the canonical name, starting with the one looked up
+    pub aliases: Vec<String>,
+    /// The addresses found, in the order the backend returned them
+    pub entries: Vec<LookupEntry>,
+}
+
+impl LookupResult {
+    /// Build a result for `name`, recording it as an alias when it differs
+    /// from `canonical_name`
+    pub(crate) fn new(name: &str, canonical_name: Option<String>, addrs: Vec<IpAddr>) -> Self {
+        let name = normalize_name(name);
+        let canonical_name = canonical_name
+            .map(|c| normalize_name(&c))
+            .unwrap_or_else(|| name.clone());
+        let aliases = if canonical_name == name {
+            Vec::new()
+        } else {
+            vec![name]
+        };
+        Self {
+            canonical_name,
+            aliases,
+            entries: addrs.into_iter().map(LookupEntry::from).collect(),
+        }
+    }
+
+    /// Just the addresses, as returned by [`get_ipaddr`](crate::get_ipaddr)
+    pub fn addrs(&self) -> Vec<IpAddr> {
+        self.entries.iter().map(|entry| entry.addr).collect()
+    }
+
+    /// The canonical name with any punycoded labels decoded, for display.
+    /// Compare against `canonical_name` instead, which keeps the ASCII form.
+    pub fn display_name(&self) -> String {
+        to_unicode(&self.canonical_name)
+    }
+
+    /// The aliases with any punycoded labels decoded, for display
+    pub fn display_aliases(&self) -> Vec<String> {
+        self.aliases.iter().map(|alias| to_unicode(alias)).collect()
+    }
+}
+
+/// Look up `hostname_or_address` through the system resolver, reporting its
+/// canonical name as well as its addresses.
+///
+/// getaddrinfo does not report the intermediate names of a CNAME chain, so
+/// the aliases hold at most the name looked up. Use
+/// [`DirectResolver::lookup`](crate::direct::DirectResolver::lookup) to see
+/// the whole chain.
+///
+/// # Parameters
+///
+/// * `hostname_or_address` - a hostname, or an address which is returned as is
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::lookup::{lookup_addrs, Family};
+///
+/// let result = lookup_addrs("127.0.0.1").unwrap();
+/// assert_eq!(result.canonical_name, "127.0.0.1");
+/// assert_eq!(result.entries[0].family, Family::V4);
+/// ```
+pub fn lookup_addrs(hostname_or_address: &str) -> Result<LookupResult, IpaddrConversionError> {
+    if let Ok(ip) = hostname_or_address.parse::<IpAddr>() {
+        return Ok(LookupResult::new(hostname_or_address, None, vec![ip]));
+    }
+    let hints = AddrInfoHints {
+        // one entry per address rather than one per socket type
+        socktype: SockType::Stream.into(),
+        flags: AI_CANONNAME,
+        ..AddrInfoHints::default()
+    };
+    let mut canonical_name = None;
+    let mut addrs = Vec::new();
+    for info in getaddrinfo(Some(hostname_or_address), None, Some(hints))? {
+        let info = info?;
+        if canonical_name.is_none() {
+            canonical_name = info.canonname;
+        }
+        let addr = info.sockaddr.ip();
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+    Ok(LookupResult::new(
+        hostname_or_address,
+        canonical_name,
+        addrs,
+    ))
+}
+
+/// Find the name registered for `addr` through the system resolver.
+///
+/// The name is returned in its ASCII form, as it is in DNS; pass it to
+/// [`to_unicode`] to show it to people.
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::lookup::reverse_lookup;
+/// use ipaddr_from_str::punycode::to_unicode;
+///
+/// let name = reverse_lookup("192.0.2.1".parse().unwrap()).unwrap();
+/// println!("{}", to_unicode(&name));
+/// ```
+pub fn reverse_lookup(addr: IpAddr) -> Result<String, IpaddrConversionError> {
+    Ok(normalize_name(&lookup_addr(&addr)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonical_name_differing_from_query_is_an_alias() {
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let result = LookupResult::new("WWW.example.", Some("web.example".into()), vec![ip]);
+        assert_eq!(result.canonical_name, "web.example");
+        assert_eq!(result.aliases, vec!["www.example"]);
+        assert_eq!(result.addrs(), vec![ip]);
+
+        let result = LookupResult::new("web.example", None, vec![]);
+        assert_eq!(result.canonical_name, "web.example");
+        assert!(result.aliases.is_empty());
+    }
+    #[test]
+    fn display_forms_are_decoded() {
+        let result = LookupResult::new(
+            "xn--bcher-kva.example",
+            Some("cdn.xn--mnchen-3ya.example".into()),
+            vec![],
+        );
+        assert_eq!(result.canonical_name, "cdn.xn--mnchen-3ya.example");
+        assert_eq!(result.display_name(), "cdn.münchen.example");
+        assert_eq!(result.display_aliases(), vec!["bücher.example"]);
+    }
+    #[test]
+    fn can_lookup_localhost() {
+        let result = lookup_addrs("localhost").unwrap();
+        assert!(!result.entries.is_empty());
+        assert!(result.entries.iter().all(|e| e.addr.is_loopback()));
+    }
+}
@@ -0,0 +1,530 @@
+//! Command line front end to the crate.
+use ipaddr_from_str::cidr::{Cidr, MAX_SPLIT};
+use ipaddr_from_str::classify::classify;
+use ipaddr_from_str::direct::DirectResolver;
+use ipaddr_from_str::dns::{RData, Record, RecordType};
+use ipaddr_from_str::extract::find_addrs;
+use ipaddr_from_str::stress::{self, StressConfig};
+use ipaddr_from_str::{get_ipaddr, SystemResolver};
+use std::env;
+use std::fs;
+use std::net::IpAddr;
+use std::process;
+use std::time::Duration;
+
+const USAGE: &str = "usage: ipaddr-from-str <command> [options] <name>...
+
+commands:
+    resolve <name>...     print the addresses of each name
+    query <name>          print the records a nameserver answers with
+    lookup <type> <name>  print the records of one type, such as A, AAAA,
+                          SRV, TXT or MX
+    reverse <addr>...     print the names each address maps back to
+    classify <addr>...    print the special-purpose block of each address
+    cidr contains <block> <addr>...
+                          print whether each address is within the block,
+                          failing unless all are
+    cidr info <block>     print the masks, size and host range of the block
+    cidr split <block> <n>
+                          print the n equal blocks making up the block
+    scan <file>           print every address mentioned in the file
+    stress <name>...      resolve the names repeatedly, reporting latency
+
+options of every command but stress:
+    --json                print a JSON array rather than lines of text
+
+query options:
+    --type <type>         record type, such as AAAA or TYPE65 (default A)
+
+stress options:
+    --qps <n>             lookups per second (default 10)
+    --duration <secs>     how long to run for (default 10)
+    --workers <n>         lookups in flight at once (default 8)
+    --ttl                 query the nameservers directly, also reporting
+                          the TTLs answered and how often they refresh";
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    process::exit(2);
+}
+
+/// The value following an option, parsed, failing with what the option
+/// `needs` if it is missing or does not parse
+fn option_value<T: std::str::FromStr>(
+    args: &mut impl Iterator<Item = String>,
+    option: &str,
+    needs: &str,
+) -> T {
+    args.next()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| usage_error(&format!("{} needs {}", option, needs)))
+}
+
+/// `text` as a JSON string
+fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// `items` as a JSON array of strings
+fn json_strings<T: ToString>(items: &[T]) -> String {
+    let items: Vec<String> = items
+        .iter()
+        .map(|item| json_string(&item.to_string()))
+        .collect();
+    format!("[{}]", items.join(","))
+}
+
+/// Print the JSON objects of a command's results as one array
+fn print_json(objects: &[String]) {
+    println!("[{}]", objects.join(","));
+}
+
+/// The arguments which are not options, and whether `--json` was given,
+/// failing on any other option
+fn positional(args: impl Iterator<Item = String>) -> (Vec<String>, bool) {
+    let mut json = false;
+    let mut positional = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--json" => json = true,
+            option if option.starts_with("--") => {
+                usage_error(&format!("unknown option {}", option))
+            }
+            _ => positional.push(arg),
+        }
+    }
+    (positional, json)
+}
+
+/// Each argument parsed as an address
+fn addrs_of(args: &[String]) -> Vec<IpAddr> {
+    args.iter()
+        .map(|arg| {
+            arg.parse()
+                .unwrap_or_else(|_| usage_error(&format!("{} is not an address", arg)))
+        })
+        .collect()
+}
+
+fn resolve(names: Vec<String>, json: bool) -> i32 {
+    let mut status = 0;
+    let mut objects = Vec::new();
+    for name in names {
+        match get_ipaddr(&name) {
+            Ok(addrs) if json => objects.push(format!(
+                "{{\"name\":{},\"addrs\":{}}}",
+                json_string(&name),
+                json_strings(&addrs)
+            )),
+            Ok(addrs) => {
+                let addrs: Vec<String> = addrs.iter().map(|ip| ip.to_string()).collect();
+                println!("{} {}", name, addrs.join(" "));
+            }
+            Err(err) => {
+                if json {
+                    objects.push(format!(
+                        "{{\"name\":{},\"error\":{}}}",
+                        json_string(&name),
+                        json_string(&err.to_string())
+                    ));
+                } else {
+                    eprintln!("{} {}", name, err);
+                }
+                status = 1;
+            }
+        }
+    }
+    if json {
+        print_json(&objects);
+    }
+    status
+}
+
+/// The data of a record in presentation format
+fn record_data(data: &RData) -> String {
+    match data {
+        RData::A(ip) => ip.to_string(),
+        RData::Aaaa(ip) => ip.to_string(),
+        RData::Cname(target) | RData::Ptr(target) => format!("{}.", target),
+        RData::Mx(mx) => mx.to_string(),
+        RData::Txt(strings) => strings
+            .iter()
+            .map(|string| format!("{:?}", string))
+            .collect::<Vec<_>>()
+            .join(" "),
+        RData::Srv(srv) => srv.to_string(),
+        RData::Caa(caa) => caa.to_string(),
+        RData::Other(bytes) => {
+            let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            format!("\\# {} {}", bytes.len(), hex)
+        }
+    }
+}
+
+/// Query the system's nameservers for the `qtype` records of `name`, and
+/// print the answers
+fn print_records(name: &str, qtype: RecordType, json: bool) -> i32 {
+    let response = DirectResolver::from_system().and_then(|resolver| resolver.query(name, qtype));
+    let answers: Vec<Record> = match response {
+        Ok(response) => response.answers,
+        Err(err) => {
+            eprintln!("{} {}", name, err);
+            return 1;
+        }
+    };
+    if json {
+        let objects: Vec<String> = answers
+            .iter()
+            .map(|record| {
+                format!(
+                    "{{\"name\":{},\"ttl\":{},\"class\":{},\"type\":{},\"data\":{}}}",
+                    json_string(&format!("{}.", record.name)),
+                    record.ttl,
+                    json_string(&record.rclass.to_string()),
+                    json_string(&record.rtype.to_string()),
+                    json_string(&record_data(&record.data))
+                )
+            })
+            .collect();
+        print_json(&objects);
+    } else {
+        for record in &answers {
+            println!(
+                "{}. {} {} {} {}",
+                record.name,
+                record.ttl,
+                record.rclass,
+                record.rtype,
+                record_data(&record.data)
+            );
+        }
+    }
+    0
+}
+
+fn query(mut args: impl Iterator<Item = String>) -> i32 {
+    let mut qtype = RecordType::A;
+    let mut name = None;
+    let mut json = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--type" => {
+                qtype = args
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or_else(|| usage_error("--type needs a record type"))
+            }
+            "--json" => json = true,
+            option if option.starts_with("--") => {
+                usage_error(&format!("unknown option {}", option))
+            }
+            _ if name.is_some() => usage_error("query takes a single name"),
+            _ => name = Some(arg),
+        }
+    }
+    let name = name.unwrap_or_else(|| usage_error("query needs a name"));
+    print_records(&name, qtype, json)
+}
+
+fn lookup(args: impl Iterator<Item = String>) -> i32 {
+    let (args, json) = positional(args);
+    let (qtype, name) = match args.as_slice() {
+        [qtype, name] => (qtype, name),
+        _ => usage_error("lookup needs a record type and a name"),
+    };
+    let qtype = qtype
+        .to_uppercase()
+        .parse()
+        .unwrap_or_else(|_| usage_error(&format!("unknown record type {}", qtype)));
+    print_records(name, qtype, json)
+}
+
+fn reverse(args: impl Iterator<Item = String>) -> i32 {
+    let (args, json) = positional(args);
+    if args.is_empty() {
+        usage_error("reverse needs at least one address");
+    }
+    let resolver = match DirectResolver::from_system() {
+        Ok(resolver) => resolver,
+        Err(err) => {
+            eprintln!("{}", err);
+            return 1;
+        }
+    };
+    let mut status = 0;
+    let mut objects = Vec::new();
+    for addr in addrs_of(&args) {
+        match resolver.ptr(addr) {
+            Ok(names) if json => objects.push(format!(
+                "{{\"addr\":{},\"names\":{}}}",
+                json_string(&addr.to_string()),
+                json_strings(&names)
+            )),
+            Ok(names) => println!("{} {}", addr, names.join(" ")),
+            Err(err) => {
+                if json {
+                    objects.push(format!(
+                        "{{\"addr\":{},\"error\":{}}}",
+                        json_string(&addr.to_string()),
+                        json_string(&err.to_string())
+                    ));
+                } else {
+                    eprintln!("{} {}", addr, err);
+                }
+                status = 1;
+            }
+        }
+    }
+    if json {
+        print_json(&objects);
+    }
+    status
+}
+
+fn classify_addrs(args: impl Iterator<Item = String>) -> i32 {
+    let (args, json) = positional(args);
+    if args.is_empty() {
+        usage_error("classify needs at least one address");
+    }
+    let mut objects = Vec::new();
+    for addr in addrs_of(&args) {
+        let class = classify(addr);
+        if json {
+            objects.push(format!(
+                "{{\"addr\":{},\"version\":{},\"class\":{},\"global\":{}}}",
+                json_string(&addr.to_string()),
+                if addr.is_ipv4() { 4 } else { 6 },
+                json_string(&class.to_string()),
+                class.is_global()
+            ));
+        } else {
+            println!("{} {}", addr, class);
+        }
+    }
+    if json {
+        print_json(&objects);
+    }
+    0
+}
+
+/// A block given on the command line
+fn block_of(arg: &str) -> Cidr {
+    arg.parse()
+        .unwrap_or_else(|err| usage_error(&format!("{} is not a CIDR block: {}", arg, err)))
+}
+
+fn cidr_contains(block: &str, addrs: &[String], json: bool) -> i32 {
+    let block = block_of(block);
+    let mut status = 0;
+    let mut objects = Vec::new();
+    for addr in addrs_of(addrs) {
+        let contained = block.contains(addr);
+        if !contained {
+            status = 1;
+        }
+        if json {
+            objects.push(format!(
+                "{{\"block\":{},\"addr\":{},\"contained\":{}}}",
+                json_string(&block.to_string()),
+                json_string(&addr.to_string()),
+                contained
+            ));
+        } else {
+            println!("{} {}", addr, if contained { "yes" } else { "no" });
+        }
+    }
+    if json {
+        print_json(&objects);
+    }
+    status
+}
+
+fn cidr_info(block: &str, json: bool) -> i32 {
+    let block = block_of(block);
+    let network = Cidr::new(block.network(), block.prefix_len()).expect("same prefix length");
+    let fields = [
+        ("network", network.to_string()),
+        ("netmask", block.netmask().to_string()),
+        ("wildcard", block.wildcard_mask().to_string()),
+        ("first", block.network().to_string()),
+        ("last", block.last().to_string()),
+        ("first_host", block.first_host().to_string()),
+        ("last_host", block.last_host().to_string()),
+    ];
+    if json {
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|(key, value)| format!("\"{}\":{}", key, json_string(value)))
+            .collect();
+        // counts are strings, as an IPv6 count overflows JSON numbers
+        println!(
+            "{{{},\"size\":\"{}\",\"hosts\":\"{}\"}}",
+            fields.join(","),
+            block.size(),
+            block.host_count()
+        );
+    } else {
+        for (key, value) in &fields {
+            println!("{:<11}{}", key, value);
+        }
+        println!("{:<11}{}", "size", block.size());
+        println!("{:<11}{}", "hosts", block.host_count());
+    }
+    0
+}
+
+fn cidr_split(block: &str, count: &str, json: bool) -> i32 {
+    let block = block_of(block);
+    let count: usize = count
+        .parse()
+        .unwrap_or_else(|_| usage_error(&format!("{} is not a number of blocks", count)));
+    let blocks = block.split(count).unwrap_or_else(|| {
+        usage_error(&format!(
+            "{} cannot be split into {} equal blocks; the count must be a power of two \
+             of at most {}",
+            block, count, MAX_SPLIT
+        ))
+    });
+    if json {
+        println!("{}", json_strings(&blocks));
+    } else {
+        for block in &blocks {
+            println!("{}", block);
+        }
+    }
+    0
+}
+
+fn cidr(args: impl Iterator<Item = String>) -> i32 {
+    let (args, json) = positional(args);
+    match args.as_slice() {
+        [command, block, addrs @ ..] if command == "contains" && !addrs.is_empty() => {
+            cidr_contains(block, addrs, json)
+        }
+        [command, block] if command == "info" => cidr_info(block, json),
+        [command, block, count] if command == "split" => cidr_split(block, count, json),
+        _ => usage_error("cidr needs contains, info or split, and their arguments"),
+    }
+}
+
+fn scan(args: impl Iterator<Item = String>) -> i32 {
+    let (args, json) = positional(args);
+    let path = match args.as_slice() {
+        [path] => path,
+        _ => usage_error("scan takes a single file"),
+    };
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) => {
+            eprintln!("{} {}", path, err);
+            return 1;
+        }
+    };
+    let found = find_addrs(&text);
+    if json {
+        let objects: Vec<String> = found
+            .iter()
+            .map(|found| {
+                format!(
+                    "{{\"line\":{},\"column\":{},\"addr\":{},\"class\":{}}}",
+                    found.line,
+                    found.column,
+                    json_string(&found.addr.to_string()),
+                    json_string(&classify(found.addr).to_string())
+                )
+            })
+            .collect();
+        print_json(&objects);
+    } else {
+        for found in &found {
+            println!("{}:{}:{} {}", path, found.line, found.column, found.addr);
+        }
+    }
+    0
+}
+
+fn stress(mut args: impl Iterator<Item = String>) -> i32 {
+    let mut qps = 10.0;
+    let mut duration = 10.0;
+    let mut workers = 8;
+    let mut ttls = false;
+    let mut names = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--qps" => qps = option_value(&mut args, "--qps", "a number of lookups per second"),
+            "--duration" => duration = option_value(&mut args, "--duration", "a number of seconds"),
+            "--workers" => workers = option_value(&mut args, "--workers", "a whole number"),
+            "--ttl" => ttls = true,
+            option if option.starts_with("--") => {
+                usage_error(&format!("unknown option {}", option))
+            }
+            _ => names.push(arg),
+        }
+    }
+    if names.is_empty() {
+        usage_error("stress needs at least one name");
+    }
+    let duration = match Duration::try_from_secs_f64(duration) {
+        Ok(duration) if !duration.is_zero() => duration,
+        _ => usage_error("--duration must be a positive number of seconds"),
+    };
+    let config = StressConfig::new(names, qps)
+        .with_duration(duration)
+        .with_workers(workers);
+    let report = if ttls {
+        DirectResolver::from_system().and_then(|resolver| stress::run_with_ttls(&resolver, &config))
+    } else {
+        stress::run(&SystemResolver, &config)
+    };
+    match report {
+        Ok(report) => {
+            println!("{}", report);
+            0
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        }
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let status = match args.next().as_deref() {
+        Some("resolve") => {
+            let (names, json) = positional(args);
+            if names.is_empty() {
+                usage_error("resolve needs at least one name");
+            }
+            resolve(names, json)
+        }
+        Some("query") => query(args),
+        Some("lookup") => lookup(args),
+        Some("reverse") => reverse(args),
+        Some("classify") => classify_addrs(args),
+        Some("cidr") => cidr(args),
+        Some("scan") => scan(args),
+        Some("stress") => stress(args),
+        Some("-h") | Some("--help") => {
+            println!("{}", USAGE);
+            0
+        }
+        Some(command) => usage_error(&format!("unknown command {}", command)),
+        None => usage_error("missing command"),
+    };
+    process::exit(status);
+}
@@ -0,0 +1,284 @@
+//! Loading MRT routing table dumps (RFC 6396) into prefix to ASN tables.
+//!
+//! Route collectors such as RouteViews and RIPE RIS publish their routing
+//! tables as `TABLE_DUMP_V2` MRT files. [`load_mrt`] reads the origin AS of
+//! every prefix in such a dump into an [`AsnTable`], so addresses can be
+//! mapped to ASes offline and in bulk. Dumps are usually published
+//! compressed, and must be decompressed first. A CSV of prefixes and ASNs
+//! can be loaded with [`AsnTable::load`] instead.
+use crate::asn::{AsnInfo, AsnTable};
+use crate::cidr::Cidr;
+use crate::errors::IpaddrConversionError;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+
+const TABLE_DUMP_V2: u16 = 13;
+const RIB_IPV4_UNICAST: u16 = 2;
+const RIB_IPV6_UNICAST: u16 = 4;
+/// The RFC 8050 subtypes, whose entries carry a path identifier
+const RIB_IPV4_UNICAST_ADDPATH: u16 = 8;
+const RIB_IPV6_UNICAST_ADDPATH: u16 = 10;
+const ATTR_AS_PATH: u8 = 2;
+const ATTR_EXTENDED_LENGTH: u8 = 0x10;
+const AS_SEQUENCE: u8 = 2;
+
+/// Reads big-endian fields off a message, None once it runs out
+struct Fields<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.buf.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+/// The origin AS of a path: the last AS of its last AS_SEQUENCE segment.
+/// TABLE_DUMP_V2 paths always use four byte ASNs.
+fn origin_as(attrs: &[u8]) -> Option<u32> {
+    let mut fields = Fields { buf: attrs, pos: 0 };
+    while let Some(flags) = fields.u8() {
+        let kind = fields.u8()?;
+        let len = if flags & ATTR_EXTENDED_LENGTH != 0 {
+            fields.u16()? as usize
+        } else {
+            fields.u8()? as usize
+        };
+        let value = fields.take(len)?;
+        if kind != ATTR_AS_PATH {
+            continue;
+        }
+        let mut path = Fields { buf: value, pos: 0 };
+        let mut origin = None;
+        while let Some(segment) = path.u8() {
+            let count = path.u8()? as usize;
+            let asns = path.take(count * 4)?;
+            if segment == AS_SEQUENCE && count > 0 {
+                let last = &asns[asns.len() - 4..];
+                origin = Some(u32::from_be_bytes([last[0], last[1], last[2], last[3]]));
+            }
+        }
+        return origin;
+    }
+    None
+}
+
+/// The prefix of a RIB entry message and the origin AS of its first route
+/// which has one
+fn parse_rib(message: &[u8], v4: bool, addpath: bool) -> Option<(Cidr, Option<u32>)> {
+    let mut fields = Fields {
+        buf: message,
+        pos: 0,
+    };
+    fields.u32()?;
+    let prefix_len = fields.u8()?;
+    let prefix = fields.take((prefix_len as usize).div_ceil(8))?;
+    let addr = if v4 {
+        let mut octets = [0u8; 4];
+        octets.get_mut(..prefix.len())?.copy_from_slice(prefix);
+        IpAddr::V4(Ipv4Addr::from(octets))
+    } else {
+        let mut octets = [0u8; 16];
+        octets.get_mut(..prefix.len())?.copy_from_slice(prefix);
+        IpAddr::V6(Ipv6Addr::from(octets))
+    };
+    let block = Cidr::new(addr, prefix_len)?;
+    let entries = fields.u16()?;
+    let mut origin = None;
+    for _ in 0..entries {
+        // peer index and originated time, then the path identifier
+        fields.take(if addpath { 10 } else { 6 })?;
+        let attrs_len = fields.u16()? as usize;
+        let attrs = fields.take(attrs_len)?;
+        origin = origin.or_else(|| origin_as(attrs));
+    }
+    Some((block, origin))
+}
+
+/// Read into `buf` until it is full or the reader ends, returning the
+/// number of bytes read
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+/// Read the prefixes of a `TABLE_DUMP_V2` MRT dump, with their origin ASes,
+/// into a table
+///
+/// # Parameters
+///
+/// * `reader` - the uncompressed dump
+///
+/// # Returns
+/// The table, without registries or countries, which dumps do not carry.
+/// Records of other types are skipped, as are prefixes whose routes have no
+/// AS_SEQUENCE; a truncated or malformed record is an InvalidInput error.
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::mrt::load_mrt_file;
+///
+/// let table = load_mrt_file("rib.20240101.0000").unwrap();
+/// let info = table.lookup("1.1.1.1".parse().unwrap()).unwrap();
+/// println!("{} is announced by AS{}", info.prefix, info.asn);
+/// ```
+pub fn load_mrt<R: Read>(mut reader: R) -> Result<AsnTable, IpaddrConversionError> {
+    let mut table = AsnTable::new();
+    let mut offset = 0u64;
+    let mut message = Vec::new();
+    loop {
+        let mut header = [0u8; 12];
+        match read_full(&mut reader, &mut header)? {
+            0 => return Ok(table),
+            12 => {}
+            _ => {
+                return Err(IpaddrConversionError::InvalidInput(format!(
+                    "MRT dump truncated at byte {}",
+                    offset
+                )))
+            }
+        }
+        let kind = u16::from_be_bytes([header[4], header[5]]);
+        let subtype = u16::from_be_bytes([header[6], header[7]]);
+        let len = u32::from_be_bytes([header[8], header[9], header[10], header[11]]) as usize;
+        message.resize(len, 0);
+        if read_full(&mut reader, &mut message)? != len {
+            return Err(IpaddrConversionError::InvalidInput(format!(
+                "MRT record at byte {} truncated",
+                offset
+            )));
+        }
+        let family = match (kind, subtype) {
+            (TABLE_DUMP_V2, RIB_IPV4_UNICAST) => Some((true, false)),
+            (TABLE_DUMP_V2, RIB_IPV6_UNICAST) => Some((false, false)),
+            (TABLE_DUMP_V2, RIB_IPV4_UNICAST_ADDPATH) => Some((true, true)),
+            (TABLE_DUMP_V2, RIB_IPV6_UNICAST_ADDPATH) => Some((false, true)),
+            _ => None,
+        };
+        if let Some((v4, addpath)) = family {
+            let (prefix, origin) = parse_rib(&message, v4, addpath).ok_or_else(|| {
+                IpaddrConversionError::InvalidInput(format!(
+                    "MRT record at byte {} malformed",
+                    offset
+                ))
+            })?;
+            if let Some(asn) = origin {
+                table.insert(AsnInfo {
+                    asn,
+                    prefix,
+                    registry: None,
+                    country: None,
+                });
+            }
+        }
+        offset += 12 + len as u64;
+    }
+}
+
+/// Read a `TABLE_DUMP_V2` MRT dump from `path`, as [`load_mrt`] does
+pub fn load_mrt_file<P: AsRef<Path>>(path: P) -> Result<AsnTable, IpaddrConversionError> {
+    load_mrt(BufReader::new(File::open(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An MRT record of `subtype` announcing `prefix` with the AS path
+    /// segments given
+    fn rib(subtype: u16, prefix_len: u8, prefix: &[u8], segments: &[(u8, &[u32])]) -> Vec<u8> {
+        let mut path = Vec::new();
+        for (kind, asns) in segments {
+            path.extend_from_slice(&[*kind, asns.len() as u8]);
+            for asn in *asns {
+                path.extend_from_slice(&asn.to_be_bytes());
+            }
+        }
+        // an ORIGIN attribute, then the AS_PATH with an extended length
+        let mut attrs = vec![0x40, 1, 1, 0, 0x50, ATTR_AS_PATH];
+        attrs.extend_from_slice(&(path.len() as u16).to_be_bytes());
+        attrs.extend_from_slice(&path);
+        let mut message = vec![0, 0, 0, 1, prefix_len];
+        message.extend_from_slice(prefix);
+        message.extend_from_slice(&1u16.to_be_bytes());
+        message.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        message.extend_from_slice(&(attrs.len() as u16).to_be_bytes());
+        message.extend_from_slice(&attrs);
+        let mut record = vec![0, 0, 0, 0];
+        record.extend_from_slice(&TABLE_DUMP_V2.to_be_bytes());
+        record.extend_from_slice(&subtype.to_be_bytes());
+        record.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        record.extend_from_slice(&message);
+        record
+    }
+
+    #[test]
+    fn rib_entries_are_loaded() {
+        let mut dump = Vec::new();
+        // a peer index table, which is skipped
+        dump.extend_from_slice(&[0, 0, 0, 0, 0, 13, 0, 1, 0, 0, 0, 2, 0, 0]);
+        dump.extend(rib(
+            RIB_IPV4_UNICAST,
+            24,
+            &[1, 1, 1],
+            &[(AS_SEQUENCE, &[3356, 13335])],
+        ));
+        dump.extend(rib(
+            RIB_IPV4_UNICAST,
+            22,
+            &[192, 0, 0],
+            &[(AS_SEQUENCE, &[174, 64500]), (1, &[64501, 64502])],
+        ));
+        dump.extend(rib(
+            RIB_IPV6_UNICAST,
+            32,
+            &[0x26, 0x06, 0x47, 0x00],
+            &[(AS_SEQUENCE, &[13335])],
+        ));
+        let table = load_mrt(dump.as_slice()).unwrap();
+        assert_eq!(table.len(), 3);
+        let info = table.lookup("1.1.1.1".parse().unwrap()).unwrap();
+        assert_eq!(
+            (info.asn, info.prefix.to_string()),
+            (13335, "1.1.1.0/24".to_string())
+        );
+        // an AS_SET after the sequence leaves its last AS the origin
+        assert_eq!(
+            table.lookup("192.0.3.1".parse().unwrap()).unwrap().asn,
+            64500
+        );
+        assert_eq!(
+            table.lookup("2606:4700::1".parse().unwrap()).unwrap().asn,
+            13335
+        );
+        dump.truncate(dump.len() - 3);
+        assert!(load_mrt(dump.as_slice()).is_err());
+    }
+}
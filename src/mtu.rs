@@ -0,0 +1,196 @@
+//! Finding the largest packet the path to a host carries unfragmented.
+//!
+//! A tunnel or VPN on the way lowers the path MTU below the MTU of the
+//! link, and packets larger than it vanish wherever the ICMP reporting
+//! them is filtered. [`discover_path_mtu`] sends UDP probes with the don't
+//! fragment flag set, starting at the MTU of the route out, and shrinks
+//! them each time a router reports the next link smaller, until one
+//! reaches the host. Enabled by the `probe` feature, on Linux.
+use crate::errors::IpaddrConversionError;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// How long [`discover_path_mtu`] waits for each probe to be answered
+pub const DEFAULT_MTU_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use crate::trace::linux::{queued_error, set_option, wait, QueuedError, IPV6_RECVERR};
+    use crate::trace::TRACE_BASE_PORT;
+    use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+    use std::io;
+    use std::mem;
+    use std::net::{IpAddr, SocketAddr};
+    use std::os::unix::io::AsRawFd;
+    use std::time::{Duration, Instant};
+
+    const IP_MTU_DISCOVER: libc::c_int = 10;
+    const IP_MTU: libc::c_int = 14;
+    const IPV6_MTU_DISCOVER: libc::c_int = 23;
+    const IPV6_MTU: libc::c_int = 24;
+    /// Set don't fragment, and refuse sends larger than the known path MTU
+    const PMTUDISC_DO: libc::c_int = 2;
+    const UNREACHABLE_V4: u8 = 3;
+    const PORT_UNREACHABLE_V4: u8 = 3;
+    const UNREACHABLE_V6: u8 = 1;
+    const PORT_UNREACHABLE_V6: u8 = 4;
+    /// How many probes of a size go unanswered before it is taken to fit
+    const PROBE_ATTEMPTS: usize = 3;
+
+    /// The path MTU the kernel knows for `socket`'s destination
+    fn known_mtu(socket: &Socket, v4: bool) -> io::Result<u32> {
+        let (level, name) = if v4 {
+            (libc::SOL_IP, IP_MTU)
+        } else {
+            (libc::SOL_IPV6, IPV6_MTU)
+        };
+        let mut mtu: libc::c_int = 0;
+        let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                level,
+                name,
+                &mut mtu as *mut libc::c_int as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(mtu as u32)
+    }
+
+    pub(super) fn discover(addr: IpAddr, timeout: Duration) -> io::Result<u32> {
+        let v4 = addr.is_ipv4();
+        // with the lengths of the IP and UDP headers
+        let (domain, header, unreachable, port_unreachable) = if v4 {
+            (Domain::ipv4(), 28, UNREACHABLE_V4, PORT_UNREACHABLE_V4)
+        } else {
+            (Domain::ipv6(), 48, UNREACHABLE_V6, PORT_UNREACHABLE_V6)
+        };
+        let socket = Socket::new(domain, Type::dgram(), Some(Protocol::udp()))?;
+        if v4 {
+            set_option(&socket, libc::SOL_IP, IP_MTU_DISCOVER, PMTUDISC_DO)?;
+            set_option(&socket, libc::SOL_IP, libc::IP_RECVERR, 1)?;
+        } else {
+            set_option(&socket, libc::SOL_IPV6, IPV6_MTU_DISCOVER, PMTUDISC_DO)?;
+            set_option(&socket, libc::SOL_IPV6, IPV6_RECVERR, 1)?;
+        }
+        socket.connect(&SockAddr::from(SocketAddr::new(addr, TRACE_BASE_PORT)))?;
+        // no datagram is larger than its length field allows
+        let mut mtu = known_mtu(&socket, v4)?.min(u32::from(u16::MAX));
+        let mut attempts = 0;
+        'probing: while attempts < PROBE_ATTEMPTS {
+            let size = (mtu as usize).saturating_sub(header);
+            match socket.send(&vec![0u8; size]) {
+                // the kernel already knows the path is smaller
+                Err(err) if err.raw_os_error() == Some(libc::EMSGSIZE) && mtu > header as u32 => {
+                    mtu = known_mtu(&socket, v4)?.min(mtu - 1);
+                    continue;
+                }
+                Err(err) => return Err(err),
+                Ok(_) => attempts += 1,
+            }
+            let started = Instant::now();
+            let mut buf = [0u8; 64];
+            loop {
+                let left = match timeout.checked_sub(started.elapsed()) {
+                    Some(left) if !left.is_zero() => left,
+                    _ => continue 'probing,
+                };
+                let events = wait(&socket, left)?;
+                if events & libc::POLLERR != 0 {
+                    match queued_error(&socket)? {
+                        Some(QueuedError { errno, info, .. })
+                            if errno == libc::EMSGSIZE && info > 0 && info < mtu =>
+                        {
+                            mtu = info;
+                            attempts = 0;
+                            continue 'probing;
+                        }
+                        Some(QueuedError {
+                            from_icmp: true,
+                            kind,
+                            code,
+                            offender,
+                            ..
+                        }) if kind == unreachable => {
+                            if code == port_unreachable && offender == Some(addr) {
+                                return Ok(mtu);
+                            }
+                            return Err(io::Error::other(format!(
+                                "{} is unreachable from {}",
+                                addr,
+                                offender.map_or("a router".to_string(), |from| from.to_string())
+                            )));
+                        }
+                        _ => {}
+                    }
+                } else if events & libc::POLLIN != 0 && socket.recv(&mut buf).is_ok() {
+                    return Ok(mtu);
+                }
+            }
+        }
+        // nothing reported the probes too large
+        Ok(mtu)
+    }
+}
+
+/// The path MTU to `addr`, waiting up to [`DEFAULT_MTU_TIMEOUT`] for each
+/// probe
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::mtu::discover_path_mtu;
+///
+/// let mtu = discover_path_mtu("192.0.2.10".parse().unwrap()).unwrap();
+/// if mtu < 1500 {
+///     println!("something on the way lowers the MTU to {}", mtu);
+/// }
+/// ```
+pub fn discover_path_mtu(addr: IpAddr) -> Result<u32, IpaddrConversionError> {
+    discover_path_mtu_within(addr, DEFAULT_MTU_TIMEOUT)
+}
+
+/// The path MTU to `addr`, waiting up to `timeout` for each probe
+///
+/// # Returns
+/// The largest packet, with its IP header, which the path carries without
+/// fragmenting it, or an error if the host is unreachable or probes cannot
+/// be sent. Where a host silently drops the probes that reach it, the MTU no
+/// router reported too large is given.
+#[cfg(target_os = "linux")]
+pub fn discover_path_mtu_within(
+    addr: IpAddr,
+    timeout: Duration,
+) -> Result<u32, IpaddrConversionError> {
+    Ok(linux::discover(addr, timeout)?)
+}
+
+/// The path MTU to `addr`; not supported on this platform
+#[cfg(not(target_os = "linux"))]
+pub fn discover_path_mtu_within(
+    _addr: IpAddr,
+    _timeout: Duration,
+) -> Result<u32, IpaddrConversionError> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        "discovering the path MTU is not supported on this platform",
+    )
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn loopback_carries_its_mtu() {
+        // the loopback interface's MTU is far above any ethernet's
+        let mtu = discover_path_mtu("127.0.0.1".parse().unwrap()).unwrap();
+        assert!(mtu > 1500);
+    }
+}
@@ -0,0 +1,160 @@
+//! Multicast group addresses.
+//!
+//! Groups are written in configuration like any other address, so this
+//! module checks that one is a group, reports an IPv6 group's scope, maps
+//! a group to the Ethernet address its frames are sent to, and detects the
+//! source-specific multicast ranges (RFC 4607).
+use crate::parse::{parse_ip, ParseError};
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr};
+
+/// The scope of an IPv6 multicast group (RFC 7346)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Ipv6Scope {
+    InterfaceLocal,
+    LinkLocal,
+    RealmLocal,
+    AdminLocal,
+    SiteLocal,
+    OrganizationLocal,
+    Global,
+    /// A scope value with no assigned meaning
+    Unassigned(u8),
+}
+
+impl Ipv6Scope {
+    fn from_bits(bits: u8) -> Self {
+        match bits {
+            0x1 => Ipv6Scope::InterfaceLocal,
+            0x2 => Ipv6Scope::LinkLocal,
+            0x3 => Ipv6Scope::RealmLocal,
+            0x4 => Ipv6Scope::AdminLocal,
+            0x5 => Ipv6Scope::SiteLocal,
+            0x8 => Ipv6Scope::OrganizationLocal,
+            0xe => Ipv6Scope::Global,
+            other => Ipv6Scope::Unassigned(other),
+        }
+    }
+}
+
+/// The scope of `addr`, or `None` if it is not a multicast group
+pub fn ipv6_scope(addr: &Ipv6Addr) -> Option<Ipv6Scope> {
+    if !addr.is_multicast() {
+        return None;
+    }
+    Some(Ipv6Scope::from_bits(addr.octets()[1] & 0x0f))
+}
+
+/// An Ethernet hardware address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let o = self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            o[0], o[1], o[2], o[3], o[4], o[5]
+        )
+    }
+}
+
+/// The Ethernet address frames for the group `addr` are sent to.
+///
+/// IPv4 groups map their low 23 bits under `01:00:5e` (RFC 1112), so 32
+/// groups share each address; IPv6 groups map their low 32 bits under
+/// `33:33` (RFC 2464).
+///
+/// # Returns
+///
+/// The address, or `None` if `addr` is not a multicast group.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::multicast::multicast_mac;
+///
+/// let mac = multicast_mac(&"239.129.1.2".parse().unwrap()).unwrap();
+/// assert_eq!(mac.to_string(), "01:00:5e:01:01:02");
+/// ```
+pub fn multicast_mac(addr: &IpAddr) -> Option<MacAddr> {
+    match addr {
+        IpAddr::V4(v4) if v4.is_multicast() => {
+            let o = v4.octets();
+            Some(MacAddr([0x01, 0x00, 0x5e, o[1] & 0x7f, o[2], o[3]]))
+        }
+        IpAddr::V6(v6) if v6.is_multicast() => {
+            let o = v6.octets();
+            Some(MacAddr([0x33, 0x33, o[12], o[13], o[14], o[15]]))
+        }
+        _ => None,
+    }
+}
+
+/// Whether `addr` is in the source-specific multicast range, `232.0.0.0/8`
+/// or `ff3x::/96`
+pub fn is_ssm(addr: &IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(v4) => v4.octets()[0] == 232,
+        IpAddr::V6(v6) => {
+            let s = v6.segments();
+            s[0] & 0xfff0 == 0xff30 && s[1..6].iter().all(|&g| g == 0)
+        }
+    }
+}
+
+/// Parse a multicast group address.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::multicast::parse_group;
+///
+/// assert!(parse_group("ff02::fb").is_ok());
+/// let err = parse_group("10.0.0.1").unwrap_err();
+/// assert_eq!(err.to_string(), "expected multicast address at byte 0");
+/// ```
+pub fn parse_group(input: &str) -> Result<IpAddr, ParseError> {
+    let addr = parse_ip(input)?;
+    if !addr.is_multicast() {
+        return Err(ParseError::new(0, "multicast address"));
+    }
+    Ok(addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn scopes_and_macs() {
+        let v6 = |text: &str| text.parse::<Ipv6Addr>().unwrap();
+        assert_eq!(ipv6_scope(&v6("ff02::1")), Some(Ipv6Scope::LinkLocal));
+        assert_eq!(ipv6_scope(&v6("ff3e::8000:1")), Some(Ipv6Scope::Global));
+        assert_eq!(ipv6_scope(&v6("ff07::1")), Some(Ipv6Scope::Unassigned(7)));
+        assert_eq!(ipv6_scope(&v6("fe80::1")), None);
+        assert_eq!(
+            multicast_mac(&ip("ff02::1:ff00:1234")).unwrap().to_string(),
+            "33:33:ff:00:12:34"
+        );
+        // the top bit of the second octet is dropped
+        assert_eq!(
+            multicast_mac(&ip("224.129.1.2")),
+            multicast_mac(&ip("224.1.1.2"))
+        );
+        assert_eq!(multicast_mac(&ip("10.0.0.1")), None);
+    }
+    #[test]
+    fn ssm_ranges() {
+        assert!(is_ssm(&ip("232.1.2.3")));
+        assert!(!is_ssm(&ip("239.1.2.3")));
+        assert!(is_ssm(&ip("ff3e::8000:1")));
+        assert!(!is_ssm(&ip("ff3e:1::1")));
+        assert!(!is_ssm(&ip("ff0e::1")));
+    }
+}
@@ -0,0 +1,439 @@
+//! The system's ARP and NDP neighbor tables, mapping addresses to hardware
+//! addresses.
+//!
+//! [`neighbors`] lists the entries the OS has learned for hosts on its
+//! links: from `/proc/net/arp` and `ip -6 neigh` on Linux, and from
+//! `arp -an` and `ndp -an` on macOS and the BSDs. Only hosts recently
+//! talked to appear, so an inventory scan connects to an address, or
+//! [`resolve_macs`] resolves a name, before looking for it. An [`OuiTable`]
+//! names the vendor a hardware address was assigned to.
+use crate::errors::IpaddrConversionError;
+use crate::parse::ParseError;
+use crate::resolver::Resolve;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A 48 bit hardware address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl MacAddr {
+    /// The organizationally unique identifier: the first three octets
+    pub fn oui(&self) -> [u8; 3] {
+        [self.0[0], self.0[1], self.0[2]]
+    }
+
+    /// Whether the address was set locally, as randomized addresses are,
+    /// rather than assigned by the vendor, so that its OUI means nothing
+    pub fn is_locally_administered(&self) -> bool {
+        self.0[0] & 0x02 != 0
+    }
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            a, b, c, d, e, g
+        )
+    }
+}
+
+impl FromStr for MacAddr {
+    type Err = ParseError;
+
+    /// Parse six groups of one or two hex digits, separated by `:` or `-`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut octets = [0u8; 6];
+        let mut offset = 0;
+        for (i, octet) in octets.iter_mut().enumerate() {
+            if i > 0 {
+                match s.as_bytes().get(offset) {
+                    Some(b':') | Some(b'-') => offset += 1,
+                    _ => return Err(ParseError::new(offset, "':' or '-'")),
+                }
+            }
+            let digits = s.as_bytes()[offset..]
+                .iter()
+                .take(2)
+                .take_while(|b| b.is_ascii_hexdigit())
+                .count();
+            if digits == 0 {
+                return Err(ParseError::new(offset, "hex digit"));
+            }
+            *octet = u8::from_str_radix(&s[offset..offset + digits], 16)
+                .map_err(|_| ParseError::new(offset, "hex digit"))?;
+            offset += digits;
+        }
+        if offset != s.len() {
+            return Err(ParseError::new(offset, "end of hardware address"));
+        }
+        Ok(MacAddr(octets))
+    }
+}
+
+/// An entry of the neighbor table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Neighbor {
+    pub addr: IpAddr,
+    pub mac: MacAddr,
+    /// The interface the neighbor was seen on
+    pub interface: String,
+}
+
+/// The neighbors of this host which have a known hardware address, IPv4
+/// entries first
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::neighbor::neighbors;
+///
+/// for neighbor in neighbors().unwrap() {
+///     println!("{} is {} on {}", neighbor.addr, neighbor.mac, neighbor.interface);
+/// }
+/// ```
+#[cfg(target_os = "linux")]
+pub fn neighbors() -> Result<Vec<Neighbor>, IpaddrConversionError> {
+    let mut table = parse_proc_net_arp(&fs::read_to_string("/proc/net/arp")?);
+    // IPv6 neighbors are only exposed over netlink; without iproute2 there
+    // are simply none
+    if let Ok(output) = command_output("ip", &["-6", "neigh", "show"]) {
+        table.extend(parse_ip_neigh(&output));
+    }
+    Ok(table)
+}
+
+/// The neighbors of this host which have a known hardware address, IPv4
+/// entries first
+#[cfg(any(
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd"
+))]
+pub fn neighbors() -> Result<Vec<Neighbor>, IpaddrConversionError> {
+    let mut table = parse_arp_an(&command_output("arp", &["-an"])?);
+    if let Ok(output) = command_output("ndp", &["-an"]) {
+        table.extend(parse_ndp_an(&output));
+    }
+    Ok(table)
+}
+
+/// The neighbors of this host; not supported on this platform
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd"
+)))]
+pub fn neighbors() -> Result<Vec<Neighbor>, IpaddrConversionError> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        "reading the neighbor table is not supported on this platform",
+    )
+    .into())
+}
+
+/// The hardware address the neighbor table holds for `addr`, if any
+pub fn lookup_mac(addr: IpAddr) -> Result<Option<MacAddr>, IpaddrConversionError> {
+    Ok(neighbors()?
+        .into_iter()
+        .find(|neighbor| neighbor.addr == addr)
+        .map(|neighbor| neighbor.mac))
+}
+
+/// Resolve `host` through `resolver` and pair each address with its
+/// hardware address, where the neighbor table knows it.
+///
+/// Addresses beyond the local links never have one: their packets go to a
+/// router.
+pub fn resolve_macs<R: Resolve + ?Sized>(
+    resolver: &R,
+    host: &str,
+) -> Result<Vec<(IpAddr, Option<MacAddr>)>, IpaddrConversionError> {
+    let addrs = resolver.resolve(host)?;
+    let table = neighbors()?;
+    Ok(addrs
+        .into_iter()
+        .map(|addr| {
+            let mac = table.iter().find(|n| n.addr == addr).map(|n| n.mac);
+            (addr, mac)
+        })
+        .collect())
+}
+
+/// Vendor names by OUI, read from a Wireshark `manuf` file or the IEEE's
+/// `oui.txt`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OuiTable {
+    vendors: HashMap<[u8; 3], String>,
+}
+
+impl OuiTable {
+    /// Parse either format. In `manuf` files, entries for blocks longer than
+    /// 24 bits are skipped, and the long vendor name is preferred.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ipaddr_from_str::neighbor::{MacAddr, OuiTable};
+    ///
+    /// let table = OuiTable::parse("00:00:0C\tCisco\tCisco Systems, Inc\n");
+    /// let mac: MacAddr = "00:00:0c:12:34:56".parse().unwrap();
+    /// assert_eq!(table.vendor(&mac), Some("Cisco Systems, Inc"));
+    /// ```
+    pub fn parse(contents: &str) -> Self {
+        let mut vendors = HashMap::new();
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let entry = if line.contains("(hex)") {
+                // oui.txt: "00-00-0C   (hex)\t\tCisco Systems, Inc"
+                let mut parts = line.splitn(2, "(hex)");
+                let prefix = parts.next().unwrap_or("").trim();
+                let name = parts.next().unwrap_or("").trim();
+                Some((prefix, name))
+            } else {
+                let fields: Vec<&str> = line.split('\t').map(str::trim).collect();
+                match fields.as_slice() {
+                    [prefix, short, rest @ ..] => {
+                        Some((*prefix, rest.first().copied().unwrap_or(*short)))
+                    }
+                    _ => None,
+                }
+            };
+            if let Some((prefix, name)) = entry {
+                if let Some(oui) = parse_oui(prefix) {
+                    vendors.insert(oui, name.to_string());
+                }
+            }
+        }
+        Self { vendors }
+    }
+
+    /// Read and parse the table at `path`
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, IpaddrConversionError> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    /// The vendor `mac` was assigned to, or None if it is unknown or the
+    /// address is locally administered
+    pub fn vendor(&self, mac: &MacAddr) -> Option<&str> {
+        if mac.is_locally_administered() {
+            return None;
+        }
+        self.vendors.get(&mac.oui()).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.vendors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vendors.is_empty()
+    }
+}
+
+/// A 24 bit prefix such as `00:00:0C` or `00-00-0C`; longer prefixes, which
+/// carry a `/` length, are not OUIs
+fn parse_oui(prefix: &str) -> Option<[u8; 3]> {
+    let groups: Vec<&str> = prefix.split([':', '-']).collect();
+    match groups.as_slice() {
+        [a, b, c] if !prefix.contains('/') => Some([
+            u8::from_str_radix(a, 16).ok()?,
+            u8::from_str_radix(b, 16).ok()?,
+            u8::from_str_radix(c, 16).ok()?,
+        ]),
+        _ => None,
+    }
+}
+
+/// Run `program` and return what it printed, failing if it did not succeed
+#[cfg(unix)]
+pub(crate) fn command_output(
+    program: &str,
+    args: &[&str],
+) -> Result<String, IpaddrConversionError> {
+    let output = std::process::Command::new(program).args(args).output()?;
+    if !output.status.success() {
+        return Err(
+            std::io::Error::other(format!("{} exited with {}", program, output.status)).into(),
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Entries of Linux's `/proc/net/arp`:
+/// `IP address  HW type  Flags  HW address  Mask  Device`
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_proc_net_arp(contents: &str) -> Vec<Neighbor> {
+    // ATF_COM: the entry is complete
+    const COMPLETE: u32 = 0x02;
+    contents
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let flags = u32::from_str_radix(fields.get(2)?.trim_start_matches("0x"), 16).ok()?;
+            if flags & COMPLETE == 0 {
+                return None;
+            }
+            Some(Neighbor {
+                addr: fields[0].parse().ok()?,
+                mac: fields.get(3)?.parse().ok()?,
+                interface: fields.get(5)?.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Entries of `ip neigh show`:
+/// `fe80::1 dev eth0 lladdr 52:54:00:12:34:56 router REACHABLE`
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_ip_neigh(output: &str) -> Vec<Neighbor> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let after = |key: &str| {
+                let pos = fields.iter().position(|field| *field == key)?;
+                fields.get(pos + 1).copied()
+            };
+            // entries which failed or are still being resolved have no lladdr
+            Some(Neighbor {
+                addr: fields.first()?.parse().ok()?,
+                mac: after("lladdr")?.parse().ok()?,
+                interface: after("dev")?.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Entries of BSD `arp -an`:
+/// `? (192.168.1.1) at 0:1b:2c:3d:4e:5f on en0 ifscope [ethernet]`
+#[cfg_attr(
+    not(any(
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd"
+    )),
+    allow(dead_code)
+)]
+fn parse_arp_an(output: &str) -> Vec<Neighbor> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let addr = fields.get(1)?.trim_start_matches('(').trim_end_matches(')');
+            let at = fields.iter().position(|field| *field == "at")?;
+            let on = fields.iter().position(|field| *field == "on")?;
+            // "(incomplete)" does not parse as a hardware address
+            Some(Neighbor {
+                addr: addr.parse().ok()?,
+                mac: fields.get(at + 1)?.parse().ok()?,
+                interface: fields.get(on + 1)?.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Entries of BSD `ndp -an`, after its header:
+/// `Neighbor  Linklayer Address  Netif  Expire  S  Flags`
+#[cfg_attr(
+    not(any(
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd"
+    )),
+    allow(dead_code)
+)]
+fn parse_ndp_an(output: &str) -> Vec<Neighbor> {
+    output
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            // link-local neighbors are listed with their scope, fe80::1%en0
+            let addr = fields.first()?.split('%').next()?;
+            Some(Neighbor {
+                addr: addr.parse().ok()?,
+                mac: fields.get(1)?.parse().ok()?,
+                interface: fields.get(2)?.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mac(text: &str) -> MacAddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn hardware_addresses_parse_in_common_spellings() {
+        assert_eq!(mac("0:1b:2c:3:4e:5f").to_string(), "00:1b:2c:03:4e:5f");
+        assert_eq!(mac("00-1B-2C-03-4E-5F"), mac("00:1b:2c:03:4e:5f"));
+        let err = "00:1b:2c:03:4e".parse::<MacAddr>().unwrap_err();
+        assert_eq!((err.offset(), err.expected()), (14, "':' or '-'"));
+        assert!("00:1b:2c:03:4e:5f:60".parse::<MacAddr>().is_err());
+        assert!(mac("02:00:00:00:00:01").is_locally_administered());
+    }
+    #[test]
+    fn tables_of_each_platform_parse() {
+        let proc_arp = "IP address       HW type     Flags       HW address            Mask     Device\n\
+                        192.168.1.1      0x1         0x2         52:54:00:12:34:56     *        eth0\n\
+                        192.168.1.7      0x1         0x0         00:00:00:00:00:00     *        eth0\n";
+        let table = parse_proc_net_arp(proc_arp);
+        assert_eq!(table.len(), 1);
+        assert_eq!(table[0].interface, "eth0");
+        let ip_neigh = "fe80::1 dev eth0 lladdr 52:54:00:12:34:56 router REACHABLE\n\
+                        fe80::9 dev eth0 FAILED\n";
+        assert_eq!(
+            parse_ip_neigh(ip_neigh)[..],
+            [Neighbor {
+                addr: "fe80::1".parse().unwrap(),
+                mac: mac("52:54:00:12:34:56"),
+                interface: "eth0".into(),
+            }]
+        );
+        let arp_an = "? (192.168.1.1) at 0:1b:2c:3d:4e:5f on en0 ifscope [ethernet]\n\
+                      ? (192.168.1.9) at (incomplete) on en0 ifscope [ethernet]\n";
+        assert_eq!(parse_arp_an(arp_an)[0].mac, mac("00:1b:2c:3d:4e:5f"));
+        let ndp_an = "Neighbor                        Linklayer Address  Netif Expire    S Flags\n\
+                      fe80::1%en0                     0:1b:2c:3d:4e:5f     en0 23h59m58s S R\n";
+        assert_eq!(
+            parse_ndp_an(ndp_an)[0].addr,
+            "fe80::1".parse::<IpAddr>().unwrap()
+        );
+    }
+    #[test]
+    fn vendors_come_from_either_table_format() {
+        let table = OuiTable::parse(
+            "# Wireshark manuf\n\
+             00:00:0C\tCisco\tCisco Systems, Inc\n\
+             00:1B:2C\tAtronOrg\n\
+             00:50:C2:00:00:00/36\tTLS\tTLS Corp\n\
+             \n\
+             AC-DE-48   (hex)\t\tPRIVATE\n",
+        );
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.vendor(&mac("00:1b:2c:00:00:01")), Some("AtronOrg"));
+        assert_eq!(table.vendor(&mac("ac:de:48:00:11:22")), Some("PRIVATE"));
+        assert_eq!(table.vendor(&mac("02:00:0c:12:34:56")), None);
+    }
+}
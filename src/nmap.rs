@@ -0,0 +1,534 @@
+//! Writing scan results in the output formats of nmap.
+//!
+//! Scan results are more use where the tools that already read nmap's
+//! output can read them too. An [`NmapReport`] gathers what lookups,
+//! sweeps, port scans, fingerprints and traces found about each address,
+//! and writes it as nmap's XML (`-oX`) or greppable (`-oG`) output, so it
+//! can be imported by vulnerability scanners and asset databases, diffed
+//! with `ndiff`, or cut up with `grep` and `awk`. Enabled by the `probe`
+//! feature.
+use crate::fingerprint::{HostFingerprint, HostReport, Service};
+use crate::lookup::LookupResult;
+use crate::portscan::{PortResult, PortScan, PortState};
+use crate::sweep::Alive;
+use crate::trace::Trace;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The version of nmap's XML output written
+pub const XML_OUTPUT_VERSION: &str = "1.05";
+
+/// What is known of one address
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Host {
+    /// The names looked up to the address
+    names: BTreeSet<String>,
+    /// The names the address reverse resolves to
+    ptr_names: BTreeSet<String>,
+    rtt: Option<Duration>,
+    /// Whether a port scan was made
+    scanned: bool,
+    ports: BTreeMap<u16, PortResult>,
+    services: BTreeMap<u16, Service>,
+    os: Option<&'static str>,
+    trace: Option<Trace>,
+}
+
+impl Host {
+    fn is_up(&self) -> bool {
+        self.rtt.is_some()
+            || self.trace.as_ref().is_some_and(|trace| trace.reached)
+            || self
+                .ports
+                .values()
+                .any(|result| result.state != PortState::Filtered)
+    }
+
+    /// nmap's state of the host, and its reason for it
+    fn status(&self) -> (&'static str, &'static str) {
+        if self.rtt.is_some() {
+            ("up", "echo-reply")
+        } else if self.is_up() {
+            ("up", "conn-refused")
+        } else if self.scanned {
+            ("down", "no-response")
+        } else {
+            ("unknown", "user-set")
+        }
+    }
+}
+
+/// Results gathered per address, for writing as nmap output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NmapReport {
+    args: String,
+    started: SystemTime,
+    hosts: BTreeMap<IpAddr, Host>,
+}
+
+/// Seconds since the epoch of `time`
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or_default()
+}
+
+/// `text` escaped for an XML attribute value
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\n' => escaped.push_str("&#10;"),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// `text` with the characters that delimit greppable fields replaced
+fn field(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '/' => '|',
+            ',' | '\t' | '\n' => ' ',
+            c => c,
+        })
+        .collect()
+}
+
+/// nmap's reason for a port's state, as seen by a connect scan
+fn reason(state: PortState) -> &'static str {
+    match state {
+        PortState::Open => "syn-ack",
+        PortState::Closed => "conn-refused",
+        PortState::Filtered => "no-response",
+    }
+}
+
+impl NmapReport {
+    /// An empty report of a run started now with the command line `args`,
+    /// which nmap records so a report says how it was made
+    pub fn new(args: &str) -> Self {
+        Self {
+            args: args.to_string(),
+            started: SystemTime::now(),
+            hosts: BTreeMap::new(),
+        }
+    }
+
+    fn host(&mut self, addr: IpAddr) -> &mut Host {
+        self.hosts.entry(addr).or_default()
+    }
+
+    /// Add the addresses a lookup found, recording the name looked up and
+    /// its aliases as their hostnames, returning the updated report
+    pub fn with_lookup(mut self, lookup: &LookupResult) -> Self {
+        for entry in &lookup.entries {
+            let host = self.host(entry.addr);
+            host.names.insert(lookup.canonical_name.clone());
+            host.names.extend(lookup.aliases.iter().cloned());
+        }
+        self
+    }
+
+    /// Add the name `addr` reverse resolves to, returning the updated report
+    pub fn with_ptr(mut self, addr: IpAddr, name: &str) -> Self {
+        self.host(addr).ptr_names.insert(name.to_string());
+        self
+    }
+
+    /// Add the hosts a sweep found alive, returning the updated report
+    pub fn with_sweep(mut self, alive: &[Alive]) -> Self {
+        for found in alive {
+            self.host(found.addr).rtt = Some(found.rtt);
+        }
+        self
+    }
+
+    /// Add the ports scanned of a host, returning the updated report
+    pub fn with_scan(mut self, scan: &PortScan) -> Self {
+        let host = self.host(scan.addr);
+        host.scanned = true;
+        host.ports
+            .extend(scan.ports.iter().map(|result| (result.port, *result)));
+        self
+    }
+
+    /// Add the services fingerprinted of a host, returning the updated
+    /// report
+    pub fn with_fingerprint(mut self, fingerprint: &HostFingerprint) -> Self {
+        let host = self.host(fingerprint.addr);
+        host.services.extend(
+            fingerprint
+                .services
+                .iter()
+                .map(|service| (service.port, service.clone())),
+        );
+        host.os = fingerprint.os.or(host.os);
+        self
+    }
+
+    /// Add the scans and fingerprints of an inventory, returning the
+    /// updated report
+    pub fn with_reports(self, reports: &[HostReport]) -> Self {
+        reports.iter().fold(self, |report, host| {
+            report
+                .with_scan(&host.scan)
+                .with_fingerprint(&host.fingerprint)
+        })
+    }
+
+    /// Add the route traced to a host, returning the updated report
+    pub fn with_trace(mut self, trace: &Trace) -> Self {
+        self.host(trace.target).trace = Some(trace.clone());
+        self
+    }
+
+    /// The number of addresses reported on
+    pub fn len(&self) -> usize {
+        self.hosts.len()
+    }
+
+    /// Whether no address is reported on
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+
+    /// The report as nmap XML output, as `nmap -oX` writes it
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ipaddr_from_str::nmap::NmapReport;
+    /// use ipaddr_from_str::sweep::Alive;
+    /// use std::time::Duration;
+    ///
+    /// let alive = [Alive { addr: "192.0.2.7".parse().unwrap(), rtt: Duration::from_millis(3) }];
+    /// let xml = NmapReport::new("sweep 192.0.2.0/24").with_sweep(&alive).to_xml();
+    /// assert!(xml.contains(r#"<address addr="192.0.2.7" addrtype="ipv4"/>"#));
+    /// assert!(xml.contains(r#"<times srtt="3000""#));
+    /// ```
+    pub fn to_xml(&self) -> String {
+        let started = unix_secs(self.started);
+        let finished = unix_secs(SystemTime::now());
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE nmaprun>\n");
+        let _ = writeln!(
+            xml,
+            "<nmaprun scanner=\"{}\" args=\"{}\" start=\"{}\" version=\"{}\" xmloutputversion=\"{}\">",
+            env!("CARGO_PKG_NAME"),
+            escape(&self.args),
+            started,
+            env!("CARGO_PKG_VERSION"),
+            XML_OUTPUT_VERSION
+        );
+        let mut up = 0;
+        for (addr, host) in &self.hosts {
+            let (state, why) = host.status();
+            if state == "up" {
+                up += 1;
+            }
+            let _ = writeln!(
+                xml,
+                "<host starttime=\"{}\" endtime=\"{}\">",
+                started, finished
+            );
+            let _ = writeln!(
+                xml,
+                "<status state=\"{}\" reason=\"{}\" reason_ttl=\"0\"/>",
+                state, why
+            );
+            let _ = writeln!(
+                xml,
+                "<address addr=\"{}\" addrtype=\"{}\"/>",
+                addr,
+                if addr.is_ipv4() { "ipv4" } else { "ipv6" }
+            );
+            xml.push_str("<hostnames>\n");
+            for (name, kind) in host
+                .names
+                .iter()
+                .map(|name| (name, "user"))
+                .chain(host.ptr_names.iter().map(|name| (name, "PTR")))
+            {
+                let _ = writeln!(
+                    xml,
+                    "<hostname name=\"{}\" type=\"{}\"/>",
+                    escape(name),
+                    kind
+                );
+            }
+            xml.push_str("</hostnames>\n");
+            if host.scanned {
+                xml.push_str("<ports>");
+                for result in host.ports.values() {
+                    let _ = write!(
+                        xml,
+                        "<port protocol=\"tcp\" portid=\"{}\"><state state=\"{}\" reason=\"{}\" reason_ttl=\"0\"/>",
+                        result.port,
+                        result.state,
+                        reason(result.state)
+                    );
+                    if let Some(service) = host.services.get(&result.port) {
+                        let _ = write!(
+                            xml,
+                            "<service name=\"{}\"",
+                            service.name.unwrap_or("unknown")
+                        );
+                        if let Some(ref product) = service.product {
+                            let _ = write!(xml, " product=\"{}\"", escape(product));
+                        }
+                        if let Some(ref banner) = service.banner {
+                            let _ = write!(xml, " servicefp=\"{}\"", escape(banner));
+                        }
+                        if service.certificate.is_some() {
+                            xml.push_str(" tunnel=\"ssl\"");
+                        }
+                        let method = if service.name.is_some() {
+                            "probed"
+                        } else {
+                            "table"
+                        };
+                        let _ = write!(xml, " method=\"{}\" conf=\"10\"/>", method);
+                        if let Some(ref certificate) = service.certificate {
+                            let _ = write!(
+                                xml,
+                                "<script id=\"ssl-cert\" output=\"Subject: commonName={}&#10;SHA-256: {}\"/>",
+                                escape(certificate.names.common_name.as_deref().unwrap_or_default()),
+                                certificate.sha256
+                            );
+                        }
+                    }
+                    xml.push_str("</port>\n");
+                }
+                xml.push_str("</ports>\n");
+            }
+            if let Some(os) = host.os {
+                let _ = writeln!(
+                    xml,
+                    "<os><osmatch name=\"{}\" accuracy=\"50\" line=\"0\"/></os>",
+                    os
+                );
+            }
+            if let Some(rtt) = host.rtt {
+                let _ = writeln!(
+                    xml,
+                    "<times srtt=\"{}\" rttvar=\"{}\" to=\"{}\"/>",
+                    rtt.as_micros(),
+                    rtt.as_micros(),
+                    (rtt * 4).as_micros().max(100_000)
+                );
+            }
+            if let Some(ref trace) = host.trace {
+                xml.push_str("<trace>\n");
+                for hop in &trace.hops {
+                    let (addr, rtt) = match (hop.addr, hop.rtt) {
+                        (Some(addr), Some(rtt)) => (addr, rtt),
+                        _ => continue,
+                    };
+                    let _ = write!(
+                        xml,
+                        "<hop ttl=\"{}\" ipaddr=\"{}\" rtt=\"{:.2}\"",
+                        hop.ttl,
+                        addr,
+                        rtt.as_secs_f64() * 1000.0
+                    );
+                    if let Some(ref name) = hop.name {
+                        let _ = write!(xml, " host=\"{}\"", escape(name));
+                    }
+                    xml.push_str("/>\n");
+                }
+                xml.push_str("</trace>\n");
+            }
+            xml.push_str("</host>\n");
+        }
+        let _ = writeln!(
+            xml,
+            "<runstats><finished time=\"{}\" elapsed=\"{}\" exit=\"success\"/><hosts up=\"{}\" down=\"{}\" total=\"{}\"/></runstats>",
+            finished,
+            finished.saturating_sub(started),
+            up,
+            self.hosts.len() - up,
+            self.hosts.len()
+        );
+        xml.push_str("</nmaprun>\n");
+        xml
+    }
+
+    /// The report as nmap greppable output, as `nmap -oG` writes it: a line
+    /// with the status of each address, and another with its ports if it
+    /// was scanned
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ipaddr_from_str::nmap::NmapReport;
+    /// use ipaddr_from_str::portscan::{PortResult, PortScan, PortState};
+    ///
+    /// let scan = PortScan {
+    ///     addr: "192.0.2.7".parse().unwrap(),
+    ///     ports: vec![PortResult { port: 22, state: PortState::Open, rtt: None }],
+    /// };
+    /// let grep = NmapReport::new("scan 192.0.2.7").with_scan(&scan).to_greppable();
+    /// assert!(grep.contains("Host: 192.0.2.7 ()\tStatus: Up\n"));
+    /// assert!(grep.contains("Host: 192.0.2.7 ()\tPorts: 22/open/tcp//unknown///\n"));
+    /// ```
+    pub fn to_greppable(&self) -> String {
+        let mut grep = String::new();
+        let _ = writeln!(
+            grep,
+            "# {} {} scan initiated at {} as: {}",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+            unix_secs(self.started),
+            field(&self.args)
+        );
+        for (addr, host) in &self.hosts {
+            let name = host
+                .ptr_names
+                .iter()
+                .chain(host.names.iter())
+                .next()
+                .map(|name| field(name))
+                .unwrap_or_default();
+            let state = match host.status().0 {
+                "up" => "Up",
+                "down" => "Down",
+                _ => "Unknown",
+            };
+            let _ = writeln!(grep, "Host: {} ({})\tStatus: {}", addr, name, state);
+            if !host.scanned {
+                continue;
+            }
+            let ports: Vec<String> = host
+                .ports
+                .values()
+                .map(|result| {
+                    let service = host.services.get(&result.port);
+                    format!(
+                        "{}/{}/tcp//{}//{}/",
+                        result.port,
+                        result.state,
+                        service
+                            .and_then(|service| service.name)
+                            .unwrap_or("unknown"),
+                        service
+                            .and_then(|service| service.product.as_deref())
+                            .map(field)
+                            .unwrap_or_default()
+                    )
+                })
+                .collect();
+            let _ = write!(
+                grep,
+                "Host: {} ({})\tPorts: {}",
+                addr,
+                name,
+                ports.join(", ")
+            );
+            if let Some(os) = host.os {
+                let _ = write!(grep, "\tOS: {}", os);
+            }
+            grep.push('\n');
+        }
+        let up = self.hosts.values().filter(|host| host.is_up()).count();
+        let _ = writeln!(
+            grep,
+            "# {} done at {} -- {} IP addresses ({} hosts up) scanned",
+            env!("CARGO_PKG_NAME"),
+            unix_secs(SystemTime::now()),
+            self.hosts.len(),
+            up
+        );
+        grep
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::Hop;
+
+    fn report() -> NmapReport {
+        let addr: IpAddr = "192.0.2.7".parse().unwrap();
+        let scan = PortScan {
+            addr,
+            ports: vec![
+                PortResult {
+                    port: 22,
+                    state: PortState::Open,
+                    rtt: None,
+                },
+                PortResult {
+                    port: 23,
+                    state: PortState::Closed,
+                    rtt: None,
+                },
+            ],
+        };
+        let fingerprint = HostFingerprint {
+            addr,
+            services: vec![Service {
+                port: 22,
+                name: Some("ssh"),
+                product: Some("OpenSSH_9.6 <FreeBSD>".into()),
+                banner: None,
+                certificate: None,
+            }],
+            os: Some("FreeBSD"),
+        };
+        let trace = Trace {
+            target: addr,
+            hops: vec![Hop {
+                ttl: 1,
+                addr: Some(addr),
+                name: Some("box.example".into()),
+                rtt: Some(Duration::from_micros(1500)),
+            }],
+            reached: true,
+        };
+        NmapReport::new("scan \"box\"")
+            .with_scan(&scan)
+            .with_fingerprint(&fingerprint)
+            .with_trace(&trace)
+            .with_ptr(addr, "box.example")
+            .with_scan(&PortScan {
+                addr: "192.0.2.8".parse().unwrap(),
+                ports: vec![],
+            })
+    }
+
+    #[test]
+    fn xml_describes_hosts_ports_and_services() {
+        let xml = report().to_xml();
+        assert!(xml.contains(r#"args="scan &quot;box&quot;""#));
+        assert!(xml.contains(r#"<status state="up" reason="conn-refused" reason_ttl="0"/>"#));
+        assert!(xml.contains(r#"<hostname name="box.example" type="PTR"/>"#));
+        assert!(xml.contains(r#"<port protocol="tcp" portid="22"><state state="open" reason="syn-ack" reason_ttl="0"/><service name="ssh" product="OpenSSH_9.6 &lt;FreeBSD&gt;" method="probed" conf="10"/></port>"#));
+        assert!(xml.contains(r#"<osmatch name="FreeBSD""#));
+        assert!(xml.contains(r#"<hop ttl="1" ipaddr="192.0.2.7" rtt="1.50" host="box.example"/>"#));
+        assert!(xml.contains(r#"<hosts up="1" down="1" total="2"/>"#));
+        assert!(xml.ends_with("</nmaprun>\n"));
+    }
+    #[test]
+    fn greppable_lines_list_ports() {
+        let grep = report().to_greppable();
+        let lines: Vec<&str> = grep.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[1], "Host: 192.0.2.7 (box.example)\tStatus: Up");
+        assert_eq!(
+            lines[2],
+            "Host: 192.0.2.7 (box.example)\tPorts: 22/open/tcp//ssh//OpenSSH_9.6 <FreeBSD>/, 23/closed/tcp//unknown///\tOS: FreeBSD"
+        );
+        assert_eq!(lines[3], "Host: 192.0.2.8 ()\tStatus: Down");
+        assert!(lines[5].ends_with("2 IP addresses (1 hosts up) scanned"));
+    }
+}
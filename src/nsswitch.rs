@@ -0,0 +1,133 @@
+//! Parsing of the `hosts` database line of nsswitch.conf(5).
+//!
+//! The line determines the order in which the hosts file and DNS are
+//! consulted. Only the source order is modelled; `[STATUS=action]` criteria
+//! are skipped and every source falls through to the next when it does not
+//! find the name, which is glibc's default behavior.
+use crate::errors::IpaddrConversionError;
+use std::fs;
+use std::path::Path;
+
+/// Location of the system name service switch configuration
+pub const NSSWITCH_PATH: &str = "/etc/nsswitch.conf";
+
+/// A source of host names
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Source {
+    /// The hosts file
+    Files,
+    /// DNS
+    Dns,
+    /// Any other nss module (eg `mdns4_minimal`, `myhostname`), which the crate's
+    /// backends do not implement and skip
+    Other(String),
+}
+
+impl Source {
+    fn parse(word: &str) -> Self {
+        match word {
+            "files" => Source::Files,
+            "dns" => Source::Dns,
+            other => Source::Other(other.to_string()),
+        }
+    }
+}
+
+/// Host lookup order from nsswitch.conf.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NsSwitch {
+    /// Sources for the hosts database, in lookup order
+    pub hosts: Vec<Source>,
+}
+
+impl Default for NsSwitch {
+    /// The order glibc uses when nsswitch.conf has no hosts line
+    fn default() -> Self {
+        Self {
+            hosts: vec![Source::Dns, Source::Files],
+        }
+    }
+}
+
+impl NsSwitch {
+    /// Parse the contents of an nsswitch.conf file
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ipaddr_from_str::nsswitch::{NsSwitch, Source};
+    /// let conf = NsSwitch::parse("passwd: files\nhosts: files [NOTFOUND=return] dns\n");
+    /// assert_eq!(conf.hosts, vec![Source::Files, Source::Dns]);
+    /// ```
+    pub fn parse(contents: &str) -> Self {
+        let mut conf = Self::default();
+        for line in contents.lines() {
+            let line = match line.find('#') {
+                Some(idx) => &line[..idx],
+                None => line,
+            };
+            let idx = match line.find(':') {
+                Some(idx) => idx,
+                None => continue,
+            };
+            if line[..idx].trim() != "hosts" {
+                continue;
+            }
+            let mut in_criteria = false;
+            let mut sources = Vec::new();
+            for word in line[idx + 1..].split_whitespace() {
+                if word.starts_with('[') {
+                    in_criteria = true;
+                }
+                if !in_criteria {
+                    sources.push(Source::parse(word));
+                }
+                if word.ends_with(']') {
+                    in_criteria = false;
+                }
+            }
+            conf.hosts = sources;
+        }
+        conf
+    }
+
+    /// Read and parse the nsswitch.conf file at `path`
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, IpaddrConversionError> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    /// Read and parse the system nsswitch.conf, falling back on the default
+    /// order if it does not exist
+    pub fn system() -> Result<Self, IpaddrConversionError> {
+        if !Path::new(NSSWITCH_PATH).exists() {
+            return Ok(Self::default());
+        }
+        Self::from_file(NSSWITCH_PATH)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_parse_hosts_line() {
+        let conf = NsSwitch::parse(
+            "hosts:   files mdns4_minimal [NOTFOUND=return] dns myhostname # comment\n",
+        );
+        assert_eq!(
+            conf.hosts,
+            vec![
+                Source::Files,
+                Source::Other("mdns4_minimal".into()),
+                Source::Dns,
+                Source::Other("myhostname".into())
+            ]
+        );
+    }
+    #[test]
+    fn missing_hosts_line_uses_default() {
+        let conf = NsSwitch::parse("passwd: files\n");
+        assert_eq!(conf, NsSwitch::default());
+    }
+}
@@ -0,0 +1,440 @@
+//! Parsers for addresses and address blocks which report where they failed.
+//!
+//! The std parsers only say that an input is invalid. These report the byte
+//! offset of the first character which could not be accepted, and what was
+//! expected there, so configuration tooling can point at the exact mistake.
+use crate::cidr::{Cidr, IpRange};
+use std::error::Error;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+/// Where and why parsing failed.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::parse::parse_ipv4;
+///
+/// let err = parse_ipv4("10.0.300.1").unwrap_err();
+/// assert_eq!(err.offset(), 5);
+/// assert_eq!(err.to_string(), "expected octet of at most 255 at byte 5");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseError {
+    offset: usize,
+    expected: &'static str,
+}
+
+impl ParseError {
+    pub(crate) fn new(offset: usize, expected: &'static str) -> Self {
+        Self { offset, expected }
+    }
+
+    /// The byte offset into the input of the character which was rejected
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// A short description of what was expected at `offset`
+    pub fn expected(&self) -> &'static str {
+        self.expected
+    }
+
+    /// The error with `input` quoted beneath it and a caret under the
+    /// offending character, for showing to the person who typed `input`
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ipaddr_from_str::parse::parse_ipv4;
+    ///
+    /// let err = parse_ipv4("10.0.0.x").unwrap_err();
+    /// assert_eq!(err.annotate("10.0.0.x"), "expected digit at byte 7\n  10.0.0.x\n         ^");
+    /// ```
+    pub fn annotate(&self, input: &str) -> String {
+        let column = input
+            .get(..self.offset)
+            .map_or(self.offset, |before| before.chars().count());
+        format!("{}\n  {}\n  {}^", self, input, " ".repeat(column))
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "expected {} at byte {}", self.expected, self.offset)
+    }
+}
+
+impl Error for ParseError {}
+
+/// Cursor over the input being parsed
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Self {
+            input: input.as_bytes(),
+            pos: 0,
+        }
+    }
+
+    fn error(&self, expected: &'static str) -> ParseError {
+        ParseError {
+            offset: self.pos,
+            expected,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, ahead: usize) -> Option<u8> {
+        self.input.get(self.pos + ahead).copied()
+    }
+
+    fn expect(&mut self, byte: u8, expected: &'static str) -> Result<(), ParseError> {
+        if self.peek() == Some(byte) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(expected))
+        }
+    }
+
+    fn end(&self) -> Result<(), ParseError> {
+        match self.peek() {
+            None => Ok(()),
+            Some(_) => Err(self.error("end of input")),
+        }
+    }
+
+    /// An unsigned decimal of at most `max`, without leading zeros
+    fn decimal(&mut self, max: u32, expected: &'static str) -> Result<u32, ParseError> {
+        let start = self.pos;
+        let mut value: u32 = 0;
+        while let Some(c) = self.peek().filter(u8::is_ascii_digit) {
+            if self.pos > start && value == 0 {
+                return Err(ParseError {
+                    offset: start,
+                    expected: "number without leading zeros",
+                });
+            }
+            value = value * 10 + (c - b'0') as u32;
+            if value > max {
+                return Err(ParseError {
+                    offset: start,
+                    expected,
+                });
+            }
+            self.pos += 1;
+        }
+        if self.pos == start {
+            return Err(self.error("digit"));
+        }
+        Ok(value)
+    }
+
+    fn ipv4(&mut self) -> Result<Ipv4Addr, ParseError> {
+        let mut octets = [0u8; 4];
+        for (i, octet) in octets.iter_mut().enumerate() {
+            if i > 0 {
+                self.expect(b'.', "'.'")?;
+            }
+            *octet = self.decimal(255, "octet of at most 255")? as u8;
+        }
+        Ok(Ipv4Addr::from(octets))
+    }
+
+    /// Whether the text up to the next ':' is a dotted quad
+    fn at_embedded_ipv4(&self) -> bool {
+        self.input[self.pos..]
+            .iter()
+            .take_while(|&&c| c != b':')
+            .any(|&c| c == b'.')
+    }
+
+    fn hex_group(&mut self) -> Result<u16, ParseError> {
+        let start = self.pos;
+        let mut value: u16 = 0;
+        while let Some(digit) = self.peek().and_then(|c| (c as char).to_digit(16)) {
+            if self.pos - start == 4 {
+                return Err(self.error("at most 4 hex digits"));
+            }
+            value = value << 4 | digit as u16;
+            self.pos += 1;
+        }
+        if self.pos == start {
+            return Err(self.error("hex digit"));
+        }
+        Ok(value)
+    }
+
+    fn ipv6(&mut self) -> Result<Ipv6Addr, ParseError> {
+        let mut head = Vec::new();
+        let mut tail = Vec::new();
+        let mut gap = false;
+        if self.peek() == Some(b':') {
+            self.expect(b':', "hex digit")?;
+            self.expect(b':', "':'")?;
+            gap = true;
+        }
+        let at_hex = |p: &Self| p.peek().is_some_and(|c| c.is_ascii_hexdigit());
+        while !gap || at_hex(self) {
+            let count = head.len() + tail.len();
+            if count == 8 || (gap && count == 7) {
+                return Err(self.error("end of address"));
+            }
+            let groups = if gap { &mut tail } else { &mut head };
+            if self.at_embedded_ipv4() {
+                if count > 6 || (gap && count > 5) {
+                    return Err(self.error("hex digit"));
+                }
+                let octets = self.ipv4()?.octets();
+                groups.push(u16::from_be_bytes([octets[0], octets[1]]));
+                groups.push(u16::from_be_bytes([octets[2], octets[3]]));
+                break;
+            }
+            groups.push(self.hex_group()?);
+            if count + 1 == if gap { 7 } else { 8 } || self.peek() != Some(b':') {
+                break;
+            }
+            if self.peek_at(1) == Some(b':') {
+                if gap {
+                    return Err(ParseError {
+                        offset: self.pos + 1,
+                        expected: "hex digit",
+                    });
+                }
+                self.pos += 2;
+                gap = true;
+            } else {
+                self.pos += 1;
+                if !at_hex(self) {
+                    return Err(self.error("hex digit"));
+                }
+            }
+        }
+        let count = head.len() + tail.len();
+        if !gap && count < 8 {
+            return Err(self.error("':'"));
+        }
+        let mut segments = [0u16; 8];
+        segments[..head.len()].copy_from_slice(&head);
+        segments[8 - tail.len()..].copy_from_slice(&tail);
+        Ok(Ipv6Addr::from(segments))
+    }
+
+    fn ip(&mut self) -> Result<IpAddr, ParseError> {
+        let is_v6 = self.input[self.pos..]
+            .iter()
+            .take_while(|&&c| c != b'/' && c != b'-')
+            .any(|&c| c == b':');
+        if is_v6 {
+            self.ipv6().map(IpAddr::V6)
+        } else {
+            self.ipv4().map(IpAddr::V4)
+        }
+    }
+}
+
+/// Parse a dotted quad IPv4 address.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::parse::parse_ipv4;
+///
+/// assert!(parse_ipv4("192.0.2.1").is_ok());
+/// assert_eq!(parse_ipv4("192.0.2").unwrap_err().expected(), "'.'");
+/// ```
+pub fn parse_ipv4(input: &str) -> Result<Ipv4Addr, ParseError> {
+    let mut parser = Parser::new(input);
+    let addr = parser.ipv4()?;
+    parser.end()?;
+    Ok(addr)
+}
+
+/// Parse an IPv6 address, in any of the RFC 4291 text forms.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::parse::parse_ipv6;
+///
+/// assert!(parse_ipv6("2001:db8::1").is_ok());
+/// assert_eq!(parse_ipv6("2001:db8::1::").unwrap_err().offset(), 12);
+/// ```
+pub fn parse_ipv6(input: &str) -> Result<Ipv6Addr, ParseError> {
+    let mut parser = Parser::new(input);
+    let addr = parser.ipv6()?;
+    parser.end()?;
+    Ok(addr)
+}
+
+/// Parse an address of either family.
+pub fn parse_ip(input: &str) -> Result<IpAddr, ParseError> {
+    let mut parser = Parser::new(input);
+    let addr = parser.ip()?;
+    parser.end()?;
+    Ok(addr)
+}
+
+/// Parse a CIDR block such as `10.0.0.0/8` or `2001:db8::/32`.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::parse::parse_cidr;
+///
+/// assert_eq!(parse_cidr("10.0.0.0/8").unwrap().prefix_len(), 8);
+/// assert_eq!(parse_cidr("10.0.0.0/33").unwrap_err().expected(), "prefix length of at most 32");
+/// ```
+pub fn parse_cidr(input: &str) -> Result<Cidr, ParseError> {
+    let mut parser = Parser::new(input);
+    let addr = parser.ip()?;
+    parser.expect(b'/', "'/'")?;
+    let prefix_len = match addr {
+        IpAddr::V4(_) => parser.decimal(32, "prefix length of at most 32")?,
+        IpAddr::V6(_) => parser.decimal(128, "prefix length of at most 128")?,
+    };
+    parser.end()?;
+    Ok(Cidr::new(addr, prefix_len as u8).expect("prefix length checked"))
+}
+
+/// Parse an inclusive range of addresses such as `10.0.0.1-10.0.0.9`.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::parse::parse_range;
+///
+/// assert!(parse_range("10.0.0.1-10.0.0.9").is_ok());
+/// assert_eq!(parse_range("10.0.0.9-10.0.0.1").unwrap_err().offset(), 9);
+/// ```
+pub fn parse_range(input: &str) -> Result<IpRange, ParseError> {
+    let mut parser = Parser::new(input);
+    let start = parser.ip()?;
+    parser.expect(b'-', "'-'")?;
+    let end_offset = parser.pos;
+    let end = match start {
+        IpAddr::V4(_) => parser.ipv4().map(IpAddr::V4)?,
+        IpAddr::V6(_) => parser.ipv6().map(IpAddr::V6)?,
+    };
+    parser.end()?;
+    IpRange::new(start, end).ok_or(ParseError {
+        offset: end_offset,
+        expected: "address no lower than the start",
+    })
+}
+
+/// Parse a socket address, `192.0.2.1:80` or `[2001:db8::1]:80`.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::parse::parse_socket_addr;
+///
+/// assert_eq!(parse_socket_addr("[::1]:53").unwrap().port(), 53);
+/// assert_eq!(parse_socket_addr("::1:53").unwrap_err().expected(), "'[' before an IPv6 address");
+/// ```
+pub fn parse_socket_addr(input: &str) -> Result<SocketAddr, ParseError> {
+    let mut parser = Parser::new(input);
+    let addr = if parser.peek() == Some(b'[') {
+        parser.pos += 1;
+        let ip = parser.ipv6()?;
+        parser.expect(b']', "']'")?;
+        parser.expect(b':', "':'")?;
+        let port = parser.decimal(u16::MAX as u32, "port of at most 65535")?;
+        SocketAddr::V6(SocketAddrV6::new(ip, port as u16, 0, 0))
+    } else {
+        if input.bytes().filter(|&c| c == b':').count() > 1 {
+            return Err(parser.error("'[' before an IPv6 address"));
+        }
+        let ip = parser.ipv4()?;
+        parser.expect(b':', "':'")?;
+        let port = parser.decimal(u16::MAX as u32, "port of at most 65535")?;
+        SocketAddr::V4(SocketAddrV4::new(ip, port as u16))
+    };
+    parser.end()?;
+    Ok(addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offset<T: fmt::Debug>(result: Result<T, ParseError>) -> (usize, &'static str) {
+        let err = result.unwrap_err();
+        (err.offset(), err.expected())
+    }
+
+    #[test]
+    fn ipv4_errors_point_at_the_problem() {
+        assert_eq!(parse_ipv4("10.1.2.3").unwrap(), Ipv4Addr::new(10, 1, 2, 3));
+        assert_eq!(offset(parse_ipv4("10.1.2.3.")), (8, "end of input"));
+        assert_eq!(offset(parse_ipv4("10..2.3")), (3, "digit"));
+        assert_eq!(
+            offset(parse_ipv4("10.01.2.3")),
+            (3, "number without leading zeros")
+        );
+        assert_eq!(offset(parse_ipv4("")), (0, "digit"));
+    }
+    #[test]
+    fn ipv6_forms_match_std() {
+        for input in &[
+            "::",
+            "::1",
+            "1::",
+            "2001:db8::1",
+            "2001:db8:0:0:0:0:0:1",
+            "1:2:3:4:5:6:7:8",
+            "1:2:3:4:5:6::8",
+            "::ffff:192.0.2.1",
+            "64:ff9b::192.0.2.1",
+            "1:2:3:4:5:6:1.2.3.4",
+            "FE80::aB",
+        ] {
+            assert_eq!(
+                parse_ipv6(input).unwrap(),
+                input.parse::<Ipv6Addr>().unwrap()
+            );
+        }
+    }
+    #[test]
+    fn ipv6_errors_point_at_the_problem() {
+        assert_eq!(offset(parse_ipv6(":1")), (1, "':'"));
+        assert_eq!(offset(parse_ipv6("1:2:3")), (5, "':'"));
+        assert_eq!(
+            offset(parse_ipv6("1:2:3:4:5:6:7:8:9")),
+            (15, "end of input")
+        );
+        assert_eq!(offset(parse_ipv6("1::2::3")), (5, "hex digit"));
+        assert_eq!(offset(parse_ipv6("12345::")), (4, "at most 4 hex digits"));
+        assert_eq!(offset(parse_ipv6("1:")), (2, "hex digit"));
+        assert_eq!(
+            offset(parse_ipv6("1:2:3:4:5:6:7::8")),
+            (15, "end of address")
+        );
+        assert_eq!(offset(parse_ipv6("::1.2.3")), (7, "'.'"));
+    }
+    #[test]
+    fn blocks_and_socket_addrs() {
+        let cidr = parse_cidr("2001:db8::/32").unwrap();
+        assert_eq!(cidr.prefix_len(), 32);
+        assert_eq!(offset(parse_cidr("10.0.0.0")), (8, "'/'"));
+        assert_eq!(offset(parse_range("10.0.0.1-::1")), (9, "digit"));
+        assert_eq!(
+            parse_socket_addr("192.0.2.1:8080").unwrap(),
+            "192.0.2.1:8080".parse().unwrap()
+        );
+        assert_eq!(
+            offset(parse_socket_addr("192.0.2.1:65536")),
+            (10, "port of at most 65535")
+        );
+        assert_eq!(offset(parse_socket_addr("[::1]53")), (5, "':'"));
+    }
+}
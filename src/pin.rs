@@ -0,0 +1,186 @@
+//! Forcing the results of chosen names for a while, for canaries and chaos
+//! tests.
+//!
+//! [`PinningResolver`] answers a pinned name with the addresses it was
+//! pinned to until the pin expires or is removed, and passes every other
+//! lookup through. Wrapped around a
+//! [`CachingResolver`](crate::cache::CachingResolver), pins take precedence
+//! over both the cache and DNS, and removing one lets the cached answer show
+//! through again.
+use crate::deadline;
+use crate::errors::IpaddrConversionError;
+use crate::resolver::{normalize_name, Resolve};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Pinned addresses and when they expire, by normalized name
+type Pins = HashMap<String, (Vec<IpAddr>, Instant)>;
+
+/// A name pinned to fixed addresses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pin {
+    pub hostname: String,
+    pub addrs: Vec<IpAddr>,
+    /// How long the pin has left
+    pub remaining: Duration,
+}
+
+/// Resolver overriding another resolver's results for pinned names.
+///
+/// Clones share their pins, so a handle kept by an admin endpoint pins for
+/// every clone in use.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::pin::PinningResolver;
+/// use ipaddr_from_str::{Resolve, StaticResolver};
+/// use std::time::Duration;
+///
+/// let stable = vec!["10.0.0.1".parse().unwrap()];
+/// let canary = vec!["10.0.0.9".parse().unwrap()];
+/// let resolver = PinningResolver::new(StaticResolver::new().with_entry("api.internal", stable.clone()));
+/// resolver.pin("api.internal", canary.clone(), Duration::from_secs(300));
+/// assert_eq!(resolver.resolve("api.internal").unwrap(), canary);
+/// resolver.unpin("api.internal");
+/// assert_eq!(resolver.resolve("api.internal").unwrap(), stable);
+/// ```
+#[derive(Debug, Clone)]
+pub struct PinningResolver<R> {
+    resolver: R,
+    pins: Arc<Mutex<Pins>>,
+}
+
+impl<R: Resolve> PinningResolver<R> {
+    /// Pass lookups through to `resolver` until a name is pinned
+    pub fn new(resolver: R) -> Self {
+        Self {
+            resolver,
+            pins: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Pins> {
+        self.pins
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Answer `hostname` with `addrs` for the next `ttl`, replacing any pin
+    /// it already has. Pinning to no addresses makes the name fail with
+    /// NotFound, as if it had been removed from DNS.
+    ///
+    /// # Parameters
+    ///
+    /// * `hostname` - the name to pin, matched as lookups are, ignoring case
+    ///   and a trailing dot
+    /// * `addrs` - the addresses to answer with
+    /// * `ttl` - how long until the pin expires by itself
+    pub fn pin(&self, hostname: &str, addrs: Vec<IpAddr>, ttl: Duration) {
+        let expires = deadline::after(ttl);
+        self.lock()
+            .insert(normalize_name(hostname), (addrs, expires));
+    }
+
+    /// Remove the pin of `hostname`, returning whether it had one which had
+    /// not expired
+    pub fn unpin(&self, hostname: &str) -> bool {
+        self.lock()
+            .remove(&normalize_name(hostname))
+            .is_some_and(|(_, expires)| expires > Instant::now())
+    }
+
+    /// Remove every pin
+    pub fn unpin_all(&self) {
+        self.lock().clear();
+    }
+
+    /// The pins in force, in no particular order
+    pub fn pins(&self) -> Vec<Pin> {
+        let now = Instant::now();
+        let mut pins = self.lock();
+        pins.retain(|_, (_, expires)| *expires > now);
+        pins.iter()
+            .map(|(hostname, (addrs, expires))| Pin {
+                hostname: hostname.clone(),
+                addrs: addrs.clone(),
+                remaining: expires.duration_since(now),
+            })
+            .collect()
+    }
+
+    /// The resolver lookups of unpinned names go to
+    pub fn inner(&self) -> &R {
+        &self.resolver
+    }
+}
+
+impl<R: Resolve> Resolve for PinningResolver<R> {
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        let name = normalize_name(hostname_or_address);
+        let pinned = {
+            let mut pins = self.lock();
+            match pins.get(&name) {
+                Some((addrs, expires)) if *expires > Instant::now() => Some(addrs.clone()),
+                Some(_) => {
+                    pins.remove(&name);
+                    None
+                }
+                None => None,
+            }
+        };
+        match pinned {
+            Some(addrs) if addrs.is_empty() => Err(IpaddrConversionError::NotFound(
+                hostname_or_address.to_string(),
+            )),
+            Some(addrs) => Ok(addrs),
+            None => self.resolver.resolve(hostname_or_address),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::StaticResolver;
+    use crate::test_util::ips;
+    use std::thread;
+
+    #[test]
+    fn pins_expire_by_themselves() {
+        let resolver = PinningResolver::new(
+            StaticResolver::new().with_entry("db.internal", ips(&["10.0.0.5"])),
+        );
+        resolver.pin(
+            "DB.internal.",
+            ips(&["10.0.0.6"]),
+            Duration::from_millis(50),
+        );
+        assert_eq!(resolver.resolve("db.internal").unwrap(), ips(&["10.0.0.6"]));
+        assert_eq!(resolver.pins()[0].hostname, "db.internal");
+        thread::sleep(Duration::from_millis(80));
+        assert_eq!(resolver.resolve("db.internal").unwrap(), ips(&["10.0.0.5"]));
+        assert!(resolver.pins().is_empty());
+        assert!(!resolver.unpin("db.internal"));
+    }
+    #[test]
+    fn clones_share_pins_and_empty_pins_fail() {
+        let resolver = PinningResolver::new(StaticResolver::new());
+        let admin = resolver.clone();
+        admin.pin("gone.internal", vec![], Duration::from_secs(60));
+        assert!(matches!(
+            resolver.resolve("gone.internal"),
+            Err(IpaddrConversionError::NotFound(_))
+        ));
+        assert!(admin.unpin("gone.internal"));
+        assert!(resolver.pins().is_empty());
+    }
+    #[test]
+    fn overlong_pins_are_kept_rather_than_overflowing() {
+        let resolver = PinningResolver::new(StaticResolver::new());
+        resolver.pin("db.internal", ips(&["10.0.0.6"]), Duration::MAX);
+        assert_eq!(resolver.resolve("db.internal").unwrap(), ips(&["10.0.0.6"]));
+    }
+}
@@ -0,0 +1,357 @@
+//! Resolution policy as data: which names resolve how, and which answers
+//! are acceptable.
+//!
+//! A [`Policy`] is a list of rules, one per line:
+//!
+//! ```text
+//! # internal names go to the corporate nameservers
+//! when host matches *.internal -> use profile corp
+//! when host matches *.ads.example -> reject
+//! when ip in 10/8 -> reject
+//! when ip in 10.1/16 -> allow
+//! ```
+//!
+//! Host rules are tried in order before a lookup, and the first whose
+//! pattern matches decides: `use profile` resolves through the
+//! [registered profile](crate::profile::register_profile) of that name,
+//! `reject` fails the lookup, and `allow` resolves as if no rule matched.
+//! IP rules are tried in order against each address of the answer, and the
+//! first whose block covers it decides whether it is kept. With the `serde`
+//! feature a policy deserializes from a list of rules, or a table holding
+//! one as `rules`, so it can be kept in a TOML or YAML file.
+use crate::cidr::Cidr;
+use crate::errors::IpaddrConversionError;
+use crate::profile::profile;
+use crate::resolver::{normalize_name, Resolve};
+use crate::ssh::wildcard_match;
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// What a rule applies to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Condition {
+    /// Names matching a pattern, in which `*` matches any run of characters
+    /// and `?` any one, ignoring case
+    HostMatches(String),
+    /// Addresses within a block
+    IpIn(Cidr),
+}
+
+/// What a rule does with what it applies to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Action {
+    /// Resolve through the profile registered under this name. Only for
+    /// host rules.
+    UseProfile(String),
+    /// Fail the lookup, or drop the address
+    Reject,
+    /// Resolve normally, or keep the address, skipping the rules after
+    Allow,
+}
+
+/// One `when <condition> -> <action>` rule
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Rule {
+    pub condition: Condition,
+    pub action: Action,
+}
+
+/// A block written with its trailing zero octets left off, as `10/8` or
+/// `172.16/12`, or in full
+fn parse_block(text: &str) -> Option<Cidr> {
+    let (addr, prefix) = text.split_once('/')?;
+    if addr.contains(':') || addr.split('.').count() >= 4 {
+        return text.parse().ok();
+    }
+    let mut octets: Vec<&str> = addr.split('.').collect();
+    octets.resize(4, "0");
+    format!("{}/{}", octets.join("."), prefix).parse().ok()
+}
+
+impl FromStr for Rule {
+    type Err = IpaddrConversionError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = |why: &str| {
+            IpaddrConversionError::InvalidInput(format!("policy rule '{}': {}", text.trim(), why))
+        };
+        let (condition, action) = text
+            .trim()
+            .strip_prefix("when ")
+            .and_then(|rest| rest.split_once("->"))
+            .ok_or_else(|| invalid("expected 'when <condition> -> <action>'"))?;
+        let condition: Vec<&str> = condition.split_whitespace().collect();
+        let condition = match condition.as_slice() {
+            ["host", "matches", pattern] => Condition::HostMatches(pattern.to_lowercase()),
+            ["ip", "in", block] => {
+                Condition::IpIn(parse_block(block).ok_or_else(|| invalid("bad block"))?)
+            }
+            _ => {
+                return Err(invalid(
+                    "expected 'host matches <pattern>' or 'ip in <block>'",
+                ))
+            }
+        };
+        let action: Vec<&str> = action.split_whitespace().collect();
+        let action = match action.as_slice() {
+            ["use", "profile", name] => Action::UseProfile(name.to_string()),
+            ["reject"] => Action::Reject,
+            ["allow"] => Action::Allow,
+            _ => {
+                return Err(invalid(
+                    "expected 'use profile <name>', 'reject' or 'allow'",
+                ))
+            }
+        };
+        if let (Condition::IpIn(_), Action::UseProfile(_)) = (&condition, &action) {
+            return Err(invalid("addresses can only be allowed or rejected"));
+        }
+        Ok(Rule { condition, action })
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.condition {
+            Condition::HostMatches(ref pattern) => write!(f, "when host matches {}", pattern)?,
+            Condition::IpIn(ref block) => write!(f, "when ip in {}", block)?,
+        }
+        match self.action {
+            Action::UseProfile(ref name) => write!(f, " -> use profile {}", name),
+            Action::Reject => write!(f, " -> reject"),
+            Action::Allow => write!(f, " -> allow"),
+        }
+    }
+}
+
+/// Rules governing resolution, applied by [`PolicyResolver`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Policy {
+    rules: Vec<Rule>,
+}
+
+impl Policy {
+    /// A policy of `rules`, tried in the order given
+    pub fn new(rules: Vec<Rule>) -> Self {
+        Self { rules }
+    }
+
+    /// Parse a policy with a rule per line. Blank lines and `#` comments
+    /// are skipped.
+    ///
+    /// # Returns
+    /// The policy, or an InvalidInput error naming the first bad rule
+    pub fn parse(text: &str) -> Result<Self, IpaddrConversionError> {
+        text.lines()
+            .map(|line| line.split('#').next().unwrap_or("").trim())
+            .filter(|line| !line.is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map(Self::new)
+    }
+
+    /// Read a policy from `path`, as [`parse`](Self::parse) does
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, IpaddrConversionError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// The rules, in the order they are tried
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// The action of the first host rule matching `hostname`
+    pub fn host_action(&self, hostname: &str) -> Option<&Action> {
+        let name = normalize_name(hostname);
+        self.rules.iter().find_map(|rule| match rule.condition {
+            Condition::HostMatches(ref pattern) if wildcard_match(pattern, &name) => {
+                Some(&rule.action)
+            }
+            _ => None,
+        })
+    }
+
+    /// Whether `addr` may be returned: false if the first IP rule covering
+    /// it rejects it
+    pub fn permits_ip(&self, addr: IpAddr) -> bool {
+        let addr = addr.to_canonical();
+        let action = self.rules.iter().find_map(|rule| match rule.condition {
+            Condition::IpIn(ref block) if block.contains(addr) => Some(&rule.action),
+            _ => None,
+        });
+        action != Some(&Action::Reject)
+    }
+}
+
+impl FromStr for Policy {
+    type Err = IpaddrConversionError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Self::parse(text)
+    }
+}
+
+/// Resolver applying a [`Policy`] to the lookups of another.
+///
+/// Clones share the policy.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::policy::{Policy, PolicyResolver};
+/// use ipaddr_from_str::{IpaddrConversionError, Resolve, StaticResolver};
+///
+/// let policy: Policy = "when host matches *.ads.example -> reject\n\
+///                       when ip in 10/8 -> reject"
+///     .parse()
+///     .unwrap();
+/// let backend = StaticResolver::new()
+///     .with_entry("tracker.ads.example", vec!["192.0.2.1".parse().unwrap()])
+///     .with_entry("app.example", vec!["10.0.0.1".parse().unwrap(), "192.0.2.2".parse().unwrap()]);
+/// let resolver = PolicyResolver::new(backend, policy);
+/// assert!(matches!(
+///     resolver.resolve("tracker.ads.example"),
+///     Err(IpaddrConversionError::Blocked(_))
+/// ));
+/// assert_eq!(resolver.resolve("app.example").unwrap(), vec!["192.0.2.2".parse::<std::net::IpAddr>().unwrap()]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct PolicyResolver<R> {
+    resolver: R,
+    policy: Arc<Policy>,
+}
+
+impl<R: Resolve> PolicyResolver<R> {
+    /// Apply `policy` to the lookups of `resolver`, which answers any name no
+    /// rule sends to a profile
+    pub fn new(resolver: R, policy: Policy) -> Self {
+        Self {
+            resolver,
+            policy: Arc::new(policy),
+        }
+    }
+
+    /// The policy applied
+    pub fn policy(&self) -> &Policy {
+        &self.policy
+    }
+}
+
+impl<R: Resolve> Resolve for PolicyResolver<R> {
+    /// Fails with Blocked if a host rule rejects the name or IP rules reject
+    /// every address, and with InvalidInput if a rule names a profile which
+    /// is not registered
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        let addrs = match hostname_or_address.parse::<IpAddr>() {
+            Ok(ip) => vec![ip],
+            Err(_) => match self.policy.host_action(hostname_or_address) {
+                Some(Action::Reject) => {
+                    return Err(IpaddrConversionError::Blocked(format!(
+                        "{} rejected by policy",
+                        hostname_or_address
+                    )))
+                }
+                Some(Action::UseProfile(name)) => profile(name)
+                    .ok_or_else(|| {
+                        IpaddrConversionError::InvalidInput(format!(
+                            "policy names unknown resolution profile '{}'",
+                            name
+                        ))
+                    })?
+                    .resolve(hostname_or_address)?,
+                Some(Action::Allow) | None => self.resolver.resolve(hostname_or_address)?,
+            },
+        };
+        let permitted: Vec<IpAddr> = addrs
+            .iter()
+            .copied()
+            .filter(|addr| self.policy.permits_ip(*addr))
+            .collect();
+        if permitted.is_empty() && !addrs.is_empty() {
+            return Err(IpaddrConversionError::Blocked(format!(
+                "every address of {} rejected by policy",
+                hostname_or_address
+            )));
+        }
+        Ok(permitted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::{register_profile, Profile};
+    use crate::resolv_conf::ResolvConf;
+    use crate::resolver::StaticResolver;
+    use crate::test_util::ips;
+
+    #[test]
+    fn rules_parse_and_display() {
+        let policy = Policy::parse(
+            "# comment\n\
+             when host matches *.Internal -> use profile corp\n\
+             \n\
+             when ip in 172.16/12 -> reject  # private\n",
+        )
+        .unwrap();
+        assert_eq!(
+            policy
+                .rules()
+                .iter()
+                .map(Rule::to_string)
+                .collect::<Vec<_>>(),
+            vec![
+                "when host matches *.internal -> use profile corp",
+                "when ip in 172.16.0.0/12 -> reject",
+            ]
+        );
+        assert!(!policy.permits_ip("::ffff:172.20.0.1".parse().unwrap()));
+        assert!(policy.permits_ip("172.32.0.1".parse().unwrap()));
+        assert!("when ip in 10/8 -> use profile corp"
+            .parse::<Rule>()
+            .is_err());
+        assert!("when host is db -> reject".parse::<Rule>().is_err());
+        assert!(Policy::parse("when ip in 10/40 -> reject").is_err());
+    }
+    #[test]
+    fn hosts_are_routed_and_answers_filtered() {
+        register_profile(
+            "test-policy-corp",
+            Profile::with_backend(
+                ResolvConf::default(),
+                StaticResolver::new().with_entry("db.internal", ips(&["10.1.0.5"])),
+            ),
+        );
+        let policy = Policy::parse(
+            "when host matches *.internal -> use profile test-policy-corp\n\
+             when host matches *.missing -> use profile test-policy-none\n\
+             when ip in 10.1/16 -> allow\n\
+             when ip in 10/8 -> reject\n",
+        )
+        .unwrap();
+        let backend = StaticResolver::new()
+            .with_entry("db.internal", ips(&["192.0.2.1"]))
+            .with_entry("rebind.example", ips(&["10.0.0.1"]));
+        let resolver = PolicyResolver::new(backend, policy);
+        assert_eq!(
+            resolver.resolve("DB.internal.").unwrap(),
+            ips(&["10.1.0.5"])
+        );
+        assert!(matches!(
+            resolver.resolve("rebind.example"),
+            Err(IpaddrConversionError::Blocked(_))
+        ));
+        assert!(matches!(
+            resolver.resolve("10.2.0.1"),
+            Err(IpaddrConversionError::Blocked(_))
+        ));
+        assert!(matches!(
+            resolver.resolve("db.missing"),
+            Err(IpaddrConversionError::InvalidInput(_))
+        ));
+    }
+}
@@ -0,0 +1,242 @@
+//! Finding which TCP ports of a host are open.
+//!
+//! [`scan_ports`] connects to each port in the ranges given, a number at
+//! once and no faster than a rate limit, and reports each as open, closed
+//! or filtered. The connections are ordinary ones, completed and then
+//! dropped, so no privilege is needed and nothing half-open is left behind.
+//! Enabled by the `probe` feature.
+use crate::errors::IpaddrConversionError;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long [`scan_ports`] waits for each connection
+pub const DEFAULT_SCAN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// What connecting to a port found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PortState {
+    /// The connection was accepted
+    Open,
+    /// The host refused the connection
+    Closed,
+    /// Nothing answered in time, or a router reported the port unreachable
+    Filtered,
+}
+
+impl fmt::Display for PortState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            PortState::Open => "open",
+            PortState::Closed => "closed",
+            PortState::Filtered => "filtered",
+        })
+    }
+}
+
+/// A port scanned
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PortResult {
+    pub port: u16,
+    pub state: PortState,
+    /// How long the host took to accept or refuse the connection
+    pub rtt: Option<Duration>,
+}
+
+/// The ports scanned of a host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortScan {
+    pub addr: IpAddr,
+    /// Every port scanned, in port order
+    pub ports: Vec<PortResult>,
+}
+
+impl PortScan {
+    /// The ports found open
+    pub fn open(&self) -> Vec<u16> {
+        self.ports
+            .iter()
+            .filter(|result| result.state == PortState::Open)
+            .map(|result| result.port)
+            .collect()
+    }
+}
+
+/// Hands out the times connections may start, `interval` apart
+#[derive(Debug)]
+pub(crate) struct Pacer {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl Pacer {
+    /// Pace `rate` starts a second, which must be positive
+    fn new(rate: f64) -> Self {
+        Self {
+            interval: Duration::from_secs_f64(1.0 / rate),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// A pacer for at most `rate_limit` starts a second, None for no limit,
+    /// or an InvalidInput error if the limit is not positive
+    pub(crate) fn limiting(rate_limit: Option<f64>) -> Result<Option<Self>, IpaddrConversionError> {
+        match rate_limit {
+            Some(rate) if !(rate > 0.0 && rate.is_finite()) => Err(
+                IpaddrConversionError::InvalidInput(format!("rate limit of {} a second", rate)),
+            ),
+            Some(rate) => Ok(Some(Self::new(rate))),
+            None => Ok(None),
+        }
+    }
+
+    /// Wait for the next turn
+    pub(crate) fn wait(&self) {
+        let start = {
+            let mut next = self
+                .next
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let start = (*next).max(Instant::now());
+            *next = start + self.interval;
+            start
+        };
+        if let Some(wait) = start.checked_duration_since(Instant::now()) {
+            thread::sleep(wait);
+        }
+    }
+}
+
+fn connect(addr: IpAddr, port: u16, timeout: Duration) -> PortResult {
+    let started = Instant::now();
+    let (state, rtt) = match TcpStream::connect_timeout(&SocketAddr::new(addr, port), timeout) {
+        Ok(_) => (PortState::Open, Some(started.elapsed())),
+        Err(err) if err.kind() == ErrorKind::ConnectionRefused => {
+            (PortState::Closed, Some(started.elapsed()))
+        }
+        Err(_) => (PortState::Filtered, None),
+    };
+    PortResult { port, state, rtt }
+}
+
+/// Scan the TCP ports of `addr` in `ranges`, waiting up to
+/// [`DEFAULT_SCAN_TIMEOUT`] for each
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::portscan::scan_ports;
+/// use ipaddr_from_str::{Resolve, SystemResolver};
+///
+/// let addr = SystemResolver.resolve("db.internal").unwrap()[0];
+/// let scan = scan_ports(addr, &[22..=22, 5432..=5439], 16, Some(100.0)).unwrap();
+/// println!("open on {}: {:?}", scan.addr, scan.open());
+/// ```
+pub fn scan_ports(
+    addr: IpAddr,
+    ranges: &[RangeInclusive<u16>],
+    concurrency: usize,
+    rate_limit: Option<f64>,
+) -> Result<PortScan, IpaddrConversionError> {
+    scan_ports_within(addr, ranges, concurrency, rate_limit, DEFAULT_SCAN_TIMEOUT)
+}
+
+/// Scan the TCP ports of `addr` in `ranges`
+///
+/// # Parameters
+///
+/// * `addr` - the host to scan
+/// * `ranges` - the ports to scan; a port in several is scanned once
+/// * `concurrency` - how many connections are attempted at once
+/// * `rate_limit` - the most connections started a second, None for no
+///   limit
+/// * `timeout` - how long each connection is waited for
+///
+/// # Returns
+/// The state of every port, or an InvalidInput error if no port is given or
+/// the rate limit is not positive
+pub fn scan_ports_within(
+    addr: IpAddr,
+    ranges: &[RangeInclusive<u16>],
+    concurrency: usize,
+    rate_limit: Option<f64>,
+    timeout: Duration,
+) -> Result<PortScan, IpaddrConversionError> {
+    let ports: Vec<u16> = ranges
+        .iter()
+        .flat_map(|range| range.clone())
+        .collect::<BTreeSet<u16>>()
+        .into_iter()
+        .collect();
+    if ports.is_empty() {
+        return Err(IpaddrConversionError::InvalidInput(
+            "no ports to scan".into(),
+        ));
+    }
+    let pacer = Pacer::limiting(rate_limit)?;
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(ports.len()));
+    thread::scope(|scope| {
+        for _ in 0..concurrency.clamp(1, ports.len()) {
+            scope.spawn(|| {
+                while let Some(&port) = ports.get(next.fetch_add(1, Ordering::SeqCst)) {
+                    if let Some(ref pacer) = pacer {
+                        pacer.wait();
+                    }
+                    let result = connect(addr, port, timeout);
+                    results
+                        .lock()
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .push(result);
+                }
+            });
+        }
+    });
+    let mut ports = results
+        .into_inner()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    ports.sort_by_key(|result| result.port);
+    Ok(PortScan { addr, ports })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn open_and_closed_ports_are_told_apart() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap().port();
+        // a port just freed is very likely closed
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let addr: IpAddr = "127.0.0.1".parse().unwrap();
+        let scan = scan_ports(addr, &[open..=open, closed..=closed, open..=open], 4, None).unwrap();
+        assert_eq!(scan.ports.len(), 2);
+        assert_eq!(scan.open(), vec![open]);
+        let closed = scan.ports.iter().find(|r| r.port == closed).unwrap();
+        assert_eq!(closed.state, PortState::Closed);
+        assert!(scan_ports(addr, &[], 4, None).is_err());
+        assert!(scan_ports(addr, &[open..=open], 4, Some(0.0)).is_err());
+    }
+    #[test]
+    fn rate_limits_pace_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let started = Instant::now();
+        let ports = port.saturating_sub(4)..=port;
+        scan_ports("127.0.0.1".parse().unwrap(), &[ports], 5, Some(50.0)).unwrap();
+        // five connections 20ms apart
+        assert!(started.elapsed() >= Duration::from_millis(80));
+    }
+}
@@ -0,0 +1,212 @@
+//! Named resolution profiles, for split-horizon setups.
+//!
+//! A laptop on a VPN needs internal names answered by the corporate
+//! nameservers and everything else by the public ones. A [`Profile`] bundles
+//! a resolver configuration (nameservers and search domains) with fixed
+//! overrides, and profiles registered under a name can be picked per call
+//! with [`get_ipaddr_with_profile`].
+use crate::direct::DirectResolver;
+use crate::errors::IpaddrConversionError;
+use crate::resolv_conf::ResolvConf;
+use crate::resolver::{Resolve, StaticResolver};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, OnceLock, RwLock};
+
+/// The registered profiles, by name
+fn profiles() -> &'static RwLock<HashMap<String, Arc<Profile>>> {
+    static PROFILES: OnceLock<RwLock<HashMap<String, Arc<Profile>>>> = OnceLock::new();
+    PROFILES.get_or_init(Default::default)
+}
+
+/// Nameservers, search domains and overrides to resolve with. Clones share
+/// the backend.
+#[derive(Clone)]
+pub struct Profile {
+    config: ResolvConf,
+    overrides: StaticResolver,
+    backend: Arc<dyn Resolve + Send + Sync>,
+}
+
+impl Profile {
+    /// New profile querying the nameservers in `config` directly
+    pub fn new(config: ResolvConf) -> Self {
+        // the profile applies the search domains itself
+        let backend = DirectResolver::new(config.clone()).with_search(false);
+        Self::with_backend(config, backend)
+    }
+
+    /// New profile which applies the search domains in `config` but
+    /// resolves through `backend` rather than `config`'s nameservers
+    pub fn with_backend<R: Resolve + Send + Sync + 'static>(
+        config: ResolvConf,
+        backend: R,
+    ) -> Self {
+        Self {
+            config,
+            overrides: StaticResolver::new(),
+            backend: Arc::new(backend),
+        }
+    }
+
+    /// Answer `hostname` with `addrs` without querying, returning the
+    /// updated profile
+    pub fn with_override<I: Into<String>>(mut self, hostname: I, addrs: Vec<IpAddr>) -> Self {
+        self.overrides.insert(hostname, addrs);
+        self
+    }
+
+    /// The configuration in use
+    pub fn config(&self) -> &ResolvConf {
+        &self.config
+    }
+}
+
+impl fmt::Debug for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Profile")
+            .field("config", &self.config)
+            .field("overrides", &self.overrides)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Resolve for Profile {
+    /// Try each search candidate against the overrides, then each through the
+    /// backend. The first error other than a name not being found is
+    /// reported if nothing answers.
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        if let Ok(ip) = hostname_or_address.parse::<IpAddr>() {
+            return Ok(vec![ip]);
+        }
+        let candidates = self.config.candidates(hostname_or_address);
+        for candidate in &candidates {
+            if let Ok(addrs) = self.overrides.resolve(candidate) {
+                return Ok(addrs);
+            }
+        }
+        let mut failure = None;
+        for candidate in &candidates {
+            match self.backend.resolve(candidate) {
+                Ok(addrs) => return Ok(addrs),
+                Err(IpaddrConversionError::NotFound(_)) => {}
+                Err(err) => {
+                    failure.get_or_insert(err);
+                }
+            }
+        }
+        Err(failure
+            .unwrap_or_else(|| IpaddrConversionError::NotFound(hostname_or_address.to_string())))
+    }
+}
+
+/// Register `profile` under `name`, replacing any profile already there
+pub fn register_profile<I: Into<String>>(name: I, profile: Profile) {
+    profiles()
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .insert(name.into(), Arc::new(profile));
+}
+
+/// Remove the profile registered under `name`, returning whether there was one
+pub fn unregister_profile(name: &str) -> bool {
+    profiles()
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .remove(name)
+        .is_some()
+}
+
+/// The profile registered under `name`
+pub fn profile(name: &str) -> Option<Arc<Profile>> {
+    profiles()
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .get(name)
+        .cloned()
+}
+
+/// Resolve `hostname_or_address` with the profile registered under `profile`.
+///
+/// # Parameters
+///
+/// * `hostname_or_address` - the hostname or ip address literal to resolve
+/// * `profile` - the name the profile was registered under
+///
+/// # Returns
+/// The addresses, or an InvalidInput error if no such profile is registered
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::profile::{register_profile, Profile};
+/// use ipaddr_from_str::resolv_conf::ResolvConf;
+/// use ipaddr_from_str::get_ipaddr_with_profile;
+///
+/// let corp = Profile::new(ResolvConf::parse("nameserver 10.0.0.53\nsearch corp.example"))
+///     .with_override("db.corp.example", vec!["10.1.2.3".parse().unwrap()]);
+/// register_profile("corp", corp);
+/// assert_eq!(get_ipaddr_with_profile("db", "corp").unwrap()[0].to_string(), "10.1.2.3");
+/// assert!(get_ipaddr_with_profile("db", "public").is_err());
+/// ```
+pub fn get_ipaddr_with_profile(
+    hostname_or_address: &str,
+    profile_name: &str,
+) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+    let selected = profile(profile_name).ok_or_else(|| {
+        IpaddrConversionError::InvalidInput(format!(
+            "unknown resolution profile '{}'",
+            profile_name
+        ))
+    })?;
+    selected.resolve(hostname_or_address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::ips;
+
+    #[test]
+    fn search_domains_then_backend() {
+        let backend = StaticResolver::new()
+            .with_entry("db.b.example", ips(&["10.0.0.2"]))
+            .with_entry("db", ips(&["192.0.2.1"]));
+        let profile =
+            Profile::with_backend(ResolvConf::parse("search a.example b.example"), backend)
+                .with_override("cache.a.example", ips(&["10.0.0.9"]));
+        assert_eq!(profile.resolve("db").unwrap(), ips(&["10.0.0.2"]));
+        assert_eq!(profile.resolve("cache").unwrap(), ips(&["10.0.0.9"]));
+        assert_eq!(profile.resolve("db.").unwrap(), ips(&["192.0.2.1"]));
+        assert!(matches!(
+            profile.resolve("missing"),
+            Err(IpaddrConversionError::NotFound(_))
+        ));
+    }
+    #[test]
+    fn profiles_are_selected_by_name() {
+        let entry = |addr| StaticResolver::new().with_entry("app", ips(&[addr]));
+        register_profile(
+            "test-inside",
+            Profile::with_backend(ResolvConf::default(), entry("10.0.0.1")),
+        );
+        register_profile(
+            "test-outside",
+            Profile::with_backend(ResolvConf::default(), entry("192.0.2.1")),
+        );
+        assert_eq!(
+            get_ipaddr_with_profile("app", "test-inside").unwrap(),
+            ips(&["10.0.0.1"])
+        );
+        assert_eq!(
+            get_ipaddr_with_profile("app", "test-outside").unwrap(),
+            ips(&["192.0.2.1"])
+        );
+        assert!(unregister_profile("test-outside"));
+        assert!(matches!(
+            get_ipaddr_with_profile("app", "test-outside"),
+            Err(IpaddrConversionError::InvalidInput(_))
+        ));
+    }
+}
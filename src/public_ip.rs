@@ -0,0 +1,539 @@
+//! Discovering the address a host is seen at from the internet.
+//!
+//! Behind NAT, no interface holds the address peers see, and services
+//! which advertise themselves, or check their reachability, have to ask
+//! something outside. [`discover_public_ip`] asks STUN servers with binding
+//! requests (RFC 5389) and "what is my IP" web endpoints, over IPv4 and IPv6
+//! separately, and reports how far the answers agreed.
+use crate::errors::IpaddrConversionError;
+use crate::resolver::{Resolve, SystemResolver};
+use crate::socket::SocketOptions;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::Duration;
+
+/// How long each source may take to answer, by default
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+/// The STUN magic cookie, which also keys the XOR of mapped addresses
+const MAGIC_COOKIE: u32 = 0x2112_a442;
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+const MAPPED_ADDRESS: u16 = 0x0001;
+const XOR_MAPPED_ADDRESS: u16 = 0x0020;
+/// Most of a web endpoint's response read
+const MAX_RESPONSE: u64 = 4096;
+
+/// Somewhere to ask for the address this host is seen at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// A STUN server, asked with a binding request over UDP
+    Stun { host: String, port: u16 },
+    /// A web endpoint answering with the caller's address as plain text.
+    /// `tls` needs the `dot` feature, which brings in rustls.
+    Http {
+        host: String,
+        port: u16,
+        path: String,
+        tls: bool,
+    },
+    /// The default gateway, asked over NAT-PMP, PCP or UPnP IGD, as
+    /// [`gateway::external_address`](crate::gateway::external_address) does.
+    /// A private answer, from a gateway behind carrier-grade NAT, is left
+    /// out.
+    #[cfg(feature = "gateway")]
+    Gateway,
+}
+
+impl Source {
+    /// A STUN server on `port`, usually 3478
+    pub fn stun(host: &str, port: u16) -> Self {
+        Source::Stun {
+            host: host.to_string(),
+            port,
+        }
+    }
+
+    /// `https://host/path`, or `http://host/path` without the `dot` feature
+    pub fn web(host: &str, path: &str) -> Self {
+        let tls = cfg!(feature = "dot");
+        Source::Http {
+            host: host.to_string(),
+            port: if tls { 443 } else { 80 },
+            path: path.to_string(),
+            tls,
+        }
+    }
+
+    /// The host asked, None for the gateway
+    pub fn host(&self) -> Option<&str> {
+        match self {
+            Source::Stun { host, .. } | Source::Http { host, .. } => Some(host),
+            #[cfg(feature = "gateway")]
+            Source::Gateway => None,
+        }
+    }
+}
+
+/// The sources asked unless configured otherwise, starting with the
+/// gateway with the `gateway` feature
+pub fn default_sources() -> Vec<Source> {
+    vec![
+        #[cfg(feature = "gateway")]
+        Source::Gateway,
+        Source::stun("stun.l.google.com", 19302),
+        Source::stun("stun.cloudflare.com", 3478),
+        Source::web("api64.ipify.org", "/"),
+    ]
+}
+
+/// How far an address can be trusted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Confidence {
+    /// The sources which answered disagreed, and none had a majority
+    Low,
+    /// Only one source answered, or a majority of them agreed
+    Medium,
+    /// Several sources answered, all alike
+    High,
+}
+
+/// The address one family is seen at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Observation {
+    /// The address most sources answered
+    pub addr: IpAddr,
+    pub confidence: Confidence,
+    /// How many sources answered `addr`
+    pub agreeing: usize,
+    /// How many sources answered at all
+    pub answered: usize,
+}
+
+/// The addresses this host is seen at, for each family it could reach a
+/// source over
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PublicIps {
+    pub v4: Option<Observation>,
+    pub v6: Option<Observation>,
+}
+
+/// The address most of `answers` agree on, and how strongly
+fn tally(answers: &[IpAddr]) -> Option<Observation> {
+    let mut votes: HashMap<IpAddr, usize> = HashMap::new();
+    for addr in answers {
+        *votes.entry(*addr).or_insert(0) += 1;
+    }
+    // ties go to the address answered first
+    let (addr, agreeing) = answers.iter().map(|addr| (*addr, votes[addr])).fold(
+        None,
+        |best: Option<(IpAddr, usize)>, candidate| match best {
+            Some(best) if best.1 >= candidate.1 => Some(best),
+            _ => Some(candidate),
+        },
+    )?;
+    let answered = answers.len();
+    let confidence = if answered > 1 && agreeing == answered {
+        Confidence::High
+    } else if answered == 1 || agreeing * 2 > answered {
+        Confidence::Medium
+    } else {
+        Confidence::Low
+    };
+    Some(Observation {
+        addr,
+        confidence,
+        agreeing,
+        answered,
+    })
+}
+
+/// A STUN binding request with `transaction` as its id
+fn binding_request(transaction: &[u8; 12]) -> [u8; 20] {
+    let mut request = [0u8; 20];
+    request[..2].copy_from_slice(&BINDING_REQUEST.to_be_bytes());
+    // no attributes, so the length stays zero
+    request[4..8].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    request[8..].copy_from_slice(transaction);
+    request
+}
+
+/// The mapped address in a binding `response` to the request `transaction`,
+/// preferring XOR-MAPPED-ADDRESS, which NATs rewriting addresses in payloads
+/// leave alone
+fn parse_binding_response(response: &[u8], transaction: &[u8; 12]) -> Option<SocketAddr> {
+    if response.len() < 20
+        || response[..2] != BINDING_SUCCESS.to_be_bytes()
+        || response[4..8] != MAGIC_COOKIE.to_be_bytes()
+        || response[8..20] != transaction[..]
+    {
+        return None;
+    }
+    let len = usize::from(u16::from_be_bytes([response[2], response[3]]));
+    let attrs = response.get(20..20 + len)?;
+    let mut mapped = None;
+    let mut at = 0;
+    while at + 4 <= attrs.len() {
+        let kind = u16::from_be_bytes([attrs[at], attrs[at + 1]]);
+        let value_len = usize::from(u16::from_be_bytes([attrs[at + 2], attrs[at + 3]]));
+        let value = attrs.get(at + 4..at + 4 + value_len)?;
+        match kind {
+            XOR_MAPPED_ADDRESS => return decode_address(value, Some(transaction)),
+            MAPPED_ADDRESS => mapped = decode_address(value, None),
+            _ => {}
+        }
+        // attributes are padded to four bytes
+        at += 4 + value_len.div_ceil(4) * 4;
+    }
+    mapped
+}
+
+/// A (XOR-)MAPPED-ADDRESS value, XORed with the cookie and `transaction` if
+/// given
+fn decode_address(value: &[u8], transaction: Option<&[u8; 12]>) -> Option<SocketAddr> {
+    let mut key = [0u8; 16];
+    if let Some(transaction) = transaction {
+        key[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        key[4..].copy_from_slice(transaction);
+    }
+    let port = u16::from_be_bytes([value.get(2)? ^ key[0], value.get(3)? ^ key[1]]);
+    let ip = match value.get(1)? {
+        0x01 => {
+            let mut octets = [0u8; 4];
+            for (i, octet) in octets.iter_mut().enumerate() {
+                *octet = value.get(4 + i)? ^ key[i];
+            }
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        0x02 => {
+            let mut octets = [0u8; 16];
+            for (i, octet) in octets.iter_mut().enumerate() {
+                *octet = value.get(4 + i)? ^ key[i];
+            }
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+/// Ask the STUN server at `server` for this host's mapped address
+fn ask_stun(server: SocketAddr, timeout: Duration) -> Result<IpAddr, IpaddrConversionError> {
+    let bind: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind)?;
+    socket.set_read_timeout(Some(timeout))?;
+    socket.connect(server)?;
+    let mut transaction = [0u8; 12];
+    transaction[..8].copy_from_slice(&crate::direct::random_u64().to_be_bytes());
+    transaction[8..].copy_from_slice(&(crate::direct::random_u64() as u32).to_be_bytes());
+    socket.send(&binding_request(&transaction))?;
+    let mut response = [0u8; 512];
+    loop {
+        let received = match socket.recv(&mut response) {
+            Ok(received) => received,
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Err(IpaddrConversionError::Timeout(server.to_string()))
+            }
+            Err(err) => return Err(err.into()),
+        };
+        // anything else is a stray datagram; keep waiting for ours
+        if let Some(mapped) = parse_binding_response(&response[..received], &transaction) {
+            return Ok(mapped.ip());
+        }
+    }
+}
+
+/// Fetch `path` from the web endpoint `host` at `addr`, and parse the body
+/// as an address
+fn ask_web(
+    host: &str,
+    path: &str,
+    tls: bool,
+    addr: SocketAddr,
+    timeout: Duration,
+) -> Result<IpAddr, IpaddrConversionError> {
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: ipaddr-from-str\r\nAccept: text/plain\r\nConnection: close\r\n\r\n",
+        path, host
+    );
+    let mut response = Vec::new();
+    if tls {
+        #[cfg(feature = "dot")]
+        {
+            let config = crate::dot::DotConfig::new(host).with_port(addr.port());
+            let mut stream = crate::dot::DotTransport::new(config)?.connect(
+                addr,
+                &SocketOptions::new(),
+                crate::deadline::clip(timeout),
+            )?;
+            stream.write_all(request.as_bytes())?;
+            // servers closing without close_notify are common, and what was
+            // read by then is complete for HTTP/1.0
+            let _ = stream.take(MAX_RESPONSE).read_to_end(&mut response);
+        }
+        #[cfg(not(feature = "dot"))]
+        return Err(IpaddrConversionError::InvalidInput(format!(
+            "https://{} needs the dot feature",
+            host
+        )));
+    } else {
+        let mut stream = SocketOptions::new()
+            .connect_timeout(timeout)
+            .connect(addr)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.write_all(request.as_bytes())?;
+        stream.take(MAX_RESPONSE).read_to_end(&mut response)?;
+    }
+    let text = String::from_utf8_lossy(&response);
+    let (head, body) = text.split_once("\r\n\r\n").unwrap_or((&text, ""));
+    if head.split_whitespace().nth(1) != Some("200") {
+        return Err(IpaddrConversionError::InvalidInput(format!(
+            "unexpected response from {}: {}",
+            host,
+            head.lines().next().unwrap_or("")
+        )));
+    }
+    body.trim().parse().map_err(|_| {
+        IpaddrConversionError::InvalidInput(format!("{} answered with no address", host))
+    })
+}
+
+/// Asker of a set of sources for this host's public addresses.
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::public_ip::{Confidence, PublicIpDiscovery, Source};
+/// use ipaddr_from_str::SystemResolver;
+///
+/// let found = PublicIpDiscovery::new()
+///     .with_sources(vec![Source::stun("stun.example.net", 3478)])
+///     .discover(&SystemResolver);
+/// if let Some(v4) = found.v4.filter(|v4| v4.confidence >= Confidence::Medium) {
+///     println!("reachable at {}", v4.addr);
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicIpDiscovery {
+    sources: Vec<Source>,
+    timeout: Duration,
+}
+
+impl Default for PublicIpDiscovery {
+    /// The [default sources](default_sources), with [`DEFAULT_TIMEOUT`]
+    fn default() -> Self {
+        Self {
+            sources: default_sources(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl PublicIpDiscovery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask `sources` instead, returning the updated discovery
+    pub fn with_sources(mut self, sources: Vec<Source>) -> Self {
+        self.sources = sources;
+        self
+    }
+
+    /// Give each source `timeout` to answer, returning the updated discovery
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Ask each source, over each family its host has an address of
+    ///
+    /// # Parameters
+    ///
+    /// * `resolver` - resolves the sources' hosts
+    ///
+    /// # Returns
+    /// The address seen for each family at least one source answered over.
+    /// Sources which cannot be resolved, reached or understood are left out.
+    pub fn discover<R: Resolve + ?Sized>(&self, resolver: &R) -> PublicIps {
+        let mut v4 = Vec::new();
+        let mut v6 = Vec::new();
+        for source in &self.sources {
+            // a source reached over one family may still answer the
+            // other's address, as proxies do
+            for answer in self.ask(resolver, source) {
+                match answer {
+                    Ok(seen @ IpAddr::V4(_)) => v4.push(seen),
+                    Ok(seen @ IpAddr::V6(_)) => v6.push(seen),
+                    Err(_) => {}
+                }
+            }
+        }
+        PublicIps {
+            v4: tally(&v4),
+            v6: tally(&v6),
+        }
+    }
+
+    /// The answers of `source`, asked over each family its host has an
+    /// address of
+    fn ask<R: Resolve + ?Sized>(
+        &self,
+        resolver: &R,
+        source: &Source,
+    ) -> Vec<Result<IpAddr, IpaddrConversionError>> {
+        let (host, port) = match source {
+            Source::Stun { host, port } | Source::Http { host, port, .. } => (host, *port),
+            #[cfg(feature = "gateway")]
+            Source::Gateway => {
+                let answer = crate::gateway::external_address(self.timeout)
+                    .map(|(addr, _)| addr)
+                    .and_then(|addr| match addr {
+                        IpAddr::V4(v4)
+                            if v4.is_private()
+                                || (v4.octets()[0] == 100 && v4.octets()[1] & 0xc0 == 64) =>
+                        {
+                            Err(IpaddrConversionError::NotFound(format!(
+                                "public address, not {}",
+                                v4
+                            )))
+                        }
+                        _ => Ok(addr),
+                    });
+                return vec![answer];
+            }
+        };
+        let addrs = match resolver.resolve(host) {
+            Ok(addrs) => addrs,
+            Err(err) => return vec![Err(err)],
+        };
+        [true, false]
+            .iter()
+            .filter_map(|family_v4| addrs.iter().find(|ip| ip.is_ipv4() == *family_v4))
+            .map(|ip| {
+                let server = SocketAddr::new(*ip, port);
+                match source {
+                    Source::Http { path, tls, .. } => {
+                        ask_web(host, path, *tls, server, self.timeout)
+                    }
+                    _ => ask_stun(server, self.timeout),
+                }
+            })
+            .collect()
+    }
+}
+
+/// The addresses this host is seen at from the internet, asking the
+/// [default sources](default_sources) through the system resolver.
+///
+/// This sends requests to third party servers.
+pub fn discover_public_ip() -> PublicIps {
+    PublicIpDiscovery::new().discover(&SystemResolver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::StaticResolver;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// A binding success for `request`, mapping to `mapped`
+    fn binding_response(request: &[u8], mapped: SocketAddr) -> Vec<u8> {
+        let mut cookie = [0u8; 16];
+        cookie.copy_from_slice(&request[4..20]);
+        let (family, octets) = match mapped.ip() {
+            IpAddr::V4(v4) => (1, v4.octets().to_vec()),
+            IpAddr::V6(v6) => (2, v6.octets().to_vec()),
+        };
+        let mut value = vec![0, family];
+        value.extend_from_slice(&(mapped.port() ^ 0x2112).to_be_bytes());
+        value.extend(octets.iter().zip(cookie.iter()).map(|(a, k)| a ^ k));
+        let mut response = BINDING_SUCCESS.to_be_bytes().to_vec();
+        response.extend_from_slice(&(value.len() as u16 + 12).to_be_bytes());
+        response.extend_from_slice(&request[4..20]);
+        // an unknown attribute first, padded, which must be skipped
+        response.extend_from_slice(&[0x80, 0x22, 0, 3, b'f', b'o', b'o', 0]);
+        response.extend_from_slice(&XOR_MAPPED_ADDRESS.to_be_bytes());
+        response.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        response.extend_from_slice(&value);
+        response
+    }
+
+    #[test]
+    fn binding_responses_are_decoded() {
+        let transaction = [7u8; 12];
+        let request = binding_request(&transaction);
+        for mapped in ["203.0.113.5:40000", "[2001:db8::77]:5060"] {
+            let mapped: SocketAddr = mapped.parse().unwrap();
+            let response = binding_response(&request, mapped);
+            assert_eq!(
+                parse_binding_response(&response, &transaction),
+                Some(mapped)
+            );
+            assert_eq!(parse_binding_response(&response, &[8u8; 12]), None);
+        }
+    }
+    #[test]
+    fn answers_are_tallied() {
+        let a: IpAddr = "198.51.100.1".parse().unwrap();
+        let b: IpAddr = "198.51.100.2".parse().unwrap();
+        let c: IpAddr = "198.51.100.3".parse().unwrap();
+        assert_eq!(tally(&[]), None);
+        assert_eq!(tally(&[a, a]).unwrap().confidence, Confidence::High);
+        assert_eq!(tally(&[b]).unwrap().confidence, Confidence::Medium);
+        let majority = tally(&[b, a, a]).unwrap();
+        assert_eq!((majority.addr, majority.agreeing), (a, 2));
+        assert_eq!(majority.confidence, Confidence::Medium);
+        let split = tally(&[c, a, b, a, c]);
+        assert_eq!(split.unwrap().confidence, Confidence::Low);
+    }
+    #[test]
+    fn sources_are_asked_over_loopback() {
+        let stun = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let stun_port = stun.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let mut request = [0u8; 64];
+            let (len, peer) = stun.recv_from(&mut request).unwrap();
+            let mapped = "203.0.113.9:1234".parse().unwrap();
+            stun.send_to(&binding_response(&request[..len], mapped), peer)
+                .unwrap();
+        });
+        let web = TcpListener::bind("127.0.0.1:0").unwrap();
+        let web_port = web.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut stream, _) = web.accept().unwrap();
+            let _ = stream.read(&mut [0u8; 1024]);
+            stream
+                .write_all(b"HTTP/1.0 200 OK\r\n\r\n203.0.113.9\n")
+                .unwrap();
+        });
+        let resolver =
+            StaticResolver::new().with_entry("stun.test", vec!["127.0.0.1".parse().unwrap()]);
+        let found = PublicIpDiscovery::new()
+            .with_sources(vec![
+                Source::stun("stun.test", stun_port),
+                Source::Http {
+                    host: "127.0.0.1".into(),
+                    port: web_port,
+                    path: "/".into(),
+                    tls: false,
+                },
+                Source::stun("unknown.test", 3478),
+            ])
+            .with_timeout(Duration::from_secs(2))
+            .discover(&resolver);
+        let v4 = found.v4.unwrap();
+        assert_eq!(v4.addr, "203.0.113.9".parse::<IpAddr>().unwrap());
+        assert_eq!((v4.confidence, v4.answered), (Confidence::High, 2));
+        assert_eq!(found.v6, None);
+    }
+}
@@ -0,0 +1,150 @@
+//! Punycode (RFC 3492) decoding of internationalized domain name labels.
+//!
+//! Names travel over DNS in their ASCII compatible encoding, where each
+//! non-ASCII label is punycoded behind an `xn--` prefix. That form is what
+//! should be compared and stored; [`to_unicode`] is for showing names to
+//! people.
+
+/// Prefix marking a punycoded label
+pub const ACE_PREFIX: &str = "xn--";
+
+const BASE: u32 = 36;
+const T_MIN: u32 = 1;
+const T_MAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 128;
+
+fn adapt(mut delta: u32, num_points: u32, first_time: bool) -> u32 {
+    delta /= if first_time { DAMP } else { 2 };
+    delta += delta / num_points;
+    let mut k = 0;
+    while delta > ((BASE - T_MIN) * T_MAX) / 2 {
+        delta /= BASE - T_MIN;
+        k += BASE;
+    }
+    k + (((BASE - T_MIN + 1) * delta) / (delta + SKEW))
+}
+
+fn digit_value(c: u8) -> Option<u32> {
+    match c {
+        b'a'..=b'z' => Some((c - b'a') as u32),
+        b'A'..=b'Z' => Some((c - b'A') as u32),
+        b'0'..=b'9' => Some((c - b'0') as u32 + 26),
+        _ => None,
+    }
+}
+
+/// Decode a punycoded label, without its `xn--` prefix.
+///
+/// # Returns
+/// The Unicode label, or None if `input` is not valid punycode
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::punycode::decode;
+///
+/// assert_eq!(decode("bcher-kva").unwrap(), "bücher");
+/// assert!(decode("bcher-kva!").is_none());
+/// ```
+pub fn decode(input: &str) -> Option<String> {
+    if !input.is_ascii() {
+        return None;
+    }
+    let (basic, extended) = match input.rfind('-') {
+        Some(pos) => (&input[..pos], &input[pos + 1..]),
+        None => ("", input),
+    };
+    let mut output: Vec<char> = basic.chars().collect();
+    let mut n = INITIAL_N;
+    let mut i: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+    let mut bytes = extended.bytes().peekable();
+    while bytes.peek().is_some() {
+        let old_i = i;
+        let mut weight: u32 = 1;
+        let mut k = BASE;
+        loop {
+            let digit = digit_value(bytes.next()?)?;
+            i = i.checked_add(digit.checked_mul(weight)?)?;
+            let t = if k <= bias {
+                T_MIN
+            } else if k >= bias + T_MAX {
+                T_MAX
+            } else {
+                k - bias
+            };
+            if digit < t {
+                break;
+            }
+            weight = weight.checked_mul(BASE - t)?;
+            k += BASE;
+        }
+        let len = output.len() as u32 + 1;
+        bias = adapt(i - old_i, len, old_i == 0);
+        n = n.checked_add(i / len)?;
+        i %= len;
+        output.insert(i as usize, std::char::from_u32(n)?);
+        i += 1;
+    }
+    Some(output.into_iter().collect())
+}
+
+/// Decode every `xn--` label of `name` for display.
+///
+/// Labels which fail to decode are left in their ASCII form, so the result is
+/// always something the operator can look up again.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::punycode::to_unicode;
+///
+/// assert_eq!(to_unicode("www.xn--bcher-kva.example"), "www.bücher.example");
+/// assert_eq!(to_unicode("xn--invalid-.example"), "xn--invalid-.example");
+/// ```
+pub fn to_unicode(name: &str) -> String {
+    name.split('.')
+        .map(|label| {
+            let prefix = label.get(..ACE_PREFIX.len());
+            match prefix {
+                Some(prefix) if prefix.eq_ignore_ascii_case(ACE_PREFIX) => {
+                    decode(&label[ACE_PREFIX.len()..])
+                        .filter(|decoded| !decoded.is_ascii())
+                        .unwrap_or_else(|| label.to_string())
+                }
+                _ => label.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_rfc_3492_samples() {
+        // samples (A), (L) and (N) of the RFC
+        assert_eq!(
+            decode("egbpdaj6bu4bxfgehfvwxn").unwrap(),
+            "\u{644}\u{64a}\u{647}\u{645}\u{627}\u{628}\u{62a}\u{643}\u{644}\u{645}\u{648}\u{634}\u{639}\u{631}\u{628}\u{64a}\u{61f}"
+        );
+        assert_eq!(
+            decode("3B-ww4c5e180e575a65lsy2b").unwrap(),
+            "3\u{5e74}B\u{7d44}\u{91d1}\u{516b}\u{5148}\u{751f}"
+        );
+        assert_eq!(
+            decode("Hello-Another-Way--fc4qua05auwb3674vfr0b").unwrap(),
+            "Hello-Another-Way-\u{305d}\u{308c}\u{305e}\u{308c}\u{306e}\u{5834}\u{6240}"
+        );
+    }
+    #[test]
+    fn rejects_invalid_input() {
+        assert!(decode("zzzzzzzzzzzzzzzzzzzzzzzzz").is_none());
+        assert!(decode("ab-c\u{e9}").is_none());
+    }
+}
@@ -0,0 +1,210 @@
+//! Forward-confirmed reverse DNS.
+//!
+//! Anyone who controls the reverse zone of an address can make its PTR
+//! record claim any name, so a reverse lookup alone proves nothing. Mail
+//! servers, ssh and access logs instead trust a name only if it resolves
+//! back to the address: [`confirm_reverse`] makes both lookups and says
+//! whether they agree.
+use crate::errors::IpaddrConversionError;
+use crate::lookup::reverse_lookup;
+use crate::resolver::{Resolve, SystemResolver};
+use dns_lookup::LookupErrorKind;
+use std::net::IpAddr;
+
+/// The name PTR records of `addr` are looked up under: the octets reversed
+/// under `in-addr.arpa` for IPv4, the nibbles reversed under `ip6.arpa`
+/// for IPv6
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::rdns::reverse_name;
+///
+/// assert_eq!(reverse_name("192.0.2.1".parse().unwrap()), "1.2.0.192.in-addr.arpa");
+/// ```
+pub fn reverse_name(addr: IpAddr) -> String {
+    let zone = match addr {
+        IpAddr::V4(_) => "in-addr.arpa",
+        IpAddr::V6(_) => "ip6.arpa",
+    };
+    format!("{}.{}", reversed_labels(addr), zone)
+}
+
+/// The octets of an IPv4 address, or the nibbles of an IPv6 address, as
+/// labels in reverse order, as reverse zones and DNS blocklists name them
+pub(crate) fn reversed_labels(addr: IpAddr) -> String {
+    match addr {
+        IpAddr::V4(v4) => {
+            let [a, b, c, d] = v4.octets();
+            format!("{}.{}.{}.{}", d, c, b, a)
+        }
+        IpAddr::V6(v6) => {
+            let labels: Vec<String> = v6
+                .octets()
+                .iter()
+                .rev()
+                .map(|byte| format!("{:x}.{:x}", byte & 0xf, byte >> 4))
+                .collect();
+            labels.join(".")
+        }
+    }
+}
+
+/// The outcome of a forward-confirmed reverse lookup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReverseVerdict {
+    /// This name, from the PTR records, resolves back to the address
+    Confirmed(String),
+    /// The address has reverse names, but none of them resolves back to it
+    Mismatch(Vec<String>),
+    /// The address has no reverse name
+    NoName,
+}
+
+impl ReverseVerdict {
+    /// The confirmed name, if there is one
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            ReverseVerdict::Confirmed(name) => Some(name),
+            _ => None,
+        }
+    }
+}
+
+/// Whether `a` and `b` are the same address, taking IPv4-mapped IPv6
+/// addresses as their IPv4 address
+fn same_addr(a: IpAddr, b: IpAddr) -> bool {
+    a.to_canonical() == b.to_canonical()
+}
+
+/// Check which of `names`, the reverse names of `addr`, resolve back to it
+/// through `resolver`
+///
+/// # Parameters
+///
+/// * `addr` - the address looked up
+/// * `names` - its reverse names, in the order they were given
+/// * `resolver` - resolves each name forward
+///
+/// # Returns
+/// The first name confirmed, Mismatch if names were given but none resolves
+/// to `addr`, or NoName if none were given. If no name is confirmed and a
+/// forward lookup failed for another reason than the name not existing, that
+/// error is returned instead, since a retry might confirm it.
+pub fn confirm_names<R: Resolve + ?Sized>(
+    addr: IpAddr,
+    names: &[String],
+    resolver: &R,
+) -> Result<ReverseVerdict, IpaddrConversionError> {
+    if names.is_empty() {
+        return Ok(ReverseVerdict::NoName);
+    }
+    let mut failure = None;
+    for name in names {
+        match resolver.resolve(name) {
+            Ok(addrs) if addrs.iter().any(|found| same_addr(*found, addr)) => {
+                return Ok(ReverseVerdict::Confirmed(name.clone()));
+            }
+            Ok(_) | Err(IpaddrConversionError::NotFound(_)) => {}
+            Err(err) => failure = failure.or(Some(err)),
+        }
+    }
+    match failure {
+        Some(err) => Err(err),
+        None => Ok(ReverseVerdict::Mismatch(names.to_vec())),
+    }
+}
+
+/// Look up the reverse name of `addr` and check it resolves back to `addr`,
+/// both through the system resolver.
+///
+/// The system resolver gives only the first PTR name; use
+/// [`DirectResolver::confirm_reverse`](crate::direct::DirectResolver::confirm_reverse)
+/// to consider them all.
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::rdns::{confirm_reverse, ReverseVerdict};
+///
+/// match confirm_reverse("192.0.2.1".parse().unwrap()).unwrap() {
+///     ReverseVerdict::Confirmed(name) => println!("connection from {}", name),
+///     ReverseVerdict::Mismatch(names) => println!("{:?} does not resolve back", names),
+///     ReverseVerdict::NoName => println!("no reverse name"),
+/// }
+/// ```
+pub fn confirm_reverse(addr: IpAddr) -> Result<ReverseVerdict, IpaddrConversionError> {
+    let name = match reverse_lookup(addr) {
+        Ok(name) => name,
+        Err(IpaddrConversionError::LookupError(ref err))
+            if matches!(err.kind(), LookupErrorKind::NoName) =>
+        {
+            return Ok(ReverseVerdict::NoName)
+        }
+        Err(IpaddrConversionError::NotFound(_)) => return Ok(ReverseVerdict::NoName),
+        Err(err) => return Err(err),
+    };
+    // getnameinfo can fall back to the numeric form, which is no name at all
+    if name.parse::<IpAddr>().is_ok() {
+        return Ok(ReverseVerdict::NoName);
+    }
+    confirm_names(addr, &[name], &SystemResolver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::StaticResolver;
+
+    #[test]
+    fn reverse_names_are_built() {
+        assert_eq!(
+            reverse_name("10.1.2.3".parse().unwrap()),
+            "3.2.1.10.in-addr.arpa"
+        );
+        assert_eq!(
+            reverse_name("2001:db8::567:89ab".parse().unwrap()),
+            "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+        );
+    }
+    #[test]
+    fn names_are_confirmed_forward() {
+        let resolver = StaticResolver::new()
+            .with_entry("mail.example.com", vec!["10.0.0.1".parse().unwrap()])
+            .with_entry("spoofed.example.com", vec!["10.0.0.9".parse().unwrap()]);
+        let addr = "10.0.0.1".parse().unwrap();
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            confirm_names(
+                addr,
+                &names(&["spoofed.example.com", "mail.example.com"]),
+                &resolver
+            )
+            .unwrap(),
+            ReverseVerdict::Confirmed("mail.example.com".into())
+        );
+        assert_eq!(
+            confirm_names(
+                addr,
+                &names(&["spoofed.example.com", "gone.example.com"]),
+                &resolver
+            )
+            .unwrap(),
+            ReverseVerdict::Mismatch(names(&["spoofed.example.com", "gone.example.com"]))
+        );
+        assert_eq!(
+            confirm_names(
+                "::ffff:10.0.0.1".parse().unwrap(),
+                &names(&["mail.example.com"]),
+                &resolver
+            )
+            .unwrap()
+            .name(),
+            Some("mail.example.com")
+        );
+        assert_eq!(
+            confirm_names(addr, &[], &resolver).unwrap(),
+            ReverseVerdict::NoName
+        );
+    }
+}
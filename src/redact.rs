@@ -0,0 +1,140 @@
+//! Masking client addresses and hostnames in what the crate writes out.
+//!
+//! [`Redacted`] displays an address with its host part masked, `192.0.2.x`
+//! or `2001:db8:1::x`, and a hostname with all but its last two labels
+//! masked, `*.*.example.com`, which keeps enough to tell networks and
+//! domains apart and no more. Once [`set_redacting`] turns redaction on,
+//! the lines of the audit log and the conflicts reported are written
+//! through it, so logs can be kept where full client addresses may not be.
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REDACTING: AtomicBool = AtomicBool::new(false);
+
+/// Turn on or off the redaction of addresses and names in the crate's own
+/// output, for the whole process
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::audit::AuditResolver;
+/// use ipaddr_from_str::redact::set_redacting;
+/// use ipaddr_from_str::{Resolve, StaticResolver};
+///
+/// set_redacting(true);
+/// let resolver = AuditResolver::new(
+///     StaticResolver::new().with_entry("laptop.corp.example.com", vec!["10.1.2.3".parse().unwrap()]),
+///     "static",
+/// );
+/// resolver.resolve("laptop.corp.example.com").unwrap();
+/// let mut exported = Vec::new();
+/// resolver.log().export(&mut exported).unwrap();
+/// let line = String::from_utf8(exported).unwrap();
+/// assert!(line.contains("\t*.*.example.com\tok 10.1.2.x\t"));
+/// ```
+pub fn set_redacting(enabled: bool) {
+    REDACTING.store(enabled, Ordering::SeqCst);
+}
+
+/// Whether the crate's own output is redacted
+pub fn is_redacting() -> bool {
+    REDACTING.load(Ordering::SeqCst)
+}
+
+/// An address or hostname, displayed masked.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::redact::Redacted;
+/// use std::net::IpAddr;
+///
+/// let client: IpAddr = "198.51.100.23".parse().unwrap();
+/// assert_eq!(Redacted(client).to_string(), "198.51.100.x");
+/// assert_eq!(Redacted("laptop-17.corp.example.com").to_string(), "*.*.example.com");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Redacted<T>(pub T);
+
+impl fmt::Display for Redacted<IpAddr> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            IpAddr::V4(v4) => {
+                let [a, b, c, _] = v4.octets();
+                write!(f, "{}.{}.{}.x", a, b, c)
+            }
+            // the /48 a site is usually given
+            IpAddr::V6(v6) => {
+                let [a, b, c, ..] = v6.segments();
+                write!(f, "{:x}:{:x}:{:x}::x", a, b, c)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Redacted<SocketAddr> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            SocketAddr::V4(v4) => write!(f, "{}:{}", Redacted(IpAddr::V4(*v4.ip())), v4.port()),
+            SocketAddr::V6(v6) => write!(f, "[{}]:{}", Redacted(IpAddr::V6(*v6.ip())), v6.port()),
+        }
+    }
+}
+
+impl fmt::Display for Redacted<&str> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Ok(addr) = self.0.parse::<IpAddr>() {
+            return Redacted(addr).fmt(f);
+        }
+        let (name, dot) = match self.0.strip_suffix('.') {
+            Some(name) => (name, "."),
+            None => (self.0, ""),
+        };
+        let labels: Vec<&str> = name.split('.').collect();
+        // a bare host name is all host
+        let kept = if labels.len() > 1 { 2 } else { 0 };
+        let masked = labels.len() - kept;
+        let mut parts = vec!["*"; masked];
+        parts.extend_from_slice(&labels[masked..]);
+        write!(f, "{}{}", parts.join("."), dot)
+    }
+}
+
+/// `addr` as the crate writes it out: redacted if [`is_redacting`]
+pub(crate) fn addr_text(addr: IpAddr) -> String {
+    if is_redacting() {
+        Redacted(addr).to_string()
+    } else {
+        addr.to_string()
+    }
+}
+
+/// `name` as the crate writes it out: redacted if [`is_redacting`]
+pub(crate) fn name_text(name: &str) -> String {
+    if is_redacting() {
+        Redacted(name).to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses_keep_their_network() {
+        let v6: IpAddr = "2001:db8:1:2::17".parse().unwrap();
+        assert_eq!(Redacted(v6).to_string(), "2001:db8:1::x");
+        let socket: SocketAddr = "[2001:db8:1:2::17]:443".parse().unwrap();
+        assert_eq!(Redacted(socket).to_string(), "[2001:db8:1::x]:443");
+        assert_eq!(Redacted("10.1.2.3").to_string(), "10.1.2.x");
+    }
+    #[test]
+    fn names_keep_their_domain() {
+        assert_eq!(Redacted("db.example.com.").to_string(), "*.example.com.");
+        assert_eq!(Redacted("example.com").to_string(), "example.com");
+        assert_eq!(Redacted("printer").to_string(), "*");
+    }
+}
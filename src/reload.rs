@@ -0,0 +1,334 @@
+//! Resolver configuration reloaded at runtime, without a restart.
+//!
+//! A [`ReloadingResolver`] builds its resolver from files: resolv.conf for
+//! the nameservers, a hosts file, a file of overrides in hosts file format
+//! answered before anything else, and a [policy](crate::policy) file.
+//! [`reload`](ReloadingResolver::reload) reads them all again and swaps the
+//! new resolver in at once, so a lookup sees either the old configuration
+//! or the new one, never a mix, and lookups already under way finish with
+//! the configuration they started with. [`watch`](ReloadingResolver::watch)
+//! reloads whenever one of the files changes.
+use crate::cancel::CancellationToken;
+use crate::direct::DirectResolver;
+use crate::errors::IpaddrConversionError;
+use crate::hosts::HostsFile;
+use crate::nsswitch::Source;
+use crate::policy::{Policy, PolicyResolver};
+use crate::resolv_conf::ResolvConf;
+use crate::resolver::Resolve;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+/// The files a configuration is read from
+#[derive(Debug, Clone, Default)]
+struct Paths {
+    resolv_conf: Option<PathBuf>,
+    hosts: Option<PathBuf>,
+    overrides: Option<PathBuf>,
+    policy: Option<PathBuf>,
+}
+
+impl Paths {
+    fn iter(&self) -> impl Iterator<Item = &PathBuf> {
+        self.resolv_conf
+            .iter()
+            .chain(&self.hosts)
+            .chain(&self.overrides)
+            .chain(&self.policy)
+    }
+}
+
+/// When `path` was last modified, None if that cannot be told
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// A resolver built from one reading of the files
+#[derive(Debug)]
+struct Loaded {
+    overrides: HostsFile,
+    resolver: PolicyResolver<DirectResolver>,
+    stamps: Vec<Option<SystemTime>>,
+}
+
+impl Loaded {
+    fn read(paths: &Paths) -> Result<Self, IpaddrConversionError> {
+        // stamped before reading, so a change made while reading is seen
+        // by the next check
+        let stamps = paths.iter().map(|path| modified(path)).collect();
+        let config = match paths.resolv_conf {
+            Some(ref path) => ResolvConf::from_file(path)?,
+            None => ResolvConf::system()?,
+        };
+        let hosts = match paths.hosts {
+            Some(ref path) => HostsFile::from_file(path)?,
+            None => HostsFile::system().unwrap_or_default(),
+        };
+        let overrides = match paths.overrides {
+            Some(ref path) => HostsFile::from_file(path)?,
+            None => HostsFile::default(),
+        };
+        let policy = match paths.policy {
+            Some(ref path) => Policy::load(path)?,
+            None => Policy::default(),
+        };
+        let direct = DirectResolver::new(config)
+            .with_sources(vec![Source::Files, Source::Dns])
+            .with_hosts(hosts);
+        Ok(Self {
+            overrides,
+            resolver: PolicyResolver::new(direct, policy),
+            stamps,
+        })
+    }
+}
+
+impl Resolve for Loaded {
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        if let Some(addrs) = self.overrides.lookup(hostname_or_address) {
+            return Ok(addrs.to_vec());
+        }
+        self.resolver.resolve(hostname_or_address)
+    }
+}
+
+/// Resolver whose configuration files can be read again while it is in use.
+///
+/// Files not set are the system's resolv.conf and hosts file, with no
+/// overrides and no policy, and only the files set are watched for
+/// changes. Clones share the configuration, so reloading one reloads all.
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::cancel::CancellationToken;
+/// use ipaddr_from_str::reload::ReloadingResolver;
+/// use ipaddr_from_str::Resolve;
+/// use std::time::Duration;
+///
+/// let resolver = ReloadingResolver::builder()
+///     .with_overrides_file("/etc/myapp/overrides")
+///     .with_policy_file("/etc/myapp/policy")
+///     .build()
+///     .unwrap();
+/// let token = CancellationToken::new();
+/// resolver.watch(Duration::from_secs(5), token.clone(), |result| {
+///     if let Err(err) = result {
+///         eprintln!("keeping the old configuration: {}", err);
+///     }
+/// });
+/// let addrs = resolver.resolve("db.internal");
+/// token.cancel();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ReloadingResolver {
+    paths: Arc<Paths>,
+    current: Arc<RwLock<Arc<Loaded>>>,
+    generation: Arc<AtomicU64>,
+}
+
+/// Sets the files a [`ReloadingResolver`] is built from
+#[derive(Debug, Clone, Default)]
+pub struct ReloadingResolverBuilder {
+    paths: Paths,
+}
+
+impl ReloadingResolverBuilder {
+    /// Read the nameservers and options from `path`, returning the updated
+    /// builder
+    pub fn with_resolv_conf<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.paths.resolv_conf = Some(path.into());
+        self
+    }
+
+    /// Read the hosts file from `path`, returning the updated builder
+    pub fn with_hosts_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.paths.hosts = Some(path.into());
+        self
+    }
+
+    /// Read overrides, in hosts file format, from `path`, returning the
+    /// updated builder. Overrides answer before the hosts file, the
+    /// nameservers and the policy.
+    pub fn with_overrides_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.paths.overrides = Some(path.into());
+        self
+    }
+
+    /// Read the policy applied to lookups from `path`, returning the
+    /// updated builder
+    pub fn with_policy_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.paths.policy = Some(path.into());
+        self
+    }
+
+    /// Read the files and build the resolver
+    ///
+    /// # Returns
+    /// The resolver, or the error reading or parsing one of the files
+    pub fn build(self) -> Result<ReloadingResolver, IpaddrConversionError> {
+        let loaded = Loaded::read(&self.paths)?;
+        Ok(ReloadingResolver {
+            paths: Arc::new(self.paths),
+            current: Arc::new(RwLock::new(Arc::new(loaded))),
+            generation: Arc::new(AtomicU64::new(0)),
+        })
+    }
+}
+
+impl ReloadingResolver {
+    /// A builder with no files set
+    pub fn builder() -> ReloadingResolverBuilder {
+        ReloadingResolverBuilder::default()
+    }
+
+    fn loaded(&self) -> Arc<Loaded> {
+        self.current
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// Read every file again and switch to the configuration they give
+    ///
+    /// # Returns
+    /// Nothing, or the error reading or parsing one of the files, in which
+    /// case the configuration in use is kept
+    pub fn reload(&self) -> Result<(), IpaddrConversionError> {
+        let loaded = Arc::new(Loaded::read(&self.paths)?);
+        *self
+            .current
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = loaded;
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Reload if any file set was modified, created or removed since the
+    /// configuration in use was read, returning whether it was reloaded
+    pub fn reload_if_changed(&self) -> Result<bool, IpaddrConversionError> {
+        let changed = self.paths.iter().map(|path| modified(path)).ne(self
+            .loaded()
+            .stamps
+            .iter()
+            .copied());
+        if changed {
+            self.reload()?;
+        }
+        Ok(changed)
+    }
+
+    /// The number of times the configuration has been reloaded
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// Check the files for changes every `interval` on a new thread,
+    /// reloading when one changed and passing `on_check` whether it did or
+    /// the error which kept the old configuration, until `token` is
+    /// cancelled
+    pub fn watch<F>(
+        &self,
+        interval: Duration,
+        token: CancellationToken,
+        mut on_check: F,
+    ) -> JoinHandle<()>
+    where
+        F: FnMut(Result<bool, IpaddrConversionError>) + Send + 'static,
+    {
+        let resolver = self.clone();
+        thread::spawn(move || {
+            while !token.sleep(interval) {
+                on_check(resolver.reload_if_changed());
+            }
+        })
+    }
+}
+
+impl Resolve for ReloadingResolver {
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        // the configuration is held for the whole lookup, and the lock only
+        // while taking it
+        self.loaded().resolve(hostname_or_address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::ips;
+    use std::fs::File;
+
+    /// A directory of configuration files for one test
+    fn config_dir(test: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("ipaddr-reload-{}-{}", test, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("resolv.conf"), "nameserver 192.0.2.53\n").unwrap();
+        fs::write(
+            dir.join("hosts"),
+            "10.0.0.1 db.internal\n10.0.0.2 cache.internal\n",
+        )
+        .unwrap();
+        fs::write(dir.join("overrides"), "").unwrap();
+        fs::write(dir.join("policy"), "").unwrap();
+        dir
+    }
+
+    fn build(dir: &Path) -> ReloadingResolver {
+        ReloadingResolver::builder()
+            .with_resolv_conf(dir.join("resolv.conf"))
+            .with_hosts_file(dir.join("hosts"))
+            .with_overrides_file(dir.join("overrides"))
+            .with_policy_file(dir.join("policy"))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn reload_swaps_the_whole_configuration() {
+        let dir = config_dir("swap");
+        let resolver = build(&dir);
+        let clone = resolver.clone();
+        assert_eq!(resolver.resolve("db.internal").unwrap(), ips(&["10.0.0.1"]));
+        fs::write(dir.join("overrides"), "10.9.9.9 db.internal\n").unwrap();
+        fs::write(dir.join("policy"), "when host matches cache.* -> reject\n").unwrap();
+        // nothing changes until reloaded
+        assert!(resolver.resolve("cache.internal").is_ok());
+        resolver.reload().unwrap();
+        assert_eq!(clone.resolve("db.internal").unwrap(), ips(&["10.9.9.9"]));
+        assert!(matches!(
+            clone.resolve("cache.internal"),
+            Err(IpaddrConversionError::Blocked(_))
+        ));
+        // a bad file keeps the configuration in use
+        fs::write(dir.join("policy"), "when nothing -> reject\n").unwrap();
+        assert!(resolver.reload().is_err());
+        assert_eq!(resolver.generation(), 1);
+        assert_eq!(resolver.resolve("db.internal").unwrap(), ips(&["10.9.9.9"]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn changed_files_are_reloaded() {
+        let dir = config_dir("changed");
+        let resolver = build(&dir);
+        assert!(!resolver.reload_if_changed().unwrap());
+        fs::write(dir.join("hosts"), "10.0.0.7 db.internal\n").unwrap();
+        // mtimes can be coarse, so the change is dated unmistakably later
+        File::options()
+            .write(true)
+            .open(dir.join("hosts"))
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        assert!(resolver.reload_if_changed().unwrap());
+        assert_eq!(resolver.resolve("db.internal").unwrap(), ips(&["10.0.0.7"]));
+        assert!(!resolver.reload_if_changed().unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
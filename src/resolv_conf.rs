@@ -0,0 +1,255 @@
+//! Parsing of resolv.conf(5) files.
+//!
+//! The system resolver reads `/etc/resolv.conf` itself; the types here exist so
+//! that the crate's own backends (see [`crate::direct`]) can honor the same
+//! configuration. Parsing is as lenient as glibc's: unknown directives and
+//! malformed values are ignored rather than treated as errors.
+use crate::errors::IpaddrConversionError;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV6};
+use std::path::Path;
+use std::time::Duration;
+
+/// Location of the system resolver configuration
+pub const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+/// Maximum number of nameservers honored, as per glibc's MAXNS
+pub const MAX_NAMESERVERS: usize = 3;
+/// Port nameservers listen on
+pub const DNS_PORT: u16 = 53;
+
+pub(crate) const MAX_NDOTS: usize = 15;
+pub(crate) const MAX_TIMEOUT_SECS: u64 = 30;
+pub(crate) const MAX_ATTEMPTS: usize = 5;
+
+/// Typed contents of a resolv.conf file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvConf {
+    /// Nameservers, in the order they are listed
+    pub nameservers: Vec<SocketAddr>,
+    /// Domains appended to names which are not fully qualified
+    pub search: Vec<String>,
+    /// Names with fewer dots than this have the search list applied first
+    pub ndots: usize,
+    /// How long to wait for an answer from a single nameserver
+    pub timeout: Duration,
+    /// How many times to cycle through the nameservers before giving up
+    pub attempts: usize,
+    /// Spread queries across nameservers rather than always starting with the first
+    pub rotate: bool,
+    /// Query over TCP rather than UDP (the `use-vc` option)
+    pub use_vc: bool,
+}
+
+impl Default for ResolvConf {
+    /// The configuration glibc falls back on when resolv.conf is empty or missing
+    fn default() -> Self {
+        Self {
+            nameservers: Vec::new(),
+            search: Vec::new(),
+            ndots: 1,
+            timeout: Duration::from_secs(5),
+            attempts: 2,
+            rotate: false,
+            use_vc: false,
+        }
+    }
+}
+
+impl ResolvConf {
+    /// Parse the contents of a resolv.conf file
+    ///
+    /// # Parameters
+    ///
+    /// * `contents` - the text of the file
+    ///
+    /// # Returns
+    /// The parsed config. When no nameservers are listed, the local host is
+    /// used, as glibc does.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ipaddr_from_str::resolv_conf::ResolvConf;
+    /// let conf = ResolvConf::parse("nameserver 10.0.0.2\nsearch corp.example.com\noptions ndots:2");
+    /// assert_eq!(conf.nameservers[0], "10.0.0.2:53".parse().unwrap());
+    /// assert_eq!(conf.search, vec!["corp.example.com"]);
+    /// assert_eq!(conf.ndots, 2);
+    /// ```
+    pub fn parse(contents: &str) -> Self {
+        let mut conf = Self::default();
+        for line in contents.lines() {
+            let line = match line.find(['#', ';']) {
+                Some(idx) => &line[..idx],
+                None => line,
+            };
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("nameserver") => {
+                    if let Some(server) = words.next().and_then(parse_nameserver) {
+                        if conf.nameservers.len() < MAX_NAMESERVERS {
+                            conf.nameservers.push(server);
+                        }
+                    }
+                }
+                // domain and search override each other; the last one wins
+                Some("domain") => {
+                    conf.search = words.next().map(normalize_domain).into_iter().collect();
+                }
+                Some("search") => conf.search = words.map(normalize_domain).collect(),
+                Some("options") => words.for_each(|option| conf.apply_option(option)),
+                _ => {}
+            }
+        }
+        if conf.nameservers.is_empty() {
+            conf.nameservers
+                .push(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), DNS_PORT));
+        }
+        conf
+    }
+
+    /// Read and parse the resolv.conf file at `path`
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, IpaddrConversionError> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    /// Read and parse the system resolv.conf
+    #[cfg(not(windows))]
+    pub fn system() -> Result<Self, IpaddrConversionError> {
+        Self::from_file(RESOLV_CONF_PATH)
+    }
+
+    /// Read the system resolver configuration from the registry
+    #[cfg(windows)]
+    pub fn system() -> Result<Self, IpaddrConversionError> {
+        crate::win_registry::resolv_conf()
+    }
+
+    /// The names to try for `name`, in the order glibc tries them.
+    ///
+    /// A name ending in a dot is absolute and tried alone. One with at least
+    /// `ndots` dots is tried as written before the search domains are
+    /// appended, and one with fewer after.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ipaddr_from_str::resolv_conf::ResolvConf;
+    /// let conf = ResolvConf::parse("search corp.example");
+    /// assert_eq!(conf.candidates("db"), vec!["db.corp.example", "db"]);
+    /// assert_eq!(conf.candidates("db.example.com."), vec!["db.example.com"]);
+    /// ```
+    pub fn candidates(&self, name: &str) -> Vec<String> {
+        search_candidates(name, &self.search, self.ndots)
+    }
+
+    fn apply_option(&mut self, option: &str) {
+        let (name, value) = match option.find(':') {
+            Some(idx) => (&option[..idx], Some(&option[idx + 1..])),
+            None => (option, None),
+        };
+        let value = value.and_then(|v| v.parse::<u64>().ok());
+        match (name, value) {
+            ("ndots", Some(v)) => self.ndots = (v as usize).min(MAX_NDOTS),
+            ("timeout", Some(v)) => {
+                self.timeout = Duration::from_secs(v.clamp(1, MAX_TIMEOUT_SECS))
+            }
+            ("attempts", Some(v)) => self.attempts = (v as usize).clamp(1, MAX_ATTEMPTS),
+            ("rotate", _) => self.rotate = true,
+            ("use-vc", _) | ("usevc", _) => self.use_vc = true,
+            _ => {}
+        }
+    }
+}
+
+/// The names to try for `name` under the `search` domains, trying it as
+/// written first if it has at least `ndots` dots, and alone if it ends in one
+pub(crate) fn search_candidates(name: &str, search: &[String], ndots: usize) -> Vec<String> {
+    if let Some(absolute) = name.strip_suffix('.') {
+        return vec![absolute.to_string()];
+    }
+    let searched = search.iter().map(|domain| format!("{}.{}", name, domain));
+    if name.matches('.').count() >= ndots {
+        std::iter::once(name.to_string()).chain(searched).collect()
+    } else {
+        searched.chain(std::iter::once(name.to_string())).collect()
+    }
+}
+
+fn normalize_domain(domain: &str) -> String {
+    domain.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Parse a nameserver entry, accepting a numeric scope id on link-local IPv6
+/// addresses. Interface-name scopes cannot be resolved here and are dropped.
+fn parse_nameserver(entry: &str) -> Option<SocketAddr> {
+    if let Ok(ip) = entry.parse::<IpAddr>() {
+        return Some(SocketAddr::new(ip, DNS_PORT));
+    }
+    let idx = entry.find('%')?;
+    let ip = entry[..idx].parse().ok()?;
+    let scope = entry[idx + 1..].parse().ok()?;
+    Some(SocketAddr::V6(SocketAddrV6::new(ip, DNS_PORT, 0, scope)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_parse_typical_file() {
+        let conf = ResolvConf::parse(
+            "# generated\nnameserver 10.0.0.2\nnameserver 2001:db8::53 ; v6\n\
+             search Corp.Example.com. example.com\noptions ndots:5 timeout:2 attempts:3 rotate use-vc\n",
+        );
+        assert_eq!(
+            conf.nameservers,
+            vec![
+                "10.0.0.2:53".parse::<SocketAddr>().unwrap(),
+                "[2001:db8::53]:53".parse().unwrap()
+            ]
+        );
+        assert_eq!(conf.search, vec!["corp.example.com", "example.com"]);
+        assert_eq!(conf.ndots, 5);
+        assert_eq!(conf.timeout, Duration::from_secs(2));
+        assert_eq!(conf.attempts, 3);
+        assert!(conf.rotate);
+        assert!(conf.use_vc);
+    }
+    #[test]
+    fn empty_file_uses_defaults() {
+        let conf = ResolvConf::parse("");
+        assert_eq!(conf.nameservers, vec!["127.0.0.1:53".parse().unwrap()]);
+        assert_eq!(conf.ndots, 1);
+        assert_eq!(conf.attempts, 2);
+        assert!(!conf.rotate);
+        assert!(!conf.use_vc);
+    }
+    #[test]
+    fn candidates_follow_ndots() {
+        let conf = ResolvConf::parse("search a.example b.example\noptions ndots:2");
+        assert_eq!(
+            conf.candidates("db.x"),
+            vec!["db.x.a.example", "db.x.b.example", "db.x"]
+        );
+        assert_eq!(
+            conf.candidates("db.x.y"),
+            vec!["db.x.y", "db.x.y.a.example", "db.x.y.b.example"]
+        );
+    }
+    #[test]
+    fn last_of_domain_and_search_wins() {
+        let conf = ResolvConf::parse("search a.example b.example\ndomain c.example\n");
+        assert_eq!(conf.search, vec!["c.example"]);
+    }
+    #[test]
+    fn limits_are_applied() {
+        let conf = ResolvConf::parse(
+            "nameserver 10.0.0.1\nnameserver 10.0.0.2\nnameserver 10.0.0.3\nnameserver 10.0.0.4\n\
+             nameserver bogus\noptions ndots:40 attempts:0 timeout:100",
+        );
+        assert_eq!(conf.nameservers.len(), MAX_NAMESERVERS);
+        assert_eq!(conf.ndots, MAX_NDOTS);
+        assert_eq!(conf.attempts, 1);
+        assert_eq!(conf.timeout, Duration::from_secs(MAX_TIMEOUT_SECS));
+    }
+}
@@ -0,0 +1,181 @@
+use crate::errors::IpaddrConversionError;
+use crate::get_ipaddr;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// A source of hostname to address resolution.
+///
+/// Everything in the crate which needs to turn a name into addresses goes
+/// through this trait, so adapters (eg the http client resolvers) work with any
+/// implementation, including ones which layer caching or policy on top of
+/// another resolver.
+///
+/// The crate's resolvers are `Clone + Send + Sync`, and cloning one is cheap:
+/// clones share any state built up, such as cache entries and statistics, so
+/// a resolver can be cloned into each thread or task rather than put behind
+/// a lock, and lookups on the clones run in parallel.
+pub trait Resolve {
+    /// Resolve `hostname_or_address` to one or more addresses
+    ///
+    /// # Parameters
+    ///
+    /// * `hostname_or_address` - the hostname or ip address literal to resolve
+    ///
+    /// # Returns
+    /// Either a Vec of IpAddr or an IpaddrConversionError
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError>;
+}
+
+/// Resolver backed by the operating system, via `get_ipaddr`, or by hickory
+/// with the `hickory` feature.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::{Resolve, SystemResolver};
+/// let result = SystemResolver.resolve("0.0.0.0");
+/// assert!(result.is_ok());
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SystemResolver;
+
+impl Resolve for SystemResolver {
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        get_ipaddr(hostname_or_address)
+    }
+}
+
+/// Resolver answering from a fixed table of names, without touching the network.
+///
+/// Names are matched case insensitively, ignoring any trailing dot. Ip address
+/// literals resolve to themselves. Unknown names result in
+/// `IpaddrConversionError::NotFound`.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::{Resolve, StaticResolver};
+/// let resolver = StaticResolver::new().with_entry("db.example.com", vec!["10.0.0.5".parse().unwrap()]);
+/// assert_eq!(resolver.resolve("DB.example.com.").unwrap(), vec!["10.0.0.5".parse::<std::net::IpAddr>().unwrap()]);
+/// assert!(resolver.resolve("web.example.com").is_err());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StaticResolver {
+    entries: Arc<HashMap<String, Vec<IpAddr>>>,
+}
+
+impl StaticResolver {
+    /// New, empty, StaticResolver
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an entry, returning the updated resolver
+    pub fn with_entry<I: Into<String>>(mut self, hostname: I, addrs: Vec<IpAddr>) -> Self {
+        self.insert(hostname, addrs);
+        self
+    }
+
+    /// Add or replace an entry
+    pub fn insert<I: Into<String>>(&mut self, hostname: I, addrs: Vec<IpAddr>) {
+        Arc::make_mut(&mut self.entries).insert(normalize_name(&hostname.into()), addrs);
+    }
+
+    /// Remove an entry, returning its addresses if it was present
+    pub fn remove(&mut self, hostname: &str) -> Option<Vec<IpAddr>> {
+        Arc::make_mut(&mut self.entries).remove(&normalize_name(hostname))
+    }
+}
+
+impl Resolve for StaticResolver {
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        if let Ok(ip) = hostname_or_address.parse::<IpAddr>() {
+            return Ok(vec![ip]);
+        }
+        self.entries
+            .get(&normalize_name(hostname_or_address))
+            .cloned()
+            .ok_or_else(|| IpaddrConversionError::NotFound(hostname_or_address.to_string()))
+    }
+}
+
+/// lowercase `name` and strip any trailing dot, for use as a lookup key
+pub(crate) fn normalize_name(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+impl<R: Resolve + ?Sized> Resolve for &R {
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        (**self).resolve(hostname_or_address)
+    }
+}
+
+impl<R: Resolve + ?Sized> Resolve for Box<R> {
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        (**self).resolve(hostname_or_address)
+    }
+}
+
+impl<R: Resolve + ?Sized> Resolve for Rc<R> {
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        (**self).resolve(hostname_or_address)
+    }
+}
+
+impl<R: Resolve + ?Sized> Resolve for Arc<R> {
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        (**self).resolve(hostname_or_address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn system_resolver_handles_literals() {
+        let ip = SystemResolver.resolve("127.0.0.1").unwrap();
+        assert_eq!(ip, vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))]);
+    }
+    #[test]
+    fn resolve_is_object_safe() {
+        let resolver: Box<dyn Resolve> = Box::new(SystemResolver);
+        let ip = resolver.resolve("0.0.0.0").unwrap();
+        assert_eq!(ip, vec![IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0))]);
+    }
+    #[test]
+    fn resolvers_are_shareable() {
+        fn shareable<T: Resolve + Clone + Send + Sync>() {}
+        shareable::<SystemResolver>();
+        shareable::<StaticResolver>();
+        shareable::<crate::direct::DirectResolver>();
+        shareable::<crate::cache::CachingResolver<crate::direct::DirectResolver>>();
+        shareable::<crate::stats::StatsResolver<StaticResolver>>();
+        shareable::<crate::sanitize::SanitizingResolver<StaticResolver>>();
+        shareable::<crate::kubernetes::KubernetesResolver<SystemResolver>>();
+        shareable::<crate::fault::FaultInjectingResolver<StaticResolver>>();
+        shareable::<crate::pin::PinningResolver<StaticResolver>>();
+        shareable::<crate::audit::AuditResolver<StaticResolver>>();
+        shareable::<crate::tenant::TenantResolver>();
+        shareable::<crate::policy::PolicyResolver<StaticResolver>>();
+        shareable::<crate::reload::ReloadingResolver>();
+        shareable::<crate::hook::HookedResolver<StaticResolver>>();
+        shareable::<crate::dns64::Dns64Resolver<StaticResolver>>();
+        shareable::<crate::seeded::SeededResolver>();
+        shareable::<crate::vip::VipAllocator>();
+        shareable::<crate::profile::Profile>();
+    }
+    #[test]
+    fn static_resolver_matches_normalized_names() {
+        let addr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let resolver = StaticResolver::new().with_entry("Host.Example.com.", vec![addr]);
+        assert_eq!(resolver.resolve("host.example.com").unwrap(), vec![addr]);
+        match resolver.resolve("other.example.com") {
+            Err(IpaddrConversionError::NotFound(name)) => assert_eq!(name, "other.example.com"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
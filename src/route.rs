@@ -0,0 +1,69 @@
+//! Finding the local address used to reach a destination.
+use crate::errors::IpaddrConversionError;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+
+/// Port the probe socket is connected to. Connecting a UDP socket sends
+/// nothing, so any port will do; this is the discard port.
+const PROBE_PORT: u16 = 9;
+
+/// The local address the OS would use as the source of packets to `dest`.
+///
+/// Connecting a UDP socket makes the OS pick a route and bind the socket to
+/// that route's source address, without sending anything. This is the
+/// address to advertise when registering a service which `dest`, or hosts on
+/// its network, should reach.
+///
+/// # Parameters
+///
+/// * `dest` - the destination to route to
+///
+/// # Returns
+/// The source address, or an `IoError` if there is no route to `dest`
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::route::source_ip_for;
+///
+/// let source = source_ip_for("127.0.0.1".parse().unwrap()).unwrap();
+/// assert!(source.is_loopback());
+/// ```
+pub fn source_ip_for(dest: IpAddr) -> Result<IpAddr, IpaddrConversionError> {
+    let bind: SocketAddr = match dest {
+        IpAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        IpAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind)?;
+    socket.connect((dest, PROBE_PORT))?;
+    let source = socket.local_addr()?.ip();
+    // some platforms accept the connect but leave the socket unbound when
+    // there is no usable route
+    if source.is_unspecified() {
+        return Err(IpaddrConversionError::IoError(io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            format!("no route to {}", dest),
+        )));
+    }
+    Ok(source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loopback_is_reached_from_loopback() {
+        let dest = "127.0.0.1".parse().unwrap();
+        assert_eq!(source_ip_for(dest).unwrap(), dest);
+    }
+    #[test]
+    fn source_matches_destination_family() {
+        // documentation addresses are only routable through a default route,
+        // which test environments may lack
+        if let Ok(source) = source_ip_for("192.0.2.1".parse().unwrap()) {
+            assert!(source.is_ipv4());
+            assert!(!source.is_unspecified());
+        }
+    }
+}
@@ -0,0 +1,465 @@
+//! Response policy zones: blocking policy distributed as DNS zone data.
+//!
+//! An [`Rpz`] reads a zone in master file format, in which each record names
+//! a trigger and its data the action:
+//!
+//! ```text
+//! $ORIGIN rpz.corp.example.
+//! bad.example        CNAME .               ; NXDOMAIN
+//! *.bad.example      CNAME .
+//! empty.example      CNAME *.              ; NODATA
+//! ok.bad.example     CNAME rpz-passthru.   ; PASSTHRU
+//! gone.example       CNAME rpz-drop.       ; DROP
+//! ads.example        A     192.0.2.80      ; rewritten to the walled garden
+//! 24.0.113.203.rpz-ip CNAME .              ; any answer in 203.0.113/24
+//! ```
+//!
+//! Owners are QNAME triggers, matched exactly or, written `*.domain`, for
+//! every name under the domain, the most specific trigger winning. Owners
+//! under `rpz-ip` are response IP triggers: a prefix length and the
+//! address's labels reversed, with `zz` standing for the `::` of an IPv6
+//! address, matched by longest prefix against every address of an answer.
+//! As in BIND, a QNAME trigger is applied before the name is resolved and
+//! takes precedence over response IP triggers. Client IP, NSDNAME and NSIP
+//! triggers, which need what a stub resolver does not see, are skipped.
+use crate::cidr::Cidr;
+use crate::errors::IpaddrConversionError;
+use crate::resolver::{normalize_name, Resolve};
+use crate::trie::PrefixTrie;
+use std::collections::HashMap;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+use std::sync::Arc;
+
+/// What a trigger does to a lookup
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RpzAction {
+    /// Answer that the name does not exist, `CNAME .`
+    NxDomain,
+    /// Answer that the name has no addresses, `CNAME *.`
+    NoData,
+    /// Resolve as if nothing matched, `CNAME rpz-passthru.`
+    Passthru,
+    /// Answer nothing, `CNAME rpz-drop.`; a stub reports it as Blocked
+    Drop,
+    /// Answer these addresses instead, from the owner's A and AAAA records
+    Rewrite(Vec<IpAddr>),
+    /// Answer the addresses of this name instead, from any other CNAME
+    Redirect(String),
+}
+
+/// A response policy zone.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::rpz::{Rpz, RpzAction};
+///
+/// let zone = Rpz::parse(
+///     "$ORIGIN rpz.corp.example.\n\
+///      *.ads.example A 192.0.2.80\n\
+///      ok.ads.example CNAME rpz-passthru.\n",
+/// )
+/// .unwrap();
+/// assert_eq!(
+///     zone.qname_action("tracker.ads.example"),
+///     Some(&RpzAction::Rewrite(vec!["192.0.2.80".parse().unwrap()]))
+/// );
+/// assert_eq!(zone.qname_action("ok.ads.example"), Some(&RpzAction::Passthru));
+/// assert_eq!(zone.qname_action("ads.example"), None);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Rpz {
+    names: HashMap<String, RpzAction>,
+    /// By the domain under which they match
+    wildcards: HashMap<String, RpzAction>,
+    ips: PrefixTrie<RpzAction>,
+    /// The response IP triggers, in the order given, for comparison
+    blocks: Vec<(Cidr, RpzAction)>,
+}
+
+impl PartialEq for Rpz {
+    fn eq(&self, other: &Self) -> bool {
+        self.names == other.names
+            && self.wildcards == other.wildcards
+            && self.blocks == other.blocks
+    }
+}
+
+impl Rpz {
+    /// Parse the text of a zone. Owners are taken relative to the last
+    /// `$ORIGIN`, absolute owners under it are stripped of it, and SOA, NS
+    /// and other records carrying no action are skipped.
+    ///
+    /// # Returns
+    /// The zone, or an InvalidInput error naming the first bad record
+    pub fn parse(text: &str) -> Result<Self, IpaddrConversionError> {
+        let mut origin = String::new();
+        let mut owner = String::new();
+        // actions by owner, A and AAAA records of an owner gathered into one
+        let mut actions: Vec<(String, RpzAction)> = Vec::new();
+        for (number, record) in records(text) {
+            let invalid = |msg: String| {
+                IpaddrConversionError::InvalidInput(format!("rpz line {}: {}", number + 1, msg))
+            };
+            let mut fields: Vec<&str> = record.split_whitespace().collect();
+            match fields.first() {
+                Some(&"$ORIGIN") => {
+                    let name = fields
+                        .get(1)
+                        .ok_or_else(|| invalid("$ORIGIN without a name".into()))?;
+                    origin = normalize_name(name);
+                    continue;
+                }
+                Some(directive) if directive.starts_with('$') => continue,
+                _ => {}
+            }
+            // a record continuing the previous owner starts with blank space
+            if !record.starts_with(char::is_whitespace) {
+                owner = relative_owner(fields.remove(0), &origin);
+            }
+            // an optional TTL and class come before the type
+            while let Some(field) = fields.first() {
+                if field.parse::<u32>().is_ok()
+                    || matches!(field.to_ascii_uppercase().as_str(), "IN" | "CH" | "HS")
+                {
+                    fields.remove(0);
+                } else {
+                    break;
+                }
+            }
+            let (rtype, data) = match fields.split_first() {
+                Some((rtype, data)) => (rtype.to_ascii_uppercase(), data),
+                None => return Err(invalid(format!("record for {} without a type", owner))),
+            };
+            let action = match (rtype.as_str(), data.first()) {
+                ("CNAME", Some(target)) => cname_action(target, &origin),
+                ("A", Some(addr)) => addr
+                    .parse::<Ipv4Addr>()
+                    .map(|v4| RpzAction::Rewrite(vec![IpAddr::V4(v4)]))
+                    .map_err(|_| invalid(format!("bad address '{}'", addr)))?,
+                ("AAAA", Some(addr)) => addr
+                    .parse::<Ipv6Addr>()
+                    .map(|v6| RpzAction::Rewrite(vec![IpAddr::V6(v6)]))
+                    .map_err(|_| invalid(format!("bad address '{}'", addr)))?,
+                ("CNAME", None) | ("A", None) | ("AAAA", None) => {
+                    return Err(invalid(format!(
+                        "{} record for {} without data",
+                        rtype, owner
+                    )))
+                }
+                _ => continue,
+            };
+            if owner.is_empty() || owner == "@" {
+                continue;
+            }
+            match (actions.last_mut(), action) {
+                (Some((last, RpzAction::Rewrite(addrs))), RpzAction::Rewrite(more))
+                    if *last == owner =>
+                {
+                    addrs.extend(more)
+                }
+                (_, action) => actions.push((owner.clone(), action)),
+            }
+        }
+        let mut zone = Self::default();
+        for (owner, action) in actions {
+            zone.insert(&owner, action).map_err(|msg| {
+                IpaddrConversionError::InvalidInput(format!("rpz trigger {}: {}", owner, msg))
+            })?;
+        }
+        Ok(zone)
+    }
+
+    /// Read the zone at `path`, as [`parse`](Self::parse) does
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, IpaddrConversionError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    fn insert(&mut self, owner: &str, action: RpzAction) -> Result<(), String> {
+        match owner.strip_suffix(".rpz-ip") {
+            Some(reversed) => {
+                let block = ip_trigger(reversed).ok_or("bad response IP trigger")?;
+                self.ips.insert(block, action.clone());
+                self.blocks.push((block, action));
+            }
+            None => match owner.strip_prefix("*.") {
+                Some(domain) => {
+                    self.wildcards.insert(domain.to_string(), action);
+                }
+                None => {
+                    self.names.insert(owner.to_string(), action);
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Number of triggers
+    pub fn len(&self) -> usize {
+        self.names.len() + self.wildcards.len() + self.blocks.len()
+    }
+
+    /// Whether the zone has no triggers
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The action of the QNAME trigger matching `hostname` most specifically
+    pub fn qname_action(&self, hostname: &str) -> Option<&RpzAction> {
+        let name = normalize_name(hostname);
+        if let Some(action) = self.names.get(&name) {
+            return Some(action);
+        }
+        let mut rest = name.as_str();
+        while let Some((_, parent)) = rest.split_once('.') {
+            if let Some(action) = self.wildcards.get(parent) {
+                return Some(action);
+            }
+            rest = parent;
+        }
+        None
+    }
+
+    /// The action of the response IP trigger covering `addr` most
+    /// specifically
+    pub fn ip_action(&self, addr: IpAddr) -> Option<&RpzAction> {
+        self.ips
+            .longest_match(addr.to_canonical())
+            .map(|(_, action)| action)
+    }
+}
+
+/// The records of a zone with their line numbers: comments stripped, and
+/// the lines of a parenthesized record joined
+fn records(text: &str) -> Vec<(usize, String)> {
+    let mut records = Vec::new();
+    let mut open: Option<(usize, String)> = None;
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or("");
+        match open.take() {
+            Some((start, mut record)) => {
+                record.push(' ');
+                record.push_str(line);
+                if line.contains(')') {
+                    records.push((start, record.replace(['(', ')'], " ")));
+                } else {
+                    open = Some((start, record));
+                }
+            }
+            None if line.contains('(') && !line.contains(')') => {
+                open = Some((number, line.to_string()))
+            }
+            None if !line.trim().is_empty() => {
+                records.push((number, line.replace(['(', ')'], " ")))
+            }
+            None => {}
+        }
+    }
+    records
+}
+
+/// `owner` relative to `origin`, normalized
+fn relative_owner(owner: &str, origin: &str) -> String {
+    if owner == "@" {
+        return owner.to_string();
+    }
+    if !owner.ends_with('.') {
+        return normalize_name(owner);
+    }
+    let owner = normalize_name(owner);
+    match owner.strip_suffix(origin).and_then(|o| o.strip_suffix('.')) {
+        Some(relative) if !origin.is_empty() => relative.to_string(),
+        _ => owner,
+    }
+}
+
+fn cname_action(target: &str, origin: &str) -> RpzAction {
+    match target.to_ascii_lowercase().as_str() {
+        "." => RpzAction::NxDomain,
+        "*." => RpzAction::NoData,
+        "rpz-passthru." | "rpz-tcp-only." => RpzAction::Passthru,
+        "rpz-drop." => RpzAction::Drop,
+        _ if target.ends_with('.') || origin.is_empty() => {
+            RpzAction::Redirect(normalize_name(target))
+        }
+        _ => RpzAction::Redirect(format!("{}.{}", normalize_name(target), origin)),
+    }
+}
+
+/// The block of a response IP trigger, such as `24.0.113.203` or
+/// `48.zz.1.db8.2001`
+fn ip_trigger(reversed: &str) -> Option<Cidr> {
+    let mut labels: Vec<&str> = reversed.split('.').collect();
+    let prefix_len: u8 = labels.remove(0).parse().ok()?;
+    labels.reverse();
+    let addr = if labels.len() == 4 && labels.iter().all(|l| l.parse::<u8>().is_ok()) {
+        IpAddr::V4(labels.join(".").parse().ok()?)
+    } else {
+        let groups: Vec<&str> = labels
+            .iter()
+            .map(|label| if *label == "zz" { "" } else { label })
+            .collect();
+        // an empty group at either end needs a second colon to make `::`
+        let mut text = groups.join(":");
+        if text.starts_with(':') {
+            text.insert(0, ':');
+        }
+        if text.ends_with(':') {
+            text.push(':');
+        }
+        IpAddr::V6(text.parse().ok()?)
+    };
+    Cidr::new(addr, prefix_len)
+}
+
+/// Resolver applying a response policy zone to the lookups of another.
+///
+/// Clones share the zone.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::rpz::{Rpz, RpzResolver};
+/// use ipaddr_from_str::{IpaddrConversionError, Resolve, StaticResolver};
+/// use std::net::IpAddr;
+///
+/// let zone = Rpz::parse(
+///     "bad.example CNAME .\n\
+///      ads.example A 192.0.2.80\n\
+///      32.9.113.0.203.rpz-ip CNAME rpz-drop.\n",
+/// )
+/// .unwrap();
+/// let backend = StaticResolver::new()
+///     .with_entry("bad.example", vec!["198.51.100.1".parse().unwrap()])
+///     .with_entry("app.example", vec!["203.0.113.9".parse().unwrap()]);
+/// let resolver = RpzResolver::new(backend, zone);
+/// assert!(matches!(
+///     resolver.resolve("bad.example"),
+///     Err(IpaddrConversionError::NotFound(_))
+/// ));
+/// assert_eq!(resolver.resolve("ads.example").unwrap(), vec!["192.0.2.80".parse::<IpAddr>().unwrap()]);
+/// assert!(matches!(
+///     resolver.resolve("app.example"),
+///     Err(IpaddrConversionError::Blocked(_))
+/// ));
+/// ```
+#[derive(Debug, Clone)]
+pub struct RpzResolver<R> {
+    resolver: R,
+    zone: Arc<Rpz>,
+}
+
+impl<R: Resolve> RpzResolver<R> {
+    /// Apply `zone` to the lookups of `resolver`
+    pub fn new(resolver: R, zone: Rpz) -> Self {
+        Self {
+            resolver,
+            zone: Arc::new(zone),
+        }
+    }
+
+    /// The zone applied
+    pub fn zone(&self) -> &Rpz {
+        &self.zone
+    }
+
+    fn apply(
+        &self,
+        hostname_or_address: &str,
+        action: &RpzAction,
+    ) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        match *action {
+            RpzAction::NxDomain => Err(IpaddrConversionError::NotFound(
+                hostname_or_address.to_string(),
+            )),
+            RpzAction::NoData => Ok(Vec::new()),
+            RpzAction::Passthru => self.resolver.resolve(hostname_or_address),
+            RpzAction::Drop => Err(IpaddrConversionError::Blocked(format!(
+                "{} dropped by response policy",
+                hostname_or_address
+            ))),
+            RpzAction::Rewrite(ref addrs) => Ok(addrs.clone()),
+            RpzAction::Redirect(ref target) => self.resolver.resolve(target),
+        }
+    }
+}
+
+impl<R: Resolve> Resolve for RpzResolver<R> {
+    /// Fails with NotFound for an NXDOMAIN action and with Blocked for a
+    /// DROP action
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        if hostname_or_address.parse::<IpAddr>().is_err() {
+            if let Some(action) = self.zone.qname_action(hostname_or_address) {
+                return self.apply(hostname_or_address, action);
+            }
+        }
+        let addrs = self.resolver.resolve(hostname_or_address)?;
+        match addrs.iter().find_map(|addr| self.zone.ip_action(*addr)) {
+            Some(RpzAction::Passthru) | None => Ok(addrs),
+            // a redirect by response IP answers the target in place of the name
+            Some(action) => self.apply(hostname_or_address, action),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::StaticResolver;
+
+    #[test]
+    fn zones_parse_origins_continuations_and_ip_triggers() {
+        let zone = Rpz::parse(
+            "$TTL 300\n\
+             $ORIGIN rpz.corp.example.\n\
+             @ IN SOA ns.corp.example. admin.corp.example. (\n\
+                 1 3600 600 86400 300 )\n\
+             @ NS ns.corp.example.\n\
+             garden.example. 300 IN A 192.0.2.80\n\
+             \x20          IN AAAA 2001:db8::80\n\
+             bad.example.rpz.corp.example. CNAME .\n\
+             moved.example CNAME portal\n\
+             48.zz.1.db8.2001.rpz-ip CNAME rpz-passthru.\n",
+        )
+        .unwrap();
+        assert_eq!(zone.len(), 4);
+        assert_eq!(
+            zone.qname_action("Garden.Example."),
+            Some(&RpzAction::Rewrite(vec![
+                "192.0.2.80".parse().unwrap(),
+                "2001:db8::80".parse().unwrap()
+            ]))
+        );
+        assert_eq!(zone.qname_action("bad.example"), Some(&RpzAction::NxDomain));
+        assert_eq!(
+            zone.qname_action("moved.example"),
+            Some(&RpzAction::Redirect("portal.rpz.corp.example".into()))
+        );
+        assert_eq!(
+            zone.ip_action("2001:db8:1:5::1".parse().unwrap()),
+            Some(&RpzAction::Passthru)
+        );
+        assert!(Rpz::parse("bad.example A not-an-address\n").is_err());
+    }
+    #[test]
+    fn passthru_names_skip_response_ip_triggers() {
+        let zone = Rpz::parse(
+            "ok.example CNAME rpz-passthru.\n\
+             empty.example CNAME *.\n\
+             8.0.0.0.10.rpz-ip CNAME .\n",
+        )
+        .unwrap();
+        let backend = StaticResolver::new()
+            .with_entry("ok.example", vec!["10.0.0.1".parse().unwrap()])
+            .with_entry("internal.example", vec!["10.0.0.2".parse().unwrap()]);
+        let resolver = RpzResolver::new(backend, zone);
+        assert!(resolver.resolve("ok.example").is_ok());
+        assert!(matches!(
+            resolver.resolve("internal.example"),
+            Err(IpaddrConversionError::NotFound(_))
+        ));
+        assert!(resolver.resolve("empty.example").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,173 @@
+//! Checking untrusted input before it is resolved.
+//!
+//! Strings from web forms and other user input may carry surrounding
+//! whitespace, control characters or sheer bulk. An [`InputPolicy`] decides
+//! what is acceptable up front, before the string reaches a regex or the
+//! network, and [`SanitizingResolver`] applies one in front of any resolver.
+use crate::errors::IpaddrConversionError;
+use crate::resolver::Resolve;
+use std::net::IpAddr;
+
+/// Longest textual domain name, per RFC 1035, including a trailing dot
+pub const MAX_NAME_LEN: usize = 254;
+
+/// What to do with whitespace surrounding the input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Whitespace {
+    /// Strip it
+    Trim,
+    /// Treat the input as invalid
+    Reject,
+}
+
+/// Rules input must satisfy before it is resolved.
+///
+/// Embedded NULs and other control characters are always rejected, as no
+/// hostname or address contains them.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::sanitize::InputPolicy;
+///
+/// assert_eq!(InputPolicy::default().sanitize(" db.example.com\n").unwrap(), "db.example.com");
+/// assert!(InputPolicy::strict().sanitize(" db.example.com").is_err());
+/// assert!(InputPolicy::default().sanitize("db\0.example.com").is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputPolicy {
+    whitespace: Whitespace,
+    max_len: usize,
+}
+
+impl Default for InputPolicy {
+    /// Trim surrounding whitespace and allow up to `MAX_NAME_LEN` bytes
+    fn default() -> Self {
+        Self {
+            whitespace: Whitespace::Trim,
+            max_len: MAX_NAME_LEN,
+        }
+    }
+}
+
+impl InputPolicy {
+    /// Policy rejecting surrounding whitespace rather than trimming it
+    pub fn strict() -> Self {
+        Self::default().with_whitespace(Whitespace::Reject)
+    }
+
+    /// Set the handling of surrounding whitespace, returning the updated policy
+    pub fn with_whitespace(mut self, whitespace: Whitespace) -> Self {
+        self.whitespace = whitespace;
+        self
+    }
+
+    /// Set the longest input accepted, in bytes, returning the updated policy.
+    /// The limit applies after trimming.
+    pub fn with_max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    /// Check `input` against the policy
+    ///
+    /// # Parameters
+    ///
+    /// * `input` - the untrusted hostname or address
+    ///
+    /// # Returns
+    /// The input, trimmed if the policy says so, or
+    /// `IpaddrConversionError::InvalidInput` describing the first problem found
+    pub fn sanitize<'a>(&self, input: &'a str) -> Result<&'a str, IpaddrConversionError> {
+        let trimmed = input.trim();
+        if trimmed.len() != input.len() && self.whitespace == Whitespace::Reject {
+            return Err(invalid("surrounding whitespace"));
+        }
+        if trimmed.is_empty() {
+            return Err(invalid("empty input"));
+        }
+        if trimmed.len() > self.max_len {
+            return Err(invalid(&format!(
+                "input of {} bytes exceeds {}",
+                trimmed.len(),
+                self.max_len
+            )));
+        }
+        if let Some(pos) = trimmed.find(|c: char| c.is_control()) {
+            return Err(invalid(&format!("control character at byte {}", pos)));
+        }
+        Ok(trimmed)
+    }
+}
+
+fn invalid(reason: &str) -> IpaddrConversionError {
+    IpaddrConversionError::InvalidInput(reason.to_string())
+}
+
+/// Resolver applying an [`InputPolicy`] before handing input to another.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::sanitize::{InputPolicy, SanitizingResolver};
+/// use ipaddr_from_str::{Resolve, SystemResolver};
+///
+/// let resolver = SanitizingResolver::new(SystemResolver, InputPolicy::default());
+/// assert!(resolver.resolve(" 127.0.0.1 ").is_ok());
+/// assert!(resolver.resolve("127.0.0.1\r\nHost: evil").is_err());
+/// ```
+#[derive(Debug, Clone)]
+pub struct SanitizingResolver<R> {
+    resolver: R,
+    policy: InputPolicy,
+}
+
+impl<R: Resolve> SanitizingResolver<R> {
+    /// New resolver checking input against `policy` before passing it on to
+    /// `resolver`
+    pub fn new(resolver: R, policy: InputPolicy) -> Self {
+        Self { resolver, policy }
+    }
+
+    /// The policy applied
+    pub fn policy(&self) -> &InputPolicy {
+        &self.policy
+    }
+}
+
+impl<R: Resolve> Resolve for SanitizingResolver<R> {
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        self.resolver
+            .resolve(self.policy.sanitize(hostname_or_address)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn whitespace_is_trimmed_or_rejected() {
+        assert_eq!(
+            InputPolicy::default().sanitize("\t10.0.0.1 ").unwrap(),
+            "10.0.0.1"
+        );
+        assert!(InputPolicy::strict().sanitize("10.0.0.1 ").is_err());
+        assert_eq!(
+            InputPolicy::strict().sanitize("10.0.0.1").unwrap(),
+            "10.0.0.1"
+        );
+        assert!(InputPolicy::default().sanitize("   ").is_err());
+    }
+    #[test]
+    fn control_characters_and_long_input_are_rejected() {
+        let policy = InputPolicy::default().with_max_len(10);
+        assert!(policy.sanitize("a\u{7f}b").is_err());
+        assert!(policy.sanitize("a.example\u{1b}").is_err());
+        assert!(policy.sanitize("long.example.com").is_err());
+        assert!(policy.sanitize("a.example").is_ok());
+        assert!(InputPolicy::default()
+            .sanitize(&"a".repeat(MAX_NAME_LEN + 1))
+            .is_err());
+    }
+}
@@ -0,0 +1,299 @@
+//! Screening lookups against feeds of known-bad names and addresses.
+//!
+//! A [`Screen`] holds the indicators of a feed in a [`BloomFilter`], which
+//! answers whether a name or address may be listed in a few hashes and a
+//! fixed amount of memory, however long the feed. [`ScreeningResolver`]
+//! consults it before every lookup, and against every address of the answer,
+//! failing with Blocked on a hit. A bloom filter is never wrong about an
+//! indicator it holds but may, at the rate it was sized for, claim one it
+//! does not, so a screen suits cheap pre-filtering in front of an exact
+//! check rather than the final word.
+use crate::errors::IpaddrConversionError;
+use crate::resolver::{normalize_name, Resolve};
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+use std::sync::Arc;
+
+/// The false positive rate a screen is sized for unless told otherwise
+pub const DEFAULT_FALSE_POSITIVE_RATE: f64 = 0.001;
+
+/// A set of strings which may claim to hold strings it does not.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::screen::BloomFilter;
+///
+/// let mut filter = BloomFilter::new(1000, 0.01).unwrap();
+/// filter.insert("evil.example");
+/// assert!(filter.contains("evil.example"));
+/// assert_eq!(filter.len(), 1);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+    len: usize,
+}
+
+impl BloomFilter {
+    /// Empty filter sized to hold `expected` items with a
+    /// `false_positive_rate` chance of claiming any other
+    ///
+    /// # Returns
+    /// The filter, or an InvalidInput error if the rate is not between 0
+    /// and 1
+    pub fn new(expected: usize, false_positive_rate: f64) -> Result<Self, IpaddrConversionError> {
+        if !(false_positive_rate > 0.0 && false_positive_rate < 1.0) {
+            return Err(IpaddrConversionError::InvalidInput(format!(
+                "false positive rate of {}",
+                false_positive_rate
+            )));
+        }
+        let expected = expected.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-expected * false_positive_rate.ln() / (ln2 * ln2))
+            .ceil()
+            .max(64.0);
+        let hashes = (bits / expected * ln2).round().clamp(1.0, 32.0) as u32;
+        Ok(Self {
+            bits: vec![0; (bits as usize).div_ceil(64)],
+            hashes,
+            len: 0,
+        })
+    }
+
+    /// The bits `item` sets, by double hashing
+    fn positions(&self, item: &str) -> impl Iterator<Item = usize> {
+        let first = fnv1a(item.as_bytes(), 0xcbf2_9ce4_8422_2325);
+        // odd, so every step reaches every bit
+        let second = fnv1a(item.as_bytes(), 0x8422_2325_cbf2_9ce4) | 1;
+        let size = (self.bits.len() * 64) as u64;
+        (0..self.hashes as u64)
+            .map(move |i| (first.wrapping_add(i.wrapping_mul(second)) % size) as usize)
+    }
+
+    /// Add `item`
+    pub fn insert(&mut self, item: &str) {
+        for position in self.positions(item).collect::<Vec<_>>() {
+            self.bits[position / 64] |= 1 << (position % 64);
+        }
+        self.len += 1;
+    }
+
+    /// Whether `item` may have been added: always true if it was
+    pub fn contains(&self, item: &str) -> bool {
+        self.positions(item)
+            .all(|position| self.bits[position / 64] & (1 << (position % 64)) != 0)
+    }
+
+    /// Number of items added
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether nothing has been added
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+fn fnv1a(data: &[u8], basis: u64) -> u64 {
+    data.iter().fold(basis, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// How an indicator is held: names normalized, addresses in their
+/// canonical form
+fn indicator_key(indicator: &str) -> String {
+    match indicator.parse::<IpAddr>() {
+        Ok(addr) => addr.to_canonical().to_string(),
+        Err(_) => normalize_name(indicator),
+    }
+}
+
+/// Known-bad names and addresses, as screened by [`ScreeningResolver`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Screen {
+    filter: BloomFilter,
+}
+
+impl Screen {
+    /// Screen of the indicators of a feed, one per line, sized for
+    /// `false_positive_rate`.
+    ///
+    /// Blank lines and `#` comments are skipped, and a line of a hosts file
+    /// sinking a name to `0.0.0.0` or `127.0.0.1` is taken as the name.
+    ///
+    /// # Returns
+    /// The screen, or an InvalidInput error if the rate is not between 0
+    /// and 1
+    pub fn parse(text: &str, false_positive_rate: f64) -> Result<Self, IpaddrConversionError> {
+        let indicators: Vec<&str> = text
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split('#').next().unwrap_or("").split_whitespace();
+                let first = fields.next()?;
+                match (first.parse::<Ipv4Addr>(), fields.next()) {
+                    (Ok(sink), Some(name)) if sink.is_unspecified() || sink.is_loopback() => {
+                        Some(name)
+                    }
+                    _ => Some(first),
+                }
+            })
+            .collect();
+        Self::from_indicators(&indicators, false_positive_rate)
+    }
+
+    /// Screen of `indicators`, names and addresses, sized for
+    /// `false_positive_rate`
+    pub fn from_indicators<S: AsRef<str>>(
+        indicators: &[S],
+        false_positive_rate: f64,
+    ) -> Result<Self, IpaddrConversionError> {
+        let mut filter = BloomFilter::new(indicators.len(), false_positive_rate)?;
+        for indicator in indicators {
+            filter.insert(&indicator_key(indicator.as_ref()));
+        }
+        Ok(Self { filter })
+    }
+
+    /// Read the feed at `path`, as [`parse`](Self::parse) does, sized for
+    /// the [`DEFAULT_FALSE_POSITIVE_RATE`]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, IpaddrConversionError> {
+        Self::parse(&fs::read_to_string(path)?, DEFAULT_FALSE_POSITIVE_RATE)
+    }
+
+    /// Number of indicators held
+    pub fn len(&self) -> usize {
+        self.filter.len()
+    }
+
+    /// Whether no indicators are held
+    pub fn is_empty(&self) -> bool {
+        self.filter.is_empty()
+    }
+
+    /// Whether `hostname`, or a domain it is under, may be listed
+    pub fn screens_name(&self, hostname: &str) -> bool {
+        let name = normalize_name(hostname);
+        let mut rest = name.as_str();
+        loop {
+            if self.filter.contains(rest) {
+                return true;
+            }
+            match rest.split_once('.') {
+                Some((_, parent)) if !parent.is_empty() => rest = parent,
+                _ => return false,
+            }
+        }
+    }
+
+    /// Whether `addr` may be listed
+    pub fn screens_ip(&self, addr: IpAddr) -> bool {
+        self.filter.contains(&addr.to_canonical().to_string())
+    }
+}
+
+/// Resolver refusing names and addresses a [`Screen`] lists.
+///
+/// Clones share the screen.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::screen::{Screen, ScreeningResolver};
+/// use ipaddr_from_str::{IpaddrConversionError, Resolve, StaticResolver};
+///
+/// let screen = Screen::parse("# feed\nevil.example\n0.0.0.0 tracker.example\n192.0.2.66\n", 0.001)
+///     .unwrap();
+/// let backend = StaticResolver::new()
+///     .with_entry("cdn.evil.example", vec!["192.0.2.1".parse().unwrap()])
+///     .with_entry("app.example", vec!["192.0.2.66".parse().unwrap()])
+///     .with_entry("ok.example", vec!["192.0.2.2".parse().unwrap()]);
+/// let resolver = ScreeningResolver::new(backend, screen);
+/// assert!(matches!(
+///     resolver.resolve("cdn.evil.example"),
+///     Err(IpaddrConversionError::Blocked(_))
+/// ));
+/// assert!(matches!(
+///     resolver.resolve("app.example"),
+///     Err(IpaddrConversionError::Blocked(_))
+/// ));
+/// assert!(resolver.resolve("ok.example").is_ok());
+/// ```
+#[derive(Debug, Clone)]
+pub struct ScreeningResolver<R> {
+    resolver: R,
+    screen: Arc<Screen>,
+}
+
+impl<R: Resolve> ScreeningResolver<R> {
+    /// Screen the lookups of `resolver` against `screen`
+    pub fn new(resolver: R, screen: Screen) -> Self {
+        Self {
+            resolver,
+            screen: Arc::new(screen),
+        }
+    }
+
+    /// The screen consulted
+    pub fn screen(&self) -> &Screen {
+        &self.screen
+    }
+}
+
+impl<R: Resolve> Resolve for ScreeningResolver<R> {
+    /// Fails with Blocked, before resolving, if the name or address looked
+    /// up is screened, and after if any address of the answer is
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        let screened = match hostname_or_address.parse::<IpAddr>() {
+            Ok(addr) => self.screen.screens_ip(addr),
+            Err(_) => self.screen.screens_name(hostname_or_address),
+        };
+        if screened {
+            return Err(IpaddrConversionError::Blocked(format!(
+                "{} is on the screening feed",
+                hostname_or_address
+            )));
+        }
+        let addrs = self.resolver.resolve(hostname_or_address)?;
+        if let Some(addr) = addrs.iter().find(|addr| self.screen.screens_ip(**addr)) {
+            return Err(IpaddrConversionError::Blocked(format!(
+                "{} resolves to {}, which is on the screening feed",
+                hostname_or_address, addr
+            )));
+        }
+        Ok(addrs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_hold_what_was_added_and_little_else() {
+        let mut filter = BloomFilter::new(1000, 0.01).unwrap();
+        for i in 0..1000 {
+            filter.insert(&format!("bad-{}.example", i));
+        }
+        assert!((0..1000).all(|i| filter.contains(&format!("bad-{}.example", i))));
+        let false_positives = (0..10_000)
+            .filter(|i| filter.contains(&format!("good-{}.example", i)))
+            .count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+        assert!(BloomFilter::new(10, 1.0).is_err());
+    }
+    #[test]
+    fn screens_match_parent_domains_and_canonical_addresses() {
+        let screen = Screen::parse("Evil.Example.\n10.0.0.1 # comment\n\n", 0.001).unwrap();
+        assert_eq!(screen.len(), 2);
+        assert!(screen.screens_name("a.b.evil.example"));
+        assert!(!screen.screens_name("notevil.example"));
+        assert!(screen.screens_ip("::ffff:10.0.0.1".parse().unwrap()));
+        assert!(!screen.screens_ip("10.0.0.2".parse().unwrap()));
+    }
+}
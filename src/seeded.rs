@@ -0,0 +1,222 @@
+//! A resolver whose answers are a pure function of a seed and the name.
+//!
+//! Simulations of large networks, and snapshot tests, need every name to
+//! resolve, to the same addresses on every run and machine, without a zone
+//! to maintain. [`SeededResolver`] hashes the seed and the name into
+//! addresses inside configured blocks, by default the `198.18.0.0/15` range
+//! set aside for benchmarking, so the fake addresses can never be mistaken
+//! for real hosts.
+use crate::address::Address;
+use crate::cidr::Cidr;
+use crate::digest::sha256;
+use crate::errors::IpaddrConversionError;
+use crate::resolver::{normalize_name, Resolve};
+use std::net::{IpAddr, Ipv4Addr};
+
+/// The block addresses are drawn from unless configured otherwise
+pub const DEFAULT_V4_BLOCK: (Ipv4Addr, u8) = (Ipv4Addr::new(198, 18, 0, 0), 15);
+
+/// 128 bits of the SHA-256 of the parts, each length prefixed so that no
+/// two lists of parts hash alike
+pub(crate) fn hash_parts(parts: &[&[u8]]) -> u128 {
+    let mut input = Vec::new();
+    for part in parts {
+        input.extend_from_slice(&(part.len() as u32).to_be_bytes());
+        input.extend_from_slice(part);
+    }
+    let digest = sha256(&input);
+    let mut high = [0u8; 16];
+    high.copy_from_slice(&digest[..16]);
+    u128::from_be_bytes(high)
+}
+
+/// The address `hash` picks in `block`. Blocks of more than two addresses
+/// never give their first or last, which are the network and broadcast
+/// addresses of an IPv4 subnet.
+pub(crate) fn host_in(block: &Cidr, hash: u128) -> IpAddr {
+    let network = Address::new(block.network());
+    let host_bits = u32::from(network.bits() - block.prefix_len());
+    // the number of hosts less one, as 2^128 does not fit
+    let last = if host_bits == 128 {
+        u128::MAX
+    } else {
+        (1u128 << host_bits) - 1
+    };
+    let offset = match last {
+        0 | 1 => hash & last,
+        _ => hash % (last - 1) + 1,
+    };
+    match network.ip() {
+        IpAddr::V4(_) => Address::from_u32((network.to_u128() + offset) as u32).ip(),
+        IpAddr::V6(_) => Address::from_u128(network.to_u128() + offset).ip(),
+    }
+}
+
+/// Resolver answering every name with addresses derived from a seed and the
+/// name.
+///
+/// The same seed, blocks and name always give the same addresses; different
+/// names usually differ, but nothing prevents two names sharing an address,
+/// as nothing is remembered between lookups. Address literals resolve to
+/// themselves.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::seeded::SeededResolver;
+/// use ipaddr_from_str::Resolve;
+///
+/// let resolver = SeededResolver::new(42);
+/// let addrs = resolver.resolve("db-17.sim").unwrap();
+/// assert_eq!(addrs, SeededResolver::new(42).resolve("DB-17.sim.").unwrap());
+/// assert_ne!(addrs, SeededResolver::new(43).resolve("db-17.sim").unwrap());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeededResolver {
+    seed: u64,
+    v4: Option<Cidr>,
+    v6: Option<Cidr>,
+    per_family: usize,
+    nxdomain_rate: f64,
+}
+
+impl SeededResolver {
+    /// Resolver seeded with `seed`, answering one address from
+    /// [`DEFAULT_V4_BLOCK`] for each name
+    pub fn new(seed: u64) -> Self {
+        let (network, prefix_len) = DEFAULT_V4_BLOCK;
+        Self {
+            seed,
+            v4: Cidr::new(network.into(), prefix_len),
+            v6: None,
+            per_family: 1,
+            nxdomain_rate: 0.0,
+        }
+    }
+
+    /// Draw IPv4 addresses from `block`, or answer none if None, returning
+    /// the updated resolver
+    pub fn with_v4_block(mut self, block: Option<Cidr>) -> Self {
+        self.v4 = block.filter(|block| block.addr().is_ipv4());
+        self
+    }
+
+    /// Draw IPv6 addresses from `block`, such as a ULA prefix, or answer none
+    /// if None, returning the updated resolver
+    pub fn with_v6_block(mut self, block: Option<Cidr>) -> Self {
+        self.v6 = block.filter(|block| block.addr().is_ipv6());
+        self
+    }
+
+    /// Answer `count` addresses of each family, returning the updated
+    /// resolver
+    pub fn with_addrs_per_family(mut self, count: usize) -> Self {
+        self.per_family = count.max(1);
+        self
+    }
+
+    /// Make a `rate` of names, chosen by the seed, fail with NotFound,
+    /// returning the updated resolver
+    pub fn with_nxdomain_rate(mut self, rate: f64) -> Self {
+        self.nxdomain_rate = rate;
+        self
+    }
+
+    /// The addresses `hostname` resolves to, IPv6 first, or an empty list if
+    /// it is one of the names chosen not to exist
+    pub fn addrs_for(&self, hostname: &str) -> Vec<IpAddr> {
+        let name = normalize_name(hostname);
+        let seed = self.seed.to_be_bytes();
+        let chosen = hash_parts(&[&seed, b"nxdomain", name.as_bytes()]);
+        if ((chosen >> 75) as f64 / (1u64 << 53) as f64) < self.nxdomain_rate {
+            return Vec::new();
+        }
+        let mut addrs = Vec::new();
+        for (family, block) in [(&b"v6"[..], self.v6), (&b"v4"[..], self.v4)] {
+            let block = match block {
+                Some(block) => block,
+                None => continue,
+            };
+            let mut index: u64 = 0;
+            while addrs
+                .iter()
+                .filter(|a: &&IpAddr| block.contains(**a))
+                .count()
+                < self.per_family
+            {
+                let hash = hash_parts(&[&seed, family, name.as_bytes(), &index.to_be_bytes()]);
+                let addr = host_in(&block, hash);
+                // a small block may run out of distinct addresses
+                if !addrs.contains(&addr) {
+                    addrs.push(addr);
+                } else if index > 4 * self.per_family as u64 {
+                    break;
+                }
+                index += 1;
+            }
+        }
+        addrs
+    }
+}
+
+impl Resolve for SeededResolver {
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        if let Ok(ip) = hostname_or_address.parse::<IpAddr>() {
+            return Ok(vec![ip]);
+        }
+        let addrs = self.addrs_for(hostname_or_address);
+        if addrs.is_empty() {
+            return Err(IpaddrConversionError::NotFound(
+                hostname_or_address.to_string(),
+            ));
+        }
+        Ok(addrs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answers_stay_inside_the_blocks() {
+        let v4: Cidr = "10.20.0.0/24".parse().unwrap();
+        let v6: Cidr = "fd00:5:6::/48".parse().unwrap();
+        let resolver = SeededResolver::new(1)
+            .with_v4_block(Some(v4))
+            .with_v6_block(Some(v6))
+            .with_addrs_per_family(3);
+        for n in 0..50 {
+            let addrs = resolver.resolve(&format!("node-{}.sim", n)).unwrap();
+            assert_eq!(addrs.len(), 6);
+            assert!(addrs[..3].iter().all(|a| v6.contains(*a)));
+            assert!(addrs[3..].iter().all(|a| v4.contains(*a)));
+            // never the network or broadcast address
+            assert!(addrs[3..]
+                .iter()
+                .all(|a| !a.to_string().ends_with(".0") && !a.to_string().ends_with(".255")));
+        }
+    }
+    #[test]
+    fn answers_are_fixed_by_the_seed() {
+        // pinned so that a change to the derivation, which would break
+        // snapshots taken with it, is noticed
+        let resolver = SeededResolver::new(7);
+        let first = resolver.resolve("api.sim").unwrap();
+        assert_eq!(first, vec!["198.19.187.138".parse::<IpAddr>().unwrap()]);
+        assert_eq!(first, resolver.resolve("API.sim.").unwrap());
+        let sparse = SeededResolver::new(7).with_nxdomain_rate(0.5);
+        let missing = (0..200)
+            .filter(|n| sparse.resolve(&format!("host-{}.sim", n)).is_err())
+            .count();
+        assert!((60..140).contains(&missing));
+        let single: Cidr = "192.0.2.7/32".parse().unwrap();
+        let pinned = SeededResolver::new(7)
+            .with_v4_block(Some(single))
+            .with_addrs_per_family(2);
+        assert_eq!(
+            pinned.addrs_for("x"),
+            vec!["192.0.2.7".parse::<IpAddr>().unwrap()]
+        );
+    }
+}
@@ -0,0 +1,173 @@
+//! Choosing the nameserver a query goes to first.
+//!
+//! A [`DirectResolver`](crate::direct::DirectResolver) walks its nameserver
+//! list until one answers; [`ServerSelection`] sets where the walk starts.
+//! In order, the first server takes every query and the others are backups,
+//! as glibc does. Rotating spreads queries evenly, as the `rotate` option
+//! asks. Fastest tries servers by their smoothed round trip time, as BIND
+//! does, so a slow or distant server is only asked when the quicker ones
+//! fail; a server not yet measured is tried first, to measure it, and one
+//! refusing or failing queries is measured as though it timed out. Hashing
+//! the name sends each name to the same server, so an upstream with a cache
+//! per server answers it from cache more often.
+use crate::parse::ParseError;
+use crate::resolver::normalize_name;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The order in which nameservers are tried
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ServerSelection {
+    /// The order listed
+    InOrder,
+    /// Starting at the next server in turn
+    Rotate,
+    /// Lowest smoothed round trip time first
+    Fastest,
+    /// Starting at a server chosen by the name queried
+    HashByName,
+}
+
+impl fmt::Display for ServerSelection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ServerSelection::InOrder => write!(f, "in-order"),
+            ServerSelection::Rotate => write!(f, "rotate"),
+            ServerSelection::Fastest => write!(f, "fastest"),
+            ServerSelection::HashByName => write!(f, "hash"),
+        }
+    }
+}
+
+impl FromStr for ServerSelection {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "in-order" => Ok(ServerSelection::InOrder),
+            "rotate" => Ok(ServerSelection::Rotate),
+            "fastest" => Ok(ServerSelection::Fastest),
+            "hash" => Ok(ServerSelection::HashByName),
+            _ => Err(ParseError::new(0, "in-order, rotate, fastest or hash")),
+        }
+    }
+}
+
+/// What selection needs to remember: the turn for rotation and the round
+/// trip times for the fastest. Shared by the clones of a resolver.
+#[derive(Debug, Default)]
+pub(crate) struct Selector {
+    next: AtomicUsize,
+    rtt: Mutex<BTreeMap<SocketAddr, Duration>>,
+}
+
+impl Selector {
+    /// The order in which to try `servers` for `name`
+    pub(crate) fn order(
+        &self,
+        selection: ServerSelection,
+        servers: &[SocketAddr],
+        name: &str,
+    ) -> Vec<SocketAddr> {
+        let mut order = servers.to_vec();
+        if order.is_empty() {
+            return order;
+        }
+        match selection {
+            ServerSelection::InOrder => {}
+            ServerSelection::Rotate => {
+                let start = self.next.fetch_add(1, Ordering::Relaxed) % order.len();
+                order.rotate_left(start);
+            }
+            ServerSelection::Fastest => {
+                let rtt = self
+                    .rtt
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+                // stable, so servers alike stay in the order listed
+                order.sort_by_key(|server| rtt.get(server).copied().unwrap_or_default());
+            }
+            ServerSelection::HashByName => {
+                // FNV-1a, so a name goes to the same server in every process
+                let hash = normalize_name(name)
+                    .bytes()
+                    .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+                        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+                    });
+                let start = (hash % order.len() as u64) as usize;
+                order.rotate_left(start);
+            }
+        }
+        order
+    }
+
+    /// Fold a round trip of `rtt` to `server`, or a timeout or failure
+    /// after it, into the server's smoothed round trip time, weighting it an
+    /// eighth as TCP does
+    pub(crate) fn record(&self, server: SocketAddr, rtt: Duration) {
+        let mut rtts = self
+            .rtt
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let smoothed = match rtts.get(&server) {
+            Some(&smoothed) => (smoothed * 7 + rtt) / 8,
+            None => rtt,
+        };
+        rtts.insert(server, smoothed);
+    }
+
+    /// The smoothed round trip time to `server`, None if it has not been
+    /// measured
+    pub(crate) fn rtt(&self, server: SocketAddr) -> Option<Duration> {
+        let rtts = self
+            .rtt
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        rtts.get(&server).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn servers() -> Vec<SocketAddr> {
+        vec![
+            "192.0.2.1:53".parse().unwrap(),
+            "192.0.2.2:53".parse().unwrap(),
+            "192.0.2.3:53".parse().unwrap(),
+        ]
+    }
+
+    #[test]
+    fn fastest_tries_unmeasured_then_quickest_servers_first() {
+        let selector = Selector::default();
+        let servers = servers();
+        selector.record(servers[0], Duration::from_millis(80));
+        selector.record(servers[1], Duration::from_millis(20));
+        let order = selector.order(ServerSelection::Fastest, &servers, "a.example");
+        assert_eq!(order, vec![servers[2], servers[1], servers[0]]);
+        selector.record(servers[1], Duration::from_millis(100));
+        assert_eq!(selector.rtt(servers[1]), Some(Duration::from_millis(30)));
+    }
+    #[test]
+    fn names_hash_to_the_same_server_whatever_their_case() {
+        let selector = Selector::default();
+        let servers = servers();
+        let first = |name| selector.order(ServerSelection::HashByName, &servers, name)[0];
+        assert_eq!(first("www.example.com"), first("WWW.Example.com."));
+        let rotated: Vec<_> = (0..3)
+            .map(|_| selector.order(ServerSelection::Rotate, &servers, "a.example")[0])
+            .collect();
+        assert_eq!(rotated, servers);
+        assert_eq!(
+            "hash".parse::<ServerSelection>().unwrap(),
+            ServerSelection::HashByName
+        );
+    }
+}
@@ -0,0 +1,218 @@
+//! Resolution as a tower service.
+//!
+//! Enabled by the `tower` feature. [`ResolveService`] answers a name with its
+//! addresses, so a resolver slots into a tower middleware stack like any
+//! other service, and [`ResolveLayer`] builds one around a [`Resolve`]
+//! implementation with caching, retries and a timeout:
+//!
+//! ```no_run
+//! use ipaddr_from_str::service::ResolveLayer;
+//! use ipaddr_from_str::SystemResolver;
+//! use std::time::Duration;
+//! use tower::{Layer, Service, ServiceExt};
+//!
+//! # async fn run() -> Result<(), ipaddr_from_str::IpaddrConversionError> {
+//! let mut service = ResolveLayer::new()
+//!     .with_cache(Duration::from_secs(60))
+//!     .with_retries(2)
+//!     .with_timeout(Duration::from_secs(2))
+//!     .layer(SystemResolver);
+//! let addrs = service.ready().await?.call("example.com".to_string()).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The timeout is set as a [deadline](crate::deadline), so it covers every
+//! retry and shortens the timeouts of a
+//! [`DirectResolver`](crate::direct::DirectResolver) underneath.
+use crate::cache::CachingResolver;
+use crate::deadline;
+use crate::errors::IpaddrConversionError;
+use crate::resolver::Resolve;
+use std::future::Future;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tower::{Layer, Service};
+
+/// A tower service resolving names through a [`Resolve`] implementation.
+///
+/// Lookups run on tokio's blocking thread pool. Clones share the resolver.
+#[derive(Clone)]
+pub struct ResolveService {
+    resolver: Arc<dyn Resolve + Send + Sync>,
+    retries: u32,
+    timeout: Option<Duration>,
+}
+
+impl ResolveService {
+    /// Serve lookups through `resolver`, without retries or a timeout
+    pub fn new<R: Resolve + Send + Sync + 'static>(resolver: R) -> Self {
+        Self::from_arc(Arc::new(resolver))
+    }
+
+    /// Serve lookups through an already shared `resolver`
+    pub fn from_arc(resolver: Arc<dyn Resolve + Send + Sync>) -> Self {
+        Self {
+            resolver,
+            retries: 0,
+            timeout: None,
+        }
+    }
+}
+
+impl std::fmt::Debug for ResolveService {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ResolveService")
+            .field("retries", &self.retries)
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Service<String> for ResolveService {
+    type Response = Vec<IpAddr>;
+    type Error = IpaddrConversionError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: String) -> Self::Future {
+        let resolver = self.resolver.clone();
+        let retries = self.retries;
+        let lookup = async move {
+            let mut attempt = 0;
+            loop {
+                match deadline::resolve(resolver.clone(), &name).await {
+                    Err(err) if err.is_transient() && attempt < retries && !deadline::expired() => {
+                        attempt += 1
+                    }
+                    result => return result,
+                }
+            }
+        };
+        match self.timeout {
+            Some(timeout) => Box::pin(deadline::scope_async(deadline::after(timeout), lookup)),
+            None => Box::pin(lookup),
+        }
+    }
+}
+
+/// Builds a [`ResolveService`] around a resolver, adding the caching,
+/// retries and timeout configured.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::service::ResolveLayer;
+/// use ipaddr_from_str::StaticResolver;
+/// use tower::Layer;
+///
+/// let service = ResolveLayer::new()
+///     .with_retries(1)
+///     .layer(StaticResolver::new());
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResolveLayer {
+    cache_ttl: Option<Duration>,
+    retries: u32,
+    timeout: Option<Duration>,
+}
+
+impl ResolveLayer {
+    /// A layer adding nothing: no cache, retries or timeout
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cache answers for `ttl`, returning the updated layer
+    pub fn with_cache(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = Some(ttl);
+        self
+    }
+
+    /// Retry lookups failing with a transient error up to `retries` times,
+    /// returning the updated layer
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Give up on a lookup, retries included, after `timeout`, returning the
+    /// updated layer
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+}
+
+impl<R: Resolve + Send + Sync + 'static> Layer<R> for ResolveLayer {
+    type Service = ResolveService;
+
+    fn layer(&self, resolver: R) -> ResolveService {
+        let mut service = match self.cache_ttl {
+            Some(ttl) => ResolveService::new(CachingResolver::new(resolver, ttl)),
+            None => ResolveService::new(resolver),
+        };
+        service.retries = self.retries;
+        service.timeout = self.timeout;
+        service
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::StaticResolver;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Times out until it has been asked `failures` times
+    struct Flaky {
+        failures: usize,
+        calls: AtomicUsize,
+    }
+
+    impl Resolve for Flaky {
+        fn resolve(&self, name: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+                return Err(IpaddrConversionError::Timeout(name.to_string()));
+            }
+            Ok(vec!["10.0.0.1".parse().unwrap()])
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn transient_failures_are_retried() {
+        let flaky = || Flaky {
+            failures: 2,
+            calls: AtomicUsize::new(0),
+        };
+        let mut service = ResolveLayer::new().with_retries(2).layer(flaky());
+        assert!(block_on(service.call("db".to_string())).is_ok());
+        let mut service = ResolveLayer::new().with_retries(1).layer(flaky());
+        assert!(block_on(service.call("db".to_string())).is_err());
+    }
+    #[test]
+    fn missing_names_are_not_retried() {
+        let mut service = ResolveLayer::new()
+            .with_retries(3)
+            .with_timeout(Duration::from_secs(5))
+            .layer(StaticResolver::new());
+        assert!(matches!(
+            block_on(service.call("missing.example".to_string())),
+            Err(IpaddrConversionError::NotFound(_))
+        ));
+    }
+}
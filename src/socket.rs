@@ -0,0 +1,455 @@
+//! Resolving and setting up sockets with their options in one call.
+//!
+//! [`SocketOptions`] collects the options real deployments set before a
+//! socket connects or binds: address and port reuse, `TCP_NODELAY`, the
+//! TTL, keepalive, the interface to bind to, the local address to send
+//! from, and whether a temporary or a stable IPv6 address is preferred as
+//! the source where the host has both. They are applied through socket2
+//! before the connect or bind, which some of them require, and the socket is
+//! handed back as its std type.
+use crate::deadline;
+use crate::errors::IpaddrConversionError;
+use crate::parse::ParseError;
+use crate::resolver::Resolve;
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Length of the queue of pending connections of listeners bound here
+pub const DEFAULT_BACKLOG: i32 = 128;
+
+/// Which kind of IPv6 source address to prefer, where the host has both
+/// (RFC 5014)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Ipv6SourcePreference {
+    /// Temporary addresses (RFC 8981), which change daily, so connections
+    /// cannot be linked by their source
+    Temporary,
+    /// Stable addresses, so servers and their allow lists see the same
+    /// source each time
+    Stable,
+}
+
+impl fmt::Display for Ipv6SourcePreference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Ipv6SourcePreference::Temporary => write!(f, "temporary"),
+            Ipv6SourcePreference::Stable => write!(f, "stable"),
+        }
+    }
+}
+
+impl FromStr for Ipv6SourcePreference {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "temporary" => Ok(Ipv6SourcePreference::Temporary),
+            "stable" => Ok(Ipv6SourcePreference::Stable),
+            _ => Err(ParseError::new(0, "temporary or stable")),
+        }
+    }
+}
+
+/// Options to set on a socket before it connects or binds
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::socket::SocketOptions;
+/// use ipaddr_from_str::SystemResolver;
+/// use std::time::Duration;
+///
+/// let stream = SocketOptions::new()
+///     .nodelay(true)
+///     .keepalive(Duration::from_secs(60))
+///     .connect_timeout(Duration::from_secs(3))
+///     .connect_host(&SystemResolver, "example.com", 443)
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SocketOptions {
+    reuse_address: bool,
+    reuse_port: bool,
+    nodelay: bool,
+    ttl: Option<u32>,
+    keepalive: Option<Duration>,
+    device: Option<String>,
+    local_addr: Option<IpAddr>,
+    ipv6_source: Option<Ipv6SourcePreference>,
+    connect_timeout: Option<Duration>,
+}
+
+impl SocketOptions {
+    /// Options leaving every setting at the system default
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set `SO_REUSEADDR`, returning the updated options
+    pub fn reuse_address(mut self, reuse: bool) -> Self {
+        self.reuse_address = reuse;
+        self
+    }
+
+    /// Set `SO_REUSEPORT`, returning the updated options. Only applied on
+    /// unix; elsewhere binding with it set fails.
+    pub fn reuse_port(mut self, reuse: bool) -> Self {
+        self.reuse_port = reuse;
+        self
+    }
+
+    /// Set `TCP_NODELAY` on TCP sockets, returning the updated options
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = nodelay;
+        self
+    }
+
+    /// Set the TTL, or the unicast hop limit for IPv6, returning the updated
+    /// options
+    pub fn ttl(mut self, ttl: u32) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Enable TCP keepalive, probing after `idle`, returning the updated
+    /// options
+    pub fn keepalive(mut self, idle: Duration) -> Self {
+        self.keepalive = Some(idle);
+        self
+    }
+
+    /// Bind to the interface named `device` with `SO_BINDTODEVICE`, so
+    /// traffic leaves through it whatever the routing table says, returning
+    /// the updated options. Only supported on Linux, where it needs
+    /// `CAP_NET_RAW`.
+    pub fn bind_device(mut self, device: &str) -> Self {
+        self.device = Some(device.to_string());
+        self
+    }
+
+    /// Connect from `addr`, returning the updated options. Destinations of
+    /// the other family are connected to without it.
+    pub fn local_addr(mut self, addr: IpAddr) -> Self {
+        self.local_addr = Some(addr);
+        self
+    }
+
+    /// Prefer an IPv6 source address of the kind `preference`, returning the
+    /// updated options. By default the system's choice, which on Linux the
+    /// `use_tempaddr` sysctl sets, is left alone. Has no effect on sockets
+    /// bound to a [local address](Self::local_addr), and is only supported
+    /// on Linux.
+    pub fn prefer_ipv6_source(mut self, preference: Ipv6SourcePreference) -> Self {
+        self.ipv6_source = Some(preference);
+        self
+    }
+
+    /// Give up on each connection attempt after `timeout`, returning the
+    /// updated options. Attempts are also bounded by the caller's
+    /// [deadline](crate::deadline).
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// A TCP socket, or a UDP one if not `tcp`, for talking to or listening
+    /// on `addr`, with the options set
+    pub(crate) fn socket(&self, addr: &SocketAddr, tcp: bool) -> io::Result<Socket> {
+        let domain = match addr {
+            SocketAddr::V4(_) => Domain::ipv4(),
+            SocketAddr::V6(_) => Domain::ipv6(),
+        };
+        let socket = if tcp {
+            Socket::new(domain, Type::stream(), Some(Protocol::tcp()))?
+        } else {
+            Socket::new(domain, Type::dgram(), Some(Protocol::udp()))?
+        };
+        if self.reuse_address {
+            socket.set_reuse_address(true)?;
+        }
+        if self.reuse_port {
+            set_reuse_port(&socket)?;
+        }
+        if tcp {
+            if self.nodelay {
+                socket.set_nodelay(true)?;
+            }
+            if let Some(idle) = self.keepalive {
+                socket.set_keepalive(Some(idle))?;
+            }
+        }
+        if let Some(ttl) = self.ttl {
+            match addr {
+                SocketAddr::V4(_) => socket.set_ttl(ttl)?,
+                SocketAddr::V6(_) => socket.set_unicast_hops_v6(ttl)?,
+            }
+        }
+        if let Some(ref device) = self.device {
+            bind_to_device(&socket, device)?;
+        }
+        if let (SocketAddr::V6(_), Some(preference)) = (addr, self.ipv6_source) {
+            set_source_preference(&socket, preference)?;
+        }
+        Ok(socket)
+    }
+
+    /// The socket to connect to `addr` from, bound to the local address if
+    /// one is set and of the same family
+    pub(crate) fn connecting_socket(&self, addr: &SocketAddr) -> io::Result<Socket> {
+        let socket = self.socket(addr, true)?;
+        if let Some(local) = self.local_addr_for(addr) {
+            socket.bind(&SocketAddr::new(local, 0).into())?;
+        }
+        Ok(socket)
+    }
+
+    /// The local address to talk to `addr` from, if one is set and of the
+    /// same family
+    pub(crate) fn local_addr_for(&self, addr: &SocketAddr) -> Option<IpAddr> {
+        self.local_addr
+            .filter(|local| local.is_ipv4() == addr.is_ipv4())
+    }
+
+    /// Connect to `addr` over TCP
+    pub fn connect(&self, addr: SocketAddr) -> Result<TcpStream, IpaddrConversionError> {
+        if deadline::expired() {
+            return Err(IpaddrConversionError::Timeout(addr.to_string()));
+        }
+        let socket = self.connecting_socket(&addr)?;
+        let target = SockAddr::from(addr);
+        let result = match self.connect_timeout.or_else(deadline::remaining) {
+            Some(timeout) => socket.connect_timeout(&target, deadline::clip(timeout)),
+            None => socket.connect(&target),
+        };
+        match result {
+            Ok(()) => Ok(socket.into_tcp_stream()),
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => {
+                Err(IpaddrConversionError::Timeout(addr.to_string()))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Resolve `host` through `resolver` and connect to the first of its
+    /// addresses which accepts, in the resolver's order
+    ///
+    /// # Returns
+    /// The connected stream, or the error of the last attempt if none
+    /// succeeded
+    pub fn connect_host<R: Resolve + ?Sized>(
+        &self,
+        resolver: &R,
+        host: &str,
+        port: u16,
+    ) -> Result<TcpStream, IpaddrConversionError> {
+        let mut last_err = None;
+        for ip in resolver.resolve(host)? {
+            match self.connect(SocketAddr::new(ip, port)) {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| IpaddrConversionError::NotFound(host.to_string())))
+    }
+
+    /// Listen for TCP connections on `addr`, with a backlog of
+    /// [`DEFAULT_BACKLOG`]
+    pub fn bind_tcp(&self, addr: SocketAddr) -> Result<TcpListener, IpaddrConversionError> {
+        let socket = self.socket(&addr, true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(DEFAULT_BACKLOG)?;
+        Ok(socket.into_tcp_listener())
+    }
+
+    /// Bind a UDP socket to `addr`
+    pub fn bind_udp(&self, addr: SocketAddr) -> Result<UdpSocket, IpaddrConversionError> {
+        let socket = self.socket(&addr, false)?;
+        socket.bind(&addr.into())?;
+        Ok(socket.into_udp_socket())
+    }
+
+    /// Resolve `host` through `resolver` and listen on the first of its
+    /// addresses which can be bound. An empty host listens on all IPv4
+    /// addresses, and `::` on all addresses.
+    pub fn bind_host<R: Resolve + ?Sized>(
+        &self,
+        resolver: &R,
+        host: &str,
+        port: u16,
+    ) -> Result<TcpListener, IpaddrConversionError> {
+        let addrs = match host {
+            "" => vec![IpAddr::V4(Ipv4Addr::UNSPECIFIED)],
+            "::" => vec![IpAddr::V6(Ipv6Addr::UNSPECIFIED)],
+            host => resolver.resolve(host)?,
+        };
+        let mut last_err = None;
+        for ip in addrs {
+            match self.bind_tcp(SocketAddr::new(ip, port)) {
+                Ok(listener) => return Ok(listener),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| IpaddrConversionError::NotFound(host.to_string())))
+    }
+}
+
+#[cfg(unix)]
+fn set_reuse_port(socket: &Socket) -> io::Result<()> {
+    socket.set_reuse_port(true)
+}
+
+#[cfg(not(unix))]
+fn set_reuse_port(_socket: &Socket) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "SO_REUSEPORT is not supported on this platform",
+    ))
+}
+
+#[cfg(target_os = "linux")]
+fn bind_to_device(socket: &Socket, device: &str) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: the option value is the name's bytes, with its length
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            device.as_ptr() as *const libc::c_void,
+            device.len() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_to_device(_socket: &Socket, _device: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "binding to a device is not supported on this platform",
+    ))
+}
+
+/// The `IPV6_ADDR_PREFERENCES` option and its flags, from linux/in6.h
+#[cfg(target_os = "linux")]
+const IPV6_ADDR_PREFERENCES: libc::c_int = 72;
+#[cfg(target_os = "linux")]
+const IPV6_PREFER_SRC_TMP: libc::c_int = 0x0001;
+#[cfg(target_os = "linux")]
+const IPV6_PREFER_SRC_PUBLIC: libc::c_int = 0x0002;
+
+#[cfg(target_os = "linux")]
+fn set_source_preference(socket: &Socket, preference: Ipv6SourcePreference) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let flags = match preference {
+        Ipv6SourcePreference::Temporary => IPV6_PREFER_SRC_TMP,
+        Ipv6SourcePreference::Stable => IPV6_PREFER_SRC_PUBLIC,
+    };
+    // SAFETY: the option value is an int, with its length
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            IPV6_ADDR_PREFERENCES,
+            &flags as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_source_preference(_socket: &Socket, _preference: Ipv6SourcePreference) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "choosing the kind of IPv6 source address is not supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::StaticResolver;
+
+    #[test]
+    fn options_are_set_before_connecting() {
+        let listener = SocketOptions::new()
+            .reuse_address(true)
+            .bind_tcp("127.0.0.1:0".parse().unwrap())
+            .unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // 192.0.2.0/24 is reserved for documentation, so never answers
+        let resolver = StaticResolver::new().with_entry(
+            "svc.example",
+            vec!["192.0.2.1".parse().unwrap(), "127.0.0.1".parse().unwrap()],
+        );
+        let stream = SocketOptions::new()
+            .nodelay(true)
+            .ttl(7)
+            .local_addr("127.0.0.1".parse().unwrap())
+            .connect_timeout(Duration::from_millis(200))
+            .connect_host(&resolver, "svc.example", port)
+            .unwrap();
+        assert!(stream.nodelay().unwrap());
+        assert_eq!(stream.ttl().unwrap(), 7);
+        assert_eq!(stream.peer_addr().unwrap().port(), port);
+    }
+    #[test]
+    fn binding_tries_each_address() {
+        let resolver = StaticResolver::new().with_entry(
+            "listen.example",
+            vec!["192.0.2.1".parse().unwrap(), "127.0.0.1".parse().unwrap()],
+        );
+        let socket = SocketOptions::new()
+            .bind_host(&resolver, "listen.example", 0)
+            .unwrap();
+        assert!(socket.local_addr().unwrap().ip().is_loopback());
+        let udp = SocketOptions::new()
+            .ttl(3)
+            .bind_udp("127.0.0.1:0".parse().unwrap())
+            .unwrap();
+        assert_eq!(udp.ttl().unwrap(), 3);
+    }
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn ipv6_source_preference_is_set_on_ipv6_sockets() {
+        use std::os::unix::io::AsRawFd;
+
+        let options = SocketOptions::new().prefer_ipv6_source("Temporary".parse().unwrap());
+        let socket = options.socket(&"[::1]:53".parse().unwrap(), false).unwrap();
+        let mut flags: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        // SAFETY: the buffer is an int, with its length
+        let result = unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                libc::IPPROTO_IPV6,
+                IPV6_ADDR_PREFERENCES,
+                &mut flags as *mut libc::c_int as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        assert_eq!(result, 0);
+        assert_eq!(
+            flags & (IPV6_PREFER_SRC_TMP | IPV6_PREFER_SRC_PUBLIC),
+            IPV6_PREFER_SRC_TMP
+        );
+        // IPv4 sockets are left alone
+        assert!(options
+            .socket(&"127.0.0.1:53".parse().unwrap(), false)
+            .is_ok());
+        assert!("sticky".parse::<Ipv6SourcePreference>().is_err());
+    }
+}
@@ -0,0 +1,606 @@
+//! Sender Policy Framework (RFC 7208) records and their evaluation.
+//!
+//! A domain publishes, in a TXT record starting `v=spf1`, which addresses
+//! may send mail in its name. [`SpfRecord`] parses such a record into its
+//! directives, and [`spf_check`] evaluates the policy of a domain for a
+//! sending address, following `include:` and `redirect=` as mail servers do.
+//! Macros are not supported; records using them evaluate to a permanent
+//! error.
+use crate::cidr::Cidr;
+use crate::direct::DirectResolver;
+use crate::dns::{RData, RecordType};
+use crate::errors::IpaddrConversionError;
+use crate::resolver::normalize_name;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Most mechanisms and modifiers causing DNS lookups one check may evaluate,
+/// counting those of included records (RFC 7208 section 4.6.4)
+pub const MAX_DNS_MECHANISMS: usize = 10;
+/// Most names an `mx` mechanism, or reverse names a `ptr` mechanism, looks at
+const MAX_NAMES: usize = 10;
+
+/// What a matching directive results in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Qualifier {
+    /// `+`, the default
+    Pass,
+    /// `-`
+    Fail,
+    /// `~`
+    SoftFail,
+    /// `?`
+    Neutral,
+}
+
+/// What a directive matches
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Mechanism {
+    /// Every sender
+    All,
+    /// Senders in an IPv4 block
+    Ip4(Cidr),
+    /// Senders in an IPv6 block
+    Ip6(Cidr),
+    /// Senders within the prefixes of the addresses of `domain`, or of the
+    /// domain being checked if None
+    A {
+        domain: Option<String>,
+        v4_prefix: u8,
+        v6_prefix: u8,
+    },
+    /// Senders within the prefixes of the addresses of the mail exchangers
+    /// of `domain`, or of the domain being checked if None
+    Mx {
+        domain: Option<String>,
+        v4_prefix: u8,
+        v6_prefix: u8,
+    },
+    /// Senders whose forward-confirmed reverse name is in `domain`, or in the
+    /// domain being checked if None. RFC 7208 discourages it, but it is
+    /// still published.
+    Ptr(Option<String>),
+    /// Every sender, if `domain` has an IPv4 address
+    Exists(String),
+    /// Senders the policy of the domain passes
+    Include(String),
+}
+
+/// A mechanism and what matching it results in
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Directive {
+    pub qualifier: Qualifier,
+    pub mechanism: Mechanism,
+}
+
+/// A parsed SPF record.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::spf::{Mechanism, Qualifier, SpfRecord};
+///
+/// let record: SpfRecord = "v=spf1 ip4:192.0.2.0/24 include:_spf.example.net ~all".parse().unwrap();
+/// assert_eq!(record.directives.len(), 3);
+/// assert_eq!(record.directives[2].qualifier, Qualifier::SoftFail);
+/// assert_eq!(record.directives[1].mechanism, Mechanism::Include("_spf.example.net".into()));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SpfRecord {
+    /// The directives, in the order they are evaluated
+    pub directives: Vec<Directive>,
+    /// The domain whose policy applies if no directive matches
+    pub redirect: Option<String>,
+}
+
+/// Whether `text` is an SPF record rather than some other TXT record
+pub fn is_spf(text: &str) -> bool {
+    let version = text.split(' ').next().unwrap_or("");
+    version.eq_ignore_ascii_case("v=spf1")
+}
+
+fn invalid(msg: String) -> IpaddrConversionError {
+    IpaddrConversionError::InvalidInput(msg)
+}
+
+/// The domain of a mechanism or modifier, normalized, refusing macros
+fn domain_spec(term: &str, domain: &str) -> Result<String, IpaddrConversionError> {
+    if domain.contains('%') {
+        return Err(invalid(format!("{}: SPF macros are not supported", term)));
+    }
+    let domain = normalize_name(domain);
+    if domain.is_empty() || domain.contains(char::is_whitespace) {
+        return Err(invalid(format!("{}: bad domain", term)));
+    }
+    Ok(domain)
+}
+
+/// Parse a prefix length of at most `max` bits
+fn prefix(term: &str, text: &str, max: u8) -> Result<u8, IpaddrConversionError> {
+    match text.parse::<u8>() {
+        Ok(len) if len <= max && (text == "0" || !text.starts_with('0')) => Ok(len),
+        _ => Err(invalid(format!("{}: bad prefix length", term))),
+    }
+}
+
+/// Split the `/v4//v6` prefix lengths off the argument of `a` or `mx`,
+/// returning the domain, if any, and the two lengths
+fn dual_cidr(term: &str, arg: &str) -> Result<(Option<String>, u8, u8), IpaddrConversionError> {
+    let (domain, lengths) = match arg.find('/') {
+        Some(at) => arg.split_at(at),
+        None => (arg, ""),
+    };
+    let (v4, v6) = match lengths.split_once("//") {
+        Some((v4, v6)) => (v4, Some(v6)),
+        None => (lengths, None),
+    };
+    let v4_prefix = match v4.strip_prefix('/') {
+        Some(len) => prefix(term, len, 32)?,
+        None if v4.is_empty() => 32,
+        None => return Err(invalid(format!("{}: bad prefix length", term))),
+    };
+    let v6_prefix = match v6 {
+        Some(len) => prefix(term, len, 128)?,
+        None => 128,
+    };
+    let domain = match domain.strip_prefix(':') {
+        Some(domain) => Some(domain_spec(term, domain)?),
+        None if domain.is_empty() => None,
+        None => return Err(invalid(format!("{}: unknown mechanism", term))),
+    };
+    Ok((domain, v4_prefix, v6_prefix))
+}
+
+/// Parse an `ip4:` or `ip6:` block, a lone address meaning the full prefix
+fn block(term: &str, arg: &str, v4: bool) -> Result<Cidr, IpaddrConversionError> {
+    let arg = arg
+        .strip_prefix(':')
+        .ok_or_else(|| invalid(format!("{}: missing address", term)))?;
+    let (addr, len) = arg.split_once('/').unwrap_or((arg, ""));
+    let addr: IpAddr = addr
+        .parse()
+        .map_err(|_| invalid(format!("{}: bad address", term)))?;
+    if addr.is_ipv4() != v4 {
+        return Err(invalid(format!("{}: wrong address family", term)));
+    }
+    let max = if v4 { 32 } else { 128 };
+    let len = if len.is_empty() {
+        max
+    } else {
+        prefix(term, len, max)?
+    };
+    Ok(Cidr::new(addr, len).expect("prefix length checked"))
+}
+
+fn parse_mechanism(term: &str, name: &str, arg: &str) -> Result<Mechanism, IpaddrConversionError> {
+    let target = |arg: &str| -> Result<String, IpaddrConversionError> {
+        match arg.strip_prefix(':') {
+            Some(domain) => domain_spec(term, domain),
+            None => Err(invalid(format!("{}: missing domain", term))),
+        }
+    };
+    Ok(match name.to_ascii_lowercase().as_str() {
+        "all" if arg.is_empty() => Mechanism::All,
+        "ip4" => Mechanism::Ip4(block(term, arg, true)?),
+        "ip6" => Mechanism::Ip6(block(term, arg, false)?),
+        "a" => {
+            let (domain, v4_prefix, v6_prefix) = dual_cidr(term, arg)?;
+            Mechanism::A {
+                domain,
+                v4_prefix,
+                v6_prefix,
+            }
+        }
+        "mx" => {
+            let (domain, v4_prefix, v6_prefix) = dual_cidr(term, arg)?;
+            Mechanism::Mx {
+                domain,
+                v4_prefix,
+                v6_prefix,
+            }
+        }
+        "ptr" if arg.is_empty() => Mechanism::Ptr(None),
+        "ptr" => Mechanism::Ptr(Some(target(arg)?)),
+        "exists" => Mechanism::Exists(target(arg)?),
+        "include" => Mechanism::Include(target(arg)?),
+        _ => return Err(invalid(format!("{}: unknown mechanism", term))),
+    })
+}
+
+impl SpfRecord {
+    /// Parse the text of an SPF TXT record, with its character strings
+    /// joined
+    ///
+    /// # Parameters
+    ///
+    /// * `text` - the record, starting `v=spf1`
+    ///
+    /// # Returns
+    /// The record, or an InvalidInput error for anything RFC 7208 makes a
+    /// permanent error, along with macros, which are not supported
+    pub fn parse(text: &str) -> Result<Self, IpaddrConversionError> {
+        if !is_spf(text) {
+            return Err(invalid(format!("{}: not an SPF record", text)));
+        }
+        let mut record = SpfRecord {
+            directives: Vec::new(),
+            redirect: None,
+        };
+        for term in text.split(' ').skip(1).filter(|term| !term.is_empty()) {
+            let name_end = term.find([':', '/', '=']).unwrap_or(term.len());
+            if term[name_end..].starts_with('=') {
+                let (name, value) = (&term[..name_end], &term[name_end + 1..]);
+                if name.eq_ignore_ascii_case("redirect") {
+                    if record.redirect.is_some() {
+                        return Err(invalid(format!("{}: repeated redirect", term)));
+                    }
+                    record.redirect = Some(domain_spec(term, value)?);
+                }
+                // exp= and unknown modifiers do not affect the result
+                continue;
+            }
+            let (qualifier, mechanism) = match term.as_bytes()[0] {
+                b'+' => (Qualifier::Pass, &term[1..]),
+                b'-' => (Qualifier::Fail, &term[1..]),
+                b'~' => (Qualifier::SoftFail, &term[1..]),
+                b'?' => (Qualifier::Neutral, &term[1..]),
+                _ => (Qualifier::Pass, term),
+            };
+            let name_end = mechanism.find([':', '/']).unwrap_or(mechanism.len());
+            let (name, arg) = mechanism.split_at(name_end);
+            record.directives.push(Directive {
+                qualifier,
+                mechanism: parse_mechanism(term, name, arg)?,
+            });
+        }
+        Ok(record)
+    }
+}
+
+impl FromStr for SpfRecord {
+    type Err = IpaddrConversionError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Self::parse(text)
+    }
+}
+
+/// The result of checking a sender against the policy of a domain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SpfResult {
+    /// The domain publishes no SPF record
+    None,
+    /// The domain makes no assertion about the sender
+    Neutral,
+    /// The sender may send for the domain
+    Pass,
+    /// The sender may not send for the domain
+    Fail,
+    /// The sender probably may not send for the domain
+    SoftFail,
+    /// A lookup failed in a way which may pass, so the check can be retried
+    TempError,
+    /// The policy is broken: unparsable, repeated, or making too many lookups
+    PermError,
+}
+
+impl From<Qualifier> for SpfResult {
+    fn from(qualifier: Qualifier) -> Self {
+        match qualifier {
+            Qualifier::Pass => SpfResult::Pass,
+            Qualifier::Fail => SpfResult::Fail,
+            Qualifier::SoftFail => SpfResult::SoftFail,
+            Qualifier::Neutral => SpfResult::Neutral,
+        }
+    }
+}
+
+impl fmt::Display for SpfResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            SpfResult::None => "none",
+            SpfResult::Neutral => "neutral",
+            SpfResult::Pass => "pass",
+            SpfResult::Fail => "fail",
+            SpfResult::SoftFail => "softfail",
+            SpfResult::TempError => "temperror",
+            SpfResult::PermError => "permerror",
+        })
+    }
+}
+
+/// The state of one check: the sender, and the lookups made so far
+struct Check<'a> {
+    resolver: &'a DirectResolver,
+    sender: IpAddr,
+    lookups: usize,
+}
+
+/// The answer of a lookup, with a missing name giving no records
+fn records<T>(result: Result<Vec<T>, IpaddrConversionError>) -> Result<Vec<T>, SpfResult> {
+    match result {
+        Ok(records) => Ok(records),
+        Err(IpaddrConversionError::NotFound(_)) => Ok(Vec::new()),
+        Err(_) => Err(SpfResult::TempError),
+    }
+}
+
+impl Check<'_> {
+    /// Count a mechanism or modifier making lookups against the limit
+    fn count_lookup(&mut self) -> Result<(), SpfResult> {
+        self.lookups += 1;
+        if self.lookups > MAX_DNS_MECHANISMS {
+            return Err(SpfResult::PermError);
+        }
+        Ok(())
+    }
+
+    /// The addresses of `domain` of the family given
+    fn addrs(&self, domain: &str, v4: bool) -> Result<Vec<IpAddr>, SpfResult> {
+        let qtype = if v4 { RecordType::A } else { RecordType::Aaaa };
+        let response = records(self.resolver.query(domain, qtype).map(|r| r.answers))?;
+        Ok(response
+            .into_iter()
+            .filter_map(|record| match record.data {
+                RData::A(ip) => Some(IpAddr::V4(ip)),
+                RData::Aaaa(ip) => Some(IpAddr::V6(ip)),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Whether the sender is within the prefix of one of the addresses of
+    /// `domain`
+    fn within(&self, domain: &str, v4_prefix: u8, v6_prefix: u8) -> Result<bool, SpfResult> {
+        let (v4, len) = match self.sender {
+            IpAddr::V4(_) => (true, v4_prefix),
+            IpAddr::V6(_) => (false, v6_prefix),
+        };
+        Ok(self
+            .addrs(domain, v4)?
+            .into_iter()
+            .any(|addr| Cidr::new(addr, len).is_some_and(|block| block.contains(self.sender))))
+    }
+
+    /// Whether a forward-confirmed reverse name of the sender is `domain` or
+    /// within it. Failed lookups just fail to match.
+    fn ptr_within(&self, domain: &str) -> bool {
+        let names = self.resolver.ptr(self.sender).unwrap_or_default();
+        let suffix = format!(".{}", domain);
+        names
+            .iter()
+            .take(MAX_NAMES)
+            .filter(|name| *name == domain || name.ends_with(&suffix))
+            .any(|name| {
+                self.addrs(name, self.sender.is_ipv4())
+                    .is_ok_and(|addrs| addrs.contains(&self.sender))
+            })
+    }
+
+    fn matches(&mut self, mechanism: &Mechanism, domain: &str) -> Result<bool, SpfResult> {
+        match mechanism {
+            Mechanism::All => Ok(true),
+            Mechanism::Ip4(block) | Mechanism::Ip6(block) => Ok(block.contains(self.sender)),
+            Mechanism::A {
+                domain: target,
+                v4_prefix,
+                v6_prefix,
+            } => {
+                self.count_lookup()?;
+                self.within(target.as_deref().unwrap_or(domain), *v4_prefix, *v6_prefix)
+            }
+            Mechanism::Mx {
+                domain: target,
+                v4_prefix,
+                v6_prefix,
+            } => {
+                self.count_lookup()?;
+                let target = target.as_deref().unwrap_or(domain);
+                let exchanges = records(self.resolver.mx(target))?;
+                if exchanges.len() > MAX_NAMES {
+                    return Err(SpfResult::PermError);
+                }
+                for mx in exchanges {
+                    if self.within(&normalize_name(&mx.exchange), *v4_prefix, *v6_prefix)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            Mechanism::Ptr(target) => {
+                self.count_lookup()?;
+                Ok(self.ptr_within(target.as_deref().unwrap_or(domain)))
+            }
+            Mechanism::Exists(target) => {
+                self.count_lookup()?;
+                Ok(!self.addrs(target, true)?.is_empty())
+            }
+            Mechanism::Include(target) => {
+                self.count_lookup()?;
+                match self.check_host(target) {
+                    SpfResult::Pass => Ok(true),
+                    SpfResult::Fail | SpfResult::SoftFail | SpfResult::Neutral => Ok(false),
+                    SpfResult::TempError => Err(SpfResult::TempError),
+                    SpfResult::None | SpfResult::PermError => Err(SpfResult::PermError),
+                }
+            }
+        }
+    }
+
+    /// Evaluate the policy of `domain` for the sender
+    fn check_host(&mut self, domain: &str) -> SpfResult {
+        let texts = match records(self.resolver.txt(domain)) {
+            Ok(texts) => texts,
+            Err(result) => return result,
+        };
+        let mut spf = texts.iter().filter(|text| is_spf(text));
+        let record = match (spf.next(), spf.next()) {
+            (None, _) => return SpfResult::None,
+            (Some(text), None) => match SpfRecord::parse(text) {
+                Ok(record) => record,
+                Err(_) => return SpfResult::PermError,
+            },
+            (Some(_), Some(_)) => return SpfResult::PermError,
+        };
+        for directive in &record.directives {
+            match self.matches(&directive.mechanism, domain) {
+                Ok(true) => return directive.qualifier.into(),
+                Ok(false) => {}
+                Err(result) => return result,
+            }
+        }
+        match record.redirect {
+            Some(target) => {
+                if let Err(result) = self.count_lookup() {
+                    return result;
+                }
+                match self.check_host(&target) {
+                    SpfResult::None => SpfResult::PermError,
+                    result => result,
+                }
+            }
+            None => SpfResult::Neutral,
+        }
+    }
+}
+
+/// Check whether `sender_ip` may send mail for `domain`, querying the
+/// system's nameservers directly.
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::spf::{spf_check, SpfResult};
+///
+/// let result = spf_check("192.0.2.1".parse().unwrap(), "example.com");
+/// if result == SpfResult::Fail {
+///     eprintln!("rejecting mail from 192.0.2.1");
+/// }
+/// ```
+pub fn spf_check(sender_ip: IpAddr, domain: &str) -> SpfResult {
+    match DirectResolver::from_system() {
+        Ok(resolver) => spf_check_with(&resolver, sender_ip, domain),
+        Err(_) => SpfResult::TempError,
+    }
+}
+
+/// Check whether `sender_ip` may send mail for `domain`
+///
+/// # Parameters
+///
+/// * `resolver` - looks up the records of the policy
+/// * `sender_ip` - the address of the sending server; an IPv4-mapped IPv6
+///   address is checked as its IPv4 address
+/// * `domain` - the domain the mail claims to be from
+///
+/// # Returns
+/// The result of the policy of `domain`, None if it has none
+pub fn spf_check_with(resolver: &DirectResolver, sender_ip: IpAddr, domain: &str) -> SpfResult {
+    let domain = normalize_name(domain);
+    if domain.is_empty() {
+        return SpfResult::None;
+    }
+    Check {
+        resolver,
+        sender: sender_ip.to_canonical(),
+        lookups: 0,
+    }
+    .check_host(&domain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::direct::tests::{answer, config_for, spawn_udp_server};
+    use crate::dns::{Mx, RCODE_NXDOMAIN};
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn records_are_parsed() {
+        let record = SpfRecord::parse("v=spf1 +a mx:example.net/24//64 ip6:2001:db8::/32 ?ptr redirect=_spf.Example.com. exp=explain").unwrap();
+        assert_eq!(
+            record.directives[1].mechanism,
+            Mechanism::Mx {
+                domain: Some("example.net".into()),
+                v4_prefix: 24,
+                v6_prefix: 64
+            }
+        );
+        assert_eq!(
+            record.directives[2].mechanism,
+            Mechanism::Ip6(Cidr::new("2001:db8::".parse().unwrap(), 32).unwrap())
+        );
+        assert_eq!(record.directives[3].qualifier, Qualifier::Neutral);
+        assert_eq!(record.redirect.as_deref(), Some("_spf.example.com"));
+        for bad in [
+            "v=spf2 -all",
+            "v=spf1 ip4:2001:db8::1",
+            "v=spf1 a/33",
+            "v=spf1 include:%{d}.example.com",
+            "v=spf1 frobnicate",
+        ] {
+            assert!(SpfRecord::parse(bad).is_err(), "{}", bad);
+        }
+    }
+    #[test]
+    fn policies_are_evaluated() {
+        let server = spawn_udp_server(|request| {
+            let question = &request.questions[0];
+            let txt = |text: &str| vec![RData::Txt(vec![text.to_string()])];
+            let data = match (question.name.as_str(), question.qtype) {
+                ("example.com", RecordType::Txt) => txt(
+                    "v=spf1 ip4:192.0.2.0/24 include:_spf.example.net a:web.example.com/30 mx -all",
+                ),
+                ("_spf.example.net", RecordType::Txt) => txt("v=spf1 ip6:2001:db8::/32 ~all"),
+                ("loop.example.com", RecordType::Txt) => txt("v=spf1 include:loop.example.com"),
+                ("web.example.com", RecordType::A) => {
+                    vec![RData::A(Ipv4Addr::new(198, 51, 100, 8))]
+                }
+                ("example.com", RecordType::Mx) => vec![RData::Mx(Mx {
+                    preference: 10,
+                    exchange: "mail.example.com".into(),
+                })],
+                ("mail.example.com", RecordType::A) => {
+                    vec![RData::A(Ipv4Addr::new(203, 0, 113, 5))]
+                }
+                ("mail.example.com", _) | ("web.example.com", _) | ("example.com", _) => vec![],
+                _ => return Some(answer(request, RCODE_NXDOMAIN, vec![])),
+            };
+            Some(answer(request, 0, data))
+        });
+        let resolver = DirectResolver::new(config_for(vec![server]));
+        let check = |ip: IpAddr, domain| spf_check_with(&resolver, ip, domain);
+        assert_eq!(
+            check(Ipv4Addr::new(192, 0, 2, 7).into(), "example.com"),
+            SpfResult::Pass
+        );
+        let v6 = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+        assert_eq!(check(v6.into(), "Example.com."), SpfResult::Pass);
+        assert_eq!(check(v6.into(), "_spf.example.net"), SpfResult::Pass);
+        assert_eq!(
+            check(Ipv4Addr::new(198, 51, 100, 11).into(), "example.com"),
+            SpfResult::Pass
+        );
+        assert_eq!(
+            check("::ffff:203.0.113.5".parse().unwrap(), "example.com"),
+            SpfResult::Pass
+        );
+        assert_eq!(
+            check(Ipv4Addr::new(10, 0, 0, 1).into(), "example.com"),
+            SpfResult::Fail
+        );
+        assert_eq!(
+            check("2001:db9::1".parse().unwrap(), "_spf.example.net"),
+            SpfResult::SoftFail
+        );
+        assert_eq!(
+            check(Ipv4Addr::new(10, 0, 0, 1).into(), "missing.example.com"),
+            SpfResult::None
+        );
+        assert_eq!(
+            check(Ipv4Addr::new(10, 0, 0, 1).into(), "loop.example.com"),
+            SpfResult::PermError
+        );
+    }
+}
@@ -0,0 +1,75 @@
+//! `ToSql` and `FromSql` for diesel's Postgres backend.
+//!
+//! `Cidr` maps onto `Inet` and `Cidr` columns and `Address` onto `Inet`,
+//! while `IpRange` is stored as `Text`, in the form it parses from. `Cidr`
+//! derives its expressions for `Cidr` columns, and for `Inet` columns takes
+//! them from [`CidrAsInet`].
+use super::{decode_inet, encode_inet};
+use crate::address::Address;
+use crate::cidr::{Cidr, IpRange};
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::{Pg, PgValue};
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::{Inet, Text};
+use std::io::Write;
+
+/// Stands in for `Cidr` to derive its expressions as an `Inet`, a type
+/// taking only one `sql_type`
+#[derive(diesel::expression::AsExpression)]
+#[diesel(foreign_derive)]
+#[diesel(sql_type = Inet)]
+#[allow(dead_code)]
+struct CidrAsInet(Cidr);
+
+impl ToSql<Inet, Pg> for Cidr {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(&encode_inet(self, false))?;
+        Ok(IsNull::No)
+    }
+}
+
+impl ToSql<diesel::sql_types::Cidr, Pg> for Cidr {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(&encode_inet(self, true))?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Inet, Pg> for Cidr {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        Ok(decode_inet(bytes.as_bytes())?)
+    }
+}
+
+impl FromSql<diesel::sql_types::Cidr, Pg> for Cidr {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        Ok(decode_inet(bytes.as_bytes())?)
+    }
+}
+
+impl ToSql<Inet, Pg> for Address {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        let host = Cidr::new(self.ip(), self.bits()).expect("full width prefix");
+        out.write_all(&encode_inet(&host, false))?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Inet, Pg> for Address {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        Ok(Address::new(decode_inet(bytes.as_bytes())?.addr()))
+    }
+}
+
+impl ToSql<Text, Pg> for IpRange {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.to_string().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Pg> for IpRange {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        Ok(std::str::from_utf8(bytes.as_bytes())?.parse()?)
+    }
+}
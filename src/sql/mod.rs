@@ -0,0 +1,104 @@
+//! Database column types for the address types.
+//!
+//! Postgres sends `inet` and `cidr` values in one binary layout: the family,
+//! the prefix length, whether the value is a `cidr`, the address length and
+//! then the address bytes. The codec for it lives here, shared by the driver
+//! specific implementations enabled by the `postgres`, `sqlx` and `diesel`
+//! features.
+use crate::cidr::Cidr;
+#[cfg(feature = "sqlx")]
+use crate::parse::{parse_cidr, parse_ip, ParseError};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+#[cfg(feature = "diesel")]
+mod diesel_types;
+#[cfg(feature = "postgres")]
+mod postgres;
+#[cfg(feature = "sqlx")]
+mod sqlx_types;
+
+/// Postgres' own address family numbers, which do not follow the OS
+const PGSQL_AF_INET: u8 = 2;
+const PGSQL_AF_INET6: u8 = 3;
+
+/// Encode `cidr` as an `inet`, or when `is_cidr` is set as a `cidr`, which
+/// must not have host bits set and so is sent as its network address
+pub(crate) fn encode_inet(cidr: &Cidr, is_cidr: bool) -> Vec<u8> {
+    let addr = if is_cidr { cidr.network() } else { cidr.addr() };
+    let (family, bytes) = match addr {
+        IpAddr::V4(ip) => (PGSQL_AF_INET, ip.octets().to_vec()),
+        IpAddr::V6(ip) => (PGSQL_AF_INET6, ip.octets().to_vec()),
+    };
+    let mut out = vec![family, cidr.prefix_len(), is_cidr as u8, bytes.len() as u8];
+    out.extend_from_slice(&bytes);
+    out
+}
+
+/// Decode an `inet` or `cidr` value
+pub(crate) fn decode_inet(raw: &[u8]) -> Result<Cidr, String> {
+    let (header, bytes) = match raw {
+        [family, bits, _, len, bytes @ ..] if bytes.len() == *len as usize => {
+            ((*family, *bits), bytes)
+        }
+        _ => return Err(format!("malformed inet value of {} bytes", raw.len())),
+    };
+    let addr = match (header.0, bytes.len()) {
+        (PGSQL_AF_INET, 4) => IpAddr::V4(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3])),
+        (PGSQL_AF_INET6, 16) => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(bytes);
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        (family, len) => {
+            return Err(format!(
+                "inet value of family {} with {} address bytes",
+                family, len
+            ))
+        }
+    };
+    Cidr::new(addr, header.1).ok_or_else(|| format!("inet prefix length {} too long", header.1))
+}
+
+/// Parse the text form of an `inet` or `cidr`, where the prefix length is
+/// left out for single hosts
+#[cfg(feature = "sqlx")]
+pub(crate) fn parse_inet_text(text: &str) -> Result<Cidr, ParseError> {
+    if text.contains('/') {
+        return parse_cidr(text);
+    }
+    let addr = parse_ip(text)?;
+    let width = if addr.is_ipv4() { 32 } else { 128 };
+    Ok(Cidr::new(addr, width).expect("full width prefix"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inet_round_trips() {
+        let cidr: Cidr = "192.0.2.7/24".parse().unwrap();
+        let inet = encode_inet(&cidr, false);
+        assert_eq!(inet, vec![2, 24, 0, 4, 192, 0, 2, 7]);
+        assert_eq!(decode_inet(&inet).unwrap(), cidr);
+        // cidr columns reject host bits
+        assert_eq!(encode_inet(&cidr, true), vec![2, 24, 1, 4, 192, 0, 2, 0]);
+
+        let cidr: Cidr = "2001:db8::/32".parse().unwrap();
+        assert_eq!(decode_inet(&encode_inet(&cidr, true)).unwrap(), cidr);
+    }
+    #[test]
+    fn malformed_values_are_rejected() {
+        assert!(decode_inet(&[2, 24, 0, 4, 192, 0, 2]).is_err());
+        assert!(decode_inet(&[3, 24, 0, 4, 192, 0, 2, 0]).is_err());
+        assert!(decode_inet(&[2, 33, 0, 4, 192, 0, 2, 0]).is_err());
+    }
+    #[cfg(feature = "sqlx")]
+    #[test]
+    fn text_form_may_leave_out_the_prefix() {
+        assert_eq!(parse_inet_text("10.0.0.1").unwrap().prefix_len(), 32);
+        assert_eq!(parse_inet_text("::1").unwrap().prefix_len(), 128);
+        assert_eq!(parse_inet_text("10.0.0.0/8").unwrap().prefix_len(), 8);
+        assert!(parse_inet_text("10.0.0.0/").is_err());
+    }
+}
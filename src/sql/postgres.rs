@@ -0,0 +1,52 @@
+//! `ToSql` and `FromSql` for the postgres and tokio-postgres crates.
+//!
+//! `Cidr` maps onto both `inet` and `cidr` columns; an `inet` keeps the host
+//! bits of the address, while a `cidr` holds only the network. `Address`
+//! maps onto `inet`, as a single host, and reads the address of any `inet`,
+//! ignoring its netmask. Postgres has no range type for addresses, so
+//! `IpRange` has no mapping.
+use super::{decode_inet, encode_inet};
+use crate::address::Address;
+use crate::cidr::Cidr;
+use bytes::BytesMut;
+use postgres_types::{accepts, to_sql_checked, FromSql, IsNull, ToSql, Type};
+use std::error::Error;
+
+type BoxError = Box<dyn Error + Sync + Send>;
+
+impl ToSql for Cidr {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, BoxError> {
+        out.extend_from_slice(&encode_inet(self, *ty == Type::CIDR));
+        Ok(IsNull::No)
+    }
+
+    accepts!(INET, CIDR);
+    to_sql_checked!();
+}
+
+impl<'a> FromSql<'a> for Cidr {
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> Result<Self, BoxError> {
+        Ok(decode_inet(raw)?)
+    }
+
+    accepts!(INET, CIDR);
+}
+
+impl ToSql for Address {
+    fn to_sql(&self, _ty: &Type, out: &mut BytesMut) -> Result<IsNull, BoxError> {
+        let host = Cidr::new(self.ip(), self.bits()).expect("full width prefix");
+        out.extend_from_slice(&encode_inet(&host, false));
+        Ok(IsNull::No)
+    }
+
+    accepts!(INET);
+    to_sql_checked!();
+}
+
+impl<'a> FromSql<'a> for Address {
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> Result<Self, BoxError> {
+        Ok(Address::new(decode_inet(raw)?.addr()))
+    }
+
+    accepts!(INET);
+}
@@ -0,0 +1,93 @@
+//! `Type`, `Encode` and `Decode` for sqlx's Postgres driver.
+//!
+//! `Cidr` and `Address` are sent as `inet`, which Postgres casts on
+//! assignment to `cidr` columns, and read from either type. `IpRange` is
+//! stored as text, in the form it parses from.
+use super::{decode_inet, encode_inet, parse_inet_text};
+use crate::address::Address;
+use crate::cidr::{Cidr, IpRange};
+use sqlx::encode::IsNull;
+use sqlx::error::BoxDynError;
+use sqlx::postgres::{PgArgumentBuffer, PgTypeInfo, PgValueFormat, PgValueRef, Postgres};
+use sqlx::{Decode, Encode, Type};
+
+fn is_inet(ty: &PgTypeInfo) -> bool {
+    *ty == PgTypeInfo::with_name("inet") || *ty == PgTypeInfo::with_name("cidr")
+}
+
+fn decode_value(value: PgValueRef<'_>) -> Result<Cidr, BoxDynError> {
+    match value.format() {
+        PgValueFormat::Binary => Ok(decode_inet(value.as_bytes()?)?),
+        PgValueFormat::Text => Ok(parse_inet_text(value.as_str()?)?),
+    }
+}
+
+impl Type<Postgres> for Cidr {
+    fn type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("inet")
+    }
+
+    fn compatible(ty: &PgTypeInfo) -> bool {
+        is_inet(ty)
+    }
+}
+
+impl Encode<'_, Postgres> for Cidr {
+    fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> IsNull {
+        buf.extend_from_slice(&encode_inet(self, false));
+        IsNull::No
+    }
+}
+
+impl<'r> Decode<'r, Postgres> for Cidr {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+        decode_value(value)
+    }
+}
+
+impl Type<Postgres> for Address {
+    fn type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("inet")
+    }
+
+    fn compatible(ty: &PgTypeInfo) -> bool {
+        is_inet(ty)
+    }
+}
+
+impl Encode<'_, Postgres> for Address {
+    fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> IsNull {
+        let host = Cidr::new(self.ip(), self.bits()).expect("full width prefix");
+        buf.extend_from_slice(&encode_inet(&host, false));
+        IsNull::No
+    }
+}
+
+impl<'r> Decode<'r, Postgres> for Address {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+        Ok(Address::new(decode_value(value)?.addr()))
+    }
+}
+
+impl Type<Postgres> for IpRange {
+    fn type_info() -> PgTypeInfo {
+        <String as Type<Postgres>>::type_info()
+    }
+
+    fn compatible(ty: &PgTypeInfo) -> bool {
+        <String as Type<Postgres>>::compatible(ty)
+    }
+}
+
+impl Encode<'_, Postgres> for IpRange {
+    fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> IsNull {
+        buf.extend_from_slice(self.to_string().as_bytes());
+        IsNull::No
+    }
+}
+
+impl<'r> Decode<'r, Postgres> for IpRange {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+        Ok(<&str as Decode<Postgres>>::decode(value)?.parse()?)
+    }
+}
@@ -0,0 +1,438 @@
+//! What `ssh <alias>` connects to, from OpenSSH's configuration files.
+//!
+//! [`SshConfig`] applies `Host` blocks the way OpenSSH does: every block
+//! whose patterns match the alias contributes, and the first value given for
+//! a keyword wins, so the effective `HostName` and `Port` of an alias are
+//! those [`SshConfig::target`] reports. [`KnownHosts`] finds the keys
+//! recorded for a host, hashed entries included. `Match` blocks other than
+//! `Match all` are never applied, and `Include` is not followed.
+use crate::digest::hmac_sha1;
+use crate::errors::IpaddrConversionError;
+use crate::resolver::Resolve;
+use std::env;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+/// The port ssh connects to when the configuration names none
+pub const DEFAULT_PORT: u16 = 22;
+
+/// The path of `name` under the user's `~/.ssh`
+fn user_file(name: &str) -> Result<PathBuf, IpaddrConversionError> {
+    let home = env::var_os("HOME")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "HOME is not set"))?;
+    Ok(Path::new(&home).join(".ssh").join(name))
+}
+
+/// Whether `name` matches one OpenSSH pattern, in which `*` matches any run
+/// of characters and `?` any one, ignoring case
+pub(crate) fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+    // the position after the last `*`, and the name position it was tried at
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((after_star, tried)) => {
+                    p = after_star;
+                    n = tried + 1;
+                    backtrack = Some((after_star, tried + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Whether `name` matches a list of patterns: some positive pattern matches
+/// and no negated one, written `!pattern`, does
+fn pattern_list_match<S: AsRef<str>>(patterns: &[S], name: &str) -> bool {
+    let mut matched = false;
+    for pattern in patterns {
+        match pattern.as_ref().strip_prefix('!') {
+            Some(negated) if wildcard_match(negated, name) => return false,
+            Some(_) => {}
+            None => matched |= wildcard_match(pattern.as_ref(), name),
+        }
+    }
+    matched
+}
+
+/// Split a configuration line into its keyword and arguments, accepting
+/// `Keyword value` and `Keyword=value`, and unquoting double quoted
+/// arguments
+fn split_line(line: &str) -> Option<(String, Vec<String>)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let end = line
+        .find(|c: char| c.is_whitespace() || c == '=')
+        .unwrap_or(line.len());
+    let keyword = line[..end].to_lowercase();
+    let rest = line[end..].trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest);
+    let mut args = Vec::new();
+    let mut chars = rest.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut arg = String::new();
+        if c == '"' {
+            chars.next();
+            arg.extend(chars.by_ref().take_while(|&c| c != '"'));
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                arg.push(c);
+                chars.next();
+            }
+        }
+        args.push(arg);
+    }
+    Some((keyword, args))
+}
+
+/// A `Host` block and the options set in it
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct HostBlock {
+    /// Patterns the alias must match; None for a block which never applies
+    patterns: Option<Vec<String>>,
+    /// Keywords, lowercased, with their arguments joined by spaces
+    options: Vec<(String, String)>,
+}
+
+/// An OpenSSH client configuration, such as `~/.ssh/config`
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::ssh::SshConfig;
+///
+/// let config = SshConfig::parse(
+///     "Host db-*\n  HostName %h.internal.example.com\n  Port 2222\n",
+/// );
+/// assert_eq!(
+///     config.target("db-1"),
+///     ("db-1.internal.example.com".to_string(), 2222)
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SshConfig {
+    blocks: Vec<HostBlock>,
+}
+
+impl SshConfig {
+    /// Parse the contents of a configuration file. Options before the first
+    /// `Host` apply to every host.
+    pub fn parse(contents: &str) -> Self {
+        let mut blocks = vec![HostBlock {
+            patterns: Some(vec!["*".to_string()]),
+            options: Vec::new(),
+        }];
+        for (keyword, args) in contents.lines().filter_map(split_line) {
+            match keyword.as_str() {
+                "host" => blocks.push(HostBlock {
+                    patterns: Some(args),
+                    options: Vec::new(),
+                }),
+                "match" => blocks.push(HostBlock {
+                    patterns: match args.as_slice() {
+                        [all] if all.eq_ignore_ascii_case("all") => Some(vec!["*".to_string()]),
+                        _ => None,
+                    },
+                    options: Vec::new(),
+                }),
+                _ => {
+                    let block = blocks.last_mut().expect("there is always a block");
+                    block.options.push((keyword, args.join(" ")));
+                }
+            }
+        }
+        Self { blocks }
+    }
+
+    /// Read and parse the configuration at `path`
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, IpaddrConversionError> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    /// The user's `~/.ssh/config`, or an empty configuration if there is none
+    pub fn user() -> Result<Self, IpaddrConversionError> {
+        match Self::from_file(user_file("config")?) {
+            Err(IpaddrConversionError::IoError(err)) if err.kind() == io::ErrorKind::NotFound => {
+                Ok(Self::default())
+            }
+            result => result,
+        }
+    }
+
+    /// The value `alias` gets for `keyword`, from the first matching block
+    /// which sets it
+    pub fn option(&self, alias: &str, keyword: &str) -> Option<&str> {
+        let keyword = keyword.to_lowercase();
+        self.blocks
+            .iter()
+            .filter(|block| {
+                block
+                    .patterns
+                    .as_ref()
+                    .is_some_and(|patterns| pattern_list_match(patterns, alias))
+            })
+            .flat_map(|block| block.options.iter())
+            .find(|(key, _)| *key == keyword)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The hostname `ssh alias` connects to: its `HostName`, with `%h`
+    /// replaced by the alias, or the alias itself
+    pub fn hostname(&self, alias: &str) -> String {
+        match self.option(alias, "HostName") {
+            Some(hostname) => hostname.replace("%h", alias).replace("%%", "%"),
+            None => alias.to_string(),
+        }
+    }
+
+    /// The port `ssh alias` connects to
+    pub fn port(&self, alias: &str) -> u16 {
+        self.option(alias, "Port")
+            .and_then(|port| port.parse().ok())
+            .unwrap_or(DEFAULT_PORT)
+    }
+
+    /// The hostname and port `ssh alias` connects to
+    pub fn target(&self, alias: &str) -> (String, u16) {
+        (self.hostname(alias), self.port(alias))
+    }
+
+    /// Resolve what `ssh alias` connects to through `resolver`
+    pub fn resolve<R: Resolve + ?Sized>(
+        &self,
+        resolver: &R,
+        alias: &str,
+    ) -> Result<Vec<SocketAddr>, IpaddrConversionError> {
+        let (hostname, port) = self.target(alias);
+        Ok(resolver
+            .resolve(&hostname)?
+            .into_iter()
+            .map(|addr| SocketAddr::new(addr, port))
+            .collect())
+    }
+}
+
+/// The hosts a known_hosts line is for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostPatterns {
+    /// Comma separated patterns, which may use wildcards and negation
+    Plain(Vec<String>),
+    /// A `|1|salt|hash` entry: the HMAC-SHA1 of the host name under the salt
+    Hashed { salt: Vec<u8>, hash: Vec<u8> },
+}
+
+/// A line of a known_hosts file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KnownHost {
+    /// `@cert-authority` or `@revoked`, if the line is marked
+    pub marker: Option<String>,
+    pub hosts: HostPatterns,
+    pub key_type: String,
+    /// The base64 encoded public key
+    pub key: String,
+}
+
+impl KnownHost {
+    /// Whether the line is for `host` reached on `port`, named as ssh names
+    /// it: `host` on the default port, `[host]:port` otherwise
+    pub fn matches(&self, host: &str, port: u16) -> bool {
+        let name = if port == DEFAULT_PORT {
+            host.to_lowercase()
+        } else {
+            format!("[{}]:{}", host.to_lowercase(), port)
+        };
+        match self.hosts {
+            HostPatterns::Plain(ref patterns) => pattern_list_match(patterns, &name),
+            HostPatterns::Hashed { ref salt, ref hash } => {
+                hmac_sha1(salt, name.as_bytes())[..] == hash[..]
+            }
+        }
+    }
+}
+
+/// The host keys of a known_hosts file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KnownHosts {
+    entries: Vec<KnownHost>,
+}
+
+impl KnownHosts {
+    /// Parse the contents of a known_hosts file, skipping lines which do not
+    /// parse
+    pub fn parse(contents: &str) -> Self {
+        let mut entries = Vec::new();
+        for line in contents.lines() {
+            let mut fields = line.split_whitespace().peekable();
+            if fields.peek().is_none_or(|first| first.starts_with('#')) {
+                continue;
+            }
+            let marker = fields
+                .next_if(|field| field.starts_with('@'))
+                .map(str::to_string);
+            let (hosts, key_type, key) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(hosts), Some(key_type), Some(key)) => (hosts, key_type, key),
+                _ => continue,
+            };
+            let hosts = match hosts.strip_prefix("|1|") {
+                Some(hashed) => {
+                    let mut parts = hashed.splitn(2, '|');
+                    let salt = parts.next().and_then(base64_decode);
+                    let hash = parts.next().and_then(base64_decode);
+                    match (salt, hash) {
+                        (Some(salt), Some(hash)) => HostPatterns::Hashed { salt, hash },
+                        _ => continue,
+                    }
+                }
+                None => HostPatterns::Plain(hosts.split(',').map(str::to_string).collect()),
+            };
+            entries.push(KnownHost {
+                marker,
+                hosts,
+                key_type: key_type.to_string(),
+                key: key.to_string(),
+            });
+        }
+        Self { entries }
+    }
+
+    /// Read and parse the known_hosts file at `path`
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, IpaddrConversionError> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    /// The user's `~/.ssh/known_hosts`
+    pub fn user() -> Result<Self, IpaddrConversionError> {
+        Self::from_file(user_file("known_hosts")?)
+    }
+
+    /// Every line of the file
+    pub fn entries(&self) -> &[KnownHost] {
+        &self.entries
+    }
+
+    /// The lines for `host` on `port`, revoked and certificate authority
+    /// lines included
+    pub fn lookup(&self, host: &str, port: u16) -> Vec<&KnownHost> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.matches(host, port))
+            .collect()
+    }
+}
+
+/// Decode standard, padded base64
+pub(crate) fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    fn value(c: u8) -> Option<u32> {
+        match c {
+            b'A'..=b'Z' => Some(u32::from(c - b'A')),
+            b'a'..=b'z' => Some(u32::from(c - b'a') + 26),
+            b'0'..=b'9' => Some(u32::from(c - b'0') + 52),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        }
+    }
+
+    if !text.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    for chunk in text.as_bytes().chunks(4) {
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        let mut bits = 0u32;
+        for &c in &chunk[..4 - padding] {
+            bits = bits << 6 | value(c)?;
+        }
+        bits <<= 6 * padding as u32;
+        out.extend_from_slice(&bits.to_be_bytes()[1..4 - padding]);
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_value_wins_across_matching_blocks() {
+        let config = SshConfig::parse(
+            "# comment\n\
+             User ops\n\
+             Host bastion\n\
+             \tHostName=203.0.113.7\n\
+             Host *.prod !legacy.prod\n\
+             \tPort 2200\n\
+             \tHostName \"%h.example.net\"\n\
+             Match exec \"true\"\n\
+             \tPort 1\n\
+             Host *\n\
+             \tPort 2300\n\
+             \tUser nobody\n",
+        );
+        assert_eq!(config.target("bastion"), ("203.0.113.7".to_string(), 2300));
+        assert_eq!(
+            config.target("API.prod"),
+            ("API.prod.example.net".to_string(), 2200)
+        );
+        assert_eq!(
+            config.target("legacy.prod"),
+            ("legacy.prod".to_string(), 2300)
+        );
+        assert_eq!(config.option("anything", "user"), Some("ops"));
+        assert!(wildcard_match("w?b-*-0*", "web-eu-01"));
+        assert!(!wildcard_match("web-*-02", "web-eu-01"));
+    }
+    #[test]
+    fn hashed_and_plain_known_hosts_match() {
+        let known = KnownHosts::parse(
+            "|1|AQIDBAUGBwgJCgsMDQ4PEBESExQ=|EiQSkbI2iH6xlKTHZxO85Msmylo= ssh-ed25519 AAAAC3Nza\n\
+             |1|AQIDBAUGBwgJCgsMDQ4PEBESExQ=|4a/CQchGa9LqSrg+ICm0tH82iZs= ssh-rsa AAAAB3Nza\n\
+             @cert-authority *.example.com,!*.lab.example.com ssh-ed25519 AAAAC3Cert\n\
+             not-enough-fields\n",
+        );
+        assert_eq!(known.entries().len(), 3);
+        assert_eq!(
+            known.lookup("git.example.com", 22)[0].key_type,
+            "ssh-ed25519"
+        );
+        let on_2222 = known.lookup("GIT.example.com", 2222);
+        assert_eq!(on_2222.len(), 1);
+        assert_eq!(on_2222[0].key_type, "ssh-rsa");
+        assert_eq!(
+            known.lookup("build.example.com", 22)[0].marker.as_deref(),
+            Some("@cert-authority")
+        );
+        assert!(known.lookup("a.lab.example.com", 22).is_empty());
+    }
+    #[test]
+    fn base64_round_trips_padding() {
+        assert_eq!(base64_decode("Zm9vYmFy").unwrap(), b"foobar");
+        assert_eq!(base64_decode("Zm9vYg==").unwrap(), b"foob");
+        assert_eq!(base64_decode("Zm9vYmE=").unwrap(), b"fooba");
+        assert!(base64_decode("Zm9").is_none());
+    }
+}
@@ -0,0 +1,466 @@
+//! Counting queries, errors, cache hits and latency.
+//!
+//! [`StatsResolver`] wraps another resolver and records every lookup through
+//! it in a [`StatsCollector`]. The same collector can be handed to a
+//! [`CachingResolver`](crate::cache::CachingResolver) with `with_stats`, so
+//! the figures include its hit ratio. Figures are available since the
+//! collector was created, or since the last snapshot, for dashboards which
+//! poll at an interval. Latencies over the last minute, or another sliding
+//! window, are kept in a [`LatencyHistogram`] as well. The last few failures
+//! are kept too, with the name looked up, for
+//! [`DebugDump`](crate::dump::DebugDump). A collector given to a resolver
+//! with a [`CircuitBreaker`] reports the health of its nameservers as well.
+use crate::breaker::{CircuitBreaker, ServerHealth};
+use crate::dump::DebugDump;
+use crate::errors::IpaddrConversionError;
+use crate::histogram::LatencyHistogram;
+use crate::resolver::Resolve;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Latencies kept per period; beyond this a uniform sample is kept
+const MAX_LATENCY_SAMPLES: usize = 10_000;
+
+/// How far back a collector's latency histogram covers unless told
+/// otherwise
+pub const DEFAULT_LATENCY_WINDOW: Duration = Duration::from_secs(60);
+
+/// Failures kept by a collector; older ones are dropped
+pub const MAX_RECENT_ERRORS: usize = 32;
+
+/// Figures for a period
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolverStats {
+    /// How long the figures cover
+    pub period: Duration,
+    pub queries: u64,
+    /// Failed queries, by error kind
+    pub errors: BTreeMap<&'static str, u64>,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    /// Nameservers skipped as their circuit was open
+    pub servers_skipped: u64,
+    /// The health of each nameserver as the figures were taken, if the
+    /// resolver has a circuit breaker
+    pub servers: Vec<ServerHealth>,
+    latency_total: Duration,
+    /// Sorted
+    latencies: Vec<Duration>,
+}
+
+impl ResolverStats {
+    /// Failed queries, of every kind
+    pub fn error_count(&self) -> u64 {
+        self.errors.values().sum()
+    }
+
+    /// Fraction of cache lookups which were hits, or None if the cache was
+    /// not consulted
+    pub fn cache_hit_ratio(&self) -> Option<f64> {
+        let lookups = self.cache_hits + self.cache_misses;
+        if lookups == 0 {
+            None
+        } else {
+            Some(self.cache_hits as f64 / lookups as f64)
+        }
+    }
+
+    /// Mean latency of the queries, or None if there were none
+    pub fn mean_latency(&self) -> Option<Duration> {
+        if self.queries == 0 {
+            None
+        } else {
+            Some(Duration::from_secs_f64(
+                self.latency_total.as_secs_f64() / self.queries as f64,
+            ))
+        }
+    }
+
+    /// The latency below which `percentile` percent of queries finished, by
+    /// the nearest rank method. None if there were no queries.
+    pub fn latency_percentile(&self, percentile: f64) -> Option<Duration> {
+        if self.latencies.is_empty() {
+            return None;
+        }
+        let rank = (percentile.clamp(0.0, 100.0) / 100.0 * self.latencies.len() as f64).ceil();
+        let index = (rank as usize).clamp(1, self.latencies.len()) - 1;
+        Some(self.latencies[index])
+    }
+}
+
+impl fmt::Display for ResolverStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ms = |d: Option<Duration>| d.map_or(0.0, |d| d.as_secs_f64() * 1000.0);
+        write!(
+            f,
+            "queries {} errors {} over {:.1}s latency mean {:.2}ms p50 {:.2}ms p99 {:.2}ms",
+            self.queries,
+            self.error_count(),
+            self.period.as_secs_f64(),
+            ms(self.mean_latency()),
+            ms(self.latency_percentile(50.0)),
+            ms(self.latency_percentile(99.0)),
+        )?;
+        if let Some(ratio) = self.cache_hit_ratio() {
+            write!(f, " cache hits {:.1}%", ratio * 100.0)?;
+        }
+        if self.servers_skipped > 0 {
+            write!(f, " servers skipped {}", self.servers_skipped)?;
+        }
+        Ok(())
+    }
+}
+
+/// Running totals for one period
+#[derive(Debug)]
+struct Period {
+    started: Instant,
+    queries: u64,
+    errors: BTreeMap<&'static str, u64>,
+    cache_hits: u64,
+    cache_misses: u64,
+    servers_skipped: u64,
+    latency_total: Duration,
+    latencies: Vec<Duration>,
+}
+
+impl Period {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            queries: 0,
+            errors: BTreeMap::new(),
+            cache_hits: 0,
+            cache_misses: 0,
+            servers_skipped: 0,
+            latency_total: Duration::from_secs(0),
+            latencies: Vec::new(),
+        }
+    }
+
+    fn record_query(&mut self, latency: Duration, error: Option<&'static str>) {
+        self.queries += 1;
+        self.latency_total += latency;
+        if let Some(kind) = error {
+            *self.errors.entry(kind).or_insert(0) += 1;
+        }
+        // reservoir sampling keeps every query equally likely to be counted
+        if self.latencies.len() < MAX_LATENCY_SAMPLES {
+            self.latencies.push(latency);
+        } else {
+            let slot = (crate::direct::random_u64() % self.queries) as usize;
+            if slot < MAX_LATENCY_SAMPLES {
+                self.latencies[slot] = latency;
+            }
+        }
+    }
+
+    fn stats(&self) -> ResolverStats {
+        let mut latencies = self.latencies.clone();
+        latencies.sort();
+        ResolverStats {
+            period: self.started.elapsed(),
+            queries: self.queries,
+            errors: self.errors.clone(),
+            cache_hits: self.cache_hits,
+            cache_misses: self.cache_misses,
+            servers_skipped: self.servers_skipped,
+            servers: Vec::new(),
+            latency_total: self.latency_total,
+            latencies,
+        }
+    }
+}
+
+/// A failed lookup, as kept by a collector
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecentError {
+    /// When the lookup failed
+    pub at: SystemTime,
+    /// The name looked up
+    pub name: String,
+    /// The [`kind`](IpaddrConversionError::kind) of the error
+    pub kind: &'static str,
+    /// The error, as displayed
+    pub error: String,
+}
+
+/// Totals shared by the resolvers recording into them
+#[derive(Debug)]
+pub struct StatsCollector {
+    // since the collector was created, and since the last snapshot
+    periods: Mutex<(Period, Period)>,
+    window: Mutex<LatencyHistogram>,
+    recent: Mutex<VecDeque<RecentError>>,
+    breaker: Mutex<Option<Arc<CircuitBreaker>>>,
+}
+
+impl Default for StatsCollector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StatsCollector {
+    /// New collector, with nothing recorded
+    pub fn new() -> Self {
+        Self {
+            periods: Mutex::new((Period::new(), Period::new())),
+            window: Mutex::new(LatencyHistogram::new(DEFAULT_LATENCY_WINDOW)),
+            recent: Mutex::new(VecDeque::new()),
+            breaker: Mutex::new(None),
+        }
+    }
+
+    /// Keep latencies over the last `window` in the histogram, in place of
+    /// the [`DEFAULT_LATENCY_WINDOW`], returning the updated collector
+    pub fn with_latency_window(self, window: Duration) -> Self {
+        Self {
+            window: Mutex::new(LatencyHistogram::new(window)),
+            ..self
+        }
+    }
+
+    fn update<F: Fn(&mut Period)>(&self, update: F) {
+        let mut periods = self
+            .periods
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        update(&mut periods.0);
+        update(&mut periods.1);
+    }
+
+    /// Record a query which took `latency`, with its result
+    pub fn record_query<T>(&self, latency: Duration, result: &Result<T, IpaddrConversionError>) {
+        let error = result.as_ref().err().map(IpaddrConversionError::kind);
+        self.update(|period| period.record_query(latency, error));
+        self.window
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .record(latency);
+    }
+
+    /// The latencies of the queries within the sliding window
+    pub fn latency_histogram(&self) -> LatencyHistogram {
+        self.window
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// The latency below which `percentile` percent of the queries within
+    /// the sliding window finished, or None if there were none
+    pub fn recent_latency(&self, percentile: f64) -> Option<Duration> {
+        self.window
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .percentile(percentile)
+    }
+
+    /// Keep the failure of the lookup of `name`, dropping the oldest once
+    /// [`MAX_RECENT_ERRORS`] are kept
+    pub fn record_error(&self, name: &str, err: &IpaddrConversionError) {
+        let mut recent = self
+            .recent
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if recent.len() == MAX_RECENT_ERRORS {
+            recent.pop_front();
+        }
+        recent.push_back(RecentError {
+            at: SystemTime::now(),
+            name: name.to_string(),
+            kind: err.kind(),
+            error: err.to_string(),
+        });
+    }
+
+    /// The failures kept, oldest first
+    pub fn recent_errors(&self) -> Vec<RecentError> {
+        let recent = self
+            .recent
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        recent.iter().cloned().collect()
+    }
+
+    /// Record a cache lookup
+    pub fn record_cache(&self, hit: bool) {
+        self.update(|period| {
+            if hit {
+                period.cache_hits += 1;
+            } else {
+                period.cache_misses += 1;
+            }
+        });
+    }
+
+    /// Record a query which skipped a nameserver, its circuit being open
+    pub fn record_skipped_server(&self) {
+        self.update(|period| period.servers_skipped += 1);
+    }
+
+    /// Include the health of the nameservers `breaker` has seen in the
+    /// figures taken from now on
+    pub(crate) fn report_health(&self, breaker: Arc<CircuitBreaker>) {
+        *self
+            .breaker
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(breaker);
+    }
+
+    fn with_health(&self, mut stats: ResolverStats) -> ResolverStats {
+        let breaker = self
+            .breaker
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(ref breaker) = *breaker {
+            stats.servers = breaker.health();
+        }
+        stats
+    }
+
+    /// Figures since the collector was created
+    pub fn stats(&self) -> ResolverStats {
+        let periods = self
+            .periods
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        self.with_health(periods.0.stats())
+    }
+
+    /// Figures since the last snapshot, or since the collector was created
+    /// for the first, starting a new period
+    pub fn snapshot(&self) -> ResolverStats {
+        let mut periods = self
+            .periods
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        self.with_health(std::mem::replace(&mut periods.1, Period::new()).stats())
+    }
+}
+
+/// Resolver recording statistics about the lookups of another resolver.
+///
+/// Clones record into the same collector.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::stats::StatsResolver;
+/// use ipaddr_from_str::{Resolve, StaticResolver};
+///
+/// let resolver = StatsResolver::new(StaticResolver::new());
+/// assert!(resolver.resolve("10.0.0.1").is_ok());
+/// assert!(resolver.resolve("missing.example").is_err());
+/// let stats = resolver.stats();
+/// assert_eq!(stats.queries, 2);
+/// assert_eq!(stats.errors["NotFound"], 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct StatsResolver<R> {
+    resolver: R,
+    collector: Arc<StatsCollector>,
+}
+
+impl<R: Resolve> StatsResolver<R> {
+    /// Record the lookups of `resolver` in a new collector
+    pub fn new(resolver: R) -> Self {
+        Self::with_collector(resolver, Arc::new(StatsCollector::new()))
+    }
+
+    /// Record the lookups of `resolver` in `collector`, which may be shared
+    /// with a cache inside `resolver`
+    pub fn with_collector(resolver: R, collector: Arc<StatsCollector>) -> Self {
+        Self {
+            resolver,
+            collector,
+        }
+    }
+
+    /// The collector being recorded into
+    pub fn collector(&self) -> &Arc<StatsCollector> {
+        &self.collector
+    }
+
+    /// Figures since the resolver was created
+    pub fn stats(&self) -> ResolverStats {
+        self.collector.stats()
+    }
+
+    /// Figures since the last snapshot, starting a new period
+    pub fn snapshot(&self) -> ResolverStats {
+        self.collector.snapshot()
+    }
+
+    /// The figures and recent failures, for debugging
+    pub fn debug_dump(&self) -> DebugDump {
+        DebugDump::new().with_stats(&self.collector)
+    }
+}
+
+impl<R: Resolve> Resolve for StatsResolver<R> {
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        let started = Instant::now();
+        let result = self.resolver.resolve(hostname_or_address);
+        self.collector.record_query(started.elapsed(), &result);
+        if let Err(ref err) = result {
+            self.collector.record_error(hostname_or_address, err);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::CachingResolver;
+    use crate::resolver::StaticResolver;
+
+    #[test]
+    fn counts_cache_hits_through_a_shared_collector() {
+        let collector = Arc::new(StatsCollector::new());
+        let cache = CachingResolver::new(
+            StaticResolver::new().with_entry("db", vec!["10.0.0.1".parse().unwrap()]),
+            Duration::from_secs(60),
+        )
+        .with_stats(collector.clone());
+        let resolver = StatsResolver::with_collector(cache, collector);
+        for _ in 0..4 {
+            resolver.resolve("db").unwrap();
+        }
+        let stats = resolver.stats();
+        assert_eq!(
+            (stats.queries, stats.cache_hits, stats.cache_misses),
+            (4, 3, 1)
+        );
+        assert_eq!(stats.cache_hit_ratio(), Some(0.75));
+        assert!(stats.latency_percentile(50.0).is_some());
+    }
+    #[test]
+    fn snapshots_cover_the_period_since_the_last() {
+        let resolver = StatsResolver::new(StaticResolver::new());
+        let _ = resolver.resolve("missing");
+        assert_eq!(resolver.snapshot().error_count(), 1);
+        let _ = resolver.resolve("10.0.0.1");
+        let second = resolver.snapshot();
+        assert_eq!((second.queries, second.error_count()), (1, 0));
+        assert_eq!(resolver.stats().queries, 2);
+        assert_eq!(second.cache_hit_ratio(), None);
+    }
+    #[test]
+    fn recent_latency_comes_from_the_windowed_histogram() {
+        let resolver = StatsResolver::new(StaticResolver::new());
+        let _ = resolver.resolve("10.0.0.1");
+        assert!(resolver.collector().recent_latency(99.0).is_some());
+        let windowed = StatsCollector::new().with_latency_window(Duration::from_secs(5));
+        assert_eq!(
+            windowed.latency_histogram().window(),
+            Duration::from_secs(5)
+        );
+        assert_eq!(windowed.recent_latency(50.0), None);
+    }
+}
@@ -0,0 +1,417 @@
+//! Load testing a resolver.
+//!
+//! [`run`] resolves a set of names at a fixed rate for a fixed time and
+//! reports latency percentiles and the error rate, to check a resolver's
+//! capacity before rolling out a change which adds load to it.
+//! [`run_with_ttls`] queries the nameservers directly instead, also
+//! reporting the TTLs they answer with and how often their cached copies
+//! expire under the load. The `ipaddr-from-str stress` command wraps both.
+use crate::direct::DirectResolver;
+use crate::dns::RecordType;
+use crate::errors::IpaddrConversionError;
+use crate::resolver::Resolve;
+use std::convert::TryFrom;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The most lookups one run may make, so that a mistyped rate or duration
+/// cannot exhaust memory keeping their latencies
+pub const MAX_LOOKUPS: usize = 10_000_000;
+
+/// What to resolve, and how hard.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StressConfig {
+    names: Vec<String>,
+    qps: f64,
+    duration: Duration,
+    workers: usize,
+}
+
+impl StressConfig {
+    /// Resolve `names` in turn at `qps` lookups per second, for 10 seconds
+    /// using 8 worker threads by default
+    pub fn new<I, S>(names: I, qps: f64) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            names: names.into_iter().map(Into::into).collect(),
+            qps,
+            duration: Duration::from_secs(10),
+            workers: 8,
+        }
+    }
+
+    /// Set how long to run for, returning the updated config
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Set how many lookups may be in flight at once, returning the updated
+    /// config. When every worker is busy the rate falls below `qps`, which
+    /// the report shows as a lower achieved rate. A run needs at least one.
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers;
+        self
+    }
+}
+
+/// Results of a load test.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StressReport {
+    /// Lookups attempted
+    pub total: usize,
+    /// Lookups which failed
+    pub errors: usize,
+    /// Time from the first lookup starting to the last finishing
+    pub elapsed: Duration,
+    /// Shortest TTL answered, in seconds, when the lookups reported them
+    pub min_ttl: Option<u32>,
+    /// Longest TTL answered, in seconds, when the lookups reported them
+    pub max_ttl: Option<u32>,
+    /// Lookups answered with a longer TTL than the one before for the same
+    /// name: the nameserver's cached copy had expired and it fetched the
+    /// records afresh
+    pub refreshes: usize,
+    /// Latency of every lookup, successful or not, sorted ascending
+    latencies: Vec<Duration>,
+}
+
+impl StressReport {
+    /// The fraction of lookups which failed, from 0 to 1
+    pub fn error_rate(&self) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            self.errors as f64 / self.total as f64
+        }
+    }
+
+    /// The lookups completed per second
+    pub fn achieved_qps(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs == 0.0 {
+            0.0
+        } else {
+            self.total as f64 / secs
+        }
+    }
+
+    /// The latency below which `percentile` percent of lookups finished, by
+    /// the nearest rank method. None if nothing was resolved.
+    pub fn latency_percentile(&self, percentile: f64) -> Option<Duration> {
+        if self.latencies.is_empty() {
+            return None;
+        }
+        let rank = (percentile.clamp(0.0, 100.0) / 100.0 * self.latencies.len() as f64).ceil();
+        let index = (rank as usize).clamp(1, self.latencies.len()) - 1;
+        Some(self.latencies[index])
+    }
+}
+
+impl fmt::Display for StressReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "lookups {} errors {} ({:.2}%) rate {:.1}/s",
+            self.total,
+            self.errors,
+            self.error_rate() * 100.0,
+            self.achieved_qps()
+        )?;
+        let ms = |p| {
+            self.latency_percentile(p)
+                .map_or(0.0, |d| d.as_secs_f64() * 1000.0)
+        };
+        write!(
+            f,
+            "latency ms p50 {:.2} p90 {:.2} p99 {:.2} max {:.2}",
+            ms(50.0),
+            ms(90.0),
+            ms(99.0),
+            ms(100.0)
+        )?;
+        if let (Some(min), Some(max)) = (self.min_ttl, self.max_ttl) {
+            write!(
+                f,
+                "\nttl s min {} max {} refreshes {}",
+                min, max, self.refreshes
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// One lookup made during a run
+struct Sample {
+    /// Position in the schedule
+    index: usize,
+    latency: Duration,
+    ok: bool,
+    /// Shortest TTL among the answers, if the lookup reports TTLs
+    ttl: Option<u32>,
+}
+
+/// Resolve the configured names through `resolver` at the configured rate,
+/// blocking until the run is over.
+///
+/// Lookups are scheduled at fixed intervals from the start, so a slow lookup
+/// does not delay the ones after it unless every worker is busy. A lookup
+/// which panics counts as failed, and its worker carries on with the rest.
+/// A run may make at most [`MAX_LOOKUPS`] lookups.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::stress::{run, StressConfig};
+/// use ipaddr_from_str::SystemResolver;
+/// use std::time::Duration;
+///
+/// let config = StressConfig::new(vec!["127.0.0.1"], 100.0).with_duration(Duration::from_millis(100));
+/// let report = run(&SystemResolver, &config).unwrap();
+/// assert_eq!(report.errors, 0);
+/// ```
+pub fn run<R>(resolver: &R, config: &StressConfig) -> Result<StressReport, IpaddrConversionError>
+where
+    R: Resolve + Clone + Send + 'static,
+{
+    let resolver = resolver.clone();
+    drive(config, move |name| resolver.resolve(name).map(|_| None))
+}
+
+/// [`run`], querying the A records of the names straight from `resolver`'s
+/// nameservers so that the report carries the TTLs they answer with and
+/// counts the [`refreshes`](StressReport::refreshes) of their caches.
+pub fn run_with_ttls(
+    resolver: &DirectResolver,
+    config: &StressConfig,
+) -> Result<StressReport, IpaddrConversionError> {
+    let resolver = resolver.clone();
+    drive(config, move |name| {
+        let response = resolver.query(name, RecordType::A)?;
+        Ok(response.answers.iter().map(|record| record.ttl).min())
+    })
+}
+
+/// Make the lookups of a run through `lookup`, which gives the shortest TTL
+/// answered if it knows it
+fn drive<F>(config: &StressConfig, lookup: F) -> Result<StressReport, IpaddrConversionError>
+where
+    F: Fn(&str) -> Result<Option<u32>, IpaddrConversionError> + Clone + Send + 'static,
+{
+    if config.names.is_empty() || !config.qps.is_finite() || config.qps <= 0.0 {
+        return Err(IpaddrConversionError::InvalidInput(
+            "stress test needs at least one name and a positive rate".to_string(),
+        ));
+    }
+    if config.workers == 0 {
+        return Err(IpaddrConversionError::InvalidInput(
+            "stress test needs at least one worker".to_string(),
+        ));
+    }
+    let lookups = (config.duration.as_secs_f64() * config.qps).ceil();
+    if lookups > MAX_LOOKUPS as f64 {
+        return Err(IpaddrConversionError::InvalidInput(format!(
+            "stress test would make {} lookups, more than the {} allowed",
+            lookups, MAX_LOOKUPS
+        )));
+    }
+    let total = lookups as usize;
+    let interval = Duration::try_from_secs_f64(1.0 / config.qps).map_err(|_| {
+        IpaddrConversionError::InvalidInput(format!("stress test rate {} is too low", config.qps))
+    })?;
+    let next = Arc::new(AtomicUsize::new(0));
+    let samples = Arc::new(Mutex::new(Vec::new()));
+    let names = Arc::new(config.names.clone());
+    let start = Instant::now();
+    let workers: Vec<_> = (0..config.workers.min(total.max(1)))
+        .map(|_| {
+            let lookup = lookup.clone();
+            let next = next.clone();
+            let samples = samples.clone();
+            let names = names.clone();
+            thread::spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                if index >= total {
+                    return;
+                }
+                let due = u32::try_from(index)
+                    .ok()
+                    .and_then(|index| interval.checked_mul(index))
+                    .and_then(|offset| start.checked_add(offset));
+                // a lookup due beyond what an Instant can hold never comes up
+                let due = match due {
+                    Some(due) => due,
+                    None => return,
+                };
+                if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                    thread::sleep(wait);
+                }
+                let began = Instant::now();
+                let name = &names[index % names.len()];
+                let result = panic::catch_unwind(AssertUnwindSafe(|| lookup(name)));
+                let sample = Sample {
+                    index,
+                    latency: began.elapsed(),
+                    ok: matches!(result, Ok(Ok(_))),
+                    ttl: result.ok().and_then(Result::ok).flatten(),
+                };
+                samples
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .push(sample);
+            })
+        })
+        .collect();
+    // the lookups are caught, but count a worker dying anyway as a failure
+    let panicked = workers
+        .into_iter()
+        .map(|worker| worker.join())
+        .filter(Result::is_err)
+        .count();
+    let elapsed = start.elapsed();
+    let mut samples = samples
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    samples.sort_by_key(|sample| sample.index);
+    let mut latencies: Vec<Duration> = samples.iter().map(|sample| sample.latency).collect();
+    latencies.sort();
+    let ttls = samples.iter().filter_map(|sample| sample.ttl);
+    let mut last_ttls = vec![None; names.len()];
+    let mut refreshes = 0;
+    for sample in samples.iter() {
+        if let Some(ttl) = sample.ttl {
+            let last = &mut last_ttls[sample.index % names.len()];
+            if last.is_some_and(|last| ttl > last) {
+                refreshes += 1;
+            }
+            *last = Some(ttl);
+        }
+    }
+    Ok(StressReport {
+        total: samples.len() + panicked,
+        errors: samples.iter().filter(|sample| !sample.ok).count() + panicked,
+        elapsed,
+        min_ttl: ttls.clone().min(),
+        max_ttl: ttls.max(),
+        refreshes,
+        latencies,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::direct::tests::{answer, config_for, spawn_udp_server};
+    use crate::dns::{self, RData};
+    use crate::resolver::StaticResolver;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    /// Resolves every name but "boom.example", on which it panics
+    #[derive(Clone)]
+    struct Panicking;
+
+    impl Resolve for Panicking {
+        fn resolve(&self, hostname: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+            assert_ne!(hostname, "boom.example");
+            Ok(vec![Ipv4Addr::LOCALHOST.into()])
+        }
+    }
+
+    #[test]
+    fn counts_errors_and_keeps_the_rate() {
+        let resolver =
+            StaticResolver::new().with_entry("up.example", vec!["10.0.0.1".parse().unwrap()]);
+        let config = StressConfig::new(vec!["up.example", "down.example"], 200.0)
+            .with_duration(Duration::from_millis(100));
+        let report = run(&resolver, &config).unwrap();
+        assert_eq!(report.total, 20);
+        assert_eq!(report.errors, 10);
+        assert!((report.error_rate() - 0.5).abs() < f64::EPSILON);
+        // the last lookup is not due until 95ms in
+        assert!(report.elapsed >= Duration::from_millis(90));
+        assert!(run(&resolver, &StressConfig::new(Vec::<String>::new(), 1.0)).is_err());
+    }
+    #[test]
+    fn runs_which_cannot_be_scheduled_are_rejected() {
+        let resolver = StaticResolver::new();
+        let rejected = |config: StressConfig| {
+            matches!(
+                run(&resolver, &config),
+                Err(IpaddrConversionError::InvalidInput(_))
+            )
+        };
+        assert!(rejected(
+            StressConfig::new(vec!["up.example"], 1.0).with_workers(0)
+        ));
+        assert!(rejected(
+            StressConfig::new(vec!["up.example"], 1e30).with_duration(Duration::from_secs(1))
+        ));
+        assert!(rejected(StressConfig::new(vec!["up.example"], 1e-300)));
+    }
+    #[test]
+    fn panicking_lookups_count_as_errors() {
+        let config = StressConfig::new(vec!["up.example", "boom.example"], 200.0)
+            .with_duration(Duration::from_millis(50))
+            .with_workers(2);
+        let report = run(&Panicking, &config).unwrap();
+        assert_eq!(report.total, 10);
+        assert_eq!(report.errors, 5);
+    }
+    #[test]
+    fn ttls_and_cache_refreshes_are_reported() {
+        let ttls = Arc::new(Mutex::new(vec![400, 200, 300]));
+        let server = spawn_udp_server(move |request| {
+            let mut response = answer(
+                request,
+                dns::RCODE_NOERROR,
+                vec![RData::A(Ipv4Addr::new(192, 0, 2, 1))],
+            );
+            response.answers[0].ttl = ttls.lock().unwrap().pop().unwrap_or(100);
+            Some(response)
+        });
+        let resolver = DirectResolver::new(config_for(vec![server]));
+        let config = StressConfig::new(vec!["www.example.com"], 100.0)
+            .with_duration(Duration::from_millis(30))
+            .with_workers(1);
+        let report = run_with_ttls(&resolver, &config).unwrap();
+        assert_eq!(report.total, 3);
+        assert_eq!(report.errors, 0);
+        assert_eq!(report.min_ttl, Some(200));
+        assert_eq!(report.max_ttl, Some(400));
+        assert_eq!(report.refreshes, 1);
+        assert!(report
+            .to_string()
+            .ends_with("ttl s min 200 max 400 refreshes 1"));
+        assert_eq!(run(&StaticResolver::new(), &config).unwrap().min_ttl, None);
+    }
+    #[test]
+    fn percentiles_use_nearest_rank() {
+        let report = StressReport {
+            total: 4,
+            errors: 0,
+            elapsed: Duration::from_secs(1),
+            latencies: (1..=4).map(Duration::from_millis).collect(),
+            ..StressReport::default()
+        };
+        assert_eq!(
+            report.latency_percentile(50.0),
+            Some(Duration::from_millis(2))
+        );
+        assert_eq!(
+            report.latency_percentile(99.0),
+            Some(Duration::from_millis(4))
+        );
+        assert_eq!(
+            report.latency_percentile(0.0),
+            Some(Duration::from_millis(1))
+        );
+        assert_eq!(StressReport::default().latency_percentile(50.0), None);
+    }
+}
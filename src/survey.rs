@@ -0,0 +1,461 @@
+//! Sweeping large blocks over many runs.
+//!
+//! A [`Survey`] probes every host of a block too large for one [`sweep`],
+//! in an order shuffled by a seed so that no one subnet is probed address
+//! after address, and no faster than a rate limit. It keeps how far it has
+//! got and what it found, and saves them to a checkpoint file, so a survey
+//! stopped or killed partway can be loaded and carried on where it left
+//! off. Enabled by the `probe` feature.
+//!
+//! The order is the permutation `i -> (offset + stride * i) mod hosts`
+//! with `stride` coprime to the number of hosts, which visits each host
+//! exactly once and is fixed by the two numbers, so a checkpoint need only
+//! hold them and a count of the targets done.
+//!
+//! [`sweep`]: crate::sweep::sweep
+use crate::address::Address;
+use crate::cancel::CancellationToken;
+use crate::cidr::Cidr;
+use crate::direct::random_u64;
+use crate::errors::IpaddrConversionError;
+use crate::portscan::Pacer;
+use crate::seeded::hash_parts;
+use crate::sweep::{icmp_socket, probe_addr, Alive, Probe};
+use std::fs;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// The most hosts one survey probes, which keeps the order's arithmetic
+/// within 128 bits
+pub const MAX_SURVEY_HOSTS: u128 = 1 << 64;
+
+const FILE_HEADER: &str = "# ipaddr-from-str survey v1";
+
+fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        let rest = a % b;
+        a = b;
+        b = rest;
+    }
+    a
+}
+
+/// `probe` as written in a checkpoint
+fn probe_text(probe: Probe) -> String {
+    match probe {
+        Probe::Icmp => "icmp".to_string(),
+        Probe::Tcp(port) => format!("tcp {}", port),
+        Probe::IcmpOrTcp(port) => format!("icmp-or-tcp {}", port),
+    }
+}
+
+/// How a survey is run
+#[derive(Debug, Clone, PartialEq)]
+pub struct SurveyConfig {
+    concurrency: usize,
+    timeout: Duration,
+    rate_limit: Option<f64>,
+    batch: usize,
+    checkpoint: Option<PathBuf>,
+}
+
+impl Default for SurveyConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 64,
+            timeout: Duration::from_secs(1),
+            rate_limit: None,
+            batch: 1024,
+            checkpoint: None,
+        }
+    }
+}
+
+impl SurveyConfig {
+    /// Probe 64 hosts at once, waiting a second for each, without a rate
+    /// limit or checkpoint file, recording progress every 1024 hosts by
+    /// default
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set how many hosts are probed at once, returning the updated config
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Set how long each probe waits for an answer, returning the updated
+    /// config
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set the most probes started a second, returning the updated config
+    pub fn with_rate_limit(mut self, rate_limit: Option<f64>) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    /// Set how many hosts are probed between checkpoints, returning the
+    /// updated config. A survey stopped partway through a batch probes the
+    /// whole batch again when resumed.
+    pub fn with_batch(mut self, batch: usize) -> Self {
+        self.batch = batch.max(1);
+        self
+    }
+
+    /// Set the file the survey is saved to after each batch, returning the
+    /// updated config
+    pub fn with_checkpoint<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.checkpoint = Some(path.into());
+        self
+    }
+}
+
+/// A sweep of a block, in a shuffled order, which can be stopped and
+/// resumed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Survey {
+    block: Cidr,
+    probe: Probe,
+    offset: u128,
+    stride: u128,
+    done: u128,
+    alive: Vec<Alive>,
+}
+
+impl Survey {
+    /// A survey of `block` by `probe` in an order of its own
+    pub fn new(block: Cidr, probe: Probe) -> Result<Self, IpaddrConversionError> {
+        Self::with_seed(block, probe, random_u64())
+    }
+
+    /// A survey of `block` by `probe` in the order `seed` picks
+    ///
+    /// # Returns
+    /// The survey, none of it yet done, or an InvalidInput error if the
+    /// block has more than [`MAX_SURVEY_HOSTS`] hosts
+    pub fn with_seed(block: Cidr, probe: Probe, seed: u64) -> Result<Self, IpaddrConversionError> {
+        let count = block.host_count();
+        if count > MAX_SURVEY_HOSTS {
+            return Err(IpaddrConversionError::InvalidInput(format!(
+                "{} has {} hosts, more than the {} a survey probes",
+                block, count, MAX_SURVEY_HOSTS
+            )));
+        }
+        let hash = hash_parts(&[b"survey", &seed.to_be_bytes()]);
+        let modulus = count.max(1);
+        // a stride near the golden section of the block puts each target
+        // far from the last
+        let mut stride =
+            ((count as f64 * 0.618) as u128 + (hash >> 64) % (count / 16 + 1)) % modulus;
+        while gcd(stride, modulus) != 1 {
+            stride = (stride + 1) % modulus;
+        }
+        Ok(Self {
+            block,
+            probe,
+            offset: hash % modulus,
+            stride,
+            done: 0,
+            alive: Vec::new(),
+        })
+    }
+
+    pub fn block(&self) -> Cidr {
+        self.block
+    }
+
+    pub fn probe(&self) -> Probe {
+        self.probe
+    }
+
+    /// The number of hosts surveyed
+    pub fn len(&self) -> u128 {
+        self.block.host_count()
+    }
+
+    /// Whether the block has no hosts to survey
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of hosts probed so far
+    pub fn done(&self) -> u128 {
+        self.done
+    }
+
+    /// Whether every host has been probed
+    pub fn is_finished(&self) -> bool {
+        self.done >= self.len()
+    }
+
+    /// The hosts found alive so far, in address order
+    pub fn alive(&self) -> &[Alive] {
+        &self.alive
+    }
+
+    /// The host probed `index`th, None past the last
+    pub fn target(&self, index: u128) -> Option<IpAddr> {
+        let count = self.len();
+        if index >= count {
+            return None;
+        }
+        let host = Address::new(self.block.first_host()).to_u128()
+            + (self.offset + self.stride * index) % count;
+        Some(match self.block.addr() {
+            IpAddr::V4(_) => Address::from_u32(host as u32).ip(),
+            IpAddr::V6(_) => Address::from_u128(host).ip(),
+        })
+    }
+
+    /// Probe the hosts not yet done, a batch at a time, until every one is
+    /// or `cancel` is cancelled
+    ///
+    /// # Parameters
+    ///
+    /// * `config` - the pace of the probes, and where to checkpoint
+    /// * `cancel` - stops the survey; the batch underway is not counted
+    ///
+    /// # Returns
+    /// Whether the survey is finished, or an InvalidInput error if the rate
+    /// limit is not positive, the error opening an ICMP socket for a survey
+    /// by [`Probe::Icmp`] alone, or the error saving a checkpoint
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use ipaddr_from_str::cancel::CancellationToken;
+    /// use ipaddr_from_str::survey::{Survey, SurveyConfig};
+    /// use ipaddr_from_str::sweep::Probe;
+    ///
+    /// let path = "survey.txt";
+    /// let mut survey = Survey::load(path)
+    ///     .or_else(|_| Survey::new("10.0.0.0/8".parse().unwrap(), Probe::Tcp(443)))
+    ///     .unwrap();
+    /// let config = SurveyConfig::new().with_rate_limit(Some(1000.0)).with_checkpoint(path);
+    /// survey.run(&config, &CancellationToken::new()).unwrap();
+    /// println!("{} hosts alive", survey.alive().len());
+    /// ```
+    pub fn run(
+        &mut self,
+        config: &SurveyConfig,
+        cancel: &CancellationToken,
+    ) -> Result<bool, IpaddrConversionError> {
+        let pacer = Pacer::limiting(config.rate_limit)?;
+        if self.probe == Probe::Icmp && !self.is_finished() {
+            // fail once, up front, rather than find no host alive
+            icmp_socket(self.block.addr())?;
+        }
+        while !self.is_finished() && !cancel.is_cancelled() {
+            let batch = (self.len() - self.done).min(config.batch as u128) as usize;
+            let next = AtomicUsize::new(0);
+            let alive = Mutex::new(Vec::new());
+            thread::scope(|scope| {
+                for _ in 0..config.concurrency.clamp(1, batch) {
+                    scope.spawn(|| loop {
+                        let index = next.fetch_add(1, Ordering::SeqCst);
+                        if index >= batch || cancel.is_cancelled() {
+                            break;
+                        }
+                        let addr = match self.target(self.done + index as u128) {
+                            Some(addr) => addr,
+                            None => break,
+                        };
+                        if let Some(ref pacer) = pacer {
+                            pacer.wait();
+                        }
+                        if let Some(rtt) = probe_addr(addr, self.probe, config.timeout) {
+                            alive
+                                .lock()
+                                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                                .push(Alive { addr, rtt });
+                        }
+                    });
+                }
+            });
+            if cancel.is_cancelled() {
+                break;
+            }
+            self.done += batch as u128;
+            self.alive.extend(
+                alive
+                    .into_inner()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()),
+            );
+            self.alive.sort_by_key(|host| host.addr);
+            self.alive.dedup_by_key(|host| host.addr);
+            if let Some(ref path) = config.checkpoint {
+                self.save(path)?;
+            }
+        }
+        Ok(self.is_finished())
+    }
+
+    /// The survey as the lines of its checkpoint
+    pub fn to_lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("block {}", self.block),
+            format!("probe {}", probe_text(self.probe)),
+            format!("order {} {}", self.offset, self.stride),
+            format!("done {}", self.done),
+        ];
+        lines.extend(
+            self.alive
+                .iter()
+                .map(|host| format!("alive {} {}", host.addr, host.rtt.as_micros())),
+        );
+        lines
+    }
+
+    /// Read a survey from the lines of its checkpoint
+    ///
+    /// # Returns
+    /// The survey, or an InvalidInput error naming the first line which
+    /// cannot be read, or the line missing
+    pub fn parse(text: &str) -> Result<Self, IpaddrConversionError> {
+        let mut block = None;
+        let mut probe = None;
+        let mut order = None;
+        let mut done = None;
+        let mut alive = Vec::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad = |why: &str| {
+                IpaddrConversionError::InvalidInput(format!("line {}: {}", idx + 1, why))
+            };
+            let number = |text: &str| text.parse::<u128>().map_err(|_| bad("bad number"));
+            let port = |text: &str| text.parse::<u16>().map_err(|_| bad("bad port"));
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                ["block", cidr] => {
+                    block = Some(cidr.parse::<Cidr>().map_err(|_| bad("bad block"))?)
+                }
+                ["probe", "icmp"] => probe = Some(Probe::Icmp),
+                ["probe", "tcp", tcp] => probe = Some(Probe::Tcp(port(tcp)?)),
+                ["probe", "icmp-or-tcp", tcp] => probe = Some(Probe::IcmpOrTcp(port(tcp)?)),
+                ["order", offset, stride] => order = Some((number(offset)?, number(stride)?)),
+                ["done", count] => done = Some(number(count)?),
+                ["alive", addr, micros] => alive.push(Alive {
+                    addr: addr.parse().map_err(|_| bad("bad address"))?,
+                    rtt: Duration::from_micros(number(micros)? as u64),
+                }),
+                _ => return Err(bad("unrecognised line")),
+            }
+        }
+        let missing = |what: &str| IpaddrConversionError::InvalidInput(format!("no {} line", what));
+        let block = block.ok_or_else(|| missing("block"))?;
+        let (offset, stride) = order.ok_or_else(|| missing("order"))?;
+        let done = done.ok_or_else(|| missing("done"))?;
+        let count = block.host_count();
+        let modulus = count.max(1);
+        if count > MAX_SURVEY_HOSTS
+            || offset >= modulus
+            || stride >= modulus
+            || gcd(stride, modulus) != 1
+            || done > count
+        {
+            return Err(IpaddrConversionError::InvalidInput(format!(
+                "the order and progress do not fit {}",
+                block
+            )));
+        }
+        alive.sort_by_key(|host| host.addr);
+        Ok(Self {
+            block,
+            probe: probe.ok_or_else(|| missing("probe"))?,
+            offset,
+            stride,
+            done,
+            alive,
+        })
+    }
+
+    /// Write the survey to `path`, through a temporary file so that a
+    /// survey killed while saving leaves the last checkpoint whole
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), IpaddrConversionError> {
+        let path = path.as_ref();
+        let mut contents = String::from(FILE_HEADER);
+        contents.push('\n');
+        for line in self.to_lines() {
+            contents.push_str(&line);
+            contents.push('\n');
+        }
+        let tmp = path.with_extension("tmp");
+        fs::File::create(&tmp)?.write_all(contents.as_bytes())?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Read a survey saved to `path`
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, IpaddrConversionError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn orders_visit_every_host_once() {
+        let block: Cidr = "10.0.0.0/22".parse().unwrap();
+        let survey = Survey::with_seed(block, Probe::Icmp, 7).unwrap();
+        let targets: Vec<IpAddr> = (0..survey.len()).filter_map(|i| survey.target(i)).collect();
+        let unique: BTreeSet<_> = targets.iter().collect();
+        assert_eq!(unique.len(), 1022);
+        assert!(unique.iter().all(|addr| block.contains(**addr)));
+        // neighbours in the order are not neighbours in the block
+        let adjacent = targets
+            .windows(2)
+            .filter(|pair| {
+                let (a, b) = (
+                    Address::new(pair[0]).to_u128(),
+                    Address::new(pair[1]).to_u128(),
+                );
+                a.abs_diff(b) == 1
+            })
+            .count();
+        assert_eq!(adjacent, 0);
+        assert_eq!(survey.target(survey.len()), None);
+        assert_eq!(Survey::with_seed(block, Probe::Icmp, 7).unwrap(), survey);
+        let huge: Cidr = "2001:db8::/48".parse().unwrap();
+        assert!(Survey::with_seed(huge, Probe::Icmp, 7).is_err());
+    }
+    #[test]
+    fn surveys_resume_from_checkpoints() {
+        // every loopback address answers, refusing what nothing listens on
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let block: Cidr = "127.0.0.0/29".parse().unwrap();
+        let mut survey = Survey::with_seed(block, Probe::Tcp(port), 1).unwrap();
+        let path = std::env::temp_dir().join(format!("survey-{}.txt", std::process::id()));
+        let config = SurveyConfig::new().with_batch(4).with_checkpoint(&path);
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        assert!(!survey.run(&config, &cancel).unwrap());
+        assert_eq!(survey.done(), 0);
+        // a survey stopped after its first batch
+        let mut lines = survey.to_lines();
+        lines[3] = "done 4".to_string();
+        let mut resumed = Survey::parse(&lines.join("\n")).unwrap();
+        assert!(resumed.run(&config, &CancellationToken::new()).unwrap());
+        assert_eq!(resumed.alive().len(), 2);
+        let loaded = Survey::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.to_lines(), resumed.to_lines());
+        assert!(loaded.is_finished());
+        assert!(Survey::parse("block 127.0.0.0/29\nprobe icmp\norder 9 1\ndone 0").is_err());
+    }
+}
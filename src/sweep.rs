@@ -0,0 +1,283 @@
+//! Finding which addresses of a block have a host answering.
+//!
+//! [`sweep`] probes every host address of a block, a number of them at
+//! once, with ICMP echo requests, TCP connections, or both, and reports
+//! those which answered with their round trip times. Enabled by the `probe`
+//! feature.
+//!
+//! Echo requests go out over an unprivileged ping socket where the OS
+//! allows one, as macOS does and Linux does for the groups in
+//! `net.ipv4.ping_group_range`, and otherwise over a raw socket, which
+//! takes root or `CAP_NET_RAW`.
+use crate::address::Address;
+use crate::cidr::Cidr;
+use crate::errors::IpaddrConversionError;
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The most host addresses one sweep probes
+pub const MAX_SWEEP_HOSTS: u128 = 1 << 16;
+
+const ECHO_REQUEST_V4: u8 = 8;
+pub(crate) const ECHO_REPLY_V4: u8 = 0;
+const ECHO_REQUEST_V6: u8 = 128;
+pub(crate) const ECHO_REPLY_V6: u8 = 129;
+/// The payload of the echo requests sent
+const ECHO_PAYLOAD: &[u8] = b"ipaddr-from-str ";
+
+/// The sequence number of the next echo request, so concurrent requests
+/// to one address are told apart
+pub(crate) static SEQUENCE: AtomicU16 = AtomicU16::new(0);
+
+/// How an address is asked whether a host is there
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Probe {
+    /// An ICMP echo request
+    Icmp,
+    /// A TCP connection to the port, which a host answers whether it
+    /// accepts or refuses it
+    Tcp(u16),
+    /// An ICMP echo request, then a TCP connection to the port if that
+    /// went unanswered or ICMP sockets cannot be opened, for hosts which
+    /// drop echo requests
+    IcmpOrTcp(u16),
+}
+
+/// An address a host answered at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Alive {
+    pub addr: IpAddr,
+    /// How long the answer took
+    pub rtt: Duration,
+}
+
+/// The internet checksum of RFC 1071
+pub(crate) fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|pair| u32::from(u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)])))
+        .sum();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// A socket for ICMP, or ICMPv6, to `addr`: a ping socket where allowed,
+/// else a raw socket
+pub(crate) fn icmp_socket(addr: IpAddr) -> io::Result<Socket> {
+    let (domain, protocol) = match addr {
+        IpAddr::V4(_) => (Domain::ipv4(), Protocol::icmpv4()),
+        IpAddr::V6(_) => (Domain::ipv6(), Protocol::icmpv6()),
+    };
+    Socket::new(domain, Type::dgram(), Some(protocol))
+        .or_else(|_| Socket::new(domain, Type::raw(), Some(protocol)))
+}
+
+/// An echo request to `addr`. The kernel fills in the checksum of ICMPv6.
+pub(crate) fn echo_request(addr: IpAddr, sequence: u16) -> Vec<u8> {
+    let kind = match addr {
+        IpAddr::V4(_) => ECHO_REQUEST_V4,
+        IpAddr::V6(_) => ECHO_REQUEST_V6,
+    };
+    let mut packet = vec![kind, 0, 0, 0];
+    // a ping socket replaces the identifier with its own
+    packet.extend_from_slice(&(std::process::id() as u16).to_be_bytes());
+    packet.extend_from_slice(&sequence.to_be_bytes());
+    packet.extend_from_slice(ECHO_PAYLOAD);
+    if addr.is_ipv4() {
+        let sum = checksum(&packet);
+        packet[2..4].copy_from_slice(&sum.to_be_bytes());
+    }
+    packet
+}
+
+/// The ICMP message of a packet received, without the IPv4 header which
+/// raw sockets and macOS ping sockets leave on
+pub(crate) fn icmp_message(packet: &[u8]) -> &[u8] {
+    match packet.first() {
+        Some(first) if first >> 4 == 4 => packet
+            .get(usize::from(first & 0x0f) * 4..)
+            .unwrap_or_default(),
+        _ => packet,
+    }
+}
+
+/// The address of a socket address received from, if an internet one
+pub(crate) fn sender(from: &SockAddr) -> Option<IpAddr> {
+    from.as_inet()
+        .map(|v4| IpAddr::V4(*v4.ip()))
+        .or_else(|| from.as_inet6().map(|v6| IpAddr::V6(*v6.ip())))
+}
+
+/// Send an echo request to `addr`, returning how long the reply took, None
+/// if there was none within `timeout`
+fn ping(addr: IpAddr, timeout: Duration) -> io::Result<Option<Duration>> {
+    let socket = icmp_socket(addr)?;
+    let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+    let reply = match addr {
+        IpAddr::V4(_) => ECHO_REPLY_V4,
+        IpAddr::V6(_) => ECHO_REPLY_V6,
+    };
+    let started = Instant::now();
+    socket.send_to(
+        &echo_request(addr, sequence),
+        &SockAddr::from(SocketAddr::new(addr, 0)),
+    )?;
+    let mut buf = [0u8; 1500];
+    loop {
+        let left = match timeout.checked_sub(started.elapsed()) {
+            Some(left) if !left.is_zero() => left,
+            _ => return Ok(None),
+        };
+        socket.set_read_timeout(Some(left))?;
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Ok(None)
+            }
+            Err(err) => return Err(err),
+        };
+        // a raw socket is handed every ICMP message the host receives
+        let message = icmp_message(&buf[..len]);
+        if sender(&from) == Some(addr)
+            && message.len() >= 8
+            && message[0] == reply
+            && message[6..8] == sequence.to_be_bytes()
+        {
+            return Ok(Some(started.elapsed()));
+        }
+    }
+}
+
+/// Connect to `port` of `addr`, returning how long the answer took
+fn connect(addr: IpAddr, port: u16, timeout: Duration) -> Option<Duration> {
+    let started = Instant::now();
+    match TcpStream::connect_timeout(&SocketAddr::new(addr, port), timeout) {
+        Ok(_) => Some(started.elapsed()),
+        Err(err) if err.kind() == ErrorKind::ConnectionRefused => Some(started.elapsed()),
+        Err(_) => None,
+    }
+}
+
+/// How long `probe` of `addr` took to be answered, None if it was not
+pub(crate) fn probe_addr(addr: IpAddr, probe: Probe, timeout: Duration) -> Option<Duration> {
+    match probe {
+        Probe::Icmp => ping(addr, timeout).ok().flatten(),
+        Probe::Tcp(port) => connect(addr, port, timeout),
+        Probe::IcmpOrTcp(port) => ping(addr, timeout)
+            .ok()
+            .flatten()
+            .or_else(|| connect(addr, port, timeout)),
+    }
+}
+
+/// Probe every host address of `block`, returning those answered at
+///
+/// # Parameters
+///
+/// * `block` - the block to sweep; IPv4 network and broadcast addresses are
+///   skipped
+/// * `probe` - how each address is probed
+/// * `concurrency` - how many addresses are probed at once
+/// * `timeout` - how long each probe waits for an answer
+///
+/// # Returns
+/// The addresses answered at, in address order, or an InvalidInput error if
+/// the block has more than [`MAX_SWEEP_HOSTS`] hosts, or the error opening
+/// an ICMP socket for a sweep by [`Probe::Icmp`] alone
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::sweep::{sweep, Probe};
+/// use std::time::Duration;
+///
+/// let alive = sweep("192.168.1.0/24".parse().unwrap(), Probe::IcmpOrTcp(22), 64, Duration::from_millis(500)).unwrap();
+/// for host in alive {
+///     println!("{} answered in {:?}", host.addr, host.rtt);
+/// }
+/// ```
+pub fn sweep(
+    block: Cidr,
+    probe: Probe,
+    concurrency: usize,
+    timeout: Duration,
+) -> Result<Vec<Alive>, IpaddrConversionError> {
+    let count = block.host_count();
+    if count > MAX_SWEEP_HOSTS {
+        return Err(IpaddrConversionError::InvalidInput(format!(
+            "{} has {} hosts, more than the {} a sweep probes",
+            block, count, MAX_SWEEP_HOSTS
+        )));
+    }
+    if probe == Probe::Icmp {
+        // fail once, up front, rather than find no host alive
+        icmp_socket(block.addr())?;
+    }
+    let first = Address::new(block.first_host()).to_u128();
+    let hosts: Vec<IpAddr> = (0..count)
+        .map(|offset| match block.addr() {
+            IpAddr::V4(_) => Address::from_u32((first + offset) as u32).ip(),
+            IpAddr::V6(_) => Address::from_u128(first + offset).ip(),
+        })
+        .collect();
+    let next = AtomicUsize::new(0);
+    let alive = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..concurrency.clamp(1, hosts.len().max(1)) {
+            scope.spawn(|| {
+                while let Some(&addr) = hosts.get(next.fetch_add(1, Ordering::SeqCst)) {
+                    if let Some(rtt) = probe_addr(addr, probe, timeout) {
+                        alive
+                            .lock()
+                            .unwrap_or_else(|poisoned| poisoned.into_inner())
+                            .push(Alive { addr, rtt });
+                    }
+                }
+            });
+        }
+    });
+    let mut alive = alive
+        .into_inner()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    alive.sort_by_key(|host| host.addr);
+    Ok(alive)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn echo_requests_checksum_to_zero() {
+        let addr: IpAddr = "192.0.2.1".parse().unwrap();
+        let request = echo_request(addr, 7);
+        assert_eq!(request[0], ECHO_REQUEST_V4);
+        assert_eq!(request[6..8], [0, 7]);
+        assert_eq!(checksum(&request), 0);
+        // an IPv4 header of five words is stripped, a bare message kept
+        let mut packet = vec![0x45; 20];
+        packet.extend_from_slice(&request);
+        assert_eq!(icmp_message(&packet), &request[..]);
+        assert_eq!(icmp_message(&request), &request[..]);
+    }
+    #[test]
+    fn tcp_sweeps_find_answering_hosts() {
+        // every loopback address answers, refusing what nothing listens on
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let block: Cidr = "127.0.0.0/29".parse().unwrap();
+        let alive = sweep(block, Probe::Tcp(port), 3, Duration::from_secs(1)).unwrap();
+        let addrs: Vec<String> = alive.iter().map(|host| host.addr.to_string()).collect();
+        assert_eq!(addrs.len(), 6);
+        assert_eq!(addrs[0], "127.0.0.1");
+        let huge: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert!(sweep(huge, Probe::Tcp(port), 1, Duration::from_secs(1)).is_err());
+    }
+}
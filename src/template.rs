@@ -0,0 +1,224 @@
+//! Expanding hostname templates into lists of names.
+//!
+//! A template is literal text with any number of groups, each expanding to a
+//! set of alternatives:
+//!
+//! * `[1-20]` numbers, `[01-20]` numbers zero padded to the width of the
+//!   start, and `[1-3,7]` lists of numbers and ranges
+//! * `{a,b,c}` literal alternatives
+//!
+//! Every combination of the groups is produced, varying the last group
+//! fastest, so `rack{a,b}-sw[1-2]` yields `racka-sw1`, `racka-sw2`,
+//! `rackb-sw1` and `rackb-sw2`.
+use crate::batch::{resolve_all_within, PartialResolution};
+use crate::errors::IpaddrConversionError;
+use crate::parse::ParseError;
+use crate::resolver::Resolve;
+use std::time::Duration;
+
+/// Most names a template may expand to, so a typo such as `[1-1000000]`
+/// fails rather than exhausting memory
+pub const MAX_EXPANSION: usize = 65536;
+
+/// Parse a number of a `[...]` group starting at `offset`, returning it, the
+/// width to pad to and the offset after it
+fn number(pattern: &str, offset: usize) -> Result<(u64, usize, usize), ParseError> {
+    let digits = pattern[offset..]
+        .bytes()
+        .take_while(u8::is_ascii_digit)
+        .count();
+    if digits == 0 {
+        return Err(ParseError::new(offset, "digit"));
+    }
+    let text = &pattern[offset..offset + digits];
+    let value = text
+        .parse::<u64>()
+        .map_err(|_| ParseError::new(offset, "smaller number"))?;
+    let width = if digits > 1 && text.starts_with('0') {
+        digits
+    } else {
+        0
+    };
+    Ok((value, width, offset + digits))
+}
+
+/// Expand a `[...]` group whose contents start at `offset`, returning the
+/// alternatives and the offset after the closing bracket
+fn numeric_group(pattern: &str, mut offset: usize) -> Result<(Vec<String>, usize), ParseError> {
+    let bytes = pattern.as_bytes();
+    let mut values = Vec::new();
+    loop {
+        let (start, width, next) = number(pattern, offset)?;
+        offset = next;
+        let mut end = start;
+        if bytes.get(offset) == Some(&b'-') {
+            let (value, _, next) = number(pattern, offset + 1)?;
+            if value < start {
+                return Err(ParseError::new(
+                    offset + 1,
+                    "number no lower than the start",
+                ));
+            }
+            end = value;
+            offset = next;
+        }
+        if end - start >= (MAX_EXPANSION - values.len()) as u64 {
+            return Err(ParseError::new(offset, "smaller range"));
+        }
+        values.extend((start..=end).map(|n| format!("{:0width$}", n, width = width)));
+        match bytes.get(offset) {
+            Some(b',') => offset += 1,
+            Some(b']') => return Ok((values, offset + 1)),
+            _ => return Err(ParseError::new(offset, "',', '-' or ']'")),
+        }
+    }
+}
+
+/// Expand a `{...}` group whose contents start at `offset`, returning the
+/// alternatives and the offset after the closing brace
+fn literal_group(pattern: &str, offset: usize) -> Result<(Vec<String>, usize), ParseError> {
+    let len = pattern[offset..]
+        .find(['}', '{', '[', ']'])
+        .filter(|&len| pattern[offset + len..].starts_with('}'))
+        .ok_or_else(|| {
+            let at = pattern[offset..]
+                .find(['{', '[', ']'])
+                .map_or(pattern.len(), |len| offset + len);
+            ParseError::new(at, "'}'")
+        })?;
+    let values = pattern[offset..offset + len]
+        .split(',')
+        .map(|value| value.to_string())
+        .collect();
+    Ok((values, offset + len + 1))
+}
+
+/// Expand `pattern` into the names it describes.
+///
+/// # Parameters
+///
+/// * `pattern` - the template, eg `web[01-20].prod.example.com`
+///
+/// # Returns
+/// The names in order, or a `ParseError` pointing at the malformed part of
+/// the template
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::template::expand;
+///
+/// assert_eq!(
+///     expand("web[08-10].example.com").unwrap(),
+///     vec!["web08.example.com", "web09.example.com", "web10.example.com"]
+/// );
+/// assert_eq!(expand("rack{a,b}-sw1").unwrap(), vec!["racka-sw1", "rackb-sw1"]);
+/// assert_eq!(expand("web[1-").unwrap_err().offset(), 6);
+/// ```
+pub fn expand(pattern: &str) -> Result<Vec<String>, ParseError> {
+    let mut names = vec![String::new()];
+    let mut offset = 0;
+    while offset < pattern.len() {
+        let literal = pattern[offset..]
+            .find(['[', ']', '{', '}'])
+            .unwrap_or(pattern.len() - offset);
+        if literal > 0 {
+            for name in names.iter_mut() {
+                name.push_str(&pattern[offset..offset + literal]);
+            }
+            offset += literal;
+            continue;
+        }
+        let (values, next) = match pattern.as_bytes()[offset] {
+            b'[' => numeric_group(pattern, offset + 1)?,
+            b'{' => literal_group(pattern, offset + 1)?,
+            _ => {
+                return Err(ParseError::new(
+                    offset,
+                    "'[' or '{' before a closing bracket",
+                ))
+            }
+        };
+        if names.len().saturating_mul(values.len()) > MAX_EXPANSION {
+            return Err(ParseError::new(offset, "template expanding to fewer names"));
+        }
+        names = names
+            .iter()
+            .flat_map(|name| values.iter().map(move |value| format!("{}{}", name, value)))
+            .collect();
+        offset = next;
+    }
+    Ok(names)
+}
+
+/// Expand `pattern` and resolve every name it describes within
+/// `total_budget`, as [`resolve_all_within`] does.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::template::resolve_template;
+/// use ipaddr_from_str::StaticResolver;
+/// use std::time::Duration;
+///
+/// let resolver = StaticResolver::new()
+///     .with_entry("web1.example.com", vec!["10.0.0.1".parse().unwrap()])
+///     .with_entry("web2.example.com", vec!["10.0.0.2".parse().unwrap()]);
+/// let result = resolve_template(&resolver, "web[1-2].example.com", Duration::from_secs(1)).unwrap();
+/// assert!(result.addrs("web2.example.com").is_some());
+/// ```
+pub fn resolve_template<R>(
+    resolver: &R,
+    pattern: &str,
+    total_budget: Duration,
+) -> Result<PartialResolution, IpaddrConversionError>
+where
+    R: Resolve + Clone + Send + 'static,
+{
+    let names = expand(pattern)?;
+    Ok(resolve_all_within(resolver, &names, total_budget))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_combine_with_the_last_varying_fastest() {
+        assert_eq!(
+            expand("rack{a,b}-sw[1-2,5]").unwrap(),
+            vec![
+                "racka-sw1",
+                "racka-sw2",
+                "racka-sw5",
+                "rackb-sw1",
+                "rackb-sw2",
+                "rackb-sw5"
+            ]
+        );
+        assert_eq!(
+            expand("plain.example.com").unwrap(),
+            vec!["plain.example.com"]
+        );
+        assert_eq!(expand("db{,-replica}").unwrap(), vec!["db", "db-replica"]);
+        assert_eq!(expand("n[9-10]").unwrap(), vec!["n9", "n10"]);
+    }
+    #[test]
+    fn malformed_templates_are_located() {
+        let at = |pattern| {
+            let err = expand(pattern).unwrap_err();
+            (err.offset(), err.expected())
+        };
+        assert_eq!(at("web[5-1]"), (6, "number no lower than the start"));
+        assert_eq!(at("web[a]"), (4, "digit"));
+        assert_eq!(at("web[1x]"), (5, "',', '-' or ']'"));
+        assert_eq!(at("web{a,b"), (7, "'}'"));
+        assert_eq!(at("web{a[1]}"), (5, "'}'"));
+        assert_eq!(at("web]"), (3, "'[' or '{' before a closing bracket"));
+        assert_eq!(at("n[1-100000]"), (10, "smaller range"));
+        assert_eq!(
+            at("n[1-1000][1-1000]"),
+            (9, "template expanding to fewer names")
+        );
+    }
+}
@@ -0,0 +1,268 @@
+//! Resolvers isolated per tenant, for gateways serving many customers.
+//!
+//! A [`ResolverPool`] holds one [`TenantResolver`] per tenant ID. Each has
+//! its own [`Profile`], so its own nameservers, search domains and
+//! overrides, its own cache and its own rate limit: one tenant's overrides
+//! never answer another's lookups, and one tenant flooding lookups neither
+//! fills another's cache nor spends its rate.
+use crate::cache::CachingResolver;
+use crate::errors::IpaddrConversionError;
+use crate::profile::Profile;
+use crate::resolver::Resolve;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// A token bucket, refilled at `rate` tokens a second up to `burst`
+#[derive(Debug)]
+struct Bucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    /// Take a token, if there is one
+    fn take(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.refilled = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// The resolver of one tenant: its profile behind its own cache, and
+/// optionally a limit on how fast it may look names up.
+///
+/// Every lookup counts against the limit, whether or not the cache answers
+/// it. Clones share the cache and the limit.
+#[derive(Debug, Clone)]
+pub struct TenantResolver {
+    tenant: String,
+    resolver: CachingResolver<Profile>,
+    limit: Option<Arc<Mutex<Bucket>>>,
+}
+
+impl TenantResolver {
+    /// New resolver for `tenant`, resolving with `profile` and caching
+    /// answers for `ttl`
+    pub fn new<I: Into<String>>(tenant: I, profile: Profile, ttl: Duration) -> Self {
+        Self {
+            tenant: tenant.into(),
+            resolver: CachingResolver::new(profile, ttl),
+            limit: None,
+        }
+    }
+
+    /// Allow `per_second` lookups a second on average, with bursts of up to
+    /// `burst`, returning the updated resolver. Lookups over the limit fail
+    /// with a RateLimited error.
+    pub fn with_rate_limit(mut self, per_second: f64, burst: u32) -> Self {
+        let burst = f64::from(burst.max(1));
+        self.limit = Some(Arc::new(Mutex::new(Bucket {
+            rate: per_second,
+            burst,
+            tokens: burst,
+            refilled: Instant::now(),
+        })));
+        self
+    }
+
+    /// The tenant this resolver belongs to
+    pub fn tenant(&self) -> &str {
+        &self.tenant
+    }
+
+    /// The tenant's cache, to inspect or clear
+    pub fn cache(&self) -> &CachingResolver<Profile> {
+        &self.resolver
+    }
+}
+
+impl Resolve for TenantResolver {
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        if let Some(ref limit) = self.limit {
+            let allowed = limit
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .take();
+            if !allowed {
+                return Err(IpaddrConversionError::RateLimited(format!(
+                    "{} for tenant {}",
+                    hostname_or_address, self.tenant
+                )));
+            }
+        }
+        self.resolver.resolve(hostname_or_address)
+    }
+}
+
+/// Resolvers by tenant ID.
+///
+/// Looking a tenant up takes a read lock and clones an `Arc`, so a shared
+/// pool can be consulted on every request. Clones share their tenants.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::profile::Profile;
+/// use ipaddr_from_str::resolv_conf::ResolvConf;
+/// use ipaddr_from_str::tenant::{ResolverPool, TenantResolver};
+/// use ipaddr_from_str::{Resolve, StaticResolver};
+/// use std::time::Duration;
+///
+/// let profile = |addr: &str| {
+///     Profile::with_backend(ResolvConf::default(), StaticResolver::new())
+///         .with_override("db.internal", vec![addr.parse().unwrap()])
+/// };
+/// let pool = ResolverPool::new();
+/// pool.insert(TenantResolver::new("acme", profile("10.0.0.1"), Duration::from_secs(60)));
+/// pool.insert(TenantResolver::new("globex", profile("10.9.9.9"), Duration::from_secs(60)));
+/// assert_eq!(pool.resolve("acme", "db.internal").unwrap()[0].to_string(), "10.0.0.1");
+/// assert_eq!(pool.resolve("globex", "db.internal").unwrap()[0].to_string(), "10.9.9.9");
+/// assert!(pool.resolve("initech", "db.internal").is_err());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ResolverPool {
+    tenants: Arc<RwLock<HashMap<String, Arc<TenantResolver>>>>,
+}
+
+impl ResolverPool {
+    /// An empty pool
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `resolver` under its tenant, returning the resolver it replaces
+    pub fn insert(&self, resolver: TenantResolver) -> Option<Arc<TenantResolver>> {
+        self.tenants
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(resolver.tenant.clone(), Arc::new(resolver))
+    }
+
+    /// Remove the resolver of `tenant`, returning it
+    pub fn remove(&self, tenant: &str) -> Option<Arc<TenantResolver>> {
+        self.tenants
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .remove(tenant)
+    }
+
+    /// The resolver of `tenant`
+    pub fn get(&self, tenant: &str) -> Option<Arc<TenantResolver>> {
+        self.tenants
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(tenant)
+            .cloned()
+    }
+
+    /// The resolver of `tenant`, creating it with `create` if the tenant
+    /// has none yet
+    pub fn get_or_insert_with<F>(&self, tenant: &str, create: F) -> Arc<TenantResolver>
+    where
+        F: FnOnce() -> TenantResolver,
+    {
+        if let Some(resolver) = self.get(tenant) {
+            return resolver;
+        }
+        self.tenants
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(tenant.to_string())
+            .or_insert_with(|| Arc::new(create()))
+            .clone()
+    }
+
+    /// The tenants in the pool, in no particular order
+    pub fn tenants(&self) -> Vec<String> {
+        self.tenants
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    /// Resolve `hostname_or_address` for `tenant`
+    ///
+    /// # Returns
+    /// The addresses, or an InvalidInput error if the tenant has no resolver
+    pub fn resolve(
+        &self,
+        tenant: &str,
+        hostname_or_address: &str,
+    ) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        self.get(tenant)
+            .ok_or_else(|| {
+                IpaddrConversionError::InvalidInput(format!("unknown tenant '{}'", tenant))
+            })?
+            .resolve(hostname_or_address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolv_conf::ResolvConf;
+    use crate::resolver::StaticResolver;
+    use crate::stats::{StatsCollector, StatsResolver};
+
+    #[test]
+    fn tenants_do_not_share_caches() {
+        let collector = Arc::new(StatsCollector::new());
+        let backend = StatsResolver::with_collector(
+            StaticResolver::new().with_entry("app", vec!["192.0.2.1".parse().unwrap()]),
+            collector.clone(),
+        );
+        let pool = ResolverPool::new();
+        for tenant in &["a", "b"] {
+            let profile = Profile::with_backend(ResolvConf::default(), backend.clone());
+            pool.insert(TenantResolver::new(
+                *tenant,
+                profile,
+                Duration::from_secs(60),
+            ));
+        }
+        pool.resolve("a", "app").unwrap();
+        pool.resolve("a", "app").unwrap();
+        assert_eq!(collector.stats().queries, 1);
+        pool.resolve("b", "app").unwrap();
+        assert_eq!(collector.stats().queries, 2);
+        pool.get("a").unwrap().cache().clear();
+        assert_eq!(pool.get("b").unwrap().cache().len(), 1);
+        assert!(pool.remove("b").is_some());
+        assert_eq!(pool.tenants(), vec!["a".to_string()]);
+    }
+    #[test]
+    fn rate_limits_apply_per_tenant() {
+        let profile = || {
+            Profile::with_backend(ResolvConf::default(), StaticResolver::new())
+                .with_override("app", vec!["10.0.0.1".parse().unwrap()])
+        };
+        let pool = ResolverPool::new();
+        pool.insert(
+            TenantResolver::new("noisy", profile(), Duration::from_secs(60))
+                .with_rate_limit(0.0, 2),
+        );
+        let quiet = pool.get_or_insert_with("quiet", || {
+            TenantResolver::new("quiet", profile(), Duration::from_secs(60))
+        });
+        assert!(pool.resolve("noisy", "app").is_ok());
+        assert!(pool.resolve("noisy", "app").is_ok());
+        assert!(matches!(
+            pool.resolve("noisy", "app"),
+            Err(IpaddrConversionError::RateLimited(_))
+        ));
+        assert!(quiet.resolve("app").is_ok());
+    }
+}
@@ -0,0 +1,7 @@
+//! Helpers shared by the tests of several modules.
+use std::net::IpAddr;
+
+/// Parse each of `addrs`, which must be addresses
+pub(crate) fn ips(addrs: &[&str]) -> Vec<IpAddr> {
+    addrs.iter().map(|a| a.parse().unwrap()).collect()
+}
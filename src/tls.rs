@@ -0,0 +1,289 @@
+//! Choosing the TLS server name and checking certificate names for a host.
+//!
+//! SNI carries hostnames only (RFC 6066), so an address literal sends none,
+//! and a certificate for an address must list it as an IP subject
+//! alternative name rather than a DNS one. [`HostOrIp`] already knows which
+//! of the two a peer is, so these helpers make the choice from it.
+use crate::host::HostOrIp;
+#[cfg(feature = "dot")]
+use std::convert::TryFrom;
+use std::convert::TryInto;
+use std::net::IpAddr;
+
+/// A subject alternative name from a server certificate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubjectAltName {
+    Dns(String),
+    Ip(IpAddr),
+}
+
+/// The server name to send as SNI for `host`.
+///
+/// # Parameters
+///
+/// * `host` - the peer as configured
+///
+/// # Returns
+///
+/// The hostname without a trailing dot, or `None` for an address literal.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::host::HostOrIp;
+/// use ipaddr_from_str::tls::sni_name;
+///
+/// let host: HostOrIp = "db.example.com.".parse().unwrap();
+/// assert_eq!(sni_name(&host), Some("db.example.com"));
+/// assert_eq!(sni_name(&"192.0.2.1".parse().unwrap()), None);
+/// ```
+pub fn sni_name(host: &HostOrIp) -> Option<&str> {
+    match host {
+        HostOrIp::Ip(_) => None,
+        HostOrIp::Host(name) => Some(name.strip_suffix('.').unwrap_or(name)),
+    }
+}
+
+/// Whether a DNS name pattern from a certificate matches `name`.
+///
+/// A wildcard is only honoured as the whole leftmost label, matches exactly
+/// one label, and needs at least two labels after it (RFC 6125 6.4.3).
+fn dns_name_matches(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.strip_suffix('.').unwrap_or(pattern);
+    let name = name.strip_suffix('.').unwrap_or(name);
+    match pattern.strip_prefix("*.") {
+        Some(suffix) if suffix.contains('.') => match name.split_once('.') {
+            Some((label, rest)) => !label.is_empty() && rest.eq_ignore_ascii_case(suffix),
+            None => false,
+        },
+        _ => pattern.eq_ignore_ascii_case(name),
+    }
+}
+
+/// Whether the certificate names in `sans` cover `host`.
+///
+/// A hostname is matched against the DNS names only, and an address literal
+/// against the IP addresses only, so a certificate for `10.0.0.1` written as
+/// a DNS name does not cover the address.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::tls::{covers_host, SubjectAltName};
+///
+/// let sans = vec![
+///     SubjectAltName::Dns("*.example.com".into()),
+///     SubjectAltName::Ip("192.0.2.1".parse().unwrap()),
+/// ];
+/// assert!(covers_host(&sans, &"db.example.com".parse().unwrap()));
+/// assert!(!covers_host(&sans, &"a.b.example.com".parse().unwrap()));
+/// assert!(covers_host(&sans, &"192.0.2.1".parse().unwrap()));
+/// ```
+pub fn covers_host(sans: &[SubjectAltName], host: &HostOrIp) -> bool {
+    match host {
+        HostOrIp::Ip(ip) => covers_ip(sans, *ip),
+        HostOrIp::Host(name) => sans.iter().any(|san| match san {
+            SubjectAltName::Dns(pattern) => dns_name_matches(pattern, name),
+            SubjectAltName::Ip(_) => false,
+        }),
+    }
+}
+
+/// Whether the certificate names in `sans` list `ip` as an IP address
+pub fn covers_ip(sans: &[SubjectAltName], ip: IpAddr) -> bool {
+    sans.contains(&SubjectAltName::Ip(ip))
+}
+
+/// The names a certificate is issued to
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CertificateNames {
+    /// The common name of the subject, which clients no longer check but
+    /// which still tells the certificates of a fleet apart
+    pub common_name: Option<String>,
+    pub sans: Vec<SubjectAltName>,
+}
+
+/// Read a DER tag and length at `offset`, returning the tag, the offset of the
+/// contents and their length
+pub(crate) fn der_header(der: &[u8], offset: usize) -> Option<(u8, usize, usize)> {
+    let tag = *der.get(offset)?;
+    let first = *der.get(offset + 1)? as usize;
+    if first < 0x80 {
+        return Some((tag, offset + 2, first));
+    }
+    let count = first & 0x7f;
+    if count == 0 || count > 4 {
+        return None;
+    }
+    let mut len = 0usize;
+    for i in 0..count {
+        len = len << 8 | *der.get(offset + 2 + i)? as usize;
+    }
+    Some((tag, offset + 2 + count, len))
+}
+
+/// The tags and contents of the DER values one after another in `der`
+fn der_values(der: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let mut values = Vec::new();
+    let mut offset = 0;
+    while offset < der.len() {
+        let (tag, start, len) = der_header(der, offset)?;
+        values.push((tag, der.get(start..start + len)?));
+        offset = start + len;
+    }
+    Some(values)
+}
+
+/// The names of a DER encoded X.509 certificate.
+///
+/// # Parameters
+///
+/// * `cert` - the certificate as sent by a server
+///
+/// # Returns
+///
+/// The subject's common name and the subject alternative names, or `None` if
+/// the certificate cannot be read
+pub fn certificate_names(cert: &[u8]) -> Option<CertificateNames> {
+    const SEQUENCE: u8 = 0x30;
+    const VERSION: u8 = 0xa0;
+    const EXTENSIONS: u8 = 0xa3;
+    const COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+    const SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+    const DNS_NAME: u8 = 0x82;
+    const IP_ADDRESS: u8 = 0x87;
+    let (tag, body) = *der_values(cert)?.first()?;
+    if tag != SEQUENCE {
+        return None;
+    }
+    let (tag, tbs) = *der_values(body)?.first()?;
+    if tag != SEQUENCE {
+        return None;
+    }
+    let mut fields = der_values(tbs)?;
+    if fields.first()?.0 == VERSION {
+        fields.remove(0);
+    }
+    // serial, signature, issuer, validity, then subject
+    let subject = fields.get(4).filter(|(tag, _)| *tag == SEQUENCE)?.1;
+    let mut names = CertificateNames::default();
+    for (_, set) in der_values(subject)? {
+        for (_, attribute) in der_values(set)? {
+            if let [(_, oid), (_, value), ..] = der_values(attribute)?[..] {
+                if oid == COMMON_NAME {
+                    names.common_name = Some(String::from_utf8_lossy(value).into_owned());
+                }
+            }
+        }
+    }
+    let extensions = match fields.iter().find(|(tag, _)| *tag == EXTENSIONS) {
+        Some((_, extensions)) => der_values(der_values(extensions)?.first()?.1)?,
+        None => return Some(names),
+    };
+    for (_, extension) in extensions {
+        let extension = der_values(extension)?;
+        if extension.first()?.1 != SUBJECT_ALT_NAME {
+            continue;
+        }
+        // after the optional critical flag
+        let value = extension.last()?.1;
+        for (tag, name) in der_values(der_values(value)?.first()?.1)? {
+            match (tag, name.len()) {
+                (DNS_NAME, _) => names.sans.push(SubjectAltName::Dns(
+                    String::from_utf8_lossy(name).into_owned(),
+                )),
+                (IP_ADDRESS, 4) => {
+                    let octets: [u8; 4] = name.try_into().ok()?;
+                    names.sans.push(SubjectAltName::Ip(IpAddr::from(octets)));
+                }
+                (IP_ADDRESS, 16) => {
+                    let octets: [u8; 16] = name.try_into().ok()?;
+                    names.sans.push(SubjectAltName::Ip(IpAddr::from(octets)));
+                }
+                _ => {}
+            }
+        }
+    }
+    Some(names)
+}
+
+/// The rustls server name for `host`: its SNI name for a hostname, or the
+/// address itself, which rustls verifies against IP names and does not send
+#[cfg(feature = "dot")]
+pub fn server_name(
+    host: &HostOrIp,
+) -> Result<rustls::ServerName, crate::errors::IpaddrConversionError> {
+    match host {
+        HostOrIp::Ip(ip) => Ok(rustls::ServerName::IpAddress(*ip)),
+        HostOrIp::Host(_) => {
+            let name = sni_name(host).unwrap_or_default();
+            rustls::ServerName::try_from(name).map_err(|_| {
+                crate::errors::IpaddrConversionError::InvalidInput(format!(
+                    "invalid server name '{}'",
+                    name
+                ))
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards_match_one_label() {
+        assert!(dns_name_matches("*.example.com", "DB.Example.com."));
+        assert!(!dns_name_matches("*.example.com", "example.com"));
+        assert!(!dns_name_matches("*.com", "example.com"));
+        assert!(!dns_name_matches("db*.example.com", "db1.example.com"));
+        assert!(dns_name_matches("db.example.com", "db.example.com"));
+    }
+    #[test]
+    fn addresses_only_match_ip_names() {
+        let sans = vec![SubjectAltName::Dns("10.0.0.1".into())];
+        let host: HostOrIp = "10.0.0.1".parse().unwrap();
+        assert!(!covers_host(&sans, &host));
+        assert!(covers_host(
+            &[SubjectAltName::Ip("10.0.0.1".parse().unwrap())],
+            &host
+        ));
+        assert!(!covers_host(
+            &[SubjectAltName::Ip("10.0.0.1".parse().unwrap())],
+            &"proxy.example.com".parse().unwrap()
+        ));
+    }
+    #[test]
+    fn certificate_names_are_read() {
+        #[rustfmt::skip]
+        let cert = [
+            0x30, 0x3c,                         // Certificate
+            0x30, 0x3a,                         // TBSCertificate
+            0xa0, 0x03, 0x02, 0x01, 0x02,       // version
+            0x02, 0x01, 0x01,                   // serial
+            0x30, 0x00,                         // signature
+            0x30, 0x00,                         // issuer
+            0x30, 0x00,                         // validity
+            0x30, 0x0d, 0x31, 0x0b, 0x30, 0x09, // subject
+            0x06, 0x03, 0x55, 0x04, 0x03,       // common name
+            0x0c, 0x02, b'd', b'b',
+            0x30, 0x00,                         // subjectPublicKeyInfo
+            0xa3, 0x19, 0x30, 0x17,             // extensions
+            0x30, 0x15,
+            0x06, 0x03, 0x55, 0x1d, 0x11,       // subject alt name
+            0x04, 0x0e, 0x30, 0x0c,
+            0x82, 0x04, b'd', b'b', b'.', b'x', // dns name
+            0x87, 0x04, 192, 0, 2, 1,           // ip address
+        ];
+        let names = certificate_names(&cert).unwrap();
+        assert_eq!(names.common_name.as_deref(), Some("db"));
+        assert_eq!(
+            names.sans,
+            vec![
+                SubjectAltName::Dns("db.x".into()),
+                SubjectAltName::Ip("192.0.2.1".parse().unwrap()),
+            ]
+        );
+        assert!(certificate_names(&cert[..20]).is_none());
+    }
+}
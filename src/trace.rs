@@ -0,0 +1,442 @@
+//! Tracing the routers on the path to a host.
+//!
+//! [`trace`] resolves a host and sends it one probe per hop with rising
+//! TTLs. The router a probe's TTL runs out at answers with an ICMP time
+//! exceeded message naming itself, and the host itself refuses a UDP probe
+//! to an unused port, or answers an ICMP echo request. Each hop found is
+//! named by a reverse lookup. Enabled by the `probe` feature.
+//!
+//! Only Linux is supported, where the ICMP messages are read from the
+//! probe socket's error queue, taking no privilege: UDP probes always work,
+//! and ICMP probes where ping sockets are allowed, or over a raw socket
+//! otherwise.
+use crate::errors::IpaddrConversionError;
+use crate::lookup::reverse_lookup;
+use crate::resolver::Resolve;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// The port UDP probes of the first hop go to, as traceroute's are; each
+/// hop after goes to the next
+pub const TRACE_BASE_PORT: u16 = 33434;
+
+/// What the hops are probed with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TraceMethod {
+    /// UDP datagrams to unused ports
+    Udp,
+    /// ICMP echo requests
+    Icmp,
+}
+
+/// A hop of the path
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Hop {
+    /// The TTL of the probe answered, from 1
+    pub ttl: u8,
+    /// The router or host which answered, None if nothing did in time
+    pub addr: Option<IpAddr>,
+    /// The name `addr` reverse resolves to, if any
+    pub name: Option<String>,
+    /// How long the answer took
+    pub rtt: Option<Duration>,
+}
+
+/// The path found to a host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trace {
+    /// The address traced to
+    pub target: IpAddr,
+    /// The hops, the last of them the target if it was reached
+    pub hops: Vec<Hop>,
+    /// Whether the target answered
+    pub reached: bool,
+}
+
+/// What answered a probe
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Answer {
+    /// A router the TTL ran out at
+    Hop(IpAddr),
+    /// The target
+    Reached(IpAddr),
+    /// A router or the target reporting the target cannot be reached
+    Unreachable(IpAddr),
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) mod linux {
+    use super::{Answer, TraceMethod, TRACE_BASE_PORT};
+    use crate::sweep::{
+        echo_request, icmp_message, icmp_socket, sender, ECHO_REPLY_V4, ECHO_REPLY_V6, SEQUENCE,
+    };
+    use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+    use std::io;
+    use std::mem;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+    use std::os::unix::io::AsRawFd;
+    use std::sync::atomic::Ordering;
+    use std::time::{Duration, Instant};
+
+    pub(crate) const IPV6_RECVERR: libc::c_int = 25;
+    const SO_EE_ORIGIN_ICMP: u8 = 2;
+    const SO_EE_ORIGIN_ICMP6: u8 = 3;
+    const TIME_EXCEEDED_V4: u8 = 11;
+    const UNREACHABLE_V4: u8 = 3;
+    const PORT_UNREACHABLE_V4: u8 = 3;
+    const TIME_EXCEEDED_V6: u8 = 3;
+    const UNREACHABLE_V6: u8 = 1;
+    const PORT_UNREACHABLE_V6: u8 = 4;
+
+    /// `struct sock_extended_err` of `linux/errqueue.h`
+    #[repr(C)]
+    struct SockExtendedErr {
+        ee_errno: u32,
+        ee_origin: u8,
+        ee_type: u8,
+        ee_code: u8,
+        _ee_pad: u8,
+        ee_info: u32,
+        _ee_data: u32,
+    }
+
+    /// The address of a `sockaddr`, if an internet one
+    ///
+    /// # Safety
+    /// `addr` must point to a socket address as long as its family says
+    unsafe fn sockaddr_ip(addr: *const libc::sockaddr) -> Option<IpAddr> {
+        match i32::from((*addr).sa_family) {
+            libc::AF_INET => {
+                let sin = &*(addr as *const libc::sockaddr_in);
+                Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                    sin.sin_addr.s_addr,
+                ))))
+            }
+            libc::AF_INET6 => {
+                let sin6 = &*(addr as *const libc::sockaddr_in6);
+                Some(IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr)))
+            }
+            _ => None,
+        }
+    }
+
+    /// An error queued on a socket
+    #[derive(Debug, Clone, Copy)]
+    pub(crate) struct QueuedError {
+        /// The errno the error stands for
+        pub(crate) errno: i32,
+        /// Whether an ICMP message reported it, rather than this host
+        pub(crate) from_icmp: bool,
+        /// The ICMP type and code
+        pub(crate) kind: u8,
+        pub(crate) code: u8,
+        /// The MTU of an `EMSGSIZE` error
+        pub(crate) info: u32,
+        /// The router or host whose ICMP message reported it
+        pub(crate) offender: Option<IpAddr>,
+    }
+
+    /// The next error queued on `socket`'s error queue, if any
+    pub(crate) fn queued_error(socket: &Socket) -> io::Result<Option<QueuedError>> {
+        let mut data = [0u8; 512];
+        let mut control = [0u64; 64];
+        let mut iov = libc::iovec {
+            iov_base: data.as_mut_ptr() as *mut libc::c_void,
+            iov_len: data.len(),
+        };
+        // zeroed plain C structs are valid
+        let mut name: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_name = &mut name as *mut _ as *mut libc::c_void;
+        msg.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = mem::size_of_val(&control) as _;
+        let flags = libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT;
+        if unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, flags) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // the control messages were written by the kernel within the
+        // buffer, each an extended error followed by its offender
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                let (level, kind) = ((*cmsg).cmsg_level, (*cmsg).cmsg_type);
+                if (level == libc::SOL_IP && kind == libc::IP_RECVERR)
+                    || (level == libc::SOL_IPV6 && kind == IPV6_RECVERR)
+                {
+                    let err = &*(libc::CMSG_DATA(cmsg) as *const SockExtendedErr);
+                    let from_icmp =
+                        err.ee_origin == SO_EE_ORIGIN_ICMP || err.ee_origin == SO_EE_ORIGIN_ICMP6;
+                    let offender = if from_icmp {
+                        sockaddr_ip((err as *const SockExtendedErr).add(1).cast())
+                    } else {
+                        None
+                    };
+                    return Ok(Some(QueuedError {
+                        errno: err.ee_errno as i32,
+                        from_icmp,
+                        kind: err.ee_type,
+                        code: err.ee_code,
+                        info: err.ee_info,
+                        offender,
+                    }));
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Classify an ICMP error about a probe to `target`
+    fn answer(target: IpAddr, kind: u8, code: u8, from: IpAddr) -> Option<Answer> {
+        let (exceeded, unreachable, port_unreachable) = match target {
+            IpAddr::V4(_) => (TIME_EXCEEDED_V4, UNREACHABLE_V4, PORT_UNREACHABLE_V4),
+            IpAddr::V6(_) => (TIME_EXCEEDED_V6, UNREACHABLE_V6, PORT_UNREACHABLE_V6),
+        };
+        if kind == exceeded {
+            Some(Answer::Hop(from))
+        } else if kind == unreachable && code == port_unreachable && from == target {
+            Some(Answer::Reached(from))
+        } else if kind == unreachable {
+            Some(Answer::Unreachable(from))
+        } else {
+            None
+        }
+    }
+
+    /// Set the integer option `name` of `socket` to `value`
+    pub(crate) fn set_option(
+        socket: &Socket,
+        level: libc::c_int,
+        name: libc::c_int,
+        value: libc::c_int,
+    ) -> io::Result<()> {
+        let ret = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                level,
+                name,
+                &value as *const libc::c_int as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Wait up to `timeout` for `socket` to have a datagram or error to
+    /// read, returning the poll events it has
+    pub(crate) fn wait(socket: &Socket, timeout: Duration) -> io::Result<libc::c_short> {
+        let mut pollfd = libc::pollfd {
+            fd: socket.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let millis = timeout.as_millis().clamp(1, i32::MAX as u128) as libc::c_int;
+        if unsafe { libc::poll(&mut pollfd, 1, millis) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(pollfd.revents)
+    }
+
+    /// Send one probe with `ttl` to `target`, returning what answered it and
+    /// how long that took, None if nothing did within `timeout`
+    pub(super) fn probe(
+        target: IpAddr,
+        ttl: u8,
+        method: TraceMethod,
+        timeout: Duration,
+    ) -> io::Result<Option<(Answer, Duration)>> {
+        let (socket, port) = match method {
+            TraceMethod::Udp => {
+                let domain = match target {
+                    IpAddr::V4(_) => Domain::ipv4(),
+                    IpAddr::V6(_) => Domain::ipv6(),
+                };
+                let socket = Socket::new(domain, Type::dgram(), Some(Protocol::udp()))?;
+                (socket, TRACE_BASE_PORT.wrapping_add(u16::from(ttl)))
+            }
+            TraceMethod::Icmp => (icmp_socket(target)?, 0),
+        };
+        let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+        match target {
+            IpAddr::V4(_) => {
+                socket.set_ttl(u32::from(ttl))?;
+                set_option(&socket, libc::SOL_IP, libc::IP_RECVERR, 1)?;
+            }
+            IpAddr::V6(_) => {
+                socket.set_unicast_hops_v6(u32::from(ttl))?;
+                set_option(&socket, libc::SOL_IPV6, IPV6_RECVERR, 1)?;
+            }
+        }
+        socket.connect(&SockAddr::from(SocketAddr::new(target, port)))?;
+        let payload = match method {
+            TraceMethod::Udp => b"ipaddr-from-str ".to_vec(),
+            TraceMethod::Icmp => echo_request(target, sequence),
+        };
+        let started = Instant::now();
+        socket.send(&payload)?;
+        let mut buf = [0u8; 1500];
+        loop {
+            let left = match timeout.checked_sub(started.elapsed()) {
+                Some(left) if !left.is_zero() => left,
+                _ => return Ok(None),
+            };
+            let events = wait(&socket, left)?;
+            if events & libc::POLLERR != 0 {
+                if let Some(QueuedError {
+                    from_icmp: true,
+                    kind,
+                    code,
+                    offender: Some(from),
+                    ..
+                }) = queued_error(&socket)?
+                {
+                    if let Some(answer) = answer(target, kind, code, from) {
+                        return Ok(Some((answer, started.elapsed())));
+                    }
+                }
+            } else if events & libc::POLLIN != 0 {
+                let (len, from) = match socket.recv_from(&mut buf) {
+                    Ok(received) => received,
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+                    Err(err) => return Err(err),
+                };
+                let reply = match target {
+                    IpAddr::V4(_) => ECHO_REPLY_V4,
+                    IpAddr::V6(_) => ECHO_REPLY_V6,
+                };
+                // any datagram back means the target answered, while a raw
+                // socket is handed every ICMP message, the time exceeded
+                // ones also queued as errors
+                let message = icmp_message(&buf[..len]);
+                let answered = method == TraceMethod::Udp
+                    || (sender(&from) == Some(target)
+                        && message.len() >= 8
+                        && message[0] == reply
+                        && message[6..8] == sequence.to_be_bytes());
+                if answered {
+                    return Ok(Some((Answer::Reached(target), started.elapsed())));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+use linux::probe;
+
+/// Send one probe; not supported on this platform
+#[cfg(not(target_os = "linux"))]
+fn probe(
+    _target: IpAddr,
+    _ttl: u8,
+    _method: TraceMethod,
+    _timeout: Duration,
+) -> std::io::Result<Option<(Answer, Duration)>> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        "tracing routes is not supported on this platform",
+    ))
+}
+
+/// Trace the path to `host`, resolved through `resolver`
+///
+/// # Parameters
+///
+/// * `resolver` - resolves `host`; its first address is traced to
+/// * `host` - a hostname or address
+/// * `method` - what each hop is probed with
+/// * `max_hops` - the most hops probed before giving up
+/// * `timeout` - how long each probe waits for an answer
+///
+/// # Returns
+/// The hops up to the target, or to the router reporting it unreachable, or
+/// to `max_hops`; or the error resolving the host or opening a probe socket
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::trace::{trace, TraceMethod};
+/// use ipaddr_from_str::SystemResolver;
+/// use std::time::Duration;
+///
+/// let path = trace(&SystemResolver, "example.com", TraceMethod::Udp, 30, Duration::from_secs(1)).unwrap();
+/// for hop in &path.hops {
+///     match (hop.addr, &hop.name) {
+///         (Some(addr), Some(name)) => println!("{:>2} {} ({}) {:?}", hop.ttl, name, addr, hop.rtt.unwrap()),
+///         (Some(addr), None) => println!("{:>2} {} {:?}", hop.ttl, addr, hop.rtt.unwrap()),
+///         _ => println!("{:>2} *", hop.ttl),
+///     }
+/// }
+/// ```
+pub fn trace<R: Resolve + ?Sized>(
+    resolver: &R,
+    host: &str,
+    method: TraceMethod,
+    max_hops: u8,
+    timeout: Duration,
+) -> Result<Trace, IpaddrConversionError> {
+    let target = *resolver
+        .resolve(host)?
+        .first()
+        .ok_or_else(|| IpaddrConversionError::NotFound(host.to_string()))?;
+    let mut hops = Vec::new();
+    let mut reached = false;
+    for ttl in 1..=max_hops {
+        let (addr, rtt, last) = match probe(target, ttl, method, timeout)? {
+            Some((Answer::Hop(addr), rtt)) => (Some(addr), Some(rtt), false),
+            Some((Answer::Reached(addr), rtt)) => {
+                reached = true;
+                (Some(addr), Some(rtt), true)
+            }
+            Some((Answer::Unreachable(addr), rtt)) => (Some(addr), Some(rtt), true),
+            None => (None, None, false),
+        };
+        hops.push(Hop {
+            ttl,
+            addr,
+            // an address without a name looks up as itself
+            name: addr
+                .and_then(|addr| reverse_lookup(addr).ok())
+                .filter(|name| name.parse::<IpAddr>().is_err()),
+            rtt,
+        });
+        if last {
+            break;
+        }
+    }
+    Ok(Trace {
+        target,
+        hops,
+        reached,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::StaticResolver;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn loopback_is_reached_in_one_hop() {
+        let resolver = StaticResolver::new().with_entry("me", vec!["127.0.0.1".parse().unwrap()]);
+        let path = trace(&resolver, "me", TraceMethod::Udp, 5, Duration::from_secs(1)).unwrap();
+        assert!(path.reached);
+        assert_eq!(path.hops.len(), 1);
+        assert_eq!(path.hops[0].addr, Some(path.target));
+        assert!(trace(
+            &resolver,
+            "nobody",
+            TraceMethod::Udp,
+            5,
+            Duration::from_secs(1)
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,242 @@
+//! Longest-prefix matching of addresses against a table of blocks.
+//!
+//! Routing tables, prefix to ASN tables and geolocation data all map
+//! blocks to values, and ask which block most specifically covers an
+//! address. [`PrefixTrie`] answers that in time proportional to the address
+//! width, however many blocks it holds.
+use crate::address::Address;
+use crate::cidr::Cidr;
+use std::iter::FromIterator;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Index of the root of the IPv4 blocks, and the IPv6 blocks
+const V4_ROOT: usize = 0;
+const V6_ROOT: usize = 1;
+
+#[derive(Debug, Clone)]
+struct Node<V> {
+    /// Indexes of the children for a 0 and a 1 bit, 0 meaning none, as the
+    /// roots are never anyone's child
+    children: [u32; 2],
+    value: Option<V>,
+}
+
+impl<V> Node<V> {
+    fn new() -> Self {
+        Node {
+            children: [0, 0],
+            value: None,
+        }
+    }
+}
+
+/// Blocks of addresses, each with a value, looked up by the most specific
+/// block covering an address.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::cidr::Cidr;
+/// use ipaddr_from_str::trie::PrefixTrie;
+///
+/// let mut table = PrefixTrie::new();
+/// table.insert(Cidr::new("10.0.0.0".parse().unwrap(), 8).unwrap(), "corp");
+/// table.insert(Cidr::new("10.20.0.0".parse().unwrap(), 16).unwrap(), "lab");
+/// let (block, value) = table.longest_match("10.20.3.4".parse().unwrap()).unwrap();
+/// assert_eq!((block.to_string().as_str(), *value), ("10.20.0.0/16", "lab"));
+/// assert_eq!(table.longest_match("10.1.1.1".parse().unwrap()).unwrap().1, &"corp");
+/// assert!(table.longest_match("192.0.2.1".parse().unwrap()).is_none());
+/// ```
+#[derive(Debug, Clone)]
+pub struct PrefixTrie<V> {
+    nodes: Vec<Node<V>>,
+    len: usize,
+}
+
+impl<V> Default for PrefixTrie<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The address bits of `addr`, left-aligned in a u128, and its width
+fn aligned(addr: IpAddr) -> (u128, u8) {
+    let address = Address::new(addr);
+    let width = address.bits();
+    (address.to_u128() << (128 - width as u32), width)
+}
+
+fn bit(bits: u128, depth: u8) -> usize {
+    ((bits >> (127 - depth as u32)) & 1) as usize
+}
+
+impl<V> PrefixTrie<V> {
+    /// An empty table
+    pub fn new() -> Self {
+        PrefixTrie {
+            nodes: vec![Node::new(), Node::new()],
+            len: 0,
+        }
+    }
+
+    /// The number of blocks in the table
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the table holds no blocks
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn root(addr: IpAddr) -> usize {
+        if addr.is_ipv4() {
+            V4_ROOT
+        } else {
+            V6_ROOT
+        }
+    }
+
+    /// Set the value of `block`, returning the value it had. The host bits
+    /// of the block are ignored.
+    pub fn insert(&mut self, block: Cidr, value: V) -> Option<V> {
+        let (bits, _) = aligned(block.addr());
+        let mut node = Self::root(block.addr());
+        for depth in 0..block.prefix_len() {
+            let side = bit(bits, depth);
+            let child = self.nodes[node].children[side] as usize;
+            node = if child != 0 {
+                child
+            } else {
+                self.nodes.push(Node::new());
+                let child = self.nodes.len() - 1;
+                self.nodes[node].children[side] = child as u32;
+                child
+            };
+        }
+        let old = self.nodes[node].value.replace(value);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    /// The value of exactly `block`, if it is in the table
+    pub fn get(&self, block: Cidr) -> Option<&V> {
+        let (bits, _) = aligned(block.addr());
+        let mut node = Self::root(block.addr());
+        for depth in 0..block.prefix_len() {
+            node = self.nodes[node].children[bit(bits, depth)] as usize;
+            if node == 0 {
+                return None;
+            }
+        }
+        self.nodes[node].value.as_ref()
+    }
+
+    /// The most specific block covering `addr`, with its value
+    pub fn longest_match(&self, addr: IpAddr) -> Option<(Cidr, &V)> {
+        let (bits, width) = aligned(addr);
+        let mut node = Self::root(addr);
+        let mut best = self.nodes[node].value.as_ref().map(|value| (0, value));
+        for depth in 0..width {
+            node = self.nodes[node].children[bit(bits, depth)] as usize;
+            if node == 0 {
+                break;
+            }
+            if let Some(value) = self.nodes[node].value.as_ref() {
+                best = Some((depth + 1, value));
+            }
+        }
+        best.map(|(prefix_len, value)| {
+            let network = bits & (u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0));
+            let network = match addr {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from((network >> 96) as u32)),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(network)),
+            };
+            (
+                Cidr::new(network, prefix_len).expect("prefix within width"),
+                value,
+            )
+        })
+    }
+}
+
+impl<V> Extend<(Cidr, V)> for PrefixTrie<V> {
+    fn extend<I: IntoIterator<Item = (Cidr, V)>>(&mut self, blocks: I) {
+        for (block, value) in blocks {
+            self.insert(block, value);
+        }
+    }
+}
+
+impl<V> FromIterator<(Cidr, V)> for PrefixTrie<V> {
+    fn from_iter<I: IntoIterator<Item = (Cidr, V)>>(blocks: I) -> Self {
+        let mut trie = Self::new();
+        trie.extend(blocks);
+        trie
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(text: &str) -> Cidr {
+        let (addr, len) = text.split_once('/').unwrap();
+        Cidr::new(addr.parse().unwrap(), len.parse().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn most_specific_block_wins() {
+        let mut trie: PrefixTrie<u32> = vec![
+            (cidr("0.0.0.0/0"), 0),
+            (cidr("192.0.2.0/24"), 24),
+            (cidr("192.0.2.128/25"), 25),
+            (cidr("2001:db8::/32"), 32),
+        ]
+        .into_iter()
+        .collect();
+        let lookup = |trie: &PrefixTrie<u32>, addr: &str| {
+            trie.longest_match(addr.parse().unwrap())
+                .map(|(block, value)| (block.to_string(), *value))
+        };
+        assert_eq!(
+            lookup(&trie, "192.0.2.200"),
+            Some(("192.0.2.128/25".into(), 25))
+        );
+        assert_eq!(
+            lookup(&trie, "192.0.2.1"),
+            Some(("192.0.2.0/24".into(), 24))
+        );
+        assert_eq!(lookup(&trie, "198.51.100.1"), Some(("0.0.0.0/0".into(), 0)));
+        assert_eq!(
+            lookup(&trie, "2001:db8:1::1"),
+            Some(("2001:db8::/32".into(), 32))
+        );
+        // IPv4 blocks never cover IPv6 addresses
+        assert_eq!(lookup(&trie, "::c000:201"), None);
+        assert_eq!(trie.insert(cidr("192.0.2.77/24"), 99), Some(24));
+        assert_eq!(trie.get(cidr("192.0.2.0/24")), Some(&99));
+        assert_eq!(trie.get(cidr("192.0.0.0/16")), None);
+        assert_eq!(trie.len(), 4);
+    }
+    #[test]
+    fn host_routes_match_exactly() {
+        let trie: PrefixTrie<&str> = vec![(cidr("10.0.0.1/32"), "host"), (cidr("::1/128"), "lo")]
+            .into_iter()
+            .collect();
+        assert_eq!(
+            trie.longest_match("10.0.0.1".parse().unwrap()).unwrap().1,
+            &"host"
+        );
+        assert!(trie.longest_match("10.0.0.2".parse().unwrap()).is_none());
+        assert_eq!(
+            trie.longest_match("::1".parse().unwrap())
+                .unwrap()
+                .0
+                .to_string(),
+            "::1/128"
+        );
+    }
+}
@@ -0,0 +1,297 @@
+//! Checking that a resolved address serves the expected protocol.
+//!
+//! A name can resolve to an address where nothing, or the wrong thing,
+//! listens: a stale record, a parked domain, a load balancer missing a
+//! listener. [`verify`] connects and performs the smallest exchange which
+//! proves the protocol is spoken, and [`resolve_and_verify`] resolves a name
+//! and keeps the addresses which pass, for deployment smoke tests.
+//!
+//! Each step is bounded by [`DEFAULT_TIMEOUT`], shortened to fit the
+//! caller's [deadline](crate::deadline), if one is in scope.
+use crate::deadline;
+use crate::direct::random_u64;
+use crate::errors::IpaddrConversionError;
+use crate::resolver::Resolve;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::time::Duration;
+
+/// Longest any one step of a check waits
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What to expect from the service
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Protocol {
+    /// Accepting a TCP connection is enough
+    Tcp,
+    /// Answers a HEAD request with an HTTP status line
+    Http,
+    /// Answers a TLS ClientHello with a handshake or an alert record
+    Tls,
+    /// Greets with a 220 SMTP banner
+    Smtp,
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Protocol::Tcp => "TCP",
+            Protocol::Http => "HTTP",
+            Protocol::Tls => "TLS",
+            Protocol::Smtp => "SMTP",
+        })
+    }
+}
+
+/// Check that `addr` serves `protocol` on `port`.
+///
+/// # Parameters
+///
+/// * `addr` - the address to check
+/// * `port` - the port the service should be listening on
+/// * `protocol` - what the service should speak
+///
+/// # Returns
+/// Nothing if the service answered as expected, a ProtocolMismatch error if
+/// it answered otherwise, or the io error or timeout met on the way
+///
+/// # Example
+///
+/// ```no_run
+/// use ipaddr_from_str::verify::{verify, Protocol};
+///
+/// verify("192.0.2.25".parse().unwrap(), 25, Protocol::Smtp).unwrap();
+/// ```
+pub fn verify(addr: IpAddr, port: u16, protocol: Protocol) -> Result<(), IpaddrConversionError> {
+    verify_as(addr, port, protocol, None)
+}
+
+/// Resolve `host` through `resolver` and [`verify`] each of its addresses,
+/// returning those which pass. The host name is sent as the HTTP Host header
+/// and the TLS server name.
+///
+/// # Returns
+/// The addresses serving `protocol`, or the first failure if none do
+pub fn resolve_and_verify<R: Resolve + ?Sized>(
+    resolver: &R,
+    host: &str,
+    port: u16,
+    protocol: Protocol,
+) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+    let addrs = resolver.resolve(host)?;
+    let name = host.trim_end_matches('.');
+    let name = Some(name).filter(|name| name.parse::<IpAddr>().is_err());
+    let mut passed = Vec::new();
+    let mut failure = None;
+    for addr in addrs {
+        match verify_as(addr, port, protocol, name) {
+            Ok(()) => passed.push(addr),
+            Err(err) => {
+                failure.get_or_insert(err);
+            }
+        }
+    }
+    match failure {
+        Some(err) if passed.is_empty() => Err(err),
+        _ if passed.is_empty() => Err(IpaddrConversionError::NotFound(host.to_string())),
+        _ => Ok(passed),
+    }
+}
+
+/// [`verify`], naming the service `host` where the protocol carries a name
+fn verify_as(
+    addr: IpAddr,
+    port: u16,
+    protocol: Protocol,
+    host: Option<&str>,
+) -> Result<(), IpaddrConversionError> {
+    let target = SocketAddr::new(addr, port);
+    if deadline::expired() {
+        return Err(IpaddrConversionError::Timeout(target.to_string()));
+    }
+    let timeout = deadline::clip(DEFAULT_TIMEOUT);
+    let mut stream =
+        TcpStream::connect_timeout(&target, timeout).map_err(|err| step_error(err, target))?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let answered = match protocol {
+        Protocol::Tcp => return Ok(()),
+        Protocol::Http => {
+            let host = host.map_or_else(|| target.to_string(), str::to_string);
+            let request = format!(
+                "HEAD / HTTP/1.1\r\nHost: {}\r\nUser-Agent: ipaddr-from-str\r\nConnection: close\r\n\r\n",
+                host
+            );
+            stream
+                .write_all(request.as_bytes())
+                .map_err(|err| step_error(err, target))?;
+            let line = first_line(&stream, target)?;
+            line.starts_with("HTTP/1.") || line.starts_with("HTTP/2")
+        }
+        Protocol::Tls => {
+            stream
+                .write_all(&client_hello(host))
+                .map_err(|err| step_error(err, target))?;
+            let mut header = [0u8; 3];
+            stream
+                .read_exact(&mut header)
+                .map_err(|err| step_error(err, target))?;
+            // a handshake or alert record of some TLS version
+            matches!(header, [0x16, 0x03, _] | [0x15, 0x03, _])
+        }
+        Protocol::Smtp => first_line(&stream, target)?.starts_with("220"),
+    };
+    if answered {
+        Ok(())
+    } else {
+        Err(IpaddrConversionError::ProtocolMismatch(format!(
+            "{} did not answer as {}",
+            target, protocol
+        )))
+    }
+}
+
+/// The first line the service sends, without its line ending
+fn first_line(stream: &TcpStream, target: SocketAddr) -> Result<String, IpaddrConversionError> {
+    let mut line = Vec::new();
+    BufReader::new(stream.take(1024))
+        .read_until(b'\n', &mut line)
+        .map_err(|err| step_error(err, target))?;
+    Ok(String::from_utf8_lossy(&line).trim_end().to_string())
+}
+
+/// Map an io error checking `target`, reporting timeouts as such
+fn step_error(err: io::Error, target: SocketAddr) -> IpaddrConversionError {
+    match err.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+            IpaddrConversionError::Timeout(target.to_string())
+        }
+        _ => err.into(),
+    }
+}
+
+/// A TLS 1.2 ClientHello offering common suites, naming `host` if given.
+/// Any server speaking TLS answers it, if only with an alert.
+fn client_hello(host: Option<&str>) -> Vec<u8> {
+    fn put_u16(buf: &mut Vec<u8>, value: usize) {
+        buf.extend_from_slice(&(value as u16).to_be_bytes());
+    }
+
+    let mut extensions = Vec::new();
+    if let Some(host) = host {
+        // server_name: a list holding one host_name entry
+        extensions.extend_from_slice(&[0x00, 0x00]);
+        put_u16(&mut extensions, host.len() + 5);
+        put_u16(&mut extensions, host.len() + 3);
+        extensions.push(0x00);
+        put_u16(&mut extensions, host.len());
+        extensions.extend_from_slice(host.as_bytes());
+    }
+    #[rustfmt::skip]
+    extensions.extend_from_slice(&[
+        // supported_groups: x25519, secp256r1, secp384r1
+        0x00, 0x0a, 0x00, 0x08, 0x00, 0x06, 0x00, 0x1d, 0x00, 0x17, 0x00, 0x18,
+        // ec_point_formats: uncompressed
+        0x00, 0x0b, 0x00, 0x02, 0x01, 0x00,
+        // signature_algorithms: ecdsa_secp256r1_sha256, rsa_pss_rsae_sha256, rsa_pkcs1_sha256
+        0x00, 0x0d, 0x00, 0x08, 0x00, 0x06, 0x04, 0x03, 0x08, 0x04, 0x04, 0x01,
+    ]);
+
+    let mut hello = vec![0x03, 0x03];
+    for _ in 0..4 {
+        hello.extend_from_slice(&random_u64().to_be_bytes());
+    }
+    // no session id
+    hello.push(0);
+    #[rustfmt::skip]
+    let suites = [
+        0xc0, 0x2b, 0xc0, 0x2f, 0xc0, 0x2c, 0xc0, 0x30, 0xcc, 0xa9, 0xcc, 0xa8,
+        0x00, 0x9c, 0x00, 0x9d, 0x00, 0x2f, 0x00, 0x35,
+    ];
+    put_u16(&mut hello, suites.len());
+    hello.extend_from_slice(&suites);
+    // null compression only
+    hello.extend_from_slice(&[0x01, 0x00]);
+    put_u16(&mut hello, extensions.len());
+    hello.extend_from_slice(&extensions);
+
+    let mut record = vec![0x16, 0x03, 0x01];
+    put_u16(&mut record, hello.len() + 4);
+    record.push(0x01);
+    record.extend_from_slice(&(hello.len() as u32).to_be_bytes()[1..]);
+    record.extend_from_slice(&hello);
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::StaticResolver;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serve one connection with `handler`, returning the port
+    fn serve<F: FnOnce(TcpStream) + Send + 'static>(handler: F) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            if let Ok((stream, _)) = listener.accept() {
+                handler(stream);
+            }
+        });
+        port
+    }
+
+    fn localhost() -> IpAddr {
+        "127.0.0.1".parse().unwrap()
+    }
+
+    #[test]
+    fn banners_must_match_the_protocol() {
+        let port = serve(|mut stream| {
+            stream.write_all(b"220 mail.example ESMTP\r\n").unwrap();
+        });
+        assert!(verify(localhost(), port, Protocol::Smtp).is_ok());
+        let port = serve(|mut stream| {
+            stream.write_all(b"SSH-2.0-OpenSSH_9.6\r\n").unwrap();
+        });
+        assert!(matches!(
+            verify(localhost(), port, Protocol::Smtp),
+            Err(IpaddrConversionError::ProtocolMismatch(_))
+        ));
+    }
+    #[test]
+    fn tls_hello_names_the_host() {
+        let port = serve(|mut stream| {
+            let mut header = [0u8; 5];
+            stream.read_exact(&mut header).unwrap();
+            let len = u16::from_be_bytes([header[3], header[4]]) as usize;
+            let mut body = vec![0u8; len];
+            stream.read_exact(&mut body).unwrap();
+            assert_eq!((header[0], body[0]), (0x16, 0x01));
+            assert!(body.windows(11).any(|w| w == b"svc.example"));
+            // handshake_failure alert
+            stream
+                .write_all(&[0x15, 0x03, 0x03, 0x00, 0x02, 0x02, 0x28])
+                .unwrap();
+        });
+        let resolver = StaticResolver::new().with_entry("svc.example", vec![localhost()]);
+        assert_eq!(
+            resolve_and_verify(&resolver, "svc.example", port, Protocol::Tls).unwrap(),
+            vec![localhost()]
+        );
+    }
+    #[test]
+    fn http_status_line_is_required() {
+        let port = serve(|stream| {
+            let mut request = String::new();
+            BufReader::new(&stream).read_line(&mut request).unwrap();
+            assert_eq!(request, "HEAD / HTTP/1.1\r\n");
+            (&stream)
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .unwrap();
+        });
+        assert!(verify(localhost(), port, Protocol::Http).is_ok());
+    }
+}
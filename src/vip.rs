@@ -0,0 +1,241 @@
+//! Assigning stable virtual addresses to hostnames inside a block.
+//!
+//! Service meshes and sidecars hand each service a virtual address which
+//! traffic is captured on, and want it to be the same across restarts and
+//! replicas without coordinating. [`VipAllocator`] hashes each name to a
+//! home address in the block and takes it if it is free. On a collision it
+//! probes further addresses from the same hash, so a name's address only
+//! depends on which names were assigned before it when their hashes
+//! collide. With a domain prefix length set, names which share a parent
+//! domain are kept inside one sub-block of that length, so the prefix
+//! identifies the namespace.
+use crate::address::Address;
+use crate::cidr::Cidr;
+use crate::errors::IpaddrConversionError;
+use crate::resolver::{normalize_name, Resolve};
+use crate::seeded::{hash_parts, host_in};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Probes of addresses derived from the hash before falling back to
+/// scanning from the home address
+const HASHED_PROBES: u64 = 64;
+/// Addresses scanned from the home address before the block is taken to be
+/// full
+const MAX_SCAN: u128 = 1 << 16;
+
+/// The assignments in both directions
+#[derive(Debug, Default)]
+struct Assignments {
+    by_name: HashMap<String, IpAddr>,
+    by_addr: HashMap<IpAddr, String>,
+}
+
+/// Allocator of virtual addresses for hostnames.
+///
+/// Clones share their assignments.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::cidr::Cidr;
+/// use ipaddr_from_str::vip::VipAllocator;
+///
+/// let vips = VipAllocator::new("240.240.0.0/16".parse().unwrap()).with_domain_prefix_len(24);
+/// let orders = vips.assign("orders.shop.svc").unwrap();
+/// let carts = vips.assign("carts.shop.svc").unwrap();
+/// // both services of the shop namespace share a /24
+/// assert!(Cidr::new(orders, 24).unwrap().contains(carts));
+/// assert_eq!(vips.reverse(orders).as_deref(), Some("orders.shop.svc"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct VipAllocator {
+    block: Cidr,
+    domain_prefix_len: Option<u8>,
+    seed: u64,
+    assignments: Arc<Mutex<Assignments>>,
+}
+
+impl VipAllocator {
+    /// Allocator handing out addresses of `block`
+    pub fn new(block: Cidr) -> Self {
+        Self {
+            block,
+            domain_prefix_len: None,
+            seed: 0,
+            assignments: Arc::new(Mutex::new(Assignments::default())),
+        }
+    }
+
+    /// Keep names sharing a parent domain inside one sub-block with this
+    /// prefix length, returning the updated allocator. Lengths not longer
+    /// than the block's own are ignored.
+    pub fn with_domain_prefix_len(mut self, prefix_len: u8) -> Self {
+        let bits = Address::new(self.block.network()).bits();
+        self.domain_prefix_len =
+            Some(prefix_len).filter(|len| *len > self.block.prefix_len() && *len <= bits);
+        self
+    }
+
+    /// Hash names with `seed`, so separate meshes sharing a block lay it out
+    /// differently, returning the updated allocator
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// The block addresses are handed out from
+    pub fn block(&self) -> Cidr {
+        self.block
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Assignments> {
+        self.assignments
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// The block `name`'s address comes from: the sub-block of its parent
+    /// domain, or the whole block
+    fn home_block(&self, name: &str) -> Cidr {
+        let prefix_len = match self.domain_prefix_len {
+            Some(prefix_len) => prefix_len,
+            None => return self.block,
+        };
+        let parent = name.split_once('.').map_or("", |(_, parent)| parent);
+        let hash = hash_parts(&[&self.seed.to_be_bytes(), b"domain", parent.as_bytes()]);
+        let network = Address::new(self.block.network());
+        let bits = network.bits();
+        let choices = u32::from(prefix_len - self.block.prefix_len());
+        let index = if choices >= 128 {
+            hash
+        } else {
+            hash & ((1u128 << choices) - 1)
+        };
+        let shift = u32::from(bits - prefix_len);
+        let offset = if shift >= 128 { 0 } else { index << shift };
+        let addr = match network.ip() {
+            IpAddr::V4(_) => Address::from_u32((network.to_u128() + offset) as u32).ip(),
+            IpAddr::V6(_) => Address::from_u128(network.to_u128() + offset).ip(),
+        };
+        Cidr::new(addr, prefix_len).expect("prefix length checked")
+    }
+
+    /// The address of `hostname`, assigning it one if it has none.
+    ///
+    /// # Returns
+    /// The address, or an InvalidInput error if the name's block has no
+    /// free address left
+    pub fn assign(&self, hostname: &str) -> Result<IpAddr, IpaddrConversionError> {
+        let name = normalize_name(hostname);
+        let mut assignments = self.lock();
+        if let Some(addr) = assignments.by_name.get(&name) {
+            return Ok(*addr);
+        }
+        let block = self.home_block(&name);
+        let seed = self.seed.to_be_bytes();
+        let hashed = (0..HASHED_PROBES)
+            .map(|probe| hash_parts(&[&seed, b"vip", name.as_bytes(), &probe.to_be_bytes()]));
+        let home = hash_parts(&[&seed, b"vip", name.as_bytes(), &0u64.to_be_bytes()]);
+        let scanned = (1..MAX_SCAN).map(|step| home.wrapping_add(step));
+        let free = hashed
+            .chain(scanned)
+            .map(|hash| host_in(&block, hash))
+            .find(|addr| !assignments.by_addr.contains_key(addr))
+            .ok_or_else(|| {
+                IpaddrConversionError::InvalidInput(format!(
+                    "no free address in {} for {}",
+                    block, name
+                ))
+            })?;
+        assignments.by_name.insert(name.clone(), free);
+        assignments.by_addr.insert(free, name);
+        Ok(free)
+    }
+
+    /// The address assigned to `hostname`, if it has one
+    pub fn get(&self, hostname: &str) -> Option<IpAddr> {
+        self.lock().by_name.get(&normalize_name(hostname)).copied()
+    }
+
+    /// The name assigned `addr`, if any
+    pub fn reverse(&self, addr: IpAddr) -> Option<String> {
+        self.lock().by_addr.get(&addr).cloned()
+    }
+
+    /// Free the address of `hostname`, returning it if it had one
+    pub fn release(&self, hostname: &str) -> Option<IpAddr> {
+        let mut assignments = self.lock();
+        let addr = assignments.by_name.remove(&normalize_name(hostname))?;
+        assignments.by_addr.remove(&addr);
+        Some(addr)
+    }
+
+    /// The number of names assigned an address
+    pub fn len(&self) -> usize {
+        self.lock().by_name.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Names resolve to their virtual address, assigned on first lookup
+impl Resolve for VipAllocator {
+    fn resolve(&self, hostname_or_address: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+        if let Ok(ip) = hostname_or_address.parse::<IpAddr>() {
+            return Ok(vec![ip]);
+        }
+        Ok(vec![self.assign(hostname_or_address)?])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assignments_are_stable_across_allocators() {
+        let block: Cidr = "fd00:10::/64".parse().unwrap();
+        let first = VipAllocator::new(block).with_seed(3);
+        let second = VipAllocator::new(block).with_seed(3);
+        for n in 0..100 {
+            let name = format!("svc-{}.mesh", n);
+            let addr = first.assign(&name).unwrap();
+            assert!(block.contains(addr));
+            assert_eq!(second.assign(&name).unwrap(), addr);
+        }
+        assert_eq!(
+            first.assign("SVC-1.mesh.").unwrap(),
+            first.get("svc-1.mesh").unwrap()
+        );
+        assert_eq!(first.len(), 100);
+    }
+    #[test]
+    fn collisions_probe_until_the_block_is_full() {
+        // two usable addresses
+        let vips = VipAllocator::new("10.9.9.0/30".parse().unwrap());
+        let a = vips.assign("a.svc").unwrap();
+        let b = vips.assign("b.svc").unwrap();
+        assert_ne!(a, b);
+        assert!(matches!(
+            vips.assign("c.svc"),
+            Err(IpaddrConversionError::InvalidInput(_))
+        ));
+        assert_eq!(vips.release("a.svc"), Some(a));
+        assert_eq!(vips.resolve("c.svc").unwrap(), vec![a]);
+        assert_eq!(vips.reverse(a).as_deref(), Some("c.svc"));
+    }
+    #[test]
+    fn parent_domains_share_a_sub_block() {
+        let vips = VipAllocator::new("100.64.0.0/10".parse().unwrap()).with_domain_prefix_len(24);
+        let block = |name: &str| Cidr::new(vips.assign(name).unwrap(), 24).unwrap().network();
+        assert_eq!(block("api.billing.svc"), block("db.billing.svc"));
+        let others: Vec<IpAddr> = (0..8).map(|n| block(&format!("x.ns{}.svc", n))).collect();
+        assert!(others
+            .iter()
+            .any(|other| *other != block("api.billing.svc")));
+    }
+}
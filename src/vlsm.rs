@@ -0,0 +1,180 @@
+//! Variable length subnet masking: carving subnets of different sizes out
+//! of one block.
+//!
+//! Given a block and the number of hosts each subnet must hold, [`plan_vlsm`]
+//! gives each subnet the smallest block holding its hosts, placing the
+//! largest first so every block stays aligned and no space is lost between
+//! them.
+use crate::address::Address;
+use crate::cidr::Cidr;
+use crate::errors::IpaddrConversionError;
+use std::net::IpAddr;
+
+/// A subnet of a plan
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Subnet {
+    /// The number of hosts the subnet was asked to hold
+    pub hosts: u128,
+    pub block: Cidr,
+}
+
+/// The subnets planned within a block, and the space left over
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VlsmPlan {
+    /// The subnets, in the order their host counts were given
+    pub subnets: Vec<Subnet>,
+    /// The unallocated rest of the block, as the fewest blocks covering it,
+    /// in address order
+    pub free: Vec<Cidr>,
+}
+
+/// The prefix length of the smallest block of the family of `addr` with
+/// room for `hosts` hosts, as [`Cidr::host_count`] counts them
+fn prefix_for(addr: IpAddr, hosts: u128) -> Option<u8> {
+    let bits = Address::new(addr).bits();
+    let needed = match addr {
+        // a /32 holds one host and a /31 two, larger blocks lose two
+        IpAddr::V4(_) if hosts <= 2 => hosts,
+        IpAddr::V4(_) => hosts.checked_add(2)?,
+        IpAddr::V6(_) => hosts,
+    };
+    let host_bits = match needed.checked_next_power_of_two() {
+        Some(size) => size.trailing_zeros() as u8,
+        None => 128,
+    };
+    bits.checked_sub(host_bits)
+}
+
+/// The block of `prefix_len` at `offset` addresses into `parent`
+fn block_at(parent: &Cidr, offset: u128, prefix_len: u8) -> Cidr {
+    let start = Address::new(parent.network()).to_u128() + offset;
+    let addr = match parent.addr() {
+        IpAddr::V4(_) => Address::from_u32(start as u32).ip(),
+        IpAddr::V6(_) => Address::from_u128(start).ip(),
+    };
+    Cidr::new(addr, prefix_len).expect("prefix within width")
+}
+
+/// Plan subnets holding `hosts[i]` hosts each within `parent`
+///
+/// # Parameters
+///
+/// * `parent` - the block to divide
+/// * `hosts` - how many hosts each subnet must hold
+///
+/// # Returns
+/// The plan, or an InvalidInput error if a subnet is asked to hold no hosts
+/// or the subnets do not fit in `parent`
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::vlsm::plan_vlsm;
+///
+/// let plan = plan_vlsm("192.0.2.0/24".parse().unwrap(), &[20, 100, 2]).unwrap();
+/// let subnets: Vec<String> = plan.subnets.iter().map(|s| s.block.to_string()).collect();
+/// assert_eq!(subnets, ["192.0.2.128/27", "192.0.2.0/25", "192.0.2.160/31"]);
+/// assert!(plan_vlsm("192.0.2.0/24".parse().unwrap(), &[200, 100]).is_err());
+/// ```
+pub fn plan_vlsm(parent: Cidr, hosts: &[u128]) -> Result<VlsmPlan, IpaddrConversionError> {
+    let mut wanted = Vec::with_capacity(hosts.len());
+    for (idx, &count) in hosts.iter().enumerate() {
+        let prefix_len = match prefix_for(parent.addr(), count) {
+            Some(prefix_len) if count > 0 && prefix_len >= parent.prefix_len() => prefix_len,
+            _ => {
+                return Err(IpaddrConversionError::InvalidInput(format!(
+                    "a subnet of {} hosts cannot be planned within {}",
+                    count, parent
+                )))
+            }
+        };
+        wanted.push((idx, prefix_len));
+    }
+    // largest first, so each block starts aligned where the last ended
+    wanted.sort_by_key(|&(idx, prefix_len)| (prefix_len, idx));
+    let size = parent.size();
+    let mut offset = 0u128;
+    let mut subnets = vec![None; hosts.len()];
+    for (idx, prefix_len) in wanted {
+        let block = block_at(&parent, offset, prefix_len);
+        let end = offset.checked_add(block.size()).filter(|end| *end <= size);
+        offset = end.ok_or_else(|| {
+            IpaddrConversionError::InvalidInput(format!(
+                "subnets for {:?} hosts do not fit within {}",
+                hosts, parent
+            ))
+        })?;
+        subnets[idx] = Some(Subnet {
+            hosts: hosts[idx],
+            block,
+        });
+    }
+    // the fewest aligned blocks covering the rest
+    let width = Address::new(parent.addr()).bits();
+    let mut free = Vec::new();
+    while offset < size {
+        let mut host_bits = offset.trailing_zeros().min(width as u32);
+        while host_bits > 0 && (1u128 << host_bits.min(127)) > size - offset {
+            host_bits -= 1;
+        }
+        let block = block_at(&parent, offset, width - host_bits as u8);
+        free.push(block);
+        offset = offset.saturating_add(block.size());
+    }
+    Ok(VlsmPlan {
+        subnets: subnets
+            .into_iter()
+            .map(|s| s.expect("every subnet placed"))
+            .collect(),
+        free,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks(cidrs: &[Cidr]) -> Vec<String> {
+        cidrs.iter().map(Cidr::to_string).collect()
+    }
+
+    #[test]
+    fn plans_pack_largest_first() {
+        let plan = plan_vlsm("10.0.0.0/24".parse().unwrap(), &[50, 1, 10, 50]).unwrap();
+        let subnets: Vec<Cidr> = plan.subnets.iter().map(|s| s.block).collect();
+        assert_eq!(
+            blocks(&subnets),
+            [
+                "10.0.0.0/26",
+                "10.0.0.144/32",
+                "10.0.0.128/28",
+                "10.0.0.64/26"
+            ]
+        );
+        assert_eq!(
+            blocks(&plan.free),
+            [
+                "10.0.0.145/32",
+                "10.0.0.146/31",
+                "10.0.0.148/30",
+                "10.0.0.152/29",
+                "10.0.0.160/27",
+                "10.0.0.192/26"
+            ]
+        );
+        // a subnet needing the whole block leaves nothing free
+        let plan = plan_vlsm("10.0.0.0/24".parse().unwrap(), &[254]).unwrap();
+        assert_eq!(plan.subnets[0].block.to_string(), "10.0.0.0/24");
+        assert!(plan.free.is_empty());
+    }
+    #[test]
+    fn infeasible_plans_are_reported() {
+        let parent: Cidr = "10.0.0.0/24".parse().unwrap();
+        assert!(plan_vlsm(parent, &[255]).is_err());
+        assert!(plan_vlsm(parent, &[126, 126, 1]).is_err());
+        assert!(plan_vlsm(parent, &[0]).is_err());
+        let plan = plan_vlsm("2001:db8::/32".parse().unwrap(), &[1 << 64, 1 << 64]).unwrap();
+        assert_eq!(plan.subnets[1].block.to_string(), "2001:db8:0:1::/64");
+        assert!(plan_vlsm("::/0".parse().unwrap(), &[u128::MAX]).is_ok());
+    }
+}
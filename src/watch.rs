@@ -0,0 +1,219 @@
+//! Watching a name for changes to its addresses.
+//!
+//! [`diff`] compares two resolutions, and [`Watcher`] re-resolves a name on an
+//! interval, reporting each change as an [`AddrDiff`] so that consumers such as
+//! connection pools know exactly which endpoints to drain and which to add.
+use crate::cancel::CancellationToken;
+use crate::errors::IpaddrConversionError;
+use crate::resolver::Resolve;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// The difference between two resolutions of a name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AddrDiff {
+    /// Addresses only in the new resolution, in its order
+    pub added: Vec<IpAddr>,
+    /// Addresses only in the old resolution, in its order
+    pub removed: Vec<IpAddr>,
+    /// Addresses in both, in the new resolution's order
+    pub unchanged: Vec<IpAddr>,
+}
+
+impl AddrDiff {
+    /// Whether anything was added or removed
+    pub fn is_changed(&self) -> bool {
+        !self.added.is_empty() || !self.removed.is_empty()
+    }
+}
+
+/// Compare the addresses of `old` and `new`, preserving their order.
+///
+/// Only membership matters; an address which moved position is unchanged.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::watch::diff;
+/// use std::net::IpAddr;
+///
+/// let ips = |a: &[&str]| a.iter().map(|a| a.parse().unwrap()).collect::<Vec<IpAddr>>();
+/// let changes = diff(&ips(&["10.0.0.1", "10.0.0.2"]), &ips(&["10.0.0.3", "10.0.0.1"]));
+/// assert_eq!(changes.added, ips(&["10.0.0.3"]));
+/// assert_eq!(changes.removed, ips(&["10.0.0.2"]));
+/// assert_eq!(changes.unchanged, ips(&["10.0.0.1"]));
+/// ```
+pub fn diff(old: &[IpAddr], new: &[IpAddr]) -> AddrDiff {
+    let mut changes = AddrDiff::default();
+    for addr in dedup(new) {
+        if old.contains(&addr) {
+            changes.unchanged.push(addr);
+        } else {
+            changes.added.push(addr);
+        }
+    }
+    changes.removed = dedup(old)
+        .into_iter()
+        .filter(|addr| !new.contains(addr))
+        .collect();
+    changes
+}
+
+/// `addrs` without repeats, keeping the first of each
+fn dedup(addrs: &[IpAddr]) -> Vec<IpAddr> {
+    let mut unique = Vec::with_capacity(addrs.len());
+    for addr in addrs {
+        if !unique.contains(addr) {
+            unique.push(*addr);
+        }
+    }
+    unique
+}
+
+/// Re-resolves a name, reporting changes to its addresses.
+///
+/// # Example
+///
+/// ```
+/// use ipaddr_from_str::watch::Watcher;
+/// use ipaddr_from_str::StaticResolver;
+/// use std::time::Duration;
+///
+/// let resolver = StaticResolver::new().with_entry("db.example", vec!["10.0.0.1".parse().unwrap()]);
+/// let mut watcher = Watcher::new(resolver, "db.example", Duration::from_secs(30));
+/// let first = watcher.poll().unwrap();
+/// assert_eq!(first.added.len(), 1);
+/// assert!(!watcher.poll().unwrap().is_changed());
+/// ```
+#[derive(Debug)]
+pub struct Watcher<R> {
+    resolver: R,
+    name: String,
+    interval: Duration,
+    current: Vec<IpAddr>,
+    token: CancellationToken,
+}
+
+impl<R: Resolve> Watcher<R> {
+    /// New watcher for `name`, re-resolving it every `interval` when run.
+    /// Nothing is known about the name until the first poll, so it reports
+    /// every address as added.
+    pub fn new<S: Into<String>>(resolver: R, name: S, interval: Duration) -> Self {
+        Self {
+            resolver,
+            name: name.into(),
+            interval,
+            current: Vec::new(),
+            token: CancellationToken::new(),
+        }
+    }
+
+    /// Stop running once `token` is cancelled, even mid-interval, returning
+    /// the updated watcher
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.token = token;
+        self
+    }
+
+    /// The addresses seen at the last successful poll
+    pub fn current(&self) -> &[IpAddr] {
+        &self.current
+    }
+
+    /// Resolve the name now, returning how its addresses changed since the
+    /// last successful poll. On error the known addresses are kept.
+    pub fn poll(&mut self) -> Result<AddrDiff, IpaddrConversionError> {
+        let addrs = self.resolver.resolve(&self.name)?;
+        let changes = diff(&self.current, &addrs);
+        self.current = addrs;
+        Ok(changes)
+    }
+
+    /// Poll every interval on the current thread, passing `on_change` each
+    /// change and each error, until it returns false or the watcher's token
+    /// is cancelled.
+    pub fn run<F>(&mut self, mut on_change: F)
+    where
+        F: FnMut(Result<AddrDiff, IpaddrConversionError>) -> bool,
+    {
+        while !self.token.is_cancelled() {
+            let keep_going = match self.poll() {
+                Ok(changes) if !changes.is_changed() => true,
+                result => on_change(result),
+            };
+            if !keep_going || self.token.sleep(self.interval) {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::StaticResolver;
+    use crate::test_util::ips;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn diff_ignores_order_and_repeats() {
+        let changes = diff(
+            &ips(&["10.0.0.1", "10.0.0.2", "10.0.0.2"]),
+            &ips(&["10.0.0.2", "10.0.0.1"]),
+        );
+        assert!(!changes.is_changed());
+        assert_eq!(changes.unchanged, ips(&["10.0.0.2", "10.0.0.1"]));
+        assert_eq!(diff(&[], &[]), AddrDiff::default());
+    }
+
+    /// Resolver whose table can be swapped while a watcher owns it
+    #[derive(Clone)]
+    struct Shared(Arc<Mutex<StaticResolver>>);
+
+    impl Resolve for Shared {
+        fn resolve(&self, host: &str) -> Result<Vec<IpAddr>, IpaddrConversionError> {
+            self.0.lock().unwrap().resolve(host)
+        }
+    }
+
+    #[test]
+    fn run_reports_only_changes() {
+        let table = Arc::new(Mutex::new(
+            StaticResolver::new().with_entry("db.example", ips(&["10.0.0.1"])),
+        ));
+        let mut watcher = Watcher::new(
+            Shared(table.clone()),
+            "db.example",
+            Duration::from_millis(1),
+        );
+        let mut seen = Vec::new();
+        let mut polls = 0;
+        watcher.run(|result| {
+            polls += 1;
+            seen.push(result.unwrap());
+            // swap the address after the initial report
+            table
+                .lock()
+                .unwrap()
+                .insert("db.example", ips(&["10.0.0.2"]));
+            seen.len() < 2
+        });
+        assert_eq!(polls, 2);
+        assert_eq!(seen[1].added, ips(&["10.0.0.2"]));
+        assert_eq!(seen[1].removed, ips(&["10.0.0.1"]));
+        assert_eq!(watcher.current(), &ips(&["10.0.0.2"])[..]);
+    }
+    #[test]
+    fn run_stops_when_cancelled() {
+        let token = CancellationToken::new();
+        let resolver = StaticResolver::new().with_entry("db.example", ips(&["10.0.0.1"]));
+        let mut watcher = Watcher::new(resolver, "db.example", Duration::from_secs(3600))
+            .with_cancellation(token.clone());
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            token.cancel();
+        });
+        watcher.run(|_| true);
+        assert_eq!(watcher.current().len(), 1);
+    }
+}
@@ -0,0 +1,138 @@
+//! Resolver configuration read from the Windows registry.
+//!
+//! Windows has no resolv.conf; the DNS client takes its nameservers and suffix
+//! search list from the TCP/IP parameters in the registry, both globally and
+//! per interface. This module reads those values into a [`ResolvConf`] so the
+//! crate's own backends behave the same on Windows as elsewhere.
+use crate::errors::IpaddrConversionError;
+use crate::resolv_conf::{ResolvConf, DNS_PORT};
+use std::net::{IpAddr, SocketAddr};
+use winreg::enums::HKEY_LOCAL_MACHINE;
+use winreg::RegKey;
+
+const TCPIP_PARAMETERS: &str = r"SYSTEM\CurrentControlSet\Services\Tcpip\Parameters";
+const TCPIP6_PARAMETERS: &str = r"SYSTEM\CurrentControlSet\Services\Tcpip6\Parameters";
+
+/// DNS settings of a single network interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceDnsConfig {
+    /// The interface's registry key, a GUID
+    pub id: String,
+    /// Statically configured nameservers, or failing that those from DHCP
+    pub nameservers: Vec<IpAddr>,
+    /// The interface's connection-specific DNS suffix, if any
+    pub domain: Option<String>,
+}
+
+/// Read a string value, treating a missing value as empty
+fn string_value(key: &RegKey, name: &str) -> String {
+    key.get_value::<String, _>(name).unwrap_or_default()
+}
+
+/// Split a registry address list, which may be comma or space separated
+fn parse_addresses(value: &str) -> Vec<IpAddr> {
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter_map(|s| s.parse().ok())
+        .collect()
+}
+
+fn non_empty(value: String) -> Option<String> {
+    let value = value.trim().trim_end_matches('.').to_ascii_lowercase();
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+fn interfaces_under(parameters: &str) -> Result<Vec<InterfaceDnsConfig>, IpaddrConversionError> {
+    let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
+    let interfaces = hklm.open_subkey(format!(r"{}\Interfaces", parameters))?;
+    let mut configs = Vec::new();
+    for id in interfaces.enum_keys() {
+        let id = id?;
+        let key = interfaces.open_subkey(&id)?;
+        let mut nameservers = parse_addresses(&string_value(&key, "NameServer"));
+        if nameservers.is_empty() {
+            nameservers = parse_addresses(&string_value(&key, "DhcpNameServer"));
+        }
+        let domain = non_empty(string_value(&key, "Domain"))
+            .or_else(|| non_empty(string_value(&key, "DhcpDomain")));
+        if !nameservers.is_empty() || domain.is_some() {
+            configs.push(InterfaceDnsConfig {
+                id,
+                nameservers,
+                domain,
+            });
+        }
+    }
+    Ok(configs)
+}
+
+/// The DNS settings of every interface which has any, IPv4 interfaces first
+pub fn interfaces() -> Result<Vec<InterfaceDnsConfig>, IpaddrConversionError> {
+    let mut configs = interfaces_under(TCPIP_PARAMETERS)?;
+    // the IPv6 stack may be disabled, in which case its key is absent
+    configs.extend(interfaces_under(TCPIP6_PARAMETERS).unwrap_or_default());
+    Ok(configs)
+}
+
+/// Build a resolver configuration equivalent to what the Windows DNS client
+/// uses: the global search list if one is set, otherwise the primary domain
+/// followed by each interface's suffix, and the nameservers of every
+/// interface in turn.
+pub fn resolv_conf() -> Result<ResolvConf, IpaddrConversionError> {
+    let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
+    let parameters = hklm.open_subkey(TCPIP_PARAMETERS)?;
+    let interfaces = interfaces()?;
+
+    let mut conf = ResolvConf::default();
+    let global_nameservers = parse_addresses(&string_value(&parameters, "NameServer"));
+    for ip in global_nameservers.into_iter().chain(
+        interfaces
+            .iter()
+            .flat_map(|i| i.nameservers.iter().cloned()),
+    ) {
+        let server = SocketAddr::new(ip, DNS_PORT);
+        if !conf.nameservers.contains(&server) {
+            conf.nameservers.push(server);
+        }
+    }
+
+    let search_list = string_value(&parameters, "SearchList");
+    conf.search = search_list
+        .split(',')
+        .filter_map(|s| non_empty(s.to_string()))
+        .collect();
+    if conf.search.is_empty() {
+        let primary = non_empty(string_value(&parameters, "Domain"))
+            .or_else(|| non_empty(string_value(&parameters, "DhcpDomain")));
+        for domain in primary
+            .into_iter()
+            .chain(interfaces.into_iter().filter_map(|i| i.domain))
+        {
+            if !conf.search.contains(&domain) {
+                conf.search.push(domain);
+            }
+        }
+    }
+    Ok(conf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry_address_lists_split_on_commas_and_spaces() {
+        assert_eq!(
+            parse_addresses("10.0.0.1,10.0.0.2 fe80::1  bogus"),
+            vec![
+                "10.0.0.1".parse::<IpAddr>().unwrap(),
+                "10.0.0.2".parse().unwrap(),
+                "fe80::1".parse().unwrap()
+            ]
+        );
+    }
+}